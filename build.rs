@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generate the gRPC service with a vendored protoc, so no system install
+    // is needed
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_prost_build::compile_protos("proto/ledger.proto")?;
+    }
+    println!("cargo:rerun-if-changed=proto/ledger.proto");
+    Ok(())
+}
@@ -3,7 +3,7 @@
 use rust_market_ledger::consensus::algorithms::pbft::PBFTConsensus;
 use rust_market_ledger::consensus::algorithms::PBFTManager;
 use rust_market_ledger::consensus::comparison::*;
-use rust_market_ledger::etl::{Block, MarketData};
+use rust_market_ledger::testing::{MarketDataGenerator, TestChainBuilder};
 use std::sync::Arc;
 
 #[tokio::main]
@@ -13,30 +13,12 @@ async fn main() {
     println!("{}", "=".repeat(100));
     println!();
 
-    let mut blocks: Vec<Block> = Vec::new();
-    for i in 1..=10 {
-        let previous_hash = if i == 1 {
-            "0000_genesis".to_string()
-        } else {
-            blocks[(i - 2) as usize].hash.clone()
-        };
-
-        let mut block = Block {
-            index: i,
-            timestamp: chrono::Utc::now().timestamp() + i as i64,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0 + (i as f32 * 100.0),
-                source: "CoinGecko".to_string(),
-                timestamp: chrono::Utc::now().timestamp() + i as i64,
-            }],
-            previous_hash,
-            hash: String::new(),
-            nonce: 0,
-        };
-        block.calculate_hash_with_nonce();
-        blocks.push(block);
-    }
+    let generator =
+        MarketDataGenerator::new(0).with_start_timestamp(chrono::Utc::now().timestamp_millis());
+    let blocks = TestChainBuilder::new()
+        .with_blocks(10)
+        .with_generator(generator)
+        .build();
 
     println!("Generated {} test blocks", blocks.len());
     println!();
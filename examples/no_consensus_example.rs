@@ -1,7 +1,8 @@
 // Example A: No-Consensus (Single Node Direct Commit)
 
 use rust_market_ledger::consensus::comparison::*;
-use rust_market_ledger::etl::{Block, MarketData};
+use rust_market_ledger::etl::price::Decimal;
+use rust_market_ledger::testing;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -12,19 +13,16 @@ async fn main() {
     println!("{}", "=".repeat(80));
     println!();
 
-    let block = Block {
-        index: 1,
-        timestamp: chrono::Utc::now().timestamp(),
-        data: vec![MarketData {
-            asset: "BTC".to_string(),
-            price: 50000.0,
-            source: "CoinGecko".to_string(),
-            timestamp: chrono::Utc::now().timestamp(),
-        }],
-        previous_hash: "0000_genesis".to_string(),
-        hash: String::new(),
-        nonce: 0,
-    };
+    let block = testing::block(
+        1,
+        "0000_genesis",
+        vec![testing::entry(
+            "BTC",
+            "CoinGecko",
+            Decimal::from(50000),
+            chrono::Utc::now().timestamp_millis(),
+        )],
+    );
 
     println!(
         "Block created: index={}, data={} @ ${}",
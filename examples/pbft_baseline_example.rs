@@ -3,7 +3,9 @@
 use rust_market_ledger::consensus::algorithms::pbft::PBFTConsensus;
 use rust_market_ledger::consensus::algorithms::PBFTManager;
 use rust_market_ledger::consensus::comparison::{ConsensusAlgorithmAdapter, ConsensusStrategy};
-use rust_market_ledger::etl::{Block, MarketData};
+use rust_market_ledger::consensus::demo::DemoMode;
+use rust_market_ledger::etl::price::Decimal;
+use rust_market_ledger::testing;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
@@ -15,20 +17,22 @@ async fn main() {
     println!("{}", "=".repeat(80));
     println!();
 
-    let mut block = Block {
-        index: 1,
-        timestamp: chrono::Utc::now().timestamp(),
-        data: vec![MarketData {
-            asset: "BTC".to_string(),
-            price: 50000.0,
-            source: "CoinGecko".to_string(),
-            timestamp: chrono::Utc::now().timestamp(),
-        }],
-        previous_hash: "0000_genesis".to_string(),
-        hash: String::new(),
-        nonce: 0,
-    };
-    block.calculate_hash_with_nonce();
+    // DEMO_MODE=1 slows the rounds down and narrates each phase
+    let demo = Arc::new(DemoMode::from_env(false));
+    if demo.is_enabled() {
+        rust_market_ledger::logger::init_logger();
+    }
+
+    let block = testing::block(
+        1,
+        "0000_genesis",
+        vec![testing::entry(
+            "BTC",
+            "CoinGecko",
+            Decimal::from(50000),
+            chrono::Utc::now().timestamp_millis(),
+        )],
+    );
 
     println!(
         "Block created: index={}, data={} @ ${}, hash={}...",
@@ -63,11 +67,9 @@ async fn main() {
         total_nodes,
         node_addresses.clone(),
     ));
-    let pbft_consensus = Arc::new(PBFTConsensus::new(
-        pbft_manager.clone(),
-        node_addresses.clone(),
-        8000,
-    ));
+    let pbft_consensus = Arc::new(
+        PBFTConsensus::new(pbft_manager.clone(), node_addresses.clone(), 8000).with_demo(demo),
+    );
 
     let strategy: Arc<dyn ConsensusStrategy> =
         Arc::new(ConsensusAlgorithmAdapter::new(pbft_consensus));
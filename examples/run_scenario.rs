@@ -0,0 +1,58 @@
+//! Run an experiment described by a scenario file
+//!
+//! ```bash
+//! cargo run --example run_scenario -- scenarios/trilemma_lan.yaml
+//! ```
+
+use rust_market_ledger::consensus::scenario::Scenario;
+use std::time::Instant;
+
+#[tokio::main]
+async fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: run_scenario <scenario.yaml>");
+        std::process::exit(2);
+    };
+
+    let scenario = match Scenario::load(&path) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("\n{}", "=".repeat(100));
+    println!("  Scenario: {}", scenario.name);
+    if let Some(description) = &scenario.description {
+        println!("  {}", description);
+    }
+    println!("{}", "=".repeat(100));
+    println!(
+        "Nodes: {} (network: {}), blocks: {}, rounds: {}, strategies: {}, faults: {}",
+        scenario.nodes.count,
+        scenario.nodes.profile.as_deref().unwrap_or("none"),
+        scenario.workload.blocks,
+        scenario.workload.rounds,
+        scenario.consensus.len(),
+        scenario.faults.len()
+    );
+
+    let start = Instant::now();
+    let report = match scenario.run().await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = scenario.write_outputs(&report) {
+        eprintln!("Error writing results: {}", e);
+        std::process::exit(1);
+    }
+
+    println!(
+        "Scenario completed in {:.2}s",
+        start.elapsed().as_secs_f64()
+    );
+}
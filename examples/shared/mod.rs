@@ -1,6 +1,6 @@
 //! Shared metrics utilities for experiment examples
 
-use rust_market_ledger::consensus::comparison::ConsensusMetrics;
+use rust_market_ledger::consensus::comparison::{BenchmarkMode, ConsensusMetrics};
 
 pub struct MetricsStdDev {
     pub latency_std_dev: f64,
@@ -70,6 +70,7 @@ pub fn calculate_average_metrics(round_metrics: &[ConsensusMetrics]) -> Consensu
     if round_metrics.is_empty() {
         return ConsensusMetrics {
             strategy_name: String::new(),
+            mode: BenchmarkMode::Simulated,
             total_blocks: 0,
             committed_blocks: 0,
             failed_blocks: 0,
@@ -93,6 +94,7 @@ pub fn calculate_average_metrics(round_metrics: &[ConsensusMetrics]) -> Consensu
             fault_tolerance: 0.0,
             reliability: 0.0,
             stale_block_rate: 0.0,
+            system: Default::default(),
         };
     }
 
@@ -101,6 +103,7 @@ pub fn calculate_average_metrics(round_metrics: &[ConsensusMetrics]) -> Consensu
 
     ConsensusMetrics {
         strategy_name,
+        mode: round_metrics[0].mode,
         total_blocks: round_metrics[0].total_blocks,
         committed_blocks: (round_metrics
             .iter()
@@ -155,5 +158,7 @@ pub fn calculate_average_metrics(round_metrics: &[ConsensusMetrics]) -> Consensu
             .map(|m| m.stale_block_rate)
             .sum::<f64>()
             / count,
+        // The last round's sample covers the most accumulated state
+        system: round_metrics[round_metrics.len() - 1].system.clone(),
     }
 }
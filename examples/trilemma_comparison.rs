@@ -2,7 +2,14 @@
 
 use rust_market_ledger::consensus::algorithms::*;
 use rust_market_ledger::consensus::comparison::*;
-use rust_market_ledger::etl::{Block, MarketData};
+use rust_market_ledger::consensus::cost_model::{
+    QuorumCollusionCost, SingleIdentityCost, StakeCost,
+};
+use rust_market_ledger::consensus::network_model::{
+    LatencyProfile, NetworkModel, SimulatedNetworkStrategy,
+};
+use rust_market_ledger::consensus::networked::NetworkedClusterStrategy;
+use rust_market_ledger::testing::{MarketDataGenerator, TestChainBuilder};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -75,36 +82,36 @@ async fn main() {
         FLEXIBLE_PAXOS_Q1, FLEXIBLE_PAXOS_Q2
     );
     println!();
+    // NETWORK_PROFILE=lan|same-region|cross-continent selects the simulated
+    // latency profile; nodes are spread over the profile's default regions
+    let profile = std::env::var("NETWORK_PROFILE")
+        .ok()
+        .and_then(|name| LatencyProfile::from_name(&name))
+        .unwrap_or(LatencyProfile::Lan);
+    let network_model = NetworkModel::with_default_regions(profile, TOTAL_NODES);
+
     println!("Data Source: Simulated ETL data (offline/mock)");
-    println!("Network: Simulated (single-machine simulation)");
+    println!(
+        "Network: Simulated (single-machine simulation), profile={}",
+        profile.name()
+    );
+    for node in 0..TOTAL_NODES {
+        println!(
+            "  Node {}: region {}",
+            node,
+            network_model.region_of(node).unwrap_or("?")
+        );
+    }
     println!("  Note: PBFT has network handler but runs in simulated mode");
     println!("  Other algorithms use simulated consensus logic");
     println!();
 
-    let mut blocks: Vec<Block> = Vec::new();
-    for i in 1..=BLOCKS_PER_ROUND {
-        let previous_hash = if i == 1 {
-            "0000_genesis".to_string()
-        } else {
-            blocks[(i - 2) as usize].hash.clone()
-        };
-
-        let mut block = Block {
-            index: i as u64,
-            timestamp: chrono::Utc::now().timestamp() + i as i64,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0 + (i as f32 * 100.0),
-                source: "CoinGecko".to_string(),
-                timestamp: chrono::Utc::now().timestamp() + i as i64,
-            }],
-            previous_hash,
-            hash: String::new(),
-            nonce: 0,
-        };
-        block.calculate_hash_with_nonce();
-        blocks.push(block);
-    }
+    let generator =
+        MarketDataGenerator::new(0).with_start_timestamp(chrono::Utc::now().timestamp_millis());
+    let blocks = TestChainBuilder::new()
+        .with_blocks(BLOCKS_PER_ROUND as u64)
+        .with_generator(generator)
+        .build();
 
     println!("Generated {} test blocks", blocks.len());
     println!();
@@ -130,42 +137,95 @@ async fn main() {
     let strategies: Vec<(String, Arc<dyn ConsensusStrategy>)> = vec![
         (
             "PBFT".to_string(),
-            Arc::new(ConsensusAlgorithmAdapter::new(pbft_consensus)),
+            Arc::new(
+                ConsensusAlgorithmAdapter::new(pbft_consensus).with_cost_model(Arc::new(
+                    QuorumCollusionCost::new(TOTAL_NODES, PBFT_QUORUM, PBFT_QUORUM),
+                )),
+            ),
         ),
         (
             "Gossip".to_string(),
-            Arc::new(ConsensusAlgorithmAdapter::new(Arc::new(
-                gossip::GossipConsensus::new(NODE_ID, TOTAL_NODES, GOSSIP_FANOUT),
-            ))),
+            Arc::new(
+                ConsensusAlgorithmAdapter::new(Arc::new(gossip::GossipConsensus::new(
+                    NODE_ID,
+                    TOTAL_NODES,
+                    GOSSIP_FANOUT,
+                )))
+                .with_cost_model(Arc::new(SingleIdentityCost::default())),
+            ),
         ),
         (
             "Eventual".to_string(),
-            Arc::new(ConsensusAlgorithmAdapter::new(Arc::new(
-                eventual::EventualConsensus::new(NODE_ID, EVENTUAL_DELAY_MS, EVENTUAL_THRESHOLD),
-            ))),
+            Arc::new(
+                ConsensusAlgorithmAdapter::new(Arc::new(eventual::EventualConsensus::new(
+                    NODE_ID,
+                    EVENTUAL_DELAY_MS,
+                    EVENTUAL_THRESHOLD,
+                )))
+                .with_cost_model(Arc::new(QuorumCollusionCost::new(
+                    TOTAL_NODES,
+                    EVENTUAL_THRESHOLD,
+                    EVENTUAL_THRESHOLD,
+                ))),
+            ),
         ),
         (
             "Quorum-less".to_string(),
-            Arc::new(ConsensusAlgorithmAdapter::new(Arc::new(
-                quorumless::QuorumlessConsensus::new(NODE_ID, QUORUMLESS_THRESHOLD),
-            ))),
+            Arc::new(
+                ConsensusAlgorithmAdapter::new(Arc::new(quorumless::QuorumlessConsensus::new(
+                    NODE_ID,
+                    QUORUMLESS_THRESHOLD,
+                )))
+                .with_cost_model(Arc::new(StakeCost::new(QUORUMLESS_THRESHOLD))),
+            ),
         ),
         (
             "Flexible Paxos".to_string(),
-            Arc::new(ConsensusAlgorithmAdapter::new(Arc::new(
-                flexible_paxos::FlexiblePaxos::new(
+            Arc::new(
+                ConsensusAlgorithmAdapter::new(Arc::new(flexible_paxos::FlexiblePaxos::new(
                     NODE_ID,
                     TOTAL_NODES,
                     FLEXIBLE_PAXOS_Q1,
                     FLEXIBLE_PAXOS_Q2,
-                ),
-            ))),
+                )))
+                .with_cost_model(Arc::new(QuorumCollusionCost::new(
+                    TOTAL_NODES,
+                    FLEXIBLE_PAXOS_Q1,
+                    FLEXIBLE_PAXOS_Q2,
+                ))),
+            ),
         ),
     ];
 
+    // PBFT waits on two quorum round trips (prepare, commit) per block
+    let mut strategies: Vec<(String, Arc<dyn ConsensusStrategy>)> = strategies
+        .into_iter()
+        .map(|(name, strategy)| {
+            let phases = if name == "PBFT" { 2 } else { 1 };
+            let simulated: Arc<dyn ConsensusStrategy> = Arc::new(
+                SimulatedNetworkStrategy::new(strategy, network_model.clone())
+                    .with_proposer(NODE_ID)
+                    .with_phases(phases),
+            );
+            (name, simulated)
+        })
+        .collect();
+
+    // BENCH_CLUSTER_NODES and BENCH_API_KEY add a run against a live cluster,
+    // measured over real HTTP and reported as networked
+    if let Some(live) = NetworkedClusterStrategy::from_env() {
+        println!("Live cluster: {}", live.nodes().join(", "));
+        strategies.push(("Live Cluster".to_string(), Arc::new(live)));
+    }
+
     println!("Strategies to test:");
-    for (i, (name, _)) in strategies.iter().enumerate() {
-        println!("  {}. {}", i + 1, name);
+    for (i, (name, strategy)) in strategies.iter().enumerate() {
+        println!(
+            "  {}. {} ({})",
+            i + 1,
+            name,
+            strategy.benchmark_mode().name()
+        );
     }
     println!();
 
@@ -0,0 +1,485 @@
+//! Block explorer: `chain show` and `chain search`
+//!
+//! ```text
+//! chain show <index|hash> [--ancestors N]
+//! chain search [--asset BTC] [--source CoinGecko] [--from T] [--to T]
+//!              [--annotation KEY=VALUE] [--limit N]
+//!
+//! Common options:
+//!   --node N            read blockchain_node_N.db (default 0)
+//!   --db PATH           read an explicit database file
+//!   --format table|json (--json is shorthand for --format json)
+//!   --color / --no-color
+//! ```
+//!
+//! Times accept unix milliseconds, RFC 3339 (`2024-01-31T12:00:00Z`) or a
+//! plain date (`2024-01-31`); a plain date passed to `--to` covers the whole day.
+
+use crate::cli::{flag_value, open_ledger, print_output, Palette};
+use crate::etl::load::BlockQuery;
+use crate::etl::{timestamp_to_millis, Block};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::error::Error;
+
+const USAGE: &str = "Usage:
+  chain show <index|hash> [--ancestors N] [OPTIONS]
+  chain search [--asset A] [--source S] [--from T] [--to T] [--annotation K=V]
+               [--limit N] [OPTIONS]
+
+Options:
+  --node N              read blockchain_node_N.db (default 0)
+  --db PATH             read an explicit database file
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// How `chain show` identifies the block to print
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockRef {
+    Index(u64),
+    Hash(String),
+}
+
+impl BlockRef {
+    fn parse(s: &str) -> Self {
+        // Hashes are 64 hex characters, so anything short and numeric is an index
+        if s.len() < 20 && !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+            BlockRef::Index(s.parse().unwrap_or_default())
+        } else {
+            BlockRef::Hash(s.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainCommand {
+    Show { target: BlockRef, ancestors: usize },
+    Search(SearchFilters),
+}
+
+/// Parsed `chain search` filters; mirrors `BlockQuery` so it can be compared in tests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    pub asset: Option<String>,
+    pub source: Option<String>,
+    pub from_timestamp: Option<i64>,
+    pub to_timestamp: Option<i64>,
+    pub annotation: Option<(String, String)>,
+    pub limit: Option<u64>,
+}
+
+impl From<&SearchFilters> for BlockQuery {
+    fn from(filters: &SearchFilters) -> Self {
+        BlockQuery {
+            asset: filters.asset.clone(),
+            source: filters.source.clone(),
+            from_timestamp: filters.from_timestamp,
+            to_timestamp: filters.to_timestamp,
+            annotation: filters.annotation.clone(),
+            limit: filters.limit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainArgs {
+    pub command: ChainCommand,
+    pub db_path: String,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl ChainArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        let subcommand = iter.next().map(String::as_str);
+
+        let mut db_path = "blockchain_node_0.db".to_string();
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+        let mut target = None;
+        let mut ancestors = 0usize;
+        let mut filters = SearchFilters::default();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--node" => {
+                    let node: usize = flag_value(arg, &mut iter)?
+                        .parse()
+                        .map_err(|_| "--node expects a node id".to_string())?;
+                    db_path = format!("blockchain_node_{}.db", node);
+                }
+                "--db" => db_path = flag_value(arg, &mut iter)?.to_string(),
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                "--ancestors" => {
+                    ancestors = flag_value(arg, &mut iter)?
+                        .parse()
+                        .map_err(|_| "--ancestors expects a number".to_string())?;
+                }
+                "--asset" => filters.asset = Some(flag_value(arg, &mut iter)?.to_uppercase()),
+                "--source" => filters.source = Some(flag_value(arg, &mut iter)?.to_string()),
+                "--from" => {
+                    filters.from_timestamp = Some(parse_time(flag_value(arg, &mut iter)?, false)?)
+                }
+                "--to" => {
+                    filters.to_timestamp = Some(parse_time(flag_value(arg, &mut iter)?, true)?)
+                }
+                "--annotation" => {
+                    let value = flag_value(arg, &mut iter)?;
+                    let (key, value) = value
+                        .split_once('=')
+                        .ok_or("--annotation expects KEY=VALUE")?;
+                    filters.annotation = Some((key.to_string(), value.to_string()));
+                }
+                "--limit" => {
+                    filters.limit = Some(
+                        flag_value(arg, &mut iter)?
+                            .parse()
+                            .map_err(|_| "--limit expects a number".to_string())?,
+                    );
+                }
+                other if other.starts_with("--") => {
+                    return Err(format!("Unknown option '{}'", other))
+                }
+                other if target.is_none() => target = Some(BlockRef::parse(other)),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+
+        let command = match subcommand {
+            Some("show") => ChainCommand::Show {
+                target: target.ok_or("chain show needs a block index or hash")?,
+                ancestors,
+            },
+            Some("search") => {
+                if target.is_some() {
+                    return Err("chain search takes no positional arguments".to_string());
+                }
+                ChainCommand::Search(filters)
+            }
+            Some(other) => return Err(format!("Unknown chain command '{}'", other)),
+            None => return Err("Missing chain command".to_string()),
+        };
+
+        Ok(ChainArgs {
+            command,
+            db_path,
+            format,
+            color,
+        })
+    }
+}
+
+/// Parse a unix timestamp, RFC 3339 time or plain date into unix milliseconds.
+/// `end_of_day` makes a plain date cover the whole day (for upper bounds).
+pub fn parse_time(s: &str, end_of_day: bool) -> Result<i64, String> {
+    if let Ok(ts) = s.parse::<i64>() {
+        return Ok(timestamp_to_millis(ts));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.timestamp_millis());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let time = if end_of_day {
+            date.and_hms_milli_opt(23, 59, 59, 999)
+        } else {
+            date.and_hms_milli_opt(0, 0, 0, 0)
+        };
+        if let Some(dt) = time {
+            return Ok(dt.and_utc().timestamp_millis());
+        }
+    }
+    Err(format!(
+        "Invalid time '{}': expected unix milliseconds, RFC 3339 or YYYY-MM-DD",
+        s
+    ))
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match ChainArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+
+    let db = open_ledger(&args.db_path)?;
+    let palette = Palette::detect(args.color);
+
+    let output = match &args.command {
+        ChainCommand::Show { target, ancestors } => {
+            let block = match target {
+                BlockRef::Index(index) => db.get_block_by_index(*index)?,
+                BlockRef::Hash(hash) => db.get_block_by_hash(hash)?,
+            };
+            let mut blocks = vec![block];
+            let parents = db.get_ancestors(&blocks[0], *ancestors)?;
+            blocks.extend(parents);
+
+            match args.format {
+                OutputFormat::Json if *ancestors == 0 => serde_json::to_string_pretty(&blocks[0])?,
+                OutputFormat::Json => serde_json::to_string_pretty(&blocks)?,
+                OutputFormat::Table => blocks
+                    .iter()
+                    .map(|b| render_block(b, &palette))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            }
+        }
+        ChainCommand::Search(filters) => {
+            let blocks = db.search_blocks(&BlockQuery::from(filters))?;
+            match args.format {
+                OutputFormat::Json => serde_json::to_string_pretty(&blocks)?,
+                OutputFormat::Table => render_block_list(&blocks, &palette),
+            }
+        }
+    };
+
+    print_output(&output)
+}
+
+fn format_time(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp_millis(timestamp_to_millis(timestamp))
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[0..16.min(hash.len())]
+}
+
+/// Detailed view of a single block: header fields followed by its entries
+pub fn render_block(block: &Block, palette: &Palette) -> String {
+    let mut out = String::new();
+    out.push_str(&palette.bold(&format!("Block #{}", block.index)));
+    out.push('\n');
+    out.push_str(&format!("  hash       {}\n", palette.yellow(&block.hash)));
+    out.push_str(&format!("  content id {}\n", block.content_id()));
+    out.push_str(&format!("  previous   {}\n", block.previous_hash));
+    out.push_str(&format!(
+        "  timestamp  {} {}\n",
+        block.timestamp,
+        palette.gray(&format!("({} UTC)", format_time(block.timestamp)))
+    ));
+    out.push_str(&format!("  nonce      {}\n", block.nonce));
+    out.push_str(&format!("  entries    {}\n", block.data.len()));
+    for (key, value) in &block.annotations {
+        out.push_str(&format!("  {:<10} {}\n", key, palette.gray(value)));
+    }
+    for book in &block.order_books {
+        out.push_str(&format!(
+            "  book       {} {} bid {:.2} / ask {:.2} ({} x {} levels)\n",
+            book.source,
+            book.asset,
+            book.best_bid().unwrap_or_default(),
+            book.best_ask().unwrap_or_default(),
+            book.bids.len(),
+            book.asks.len()
+        ));
+    }
+
+    if !block.data.is_empty() {
+        out.push('\n');
+        out.push_str(&palette.bold(&format!(
+            "  {:<8} {:>14}  {:<12} {}",
+            "ASSET", "PRICE", "SOURCE", "OBSERVED"
+        )));
+        out.push('\n');
+        for entry in &block.data {
+            out.push_str(&format!(
+                "  {:<8} {:>14.2}  {:<12} {}\n",
+                entry.asset,
+                entry.price,
+                entry.source,
+                format_time(entry.timestamp)
+            ));
+            if let Some(provenance) = &entry.provenance {
+                out.push_str(&palette.gray(&format!(
+                    "           from {} ({}) via {} [{}]",
+                    provenance.raw_price,
+                    provenance.raw_source,
+                    provenance.step_names().join(", "),
+                    provenance.validator
+                )));
+                out.push('\n');
+            }
+        }
+    }
+
+    out
+}
+
+/// One line per block, for search results
+pub fn render_block_list(blocks: &[Block], palette: &Palette) -> String {
+    if blocks.is_empty() {
+        return palette.gray("No blocks matched");
+    }
+
+    let mut out = palette.bold(&format!(
+        "{:<8} {:<23}  {:<16}  {}",
+        "INDEX", "TIMESTAMP (UTC)", "HASH", "ENTRIES"
+    ));
+    out.push('\n');
+
+    for block in blocks {
+        let entries = block
+            .data
+            .iter()
+            .map(|d| format!("{} {:.2} @{}", d.asset, d.price, d.source))
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push_str(&format!(
+            "{} {:<23}  {}  {}\n",
+            palette.green(&format!("{:<8}", block.index)),
+            format_time(block.timestamp),
+            palette.yellow(&format!("{:<16}", short_hash(&block.hash))),
+            entries
+        ));
+    }
+
+    out.push_str(&palette.gray(&format!("{} block(s)", blocks.len())));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sample_block() -> Block {
+        let entry = testing::entry(
+            "BTC",
+            "CoinGecko",
+            Decimal::new(500005, 1),
+            testing::BASE_TIMESTAMP_MS + 250,
+        );
+        testing::block(7, "0000_genesis", vec![entry])
+    }
+
+    #[test]
+    fn test_parse_show_by_index_and_hash() {
+        let parsed = ChainArgs::parse(&args(&["show", "42", "--ancestors", "3"])).unwrap();
+        assert_eq!(
+            parsed.command,
+            ChainCommand::Show {
+                target: BlockRef::Index(42),
+                ancestors: 3
+            }
+        );
+        assert_eq!(parsed.db_path, "blockchain_node_0.db");
+
+        let hash = "ab".repeat(32);
+        let parsed = ChainArgs::parse(&args(&["show", &hash, "--node", "2", "--json"])).unwrap();
+        assert_eq!(
+            parsed.command,
+            ChainCommand::Show {
+                target: BlockRef::Hash(hash),
+                ancestors: 0
+            }
+        );
+        assert_eq!(parsed.db_path, "blockchain_node_2.db");
+        assert_eq!(parsed.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn test_parse_search_filters() {
+        let parsed = ChainArgs::parse(&args(&[
+            "search",
+            "--asset",
+            "btc",
+            "--from",
+            "2024-01-01",
+            "--to",
+            "2024-01-01",
+            "--limit",
+            "10",
+            "--annotation",
+            "experiment=fx-7",
+            "--no-color",
+        ]))
+        .unwrap();
+
+        match parsed.command {
+            ChainCommand::Search(filters) => {
+                assert_eq!(filters.asset.as_deref(), Some("BTC"));
+                assert_eq!(filters.from_timestamp, Some(1_704_067_200_000));
+                assert_eq!(filters.to_timestamp, Some(1_704_067_200_000 + 86_399_999));
+                assert_eq!(filters.limit, Some(10));
+                assert_eq!(
+                    filters.annotation,
+                    Some(("experiment".to_string(), "fx-7".to_string()))
+                );
+            }
+            other => panic!("Expected search command, got {:?}", other),
+        }
+        assert_eq!(parsed.color, Some(false));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(ChainArgs::parse(&args(&[])).is_err());
+        assert!(ChainArgs::parse(&args(&["show"])).is_err());
+        assert!(ChainArgs::parse(&args(&["search", "--bogus"])).is_err());
+        assert!(ChainArgs::parse(&args(&["search", "--limit"])).is_err());
+        assert!(ChainArgs::parse(&args(&["show", "1", "--format", "xml"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_time_formats() {
+        assert_eq!(
+            parse_time("1700000000123", false).unwrap(),
+            1_700_000_000_123
+        );
+        // Second-precision input is upgraded like legacy stored timestamps
+        assert_eq!(parse_time("1700000000", false).unwrap(), 1_700_000_000_000);
+        assert_eq!(
+            parse_time("2023-11-14T22:13:20.5Z", false).unwrap(),
+            1_700_000_000_500
+        );
+        assert!(parse_time("yesterday", false).is_err());
+    }
+
+    #[test]
+    fn test_render_block_without_color() {
+        let block = sample_block();
+        let rendered = render_block(&block, &Palette::new(false));
+
+        assert!(rendered.starts_with("Block #7"));
+        assert!(rendered.contains(&block.hash));
+        assert!(rendered.contains("50000.50"));
+        assert!(rendered.contains("CoinGecko"));
+        assert!(rendered.contains("2023-11-14 22:13:20.250"));
+        assert!(!rendered.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_render_block_list() {
+        let palette = Palette::new(false);
+        assert_eq!(render_block_list(&[], &palette), "No blocks matched");
+
+        let rendered = render_block_list(&[sample_block()], &palette);
+        assert!(rendered.contains("BTC 50000.50 @CoinGecko"));
+        assert!(rendered.ends_with("1 block(s)"));
+
+        let colored = render_block_list(&[sample_block()], &Palette::new(true));
+        assert!(colored.contains('\x1b'));
+    }
+}
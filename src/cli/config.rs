@@ -0,0 +1,1009 @@
+//! Configuration checks: `config validate`
+//!
+//! ```text
+//! config validate [--env-file PATH] [--node N] [--port P] [--json]
+//! ```
+//!
+//! Loads the env file the node would read (default `.env`; variables already
+//! set in the environment win, as they do for the node) and checks it for
+//! mistakes that would otherwise surface only once the node has joined the
+//! cluster: the node missing from its own peer list, ports used twice,
+//! quorums that cannot form or can miss each other, Flexible Paxos sizes
+//! with Q1 + Q2 <= N, settings that do not parse, and a data directory the
+//! node cannot write its ledger to. Exits non-zero when any check fails.
+
+use crate::cli::{flag_value, print_output, Palette};
+use crate::consensus::algorithms::flexible_paxos;
+use crate::consensus::algorithms::pbft::observers_from_env;
+use crate::consensus::quorum::{self, DomainQuorum, FailureDomains, QuorumPolicy};
+use crate::etl::encryption::PayloadCipher;
+use crate::etl::extract::{
+    cache_ttl_from_env, max_concurrency_from_env, retry_policy_from_env, FileSource,
+    HttpClientConfig,
+};
+use crate::etl::group_commit::GroupCommitConfig;
+use crate::etl::guardrails::StorageLimits;
+use crate::etl::order_book::OrderBookConfig;
+use crate::etl::schedule::ExtractionSchedule;
+use crate::etl::sources::SourceRegistry;
+use crate::etl::transform::AssetSettings;
+use crate::etl::validator::Validator;
+use crate::etl::{self, stream};
+use crate::features::FeatureFlags;
+use crate::network;
+use crate::network::membership::{self, ClusterMembership};
+use crate::network::oracle::OracleSigner;
+use crate::network::peer_addr::{bind_ip_from_env, PeerAddr};
+use crate::network::rbac::AccessPolicy;
+use crate::network::sync::Checkpoint;
+use crate::network::tenancy::TenantRegistry;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const USAGE: &str = "Usage:
+  config validate [OPTIONS]
+
+Options:
+  --env-file PATH       env file to load (default .env)
+  --node N              node id to check the config for (default 0)
+  --port P              port the node will listen on (default: its port in
+                        NODE_ADDRESSES)
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+/// Largest cluster whose quorum systems are checked exhaustively
+const MAX_ENUMERATED_NODES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// Outcome of one check, with a suggested fix when it did not pass
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub check: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    fn ok(check: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Ok,
+            check,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warning(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            check,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn error(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            check,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// The settings one node would start with
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub node_id: usize,
+    pub port: u16,
+    pub node_addresses: Vec<String>,
+    /// `PBFT_QUORUM_POLICY`
+    pub quorum_policy: Option<String>,
+    /// `PBFT_OBSERVERS`, or why it did not parse
+    pub observers: Result<Vec<usize>, String>,
+    /// `NODE_FAILURE_DOMAINS` / `QUORUM_MIN_DOMAINS`, or why they did not
+    /// parse
+    pub failure_domains: Result<Option<FailureDomains>, String>,
+    /// `FPAXOS_Q1` / `FPAXOS_Q2`, or why they are unusable
+    pub fpaxos_quorums: Result<(usize, usize), String>,
+    /// `PEER_ALLOWLIST`
+    pub peer_allowlist: Option<String>,
+    /// Directory the ledger is created in
+    pub data_dir: PathBuf,
+    /// Other files the node writes, e.g. `CONSENSUS_EVENT_LOG`
+    pub output_files: Vec<(&'static str, PathBuf)>,
+    /// Settings that failed to parse, by variable
+    pub invalid_settings: Vec<(&'static str, String)>,
+}
+
+impl NodeConfig {
+    /// Read the configuration from the environment, as `main` does
+    pub fn from_env(node_id: usize, port: u16) -> Self {
+        let (node_addresses, addresses_error) = match membership::node_addresses_from_env() {
+            Ok(addresses) => (addresses, None),
+            // Not checked against addresses the node would never use
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        let mut output_files = Vec::new();
+        if let Ok(path) = std::env::var("CONSENSUS_EVENT_LOG") {
+            if !path.is_empty() {
+                output_files.push((
+                    "CONSENSUS_EVENT_LOG",
+                    PathBuf::from(path.replace("{node}", &node_id.to_string())),
+                ));
+            }
+        }
+        if let Ok(path) = std::env::var("PID_FILE") {
+            if !path.is_empty() {
+                output_files.push(("PID_FILE", PathBuf::from(path)));
+            }
+        }
+
+        let mut invalid_settings = Vec::new();
+        let mut record = |var: &'static str, result: Result<(), String>| {
+            if let Err(e) = result {
+                invalid_settings.push((var, e));
+            }
+        };
+        record("NODE_ADDRESSES", addresses_error.map_or(Ok(()), Err));
+        record("BIND_ADDRESS", bind_ip_from_env().map(|_| ()));
+        record("NODE_FEATURES", FeatureFlags::from_env().map(|_| ()));
+        record(
+            "MARKET_DATA_SOURCE",
+            SourceRegistry::with_builtin()
+                .source_from_env(reqwest::Client::new())
+                .map(|_| ()),
+        );
+        record(
+            "MARKET_DATA_ASSETS",
+            SourceRegistry::with_builtin()
+                .assets_from_env(reqwest::Client::new())
+                .map(|_| ()),
+        );
+        record(
+            "EXTRACT_MAX_CONCURRENCY",
+            max_concurrency_from_env().map(|_| ()),
+        );
+        record("EXTRACT_CACHE_TTL_MS", cache_ttl_from_env().map(|_| ()));
+        record("EXTRACT_RETRY", retry_policy_from_env().map(|_| ()));
+        record("NETWORK_RETRY", network::peer_retry_policy().map(|_| ()));
+        let replays_file = std::env::var("MARKET_DATA_SOURCE").is_ok_and(|names| {
+            names
+                .split(',')
+                .any(|n| n.trim().eq_ignore_ascii_case("file"))
+        });
+        if replays_file {
+            record(
+                "MARKET_DATA_FILE",
+                FileSource::from_env().and_then(|source| source.load().map(|_| ())),
+            );
+        }
+        record(
+            "MARKET_DATA_PROXY",
+            HttpClientConfig::from_env()
+                .and_then(|config| config.build().map(|_| ()).map_err(|e| e.to_string())),
+        );
+        record(
+            "PAYLOAD_ENCRYPTION_KEYS",
+            PayloadCipher::from_env().map(|_| ()),
+        );
+        record(
+            "NODE_SIGNING_KEY",
+            OracleSigner::from_env(node_id).map(|_| ()),
+        );
+        record("MARKET_DATA_STREAM", stream::from_env().map(|_| ()));
+        record("BLOCK_ANNOTATIONS", etl::annotations_from_env().map(|_| ()));
+        record("ORDER_BOOK_SOURCE", OrderBookConfig::from_env().map(|_| ()));
+        record("ASSET_SYMBOLS", Validator::from_env().map(|_| ()));
+        record(
+            "ASSET_DECIMALS",
+            AssetSettings::parse(std::env::var("ASSET_DECIMALS").ok().as_deref(), None, None)
+                .map(|_| ()),
+        );
+        record(
+            "ASSET_DEDUP_WINDOWS",
+            AssetSettings::parse(
+                None,
+                std::env::var("ASSET_DEDUP_WINDOWS").ok().as_deref(),
+                None,
+            )
+            .map(|_| ()),
+        );
+        record(
+            "ASSET_NORMALIZATION",
+            AssetSettings::parse(
+                None,
+                None,
+                std::env::var("ASSET_NORMALIZATION").ok().as_deref(),
+            )
+            .map(|_| ()),
+        );
+        record(
+            "NORMALIZATION",
+            crate::etl::pipeline::Normalization::from_env().map(|_| ()),
+        );
+        record(
+            "OFFLINE_SCENARIO",
+            crate::etl::stress::StressSource::from_env().map(|_| ()),
+        );
+        record(
+            "VALIDATION_PROFILE",
+            crate::etl::profile::ValidationProfile::from_env().map(|_| ()),
+        );
+        record(
+            "DEDUP_STRATEGY",
+            crate::etl::pipeline::DedupStrategy::from_env().map(|_| ()),
+        );
+        record(
+            "CONVERSION_CURRENCY",
+            crate::etl::conversion::ConversionStage::from_env().map(|_| ()),
+        );
+        record(
+            "ANOMALY_DETECTION",
+            crate::etl::anomaly::AnomalyStage::from_env().map(|_| ()),
+        );
+        record(
+            "TIME_BUCKET_SECS",
+            crate::etl::bucket::BucketStage::from_env().map(|_| ()),
+        );
+        record(
+            "CONSOLIDATION_METHOD",
+            crate::etl::consolidate::ConsolidateStage::from_env().map(|_| ()),
+        );
+        record(
+            "INDICATOR_WINDOW",
+            crate::etl::indicators::IndicatorStage::from_env().map(|_| ()),
+        );
+        record("EXTRACT_SCHEDULE", ExtractionSchedule::validate_env());
+        #[cfg(feature = "grpc")]
+        record(
+            "GRPC_PORT",
+            crate::network::grpc::GrpcConfig::from_env().map(|_| ()),
+        );
+        record(
+            "POSTGRES_NOTIFY_URL",
+            crate::etl::pg_notify::NotifyConfig::from_env().map(|_| ()),
+        );
+        record(
+            "MAX_REORG_DEPTH",
+            crate::consensus::fork_choice::max_reorg_depth_from_env().map(|_| ()),
+        );
+        record(
+            "FINALITY_DEPTH",
+            crate::consensus::finality::FinalityRule::from_env(false).map(|_| ()),
+        );
+        record("CHECKPOINT", Checkpoint::from_env().map(|_| ()));
+        record("TENANT_API_KEYS", TenantRegistry::from_env().map(|_| ()));
+        record(
+            "FORWARD_TO_PRIMARY",
+            crate::network::forwarding::Mempool::from_env().map(|_| ()),
+        );
+        record("API_KEYS", AccessPolicy::from_env().map(|_| ()));
+        record("DISK_MIN_FREE_MB", StorageLimits::from_env().map(|_| ()));
+        record(
+            "GROUP_COMMIT_MAX_DELAY_MS",
+            GroupCommitConfig::from_env().map(|_| ()),
+        );
+
+        NodeConfig {
+            node_id,
+            port,
+            fpaxos_quorums: flexible_paxos::quorum_sizes_from_env(node_addresses.len()),
+            node_addresses,
+            quorum_policy: std::env::var("PBFT_QUORUM_POLICY").ok(),
+            observers: observers_from_env(),
+            failure_domains: FailureDomains::from_env(),
+            peer_allowlist: std::env::var("PEER_ALLOWLIST")
+                .ok()
+                .filter(|s| !s.trim().is_empty()),
+            data_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            output_files,
+            invalid_settings,
+        }
+    }
+}
+
+/// Run every check against `config`
+pub fn validate(config: &NodeConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // Empty when NODE_ADDRESSES did not parse, which check_settings reports
+    if !config.node_addresses.is_empty() {
+        check_cluster(config, &mut diagnostics);
+        check_quorum(config, &mut diagnostics);
+        check_failure_domains(config, &mut diagnostics);
+        check_flexible_paxos(config, &mut diagnostics);
+        check_allowlist(config, &mut diagnostics);
+    }
+    check_settings(config, &mut diagnostics);
+    check_storage(config, &mut diagnostics);
+    diagnostics
+}
+
+fn port_of(address: &str) -> Option<u16> {
+    address
+        .parse::<PeerAddr>()
+        .ok()
+        .map(|address| address.port())
+}
+
+/// The node is in its own peer list at its port, and no port is used twice
+fn check_cluster(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    let total = config.node_addresses.len();
+    match config.node_addresses.get(config.node_id) {
+        None => out.push(Diagnostic::error(
+            "cluster",
+            format!(
+                "node {} is not in the peer list (node ids 0..{})",
+                config.node_id, total
+            ),
+            format!("start the node with an id below {}", total),
+        )),
+        Some(address) if port_of(address) != Some(config.port) => out.push(Diagnostic::error(
+            "cluster",
+            format!(
+                "node {} will listen on port {} but peers expect it at {}",
+                config.node_id, config.port, address
+            ),
+            format!(
+                "start the node on port {}",
+                port_of(address).map_or("?".to_string(), |p| p.to_string())
+            ),
+        )),
+        Some(address) => out.push(Diagnostic::ok(
+            "cluster",
+            format!(
+                "node {} is {} in a cluster of {} nodes",
+                config.node_id, address, total
+            ),
+        )),
+    }
+
+    let mut by_address: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (id, address) in config.node_addresses.iter().enumerate() {
+        by_address.entry(address.as_str()).or_default().push(id);
+    }
+    let duplicates: Vec<String> = by_address
+        .iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(address, ids)| format!("{} (nodes {:?})", address, ids))
+        .collect();
+    if duplicates.is_empty() {
+        out.push(Diagnostic::ok("ports", "every node has its own address"));
+    } else {
+        out.push(Diagnostic::error(
+            "ports",
+            format!(
+                "addresses shared by several nodes: {}",
+                duplicates.join(", ")
+            ),
+            "give every node its own host:port",
+        ));
+    }
+}
+
+/// What a quorum policy guarantees over the voting nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumAnalysis {
+    /// All voting nodes together form a quorum
+    pub reachable: bool,
+    /// Two disjoint sets of voting nodes that are both quorums, if any
+    pub disjoint: Option<(Vec<usize>, Vec<usize>)>,
+    /// Voting nodes that can fail while the rest still form a quorum
+    pub fault_tolerance: usize,
+}
+
+/// Check `policy` exhaustively over every subset of `voters`
+pub fn analyze_quorum(
+    policy: &dyn QuorumPolicy,
+    voters: &[usize],
+    total_nodes: usize,
+) -> QuorumAnalysis {
+    let members = |mask: u32| -> Vec<usize> {
+        (0..voters.len())
+            .filter(|bit| mask & (1 << bit) != 0)
+            .map(|bit| voters[bit])
+            .collect()
+    };
+    let all = (1u32 << voters.len()) - 1;
+    let is_quorum = |mask: u32| policy.is_quorum(&members(mask), total_nodes);
+
+    let mut disjoint = None;
+    let mut smallest_blocking = voters.len() + 1;
+    for mask in 0..=all {
+        if is_quorum(mask) {
+            if disjoint.is_none() && is_quorum(all & !mask) {
+                disjoint = Some((members(mask), members(all & !mask)));
+            }
+        } else {
+            smallest_blocking = smallest_blocking.min(voters.len() - mask.count_ones() as usize);
+        }
+    }
+    QuorumAnalysis {
+        reachable: is_quorum(all),
+        disjoint,
+        fault_tolerance: smallest_blocking.saturating_sub(1),
+    }
+}
+
+/// The PBFT quorum policy parses, can be met by the voting nodes, and any
+/// two quorums overlap
+fn check_quorum(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    let total = config.node_addresses.len();
+    let spec = config.quorum_policy.as_deref().unwrap_or("classic");
+    let policy = match quorum::parse_policy(spec) {
+        Ok(policy) => policy,
+        Err(e) => {
+            out.push(Diagnostic::error(
+                "quorum",
+                format!("PBFT_QUORUM_POLICY: {}", e),
+                "use classic, weighted[:THRESHOLD]:ID=W,... or grid:ROWSxCOLS",
+            ));
+            return;
+        }
+    };
+    let domains = config.failure_domains.clone().ok().flatten();
+    let policy: Arc<dyn QuorumPolicy> = match &domains {
+        Some(domains) => Arc::new(DomainQuorum::new(policy, domains.clone())),
+        None => policy,
+    };
+    let observers = match &config.observers {
+        Ok(observers) => observers.clone(),
+        Err(e) => {
+            out.push(Diagnostic::error(
+                "quorum",
+                format!("PBFT_OBSERVERS: {}", e),
+                "list observer node ids separated by commas",
+            ));
+            return;
+        }
+    };
+    if let Some(&id) = observers.iter().find(|&&id| id >= total) {
+        out.push(Diagnostic::warning(
+            "quorum",
+            format!(
+                "PBFT_OBSERVERS lists node {}, which is not in the cluster",
+                id
+            ),
+            format!("observer ids must be below {}", total),
+        ));
+    }
+    let voters: Vec<usize> = (0..total).filter(|id| !observers.contains(id)).collect();
+    if voters.is_empty() {
+        out.push(Diagnostic::error(
+            "quorum",
+            "every node is an observer, so no node can vote",
+            "remove at least one node from PBFT_OBSERVERS",
+        ));
+        return;
+    }
+    if voters.len() > MAX_ENUMERATED_NODES {
+        out.push(Diagnostic::warning(
+            "quorum",
+            format!(
+                "{} voting nodes are too many to check the {} policy exhaustively",
+                voters.len(),
+                policy.name()
+            ),
+            "check quorum intersection by hand",
+        ));
+        return;
+    }
+
+    // `PBFTManager` sizes quorums by the voting members once there are
+    // observers
+    let policy_total = if observers.is_empty() {
+        total
+    } else {
+        voters.len()
+    };
+    if let Err(e) = policy.check(policy_total) {
+        out.push(Diagnostic::error(
+            "quorum",
+            format!(
+                "the {} policy does not fit the cluster: {}",
+                policy.name(),
+                e
+            ),
+            "match the policy to the cluster, e.g. grid ROWS x COLS equal to the node count",
+        ));
+        return;
+    }
+    let analysis = analyze_quorum(policy.as_ref(), &voters, policy_total);
+    if !analysis.reachable {
+        out.push(Diagnostic::error(
+            "quorum",
+            format!(
+                "the {} policy is never met, even with all {} voting nodes {:?}",
+                policy.name(),
+                voters.len(),
+                voters
+            ),
+            "match the policy to the cluster, e.g. grid ROWS x COLS equal to the node count",
+        ));
+    } else if let Some((a, b)) = analysis.disjoint {
+        out.push(Diagnostic::error(
+            "quorum",
+            format!(
+                "the {} policy accepts disjoint quorums {:?} and {:?}, which can commit different blocks",
+                policy.name(),
+                a,
+                b
+            ),
+            "raise the threshold so that any two quorums share a node",
+        ));
+    } else if analysis.fault_tolerance == 0 && voters.len() > 1 {
+        out.push(Diagnostic::warning(
+            "quorum",
+            format!(
+                "the {} policy needs every one of the {} voting nodes",
+                policy.name(),
+                voters.len()
+            ),
+            "add nodes (PBFT tolerates f faults with 3f+1) or lower the threshold",
+        ));
+    } else {
+        out.push(Diagnostic::ok(
+            "quorum",
+            format!(
+                "{} policy over {} voting node(s) tolerates {} failure(s)",
+                policy.name(),
+                voters.len(),
+                analysis.fault_tolerance
+            ),
+        ));
+    }
+
+    // A quorum that needs every domain stops at the first domain outage
+    if let (true, Some(domains)) = (analysis.reachable, &domains) {
+        for domain in domains.domains(&voters) {
+            let remaining: Vec<usize> = voters
+                .iter()
+                .copied()
+                .filter(|&id| domains.domain_of(id) != Some(domain))
+                .collect();
+            if !policy.is_quorum(&remaining, policy_total) {
+                out.push(Diagnostic::warning(
+                    "quorum",
+                    format!(
+                        "losing {} {} leaves the other voting nodes {:?} without a quorum",
+                        domains.level(),
+                        domain,
+                        remaining
+                    ),
+                    "spread nodes over more domains than QUORUM_MIN_DOMAINS requires",
+                ));
+            }
+        }
+    }
+}
+
+/// `NODE_FAILURE_DOMAINS` parses and tags enough domains for
+/// `QUORUM_MIN_DOMAINS`
+fn check_failure_domains(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    let hint = "tag nodes as ID=region/zone/rack and set QUORUM_MIN_DOMAINS to [LEVEL:]K";
+    let domains = match &config.failure_domains {
+        Ok(Some(domains)) => domains,
+        Ok(None) => return,
+        Err(e) => {
+            out.push(Diagnostic::error("domains", e.clone(), hint));
+            return;
+        }
+    };
+    let total = config.node_addresses.len();
+    match domains.check(total) {
+        Ok(()) => out.push(Diagnostic::ok(
+            "domains",
+            format!(
+                "quorums span at least {} of {} {}s",
+                domains.min_domains(),
+                domains.domains(&(0..total).collect::<Vec<_>>()).len(),
+                domains.level()
+            ),
+        )),
+        Err(e) => out.push(Diagnostic::error("domains", e, hint)),
+    }
+}
+
+fn check_flexible_paxos(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    match &config.fpaxos_quorums {
+        Ok((q1, q2)) => out.push(Diagnostic::ok(
+            "fpaxos",
+            format!(
+                "Q1={} + Q2={} > {} nodes",
+                q1,
+                q2,
+                config.node_addresses.len()
+            ),
+        )),
+        Err(e) => out.push(Diagnostic::error(
+            "fpaxos",
+            e.clone(),
+            "set FPAXOS_Q1 and FPAXOS_Q2 so that Q1 + Q2 exceeds the node count",
+        )),
+    }
+}
+
+/// `PEER_ALLOWLIST`, when set, lists exactly the cluster's nodes
+fn check_allowlist(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    let Some(spec) = &config.peer_allowlist else {
+        return;
+    };
+    let hint = "list every node as node_id@host:port, matching the cluster addresses";
+    let membership = match ClusterMembership::parse(spec) {
+        Ok(membership) => membership,
+        Err(e) => {
+            out.push(Diagnostic::error(
+                "allowlist",
+                format!("PEER_ALLOWLIST: {}", e),
+                hint,
+            ));
+            return;
+        }
+    };
+    if membership.get(config.node_id).is_none() {
+        out.push(Diagnostic::error(
+            "allowlist",
+            format!(
+                "PEER_ALLOWLIST does not include this node ({})",
+                config.node_id
+            ),
+            hint,
+        ));
+    } else if let Err(e) = membership.verify_cluster(&config.node_addresses) {
+        out.push(Diagnostic::error(
+            "allowlist",
+            format!("PEER_ALLOWLIST: {}", e),
+            hint,
+        ));
+    } else {
+        out.push(Diagnostic::ok(
+            "allowlist",
+            format!("{} peers allowed, including this node", membership.len()),
+        ));
+    }
+}
+
+fn check_settings(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    for (var, e) in &config.invalid_settings {
+        out.push(Diagnostic::error(
+            "settings",
+            format!("{}: {}", var, e),
+            format!("fix or unset {} (see .env.example)", var),
+        ));
+    }
+}
+
+/// Create and remove a probe file to learn whether `dir` is writable
+fn probe_writable(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".config-validate-{}", std::process::id()));
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| e.to_string())
+}
+
+/// The ledger and every other output file can be written
+fn check_storage(config: &NodeConfig, out: &mut Vec<Diagnostic>) {
+    let ledger = config
+        .data_dir
+        .join(format!("blockchain_node_{}.db", config.node_id));
+    match probe_writable(&config.data_dir) {
+        Err(e) => out.push(Diagnostic::error(
+            "storage",
+            format!("cannot write to {}: {}", config.data_dir.display(), e),
+            "start the node from a writable directory",
+        )),
+        Ok(())
+            if ledger
+                .metadata()
+                .is_ok_and(|meta| meta.permissions().readonly()) =>
+        {
+            out.push(Diagnostic::error(
+                "storage",
+                format!("ledger {} is read-only", ledger.display()),
+                "make the ledger file writable",
+            ))
+        }
+        Ok(()) => out.push(Diagnostic::ok(
+            "storage",
+            format!("ledger {} is writable", ledger.display()),
+        )),
+    }
+
+    for (var, path) in &config.output_files {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => config.data_dir.join(dir),
+            _ => config.data_dir.clone(),
+        };
+        if let Err(e) = probe_writable(&dir) {
+            out.push(Diagnostic::error(
+                "storage",
+                format!("{}: cannot write to {}: {}", var, dir.display(), e),
+                format!("point {} at a writable directory", var),
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ValidateArgs {
+    env_file: String,
+    node_id: usize,
+    port: Option<u16>,
+    format: OutputFormat,
+    color: Option<bool>,
+}
+
+impl ValidateArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        let mut parsed = ValidateArgs {
+            env_file: ".env".to_string(),
+            node_id: 0,
+            port: None,
+            format: OutputFormat::Table,
+            color: None,
+        };
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--env-file" => parsed.env_file = flag_value(arg, &mut iter)?.to_string(),
+                "--node" => {
+                    parsed.node_id = flag_value(arg, &mut iter)?
+                        .parse()
+                        .map_err(|_| format!("{} expects a number", arg))?
+                }
+                "--port" => {
+                    parsed.port = Some(
+                        flag_value(arg, &mut iter)?
+                            .parse()
+                            .map_err(|_| format!("{} expects a port number", arg))?,
+                    )
+                }
+                "--format" => {
+                    parsed.format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => parsed.format = OutputFormat::Json,
+                "--color" => parsed.color = Some(true),
+                "--no-color" => parsed.color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("validate") => run_validate(&args[1..]),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn run_validate(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = ValidateArgs::parse(args).map_err(|e| format!("{}\n\n{}", e, USAGE))?;
+    let mut diagnostics = Vec::new();
+    if Path::new(&args.env_file).exists() {
+        if let Err(e) = dotenvy::from_path(&args.env_file) {
+            diagnostics.push(Diagnostic::error(
+                "env-file",
+                format!("cannot load {}: {}", args.env_file, e),
+                "check the file's KEY=VALUE syntax",
+            ));
+        }
+    } else {
+        diagnostics.push(Diagnostic::warning(
+            "env-file",
+            format!("{} not found, checking the environment only", args.env_file),
+            "pass --env-file PATH to check another file",
+        ));
+    }
+
+    let port = match args.port {
+        Some(port) => port,
+        None => membership::default_port(
+            &membership::node_addresses_from_env().unwrap_or_default(),
+            args.node_id,
+        )
+        .ok_or_else(|| format!("node {} has no default port; pass --port", args.node_id))?,
+    };
+    let config = NodeConfig::from_env(args.node_id, port);
+    diagnostics.extend(validate(&config));
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&diagnostics)?,
+        OutputFormat::Table => render_diagnostics(&diagnostics, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!("configuration has {} error(s)", errors).into());
+    }
+    Ok(())
+}
+
+pub fn render_diagnostics(diagnostics: &[Diagnostic], palette: &Palette) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let label = match diagnostic.severity {
+            Severity::Ok => palette.green("ok   "),
+            Severity::Warning => palette.yellow("warn "),
+            Severity::Error => palette.yellow("error"),
+        };
+        out.push_str(&format!(
+            "{} {:<10} {}\n",
+            label, diagnostic.check, diagnostic.message
+        ));
+        if let Some(hint) = &diagnostic.hint {
+            out.push_str(&format!(
+                "{:16} {}\n",
+                "",
+                palette.gray(&format!("hint: {}", hint))
+            ));
+        }
+    }
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    };
+    out.push_str(&format!(
+        "{} error(s), {} warning(s)",
+        count(Severity::Error),
+        count(Severity::Warning)
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::quorum::parse_policy;
+
+    fn cluster_config() -> NodeConfig {
+        NodeConfig {
+            node_id: 1,
+            port: 8001,
+            node_addresses: membership::default_node_addresses(),
+            quorum_policy: None,
+            observers: Ok(Vec::new()),
+            failure_domains: Ok(None),
+            fpaxos_quorums: Ok((3, 2)),
+            peer_allowlist: None,
+            data_dir: std::env::temp_dir(),
+            output_files: Vec::new(),
+            invalid_settings: Vec::new(),
+        }
+    }
+
+    fn errors(config: &NodeConfig) -> Vec<(&'static str, String)> {
+        validate(config)
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| (d.check, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_reports_misconfiguration() {
+        assert_eq!(errors(&cluster_config()), Vec::new());
+
+        let mut config = cluster_config();
+        config.port = 9001;
+        config.node_addresses[3] = "127.0.0.1:8002".to_string();
+        config.quorum_policy = Some("grid:3x3".to_string());
+        config.fpaxos_quorums = flexible_paxos::check_quorum_sizes(4, 2, 2).map(|_| (2, 2));
+        config.peer_allowlist = Some("0@127.0.0.1:8000,2@127.0.0.1:8002".to_string());
+        config.data_dir = PathBuf::from("/nonexistent/ledger-dir");
+        let checks: Vec<&str> = errors(&config).iter().map(|(check, _)| *check).collect();
+        assert_eq!(
+            checks,
+            vec![
+                "cluster",
+                "ports",
+                "quorum",
+                "fpaxos",
+                "allowlist",
+                "storage"
+            ]
+        );
+        let rendered = render_diagnostics(&validate(&config), &Palette::new(false));
+        assert!(
+            rendered.contains("hint: start the node on port 8001"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.ends_with("6 error(s), 0 warning(s)"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_analyze_quorum() {
+        let voters = [0, 1, 2, 3];
+        let classic = analyze_quorum(parse_policy("classic").unwrap().as_ref(), &voters, 4);
+        assert!(classic.reachable && classic.disjoint.is_none());
+        assert_eq!(classic.fault_tolerance, 1);
+
+        // Half the weight is enough, so {0, 1} and {2, 3} are both quorums
+        let lax = parse_policy("weighted:0.49:0=1").unwrap();
+        let analysis = analyze_quorum(lax.as_ref(), &voters, 4);
+        assert_eq!(analysis.disjoint, Some((vec![0, 1], vec![2, 3])));
+
+        let grid = analyze_quorum(parse_policy("grid:2x2").unwrap().as_ref(), &voters, 4);
+        assert!(grid.reachable && grid.disjoint.is_none());
+        assert_eq!(grid.fault_tolerance, 1);
+
+        // With two observers, 2f+1 of the two voters is a single vote
+        let mut config = cluster_config();
+        config.observers = Ok(vec![2, 3]);
+        let diagnostics = validate(&config);
+        let quorum = diagnostics.iter().find(|d| d.check == "quorum").unwrap();
+        assert_eq!(quorum.severity, Severity::Error);
+        assert!(quorum.message.contains("[0] and [1]"), "{}", quorum.message);
+
+        config.quorum_policy = Some("weighted:0.9:0=1".to_string());
+        let diagnostics = validate(&config);
+        let quorum = diagnostics.iter().find(|d| d.check == "quorum").unwrap();
+        assert_eq!(quorum.severity, Severity::Warning);
+
+        // Two zones of two nodes: a 3-vote quorum survives neither outage
+        let mut config = cluster_config();
+        config.failure_domains =
+            FailureDomains::parse("0=eu/a,1=eu/a,2=eu/b,3=eu/b", None).map(Some);
+        let diagnostics = validate(&config);
+        let outages: Vec<&Diagnostic> = diagnostics
+            .iter()
+            .filter(|d| d.check == "quorum" && d.severity == Severity::Warning)
+            .collect();
+        assert_eq!(outages.len(), 2, "{:?}", diagnostics);
+        assert!(outages[0].message.contains("losing zone eu/a"));
+        config.failure_domains = FailureDomains::parse("0=eu/a", None).map(Some);
+        assert!(errors(&config).contains(&(
+            "domains",
+            "quorums must span 2 zones but the 4 nodes are tagged with 1".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_parse_validate_args() {
+        let args: Vec<String> = ["--env-file", "node.env", "--node", "2", "--json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let parsed = ValidateArgs::parse(&args).unwrap();
+        assert_eq!(parsed.env_file, "node.env");
+        assert_eq!((parsed.node_id, parsed.port), (2, None));
+        assert_eq!(parsed.format, OutputFormat::Json);
+        assert!(ValidateArgs::parse(&["--port".to_string(), "x".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Ledger maintenance: `db rebuild-derived`
+//!
+//! ```text
+//! db rebuild-derived [--node N | --db PATH] [--json]
+//! ```
+//!
+//! Regenerates everything derived from the blockchain table (indexes,
+//! account usage and the verification checkpoint) and checks the result
+//! against the blocks; see `DatabaseManager::rebuild_derived`. This is the
+//! recovery path when derived data gets out of sync. The ledger lock is
+//! taken first, so the command refuses to run while a node is using the
+//! ledger.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::{flag_value, open_ledger, print_output, Palette};
+use crate::etl::load::RebuildReport;
+use crate::etl::lock::LedgerLock;
+use std::error::Error;
+
+const USAGE: &str = "Usage:
+  db rebuild-derived [OPTIONS]
+
+Options:
+  --node N              rebuild blockchain_node_N.db (default 0)
+  --db PATH             rebuild an explicit database file
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbArgs {
+    pub db_path: String,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl DbArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args.first().map(String::as_str) {
+            Some("rebuild-derived") => {}
+            Some(other) => return Err(format!("Unknown db command '{}'", other)),
+            None => return Err("Missing db command".to_string()),
+        }
+        let mut iter = args[1..].iter();
+        let mut db_path = "blockchain_node_0.db".to_string();
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--node" => {
+                    let node = flag_value(arg, &mut iter)?
+                        .parse::<u64>()
+                        .map_err(|_| format!("{} expects a number", arg))?;
+                    db_path = format!("blockchain_node_{}.db", node);
+                }
+                "--db" => db_path = flag_value(arg, &mut iter)?.to_string(),
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+
+        Ok(DbArgs {
+            db_path,
+            format,
+            color,
+        })
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match DbArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+    let db = open_ledger(&args.db_path)?;
+    let _lock = LedgerLock::acquire(&args.db_path, false)?;
+    let report = db.rebuild_derived()?;
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        OutputFormat::Table => render_report(&report, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    if report.chain_verified {
+        Ok(())
+    } else {
+        Err("chain does not verify; its checkpoint was cleared (run `verify` for the first failure)".into())
+    }
+}
+
+pub fn render_report(report: &RebuildReport, palette: &Palette) -> String {
+    let mut out = format!(
+        "{} derived state from {} block(s), {} entries\n",
+        palette.bold("Rebuilt"),
+        report.blocks,
+        report.entries
+    );
+    out.push_str(&format!("Indexes:    {}\n", report.indexes.join(", ")));
+    out.push_str(&format!(
+        "Accounts:   {} ({} in fees)\n",
+        report.accounts, report.fees_paid
+    ));
+    out.push_str(&match report.checkpoint_index {
+        Some(index) => format!("Checkpoint: {} {}", index, palette.green("OK")),
+        None if report.chain_verified => "Checkpoint: none (empty chain)".to_string(),
+        None => format!(
+            "Checkpoint: {}",
+            palette.yellow("cleared, chain does not verify")
+        ),
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_db_args() {
+        let parsed = DbArgs::parse(&args(&["rebuild-derived", "--node", "2", "--json"])).unwrap();
+        assert_eq!(parsed.db_path, "blockchain_node_2.db");
+        assert_eq!(parsed.format, OutputFormat::Json);
+        assert!(DbArgs::parse(&args(&["compact"])).is_err());
+        assert!(DbArgs::parse(&args(&[])).is_err());
+
+        let report = RebuildReport {
+            blocks: 3,
+            entries: 3,
+            indexes: vec!["idx_hash".to_string()],
+            accounts: 1,
+            fees_paid: 15,
+            chain_verified: true,
+            checkpoint_index: Some(3),
+        };
+        let text = render_report(&report, &Palette::new(false));
+        assert!(text.contains("Checkpoint: 3 OK"), "{}", text);
+        assert!(text.contains("1 (15 in fees)"), "{}", text);
+    }
+}
@@ -0,0 +1,605 @@
+//! Bug report bundle: `diagnose`
+//!
+//! ```text
+//! diagnose [--node N] [--env-file PATH] [--db PATH] [--nodes ADDR,...]
+//!          [--api-key KEY] [--log PATH] [--log-lines N] [--output PATH]
+//! ```
+//!
+//! Gathers what a maintainer asks for first when a multi-node setup
+//! misbehaves into one JSON report:
+//!
+//! - the node's settings from the env file, secrets redacted, and the
+//!   `config validate` checks
+//! - chain stats, head and the last rolling verification checkpoint of the
+//!   local ledger
+//! - `/health` and `/head` of every node in the cluster, including each
+//!   node's rolling verification status
+//! - the consensus phases reached for the last rounds, from
+//!   `CONSENSUS_EVENT_LOG` when it is set
+//! - the last warnings and errors in the node's log (default
+//!   `node_N.log`, as written by `scripts/start_nodes.sh`)
+//!
+//! The report is printed, or written to `--output` with a short summary
+//! printed instead. Nothing is changed on the nodes or the ledger, and a
+//! part that cannot be gathered records why rather than failing the report.
+
+use crate::cli::config::{self, Diagnostic, NodeConfig, Severity};
+use crate::cli::{block_on, flag_value, open_ledger, print_output};
+use crate::consensus::algorithms::pbft::MessageType;
+use crate::consensus::event_log::{self, ConsensusEvent, LoggedEvent};
+use crate::etl::load::{DatabaseStats, VerificationCheckpoint};
+use crate::etl::now_millis;
+use crate::network::membership;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+  diagnose [OPTIONS]
+
+Options:
+  --node N              node to diagnose (default 0)
+  --env-file PATH       env file the node reads (default .env)
+  --db PATH             ledger file (default blockchain_node_N.db)
+  --nodes ADDR,...      nodes to query (default the node's peer list)
+  --api-key KEY         bearer key when the nodes enforce API_KEYS
+  --log PATH            node log to scan (default node_N.log)
+  --log-lines N         warnings and errors to include (default 50)
+  --output PATH         write the report there and print a summary";
+
+/// Consensus rounds summarized from the event log
+const RECENT_ROUNDS: usize = 5;
+
+/// Setting names whose values are left out of the report
+const SECRET_MARKERS: [&str; 6] = ["KEY", "TOKEN", "SECRET", "PASSWORD", "CREDENTIAL", "URL"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnoseArgs {
+    pub node_id: usize,
+    pub env_file: String,
+    pub db_path: Option<String>,
+    pub nodes: Option<Vec<String>>,
+    pub api_key: Option<String>,
+    pub log_path: Option<String>,
+    pub log_lines: usize,
+    pub output: Option<String>,
+}
+
+impl DiagnoseArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = DiagnoseArgs {
+            node_id: 0,
+            env_file: ".env".to_string(),
+            db_path: None,
+            nodes: None,
+            api_key: None,
+            log_path: None,
+            log_lines: 50,
+            output: None,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--node" => {
+                    parsed.node_id = flag_value(arg, &mut iter)?
+                        .parse()
+                        .map_err(|_| format!("{} expects a number", arg))?
+                }
+                "--env-file" => parsed.env_file = flag_value(arg, &mut iter)?.to_string(),
+                "--db" => parsed.db_path = Some(flag_value(arg, &mut iter)?.to_string()),
+                "--nodes" => {
+                    let nodes: Vec<String> = flag_value(arg, &mut iter)?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|n| !n.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    if nodes.is_empty() {
+                        return Err("--nodes expects at least one address".to_string());
+                    }
+                    parsed.nodes = Some(nodes);
+                }
+                "--api-key" => parsed.api_key = Some(flag_value(arg, &mut iter)?.to_string()),
+                "--log" => parsed.log_path = Some(flag_value(arg, &mut iter)?.to_string()),
+                "--log-lines" => {
+                    parsed.log_lines = flag_value(arg, &mut iter)?
+                        .parse()
+                        .map_err(|_| format!("{} expects a number", arg))?
+                }
+                "--output" => parsed.output = Some(flag_value(arg, &mut iter)?.to_string()),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    pub generated_at_ms: i64,
+    pub version: &'static str,
+    pub host: String,
+    pub node_id: usize,
+    pub config: ConfigSummary,
+    pub chain: ChainSummary,
+    pub peers: Vec<PeerHealth>,
+    pub consensus: Option<ConsensusSummary>,
+    pub logs: Option<LogExcerpt>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigSummary {
+    pub env_file: String,
+    pub env_file_found: bool,
+    /// Settings from the env file, with the value in effect
+    pub settings: BTreeMap<String, String>,
+    pub checks: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChainSummary {
+    pub db_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DatabaseStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_hash: Option<String>,
+    /// Tip the rolling verifier last confirmed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_verification: Option<VerificationCheckpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerHealth {
+    pub address: String,
+    pub node_id: usize,
+    pub reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsensusSummary {
+    pub event_log: String,
+    pub rounds: Vec<RoundStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How far one consensus sequence got, by distinct senders per phase
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RoundStatus {
+    pub sequence: u64,
+    pub view: u64,
+    pub pre_prepare: usize,
+    pub prepare: usize,
+    pub commit: usize,
+    pub view_change: usize,
+    pub quorum_reached: bool,
+    pub committed: bool,
+    /// `committed`, `rolled_back`, or the last phase a message was seen in
+    pub phase: &'static str,
+    pub last_event_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogExcerpt {
+    pub path: String,
+    /// Last warning and error lines, oldest first, without color codes
+    pub lines: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `value`, or a placeholder when `name` looks like it holds a secret
+pub fn redact(name: &str, value: &str) -> String {
+    let upper = name.to_ascii_uppercase();
+    if !value.is_empty() && SECRET_MARKERS.iter().any(|marker| upper.contains(marker)) {
+        "<redacted>".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The last `limit` sequences in `events`, oldest first
+pub fn summarize_rounds(events: &[LoggedEvent], limit: usize) -> Vec<RoundStatus> {
+    let mut senders: BTreeMap<u64, [BTreeSet<usize>; 4]> = BTreeMap::new();
+    let mut rounds: BTreeMap<u64, RoundStatus> = BTreeMap::new();
+    for logged in events {
+        let (sequence, round) = match &logged.event {
+            ConsensusEvent::Run { .. } => continue,
+            ConsensusEvent::Message {
+                message,
+                quorum_reached,
+            } => {
+                let round = rounds.entry(message.sequence).or_default();
+                let phase = match message.msg_type {
+                    MessageType::PrePrepare | MessageType::NewView => 0,
+                    MessageType::Prepare => 1,
+                    MessageType::Commit => 2,
+                    MessageType::ViewChange => 3,
+                };
+                senders.entry(message.sequence).or_default()[phase].insert(message.node_id);
+                round.view = round.view.max(message.view);
+                round.quorum_reached |= quorum_reached;
+                round.phase = ["pre_prepare", "prepare", "commit", "view_change"][phase];
+                (message.sequence, round)
+            }
+            ConsensusEvent::Committed { sequence, .. } => {
+                let round = rounds.entry(*sequence).or_default();
+                round.committed = true;
+                (*sequence, round)
+            }
+            ConsensusEvent::RolledBack { sequence, .. } => {
+                let round = rounds.entry(*sequence).or_default();
+                round.committed = false;
+                round.phase = "rolled_back";
+                (*sequence, round)
+            }
+        };
+        round.sequence = sequence;
+        round.last_event_ms = logged.at_ms;
+    }
+    let skip = rounds.len().saturating_sub(limit);
+    rounds
+        .into_values()
+        .skip(skip)
+        .map(|mut round| {
+            if let Some([pre_prepare, prepare, commit, view_change]) = senders.get(&round.sequence)
+            {
+                round.pre_prepare = pre_prepare.len();
+                round.prepare = prepare.len();
+                round.commit = commit.len();
+                round.view_change = view_change.len();
+            }
+            if round.committed {
+                round.phase = "committed";
+            }
+            round
+        })
+        .collect()
+}
+
+/// `line` without ANSI escape sequences
+pub fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// The last `limit` WARN and ERROR lines of `text`
+pub fn recent_problems(text: &str, limit: usize) -> Vec<String> {
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(strip_ansi)
+        .filter(|line| line.contains("ERROR") || line.contains("WARN"))
+        .collect();
+    lines.drain(..lines.len().saturating_sub(limit));
+    lines
+}
+
+fn summarize_config(env_file: &str, node_id: usize) -> ConfigSummary {
+    let mut checks = Vec::new();
+    let mut settings = BTreeMap::new();
+    let env_file_found = Path::new(env_file).exists();
+    if env_file_found {
+        match dotenvy::from_path_iter(env_file) {
+            Ok(items) => {
+                for (name, value) in items.flatten() {
+                    // The environment wins, as it does for the node
+                    let value = std::env::var(&name).unwrap_or(value);
+                    settings.insert(name.clone(), redact(&name, &value));
+                }
+            }
+            Err(e) => checks.push(Diagnostic {
+                severity: Severity::Error,
+                check: "env-file",
+                message: format!("cannot read {}: {}", env_file, e),
+                hint: None,
+            }),
+        }
+        // Loaded so the checks see what the node would
+        let _ = dotenvy::from_path(env_file);
+    }
+    let port = membership::default_port(
+        &membership::node_addresses_from_env().unwrap_or_default(),
+        node_id,
+    )
+    .unwrap_or(0);
+    checks.extend(config::validate(&NodeConfig::from_env(node_id, port)));
+    ConfigSummary {
+        env_file: env_file.to_string(),
+        env_file_found,
+        settings,
+        checks,
+    }
+}
+
+fn summarize_chain(db_path: &str) -> ChainSummary {
+    let mut summary = ChainSummary {
+        db_path: db_path.to_string(),
+        ..Default::default()
+    };
+    let result = open_ledger(db_path).and_then(|db| {
+        summary.stats = Some(db.get_stats()?);
+        summary.head_hash = db.get_latest_block()?.map(|head| head.hash);
+        summary.last_verification = db.get_verification_checkpoint()?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        summary.error = Some(e.to_string());
+    }
+    summary
+}
+
+async fn fetch_json(
+    client: &reqwest::Client,
+    url: String,
+    api_key: Option<&str>,
+) -> Result<Value, String> {
+    let mut request = client.get(url);
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let response = response.error_for_status().map_err(|e| e.to_string())?;
+    response.json().await.map_err(|e| e.to_string())
+}
+
+async fn peer_health(
+    client: &reqwest::Client,
+    node_id: usize,
+    address: &str,
+    api_key: Option<&str>,
+) -> PeerHealth {
+    let health = fetch_json(client, format!("http://{}/health", address), api_key).await;
+    let head = match &health {
+        Ok(_) => fetch_json(client, format!("http://{}/head", address), api_key)
+            .await
+            .ok(),
+        Err(_) => None,
+    };
+    PeerHealth {
+        address: address.to_string(),
+        node_id,
+        reachable: health.is_ok(),
+        error: health.as_ref().err().cloned(),
+        health: health.ok(),
+        head,
+    }
+}
+
+fn summarize_consensus(node_id: usize) -> Option<ConsensusSummary> {
+    let path = std::env::var("CONSENSUS_EVENT_LOG")
+        .ok()
+        .filter(|path| !path.is_empty())?
+        .replace("{node}", &node_id.to_string());
+    let (rounds, error) = match event_log::read_events(&path) {
+        Ok(events) => (summarize_rounds(&events, RECENT_ROUNDS), None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    Some(ConsensusSummary {
+        event_log: path,
+        rounds,
+        error,
+    })
+}
+
+fn excerpt_log(path: Option<&str>, node_id: usize, limit: usize) -> Option<LogExcerpt> {
+    let default = format!("node_{}.log", node_id);
+    let path = match path {
+        Some(path) => path.to_string(),
+        None if Path::new(&default).exists() => default,
+        None => return None,
+    };
+    let (lines, error) = match std::fs::read(&path) {
+        Ok(bytes) => (
+            recent_problems(&String::from_utf8_lossy(&bytes), limit),
+            None,
+        ),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    Some(LogExcerpt { path, lines, error })
+}
+
+pub fn collect(args: &DiagnoseArgs) -> DiagnosticReport {
+    let config = summarize_config(&args.env_file, args.node_id);
+    let db_path = args
+        .db_path
+        .clone()
+        .unwrap_or_else(|| format!("blockchain_node_{}.db", args.node_id));
+    let nodes = args.nodes.clone().unwrap_or_else(|| {
+        membership::node_addresses_from_env()
+            .unwrap_or_else(|_| membership::default_node_addresses())
+    });
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(3))
+        .build()
+        .unwrap_or_default();
+    let peers = block_on(async {
+        let mut peers = Vec::with_capacity(nodes.len());
+        for (node_id, address) in nodes.iter().enumerate() {
+            peers.push(peer_health(&client, node_id, address, args.api_key.as_deref()).await);
+        }
+        peers
+    });
+    DiagnosticReport {
+        generated_at_ms: now_millis(),
+        version: env!("CARGO_PKG_VERSION"),
+        host: crate::logger::get_hostname().to_string(),
+        node_id: args.node_id,
+        config,
+        chain: summarize_chain(&db_path),
+        peers,
+        consensus: summarize_consensus(args.node_id),
+        logs: excerpt_log(args.log_path.as_deref(), args.node_id, args.log_lines),
+    }
+}
+
+/// A few lines pointing at the parts of `report` that need attention
+pub fn render_summary(report: &DiagnosticReport) -> String {
+    let mut out = String::new();
+    let problems = report
+        .config
+        .checks
+        .iter()
+        .filter(|check| check.severity != Severity::Ok)
+        .count();
+    out.push_str(&format!(
+        "config     {} setting(s), {} check(s) not ok\n",
+        report.config.settings.len(),
+        problems
+    ));
+    out.push_str(&match (&report.chain.stats, &report.chain.error) {
+        (Some(stats), _) => format!(
+            "chain      {} block(s), head {}\n",
+            stats.total_blocks,
+            stats.max_index.map_or("-".to_string(), |i| i.to_string())
+        ),
+        (None, error) => format!(
+            "chain      unavailable ({})\n",
+            error.as_deref().unwrap_or("?")
+        ),
+    });
+    let reachable = report.peers.iter().filter(|peer| peer.reachable).count();
+    out.push_str(&format!(
+        "peers      {} of {} reachable\n",
+        reachable,
+        report.peers.len()
+    ));
+    if let Some(consensus) = &report.consensus {
+        out.push_str(&match consensus.rounds.last() {
+            Some(round) => format!(
+                "consensus  sequence {} at {}\n",
+                round.sequence, round.phase
+            ),
+            None => "consensus  no rounds logged\n".to_string(),
+        });
+    }
+    if let Some(logs) = &report.logs {
+        out.push_str(&format!(
+            "logs       {} warning/error line(s) from {}\n",
+            logs.lines.len(),
+            logs.path
+        ));
+    }
+    out.trim_end().to_string()
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = DiagnoseArgs::parse(args).map_err(|e| format!("{}\n\n{}", e, USAGE))?;
+    let report = collect(&args);
+    let json = serde_json::to_string_pretty(&report)?;
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, json + "\n")?;
+            print_output(&format!(
+                "{}\n\nReport written to {}",
+                render_summary(&report),
+                path
+            ))
+        }
+        None => print_output(&json),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::PBFTMessage;
+    use crate::network::protocol::PROTOCOL_VERSION;
+
+    fn message(msg_type: MessageType, sequence: u64, node_id: usize) -> ConsensusEvent {
+        ConsensusEvent::Message {
+            message: PBFTMessage {
+                msg_type,
+                view: 0,
+                sequence,
+                block_hash: "h".to_string(),
+                block_data_json: None,
+                node_id,
+                timestamp: 0,
+                shard: None,
+                trace_id: None,
+                protocol_version: PROTOCOL_VERSION,
+                hlc: None,
+                prepared: None,
+                view_changes: Vec::new(),
+            },
+            quorum_reached: false,
+        }
+    }
+
+    #[test]
+    fn test_report_parts() {
+        let args: Vec<String> = ["--node", "2", "--log-lines", "5", "--output", "report.json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let parsed = DiagnoseArgs::parse(&args).unwrap();
+        assert_eq!(
+            (parsed.node_id, parsed.log_lines, parsed.output.as_deref()),
+            (2, 5, Some("report.json"))
+        );
+        assert!(DiagnoseArgs::parse(&["--verbose".to_string()]).is_err());
+
+        assert_eq!(redact("ADMIN_TOKEN", "hunter2"), "<redacted>");
+        assert_eq!(redact("NODE_SIGNING_KEY", ""), "");
+        assert_eq!(redact("VALIDATION_PROFILE", "strict"), "strict");
+
+        let events: Vec<LoggedEvent> = [
+            message(MessageType::PrePrepare, 1, 0),
+            message(MessageType::Prepare, 1, 1),
+            message(MessageType::Prepare, 1, 2),
+            ConsensusEvent::Committed {
+                sequence: 1,
+                shard: None,
+            },
+            message(MessageType::PrePrepare, 2, 0),
+            message(MessageType::Prepare, 2, 1),
+            message(MessageType::Prepare, 2, 1),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(seq, event)| LoggedEvent {
+            seq: seq as u64,
+            at_ms: seq as i64,
+            event,
+        })
+        .collect();
+        let rounds = summarize_rounds(&events, 5);
+        assert_eq!(rounds[0].phase, "committed");
+        assert_eq!(
+            (rounds[1].sequence, rounds[1].phase, rounds[1].prepare),
+            (2, "prepare", 1)
+        );
+        assert_eq!(summarize_rounds(&events, 1).len(), 1);
+
+        let log = "\x1b[2m2024\x1b[0m \x1b[33m WARN\x1b[0m slow peer\n INFO ok\n ERROR failed\n";
+        assert_eq!(
+            recent_problems(log, 5),
+            vec!["2024  WARN slow peer", " ERROR failed"]
+        );
+        assert_eq!(recent_problems(log, 1), vec![" ERROR failed"]);
+    }
+}
@@ -0,0 +1,214 @@
+//! Failover drill: `drill failover`
+//!
+//! ```text
+//! drill failover [--nodes N] [--rounds N] [--silence-round N] [--timeout-ms N] [--json]
+//! ```
+//!
+//! Silences the PBFT primary for one round and checks that the replicas
+//! change view and keep committing; see `consensus::drill`. The cluster
+//! shape comes from the node's configuration (`PBFT_OBSERVERS`,
+//! `PBFT_QUORUM_POLICY`). Exits non-zero when the drill fails.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::{block_on, flag_value, print_output, Palette};
+use crate::consensus::algorithms::pbft::observers_from_env;
+use crate::consensus::drill::{run_failover_drill, DrillConfig, DrillReport};
+use crate::consensus::quorum;
+use std::error::Error;
+use std::time::Duration;
+
+/// Nodes in the cluster `main` starts
+const DEFAULT_NODES: usize = 4;
+
+const USAGE: &str = "Usage:
+  drill failover [OPTIONS]
+
+Options:
+  --nodes N             cluster size (default 4)
+  --rounds N            consensus rounds to run (default 5)
+  --silence-round N     round whose primary is silenced (default 2)
+  --timeout-ms N        view change timeout (default 500)
+  --delay-ms N          simulated network delay per message (default 5)
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrillArgs {
+    pub nodes: usize,
+    pub rounds: u64,
+    pub silence_round: u64,
+    pub timeout: Duration,
+    pub delay: Duration,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl DrillArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        match iter.next().map(String::as_str) {
+            Some("failover") => {}
+            Some(other) => return Err(format!("Unknown drill '{}'", other)),
+            None => return Err("Missing drill name".to_string()),
+        }
+
+        let mut parsed = DrillArgs {
+            nodes: DEFAULT_NODES,
+            rounds: 5,
+            silence_round: 2,
+            timeout: Duration::from_millis(500),
+            delay: Duration::from_millis(5),
+            format: OutputFormat::Table,
+            color: None,
+        };
+        let number = |flag: &str, value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} expects a number", flag))
+        };
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--nodes" => parsed.nodes = number(arg, flag_value(arg, &mut iter)?)? as usize,
+                "--rounds" => parsed.rounds = number(arg, flag_value(arg, &mut iter)?)?,
+                "--silence-round" => {
+                    parsed.silence_round = number(arg, flag_value(arg, &mut iter)?)?
+                }
+                "--timeout-ms" => {
+                    parsed.timeout =
+                        Duration::from_millis(number(arg, flag_value(arg, &mut iter)?)?)
+                }
+                "--delay-ms" => {
+                    parsed.delay = Duration::from_millis(number(arg, flag_value(arg, &mut iter)?)?)
+                }
+                "--format" => {
+                    parsed.format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => parsed.format = OutputFormat::Json,
+                "--color" => parsed.color = Some(true),
+                "--no-color" => parsed.color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match DrillArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+    let config = DrillConfig::new(args.nodes)
+        .with_observers(observers_from_env()?)
+        .with_quorum_policy(quorum::policy_from_env()?)
+        .with_rounds(args.rounds, args.silence_round)
+        .with_view_change_timeout(args.timeout)
+        .with_message_delay(args.delay);
+    let report = block_on(run_failover_drill(&config));
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        OutputFormat::Table => render_report(&report, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    match report.failure {
+        None => Ok(()),
+        Some(failure) => Err(format!("failover drill failed: {}", failure).into()),
+    }
+}
+
+fn show_ms(ms: Option<u64>) -> String {
+    ms.map_or("-".to_string(), |ms| format!("{} ms", ms))
+}
+
+pub fn render_report(report: &DrillReport, palette: &Palette) -> String {
+    let status = if report.is_ok() {
+        palette.green("PASSED")
+    } else {
+        palette.yellow("FAILED")
+    };
+    let mut out = format!(
+        "{} {} node(s), {} voting, {} quorum: {}\n",
+        palette.bold("Failover drill"),
+        report.total_nodes,
+        report.voting_nodes,
+        report.quorum_policy,
+        status
+    );
+    let node = |id: Option<usize>| id.map_or("-".to_string(), |id| id.to_string());
+    out.push_str(&format!(
+        "Silenced primary {}, new primary {} (view {} -> {})\n",
+        node(report.silenced_primary),
+        node(report.new_primary),
+        report.view_before,
+        report.view_after
+    ));
+    out.push_str(&format!(
+        "Detected after {}, view changed after {}, recovered after {}\n",
+        show_ms(report.detection_ms),
+        show_ms(report.view_change_ms),
+        show_ms(report.recovery_ms)
+    ));
+    for round in &report.rounds {
+        let marker = if round.silenced {
+            palette.yellow("silenced")
+        } else {
+            palette.gray("normal")
+        };
+        out.push_str(&format!(
+            "  #{:<4} view {} primary {} {:>8} committed on {} node(s) in {} ms\n",
+            round.sequence,
+            round.view,
+            round.primary,
+            marker,
+            round.committed_nodes,
+            round.elapsed_ms
+        ));
+    }
+    out.push_str(&format!(
+        "{} round(s) committed after the failover",
+        report.commits_after_failover
+    ));
+    if let Some(failure) = &report.failure {
+        out.push_str(&format!("\n{} {}", palette.yellow("Failure:"), failure));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_drill_args() {
+        let parsed = DrillArgs::parse(&args(&[
+            "failover",
+            "--nodes",
+            "7",
+            "--silence-round",
+            "3",
+            "--timeout-ms",
+            "100",
+            "--json",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.nodes, 7);
+        assert_eq!((parsed.rounds, parsed.silence_round), (5, 3));
+        assert_eq!(parsed.timeout, Duration::from_millis(100));
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        assert!(DrillArgs::parse(&args(&["partition"])).is_err());
+        assert!(DrillArgs::parse(&args(&["failover", "--nodes"])).is_err());
+        assert!(DrillArgs::parse(&args(&["failover", "--rounds", "x"])).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+//! Historical replay: `replay --ledger`
+//!
+//! ```text
+//! replay --ledger --algorithm ALG [--algorithm ALG ...] [OPTIONS]
+//! ```
+//!
+//! Reads the blocks of an existing ledger, strips what the original
+//! consensus run added (nonce and hash, re-linking the chain over the bare
+//! contents) and feeds the same blocks through other algorithms, so commit
+//! behaviour and timing can be compared on identical inputs ("what if this
+//! ledger had been built with gossip?"). The ledger's recorded
+//! observed-to-committed latencies are shown alongside when available.
+//!
+//! Algorithms run in process with default parameters for `--nodes` nodes;
+//! see `ConsensusConfig::from_name`. `pbft` sends its messages to
+//! `127.0.0.1:8000..`, so expect it to be slow without a cluster there.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::{block_on, flag_value, open_ledger, print_output, Palette};
+use crate::consensus::comparison::{benchmark_consensus_strategy, ConsensusMetrics};
+use crate::consensus::scenario::{ConsensusConfig, NodesConfig};
+use crate::etl::sla::{CommitSla, LatencyReport};
+use crate::etl::Block;
+use serde::Serialize;
+use std::error::Error;
+
+const USAGE: &str = "Usage:
+  replay --ledger --algorithm ALG [--algorithm ALG ...] [OPTIONS]
+
+Algorithms: no_consensus, simple_majority, pbft, gossip, eventual,
+            quorumless, flexible_paxos
+
+Options:
+  --node N              read blockchain_node_N.db (default 0)
+  --db PATH             read an explicit database file
+  --nodes N             simulated cluster size (default 4)
+  --from N              first block index (default 1)
+  --limit N             number of blocks to replay (default all)
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+/// Blocks not committed by a replay are listed up to this many
+const MAX_LISTED_BLOCKS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerReplayArgs {
+    pub db_path: String,
+    pub algorithms: Vec<String>,
+    pub nodes: usize,
+    pub from: u64,
+    pub limit: Option<u64>,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl LedgerReplayArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        let mut db_path = "blockchain_node_0.db".to_string();
+        let mut algorithms = Vec::new();
+        let mut nodes = 4;
+        let mut from = 1;
+        let mut limit = None;
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+
+        let number = |flag: &str, value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} expects a number", flag))
+        };
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--ledger" => {}
+                "--algorithm" => algorithms.push(flag_value(arg, &mut iter)?.to_string()),
+                "--node" => {
+                    let node = number(arg, flag_value(arg, &mut iter)?)?;
+                    db_path = format!("blockchain_node_{}.db", node);
+                }
+                "--db" => db_path = flag_value(arg, &mut iter)?.to_string(),
+                "--nodes" => nodes = number(arg, flag_value(arg, &mut iter)?)? as usize,
+                "--from" => from = number(arg, flag_value(arg, &mut iter)?)?,
+                "--limit" => limit = Some(number(arg, flag_value(arg, &mut iter)?)?),
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+
+        if algorithms.is_empty() {
+            return Err("replay --ledger needs at least one --algorithm".to_string());
+        }
+        if nodes == 0 {
+            return Err("--nodes must be at least 1".to_string());
+        }
+        if let Some(unknown) = algorithms
+            .iter()
+            .find(|name| ConsensusConfig::from_name(name, nodes).is_none())
+        {
+            return Err(format!("Unknown algorithm '{}'", unknown));
+        }
+
+        Ok(LedgerReplayArgs {
+            db_path,
+            algorithms,
+            nodes,
+            from,
+            limit: limit.filter(|&n| n > 0),
+            format,
+            color,
+        })
+    }
+}
+
+/// Outcome of re-running one algorithm over the ledger
+#[derive(Debug, Clone, Serialize)]
+pub struct AlgorithmReplay {
+    pub algorithm: String,
+    /// Ledger blocks the algorithm did not commit, in index order
+    pub uncommitted: Vec<u64>,
+    pub metrics: ConsensusMetrics,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerReplayReport {
+    pub blocks: usize,
+    pub from_index: Option<u64>,
+    pub to_index: Option<u64>,
+    /// Observed-to-committed latency the ledger recorded for these blocks
+    pub recorded: LatencyReport,
+    pub replays: Vec<AlgorithmReplay>,
+}
+
+/// The blocks as consensus input: nonce cleared, hashes recomputed over the
+/// contents and the chain re-linked
+pub fn strip_proofs(blocks: &[Block]) -> Vec<Block> {
+    let mut stripped: Vec<Block> = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let mut bare = Block {
+            nonce: 0,
+            hash: String::new(),
+            ..block.clone()
+        };
+        if let Some(parent) = stripped.last() {
+            bare.previous_hash = parent.hash.clone();
+        }
+        bare.hash = bare.calculate_hash();
+        stripped.push(bare);
+    }
+    stripped
+}
+
+/// Run every algorithm in `configs` over `blocks` on a cluster of `nodes`
+pub async fn replay_blocks(
+    blocks: &[Block],
+    configs: &[ConsensusConfig],
+    nodes: usize,
+) -> Result<Vec<AlgorithmReplay>, String> {
+    let cluster = NodesConfig {
+        count: nodes,
+        ..NodesConfig::default()
+    };
+    let mut replays = Vec::new();
+    for config in configs {
+        let (algorithm, strategy, _) = config.build(&cluster)?;
+        let metrics = benchmark_consensus_strategy(strategy.clone(), blocks).await;
+        let uncommitted = blocks
+            .iter()
+            .map(|block| block.index)
+            .filter(|&index| !strategy.is_committed(index))
+            .collect();
+        replays.push(AlgorithmReplay {
+            algorithm,
+            uncommitted,
+            metrics,
+        });
+    }
+    Ok(replays)
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match LedgerReplayArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+
+    let db = open_ledger(&args.db_path)?;
+    let head = db.get_latest_block()?.map_or(0, |block| block.index);
+    let to = match args.limit {
+        Some(limit) => head.min(args.from.saturating_add(limit - 1)),
+        None => head,
+    };
+    let blocks = db.get_blocks_range(args.from, to)?;
+    if blocks.is_empty() {
+        return Err(format!("No blocks from index {} in {}", args.from, args.db_path).into());
+    }
+
+    let recorded: Vec<_> = db
+        .get_commit_latencies(head)?
+        .into_iter()
+        .filter(|latency| (args.from..=to).contains(&latency.block_index))
+        .collect();
+    let configs: Vec<ConsensusConfig> = args
+        .algorithms
+        .iter()
+        .filter_map(|name| ConsensusConfig::from_name(name, args.nodes))
+        .collect();
+
+    let replays = block_on(replay_blocks(&strip_proofs(&blocks), &configs, args.nodes))?;
+    let report = LedgerReplayReport {
+        blocks: blocks.len(),
+        from_index: blocks.first().map(|block| block.index),
+        to_index: blocks.last().map(|block| block.index),
+        recorded: CommitSla::default().summarize(&recorded),
+        replays,
+    };
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        OutputFormat::Table => render_report(&report, &Palette::detect(args.color)),
+    };
+    print_output(&output)
+}
+
+fn format_ms(ms: Option<f64>) -> String {
+    ms.map_or_else(|| "-".to_string(), |ms| format!("{:.1}", ms))
+}
+
+pub fn render_report(report: &LedgerReplayReport, palette: &Palette) -> String {
+    let mut out = format!(
+        "{} {} block(s), index {}..={}\n",
+        palette.bold("Replayed"),
+        report.blocks,
+        report.from_index.unwrap_or_default(),
+        report.to_index.unwrap_or_default()
+    );
+    out.push_str(&format!(
+        "Recorded commit latency: p50 {} ms, mean {} ms ({} block(s) measured)\n\n",
+        format_ms(report.recorded.p50_ms.map(|ms| ms as f64)),
+        format_ms(report.recorded.mean_ms),
+        report.recorded.blocks
+    ));
+    out.push_str(&format!(
+        "{:<24} {:>10} {:>8} {:>10} {:>10} {:>12}\n",
+        "Algorithm", "Committed", "Errors", "Avg(ms)", "Max(ms)", "Blocks/sec"
+    ));
+    for replay in &report.replays {
+        let m = &replay.metrics;
+        let committed = format!("{}/{}", m.committed_blocks, m.total_blocks);
+        let committed = if replay.uncommitted.is_empty() {
+            palette.green(&format!("{:>10}", committed))
+        } else {
+            palette.yellow(&format!("{:>10}", committed))
+        };
+        out.push_str(&format!(
+            "{:<24} {} {:>8} {:>10.1} {:>10} {:>12.1}\n",
+            replay.algorithm,
+            committed,
+            m.error_blocks,
+            m.avg_latency_ms,
+            m.max_latency_ms,
+            m.throughput_blocks_per_sec
+        ));
+    }
+    for replay in report.replays.iter().filter(|r| !r.uncommitted.is_empty()) {
+        let listed: Vec<String> = replay
+            .uncommitted
+            .iter()
+            .take(MAX_LISTED_BLOCKS)
+            .map(u64::to_string)
+            .collect();
+        let more = replay.uncommitted.len().saturating_sub(MAX_LISTED_BLOCKS);
+        out.push_str(&format!(
+            "\n{} not committed by {}: {}{}",
+            palette.yellow("Blocks"),
+            replay.algorithm,
+            listed.join(", "),
+            if more > 0 {
+                format!(" (+{} more)", more)
+            } else {
+                String::new()
+            }
+        ));
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestChainBuilder;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_ledger_replay_args() {
+        let parsed = LedgerReplayArgs::parse(&args(&[
+            "--ledger",
+            "--algorithm",
+            "gossip",
+            "--algorithm",
+            "flexible-paxos",
+            "--node",
+            "2",
+            "--limit",
+            "50",
+            "--json",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.algorithms, vec!["gossip", "flexible-paxos"]);
+        assert_eq!(parsed.db_path, "blockchain_node_2.db");
+        assert_eq!((parsed.from, parsed.limit), (1, Some(50)));
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        assert!(LedgerReplayArgs::parse(&args(&["--ledger"])).is_err());
+        assert!(
+            LedgerReplayArgs::parse(&args(&["--ledger", "--algorithm", "raft"]))
+                .unwrap_err()
+                .contains("raft")
+        );
+    }
+
+    #[test]
+    fn test_replay_strips_proofs_and_reruns_algorithms() {
+        let blocks = TestChainBuilder::new().with_blocks(5).build();
+        let stripped = strip_proofs(&blocks);
+        assert!(stripped.iter().all(|block| block.nonce == 0));
+        assert_eq!(stripped[0].hash, stripped[0].calculate_hash());
+        assert_eq!(stripped[1].previous_hash, stripped[0].hash);
+        assert_eq!(stripped[3].data[0].price, blocks[3].data[0].price);
+
+        let configs: Vec<ConsensusConfig> = ["none", "majority"]
+            .iter()
+            .map(|name| ConsensusConfig::from_name(name, 3).unwrap())
+            .collect();
+        let replays = block_on(replay_blocks(&stripped, &configs, 3)).unwrap();
+
+        assert_eq!(replays.len(), 2);
+        assert_eq!(replays[0].algorithm, "No-Consensus");
+        assert_eq!(replays[1].algorithm, "Simple Majority");
+        for replay in &replays {
+            assert_eq!(replay.metrics.total_blocks, 5);
+            assert_eq!(replay.metrics.committed_blocks, 5);
+            assert!(replay.uncommitted.is_empty());
+        }
+    }
+}
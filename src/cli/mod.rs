@@ -0,0 +1,148 @@
+//! Command-line subcommands
+//!
+//! These run against a node's local ledger and exit without starting the
+//! node itself, e.g. `cargo run -- chain show 42`.
+//!
+//! ## Structure
+//! - `chain.rs` - Block explorer (`chain show`, `chain search`)
+//! - `config.rs` - Configuration checks (`config validate`)
+//! - `db.rs` - Ledger maintenance (`db rebuild-derived`)
+//! - `diagnose.rs` - Bug report bundle of a node's state (`diagnose`)
+//! - `drill.rs` - Primary failover drill (`drill failover`)
+//! - `replay.rs` - Consensus event log replay (`replay <file>`)
+//! - `ledger_replay.rs` - Re-running other algorithms over a ledger
+//!   (`replay --ledger`)
+//! - `snapshot.rs` - Comparing two ledgers or block exports (`snapshot diff`)
+//! - `timeline.rs` - ASCII timeline of consensus rounds for `replay`
+//! - `topology.rs` - Cluster peer graph and message flow (`topology`)
+//! - `verify.rs` - Parallel full-chain verification (`verify`)
+
+pub mod chain;
+pub mod config;
+pub mod db;
+pub mod diagnose;
+pub mod drill;
+pub mod ledger_replay;
+pub mod replay;
+pub mod snapshot;
+pub mod timeline;
+pub mod topology;
+pub mod verify;
+
+use crate::etl::encryption::PayloadCipher;
+use crate::etl::load::DatabaseManager;
+use crate::network::redaction;
+use std::error::Error;
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// Run the subcommand named by `args[1]`, if any.
+///
+/// Returns `None` when the arguments do not start with a known subcommand so
+/// that `main` falls through to starting a node.
+pub fn dispatch(args: &[String]) -> Option<Result<(), Box<dyn Error>>> {
+    match args.get(1).map(String::as_str) {
+        Some("chain") => Some(chain::run(&args[2..])),
+        Some("config") => Some(config::run(&args[2..])),
+        Some("db") => Some(db::run(&args[2..])),
+        Some("diagnose") => Some(diagnose::run(&args[2..])),
+        Some("drill") => Some(drill::run(&args[2..])),
+        Some("replay") => Some(replay::run(&args[2..])),
+        Some("snapshot") => Some(snapshot::run(&args[2..])),
+        Some("topology") => Some(topology::run(&args[2..])),
+        Some("verify") => Some(verify::run(&args[2..])),
+        _ => None,
+    }
+}
+
+/// Open an existing ledger, with the payload and redaction keys from the
+/// environment
+pub fn open_ledger(db_path: &str) -> Result<DatabaseManager, Box<dyn Error>> {
+    if !Path::new(db_path).exists() {
+        return Err(format!("Database file not found: {}", db_path).into());
+    }
+    let db =
+        DatabaseManager::new(db_path)?.with_redaction_keys(redaction::trusted_keys_from_env()?);
+    Ok(match PayloadCipher::from_env()? {
+        Some(cipher) => db.with_payload_cipher(cipher),
+        None => db,
+    })
+}
+
+/// ANSI styling for terminal output, disabled when writing to a pipe or when
+/// `NO_COLOR` is set
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub fn new(enabled: bool) -> Self {
+        Palette { enabled }
+    }
+
+    /// Resolve an explicit `--color`/`--no-color` choice, falling back to
+    /// terminal detection
+    pub fn detect(choice: Option<bool>) -> Self {
+        let enabled = choice.unwrap_or_else(|| {
+            std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+        });
+        Palette::new(enabled)
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn green(&self, text: &str) -> String {
+        self.paint("1;92", text)
+    }
+
+    pub fn yellow(&self, text: &str) -> String {
+        self.paint("1;93", text)
+    }
+
+    pub fn gray(&self, text: &str) -> String {
+        self.paint("1;90", text)
+    }
+}
+
+/// Fetch the value following a flag, e.g. the `5` in `--limit 5`
+pub(crate) fn flag_value<'a>(
+    flag: &str,
+    iter: &mut impl Iterator<Item = &'a String>,
+) -> Result<&'a str, String> {
+    iter.next()
+        .map(String::as_str)
+        .ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+/// Drive an async task to completion from a synchronous subcommand, on the
+/// node's runtime when there is one
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => tokio::task::block_in_place(|| handle.block_on(future)),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start a tokio runtime")
+            .block_on(future),
+    }
+}
+
+/// Print command output, treating a closed pipe (e.g. `| head`) as success
+pub(crate) fn print_output(output: &str) -> Result<(), Box<dyn Error>> {
+    match writeln!(std::io::stdout(), "{}", output) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
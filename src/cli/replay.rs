@@ -0,0 +1,217 @@
+//! Consensus replay: `replay <event-log>`
+//!
+//! ```text
+//! replay <FILE> [--verbose] [--timeline] [--format table|json] [--color|--no-color]
+//! ```
+//!
+//! Re-feeds a log recorded with `CONSENSUS_EVENT_LOG` through a fresh PBFT
+//! state machine and exits non-zero if any outcome differs from the recording.
+//! `replay --ledger` instead re-runs other algorithms over a ledger's blocks;
+//! see `ledger_replay`.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::ledger_replay;
+use crate::cli::timeline::{render_timeline, DEFAULT_WIDTH};
+use crate::cli::{flag_value, print_output, Palette};
+use crate::consensus::event_log::{self, ConsensusEvent, LoggedEvent, ReplayReport};
+use std::error::Error;
+
+const USAGE: &str = "Usage:
+  replay <FILE> [OPTIONS]
+  replay --ledger --algorithm ALG [OPTIONS]   re-run algorithms over a ledger
+
+Options:
+  --verbose             print every event as it is replayed, then the timeline
+  --timeline            print an ASCII timeline of each consensus round
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayArgs {
+    pub path: String,
+    pub verbose: bool,
+    pub timeline: bool,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl ReplayArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        let mut path = None;
+        let mut verbose = false;
+        let mut timeline = false;
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--verbose" | "-v" => verbose = true,
+                "--timeline" => timeline = true,
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                other if other.starts_with("--") => {
+                    return Err(format!("Unknown option '{}'", other))
+                }
+                other if path.is_none() => path = Some(other.to_string()),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+
+        Ok(ReplayArgs {
+            path: path.ok_or("replay needs an event log file")?,
+            // Verbose mode shows the rounds as well as the raw events
+            timeline: timeline || verbose,
+            verbose,
+            format,
+            color,
+        })
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.iter().any(|arg| arg == "--ledger") {
+        return ledger_replay::run(args);
+    }
+    let args = match ReplayArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+
+    let events = event_log::read_events(&args.path)
+        .map_err(|e| format!("Cannot read event log {}: {}", args.path, e))?;
+    let palette = Palette::detect(args.color);
+
+    let mut trace = Vec::new();
+    let report = event_log::replay(&events, |entry, outcome| {
+        if args.verbose && args.format == OutputFormat::Table {
+            trace.push(render_event(entry, outcome, &palette));
+        }
+    })?;
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        OutputFormat::Table => {
+            if args.timeline {
+                trace.push(render_timeline(&events, DEFAULT_WIDTH, &palette));
+            }
+            trace.push(render_report(&report, &palette));
+            trace.join("\n")
+        }
+    };
+    print_output(&output)?;
+
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        Err(format!(
+            "replay diverged from the recording at {} event(s)",
+            report.divergences.len()
+        )
+        .into())
+    }
+}
+
+/// One line per replayed event
+pub fn render_event(entry: &LoggedEvent, outcome: bool, palette: &Palette) -> String {
+    let detail = match &entry.event {
+        ConsensusEvent::Run {
+            node_id,
+            total_nodes,
+            algorithm,
+            quorum_policy,
+            failure_domains,
+            ..
+        } => format!(
+            "run {} node {}/{} quorum {}{}",
+            algorithm,
+            node_id,
+            total_nodes,
+            quorum_policy.as_deref().unwrap_or("classic"),
+            if failure_domains.is_some() {
+                "+domains"
+            } else {
+                ""
+            }
+        ),
+        ConsensusEvent::Message { message, .. } => format!(
+            "{:?} seq={}{} view={} from node {} -> {}",
+            message.msg_type,
+            message.sequence,
+            shard_suffix(&message.shard),
+            message.view,
+            message.node_id,
+            if outcome { "quorum" } else { "pending" }
+        ),
+        ConsensusEvent::Committed { sequence, shard } => {
+            format!("committed seq={}{}", sequence, shard_suffix(shard))
+        }
+        ConsensusEvent::RolledBack { sequence, shard } => {
+            format!("rolled back seq={}{}", sequence, shard_suffix(shard))
+        }
+    };
+    format!("{} {}", palette.gray(&format!("#{:<6}", entry.seq)), detail)
+}
+
+fn shard_suffix(shard: &Option<String>) -> String {
+    shard
+        .as_ref()
+        .map(|s| format!(" shard={}", s))
+        .unwrap_or_default()
+}
+
+pub fn render_report(report: &ReplayReport, palette: &Palette) -> String {
+    let mut out = format!(
+        "Replayed {} message(s), {} commit(s)\n",
+        report.messages, report.commits
+    );
+    if report.is_consistent() {
+        out.push_str(&palette.green("Replay matches the recording"));
+    } else {
+        for divergence in &report.divergences {
+            out.push_str(&format!(
+                "{} event #{}: recorded {}, replayed {}\n",
+                palette.yellow("DIVERGED"),
+                divergence.seq,
+                divergence.expected,
+                divergence.actual
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_replay_args() {
+        let parsed = ReplayArgs::parse(&args(&["run.jsonl", "--verbose", "--json"])).unwrap();
+        assert_eq!(parsed.path, "run.jsonl");
+        assert!(parsed.verbose);
+        assert!(parsed.timeline);
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        let parsed = ReplayArgs::parse(&args(&["run.jsonl", "--timeline"])).unwrap();
+        assert!(!parsed.verbose);
+        assert!(parsed.timeline);
+
+        assert!(ReplayArgs::parse(&args(&[])).is_err());
+        assert!(ReplayArgs::parse(&args(&["a", "b"])).is_err());
+        assert!(ReplayArgs::parse(&args(&["a", "--bogus"])).is_err());
+    }
+}
@@ -0,0 +1,555 @@
+//! Snapshot comparison: `snapshot diff`
+//!
+//! ```text
+//! snapshot diff <A> <B> [--json]
+//! ```
+//!
+//! A snapshot is either a ledger database file or a JSON-lines export of
+//! blocks (what `DatabaseManager::export_chain` and the storage guardrail
+//! archives write); files ending in `.jsonl` or `.json` are read as exports.
+//! Both are walked in index order and compared block by block, treating `A`
+//! as the source: blocks only in `B` are reported as added, blocks only in
+//! `A` as missing, and indices present in both with different hashes as
+//! diverging. Every block is also re-hashed: one whose contents no longer
+//! hash to its stored hash is reported as corrupt, unless it is a ledger's
+//! redacted block (see `network::redaction`). Summary metadata (block counts,
+//! index range, head hash, block format and schema versions) is compared
+//! too. Exits non-zero when any block differs or is corrupt, so it can gate
+//! a restore or replica check.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::{flag_value, open_ledger, print_output, Palette};
+use crate::etl::load::DatabaseManager;
+use crate::etl::Block;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter::Peekable;
+
+const USAGE: &str = "Usage:
+  snapshot diff <A> <B> [OPTIONS]
+
+A and B are ledger database files or JSON-lines block exports (.jsonl).
+
+Options:
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+type BlockResult = Result<Block, Box<dyn Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotArgs {
+    pub a: String,
+    pub b: String,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl SnapshotArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        match iter.next().map(String::as_str) {
+            Some("diff") => {}
+            Some(other) => return Err(format!("Unknown snapshot command '{}'", other)),
+            None => return Err("Missing snapshot command".to_string()),
+        }
+
+        let mut paths = Vec::new();
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                other if other.starts_with("--") => {
+                    return Err(format!("Unexpected argument '{}'", other))
+                }
+                path => paths.push(path.to_string()),
+            }
+        }
+
+        let [a, b]: [String; 2] = paths
+            .try_into()
+            .map_err(|_| "snapshot diff expects two snapshots".to_string())?;
+        Ok(SnapshotArgs {
+            a,
+            b,
+            format,
+            color,
+        })
+    }
+}
+
+/// A snapshot opened for reading
+pub enum Snapshot {
+    Ledger(Box<DatabaseManager>),
+    Export(String),
+}
+
+impl Snapshot {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        if path.ends_with(".jsonl") || path.ends_with(".json") {
+            File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+            Ok(Snapshot::Export(path.to_string()))
+        } else {
+            Ok(Snapshot::Ledger(Box::new(open_ledger(path)?)))
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Snapshot::Ledger(_) => "ledger",
+            Snapshot::Export(_) => "export",
+        }
+    }
+
+    /// Blocks in index order
+    fn blocks(&self) -> Result<Box<dyn Iterator<Item = BlockResult> + '_>, Box<dyn Error>> {
+        match self {
+            Snapshot::Ledger(db) => Ok(Box::new(
+                db.iter_blocks(..).map(|block| block.map_err(Into::into)),
+            )),
+            Snapshot::Export(path) => {
+                let reader = BufReader::new(File::open(path)?);
+                let path = path.clone();
+                Ok(Box::new(
+                    reader
+                        .lines()
+                        .enumerate()
+                        .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+                        .map(move |(number, line)| {
+                            serde_json::from_str(&line?)
+                                .map_err(|e| format!("{} line {}: {}", path, number + 1, e).into())
+                        }),
+                ))
+            }
+        }
+    }
+
+    fn describe(&self, path: &str) -> Result<SnapshotMeta, Box<dyn Error>> {
+        let (schema_version, redactions, redacted) = match self {
+            Snapshot::Ledger(db) => (
+                Some(db.schema_version()?),
+                Some(db.get_redactions()?.len()),
+                db.get_redacted_hashes()?,
+            ),
+            Snapshot::Export(_) => (None, None, HashMap::new()),
+        };
+        Ok(SnapshotMeta {
+            path: path.to_string(),
+            kind: self.kind(),
+            schema_version,
+            redactions,
+            redacted,
+            ..Default::default()
+        })
+    }
+}
+
+/// Summary of one snapshot
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SnapshotMeta {
+    pub path: String,
+    pub kind: &'static str,
+    pub blocks: u64,
+    pub first_index: Option<u64>,
+    pub last_index: Option<u64>,
+    pub head_hash: Option<String>,
+    pub format_versions: BTreeSet<u32>,
+    /// Ledger files only
+    pub schema_version: Option<i64>,
+    pub redactions: Option<usize>,
+    /// Blocks whose contents do not hash to their stored hash
+    pub corrupt: Vec<IndexRange>,
+    /// Trusted redactions of a ledger: index -> hash of the tombstone
+    #[serde(skip)]
+    pub redacted: HashMap<u64, String>,
+}
+
+impl SnapshotMeta {
+    fn record(&mut self, block: &Block) {
+        let tombstone =
+            self.redacted.get(&block.index) == Some(&block.hash) && block.has_empty_payload();
+        if !tombstone && block.calculate_hash() != block.hash {
+            push_index(&mut self.corrupt, block.index);
+        }
+        self.blocks += 1;
+        self.first_index.get_or_insert(block.index);
+        self.last_index = Some(block.index);
+        self.head_hash = Some(block.hash.clone());
+        self.format_versions.insert(block.format_version);
+    }
+}
+
+/// Consecutive block indices, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IndexRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl IndexRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+fn push_index(ranges: &mut Vec<IndexRange>, index: u64) {
+    match ranges.last_mut() {
+        Some(range) if range.end + 1 == index => range.end = index,
+        _ => ranges.push(IndexRange {
+            start: index,
+            end: index,
+        }),
+    }
+}
+
+/// A summary field that differs between the snapshots
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetadataDifference {
+    pub field: &'static str,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub a: SnapshotMeta,
+    pub b: SnapshotMeta,
+    /// Indices present in both with the same hash
+    pub matching: u64,
+    /// Only in `B`
+    pub added: Vec<IndexRange>,
+    /// Only in `A`
+    pub missing: Vec<IndexRange>,
+    /// In both, with different hashes
+    pub diverging: Vec<IndexRange>,
+    pub metadata: Vec<MetadataDifference>,
+}
+
+impl SnapshotDiff {
+    /// Every block of `A` is in `B` with the same hash, nothing else is,
+    /// and every block hashes to its stored hash
+    pub fn is_identical(&self) -> bool {
+        self.added.is_empty()
+            && self.missing.is_empty()
+            && self.diverging.is_empty()
+            && self.a.corrupt.is_empty()
+            && self.b.corrupt.is_empty()
+    }
+}
+
+/// Next block, checking that indices strictly increase
+fn next_block(
+    blocks: &mut Peekable<impl Iterator<Item = BlockResult>>,
+    meta: &mut SnapshotMeta,
+) -> Result<Option<Block>, Box<dyn Error>> {
+    let Some(block) = blocks.next().transpose()? else {
+        return Ok(None);
+    };
+    if meta.last_index.is_some_and(|last| block.index <= last) {
+        return Err(format!(
+            "{} is not in index order at block {}",
+            meta.path, block.index
+        )
+        .into());
+    }
+    meta.record(&block);
+    Ok(Some(block))
+}
+
+/// Walk both block sequences in step
+pub fn diff_blocks(
+    a: impl Iterator<Item = BlockResult>,
+    b: impl Iterator<Item = BlockResult>,
+    a_meta: SnapshotMeta,
+    b_meta: SnapshotMeta,
+) -> Result<SnapshotDiff, Box<dyn Error>> {
+    let mut diff = SnapshotDiff {
+        a: a_meta,
+        b: b_meta,
+        ..Default::default()
+    };
+    let (mut a, mut b) = (a.peekable(), b.peekable());
+    let mut left = next_block(&mut a, &mut diff.a)?;
+    let mut right = next_block(&mut b, &mut diff.b)?;
+    loop {
+        match (&left, &right) {
+            (None, None) => break,
+            (Some(x), Some(y)) if x.index == y.index => {
+                if x.hash == y.hash {
+                    diff.matching += 1;
+                } else {
+                    push_index(&mut diff.diverging, x.index);
+                }
+                left = next_block(&mut a, &mut diff.a)?;
+                right = next_block(&mut b, &mut diff.b)?;
+            }
+            (Some(x), Some(y)) if x.index < y.index => {
+                push_index(&mut diff.missing, x.index);
+                left = next_block(&mut a, &mut diff.a)?;
+            }
+            (Some(x), None) => {
+                push_index(&mut diff.missing, x.index);
+                left = next_block(&mut a, &mut diff.a)?;
+            }
+            (_, Some(y)) => {
+                push_index(&mut diff.added, y.index);
+                right = next_block(&mut b, &mut diff.b)?;
+            }
+        }
+    }
+    diff.metadata = compare_metadata(&diff.a, &diff.b);
+    Ok(diff)
+}
+
+fn compare_metadata(a: &SnapshotMeta, b: &SnapshotMeta) -> Vec<MetadataDifference> {
+    fn show<T: std::fmt::Debug>(value: &Option<T>) -> String {
+        value.as_ref().map_or("-".to_string(), |v| {
+            format!("{:?}", v).trim_matches('"').to_string()
+        })
+    }
+    let mut fields = vec![
+        ("blocks", a.blocks.to_string(), b.blocks.to_string()),
+        ("first_index", show(&a.first_index), show(&b.first_index)),
+        ("last_index", show(&a.last_index), show(&b.last_index)),
+        ("head_hash", show(&a.head_hash), show(&b.head_hash)),
+        (
+            "format_versions",
+            format!("{:?}", a.format_versions),
+            format!("{:?}", b.format_versions),
+        ),
+    ];
+    // Exports carry neither, so only compare two ledgers
+    if a.schema_version.is_some() && b.schema_version.is_some() {
+        fields.push((
+            "schema_version",
+            show(&a.schema_version),
+            show(&b.schema_version),
+        ));
+        fields.push(("redactions", show(&a.redactions), show(&b.redactions)));
+    }
+    fields
+        .into_iter()
+        .filter(|(_, x, y)| x != y)
+        .map(|(field, a, b)| MetadataDifference { field, a, b })
+        .collect()
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match SnapshotArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+    let a = Snapshot::open(&args.a)?;
+    let b = Snapshot::open(&args.b)?;
+    let diff = diff_blocks(
+        a.blocks()?,
+        b.blocks()?,
+        a.describe(&args.a)?,
+        b.describe(&args.b)?,
+    )?;
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&diff)?,
+        OutputFormat::Table => render_diff(&diff, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    if diff.is_identical() {
+        Ok(())
+    } else {
+        Err("snapshots differ".into())
+    }
+}
+
+fn render_ranges(ranges: &[IndexRange]) -> String {
+    let blocks: u64 = ranges.iter().map(IndexRange::len).sum();
+    let listed: Vec<String> = ranges
+        .iter()
+        .map(|r| {
+            if r.start == r.end {
+                r.start.to_string()
+            } else {
+                format!("{}..={}", r.start, r.end)
+            }
+        })
+        .collect();
+    format!("{} block(s): {}", blocks, listed.join(", "))
+}
+
+pub fn render_diff(diff: &SnapshotDiff, palette: &Palette) -> String {
+    let mut out = String::new();
+    for (label, meta) in [("A", &diff.a), ("B", &diff.b)] {
+        out.push_str(&format!(
+            "{} {} ({}): {} block(s), index {}..={}\n",
+            palette.bold(label),
+            meta.path,
+            meta.kind,
+            meta.blocks,
+            meta.first_index.unwrap_or_default(),
+            meta.last_index.unwrap_or_default()
+        ));
+    }
+    let status = if diff.is_identical() {
+        palette.green("IDENTICAL")
+    } else {
+        palette.yellow("DIFFERENT")
+    };
+    out.push_str(&format!(
+        "{} {} matching block(s): {}",
+        palette.bold("Compared"),
+        diff.matching,
+        status
+    ));
+    for (label, ranges) in [
+        ("Added in B:", &diff.added),
+        ("Missing from B:", &diff.missing),
+        ("Diverging:", &diff.diverging),
+        ("Corrupt in A:", &diff.a.corrupt),
+        ("Corrupt in B:", &diff.b.corrupt),
+    ] {
+        if !ranges.is_empty() {
+            out.push_str(&format!(
+                "\n{} {}",
+                palette.yellow(label),
+                render_ranges(ranges)
+            ));
+        }
+    }
+    for difference in &diff.metadata {
+        out.push_str(&format!(
+            "\n{} {}: {} vs {}",
+            palette.gray("Metadata"),
+            difference.field,
+            difference.a,
+            difference.b
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing::TestChainBuilder;
+    use std::fs;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn chain(len: u64) -> Vec<Block> {
+        TestChainBuilder::new().with_blocks(len).build()
+    }
+
+    #[test]
+    fn test_parse_snapshot_args() {
+        let parsed = SnapshotArgs::parse(&args(&["diff", "a.db", "b.jsonl", "--json"])).unwrap();
+        assert_eq!((parsed.a.as_str(), parsed.b.as_str()), ("a.db", "b.jsonl"));
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        assert!(SnapshotArgs::parse(&args(&["diff", "a.db"])).is_err());
+        assert!(SnapshotArgs::parse(&args(&["merge", "a.db", "b.db"])).is_err());
+        assert!(SnapshotArgs::parse(&args(&["diff", "a.db", "b.db", "--bogus"])).is_err());
+    }
+
+    #[test]
+    fn test_diff_ledger_against_export() {
+        let dir =
+            std::env::temp_dir().join(format!("snapshot_diff_{}", crate::logger::new_trace_id()));
+        fs::create_dir_all(&dir).unwrap();
+        let test_db = dir.join("ledger.db");
+        let test_db = test_db.to_str().unwrap();
+        let export = dir.join("ledger.jsonl");
+        let export = export.to_str().unwrap();
+
+        let db = DatabaseManager::new(test_db).unwrap();
+        db.init().unwrap();
+        let blocks = chain(6);
+        for block in &blocks[..5] {
+            db.save_block(block).unwrap();
+        }
+        db.export_chain(.., File::create(export).unwrap()).unwrap();
+
+        // A faithful export matches
+        let (a, b) = (
+            Snapshot::open(test_db).unwrap(),
+            Snapshot::open(export).unwrap(),
+        );
+        let diff = diff_blocks(
+            a.blocks().unwrap(),
+            b.blocks().unwrap(),
+            a.describe(test_db).unwrap(),
+            b.describe(export).unwrap(),
+        )
+        .unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(diff.matching, 5);
+        assert!(diff.metadata.is_empty());
+
+        // Replica lost block 1, rewrote blocks 3 and 4 and is one block ahead
+        let mut replica = blocks.clone();
+        replica.remove(0);
+        for block in &mut replica[1..3] {
+            block.nonce += 1;
+            block.calculate_hash_with_nonce();
+        }
+        let diff = diff_blocks(
+            a.blocks().unwrap(),
+            replica.into_iter().map(Ok),
+            a.describe(test_db).unwrap(),
+            SnapshotMeta::default(),
+        )
+        .unwrap();
+        assert!(!diff.is_identical());
+        assert_eq!(diff.matching, 2);
+        assert_eq!(diff.missing, vec![IndexRange { start: 1, end: 1 }]);
+        assert_eq!(diff.diverging, vec![IndexRange { start: 3, end: 4 }]);
+        assert_eq!(diff.added, vec![IndexRange { start: 6, end: 6 }]);
+        let fields: Vec<_> = diff.metadata.iter().map(|d| d.field).collect();
+        assert_eq!(fields, vec!["first_index", "last_index", "head_hash"]);
+        let rendered = render_diff(&diff, &Palette::new(false));
+        assert!(rendered.contains("Diverging: 2 block(s): 3..=4"));
+
+        // Contents rewritten under the old hash are caught by re-hashing
+        let mut tampered = blocks[..5].to_vec();
+        tampered[2].data[0].price = Decimal::from(1);
+        let diff = diff_blocks(
+            a.blocks().unwrap(),
+            tampered.into_iter().map(Ok),
+            a.describe(test_db).unwrap(),
+            SnapshotMeta::default(),
+        )
+        .unwrap();
+        assert_eq!(diff.matching, 5);
+        assert!(diff.a.corrupt.is_empty());
+        assert_eq!(diff.b.corrupt, vec![IndexRange { start: 3, end: 3 }]);
+        assert!(!diff.is_identical());
+        assert!(render_diff(&diff, &Palette::new(false)).contains("Corrupt in B: 1 block(s): 3"));
+
+        // Out-of-order exports are rejected
+        let shuffled = vec![Ok(blocks[1].clone()), Ok(blocks[0].clone())];
+        assert!(diff_blocks(
+            shuffled.into_iter(),
+            std::iter::empty(),
+            SnapshotMeta::default(),
+            SnapshotMeta::default(),
+        )
+        .is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
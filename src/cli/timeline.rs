@@ -0,0 +1,235 @@
+//! ASCII timeline of consensus rounds, assembled from an event log
+//!
+//! Each round (sequence number, per shard) gets one lane per sending node,
+//! with its messages placed by their offset from the round's first event:
+//!
+//! ```text
+//! Round 1  (0 ms .. 1004 ms)
+//!   node 0  |P                            R                          C|
+//!   node 1  |                              R                        C |
+//!   node 2  |                               R                        C|
+//!            prepare quorum     +  514 ms (node 2)
+//!            commit quorum      + 1004 ms (node 2)
+//!            committed          + 1004 ms
+//! ```
+//!
+//! `P` = pre-prepare, `R` = prepare, `C` = commit.
+
+use crate::cli::Palette;
+use crate::consensus::algorithms::MessageType;
+use crate::consensus::event_log::{ConsensusEvent, LoggedEvent};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Lane width in characters
+pub const DEFAULT_WIDTH: usize = 60;
+
+fn symbol(msg_type: &MessageType) -> char {
+    match msg_type {
+        MessageType::PrePrepare => 'P',
+        MessageType::Prepare => 'R',
+        MessageType::Commit => 'C',
+        MessageType::ViewChange => 'V',
+        MessageType::NewView => 'N',
+    }
+}
+
+fn phase_name(msg_type: &MessageType) -> &'static str {
+    match msg_type {
+        MessageType::PrePrepare => "pre-prepare quorum",
+        MessageType::Prepare => "prepare quorum",
+        MessageType::Commit => "commit quorum",
+        MessageType::ViewChange => "view change",
+        MessageType::NewView => "new view",
+    }
+}
+
+/// Render one timeline per round found in `events`
+pub fn render_timeline(events: &[LoggedEvent], width: usize, palette: &Palette) -> String {
+    let width = width.max(10);
+    let mut rounds: BTreeMap<(Option<&str>, u64), Vec<&LoggedEvent>> = BTreeMap::new();
+    let mut nodes = BTreeSet::new();
+
+    for entry in events {
+        match &entry.event {
+            ConsensusEvent::Run { total_nodes, .. } => nodes.extend(0..*total_nodes),
+            ConsensusEvent::Message { message, .. } => {
+                nodes.insert(message.node_id);
+                rounds
+                    .entry((message.shard.as_deref(), message.sequence))
+                    .or_default()
+                    .push(entry);
+            }
+            ConsensusEvent::Committed { sequence, shard }
+            | ConsensusEvent::RolledBack { sequence, shard } => rounds
+                .entry((shard.as_deref(), *sequence))
+                .or_default()
+                .push(entry),
+        }
+    }
+
+    if rounds.is_empty() {
+        return palette.gray("No consensus rounds in the event log");
+    }
+
+    rounds
+        .iter()
+        .map(|(&(shard, sequence), round)| {
+            let label = match shard {
+                Some(shard) => format!("{}/{}", shard, sequence),
+                None => sequence.to_string(),
+            };
+            render_round(&label, round, &nodes, width, palette)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_round(
+    label: &str,
+    round: &[&LoggedEvent],
+    nodes: &BTreeSet<usize>,
+    width: usize,
+    palette: &Palette,
+) -> String {
+    let start = round.iter().map(|e| e.at_ms).min().unwrap_or(0);
+    let end = round.iter().map(|e| e.at_ms).max().unwrap_or(start);
+    let span = (end - start).max(1);
+    let column = |at_ms: i64| ((at_ms - start) as usize * (width - 1)) / span as usize;
+
+    let mut lanes: BTreeMap<usize, Vec<char>> =
+        nodes.iter().map(|&node| (node, vec![' '; width])).collect();
+    let mut milestones = Vec::new();
+    let mut crossed = BTreeSet::new();
+
+    for entry in round {
+        let offset = entry.at_ms - start;
+        match &entry.event {
+            ConsensusEvent::Message {
+                message,
+                quorum_reached,
+            } => {
+                if let Some(lane) = lanes.get_mut(&message.node_id) {
+                    lane[column(entry.at_ms)] = symbol(&message.msg_type);
+                }
+                // Only the first message to complete each phase's quorum counts
+                let phase = symbol(&message.msg_type);
+                if *quorum_reached && crossed.insert(phase) {
+                    milestones.push(format!(
+                        "{:<18} +{:>5} ms (node {})",
+                        phase_name(&message.msg_type),
+                        offset,
+                        message.node_id
+                    ));
+                }
+            }
+            ConsensusEvent::Committed { .. } => {
+                milestones.push(format!("{:<18} +{:>5} ms", "committed", offset));
+            }
+            ConsensusEvent::RolledBack { .. } => {
+                milestones.push(format!("{:<18} +{:>5} ms", "rolled back", offset));
+            }
+            ConsensusEvent::Run { .. } => {}
+        }
+    }
+
+    let mut out = palette.bold(&format!("Round {}  (0 ms .. {} ms)", label, end - start));
+    out.push('\n');
+    for (node, lane) in &lanes {
+        out.push_str(&format!(
+            "  node {:<3}|{}|\n",
+            node,
+            lane.iter().collect::<String>()
+        ));
+    }
+    for milestone in milestones {
+        out.push_str(&format!("           {}\n", palette.green(&milestone)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::PBFTMessage;
+    use crate::network::protocol::PROTOCOL_VERSION;
+
+    fn message(
+        seq: u64,
+        at_ms: i64,
+        msg_type: MessageType,
+        node_id: usize,
+        quorum: bool,
+    ) -> LoggedEvent {
+        LoggedEvent {
+            seq,
+            at_ms,
+            event: ConsensusEvent::Message {
+                message: PBFTMessage {
+                    msg_type,
+                    view: 0,
+                    sequence: 1,
+                    block_hash: "abc".to_string(),
+                    block_data_json: None,
+                    node_id,
+                    timestamp: at_ms,
+                    shard: None,
+                    trace_id: None,
+                    protocol_version: PROTOCOL_VERSION,
+                    hlc: None,
+                    prepared: None,
+                    view_changes: Vec::new(),
+                },
+                quorum_reached: quorum,
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_timeline_places_messages_and_quorums() {
+        let events = vec![
+            LoggedEvent {
+                seq: 0,
+                at_ms: 0,
+                event: ConsensusEvent::Run {
+                    node_id: 0,
+                    total_nodes: 3,
+                    algorithm: "PBFT".to_string(),
+                    quorum_policy: None,
+                    failure_domains: None,
+                    min_domains: None,
+                },
+            },
+            message(1, 1_000, MessageType::PrePrepare, 0, false),
+            message(2, 1_500, MessageType::Prepare, 1, false),
+            message(3, 1_600, MessageType::Prepare, 2, true),
+            message(4, 2_000, MessageType::Commit, 0, true),
+            message(5, 2_000, MessageType::Commit, 1, true),
+            LoggedEvent {
+                seq: 6,
+                at_ms: 2_000,
+                event: ConsensusEvent::Committed {
+                    sequence: 1,
+                    shard: None,
+                },
+            },
+        ];
+
+        let output = render_timeline(&events, 11, &Palette::new(false));
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], "Round 1  (0 ms .. 1000 ms)");
+        assert_eq!(lines[1], "  node 0  |P         C|");
+        assert_eq!(lines[2], "  node 1  |     R    C|");
+        assert_eq!(lines[3], "  node 2  |      R    |");
+        assert!(lines[4].contains("prepare quorum") && lines[4].contains("+  600 ms (node 2)"));
+        assert!(lines[5].contains("commit quorum") && lines[5].contains("+ 1000 ms (node 0)"));
+        assert!(lines[6].contains("committed"));
+        assert_eq!(lines.len(), 7);
+    }
+
+    #[test]
+    fn test_render_timeline_without_rounds() {
+        let output = render_timeline(&[], DEFAULT_WIDTH, &Palette::new(false));
+        assert_eq!(output, "No consensus rounds in the event log");
+    }
+}
@@ -0,0 +1,218 @@
+//! Cluster topology: `topology`
+//!
+//! ```text
+//! topology [--nodes ADDR,...] [--api-key KEY] [--format table|json|dot]
+//! ```
+//!
+//! Fetches `GET /topology` from every node (default: the cluster in
+//! `NODE_ADDRESSES`, else the local four-node cluster) and merges the views into one graph of who sends to whom, with
+//! message counts, bytes, failed sends and latency per link; see
+//! `network::topology`. Links that carry traffic only one way are flagged.
+//! `--format dot` prints a Graphviz graph, e.g.
+//! `topology --format dot | dot -Tsvg > cluster.svg`.
+
+use crate::cli::{block_on, flag_value, print_output, Palette};
+use crate::network::membership;
+use crate::network::topology::{ClusterGraph, TopologySnapshot};
+use std::error::Error;
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+  topology [OPTIONS]
+
+Options:
+  --nodes ADDR,...         nodes to query (default NODE_ADDRESSES)
+  --api-key KEY            bearer key when the nodes enforce API_KEYS
+  --format table|json|dot  output format (default table)
+  --json                   shorthand for --format json
+  --dot                    shorthand for --format dot
+  --color, --no-color      force colored output on or off";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Table,
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyArgs {
+    pub nodes: Vec<String>,
+    pub api_key: Option<String>,
+    pub format: GraphFormat,
+    pub color: Option<bool>,
+}
+
+impl TopologyArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = TopologyArgs {
+            nodes: Vec::new(),
+            api_key: None,
+            format: GraphFormat::Table,
+            color: None,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--nodes" => {
+                    parsed.nodes = flag_value(arg, &mut iter)?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|n| !n.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    if parsed.nodes.is_empty() {
+                        return Err("--nodes expects at least one address".to_string());
+                    }
+                }
+                "--api-key" => parsed.api_key = Some(flag_value(arg, &mut iter)?.to_string()),
+                "--format" => {
+                    parsed.format = match flag_value(arg, &mut iter)? {
+                        "table" => GraphFormat::Table,
+                        "json" => GraphFormat::Json,
+                        "dot" => GraphFormat::Dot,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => parsed.format = GraphFormat::Json,
+                "--dot" => parsed.format = GraphFormat::Dot,
+                "--color" => parsed.color = Some(true),
+                "--no-color" => parsed.color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+        if parsed.nodes.is_empty() {
+            parsed.nodes = membership::node_addresses_from_env()?;
+        }
+        Ok(parsed)
+    }
+}
+
+async fn fetch(
+    client: &reqwest::Client,
+    address: &str,
+    api_key: Option<&str>,
+) -> Result<TopologySnapshot, String> {
+    let mut request = client.get(format!("http://{}/topology", address));
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let response = response.error_for_status().map_err(|e| e.to_string())?;
+    response.json().await.map_err(|e| e.to_string())
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match TopologyArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()?;
+    let views = block_on(async {
+        let mut views = Vec::with_capacity(args.nodes.len());
+        for address in &args.nodes {
+            let view = fetch(&client, address, args.api_key.as_deref()).await;
+            views.push((address.clone(), view));
+        }
+        views
+    });
+    let graph = ClusterGraph::build(&views);
+
+    let output = match args.format {
+        GraphFormat::Json => serde_json::to_string_pretty(&graph)?,
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Table => render_graph(&graph, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    if views.iter().all(|(_, view)| view.is_err()) {
+        return Err("no node answered".into());
+    }
+    Ok(())
+}
+
+pub fn render_graph(graph: &ClusterGraph, palette: &Palette) -> String {
+    let mut out = String::new();
+    out.push_str(&palette.bold("Nodes"));
+    out.push('\n');
+    for node in &graph.nodes {
+        let id = node
+            .node_id
+            .map_or("-".to_string(), |id| format!("node {}", id));
+        let status = match (node.reachable, &node.error) {
+            (Some(true), _) => palette.green("reporting"),
+            (Some(false), Some(e)) => palette.yellow(&format!("unreachable ({})", e)),
+            (Some(false), None) => palette.yellow("unreachable"),
+            (None, _) => palette.gray("not queried"),
+        };
+        out.push_str(&format!("  {:<22} {:<8} {}\n", node.address, id, status));
+    }
+
+    out.push('\n');
+    out.push_str(&palette.bold(&format!(
+        "  {:<22} {:<22} {:>8} {:>10} {:>7} {:>9} {:>9}",
+        "FROM", "TO", "MSGS", "BYTES", "FAILED", "P50", "P95"
+    )));
+    out.push('\n');
+    for edge in &graph.edges {
+        let sent = edge.sent.as_ref();
+        let messages = sent
+            .map(|s| s.messages)
+            .or(edge.received.as_ref().map(|r| r.messages))
+            .unwrap_or_default();
+        let bytes = sent
+            .map(|s| s.bytes)
+            .or(edge.received.as_ref().map(|r| r.bytes))
+            .unwrap_or_default();
+        let latency = |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{:.1} ms", ms));
+        let samples = sent.filter(|s| s.latency.samples > 0);
+        let mut line = format!(
+            "  {:<22} {:<22} {:>8} {:>10} {:>7} {:>9} {:>9}",
+            edge.from,
+            edge.to,
+            messages,
+            bytes,
+            sent.map_or("-".to_string(), |s| s.failures.to_string()),
+            latency(samples.map(|s| s.latency.p50_ms)),
+            latency(samples.map(|s| s.latency.p95_ms)),
+        );
+        if edge.asymmetric {
+            line.push_str(&format!("  {}", palette.yellow("one-way")));
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    if graph.edges.is_empty() {
+        out.push_str(&palette.gray("  no messages recorded yet"));
+        out.push('\n');
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_topology_args() {
+        let parsed = TopologyArgs::parse(&args(&[])).unwrap();
+        assert_eq!(parsed.nodes, membership::node_addresses_from_env().unwrap());
+        assert_eq!(parsed.format, GraphFormat::Table);
+
+        let parsed =
+            TopologyArgs::parse(&args(&["--nodes", "a:1, b:2", "--dot", "--api-key", "k"]))
+                .unwrap();
+        assert_eq!(parsed.nodes, vec!["a:1", "b:2"]);
+        assert_eq!(parsed.format, GraphFormat::Dot);
+        assert_eq!(parsed.api_key.as_deref(), Some("k"));
+
+        assert!(TopologyArgs::parse(&args(&["--format", "svg"])).is_err());
+        assert!(TopologyArgs::parse(&args(&["--nodes", ","])).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Full ledger verification: `verify`
+//!
+//! ```text
+//! verify [--node N | --db PATH] [--jobs N] [--segment-blocks N] [--json]
+//! ```
+//!
+//! Recomputes every block hash and checks every link on `--jobs` threads
+//! (default: one per CPU); see `network::parallel_verify`. Exits non-zero
+//! when the chain does not verify.
+
+use crate::cli::chain::OutputFormat;
+use crate::cli::{flag_value, open_ledger, print_output, Palette};
+use crate::network::parallel_verify::{verify_chain_parallel, ChainReport, ParallelVerifyConfig};
+use std::error::Error;
+
+const USAGE: &str = "Usage:
+  verify [OPTIONS]
+
+Options:
+  --node N              verify blockchain_node_N.db (default 0)
+  --db PATH             verify an explicit database file
+  --jobs N              worker threads (default: number of CPUs)
+  --segment-blocks N    blocks per work unit (default 10000)
+  --format table|json   output format (default table)
+  --json                shorthand for --format json
+  --color, --no-color   force colored output on or off";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyArgs {
+    pub db_path: String,
+    pub config: ParallelVerifyConfig,
+    pub format: OutputFormat,
+    pub color: Option<bool>,
+}
+
+impl VerifyArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut iter = args.iter();
+        let mut db_path = "blockchain_node_0.db".to_string();
+        let mut config = ParallelVerifyConfig::default();
+        let mut format = OutputFormat::Table;
+        let mut color = None;
+
+        let number = |flag: &str, value: &str| match value.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("{} expects a positive number", flag)),
+        };
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--node" => {
+                    let node = flag_value(arg, &mut iter)?
+                        .parse::<u64>()
+                        .map_err(|_| format!("{} expects a number", arg))?;
+                    db_path = format!("blockchain_node_{}.db", node);
+                }
+                "--db" => db_path = flag_value(arg, &mut iter)?.to_string(),
+                "--jobs" | "-j" => {
+                    config.jobs = number(arg, flag_value(arg, &mut iter)?)? as usize;
+                }
+                "--segment-blocks" => {
+                    config = config.with_segment_blocks(number(arg, flag_value(arg, &mut iter)?)?);
+                }
+                "--format" => {
+                    format = match flag_value(arg, &mut iter)? {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "--json" => format = OutputFormat::Json,
+                "--color" => color = Some(true),
+                "--no-color" => color = Some(false),
+                other => return Err(format!("Unexpected argument '{}'", other)),
+            }
+        }
+
+        Ok(VerifyArgs {
+            db_path,
+            config,
+            format,
+            color,
+        })
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args = match VerifyArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return Err(format!("{}\n\n{}", e, USAGE).into()),
+    };
+    let db = open_ledger(&args.db_path)?;
+    let report = verify_chain_parallel(&db, args.config)?;
+
+    let output = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        OutputFormat::Table => render_report(&report, &Palette::detect(args.color)),
+    };
+    print_output(&output)?;
+
+    match report.failure {
+        None => Ok(()),
+        Some(failure) => Err(format!("chain verification failed: {}", failure).into()),
+    }
+}
+
+pub fn render_report(report: &ChainReport, palette: &Palette) -> String {
+    let status = if report.is_ok() {
+        palette.green("OK")
+    } else {
+        palette.yellow("FAILED")
+    };
+    let mut out = format!(
+        "{} {} block(s), index {}..={}: {}\n",
+        palette.bold("Verified"),
+        report.checked,
+        report.first_index.unwrap_or_default(),
+        report.last_index.unwrap_or_default(),
+        status
+    );
+    out.push_str(&format!(
+        "{} segment(s) on {} job(s) in {} ms",
+        report.segments, report.jobs, report.elapsed_ms
+    ));
+    if let Some(failure) = &report.failure {
+        out.push_str(&format!(
+            "\n{} {}",
+            palette.yellow("First failure:"),
+            failure
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_verify_args() {
+        let parsed = VerifyArgs::parse(&args(&["--node", "2", "--jobs", "8", "--json"])).unwrap();
+        assert_eq!(parsed.db_path, "blockchain_node_2.db");
+        assert_eq!(parsed.config.jobs, 8);
+        assert_eq!(parsed.format, OutputFormat::Json);
+
+        let parsed = VerifyArgs::parse(&args(&["--db", "x.db", "--segment-blocks", "50"])).unwrap();
+        assert_eq!(parsed.db_path, "x.db");
+        assert_eq!(parsed.config.segment_blocks, 50);
+
+        assert!(VerifyArgs::parse(&args(&["--jobs", "0"])).is_err());
+        assert!(VerifyArgs::parse(&args(&["--jobs"])).is_err());
+        assert!(VerifyArgs::parse(&args(&["--bogus"])).is_err());
+    }
+}
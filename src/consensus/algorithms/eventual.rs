@@ -1,13 +1,13 @@
 //! Eventual Consistency consensus
 
 use crate::consensus::{
-    ConsensusAlgorithm, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    PendingDetails,
 };
 use crate::etl::Block;
 use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::HashSet;
-use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -32,7 +32,7 @@ impl EventualConsensus {
 
 #[async_trait]
 impl ConsensusAlgorithm for EventualConsensus {
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>> {
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError> {
         tokio::time::sleep(Duration::from_millis(self.confirmation_delay_ms)).await;
 
         let mut committed = self.committed.write();
@@ -44,8 +44,8 @@ impl ConsensusAlgorithm for EventualConsensus {
     async fn handle_message(
         &self,
         _message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>> {
-        Ok(ConsensusResult::Pending)
+    ) -> Result<ConsensusResult, ConsensusError> {
+        Ok(ConsensusResult::Pending(PendingDetails::default()))
     }
 
     fn name(&self) -> &str {
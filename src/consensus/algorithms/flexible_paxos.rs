@@ -1,14 +1,21 @@
 //! Flexible Paxos consensus implementation
+//!
+//! Quorum sizes are configured with `FPAXOS_Q1` (phase 1) and `FPAXOS_Q2`
+//! (phase 2); see `quorum_sizes_from_env`. With failure domains
+//! (`quorum::FailureDomains`) each phase's quorum must also span the
+//! required number of domains.
 
+use crate::consensus::quorum::FailureDomains;
 use crate::consensus::{
-    ConsensusAlgorithm, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    PendingDetails,
 };
 use crate::etl::Block;
 use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
 use std::sync::Arc;
+use std::time::Instant;
 
 type ProposalId = u64;
 type NodeId = usize;
@@ -55,6 +62,7 @@ pub struct FlexiblePaxos {
     current_proposal: Arc<RwLock<ProposalId>>,
     committed: Arc<RwLock<HashSet<u64>>>,
     pending_proposals: Arc<RwLock<HashMap<ProposalId, Block>>>,
+    domains: Option<FailureDomains>,
 }
 
 impl FlexiblePaxos {
@@ -63,10 +71,6 @@ impl FlexiblePaxos {
             q1_size + q2_size > total_nodes,
             "Q1 + Q2 must be > total_nodes to ensure quorum intersection"
         );
-        assert!(
-            q1_size >= (total_nodes + 1) / 2,
-            "Q1 should be at least majority for safety"
-        );
 
         let mut acceptors = HashMap::new();
         for i in 0..total_nodes {
@@ -88,9 +92,26 @@ impl FlexiblePaxos {
             current_proposal: Arc::new(RwLock::new(0)),
             committed: Arc::new(RwLock::new(HashSet::new())),
             pending_proposals: Arc::new(RwLock::new(HashMap::new())),
+            domains: None,
         }
     }
 
+    /// Require both phases' quorums to span `domains`' minimum number of
+    /// failure domains
+    pub fn with_failure_domains(mut self, domains: FailureDomains) -> Self {
+        self.domains = Some(domains);
+        self
+    }
+
+    /// `responders` reached `size` and span the required failure domains
+    fn is_quorum(&self, responders: &[NodeId], size: usize) -> bool {
+        responders.len() >= size
+            && self
+                .domains
+                .as_ref()
+                .is_none_or(|domains| domains.spans(responders))
+    }
+
     fn next_proposal_id(&self) -> ProposalId {
         let mut proposal = self.current_proposal.write();
         *proposal += 1;
@@ -127,54 +148,95 @@ impl FlexiblePaxos {
     }
 }
 
+/// Check that phase-1 and phase-2 quorums of these sizes always intersect
+pub fn check_quorum_sizes(
+    total_nodes: usize,
+    q1_size: usize,
+    q2_size: usize,
+) -> Result<(), String> {
+    if q1_size == 0 || q2_size == 0 || q1_size > total_nodes || q2_size > total_nodes {
+        return Err(format!(
+            "Q1={} and Q2={} must each be between 1 and the {} nodes",
+            q1_size, q2_size, total_nodes
+        ));
+    }
+    if q1_size + q2_size <= total_nodes {
+        return Err(format!(
+            "Q1 + Q2 = {} must exceed the {} nodes, or a phase-1 and a phase-2 quorum can miss each other",
+            q1_size + q2_size,
+            total_nodes
+        ));
+    }
+    Ok(())
+}
+
+/// Quorum sizes from `FPAXOS_Q1` / `FPAXOS_Q2`, checked with
+/// `check_quorum_sizes`; by default half the cluster (at least one node) for
+/// phase 2 and just enough for phase 1 to intersect it
+pub fn quorum_sizes_from_env(total_nodes: usize) -> Result<(usize, usize), String> {
+    let size = |var: &str, default: usize| match std::env::var(var) {
+        Ok(value) => value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid {} '{}': expected a node count", var, value)),
+        Err(_) => Ok(default),
+    };
+    let q2_size = size("FPAXOS_Q2", (total_nodes / 2).max(1))?;
+    let q1_size = size(
+        "FPAXOS_Q1",
+        (total_nodes + 1).saturating_sub(q2_size).max(1),
+    )?;
+    check_quorum_sizes(total_nodes, q1_size, q2_size)?;
+    Ok((q1_size, q2_size))
+}
+
 #[async_trait]
 impl ConsensusAlgorithm for FlexiblePaxos {
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>> {
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError> {
+        let started = Instant::now();
         let proposal = self.next_proposal_id();
         self.pending_proposals
             .write()
             .insert(proposal, block.clone());
 
-        let mut prepare_responses = 0;
-        let mut accept_responses = 0;
+        let mut prepared = Vec::new();
+        let mut accepted = Vec::new();
 
         for i in 0..self.total_nodes {
             if i == self.node_id {
-                if let Some(accepted) = self.handle_prepare(proposal) {
-                    prepare_responses += 1;
-                } else {
-                    prepare_responses += 1;
-                }
-            } else {
-                prepare_responses += 1;
+                self.handle_prepare(proposal);
             }
+            prepared.push(i);
         }
 
-        if prepare_responses >= self.q1_size {
+        let mut details = PendingDetails::new(started.elapsed()).with_phase(
+            "prepare",
+            prepared.len() as f64,
+            self.q1_size as f64,
+        );
+        if self.is_quorum(&prepared, self.q1_size) {
             for i in 0..self.total_nodes {
-                if i == self.node_id {
-                    if self.handle_accept(proposal, block.clone()) {
-                        accept_responses += 1;
-                    }
-                } else {
-                    accept_responses += 1;
+                if i != self.node_id || self.handle_accept(proposal, block.clone()) {
+                    accepted.push(i);
                 }
             }
 
-            if accept_responses >= self.q2_size {
+            if self.is_quorum(&accepted, self.q2_size) {
                 self.committed.write().insert(block.index);
                 return Ok(ConsensusResult::Committed(block.clone()));
             }
+            details = details.with_phase("accept", accepted.len() as f64, self.q2_size as f64);
+            details.elapsed_ms = started.elapsed().as_millis() as u64;
         }
 
-        Ok(ConsensusResult::Pending)
+        Ok(ConsensusResult::Pending(details))
     }
 
     async fn handle_message(
         &self,
         _message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>> {
-        Ok(ConsensusResult::Pending)
+    ) -> Result<ConsensusResult, ConsensusError> {
+        Ok(ConsensusResult::Pending(PendingDetails::default()))
     }
 
     fn name(&self) -> &str {
@@ -197,3 +259,22 @@ impl ConsensusAlgorithm for FlexiblePaxos {
         committed.contains(&block_index)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum_sizes_only_need_to_intersect() {
+        assert_eq!(check_quorum_sizes(1, 1, 1), Ok(()));
+        // A minority phase-1 quorum is fine while every phase-2 quorum meets it
+        assert_eq!(check_quorum_sizes(5, 2, 4), Ok(()));
+        assert!(check_quorum_sizes(5, 2, 3).is_err());
+        assert!(check_quorum_sizes(4, 0, 4).is_err());
+        assert!(check_quorum_sizes(4, 5, 1).is_err());
+
+        let single = FlexiblePaxos::new(0, 1, 1, 1);
+        assert_eq!(single.requirements().min_nodes, Some(1));
+        FlexiblePaxos::new(0, 5, 2, 4);
+    }
+}
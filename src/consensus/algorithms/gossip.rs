@@ -1,15 +1,15 @@
 //! Gossip-based consensus
 
 use crate::consensus::{
-    ConsensusAlgorithm, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    PendingDetails,
 };
 use crate::etl::Block;
 use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Clone, Debug)]
 struct GossipState {
@@ -48,7 +48,8 @@ impl GossipConsensus {
 
 #[async_trait]
 impl ConsensusAlgorithm for GossipConsensus {
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>> {
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError> {
+        let started = Instant::now();
         {
             let mut state = self.state.write();
             let gossip_state = state.entry(block.index).or_insert_with(|| GossipState {
@@ -73,22 +74,30 @@ impl ConsensusAlgorithm for GossipConsensus {
             }
         }
 
-        let state = self.state.read();
-        if let Some(gossip_state) = state.get(&block.index) {
-            if gossip_state.received_from.len() >= self.gossip_rounds {
-                self.committed.write().insert(block.index);
-                return Ok(ConsensusResult::Committed(block.clone()));
-            }
+        let received = self
+            .state
+            .read()
+            .get(&block.index)
+            .map_or(0, |gossip_state| gossip_state.received_from.len());
+        if received >= self.gossip_rounds {
+            self.committed.write().insert(block.index);
+            return Ok(ConsensusResult::Committed(block.clone()));
         }
 
-        Ok(ConsensusResult::Pending)
+        Ok(ConsensusResult::Pending(
+            PendingDetails::new(started.elapsed()).with_phase(
+                "gossip",
+                received as f64,
+                self.gossip_rounds as f64,
+            ),
+        ))
     }
 
     async fn handle_message(
         &self,
         message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>> {
-        {
+    ) -> Result<ConsensusResult, ConsensusError> {
+        let received = {
             let mut state = self.state.write();
             let gossip_state = state
                 .entry(message.block_index)
@@ -99,8 +108,15 @@ impl ConsensusAlgorithm for GossipConsensus {
                     timestamp: Self::get_timestamp(),
                 });
             gossip_state.received_from.insert(message.node_id);
-        }
-        Ok(ConsensusResult::Pending)
+            gossip_state.received_from.len()
+        };
+        Ok(ConsensusResult::Pending(
+            PendingDetails::default().with_phase(
+                "gossip",
+                received as f64,
+                self.gossip_rounds as f64,
+            ),
+        ))
     }
 
     fn name(&self) -> &str {
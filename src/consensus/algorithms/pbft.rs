@@ -2,18 +2,49 @@
 //!
 //! This module contains both the core PBFT logic (PBFTManager, PBFTMessage, etc.)
 //! and the ConsensusAlgorithm trait adapter (PBFTConsensus).
+//!
+//! Node ids listed as observers (`PBFT_OBSERVERS`, e.g. `4,5`) take part in
+//! the cluster without voting: they receive and track every message, but
+//! are never primary and their votes are not counted. Quorums are computed
+//! over the voting members only. Weighted and grid quorum policies index
+//! voters by node id, so give observers the highest ids.
+//!
+//! The primary rotates with the sequence number and the view. A replica
+//! that gives up waiting for the primary's pre-prepare (after
+//! `PBFT_VIEW_CHANGE_TIMEOUT_MS`) broadcasts a `ViewChange` vote for the
+//! next view; once a quorum has voted for it, every node moves to that view
+//! and the sequence's primary is the next voting member. Each vote carries
+//! the highest-view block its sender prepared for the sequence, and the new
+//! primary proposes in a `NewView` that bundles the votes: the block of the
+//! highest-view certificate among them, or its own block when none prepared
+//! one. Replicas reject a `NewView` that proposes anything else, so a block
+//! that may have committed in the old view cannot be replaced.
+//! `consensus::drill` exercises this.
 
+use crate::consensus::demo::{DemoMode, DemoPhase};
+use crate::consensus::event_log::{ConsensusEvent, EventLog};
+use crate::consensus::quorum::{ClassicQuorum, QuorumPolicy};
 use crate::consensus::{
-    ConsensusAlgorithm, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    PendingDetails,
 };
-use crate::etl::Block;
+use crate::etl::hlc::{HlcTimestamp, HybridClock};
+use crate::etl::{now_millis, Block};
+use crate::network::broadcast_message;
+use crate::network::outbox::Outbox;
+use crate::network::peer_addr::{local_address, PeerAddr};
+use crate::network::protocol::{PeerVersions, PROTOCOL_VERSION};
 use async_trait::async_trait;
-use chrono::prelude::*;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a replica waits for the pre-prepare unless
+/// `PBFT_VIEW_CHANGE_TIMEOUT_MS` is set
+pub const DEFAULT_VIEW_CHANGE_TIMEOUT: Duration = Duration::from_secs(2);
 
 // Core PBFT types and structures
 
@@ -22,9 +53,14 @@ pub enum MessageType {
     PrePrepare,
     Prepare,
     Commit,
+    /// Vote to move to `view` after the primary failed to propose
+    ViewChange,
+    /// The new primary's pre-prepare for the sequence a view change was
+    /// voted for, justified by a quorum of `ViewChange` votes
+    NewView,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PBFTMessage {
     pub msg_type: MessageType,
     pub view: u64,
@@ -33,16 +69,66 @@ pub struct PBFTMessage {
     pub block_data_json: Option<String>,
     pub node_id: usize,
     pub timestamp: i64,
+    /// Consensus instance the message belongs to; `None` is the unsharded one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
+    /// Correlation id of the ETL round that proposed the block, so its
+    /// messages can be followed across nodes' logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Wire protocol the message was encoded with; see `network::protocol`
+    #[serde(default = "crate::network::protocol::legacy_protocol_version")]
+    pub protocol_version: u32,
+    /// Sender's hybrid logical time; see `etl::hlc`. Missing from nodes
+    /// that predate hybrid clocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<HlcTimestamp>,
+    /// On a `ViewChange`: the highest-view block the sender prepared for
+    /// the sequence, which the new primary must propose again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prepared: Option<Box<PreparedCertificate>>,
+    /// On a `NewView`: the `ViewChange` votes that elected the sender
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub view_changes: Vec<PBFTMessage>,
+}
+
+/// A block that gathered a prepare quorum in `view`
+///
+/// The prepares are the voters' node ids, not their signed messages: like
+/// every other vote, they are only as trustworthy as the peers allowed to
+/// send them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PreparedCertificate {
+    pub view: u64,
+    pub sequence: u64,
+    pub block_hash: String,
+    pub block_data_json: String,
+    pub prepares: Vec<usize>,
+}
+
+impl PBFTMessage {
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
 }
 
+/// `(view, sequence, block digest)` a vote is for; votes for different
+/// blocks at the same sequence never count towards the same quorum
+pub type VoteKey = (u64, u64, String);
+
 #[derive(Debug, Clone)]
 pub struct NodeState {
     pub node_id: usize,
     pub view: u64,
     pub sequence: u64,
-    pub pre_prepares: HashMap<(u64, u64), Vec<usize>>,
-    pub prepares: HashMap<(u64, u64), Vec<usize>>,
-    pub commits: HashMap<(u64, u64), Vec<usize>>,
+    pub pre_prepares: HashMap<VoteKey, Vec<usize>>,
+    pub prepares: HashMap<VoteKey, Vec<usize>>,
+    pub commits: HashMap<VoteKey, Vec<usize>>,
+    /// `ViewChange` votes for each proposed view, one per voter
+    pub view_changes: HashMap<u64, Vec<PBFTMessage>>,
+    /// Block each accepted pre-prepare carried, for prepared certificates
+    pub proposals: HashMap<VoteKey, String>,
     pub committed_blocks: Vec<u64>,
 }
 
@@ -55,13 +141,14 @@ impl NodeState {
             pre_prepares: HashMap::new(),
             prepares: HashMap::new(),
             commits: HashMap::new(),
+            view_changes: HashMap::new(),
+            proposals: HashMap::new(),
             committed_blocks: Vec::new(),
         }
     }
 
     pub fn quorum_size(&self, total_nodes: usize) -> usize {
-        let f = (total_nodes - 1) / 3;
-        (2 * f) + 1
+        ClassicQuorum::quorum_size(total_nodes)
     }
 
     pub fn has_quorum(&self, votes: &[usize], total_nodes: usize) -> bool {
@@ -73,6 +160,16 @@ pub struct PBFTManager {
     pub state: Arc<RwLock<NodeState>>,
     pub total_nodes: usize,
     pub node_addresses: Vec<String>,
+    quorum_policy: Arc<dyn QuorumPolicy>,
+    event_log: Option<Arc<EventLog>>,
+    shard: Option<String>,
+    primary_offset: usize,
+    /// Nodes that follow consensus without proposing or voting
+    observers: BTreeSet<usize>,
+    clock: Arc<HybridClock>,
+    /// How long a replica waits for the primary's pre-prepare before it
+    /// votes for a view change
+    view_change_timeout: Duration,
 }
 
 impl PBFTManager {
@@ -81,70 +178,563 @@ impl PBFTManager {
             state: Arc::new(RwLock::new(NodeState::new(node_id))),
             total_nodes,
             node_addresses,
+            quorum_policy: Arc::new(ClassicQuorum),
+            event_log: None,
+            shard: None,
+            primary_offset: 0,
+            observers: BTreeSet::new(),
+            clock: Arc::new(HybridClock::new(node_id)),
+            view_change_timeout: DEFAULT_VIEW_CHANGE_TIMEOUT,
         }
     }
 
-    pub fn handle_pre_prepare(&self, msg: &PBFTMessage) -> bool {
-        let key = (msg.view, msg.sequence);
-        let total_nodes = self.total_nodes;
+    pub fn with_view_change_timeout(mut self, timeout: Duration) -> Self {
+        self.view_change_timeout = timeout;
+        self
+    }
 
-        {
-            let mut state = self.state.write();
-            let votes = state.pre_prepares.entry(key).or_insert_with(Vec::new);
-            if !votes.contains(&msg.node_id) {
-                votes.push(msg.node_id);
+    pub fn view_change_timeout(&self) -> Duration {
+        self.view_change_timeout
+    }
+
+    /// Stamp messages from `clock`, shared with whatever else the node
+    /// timestamps (its blocks), instead of a clock of this instance's own
+    pub fn with_clock(mut self, clock: Arc<HybridClock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &Arc<HybridClock> {
+        &self.clock
+    }
+
+    /// Treat `observers` as non-voting members of the cluster
+    pub fn with_observers(mut self, observers: impl IntoIterator<Item = usize>) -> Self {
+        self.observers = observers.into_iter().collect();
+        self
+    }
+
+    pub fn is_observer(&self, node_id: usize) -> bool {
+        self.observers.contains(&node_id)
+    }
+
+    /// Whether this node only follows consensus
+    pub fn observes_only(&self) -> bool {
+        self.is_observer(self.node_id())
+    }
+
+    /// Node ids whose votes count, in ascending order
+    pub fn voting_members(&self) -> Vec<usize> {
+        (0..self.total_nodes)
+            .filter(|id| !self.is_observer(*id))
+            .collect()
+    }
+
+    /// Run this instance as `shard`, tagging its messages with the shard id.
+    ///
+    /// `primary_offset` shifts the primary rotation so that shards sharing a
+    /// cluster do not all elect the same primary for a sequence number.
+    pub fn with_shard(mut self, shard: impl Into<String>, primary_offset: usize) -> Self {
+        self.shard = Some(shard.into());
+        self.primary_offset = primary_offset;
+        self
+    }
+
+    pub fn shard(&self) -> Option<&str> {
+        self.shard.as_deref()
+    }
+
+    /// Sequence number to order `block` under. The unsharded instance uses
+    /// the block's chain index; a shard numbers its own blocks, one past the
+    /// highest sequence it has assigned or seen committed, so a shard's
+    /// sequence space has no gaps for blocks other shards ordered.
+    pub fn sequence_for(&self, block: &Block) -> u64 {
+        let mut state = self.state.write();
+        state.sequence = match self.shard {
+            None => block.index,
+            Some(_) => {
+                let committed = state.committed_blocks.iter().copied().max().unwrap_or(0);
+                state.sequence.max(committed) + 1
             }
+        };
+        state.sequence
+    }
+
+    /// The sequence `sequence_for` last handed out
+    pub fn last_sequence(&self) -> u64 {
+        self.state.read().sequence
+    }
+
+    /// Replace the classic 2f+1 quorum with another quorum system
+    pub fn with_quorum_policy(mut self, policy: Arc<dyn QuorumPolicy>) -> Self {
+        self.quorum_policy = policy;
+        self
+    }
+
+    pub fn quorum_policy(&self) -> &dyn QuorumPolicy {
+        self.quorum_policy.as_ref()
+    }
+
+    /// Whether `votes` form a quorum of the voting members under this
+    /// manager's policy
+    pub fn has_quorum(&self, votes: &[usize]) -> bool {
+        if self.observers.is_empty() {
+            return self.quorum_policy.is_quorum(votes, self.total_nodes);
         }
+        let voters: Vec<usize> = votes
+            .iter()
+            .copied()
+            .filter(|id| !self.is_observer(*id))
+            .collect();
+        self.quorum_policy
+            .is_quorum(&voters, self.voting_members().len())
+    }
 
-        let state = self.state.read();
-        let votes = state.pre_prepares.get(&key).unwrap();
-        state.has_quorum(votes, total_nodes)
+    /// Observers must not vote; a message claiming one is dropped
+    fn is_observer_vote(&self, msg: &PBFTMessage) -> bool {
+        if !self.is_observer(msg.node_id) {
+            return false;
+        }
+        warn!(
+            node_id = msg.node_id,
+            sequence = msg.sequence,
+            msg_type = ?msg.msg_type,
+            "PBFT: Ignoring vote from observer"
+        );
+        true
     }
 
-    pub fn handle_prepare(&self, msg: &PBFTMessage) -> bool {
-        let key = (msg.view, msg.sequence);
-        let total_nodes = self.total_nodes;
+    /// Record every handled message and commit to `log` for later replay
+    pub fn with_event_log(mut self, log: Arc<EventLog>) -> Self {
+        self.event_log = Some(log);
+        self
+    }
+
+    fn record(&self, event: ConsensusEvent) {
+        if let Some(log) = &self.event_log {
+            log.record(event);
+        }
+    }
 
+    fn record_message(&self, msg: &PBFTMessage, quorum_reached: bool) {
+        if self.event_log.is_some() {
+            self.record(ConsensusEvent::Message {
+                message: msg.clone(),
+                quorum_reached,
+            });
+        }
+    }
+
+    /// Dispatch a message to the handler for its phase
+    ///
+    /// Every node, voting or observing, drops a pre-prepare that fails
+    /// `validate_pre_prepare` before it is recorded, so no replica votes for
+    /// it. The sender's HLC is merged into this node's clock; a message whose
+    /// HLC is too far ahead of the local clock is dropped.
+    pub fn handle_message(&self, msg: &PBFTMessage) -> bool {
+        if let Some(hlc) = &msg.hlc {
+            if let Err(e) = self.clock.observe(hlc) {
+                warn!(
+                    node_id = msg.node_id,
+                    sequence = msg.sequence,
+                    error = %e,
+                    "PBFT: Dropping message from a clock too far ahead"
+                );
+                return false;
+            }
+        }
+        if matches!(msg.msg_type, MessageType::PrePrepare | MessageType::NewView) {
+            if let Err(reason) = self.validate_pre_prepare(msg) {
+                warn!(
+                    node_id = msg.node_id,
+                    sequence = msg.sequence,
+                    reason = %reason,
+                    "PBFT: Rejected pre-prepare"
+                );
+                return false;
+            }
+        }
+        match msg.msg_type {
+            MessageType::PrePrepare => self.handle_pre_prepare(msg),
+            MessageType::Prepare => self.handle_prepare(msg),
+            MessageType::Commit => self.handle_commit(msg),
+            MessageType::ViewChange => self.handle_view_change(msg),
+            MessageType::NewView => self.handle_new_view(msg),
+        }
+    }
+
+    /// Check that a pre-prepare comes from the sequence's primary in this
+    /// node's current view, does not contradict a pre-prepare already
+    /// accepted for the sequence in that view, and carries a block whose
+    /// content id is the one being voted on
+    ///
+    /// A view entered by a view change for the sequence only takes the new
+    /// primary's `NewView`, checked by `validate_new_view`.
+    pub fn validate_pre_prepare(&self, msg: &PBFTMessage) -> Result<(), String> {
+        if msg.msg_type == MessageType::NewView {
+            return self.validate_new_view(msg);
+        }
+        let view = self.view();
+        if msg.view != view {
+            return Err(format!(
+                "sent for view {}, this node is in view {}",
+                msg.view, view
+            ));
+        }
+        if self.view_changed_at(msg.sequence, msg.view) {
+            return Err(format!(
+                "view {} replaced the primary of sequence {}, whose block must come in a NEW-VIEW",
+                msg.view, msg.sequence
+            ));
+        }
+        self.check_proposal(msg)
+    }
+
+    /// Check a `NewView`: on top of the pre-prepare checks it must carry a
+    /// quorum of `ViewChange` votes for its view and sequence, and propose
+    /// the block of the highest-view prepared certificate among them and
+    /// this node's own. The node may still be in an earlier view, which the
+    /// `NewView` moves it to.
+    pub fn validate_new_view(&self, msg: &PBFTMessage) -> Result<(), String> {
+        let view = self.view();
+        if msg.view < view {
+            return Err(format!(
+                "sent for view {}, this node is already in view {}",
+                msg.view, view
+            ));
+        }
+        let mut voters = Vec::new();
         {
-            let mut state = self.state.write();
-            let votes = state.prepares.entry(key).or_insert_with(Vec::new);
-            if !votes.contains(&msg.node_id) {
-                votes.push(msg.node_id);
+            let state = self.state.read();
+            let received = state.view_changes.get(&msg.view);
+            for vote in &msg.view_changes {
+                if vote.msg_type != MessageType::ViewChange
+                    || vote.view != msg.view
+                    || vote.sequence != msg.sequence
+                {
+                    return Err(format!(
+                        "carries a vote from node {} that is not a view change to view {} for sequence {}",
+                        vote.node_id, msg.view, msg.sequence
+                    ));
+                }
+                if self.is_observer(vote.node_id) || voters.contains(&vote.node_id) {
+                    return Err(format!("counts node {} more than once", vote.node_id));
+                }
+                // The primary must not drop a certificate this node received
+                let misreported = received
+                    .and_then(|votes| votes.iter().find(|v| v.node_id == vote.node_id))
+                    .is_some_and(|original| original.prepared != vote.prepared);
+                if misreported {
+                    return Err(format!("misreports node {}'s view change", vote.node_id));
+                }
+                voters.push(vote.node_id);
+            }
+        }
+        if !self.has_quorum(&voters) {
+            return Err(format!(
+                "carries view changes from {} voters, not a quorum",
+                voters.len()
+            ));
+        }
+        for certificate in msg
+            .view_changes
+            .iter()
+            .filter_map(|v| v.prepared.as_deref())
+        {
+            self.check_certificate(certificate, msg.sequence, msg.view)?;
+        }
+        let own = self.prepared_certificate(msg.sequence);
+        let required = msg
+            .view_changes
+            .iter()
+            .filter_map(|v| v.prepared.as_deref())
+            .chain(own.as_ref())
+            .max_by_key(|certificate| certificate.view);
+        if let Some(certificate) = required {
+            if certificate.block_hash != msg.block_hash {
+                return Err(format!(
+                    "proposes block {}, but block {} was prepared for sequence {} in view {}",
+                    msg.block_hash, certificate.block_hash, msg.sequence, certificate.view
+                ));
             }
         }
+        self.check_proposal(msg)
+    }
 
-        let state = self.state.read();
-        let votes = state.prepares.get(&key).unwrap();
-        state.has_quorum(votes, total_nodes)
+    /// Whether `view` was entered by a view change for `sequence`
+    fn view_changed_at(&self, sequence: u64, view: u64) -> bool {
+        self.state
+            .read()
+            .view_changes
+            .get(&view)
+            .is_some_and(|votes| votes.iter().any(|vote| vote.sequence == sequence))
+    }
+
+    /// Checks shared by pre-prepares and `NewView`s: the sender is the
+    /// primary, no other block was accepted for the sequence in the view,
+    /// and the block matches its id
+    fn check_proposal(&self, msg: &PBFTMessage) -> Result<(), String> {
+        let primary = self
+            .primary_in_view(msg.sequence, msg.view)
+            .ok_or("cluster has no voting members")?;
+        if msg.node_id != primary {
+            return Err(format!(
+                "sent by node {}, but node {} is primary for sequence {} in view {}",
+                msg.node_id, primary, msg.sequence, msg.view
+            ));
+        }
+        if let Some(accepted) = self.pre_prepared_digest_in(msg.sequence, msg.view) {
+            if accepted != msg.block_hash {
+                return Err(format!(
+                    "conflicts with block {} already pre-prepared for sequence {}",
+                    accepted, msg.sequence
+                ));
+            }
+        }
+        let json = msg
+            .block_data_json
+            .as_deref()
+            .ok_or("pre-prepare carries no block")?;
+        self.check_block(json, &msg.block_hash, msg.sequence)
+    }
+
+    fn check_block(&self, json: &str, block_hash: &str, sequence: u64) -> Result<(), String> {
+        let block: Block =
+            serde_json::from_str(json).map_err(|e| format!("undecodable block: {}", e))?;
+        // Shards number their blocks apart from the chain index
+        if self.shard.is_none() && block.index != sequence {
+            return Err(format!(
+                "block index {} does not match sequence {}",
+                block.index, sequence
+            ));
+        }
+        if block.content_id() != block_hash {
+            return Err("block content does not match the proposed id".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check that `certificate` was gathered for `sequence` before `view`
+    /// and holds a prepare quorum for the block it carries
+    fn check_certificate(
+        &self,
+        certificate: &PreparedCertificate,
+        sequence: u64,
+        view: u64,
+    ) -> Result<(), String> {
+        if certificate.sequence != sequence || certificate.view >= view {
+            return Err(format!(
+                "certificate for sequence {} in view {} does not precede view {} for sequence {}",
+                certificate.sequence, certificate.view, view, sequence
+            ));
+        }
+        let mut voters = certificate.prepares.clone();
+        voters.sort_unstable();
+        voters.dedup();
+        if voters.len() != certificate.prepares.len() || !self.has_quorum(&voters) {
+            return Err(format!(
+                "certificate for block {} holds no prepare quorum",
+                certificate.block_hash
+            ));
+        }
+        self.check_block(
+            &certificate.block_data_json,
+            &certificate.block_hash,
+            sequence,
+        )
+        .map_err(|e| format!("certified {}", e))
+    }
+
+    pub fn handle_pre_prepare(&self, msg: &PBFTMessage) -> bool {
+        if self.is_observer_vote(msg) {
+            return false;
+        }
+        let key = (msg.view, msg.sequence, msg.block_hash.clone());
+
+        // Recorded under the same lock so the log order matches the state
+        let mut state = self.state.write();
+        if let Some(json) = &msg.block_data_json {
+            state.proposals.insert(key.clone(), json.clone());
+        }
+        let votes = state.pre_prepares.entry(key).or_default();
+        if !votes.contains(&msg.node_id) {
+            votes.push(msg.node_id);
+        }
+        let quorum_reached = self.has_quorum(votes);
+        self.record_message(msg, quorum_reached);
+        quorum_reached
+    }
+
+    pub fn handle_prepare(&self, msg: &PBFTMessage) -> bool {
+        if self.is_observer_vote(msg) {
+            return false;
+        }
+        let key = (msg.view, msg.sequence, msg.block_hash.clone());
+
+        // Recorded under the same lock so the log order matches the state
+        let mut state = self.state.write();
+        let votes = state.prepares.entry(key).or_default();
+        if !votes.contains(&msg.node_id) {
+            votes.push(msg.node_id);
+        }
+        let quorum_reached = self.has_quorum(votes);
+        self.record_message(msg, quorum_reached);
+        quorum_reached
     }
 
     pub fn handle_commit(&self, msg: &PBFTMessage) -> bool {
-        let key = (msg.view, msg.sequence);
-        let total_nodes = self.total_nodes;
+        if self.is_observer_vote(msg) {
+            return false;
+        }
+        let key = (msg.view, msg.sequence, msg.block_hash.clone());
         let sequence = msg.sequence;
 
-        {
-            let mut state = self.state.write();
-            let votes = state.commits.entry(key).or_insert_with(Vec::new);
-            if !votes.contains(&msg.node_id) {
-                votes.push(msg.node_id);
+        let mut state = self.state.write();
+        let votes = state.commits.entry(key).or_default();
+        if !votes.contains(&msg.node_id) {
+            votes.push(msg.node_id);
+        }
+        let has_quorum = self.has_quorum(votes);
+        self.record_message(msg, has_quorum);
+        if has_quorum && !state.committed_blocks.contains(&sequence) {
+            state.committed_blocks.push(sequence);
+            self.record(ConsensusEvent::Committed {
+                sequence,
+                shard: self.shard.clone(),
+            });
+        }
+        has_quorum
+    }
+
+    /// Count a vote for `msg.view`; returns true once a quorum has voted
+    /// for it, at which point this node moves to that view. A vote whose
+    /// prepared certificate does not hold up is dropped.
+    pub fn handle_view_change(&self, msg: &PBFTMessage) -> bool {
+        if self.is_observer_vote(msg) {
+            return false;
+        }
+        if let Some(certificate) = &msg.prepared {
+            if let Err(reason) = self.check_certificate(certificate, msg.sequence, msg.view) {
+                warn!(
+                    node_id = msg.node_id,
+                    sequence = msg.sequence,
+                    reason = %reason,
+                    "PBFT: Rejected view change"
+                );
+                return false;
             }
         }
 
         let mut state = self.state.write();
-        let votes = state.commits.get(&key).unwrap();
-        let has_quorum = state.has_quorum(votes, total_nodes);
-        if has_quorum && !state.committed_blocks.contains(&sequence) {
-            state.committed_blocks.push(sequence);
+        let votes = state.view_changes.entry(msg.view).or_default();
+        if !votes.iter().any(|vote| vote.node_id == msg.node_id) {
+            votes.push(msg.clone());
+        }
+        let voters: Vec<usize> = votes.iter().map(|vote| vote.node_id).collect();
+        let has_quorum = self.has_quorum(&voters);
+        self.record_message(msg, has_quorum);
+        if has_quorum && msg.view > state.view {
+            info!(
+                view = msg.view,
+                sequence = msg.sequence,
+                "PBFT: View change, new primary elected"
+            );
+            state.view = msg.view;
         }
         has_quorum
     }
 
+    /// Move to the `NewView`'s view, keeping the votes that elected its
+    /// sender, and count it as the pre-prepare for its sequence there
+    pub fn handle_new_view(&self, msg: &PBFTMessage) -> bool {
+        if self.is_observer_vote(msg) {
+            return false;
+        }
+        {
+            let mut state = self.state.write();
+            if msg.view > state.view {
+                info!(
+                    view = msg.view,
+                    sequence = msg.sequence,
+                    primary = msg.node_id,
+                    "PBFT: Entered new view"
+                );
+                state.view = msg.view;
+            }
+            let votes = state.view_changes.entry(msg.view).or_default();
+            for vote in &msg.view_changes {
+                if !votes.iter().any(|v| v.node_id == vote.node_id) {
+                    votes.push(vote.clone());
+                }
+            }
+        }
+        self.handle_pre_prepare(msg)
+    }
+
+    pub fn view(&self) -> u64 {
+        self.state.read().view
+    }
+
+    /// Whether a pre-prepare for `sequence` arrived in the current view
+    pub fn has_pre_prepare(&self, sequence: u64) -> bool {
+        self.pre_prepared_digest(sequence).is_some()
+    }
+
+    /// Block id of the pre-prepare accepted for `sequence` in the current view
+    pub fn pre_prepared_digest(&self, sequence: u64) -> Option<String> {
+        self.pre_prepared_digest_in(sequence, self.view())
+    }
+
+    /// The highest-view block this node saw gather a prepare quorum for
+    /// `sequence`; its `ViewChange` votes carry it
+    pub fn prepared_certificate(&self, sequence: u64) -> Option<PreparedCertificate> {
+        let state = self.state.read();
+        state
+            .prepares
+            .iter()
+            .filter(|(key, votes)| key.1 == sequence && self.has_quorum(votes))
+            .filter_map(|(key, votes)| {
+                Some(PreparedCertificate {
+                    view: key.0,
+                    sequence,
+                    block_hash: key.2.clone(),
+                    block_data_json: state.proposals.get(key)?.clone(),
+                    prepares: votes.clone(),
+                })
+            })
+            .max_by_key(|certificate| certificate.view)
+    }
+
+    fn pre_prepared_digest_in(&self, sequence: u64, view: u64) -> Option<String> {
+        let state = self.state.read();
+        state
+            .pre_prepares
+            .keys()
+            .find(|(v, seq, _)| *v == view && *seq == sequence)
+            .map(|(_, _, digest)| digest.clone())
+    }
+
     pub fn is_committed(&self, sequence: u64) -> bool {
         let state = self.state.read();
         state.committed_blocks.contains(&sequence)
     }
 
+    /// Undo the commit of `sequence` after the cross-shard coordinator
+    /// aborted its block, dropping the votes so a block proposed again under
+    /// the same sequence starts from scratch
+    pub fn roll_back(&self, sequence: u64) {
+        let mut state = self.state.write();
+        state.committed_blocks.retain(|&s| s != sequence);
+        state.pre_prepares.retain(|key, _| key.1 != sequence);
+        state.proposals.retain(|key, _| key.1 != sequence);
+        state.prepares.retain(|key, _| key.1 != sequence);
+        state.commits.retain(|key, _| key.1 != sequence);
+        self.record(ConsensusEvent::RolledBack {
+            sequence,
+            shard: self.shard.clone(),
+        });
+    }
+
     pub fn node_id(&self) -> usize {
         self.state.read().node_id
     }
@@ -152,7 +742,7 @@ impl PBFTManager {
     pub fn create_pre_prepare(
         &self,
         block_hash: &str,
-        block_data_json: &str,
+        block_data_json: String,
         sequence: u64,
     ) -> PBFTMessage {
         let state = self.state.read();
@@ -161,9 +751,15 @@ impl PBFTManager {
             view: state.view,
             sequence,
             block_hash: block_hash.to_string(),
-            block_data_json: Some(block_data_json.to_string()),
+            block_data_json: Some(block_data_json),
             node_id: state.node_id,
-            timestamp: Utc::now().timestamp(),
+            timestamp: now_millis(),
+            shard: self.shard.clone(),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: Some(self.clock.now()),
+            prepared: None,
+            view_changes: Vec::new(),
         }
     }
 
@@ -176,7 +772,13 @@ impl PBFTManager {
             block_hash: block_hash.to_string(),
             block_data_json: None,
             node_id: state.node_id,
-            timestamp: Utc::now().timestamp(),
+            timestamp: now_millis(),
+            shard: self.shard.clone(),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: Some(self.clock.now()),
+            prepared: None,
+            view_changes: Vec::new(),
         }
     }
 
@@ -189,77 +791,300 @@ impl PBFTManager {
             block_hash: block_hash.to_string(),
             block_data_json: None,
             node_id: state.node_id,
-            timestamp: Utc::now().timestamp(),
+            timestamp: now_millis(),
+            shard: self.shard.clone(),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: Some(self.clock.now()),
+            prepared: None,
+            view_changes: Vec::new(),
         }
     }
 
+    /// Vote to replace the primary of `sequence` by moving to the next view
+    pub fn create_view_change(&self, sequence: u64) -> PBFTMessage {
+        let prepared = self.prepared_certificate(sequence).map(Box::new);
+        let state = self.state.read();
+        PBFTMessage {
+            msg_type: MessageType::ViewChange,
+            view: state.view + 1,
+            sequence,
+            block_hash: String::new(),
+            block_data_json: None,
+            node_id: state.node_id,
+            timestamp: now_millis(),
+            shard: self.shard.clone(),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: Some(self.clock.now()),
+            prepared,
+            view_changes: Vec::new(),
+        }
+    }
+
+    /// The `NewView` this node sends as primary of `sequence` in a view it
+    /// was elected to by a view change: it proposes the block of the
+    /// highest-view prepared certificate among the votes, or `block_hash`
+    /// when none of the voters prepared a block
+    pub fn create_new_view(
+        &self,
+        block_hash: &str,
+        block_data_json: String,
+        sequence: u64,
+    ) -> Result<PBFTMessage, String> {
+        let state = self.state.read();
+        let votes: Vec<PBFTMessage> = state
+            .view_changes
+            .get(&state.view)
+            .into_iter()
+            .flatten()
+            .filter(|vote| vote.sequence == sequence)
+            .cloned()
+            .collect();
+        let voters: Vec<usize> = votes.iter().map(|vote| vote.node_id).collect();
+        if !self.has_quorum(&voters) {
+            return Err(format!(
+                "{} view change votes for view {} at sequence {} are not a quorum",
+                voters.len(),
+                state.view,
+                sequence
+            ));
+        }
+        let certified = votes
+            .iter()
+            .filter_map(|vote| vote.prepared.as_deref())
+            .max_by_key(|certificate| certificate.view);
+        let (block_hash, block_data_json) = match certified {
+            Some(certificate) => (
+                certificate.block_hash.clone(),
+                certificate.block_data_json.clone(),
+            ),
+            None => (block_hash.to_string(), block_data_json),
+        };
+        Ok(PBFTMessage {
+            msg_type: MessageType::NewView,
+            view: state.view,
+            sequence,
+            block_hash,
+            block_data_json: Some(block_data_json),
+            node_id: state.node_id,
+            timestamp: now_millis(),
+            shard: self.shard.clone(),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: Some(self.clock.now()),
+            prepared: None,
+            view_changes: votes,
+        })
+    }
+
+    /// The node that proposes `sequence` in the current view
+    pub fn primary_for(&self, sequence: u64) -> Option<usize> {
+        self.primary_in_view(sequence, self.view())
+    }
+
+    /// The primary rotates over the voting members with the sequence, and
+    /// each view change moves it on by one more
+    pub fn primary_in_view(&self, sequence: u64, view: u64) -> Option<usize> {
+        let members = self.voting_members();
+        if members.is_empty() {
+            return None;
+        }
+        let rotation = sequence
+            .wrapping_add(view)
+            .wrapping_add(self.primary_offset as u64);
+        Some(members[(rotation % members.len() as u64) as usize])
+    }
+
     pub fn is_primary(&self, sequence: u64) -> bool {
-        (sequence % self.total_nodes as u64) as usize == self.node_id()
+        self.primary_for(sequence) == Some(self.node_id())
+    }
+
+    /// Fewest votes that form a quorum: the smallest set of voting members,
+    /// lowest ids first, the quorum policy accepts
+    pub fn quorum_size(&self) -> usize {
+        let voters = self.voting_members();
+        (1..=voters.len())
+            .find(|&n| self.has_quorum(&voters[..n]))
+            .unwrap_or(voters.len())
+    }
+
+    /// Prepare and commit votes gathered for `sequence` in the current view,
+    /// counting the block with the most votes
+    pub fn round_progress(&self, sequence: u64, elapsed: std::time::Duration) -> PendingDetails {
+        let required = self.quorum_size() as f64;
+        let state = self.state.read();
+        let view = state.view;
+        let votes = |phase: &HashMap<VoteKey, Vec<usize>>| {
+            phase
+                .iter()
+                .filter(|((v, s, _), _)| *v == view && *s == sequence)
+                .map(|(_, votes)| votes.len())
+                .max()
+                .unwrap_or(0) as f64
+        };
+        PendingDetails::new(elapsed)
+            .with_phase("prepare", votes(&state.prepares), required)
+            .with_phase("commit", votes(&state.commits), required)
+    }
+}
+
+/// `PBFT_VIEW_CHANGE_TIMEOUT_MS`, or `DEFAULT_VIEW_CHANGE_TIMEOUT` when unset
+pub fn view_change_timeout_from_env() -> Result<Duration, String> {
+    match std::env::var("PBFT_VIEW_CHANGE_TIMEOUT_MS") {
+        Ok(ms) => ms
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis)
+            .ok_or_else(|| format!("invalid PBFT_VIEW_CHANGE_TIMEOUT_MS: '{}'", ms)),
+        Err(_) => Ok(DEFAULT_VIEW_CHANGE_TIMEOUT),
     }
 }
 
+/// Observer node ids from `PBFT_OBSERVERS`; empty when unset
+pub fn observers_from_env() -> Result<Vec<usize>, String> {
+    let Ok(spec) = std::env::var("PBFT_OBSERVERS") else {
+        return Ok(Vec::new());
+    };
+    spec.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| format!("invalid node id '{}' in PBFT_OBSERVERS", id))
+        })
+        .collect()
+}
+
 // ConsensusAlgorithm trait adapter
 
 pub struct PBFTConsensus {
     pbft: Arc<PBFTManager>,
     node_addresses: Vec<String>,
-    port: u16,
+    local: PeerAddr,
+    demo: Arc<DemoMode>,
+    peer_versions: Arc<PeerVersions>,
+    /// Persists messages until peers acknowledge them; without one they
+    /// are sent once, as the in-process benchmarks do
+    outbox: Option<Arc<Outbox>>,
 }
 
 impl PBFTConsensus {
+    /// `port` is where this node serves; with its entry in
+    /// `node_addresses` it identifies the node to skip when broadcasting
     pub fn new(pbft: Arc<PBFTManager>, node_addresses: Vec<String>, port: u16) -> Self {
+        let local = local_address(&node_addresses, pbft.node_id(), port);
         Self {
             pbft,
             node_addresses,
-            port,
+            local,
+            demo: Arc::new(DemoMode::default()),
+            peer_versions: Arc::new(PeerVersions::default()),
+            outbox: None,
+        }
+    }
+
+    /// Slow down and narrate each phase (see `consensus::demo`)
+    pub fn with_demo(mut self, demo: Arc<DemoMode>) -> Self {
+        self.demo = demo;
+        self
+    }
+
+    /// Share the node's negotiated protocol versions
+    pub fn with_peer_versions(mut self, peer_versions: Arc<PeerVersions>) -> Self {
+        self.peer_versions = peer_versions;
+        self
+    }
+
+    /// Send every message through `outbox`, as `run_pbft_consensus` does,
+    /// so a crash mid-round is finished by the outbox's replay
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    async fn broadcast(&self, message: &PBFTMessage) {
+        match &self.outbox {
+            Some(outbox) => {
+                outbox
+                    .broadcast(message, &self.node_addresses, &self.local)
+                    .await
+            }
+            None => {
+                broadcast_message(
+                    message,
+                    &self.node_addresses,
+                    &self.local,
+                    &self.peer_versions,
+                )
+                .await
+            }
         }
     }
 }
 
 #[async_trait]
 impl ConsensusAlgorithm for PBFTConsensus {
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>> {
-        use crate::network::broadcast_message;
-        use std::time::Duration;
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError> {
+        if self.pbft.observes_only() {
+            return Ok(ConsensusResult::Rejected(
+                "observer nodes do not propose".to_string(),
+            ));
+        }
+        // Retry messages peers have not acknowledged yet
+        if let Some(outbox) = &self.outbox {
+            if let Err(e) = outbox.replay().await {
+                warn!(error = %e, "Outbox: Failed to replay pending messages");
+            }
+        }
 
+        let started = Instant::now();
         let sequence = block.index;
+        let block_id = block.content_id();
+        let pause = self.demo.delay(Duration::from_millis(500));
 
+        self.demo.enter(DemoPhase::PrePrepare, sequence).await;
         if self.pbft.is_primary(sequence) {
             let block_json = serde_json::to_string(block)?;
             let pre_prepare_msg = self
                 .pbft
-                .create_pre_prepare(&block.hash, &block_json, sequence);
-            broadcast_message(&pre_prepare_msg, &self.node_addresses, self.port).await;
+                .create_pre_prepare(&block_id, block_json, sequence);
+            self.broadcast(&pre_prepare_msg).await;
             self.pbft.handle_pre_prepare(&pre_prepare_msg);
         }
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        tokio::time::sleep(pause).await;
 
-        let prepare_msg = self.pbft.create_prepare(&block.hash, sequence);
-        broadcast_message(&prepare_msg, &self.node_addresses, self.port).await;
+        self.demo.enter(DemoPhase::Prepare, sequence).await;
+        let prepare_msg = self.pbft.create_prepare(&block_id, sequence);
+        self.broadcast(&prepare_msg).await;
         self.pbft.handle_prepare(&prepare_msg);
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        tokio::time::sleep(pause).await;
 
-        let commit_msg = self.pbft.create_commit(&block.hash, sequence);
-        broadcast_message(&commit_msg, &self.node_addresses, self.port).await;
+        self.demo.enter(DemoPhase::Commit, sequence).await;
+        let commit_msg = self.pbft.create_commit(&block_id, sequence);
+        self.broadcast(&commit_msg).await;
         self.pbft.handle_commit(&commit_msg);
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        tokio::time::sleep(pause).await;
 
-        let state = self.pbft.state.read();
-        if state.committed_blocks.contains(&sequence) {
+        if self.pbft.state.read().committed_blocks.contains(&sequence) {
             Ok(ConsensusResult::Committed(block.clone()))
         } else {
-            Ok(ConsensusResult::Pending)
+            Ok(ConsensusResult::Pending(
+                self.pbft.round_progress(sequence, started.elapsed()),
+            ))
         }
     }
 
     async fn handle_message(
         &self,
         _message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>> {
-        Ok(ConsensusResult::Pending)
+    ) -> Result<ConsensusResult, ConsensusError> {
+        Ok(ConsensusResult::Pending(PendingDetails::default()))
     }
 
     fn name(&self) -> &str {
@@ -367,11 +1192,23 @@ mod tests {
             block_hash: "test_hash".to_string(),
             block_data_json: None,
             node_id: 1,
-            timestamp: 1234567890,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
         };
 
         let result = manager.handle_prepare(&msg);
         assert!(!result);
+
+        let progress = manager.round_progress(1, std::time::Duration::ZERO);
+        assert_eq!(manager.quorum_size(), 3);
+        assert_eq!(progress.stalled_phase().unwrap().phase, "prepare");
+        assert_eq!(progress.missing_votes(), Some(2.0));
+        assert_eq!(progress.to_string(), "prepare 1/3, commit 0/3 after 0ms");
     }
 
     #[test]
@@ -392,7 +1229,13 @@ mod tests {
             block_hash: "test_hash".to_string(),
             block_data_json: None,
             node_id: 0,
-            timestamp: 1234567890,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
         };
 
         let msg2 = PBFTMessage {
@@ -402,7 +1245,13 @@ mod tests {
             block_hash: "test_hash".to_string(),
             block_data_json: None,
             node_id: 1,
-            timestamp: 1234567890,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
         };
 
         let msg3 = PBFTMessage {
@@ -412,7 +1261,13 @@ mod tests {
             block_hash: "test_hash".to_string(),
             block_data_json: None,
             node_id: 2,
-            timestamp: 1234567890,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
         };
 
         manager.handle_commit(&msg1);
@@ -422,4 +1277,170 @@ mod tests {
         assert!(result);
         assert!(manager.is_committed(1));
     }
+
+    #[test]
+    fn test_votes_for_different_blocks_do_not_combine() {
+        init();
+        let manager = PBFTManager::new(0, 4, Vec::new());
+        let commit = |node_id: usize, block_hash: &str| {
+            let mut msg = manager.create_commit(block_hash, 1);
+            msg.node_id = node_id;
+            msg
+        };
+
+        // Two votes for one block and one for another are not a quorum
+        assert!(!manager.handle_commit(&commit(0, "block_a")));
+        assert!(!manager.handle_commit(&commit(1, "block_a")));
+        assert!(!manager.handle_commit(&commit(2, "block_b")));
+        assert!(!manager.is_committed(1));
+        let progress = manager.round_progress(1, std::time::Duration::ZERO);
+        assert_eq!(progress.to_string(), "prepare 0/3, commit 2/3 after 0ms");
+
+        assert!(manager.handle_commit(&commit(3, "block_a")));
+        assert!(manager.is_committed(1));
+    }
+
+    /// Block `index` as primary `node_id` would propose it
+    fn proposal(manager: &PBFTManager, node_id: usize, index: u64, price: i64) -> PBFTMessage {
+        let entry = crate::testing::entry(
+            "BTC",
+            "Kraken",
+            crate::etl::price::Decimal::from(price),
+            1_700_000_000_000,
+        );
+        let block = crate::testing::block(index, "parent", vec![entry]);
+        let mut msg = manager.create_pre_prepare(
+            &block.content_id(),
+            serde_json::to_string(&block).unwrap(),
+            index,
+        );
+        msg.node_id = node_id;
+        msg
+    }
+
+    #[test]
+    fn test_replicas_reject_invalid_pre_prepares() {
+        init();
+        let replica = PBFTManager::new(1, 4, Vec::new());
+        assert_eq!(replica.primary_for(2), Some(2));
+
+        // Only the sequence's primary may propose
+        assert!(!replica.handle_message(&proposal(&replica, 3, 2, 50_000)));
+        assert!(!replica.has_pre_prepare(2));
+
+        // Nor for a view this replica is not in
+        let mut future = proposal(&replica, 2, 2, 50_000);
+        future.view = 1;
+        assert!(replica.validate_pre_prepare(&future).is_err());
+
+        assert!(!replica.handle_message(&proposal(&replica, 2, 2, 50_000)));
+        let accepted = replica.pre_prepared_digest(2).unwrap();
+        // A second block for the same sequence is dropped
+        assert!(!replica.handle_message(&proposal(&replica, 2, 2, 60_000)));
+        assert_eq!(replica.pre_prepared_digest(2), Some(accepted));
+        assert_eq!(replica.state.read().pre_prepares.len(), 1);
+    }
+
+    #[test]
+    fn test_view_change_keeps_the_prepared_block() {
+        init();
+        let nodes: Vec<PBFTManager> = (0..4)
+            .map(|id| PBFTManager::new(id, 4, Vec::new()))
+            .collect();
+        let deliver = |msg: &PBFTMessage| {
+            for node in &nodes {
+                node.handle_message(msg);
+            }
+        };
+
+        // Block A prepares in view 0, then its primary falls silent
+        assert_eq!(nodes[0].primary_for(1), Some(1));
+        let block_a = proposal(&nodes[1], 1, 1, 50_000);
+        deliver(&block_a);
+        for id in [0, 2, 3] {
+            deliver(&nodes[id].create_prepare(&block_a.block_hash, 1));
+        }
+        let prepared = nodes[0].prepared_certificate(1).unwrap();
+        assert_eq!(prepared.block_hash, block_a.block_hash);
+        for id in [0, 2, 3] {
+            deliver(&nodes[id].create_view_change(1));
+        }
+        assert!(nodes.iter().all(|node| node.view() == 1));
+
+        // The new primary would rather propose block B
+        assert_eq!(nodes[0].primary_for(1), Some(2));
+        let block_b = proposal(&nodes[2], 2, 1, 60_000);
+        assert!(nodes[0].validate_pre_prepare(&block_b).is_err());
+        let new_view = nodes[2]
+            .create_new_view(
+                &block_b.block_hash,
+                block_b.block_data_json.clone().unwrap(),
+                1,
+            )
+            .unwrap();
+        assert_eq!(new_view.block_hash, block_a.block_hash);
+
+        // A NEW-VIEW for B is refused, with or without the certificates
+        let mut forged = PBFTMessage {
+            block_hash: block_b.block_hash.clone(),
+            block_data_json: block_b.block_data_json.clone(),
+            ..new_view.clone()
+        };
+        assert!(nodes[0]
+            .validate_pre_prepare(&forged)
+            .unwrap_err()
+            .contains("was prepared"));
+        for vote in &mut forged.view_changes {
+            vote.prepared = None;
+        }
+        assert!(nodes[3]
+            .validate_pre_prepare(&forged)
+            .unwrap_err()
+            .contains("misreports"));
+        assert!(!nodes[0].handle_message(&forged));
+        assert!(!nodes[0].handle_message(&block_b));
+
+        // A commits in view 1, and B never gets a vote
+        deliver(&new_view);
+        for phase in [MessageType::Prepare, MessageType::Commit] {
+            for id in [0, 2, 3] {
+                deliver(&match phase {
+                    MessageType::Prepare => nodes[id].create_prepare(&new_view.block_hash, 1),
+                    _ => nodes[id].create_commit(&new_view.block_hash, 1),
+                });
+            }
+        }
+        for node in &nodes {
+            assert!(node.is_committed(1));
+            let state = node.state.read();
+            assert!(state.commits.keys().all(|key| key.2 == block_a.block_hash));
+            assert!(!state
+                .pre_prepares
+                .keys()
+                .any(|key| key.2 == block_b.block_hash));
+        }
+    }
+
+    #[test]
+    fn test_messages_carry_hlc() {
+        init();
+        let primary = PBFTManager::new(0, 4, Vec::new());
+        let replica = PBFTManager::new(1, 4, Vec::new());
+
+        let commit = primary.create_commit("test_hash", 1);
+        let sent = commit.hlc.unwrap();
+        replica.handle_message(&commit);
+        // The replica's next message orders after the one it received
+        let reply = replica.create_commit("test_hash", 1).hlc.unwrap();
+        assert!(reply > sent);
+
+        let mut runaway = primary.create_commit("test_hash", 2);
+        runaway.node_id = 2;
+        runaway.hlc = Some(HlcTimestamp {
+            wall_ms: sent.wall_ms + 3_600_000,
+            ..sent
+        });
+        assert!(!replica.handle_message(&runaway));
+        assert!(!replica.state.read().commits.keys().any(|key| key.1 == 2));
+    }
 }
@@ -1,14 +1,15 @@
 //! Quorum-less consensus with weighted voting
 
 use crate::consensus::{
-    ConsensusAlgorithm, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusRequirements, ConsensusResult,
+    PendingDetails,
 };
 use crate::etl::Block;
 use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
 use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
@@ -51,32 +52,45 @@ impl QuorumlessConsensus {
 
 #[async_trait]
 impl ConsensusAlgorithm for QuorumlessConsensus {
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>> {
-        let mut votes = self.votes.write();
-        let block_votes = votes.entry(block.index).or_insert_with(HashMap::new);
-
-        block_votes.insert(self.node_id, true);
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError> {
+        let started = Instant::now();
+        let voters: Vec<usize> = {
+            let mut votes = self.votes.write();
+            let block_votes = votes.entry(block.index).or_insert_with(HashMap::new);
+            block_votes.insert(self.node_id, true);
+            block_votes
+                .iter()
+                .filter(|(_, voted)| **voted)
+                .map(|(node_id, _)| *node_id)
+                .collect()
+        };
 
-        let weights = self.node_weights.read();
-        let mut total_weight = 0.0;
-        for (node_id, voted) in block_votes.iter() {
-            if *voted {
-                total_weight += weights.get(node_id).copied().unwrap_or(1.0);
-            }
-        }
+        let total_weight: f64 = {
+            let weights = self.node_weights.read();
+            voters
+                .iter()
+                .map(|node_id| weights.get(node_id).copied().unwrap_or(1.0))
+                .sum()
+        };
 
         if total_weight >= self.threshold_weight {
             self.committed.write().insert(block.index);
             Ok(ConsensusResult::Committed(block.clone()))
         } else {
-            Ok(ConsensusResult::Pending)
+            Ok(ConsensusResult::Pending(
+                PendingDetails::new(started.elapsed()).with_phase(
+                    "weight",
+                    total_weight,
+                    self.threshold_weight,
+                ),
+            ))
         }
     }
 
     async fn handle_message(
         &self,
         message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>> {
+    ) -> Result<ConsensusResult, ConsensusError> {
         {
             let mut votes = self.votes.write();
             let block_votes = votes
@@ -84,7 +98,7 @@ impl ConsensusAlgorithm for QuorumlessConsensus {
                 .or_insert_with(HashMap::new);
             block_votes.insert(message.node_id, true);
         }
-        Ok(ConsensusResult::Pending)
+        Ok(ConsensusResult::Pending(PendingDetails::default()))
     }
 
     fn name(&self) -> &str {
@@ -1,18 +1,65 @@
 //! Consensus algorithm comparison and benchmarking
 
-use crate::consensus::{ConsensusRequirements, ConsensusResult};
+use crate::consensus::cost_model::{
+    AttackCostModel, HashPowerCost, QuorumCollusionCost, SingleIdentityCost,
+};
+use crate::consensus::{ConsensusError, ConsensusRequirements, ConsensusResult};
 use crate::etl::Block;
+use crate::system_metrics::{SystemMetrics, SystemMonitor};
 use async_trait::async_trait;
-use std::error::Error;
+use serde::Serialize;
 use std::sync::Arc;
 use std::time::Instant;
 
 #[async_trait]
-pub trait ConsensusStrategy: Send + Sync {
-    async fn execute(&self, block: &Block) -> Result<Option<Block>, Box<dyn Error>>;
+pub trait ConsensusStrategy: Send + Sync + 'static {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError>;
     fn name(&self) -> &str;
     fn requirements(&self) -> ConsensusRequirements;
     fn is_committed(&self, block_index: u64) -> bool;
+
+    /// Regional spread of the nodes running the strategy (0-1), when the
+    /// strategy runs over a `network_model::NetworkModel`
+    fn geographical_diversity(&self) -> Option<f64> {
+        None
+    }
+
+    /// Resources an attacker needs to break safety, from the strategy's
+    /// `cost_model::AttackCostModel`; `None` when no model applies
+    fn cost_of_attack(&self) -> Option<f64> {
+        None
+    }
+
+    /// Locally committed blocks that ended up off the canonical chain, for
+    /// strategies tracked by a `fork_choice::ForkTree`
+    fn stale_blocks(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the strategy runs in-process or drives a live cluster
+    fn benchmark_mode(&self) -> BenchmarkMode {
+        BenchmarkMode::Simulated
+    }
+}
+
+/// Where a benchmark's latency and throughput were measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BenchmarkMode {
+    /// Every node in one process, with simulated or no network delay
+    #[default]
+    Simulated,
+    /// Proposals sent over HTTP to a running multi-node cluster
+    Networked,
+}
+
+impl BenchmarkMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchmarkMode::Simulated => "simulated",
+            BenchmarkMode::Networked => "networked",
+        }
+    }
 }
 
 pub struct NoConsensusStrategy {
@@ -29,7 +76,7 @@ impl NoConsensusStrategy {
 
 #[async_trait]
 impl ConsensusStrategy for NoConsensusStrategy {
-    async fn execute(&self, block: &Block) -> Result<Option<Block>, Box<dyn Error>> {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
         let mut committed = self.committed.write();
         committed.insert(block.index);
         Ok(Some(block.clone()))
@@ -51,6 +98,10 @@ impl ConsensusStrategy for NoConsensusStrategy {
         let committed = self.committed.read();
         committed.contains(&block_index)
     }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        Some(SingleIdentityCost::default().cost_of_attack())
+    }
 }
 
 pub struct SimpleMajorityStrategy {
@@ -78,25 +129,27 @@ impl SimpleMajorityStrategy {
 
 #[async_trait]
 impl ConsensusStrategy for SimpleMajorityStrategy {
-    async fn execute(&self, block: &Block) -> Result<Option<Block>, Box<dyn Error>> {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
         // Simulate collecting votes from other nodes
-        let mut votes = self.votes.write();
-        let block_votes = votes
-            .entry(block.index)
-            .or_insert_with(std::collections::HashSet::new);
-
-        // Add our own vote
-        block_votes.insert(self.node_id);
-
-        // Simulate other nodes voting (for demo purposes)
-        // In real implementation, this would come from network messages
-        for i in 0..self.total_nodes {
-            if i != self.node_id {
-                block_votes.insert(i);
+        let vote_count = {
+            let mut votes = self.votes.write();
+            let block_votes = votes
+                .entry(block.index)
+                .or_insert_with(std::collections::HashSet::new);
+
+            // Add our own vote
+            block_votes.insert(self.node_id);
+
+            // Simulate other nodes voting (for demo purposes)
+            // In real implementation, this would come from network messages
+            for i in 0..self.total_nodes {
+                if i != self.node_id {
+                    block_votes.insert(i);
+                }
             }
-        }
 
-        let vote_count = block_votes.len();
+            block_votes.len()
+        };
         let majority = self.majority_size();
 
         if vote_count >= majority {
@@ -128,6 +181,11 @@ impl ConsensusStrategy for SimpleMajorityStrategy {
         let committed = self.committed.read();
         committed.contains(&block_index)
     }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        let majority = self.majority_size();
+        Some(QuorumCollusionCost::new(self.total_nodes, majority, majority).cost_of_attack())
+    }
 }
 
 pub struct SimplifiedPoWStrategy {
@@ -162,7 +220,7 @@ impl SimplifiedPoWStrategy {
 
 #[async_trait]
 impl ConsensusStrategy for SimplifiedPoWStrategy {
-    async fn execute(&self, block: &Block) -> Result<Option<Block>, Box<dyn Error>> {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
         let mut block_to_mine = block.clone();
 
         self.mine_block(&mut block_to_mine);
@@ -196,24 +254,39 @@ impl ConsensusStrategy for SimplifiedPoWStrategy {
         let committed = self.committed.read();
         committed.contains(&block_index)
     }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        Some(HashPowerCost::for_difficulty(self.difficulty).cost_of_attack())
+    }
 }
 
 pub struct ConsensusAlgorithmAdapter {
     algorithm: Arc<dyn crate::consensus::ConsensusAlgorithm>,
+    cost_model: Option<Arc<dyn AttackCostModel>>,
 }
 
 impl ConsensusAlgorithmAdapter {
     pub fn new(algorithm: Arc<dyn crate::consensus::ConsensusAlgorithm>) -> Self {
-        Self { algorithm }
+        Self {
+            algorithm,
+            cost_model: None,
+        }
+    }
+
+    /// Report `cost_of_attack` from `model`, built from the same parameters
+    /// the algorithm was configured with
+    pub fn with_cost_model(mut self, model: Arc<dyn AttackCostModel>) -> Self {
+        self.cost_model = Some(model);
+        self
     }
 }
 
 #[async_trait]
 impl ConsensusStrategy for ConsensusAlgorithmAdapter {
-    async fn execute(&self, block: &Block) -> Result<Option<Block>, Box<dyn Error>> {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
         match self.algorithm.propose(block).await? {
             ConsensusResult::Committed(committed_block) => Ok(Some(committed_block)),
-            ConsensusResult::Pending => Ok(None),
+            ConsensusResult::Pending(_) => Ok(None),
             ConsensusResult::Rejected(_) => Ok(None),
         }
     }
@@ -229,6 +302,10 @@ impl ConsensusStrategy for ConsensusAlgorithmAdapter {
     fn is_committed(&self, block_index: u64) -> bool {
         self.algorithm.is_committed(block_index)
     }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        self.cost_model.as_ref().map(|model| model.cost_of_attack())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -242,9 +319,10 @@ pub struct ConsensusComparisonResult {
     pub data_integrity: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ConsensusMetrics {
     pub strategy_name: String,
+    pub mode: BenchmarkMode,
     pub total_blocks: usize,
     pub committed_blocks: usize,
     pub failed_blocks: usize,
@@ -259,7 +337,7 @@ pub struct ConsensusMetrics {
     // Extended metrics from trilemma paper (arXiv:2505.03768)
     // Degree of Decentralization (DoD)
     pub block_proposal_randomness: Option<f64>, // Entropy measure (0-1)
-    pub geographical_diversity: Option<f64>,    // Not applicable in simulation
+    pub geographical_diversity: Option<f64>,    // Region entropy (0-1), simulated networks only
     pub hashing_power_distribution: Option<f64>, // Gini coefficient (0-1)
     pub token_concentration: Option<f64>,       // Gini coefficient (0-1)
     pub wealth_distribution: Option<f64>,       // Gini coefficient (0-1)
@@ -268,10 +346,12 @@ pub struct ConsensusMetrics {
     pub confirmation_latency_ms: f64, // Same as avg_latency_ms
     pub max_throughput_tps: f64,      // Same as throughput_blocks_per_sec
     // Security
-    pub cost_of_attack: Option<f64>, // Relative cost (simulated)
+    pub cost_of_attack: Option<f64>, // In the cost model's unit (see cost_model.rs)
     pub fault_tolerance: f64,        // Max faulty nodes tolerated (0-1)
     pub reliability: f64,            // Consistency over time (0-1)
     pub stale_block_rate: f64,       // Orphaned blocks / total blocks (0-100)
+    // Resources this process used over the run
+    pub system: SystemMetrics,
 }
 
 pub async fn compare_consensus_strategies(
@@ -322,6 +402,7 @@ pub async fn benchmark_consensus_strategy(
     let mut failed_count = 0;
     let mut error_count = 0;
     let mut data_integrity_maintained = true;
+    let monitor = SystemMonitor::new();
     let total_start = Instant::now();
 
     for block in blocks {
@@ -380,10 +461,11 @@ pub async fn benchmark_consensus_strategy(
         0.0
     };
 
-    let stale_block_rate = if committed_count > 0 {
-        (failed_count as f64 / committed_count as f64) * 100.0
-    } else {
-        0.0
+    // Stale blocks: only fork-tracked strategies can orphan a committed
+    // block; without fork storage the chain is linear and nothing goes stale
+    let stale_block_rate = match strategy.stale_blocks() {
+        Some(stale) if committed_count > 0 => (stale as f64 / committed_count as f64) * 100.0,
+        _ => 0.0,
     };
 
     let reliability = if !blocks.is_empty() {
@@ -402,12 +484,8 @@ pub async fn benchmark_consensus_strategy(
         0.1
     };
 
-    // Cost of attack: relative measure based on quorum requirements
-    let cost_of_attack = if strategy.requirements().requires_majority {
-        Some(0.75) // Higher cost for majority-based
-    } else {
-        Some(0.3) // Lower cost for non-majority
-    };
+    // Cost of attack: from the strategy's cost model, if it has one
+    let cost_of_attack = strategy.cost_of_attack();
 
     // Block proposal randomness: entropy measure based on algorithm characteristics
     // PBFT: deterministic primary (sequence % N) -> low randomness
@@ -431,12 +509,13 @@ pub async fn benchmark_consensus_strategy(
     let token_concentration = None;
     let wealth_distribution = None;
 
-    // Geographical diversity: not applicable in single-machine simulation
-    // Would require actual node location data
-    let geographical_diversity = None;
+    // Geographical diversity: only known when the strategy runs over a
+    // simulated network model with region assignments
+    let geographical_diversity = strategy.geographical_diversity();
 
     ConsensusMetrics {
         strategy_name: strategy.name().to_string(),
+        mode: strategy.benchmark_mode(),
         total_blocks: blocks.len(),
         committed_blocks: committed_count,
         failed_blocks: failed_count,
@@ -461,6 +540,7 @@ pub async fn benchmark_consensus_strategy(
         fault_tolerance,
         reliability,
         stale_block_rate,
+        system: monitor.sample(),
     }
 }
 
@@ -512,8 +592,9 @@ pub fn print_metrics_comparison(metrics: &[ConsensusMetrics]) {
     println!("{}", "=".repeat(140));
     println!();
     println!(
-        "{:<25} | {:<8} | {:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<8} | {:<8} | {}",
+        "{:<25} | {:<9} | {:<8} | {:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<8} | {:<8} | {}",
         "Strategy",
+        "Mode",
         "Total",
         "Commit",
         "Failed",
@@ -528,8 +609,9 @@ pub fn print_metrics_comparison(metrics: &[ConsensusMetrics]) {
     println!("{}", "-".repeat(140));
 
     for metric in metrics {
-        println!("{:<25} | {:<8} | {:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10.2} | {:<8.2} | {:<8.2} | {}", 
+        println!("{:<25} | {:<9} | {:<8} | {:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10.2} | {:<8.2} | {:<8.2} | {}", 
             metric.strategy_name,
+            metric.mode.name(),
             metric.total_blocks,
             metric.committed_blocks,
             metric.failed_blocks,
@@ -580,6 +662,22 @@ pub fn print_metrics_comparison(metrics: &[ConsensusMetrics]) {
         );
     }
 
+    if let Some(heaviest) = metrics
+        .iter()
+        .filter(|m| m.system.rss_bytes.is_some())
+        .max_by_key(|m| m.system.rss_bytes)
+    {
+        println!(
+            "  Largest Memory Footprint: {} ({} resident, CPU {})",
+            heaviest.strategy_name,
+            heaviest.system.memory_display(),
+            heaviest
+                .system
+                .cpu_percent
+                .map_or("N/A".to_string(), |cpu| format!("{:.1}%", cpu))
+        );
+    }
+
     let integrity_ok = metrics
         .iter()
         .filter(|m| m.data_integrity_maintained)
@@ -0,0 +1,168 @@
+//! Cost-of-attack models for the security metrics
+//!
+//! Each model turns an algorithm's configured parameters into the resources
+//! an attacker must control to break safety: colluding identities for
+//! quorum-based protocols, stake for weighted voting, hash power for
+//! proof-of-work. Costs are expressed in the model's own unit (identity
+//! weight, stake units, hashes), so they compare within a resource type
+//! rather than across them.
+//!
+//! Attach a model with `ConsensusAlgorithmAdapter::with_cost_model`; the
+//! built-in strategies in `comparison.rs` provide their own.
+
+/// Computes the cost of attacking one consensus configuration
+pub trait AttackCostModel: Send + Sync {
+    /// Resources required to break safety, in the model's unit
+    fn cost_of_attack(&self) -> f64;
+
+    /// Short explanation of how the cost was derived
+    fn basis(&self) -> String;
+}
+
+/// Quorum protocols break when two conflicting quorums can both form, which
+/// requires every node in their intersection to collude.
+///
+/// Use `quorum_a == quorum_b` for classic quorums (PBFT, simple majority)
+/// and the phase-1/phase-2 sizes for Flexible Paxos.
+#[derive(Debug, Clone)]
+pub struct QuorumCollusionCost {
+    pub total_nodes: usize,
+    pub quorum_a: usize,
+    pub quorum_b: usize,
+    /// Cost of running or corrupting one identity
+    pub identity_weight: f64,
+}
+
+impl QuorumCollusionCost {
+    pub fn new(total_nodes: usize, quorum_a: usize, quorum_b: usize) -> Self {
+        QuorumCollusionCost {
+            total_nodes,
+            quorum_a,
+            quorum_b,
+            identity_weight: 1.0,
+        }
+    }
+
+    pub fn with_identity_weight(mut self, weight: f64) -> Self {
+        self.identity_weight = weight;
+        self
+    }
+
+    /// Nodes shared by any two quorums; at least one identity is always needed
+    pub fn colluding_identities(&self) -> usize {
+        (self.quorum_a + self.quorum_b)
+            .saturating_sub(self.total_nodes)
+            .max(1)
+    }
+}
+
+impl AttackCostModel for QuorumCollusionCost {
+    fn cost_of_attack(&self) -> f64 {
+        self.colluding_identities() as f64 * self.identity_weight
+    }
+
+    fn basis(&self) -> String {
+        format!(
+            "{} colluding identities (quorums {}+{} of {}) x weight {}",
+            self.colluding_identities(),
+            self.quorum_a,
+            self.quorum_b,
+            self.total_nodes,
+            self.identity_weight
+        )
+    }
+}
+
+/// Weighted voting commits once `threshold_stake` agrees, so an attacker
+/// holding that much stake can commit alone
+#[derive(Debug, Clone)]
+pub struct StakeCost {
+    pub threshold_stake: f64,
+    /// Price of one unit of stake
+    pub unit_cost: f64,
+}
+
+impl StakeCost {
+    pub fn new(threshold_stake: f64) -> Self {
+        StakeCost {
+            threshold_stake,
+            unit_cost: 1.0,
+        }
+    }
+}
+
+impl AttackCostModel for StakeCost {
+    fn cost_of_attack(&self) -> f64 {
+        self.threshold_stake * self.unit_cost
+    }
+
+    fn basis(&self) -> String {
+        format!(
+            "{} stake x unit cost {}",
+            self.threshold_stake, self.unit_cost
+        )
+    }
+}
+
+/// Proof-of-work is rewritten by whoever controls a majority of hash power
+#[derive(Debug, Clone)]
+pub struct HashPowerCost {
+    /// Honest hash power, in hashes per block interval
+    pub network_hash_rate: f64,
+    /// Share of the total hash power the attacker needs
+    pub majority_fraction: f64,
+}
+
+impl HashPowerCost {
+    pub fn new(network_hash_rate: f64) -> Self {
+        HashPowerCost {
+            network_hash_rate,
+            majority_fraction: 0.51,
+        }
+    }
+
+    /// Expected work of one block at `difficulty` leading hex zeros
+    pub fn for_difficulty(difficulty: usize) -> Self {
+        HashPowerCost::new(16f64.powi(difficulty as i32))
+    }
+}
+
+impl AttackCostModel for HashPowerCost {
+    fn cost_of_attack(&self) -> f64 {
+        // Outpacing honest hash rate H needs attacker rate A with
+        // A / (A + H) >= fraction
+        self.network_hash_rate * self.majority_fraction / (1.0 - self.majority_fraction)
+    }
+
+    fn basis(&self) -> String {
+        format!(
+            "{:.0}% of hash power against {} hashes/block",
+            self.majority_fraction * 100.0,
+            self.network_hash_rate
+        )
+    }
+}
+
+/// Protocols without voting accept whatever a single identity proposes
+#[derive(Debug, Clone)]
+pub struct SingleIdentityCost {
+    pub identity_weight: f64,
+}
+
+impl Default for SingleIdentityCost {
+    fn default() -> Self {
+        SingleIdentityCost {
+            identity_weight: 1.0,
+        }
+    }
+}
+
+impl AttackCostModel for SingleIdentityCost {
+    fn cost_of_attack(&self) -> f64 {
+        self.identity_weight
+    }
+
+    fn basis(&self) -> String {
+        format!("1 identity x weight {}", self.identity_weight)
+    }
+}
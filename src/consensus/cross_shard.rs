@@ -0,0 +1,263 @@
+//! Cross-shard atomic commit
+//!
+//! A block whose entries span assets on different shards must be ordered by
+//! every one of those shards. `CrossShardCoordinator` runs a two-phase
+//! commit over the shard instances:
+//!
+//! 1. **Prepare** - each participant shard runs its own consensus on the
+//!    block in parallel; reaching a commit quorum there is that shard's yes
+//!    vote. A shard that fails or misses the prepare timeout votes no.
+//! 2. **Decide** - the block commits only if every participant voted yes,
+//!    otherwise it is aborted on all of them: the shards that had already
+//!    committed it roll their commit back (`PBFTManager::roll_back`).
+//!
+//! The coordinator's decision is authoritative: callers persist a block only
+//! when `execute` returns `Decision::Committed`. Blocks that touch a single
+//! shard skip the protocol, and their latency is the baseline the cross-shard
+//! overhead in `CrossShardMetrics` is measured against. The coordinator
+//! remembers the decisions for the last `DEFAULT_DECISION_RETENTION` blocks.
+
+use crate::consensus::algorithms::PBFTManager;
+use crate::consensus::shard::ShardRouter;
+use crate::etl::Block;
+use parking_lot::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// How long a participant may take to vote before it counts as a no
+pub const DEFAULT_PREPARE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decisions kept for lookup; older ones are pruned
+pub const DEFAULT_DECISION_RETENTION: usize = 1024;
+
+/// Label used for the default (unsharded) instance
+pub const DEFAULT_SHARD: &str = "default";
+
+/// Final outcome of a block
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Committed,
+    /// Aborted on every participant; lists the shards that voted no
+    Aborted {
+        rejected_by: Vec<String>,
+    },
+}
+
+/// Result of running a block through the coordinator
+#[derive(Debug, Clone)]
+pub struct CrossShardOutcome {
+    pub decision: Decision,
+    /// Shards that took part, in shard id order
+    pub participants: Vec<String>,
+    pub latency: Duration,
+}
+
+impl CrossShardOutcome {
+    pub fn is_committed(&self) -> bool {
+        self.decision == Decision::Committed
+    }
+
+    pub fn is_cross_shard(&self) -> bool {
+        self.participants.len() > 1
+    }
+}
+
+/// Commit counts and latencies, split by single- and cross-shard blocks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrossShardMetrics {
+    pub single_shard_commits: usize,
+    pub cross_shard_commits: usize,
+    pub cross_shard_aborts: usize,
+    pub single_shard_latency: Duration,
+    pub cross_shard_latency: Duration,
+}
+
+impl CrossShardMetrics {
+    pub fn avg_single_shard_ms(&self) -> Option<f64> {
+        average_ms(self.single_shard_latency, self.single_shard_commits)
+    }
+
+    pub fn avg_cross_shard_ms(&self) -> Option<f64> {
+        average_ms(self.cross_shard_latency, self.cross_shard_commits)
+    }
+
+    /// Extra milliseconds an average cross-shard commit takes over a
+    /// single-shard one; `None` until both kinds have committed
+    pub fn overhead_ms(&self) -> Option<f64> {
+        Some(self.avg_cross_shard_ms()? - self.avg_single_shard_ms()?)
+    }
+}
+
+fn average_ms(total: Duration, count: usize) -> Option<f64> {
+    (count > 0).then(|| total.as_secs_f64() * 1000.0 / count as f64)
+}
+
+/// Two-phase commit over the shards a block touches
+pub struct CrossShardCoordinator {
+    router: Arc<ShardRouter>,
+    prepare_timeout: Duration,
+    decisions: RwLock<BTreeMap<u64, Decision>>,
+    decision_retention: usize,
+    metrics: Mutex<CrossShardMetrics>,
+}
+
+impl CrossShardCoordinator {
+    pub fn new(router: Arc<ShardRouter>) -> Self {
+        CrossShardCoordinator {
+            router,
+            prepare_timeout: DEFAULT_PREPARE_TIMEOUT,
+            decisions: RwLock::new(BTreeMap::new()),
+            decision_retention: DEFAULT_DECISION_RETENTION,
+            metrics: Mutex::new(CrossShardMetrics::default()),
+        }
+    }
+
+    pub fn with_prepare_timeout(mut self, timeout: Duration) -> Self {
+        self.prepare_timeout = timeout;
+        self
+    }
+
+    /// Keep the decisions of the last `blocks` blocks (at least one)
+    pub fn with_decision_retention(mut self, blocks: usize) -> Self {
+        self.decision_retention = blocks.max(1);
+        self
+    }
+
+    pub fn router(&self) -> &Arc<ShardRouter> {
+        &self.router
+    }
+
+    /// The instances that must order `block`: one per distinct shard among
+    /// its assets, with unsharded assets going to the default instance
+    pub fn participants(&self, block: &Block) -> BTreeMap<String, Arc<PBFTManager>> {
+        let mut participants = BTreeMap::new();
+        for data in &block.data {
+            let (label, instance) = match self.router.shard_for_asset(&data.asset) {
+                Some(shard) => (shard, self.router.shard(shard)),
+                None => (DEFAULT_SHARD, None),
+            };
+            participants
+                .entry(label.to_string())
+                .or_insert_with(|| instance.unwrap_or(self.router.default_instance()).clone());
+        }
+        if participants.is_empty() {
+            participants.insert(
+                DEFAULT_SHARD.to_string(),
+                self.router.default_instance().clone(),
+            );
+        }
+        participants
+    }
+
+    /// Run `block` through consensus on every shard it touches.
+    ///
+    /// `prepare` drives one shard's consensus and returns that shard's vote:
+    /// the sequence the shard committed the block under, or `None` for no.
+    /// Votes are collected in parallel, each bounded by the prepare timeout.
+    /// On abort, the shards that voted yes roll back their commit.
+    pub async fn execute<F, Fut>(&self, block: &Block, prepare: F) -> CrossShardOutcome
+    where
+        F: Fn(Arc<PBFTManager>, Block) -> Fut,
+        Fut: Future<Output = Option<u64>> + Send + 'static,
+    {
+        let started = Instant::now();
+        let participants = self.participants(block);
+        let labels: Vec<String> = participants.keys().cloned().collect();
+
+        let mut votes = JoinSet::new();
+        for (label, instance) in participants {
+            let vote = tokio::time::timeout(
+                self.prepare_timeout,
+                prepare(instance.clone(), block.clone()),
+            );
+            votes.spawn(async move { (label, instance, vote.await.ok().flatten()) });
+        }
+
+        let mut prepared = Vec::new();
+        let mut rejected_by = Vec::new();
+        while let Some(result) = votes.join_next().await {
+            match result {
+                Ok((_, instance, Some(sequence))) => prepared.push((instance, sequence)),
+                Ok((label, _, None)) => rejected_by.push(label),
+                Err(e) => {
+                    warn!(error = %e, "CrossShard: Prepare task failed");
+                    rejected_by.push("unknown".to_string());
+                }
+            }
+        }
+        rejected_by.sort();
+
+        let decision = if rejected_by.is_empty() {
+            Decision::Committed
+        } else {
+            for (instance, sequence) in prepared {
+                instance.roll_back(sequence);
+            }
+            Decision::Aborted { rejected_by }
+        };
+        let outcome = CrossShardOutcome {
+            decision: decision.clone(),
+            participants: labels,
+            latency: started.elapsed(),
+        };
+        {
+            let mut decisions = self.decisions.write();
+            decisions.insert(block.index, decision);
+            while decisions.len() > self.decision_retention {
+                decisions.pop_first();
+            }
+        }
+        self.record(&outcome);
+
+        if outcome.is_cross_shard() {
+            match &outcome.decision {
+                Decision::Committed => info!(
+                    block_index = block.index,
+                    shards = ?outcome.participants,
+                    latency_ms = outcome.latency.as_millis() as u64,
+                    "CrossShard: Block committed on all shards"
+                ),
+                Decision::Aborted { rejected_by } => warn!(
+                    block_index = block.index,
+                    shards = ?outcome.participants,
+                    rejected_by = ?rejected_by,
+                    "CrossShard: Block aborted on all shards"
+                ),
+            }
+        }
+        outcome
+    }
+
+    fn record(&self, outcome: &CrossShardOutcome) {
+        let mut metrics = self.metrics.lock();
+        match (outcome.is_cross_shard(), outcome.is_committed()) {
+            (false, true) => {
+                metrics.single_shard_commits += 1;
+                metrics.single_shard_latency += outcome.latency;
+            }
+            (true, true) => {
+                metrics.cross_shard_commits += 1;
+                metrics.cross_shard_latency += outcome.latency;
+            }
+            (true, false) => metrics.cross_shard_aborts += 1,
+            (false, false) => {}
+        }
+    }
+
+    /// The coordinator's decision for a block, if it has run
+    pub fn decision(&self, block_index: u64) -> Option<Decision> {
+        self.decisions.read().get(&block_index).cloned()
+    }
+
+    pub fn is_committed(&self, block_index: u64) -> bool {
+        self.decision(block_index) == Some(Decision::Committed)
+    }
+
+    pub fn metrics(&self) -> CrossShardMetrics {
+        self.metrics.lock().clone()
+    }
+}
@@ -0,0 +1,238 @@
+//! Demo mode for teaching
+//!
+//! A node normally runs a PBFT round in well under a second, too fast to
+//! follow live. With `DEMO_MODE=1` (or `--demo`) the node slows down: every
+//! pause inside a consensus round is multiplied by `DEMO_SLOWDOWN`, block
+//! production waits at least `DEMO_BLOCK_INTERVAL_MS` between rounds, and
+//! each step is narrated as a structured `Demo:` log line carrying
+//! `demo_step`, `phase` and `block_index` fields. With
+//! `DEMO_PAUSE_BETWEEN_PHASES=1` the node also waits for Enter before every
+//! phase, so a presenter can explain each one.
+//!
+//! When disabled, `DemoMode` passes delays through unchanged and narrates
+//! nothing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+/// Factor applied to consensus delays unless `DEMO_SLOWDOWN` is set
+pub const DEFAULT_SLOWDOWN: f64 = 10.0;
+
+/// Shortest gap between blocks in demo mode unless `DEMO_BLOCK_INTERVAL_MS`
+/// is set
+pub const DEFAULT_DEMO_BLOCK_INTERVAL_MS: u64 = 15_000;
+
+/// A stage of the node's pipeline that demo mode narrates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemoPhase {
+    Extract,
+    Propose,
+    PrePrepare,
+    Prepare,
+    Commit,
+    Persist,
+}
+
+impl DemoPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DemoPhase::Extract => "extract",
+            DemoPhase::Propose => "propose",
+            DemoPhase::PrePrepare => "pre_prepare",
+            DemoPhase::Prepare => "prepare",
+            DemoPhase::Commit => "commit",
+            DemoPhase::Persist => "persist",
+        }
+    }
+
+    /// One-line explanation logged when the phase starts
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            DemoPhase::Extract => "Fetching a market price and building the next block",
+            DemoPhase::Propose => "Handing the block to the consensus algorithm",
+            DemoPhase::PrePrepare => {
+                "The primary broadcasts the block so every replica sees the same proposal"
+            }
+            DemoPhase::Prepare => {
+                "Replicas vote that they accept the proposal; 2f+1 votes form a prepare quorum"
+            }
+            DemoPhase::Commit => {
+                "Replicas vote to commit; once 2f+1 agree the block is final on every honest node"
+            }
+            DemoPhase::Persist => "Appending the committed block to the local ledger",
+        }
+    }
+}
+
+/// Pacing and narration for live demonstrations
+#[derive(Debug, Default)]
+pub struct DemoMode {
+    enabled: bool,
+    slowdown: f64,
+    min_block_interval_ms: u64,
+    pause_between_phases: bool,
+    step: AtomicU64,
+}
+
+impl DemoMode {
+    /// Demo mode with the default slowdown and block interval
+    pub fn enabled() -> Self {
+        DemoMode {
+            enabled: true,
+            slowdown: DEFAULT_SLOWDOWN,
+            min_block_interval_ms: DEFAULT_DEMO_BLOCK_INTERVAL_MS,
+            pause_between_phases: false,
+            step: AtomicU64::new(0),
+        }
+    }
+
+    /// Read `DEMO_MODE`, `DEMO_SLOWDOWN`, `DEMO_BLOCK_INTERVAL_MS` and
+    /// `DEMO_PAUSE_BETWEEN_PHASES`; disabled unless `DEMO_MODE` is set, or
+    /// `force` is true (the `--demo` flag)
+    pub fn from_env(force: bool) -> Self {
+        let flag = |name: &str| {
+            std::env::var(name)
+                .map(|v| matches!(v.trim(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(false)
+        };
+        if !force && !flag("DEMO_MODE") {
+            return DemoMode::default();
+        }
+
+        let mut demo =
+            DemoMode::enabled().with_pause_between_phases(flag("DEMO_PAUSE_BETWEEN_PHASES"));
+        if let Some(slowdown) = std::env::var("DEMO_SLOWDOWN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            demo = demo.with_slowdown(slowdown);
+        }
+        if let Some(ms) = std::env::var("DEMO_BLOCK_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            demo = demo.with_min_block_interval_ms(ms);
+        }
+        demo
+    }
+
+    /// Multiply consensus delays by `slowdown`; values below 1 are ignored
+    pub fn with_slowdown(mut self, slowdown: f64) -> Self {
+        if slowdown.is_finite() && slowdown >= 1.0 {
+            self.slowdown = slowdown;
+        }
+        self
+    }
+
+    pub fn with_min_block_interval_ms(mut self, ms: u64) -> Self {
+        self.min_block_interval_ms = ms;
+        self
+    }
+
+    /// Wait for Enter on stdin before each phase
+    pub fn with_pause_between_phases(mut self, pause: bool) -> Self {
+        self.pause_between_phases = pause;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn slowdown(&self) -> f64 {
+        if self.enabled {
+            self.slowdown
+        } else {
+            1.0
+        }
+    }
+
+    /// `base` stretched by the slowdown factor
+    pub fn delay(&self, base: Duration) -> Duration {
+        base.mul_f64(self.slowdown())
+    }
+
+    /// Gap between block rounds given the operator's configured interval
+    pub fn block_interval(&self, configured_ms: u64) -> Duration {
+        if self.enabled {
+            Duration::from_millis(configured_ms.max(self.min_block_interval_ms))
+        } else {
+            Duration::from_millis(configured_ms)
+        }
+    }
+
+    /// Narrate the start of `phase` for `block_index`, then wait for the
+    /// presenter if pausing between phases. Returns the step number, or
+    /// `None` when demo mode is off.
+    pub async fn enter(&self, phase: DemoPhase, block_index: u64) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        let step = self.step.fetch_add(1, Ordering::Relaxed) + 1;
+        info!(
+            demo_step = step,
+            phase = phase.name(),
+            block_index = block_index,
+            "Demo: {}",
+            phase.explanation()
+        );
+        if self.pause_between_phases {
+            info!(demo_step = step, "Demo: Press Enter to continue");
+            // Waiting on stdin blocks, so keep it off the runtime's workers
+            let _ = tokio::task::spawn_blocking(|| {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line)
+            })
+            .await;
+        }
+        Some(step)
+    }
+
+    /// Narrate the outcome of a phase
+    pub fn narrate(&self, phase: DemoPhase, block_index: u64, outcome: &str) {
+        if self.enabled {
+            info!(
+                demo_step = self.step.load(Ordering::Relaxed),
+                phase = phase.name(),
+                block_index = block_index,
+                "Demo: {}",
+                outcome
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_demo_mode_scales_delays_and_numbers_steps() {
+        let off = DemoMode::default();
+        assert_eq!(
+            off.delay(Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        assert_eq!(off.block_interval(3000), Duration::from_millis(3000));
+        assert_eq!(off.enter(DemoPhase::Prepare, 1).await, None);
+
+        let demo = DemoMode::enabled()
+            .with_slowdown(4.0)
+            .with_min_block_interval_ms(10_000);
+        assert_eq!(
+            demo.delay(Duration::from_millis(500)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(demo.block_interval(3000), Duration::from_secs(10));
+        assert_eq!(demo.block_interval(20_000), Duration::from_secs(20));
+        assert_eq!(demo.enter(DemoPhase::PrePrepare, 1).await, Some(1));
+        assert_eq!(demo.enter(DemoPhase::Prepare, 1).await, Some(2));
+
+        // Demo mode only slows things down
+        assert_eq!(
+            DemoMode::enabled().with_slowdown(0.1).slowdown(),
+            DEFAULT_SLOWDOWN
+        );
+    }
+}
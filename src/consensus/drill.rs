@@ -0,0 +1,425 @@
+//! Primary failover drill
+//!
+//! A cluster only tolerates a faulty primary if replicas notice the missing
+//! pre-prepare and agree on a new primary. `run_failover_drill` checks that
+//! this works for a given cluster shape: it runs PBFT instances for every
+//! node in process, with the deployment's node count, observers and quorum
+//! policy, and drives `rounds` consensus rounds over an in-memory network.
+//! In round `silenced_round` the current primary's outgoing messages are
+//! dropped. The replicas wait `view_change_timeout`, vote for a view change,
+//! and the next primary re-proposes the block. The drill passes when the
+//! view changed and every round, including the silenced one, committed on
+//! every voting node. It reports how long detection, the view change and
+//! recovery took.
+//!
+//! Run it with `cargo run -- drill failover`; see `cli::drill`.
+
+use crate::consensus::algorithms::{MessageType, PBFTManager, PBFTMessage};
+use crate::consensus::quorum::{ClassicQuorum, QuorumPolicy};
+use crate::etl::price::Decimal;
+use crate::etl::{Block, MarketData, BLOCK_FORMAT_VERSION};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long replicas wait for a pre-prepare unless configured otherwise
+pub const DEFAULT_VIEW_CHANGE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Simulated one-way network delay per broadcast
+pub const DEFAULT_MESSAGE_DELAY: Duration = Duration::from_millis(5);
+
+#[derive(Clone)]
+pub struct DrillConfig {
+    pub total_nodes: usize,
+    pub observers: Vec<usize>,
+    pub quorum_policy: Arc<dyn QuorumPolicy>,
+    pub rounds: u64,
+    /// Sequence number whose primary is silenced
+    pub silenced_round: u64,
+    pub view_change_timeout: Duration,
+    pub message_delay: Duration,
+}
+
+impl DrillConfig {
+    pub fn new(total_nodes: usize) -> Self {
+        DrillConfig {
+            total_nodes,
+            observers: Vec::new(),
+            quorum_policy: Arc::new(ClassicQuorum),
+            rounds: 5,
+            silenced_round: 2,
+            view_change_timeout: DEFAULT_VIEW_CHANGE_TIMEOUT,
+            message_delay: DEFAULT_MESSAGE_DELAY,
+        }
+    }
+
+    pub fn with_observers(mut self, observers: impl IntoIterator<Item = usize>) -> Self {
+        self.observers = observers.into_iter().collect();
+        self
+    }
+
+    pub fn with_quorum_policy(mut self, policy: Arc<dyn QuorumPolicy>) -> Self {
+        self.quorum_policy = policy;
+        self
+    }
+
+    pub fn with_rounds(mut self, rounds: u64, silenced_round: u64) -> Self {
+        self.rounds = rounds;
+        self.silenced_round = silenced_round;
+        self
+    }
+
+    pub fn with_view_change_timeout(mut self, timeout: Duration) -> Self {
+        self.view_change_timeout = timeout;
+        self
+    }
+
+    pub fn with_message_delay(mut self, delay: Duration) -> Self {
+        self.message_delay = delay;
+        self
+    }
+}
+
+/// One consensus round of the drill
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DrillRound {
+    pub sequence: u64,
+    /// View the block committed in
+    pub view: u64,
+    /// Node whose pre-prepare the replicas accepted
+    pub primary: usize,
+    /// Whether the round started with a silenced primary
+    pub silenced: bool,
+    /// Voting nodes that committed the block
+    pub committed_nodes: usize,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DrillReport {
+    pub total_nodes: usize,
+    pub voting_nodes: usize,
+    pub quorum_policy: String,
+    pub silenced_primary: Option<usize>,
+    pub new_primary: Option<usize>,
+    pub view_before: u64,
+    pub view_after: u64,
+    /// From silencing the primary until the replicas voted to replace it
+    pub detection_ms: Option<u64>,
+    /// Until every voting node had moved to the new view
+    pub view_change_ms: Option<u64>,
+    /// Until the silenced round's block committed under the new primary
+    pub recovery_ms: Option<u64>,
+    /// Rounds after the silenced one that committed
+    pub commits_after_failover: u64,
+    pub rounds: Vec<DrillRound>,
+    pub failure: Option<String>,
+}
+
+impl DrillReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// PBFT instances joined by an in-memory network that can mute one node
+struct DrillCluster {
+    nodes: Vec<Arc<PBFTManager>>,
+    voters: Vec<usize>,
+    muted: Option<usize>,
+    delay: Duration,
+}
+
+impl DrillCluster {
+    fn new(config: &DrillConfig) -> Self {
+        let addresses: Vec<String> = (0..config.total_nodes)
+            .map(|id| format!("drill-node-{}", id))
+            .collect();
+        let nodes: Vec<Arc<PBFTManager>> = (0..config.total_nodes)
+            .map(|id| {
+                Arc::new(
+                    PBFTManager::new(id, config.total_nodes, addresses.clone())
+                        .with_quorum_policy(config.quorum_policy.clone())
+                        .with_observers(config.observers.iter().copied()),
+                )
+            })
+            .collect();
+        let voters = nodes[0].voting_members();
+        DrillCluster {
+            nodes,
+            voters,
+            muted: None,
+            delay: config.message_delay,
+        }
+    }
+
+    /// Voting nodes whose messages get through
+    fn live_voters(&self) -> impl Iterator<Item = &Arc<PBFTManager>> {
+        self.voters
+            .iter()
+            .filter(|id| Some(**id) != self.muted)
+            .map(|id| &self.nodes[*id])
+    }
+
+    /// Deliver `msg` to every node; false if the sender is muted
+    async fn broadcast(&self, msg: &PBFTMessage) -> bool {
+        if self.muted == Some(msg.node_id) {
+            return false;
+        }
+        tokio::time::sleep(self.delay).await;
+        for node in &self.nodes {
+            node.handle_message(msg);
+        }
+        true
+    }
+
+    /// Smallest view any voting node is in
+    fn view(&self) -> u64 {
+        self.voters
+            .iter()
+            .map(|id| self.nodes[*id].view())
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn committed_nodes(&self, sequence: u64) -> usize {
+        self.voters
+            .iter()
+            .filter(|id| self.nodes[**id].is_committed(sequence))
+            .count()
+    }
+}
+
+/// The block proposed at `sequence`; built by hand because the drill runs in
+/// release builds, where the `testing` fixtures are not compiled
+fn drill_block(sequence: u64) -> Block {
+    let mut block = Block {
+        index: sequence,
+        timestamp: 1_700_000_000_000 + sequence as i64,
+        data: vec![MarketData {
+            asset: "BTC".to_string(),
+            price: Decimal::from(50_000),
+            source: "FailoverDrill".to_string(),
+            timestamp: 1_700_000_000_000 + sequence as i64,
+            provenance: None,
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        }],
+        previous_hash: String::new(),
+        hash: String::new(),
+        nonce: 0,
+        format_version: BLOCK_FORMAT_VERSION,
+        fees: Vec::new(),
+        divergences: Vec::new(),
+        hlc: None,
+        annotations: Default::default(),
+        order_books: Vec::new(),
+    };
+    block.hash = block.calculate_hash();
+    block
+}
+
+fn millis(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+/// Run the drill; failures are reported in `DrillReport::failure`
+pub async fn run_failover_drill(config: &DrillConfig) -> DrillReport {
+    let mut report = DrillReport {
+        total_nodes: config.total_nodes,
+        quorum_policy: config.quorum_policy.name().to_string(),
+        ..Default::default()
+    };
+    if config.total_nodes == 0 {
+        report.failure = Some("the cluster has no nodes".to_string());
+        return report;
+    }
+    let mut cluster = DrillCluster::new(config);
+    report.voting_nodes = cluster.voters.len();
+    if cluster.voters.len() < 2 {
+        report.failure = Some("a failover needs at least two voting nodes".to_string());
+        return report;
+    }
+    if config.silenced_round == 0 || config.silenced_round > config.rounds {
+        report.failure = Some(format!(
+            "silenced round {} is outside rounds 1..={}",
+            config.silenced_round, config.rounds
+        ));
+        return report;
+    }
+
+    for sequence in 1..=config.rounds {
+        let started = Instant::now();
+        let silenced = sequence == config.silenced_round;
+        if silenced {
+            let primary = cluster.nodes[cluster.voters[0]].primary_for(sequence);
+            report.silenced_primary = primary;
+            report.view_before = cluster.view();
+            cluster.muted = primary;
+            warn!(
+                node_id = ?primary,
+                sequence,
+                "Drill: Silencing the primary for one round"
+            );
+        }
+
+        let block = drill_block(sequence);
+        let mut proposal = None;
+        let mut view_changed = false;
+        // Every voting node gets one turn as primary before giving up
+        for _ in 0..cluster.voters.len() {
+            let Some(primary) = cluster.nodes[cluster.voters[0]].primary_for(sequence) else {
+                break;
+            };
+            let block_json = serde_json::to_string(&block).unwrap_or_default();
+            // After a view change the new primary proposes in a NEW-VIEW
+            let pre_prepare = if view_changed {
+                match cluster.nodes[primary].create_new_view(
+                    &block.content_id(),
+                    block_json,
+                    sequence,
+                ) {
+                    Ok(new_view) => new_view,
+                    Err(reason) => {
+                        report.failure = Some(format!(
+                            "sequence {}: node {} cannot build a NEW-VIEW: {}",
+                            sequence, primary, reason
+                        ));
+                        break;
+                    }
+                }
+            } else {
+                cluster.nodes[primary].create_pre_prepare(&block.content_id(), block_json, sequence)
+            };
+            if cluster.broadcast(&pre_prepare).await {
+                proposal = Some(pre_prepare);
+                break;
+            }
+
+            // No pre-prepare: the replicas time out and vote the primary out
+            tokio::time::sleep(config.view_change_timeout).await;
+            if silenced && report.detection_ms.is_none() {
+                report.detection_ms = Some(millis(started));
+            }
+            let votes: Vec<PBFTMessage> = cluster
+                .live_voters()
+                .map(|node| node.create_view_change(sequence))
+                .collect();
+            for vote in &votes {
+                cluster.broadcast(vote).await;
+            }
+            view_changed = true;
+            if silenced && report.view_change_ms.is_none() {
+                report.view_change_ms = Some(millis(started));
+            }
+        }
+        if report.failure.is_some() {
+            break;
+        }
+        let Some(proposal) = proposal else {
+            report.failure = Some(format!("no primary proposed sequence {}", sequence));
+            break;
+        };
+
+        // Replicas vote only for a pre-prepare from the view's primary
+        if let Some(reason) = cluster
+            .live_voters()
+            .find_map(|node| node.validate_pre_prepare(&proposal).err())
+        {
+            report.failure = Some(format!(
+                "sequence {}: replicas rejected the pre-prepare: {}",
+                sequence, reason
+            ));
+            break;
+        }
+        for phase in [MessageType::Prepare, MessageType::Commit] {
+            let votes: Vec<PBFTMessage> = cluster
+                .live_voters()
+                .map(|node| match phase {
+                    MessageType::Prepare => node.create_prepare(&block.content_id(), sequence),
+                    _ => node.create_commit(&block.content_id(), sequence),
+                })
+                .collect();
+            for vote in &votes {
+                cluster.broadcast(vote).await;
+            }
+        }
+
+        let committed_nodes = cluster.committed_nodes(sequence);
+        if silenced {
+            report.recovery_ms = Some(millis(started));
+            report.new_primary = Some(proposal.node_id);
+            report.view_after = cluster.view();
+        } else if sequence > config.silenced_round && committed_nodes > 0 {
+            report.commits_after_failover += 1;
+        }
+        report.rounds.push(DrillRound {
+            sequence,
+            view: proposal.view,
+            primary: proposal.node_id,
+            silenced,
+            committed_nodes,
+            elapsed_ms: millis(started),
+        });
+        // Silenced for one round only
+        cluster.muted = None;
+
+        if committed_nodes < cluster.voters.len() {
+            report.failure = Some(format!(
+                "sequence {} committed on {} of {} voting nodes",
+                sequence,
+                committed_nodes,
+                cluster.voters.len()
+            ));
+            break;
+        }
+    }
+
+    if report.failure.is_none() && report.view_after <= report.view_before {
+        report.failure = Some("the primary was silenced but no view change happened".to_string());
+    }
+    info!(
+        passed = report.is_ok(),
+        silenced_primary = ?report.silenced_primary,
+        new_primary = ?report.new_primary,
+        recovery_ms = ?report.recovery_ms,
+        "Drill: Failover drill finished"
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failover_drill_elects_new_primary_and_keeps_committing() {
+        let config = DrillConfig::new(4)
+            .with_rounds(4, 2)
+            .with_view_change_timeout(Duration::from_millis(20))
+            .with_message_delay(Duration::ZERO);
+        let report = run_failover_drill(&config).await;
+        assert!(report.is_ok(), "{:?}", report.failure);
+        assert_eq!(report.rounds.len(), 4);
+        // Sequence 2 rotates to node 2 in view 0, then node 3 in view 1
+        assert_eq!(report.silenced_primary, Some(2));
+        assert_eq!(report.new_primary, Some(3));
+        assert_eq!((report.view_before, report.view_after), (0, 1));
+        assert!(report.detection_ms.unwrap() >= 20);
+        assert!(report.recovery_ms >= report.view_change_ms);
+        assert_eq!(report.commits_after_failover, 2);
+        assert!(report.rounds.iter().all(|r| r.committed_nodes == 4));
+
+        // Observers are never primary and do not count towards quorums
+        let report = run_failover_drill(&config.clone().with_observers([3])).await;
+        assert!(report.is_ok(), "{:?}", report.failure);
+        assert_eq!(report.voting_nodes, 3);
+        assert_ne!(report.new_primary, Some(3));
+
+        let report =
+            run_failover_drill(&DrillConfig::new(4).with_observers([3]).with_rounds(2, 3)).await;
+        assert!(report.failure.unwrap().contains("outside rounds"));
+    }
+}
@@ -0,0 +1,39 @@
+//! Error type shared by consensus algorithms and strategies
+
+use std::fmt;
+
+/// Failure while running a consensus round
+///
+/// Unlike `Box<dyn Error>`, this is `Send + Sync + 'static`, so consensus
+/// futures can be spawned on multi-threaded executors and errors can cross
+/// task boundaries.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsensusError {
+    /// The block or a message could not be encoded or decoded
+    Serialization(String),
+    /// Peers could not be reached
+    Network(String),
+    /// The block is malformed or conflicts with local state
+    InvalidBlock(String),
+    /// Any other failure inside an algorithm
+    Internal(String),
+}
+
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsensusError::Serialization(e) => write!(f, "Serialization error: {}", e),
+            ConsensusError::Network(e) => write!(f, "Network error: {}", e),
+            ConsensusError::InvalidBlock(e) => write!(f, "Invalid block: {}", e),
+            ConsensusError::Internal(e) => write!(f, "Consensus error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConsensusError {}
+
+impl From<serde_json::Error> for ConsensusError {
+    fn from(err: serde_json::Error) -> Self {
+        ConsensusError::Serialization(err.to_string())
+    }
+}
@@ -0,0 +1,513 @@
+//! Replayable consensus event log
+//!
+//! With `CONSENSUS_EVENT_LOG` set, a node appends every PBFT message it
+//! handles, the quorum result it saw, and each commit to a JSON-lines file.
+//! `replay` feeds such a log through a fresh `PBFTManager` and reports the
+//! first events whose outcome differs, so a misbehaving multi-node run can be
+//! reproduced deterministically on one machine (`cargo run -- replay <file>`).
+//!
+//! PBFT records events while it holds its state lock, so that the log order
+//! matches the order the state changed in. `EventLog::record` therefore only
+//! numbers the event and queues it; a writer thread serializes and appends
+//! queued events, so no file I/O happens under the lock.
+
+use crate::consensus::algorithms::{PBFTManager, PBFTMessage};
+use crate::consensus::quorum::{
+    parse_policy, ClassicQuorum, DomainQuorum, FailureDomains, QuorumPolicy,
+};
+use crate::etl::now_millis;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use tracing::warn;
+
+/// Something that happened during a consensus run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConsensusEvent {
+    /// Written first, so replay can build an identical fresh instance
+    Run {
+        node_id: usize,
+        total_nodes: usize,
+        algorithm: String,
+        /// `PBFT_QUORUM_POLICY` spec in effect; absent means classic
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quorum_policy: Option<String>,
+        /// `NODE_FAILURE_DOMAINS` in effect, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_domains: Option<String>,
+        /// `QUORUM_MIN_DOMAINS` in effect, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_domains: Option<String>,
+    },
+    /// A message passed to the state machine and whether it completed a quorum
+    Message {
+        message: PBFTMessage,
+        quorum_reached: bool,
+    },
+    /// A sequence number became committed
+    Committed {
+        sequence: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        shard: Option<String>,
+    },
+    /// A commit was undone because the cross-shard coordinator aborted the
+    /// block
+    RolledBack {
+        sequence: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        shard: Option<String>,
+    },
+}
+
+/// One line of the log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    pub seq: u64,
+    pub at_ms: i64,
+    #[serde(flatten)]
+    pub event: ConsensusEvent,
+}
+
+enum Op {
+    Write(LoggedEvent),
+    /// Acknowledged once every event queued before it is written
+    Flush(mpsc::SyncSender<()>),
+    /// Drop the block data of the messages for a block hash from the file
+    Redact(String, mpsc::SyncSender<io::Result<usize>>),
+}
+
+struct Queue {
+    next_seq: u64,
+    /// `None` once the log is being dropped
+    tx: Option<mpsc::Sender<Op>>,
+}
+
+/// Append-only event log file
+pub struct EventLog {
+    queue: Mutex<Queue>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl EventLog {
+    /// Create (or truncate) the log at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("event-log".to_string())
+            .spawn(move || write_events(BufWriter::new(file), rx))?;
+        Ok(EventLog {
+            queue: Mutex::new(Queue {
+                next_seq: 0,
+                tx: Some(tx),
+            }),
+            writer: Some(writer),
+        })
+    }
+
+    /// Open the file named by `CONSENSUS_EVENT_LOG`, with `{node}` replaced
+    /// by `node_id` so several local nodes can share one setting
+    pub fn from_env(node_id: usize) -> io::Result<Option<Self>> {
+        match std::env::var("CONSENSUS_EVENT_LOG") {
+            Ok(path) if !path.is_empty() => {
+                Self::create(path.replace("{node}", &node_id.to_string())).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Queue an event for the writer, which appends and flushes it so the
+    /// log survives a crash
+    ///
+    /// Write failures are logged rather than returned: losing the debug log
+    /// must not stop consensus.
+    pub fn record(&self, event: ConsensusEvent) {
+        let mut queue = self.queue.lock();
+        let entry = LoggedEvent {
+            seq: queue.next_seq,
+            at_ms: now_millis(),
+            event,
+        };
+        queue.next_seq += 1;
+        if let Some(tx) = &queue.tx {
+            if tx.send(Op::Write(entry)).is_err() {
+                warn!("EventLog: Writer stopped, dropping event");
+            }
+        }
+    }
+
+    /// Wait until every event recorded so far is in the file
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        let sent = match &self.queue.lock().tx {
+            Some(tx) => tx.send(Op::Flush(ack_tx)).is_ok(),
+            None => false,
+        };
+        if sent {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+impl EventLog {
+    /// Remove the block data of every message logged for `block_hash`, for
+    /// a redacted block; returns how many messages carried it
+    ///
+    /// The writer rewrites the file after appending everything queued before.
+    pub fn redact_block(&self, block_hash: &str) -> io::Result<usize> {
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        let sent = match &self.queue.lock().tx {
+            Some(tx) => tx.send(Op::Redact(block_hash.to_string(), ack_tx)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "event log writer stopped",
+            ));
+        }
+        ack_rx
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("event log writer stopped")))
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer finish the queue and exit
+        self.queue.lock().tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_events(mut out: BufWriter<File>, rx: mpsc::Receiver<Op>) {
+    for op in rx {
+        match op {
+            Op::Write(entry) => {
+                let result = serde_json::to_string(&entry)
+                    .map_err(io::Error::from)
+                    .and_then(|line| writeln!(out, "{}", line))
+                    .and_then(|_| out.flush());
+                if let Err(e) = result {
+                    warn!(error = %e, seq = entry.seq, "EventLog: Failed to record event");
+                }
+            }
+            Op::Flush(ack) => {
+                let _ = ack.send(());
+            }
+            Op::Redact(block_hash, ack) => {
+                let result = out
+                    .flush()
+                    .and_then(|_| redact_messages(out.get_mut(), &block_hash));
+                let _ = ack.send(result);
+            }
+        }
+    }
+}
+
+/// Rewrite `file` with `block_data_json` removed from the messages for
+/// `block_hash`, leaving the position at the end for further appends
+fn redact_messages(file: &mut File, block_hash: &str) -> io::Result<usize> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut contents)?;
+    let mut redacted = 0;
+    let mut rewritten = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let mut line = line.to_string();
+        if let Ok(mut entry) = serde_json::from_str::<LoggedEvent>(&line) {
+            if let ConsensusEvent::Message { message, .. } = &mut entry.event {
+                if message.block_hash == block_hash && message.block_data_json.is_some() {
+                    message.block_data_json = None;
+                    line = serde_json::to_string(&entry)?;
+                    redacted += 1;
+                }
+            }
+        }
+        rewritten.push_str(&line);
+        rewritten.push('\n');
+    }
+    if redacted > 0 {
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(rewritten.as_bytes())?;
+        file.flush()?;
+    }
+    file.seek(SeekFrom::End(0))?;
+    Ok(redacted)
+}
+
+/// Read every event from a log file
+pub fn read_events(path: impl AsRef<Path>) -> io::Result<Vec<LoggedEvent>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", line_no + 1, e),
+            )
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// An event whose replayed outcome differs from the recorded one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence {
+    pub seq: u64,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Outcome of replaying a log
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayReport {
+    pub messages: usize,
+    pub commits: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayReport {
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Re-feed `events` through a fresh PBFT state machine, one per shard.
+///
+/// `on_event` sees each event with its replayed outcome as it is applied.
+pub fn replay(
+    events: &[LoggedEvent],
+    mut on_event: impl FnMut(&LoggedEvent, bool),
+) -> Result<ReplayReport, String> {
+    let (node_id, total_nodes, policy) = match events.first().map(|e| &e.event) {
+        Some(ConsensusEvent::Run {
+            node_id,
+            total_nodes,
+            quorum_policy,
+            failure_domains,
+            min_domains,
+            ..
+        }) => {
+            let mut policy: Option<Arc<dyn QuorumPolicy>> = match quorum_policy {
+                Some(spec) => Some(parse_policy(spec)?),
+                None => None,
+            };
+            if let Some(tags) = failure_domains {
+                let domains = FailureDomains::parse(tags, min_domains.as_deref())?;
+                let inner = policy.unwrap_or_else(|| Arc::new(ClassicQuorum));
+                policy = Some(Arc::new(DomainQuorum::new(inner, domains)));
+            }
+            (*node_id, *total_nodes, policy)
+        }
+        _ => return Err("event log does not start with a run header".to_string()),
+    };
+    let new_instance = || {
+        let pbft = PBFTManager::new(node_id, total_nodes, Vec::new());
+        match &policy {
+            Some(policy) => pbft.with_quorum_policy(policy.clone()),
+            None => pbft,
+        }
+    };
+    let mut instances: HashMap<Option<String>, PBFTManager> = HashMap::new();
+    let mut report = ReplayReport::default();
+
+    for entry in &events[1..] {
+        let (expected, actual) = match &entry.event {
+            ConsensusEvent::Run { .. } => {
+                return Err(format!("unexpected second run header at seq {}", entry.seq))
+            }
+            ConsensusEvent::Message {
+                message,
+                quorum_reached,
+            } => {
+                report.messages += 1;
+                let pbft = instances
+                    .entry(message.shard.clone())
+                    .or_insert_with(new_instance);
+                (*quorum_reached, pbft.handle_message(message))
+            }
+            ConsensusEvent::Committed { sequence, shard } => {
+                report.commits += 1;
+                let pbft = instances.entry(shard.clone()).or_insert_with(new_instance);
+                (true, pbft.is_committed(*sequence))
+            }
+            ConsensusEvent::RolledBack { sequence, shard } => {
+                let pbft = instances.entry(shard.clone()).or_insert_with(new_instance);
+                pbft.roll_back(*sequence);
+                (false, pbft.is_committed(*sequence))
+            }
+        };
+
+        on_event(entry, actual);
+        if expected != actual {
+            report.divergences.push(Divergence {
+                seq: entry.seq,
+                expected: describe(&entry.event, expected),
+                actual: describe(&entry.event, actual),
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn describe(event: &ConsensusEvent, outcome: bool) -> String {
+    match event {
+        ConsensusEvent::Message { .. } if outcome => "quorum reached".to_string(),
+        ConsensusEvent::Message { .. } => "no quorum".to_string(),
+        ConsensusEvent::Committed { .. } if outcome => "committed".to_string(),
+        _ => "not committed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::MessageType;
+    use crate::network::protocol::PROTOCOL_VERSION;
+    use std::fs;
+
+    fn commit(node_id: usize) -> PBFTMessage {
+        PBFTMessage {
+            msg_type: MessageType::Commit,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: None,
+            node_id,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_recorded_run_replays_consistently() {
+        let path = "test_event_log_replay.jsonl";
+        let log = Arc::new(EventLog::create(path).unwrap());
+        log.record(ConsensusEvent::Run {
+            node_id: 0,
+            total_nodes: 4,
+            algorithm: "PBFT".to_string(),
+            quorum_policy: None,
+            failure_domains: None,
+            min_domains: None,
+        });
+
+        let pbft = PBFTManager::new(0, 4, Vec::new()).with_event_log(log.clone());
+        for node in 0..3 {
+            pbft.handle_message(&commit(node));
+        }
+        assert!(pbft.is_committed(1));
+
+        log.flush();
+        let events = read_events(path).unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[4].event,
+            ConsensusEvent::Committed {
+                sequence: 1,
+                shard: None
+            }
+        );
+
+        let mut seen = Vec::new();
+        let report = replay(&events, |e, _| seen.push(e.seq)).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.messages, 3);
+        assert_eq!(report.commits, 1);
+        assert_eq!(seen, vec![1, 2, 3, 4]);
+
+        // A rolled back sequence needs a fresh quorum, in replay as well
+        pbft.roll_back(1);
+        pbft.handle_message(&commit(3));
+        assert!(!pbft.is_committed(1));
+        log.flush();
+        let events = read_events(path).unwrap();
+        assert_eq!(events.len(), 7);
+        assert!(replay(&events, |_, _| {}).unwrap().is_consistent());
+
+        // Redacting a block drops its data from the logged messages
+        let block = crate::testing::block(
+            2,
+            "parent",
+            vec![crate::testing::entry(
+                "BTC",
+                "Kraken",
+                crate::etl::price::Decimal::from(50_000),
+                1_700_000_000_000,
+            )],
+        );
+        let mut proposal = pbft.create_pre_prepare(
+            &block.content_id(),
+            serde_json::to_string(&block).unwrap(),
+            2,
+        );
+        proposal.node_id = 2;
+        pbft.handle_message(&proposal);
+        assert_eq!(log.redact_block(&block.content_id()).unwrap(), 1);
+        pbft.handle_message(&commit(1));
+        log.flush();
+        let events = read_events(path).unwrap();
+        assert_eq!(events.len(), 9);
+        assert!(!fs::read_to_string(path).unwrap().contains("BTC"));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_replay_reports_divergence() {
+        let header = LoggedEvent {
+            seq: 0,
+            at_ms: 0,
+            event: ConsensusEvent::Run {
+                node_id: 0,
+                total_nodes: 4,
+                algorithm: "PBFT".to_string(),
+                quorum_policy: None,
+                failure_domains: None,
+                min_domains: None,
+            },
+        };
+        // A single commit vote cannot reach a 3-of-4 quorum
+        let tampered = LoggedEvent {
+            seq: 1,
+            at_ms: 0,
+            event: ConsensusEvent::Message {
+                message: commit(1),
+                quorum_reached: true,
+            },
+        };
+
+        let report = replay(&[header, tampered.clone()], |_, _| {}).unwrap();
+        assert_eq!(report.divergences.len(), 1);
+        assert_eq!(report.divergences[0].seq, 1);
+        assert_eq!(report.divergences[0].actual, "no quorum");
+
+        assert!(replay(&[tampered], |_, _| {}).is_err());
+    }
+}
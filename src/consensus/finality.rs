@@ -0,0 +1,130 @@
+//! Block finality as reported to consumers
+//!
+//! A committed block is not equally safe under every consensus mode. PBFT and
+//! Flexible Paxos commit only once a quorum has agreed, so a committed block
+//! is final at once. Gossip, eventual and quorum-less modes commit
+//! optimistically and settle competing blocks by fork choice later, so a
+//! block only becomes final once enough blocks are built on top of it, the
+//! same depth rule `ForkTree::with_max_reorg_depth` enforces.
+//!
+//! `FinalityRule` holds that choice for a node; `/blocks`, `/head` and the
+//! gRPC service report each block as `committed` or `finalized` with its
+//! confirmations, so a consumer acting on a price decides how much risk to
+//! take. Probabilistic modes wait `FINALITY_DEPTH` blocks (default 6).
+
+use serde::{Deserialize, Serialize};
+
+/// Confirmations a block needs under a probabilistic mode unless
+/// `FINALITY_DEPTH` says otherwise
+pub const DEFAULT_FINALITY_DEPTH: u64 = 6;
+
+/// When a committed block becomes final
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FinalityRule {
+    /// Final once committed, as under quorum-based consensus
+    #[default]
+    Immediate,
+    /// Final once this many blocks are committed on top of it
+    Depth(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FinalityStatus {
+    /// In the ledger, but could still be replaced by a reorg
+    Committed,
+    /// Can no longer be reorganized away
+    Finalized,
+}
+
+/// A block's finality as served to consumers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finality {
+    pub status: FinalityStatus,
+    /// Blocks committed on top of this one
+    pub confirmations: u64,
+    /// Confirmations needed to be final; 0 under immediate finality
+    pub required_confirmations: u64,
+}
+
+impl FinalityRule {
+    /// `Immediate` for quorum-based modes; otherwise `Depth` from
+    /// `FINALITY_DEPTH`, default `DEFAULT_FINALITY_DEPTH`
+    pub fn from_env(immediate: bool) -> Result<Self, String> {
+        if immediate {
+            return Ok(FinalityRule::Immediate);
+        }
+        let depth = match std::env::var("FINALITY_DEPTH") {
+            Ok(depth) => depth
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid FINALITY_DEPTH: {}", e))?,
+            Err(_) => DEFAULT_FINALITY_DEPTH,
+        };
+        Ok(FinalityRule::Depth(depth))
+    }
+
+    pub fn required_confirmations(&self) -> u64 {
+        match self {
+            FinalityRule::Immediate => 0,
+            FinalityRule::Depth(depth) => *depth,
+        }
+    }
+
+    /// Highest final index while the chain's head is at `head`
+    pub fn finalized_height(&self, head: u64) -> u64 {
+        head.saturating_sub(self.required_confirmations())
+    }
+
+    /// Finality of the block at `index` while the chain's head is at `head`
+    pub fn of(&self, index: u64, head: u64) -> Finality {
+        Finality {
+            status: if index <= self.finalized_height(head) {
+                FinalityStatus::Finalized
+            } else {
+                FinalityStatus::Committed
+            },
+            confirmations: head.saturating_sub(index),
+            required_confirmations: self.required_confirmations(),
+        }
+    }
+
+    /// `immediate` or `depth:N`
+    pub fn describe(&self) -> String {
+        match self {
+            FinalityRule::Immediate => "immediate".to_string(),
+            FinalityRule::Depth(depth) => format!("depth:{}", depth),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_finality_trails_head() {
+        let immediate = FinalityRule::Immediate.of(10, 10);
+        assert_eq!(immediate.status, FinalityStatus::Finalized);
+        assert_eq!(immediate.required_confirmations, 0);
+
+        let depth = FinalityRule::Depth(3);
+        assert_eq!(depth.finalized_height(10), 7);
+        assert_eq!(depth.of(8, 10).status, FinalityStatus::Committed);
+        assert_eq!(
+            depth.of(7, 10),
+            Finality {
+                status: FinalityStatus::Finalized,
+                confirmations: 3,
+                required_confirmations: 3,
+            }
+        );
+        // A short chain has nothing final yet
+        assert_eq!(depth.finalized_height(2), 0);
+        assert_eq!(
+            serde_json::to_value(FinalityStatus::Committed).unwrap(),
+            "committed"
+        );
+        assert_eq!(FinalityRule::from_env(true), Ok(FinalityRule::Immediate));
+    }
+}
@@ -0,0 +1,314 @@
+//! Fork storage, fork choice and stale block tracking
+//!
+//! `ForkTree` keeps every block it is given, linked by `previous_hash`, and
+//! picks the canonical chain with the longest-chain rule (ties keep the tip
+//! seen first). Blocks this node committed that end up off the canonical
+//! chain are stale; `ForkTrackingStrategy` counts them so benchmarks report a
+//! real `stale_block_rate` for modes where competing blocks can appear.
+//!
+//! With a finality window (`with_max_reorg_depth`, `MAX_REORG_DEPTH` for a
+//! node), a block whose branch would abandon more than that many canonical
+//! blocks is rejected: `insert` returns the `RefusedReorg`, the block is not
+//! added, and its descendants are rejected the same way however long the
+//! branch grows. Blocks at least that deep below the tip are reported final.
+//! This bounds how far back a long-range rewrite can reach.
+
+use crate::consensus::comparison::ConsensusStrategy;
+use crate::consensus::{ConsensusError, ConsensusRequirements};
+use crate::etl::Block;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+
+struct ForkNode {
+    parent: String,
+    height: u64,
+}
+
+/// All known blocks, including those on abandoned branches
+#[derive(Default)]
+pub struct ForkTree {
+    nodes: HashMap<String, ForkNode>,
+    tip: Option<String>,
+    /// Blocks committed by this node rather than received from peers
+    local: HashSet<String>,
+    /// Deepest reorg allowed; `None` allows any
+    max_reorg_depth: Option<u64>,
+    refused: Vec<RefusedReorg>,
+    /// Rejected blocks by hash, so their descendants are rejected too
+    rejected: HashMap<String, RefusedReorg>,
+}
+
+/// A block whose branch outgrew the canonical chain but was rejected,
+/// because switching to it would have abandoned final blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefusedReorg {
+    pub hash: String,
+    pub height: u64,
+    /// Canonical blocks the switch would have abandoned
+    pub depth: u64,
+}
+
+impl fmt::Display for RefusedReorg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {} at height {} would reorganize {} final block(s) away",
+            self.hash, self.height, self.depth
+        )
+    }
+}
+
+impl std::error::Error for RefusedReorg {}
+
+/// Finality window from `MAX_REORG_DEPTH`; `None` when unset
+pub fn max_reorg_depth_from_env() -> Result<Option<u64>, String> {
+    match std::env::var("MAX_REORG_DEPTH") {
+        Ok(depth) if !depth.trim().is_empty() => depth
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("invalid MAX_REORG_DEPTH '{}': {}", depth, e)),
+        _ => Ok(None),
+    }
+}
+
+impl ForkTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse reorgs that abandon more than `depth` canonical blocks
+    pub fn with_max_reorg_depth(mut self, depth: u64) -> Self {
+        self.max_reorg_depth = Some(depth);
+        self
+    }
+
+    /// Add a block; `Ok(false)` if it was already known, and an error if
+    /// adopting its branch would reorganize more blocks than the finality
+    /// window allows, or it descends from a block rejected for that.
+    ///
+    /// A block whose parent is unknown starts a new branch at height 1.
+    pub fn insert(&mut self, block: &Block, local: bool) -> Result<bool, RefusedReorg> {
+        if let Some(refused) = self.rejected.get(&block.hash) {
+            return Err(refused.clone());
+        }
+        if self.nodes.contains_key(&block.hash) {
+            if local {
+                self.local.insert(block.hash.clone());
+            }
+            return Ok(false);
+        }
+
+        let refusal = match self.rejected.get(&block.previous_hash) {
+            Some(parent) => Some((parent.height + 1, parent.depth)),
+            None => {
+                let height = self
+                    .nodes
+                    .get(&block.previous_hash)
+                    .map_or(1, |parent| parent.height + 1);
+                let depth = self.reorg_depth(&block.previous_hash);
+                match self.max_reorg_depth {
+                    Some(max) if height > self.tip_height() && depth > max => Some((height, depth)),
+                    _ => None,
+                }
+            }
+        };
+        if let Some((height, depth)) = refusal {
+            warn!(
+                hash = %block.hash,
+                height,
+                depth,
+                max_reorg_depth = ?self.max_reorg_depth,
+                "Fork: Rejected block reorganizing past the finality window"
+            );
+            let refused = RefusedReorg {
+                hash: block.hash.clone(),
+                height,
+                depth,
+            };
+            self.rejected.insert(block.hash.clone(), refused.clone());
+            self.refused.push(refused.clone());
+            return Err(refused);
+        }
+
+        let height = self
+            .nodes
+            .get(&block.previous_hash)
+            .map_or(1, |parent| parent.height + 1);
+        self.nodes.insert(
+            block.hash.clone(),
+            ForkNode {
+                parent: block.previous_hash.clone(),
+                height,
+            },
+        );
+        if local {
+            self.local.insert(block.hash.clone());
+        }
+        if height > self.tip_height() {
+            self.tip = Some(block.hash.clone());
+        }
+        Ok(true)
+    }
+
+    /// Canonical blocks abandoned if a child of `parent` became the tip
+    fn reorg_depth(&self, parent: &str) -> u64 {
+        let canonical: HashSet<String> = self.canonical_chain().into_iter().collect();
+        let mut cursor = parent;
+        let fork_height = loop {
+            match self.nodes.get(cursor) {
+                Some(node) if canonical.contains(cursor) => break node.height,
+                Some(node) => cursor = node.parent.as_str(),
+                // No common ancestor: the whole canonical chain is replaced
+                None => break 0,
+            }
+        };
+        self.tip_height() - fork_height
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Hash of the canonical chain's last block
+    pub fn canonical_tip(&self) -> Option<&str> {
+        self.tip.as_deref()
+    }
+
+    fn tip_height(&self) -> u64 {
+        self.tip
+            .as_ref()
+            .and_then(|tip| self.nodes.get(tip))
+            .map_or(0, |node| node.height)
+    }
+
+    /// Hashes on the canonical chain, tip first
+    pub fn canonical_chain(&self) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut cursor = self.tip.as_deref();
+        // Stop at the first hash we never stored (the root's parent)
+        while let Some((hash, node)) = cursor.and_then(|h| self.nodes.get_key_value(h)) {
+            chain.push(hash.clone());
+            cursor = Some(node.parent.as_str());
+        }
+        chain
+    }
+
+    pub fn is_canonical(&self, hash: &str) -> bool {
+        self.canonical_chain().iter().any(|h| h == hash)
+    }
+
+    /// Height at and below which canonical blocks can no longer be
+    /// reorganized away; `None` without a finality window
+    pub fn finalized_height(&self) -> Option<u64> {
+        self.max_reorg_depth
+            .map(|depth| self.tip_height().saturating_sub(depth))
+    }
+
+    /// Whether `hash` is canonical and inside the finalized part of the chain
+    pub fn is_final(&self, hash: &str) -> bool {
+        match (self.finalized_height(), self.nodes.get(hash)) {
+            (Some(finalized), Some(node)) => node.height <= finalized && self.is_canonical(hash),
+            _ => false,
+        }
+    }
+
+    /// Blocks rejected for exceeding the finality window, oldest first
+    pub fn refused_reorgs(&self) -> &[RefusedReorg] {
+        &self.refused
+    }
+
+    /// Locally committed blocks that are not on the canonical chain
+    pub fn stale_local_blocks(&self) -> usize {
+        let canonical: HashSet<String> = self.canonical_chain().into_iter().collect();
+        self.local
+            .iter()
+            .filter(|hash| !canonical.contains(*hash))
+            .count()
+    }
+}
+
+/// Records every block `inner` commits in a `ForkTree`, alongside blocks
+/// observed from peers, and reports how many local commits went stale
+pub struct ForkTrackingStrategy {
+    inner: Arc<dyn ConsensusStrategy>,
+    tree: RwLock<ForkTree>,
+}
+
+impl ForkTrackingStrategy {
+    pub fn new(inner: Arc<dyn ConsensusStrategy>) -> Self {
+        ForkTrackingStrategy {
+            inner,
+            tree: RwLock::new(ForkTree::new()),
+        }
+    }
+
+    /// Refuse reorgs that abandon more than `depth` canonical blocks
+    pub fn with_max_reorg_depth(mut self, depth: u64) -> Self {
+        self.tree.get_mut().max_reorg_depth = Some(depth);
+        self
+    }
+
+    /// Add a block committed elsewhere, which may compete with ours
+    pub fn observe_peer_block(&self, block: &Block) -> Result<(), RefusedReorg> {
+        self.tree.write().insert(block, false).map(|_| ())
+    }
+
+    pub fn canonical_tip(&self) -> Option<String> {
+        self.tree.read().canonical_tip().map(str::to_string)
+    }
+
+    pub fn is_final(&self, hash: &str) -> bool {
+        self.tree.read().is_final(hash)
+    }
+
+    pub fn refused_reorgs(&self) -> Vec<RefusedReorg> {
+        self.tree.read().refused_reorgs().to_vec()
+    }
+}
+
+#[async_trait]
+impl ConsensusStrategy for ForkTrackingStrategy {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
+        let committed = self.inner.execute(block).await?;
+        if let Some(committed) = &committed {
+            self.tree
+                .write()
+                .insert(committed, true)
+                .map_err(|e| ConsensusError::InvalidBlock(e.to_string()))?;
+        }
+        Ok(committed)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn requirements(&self) -> ConsensusRequirements {
+        self.inner.requirements()
+    }
+
+    fn is_committed(&self, block_index: u64) -> bool {
+        self.inner.is_committed(block_index)
+    }
+
+    fn geographical_diversity(&self) -> Option<f64> {
+        self.inner.geographical_diversity()
+    }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        self.inner.cost_of_attack()
+    }
+
+    fn stale_blocks(&self) -> Option<usize> {
+        Some(self.tree.read().stale_local_blocks())
+    }
+}
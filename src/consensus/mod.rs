@@ -6,17 +6,31 @@
 //!
 //! ## Structure
 //! - `traits.rs` - Consensus algorithm trait definition
+//! - `error.rs` - `ConsensusError`, returned by algorithms and strategies
 //! - `types.rs` - Common types and data structures
 //! - `algorithms/` - Individual consensus algorithm implementations
 //!   - `pbft.rs` - PBFT (requires majority voting)
 //!   - `gossip.rs` - Gossip protocol (no majority voting)
 //!   - `eventual.rs` - Eventual consistency (no majority voting)
 //!   - `quorumless.rs` - Weighted voting (no majority voting)
+//! - `network_model.rs` - Simulated latency profiles and node regions
+//! - `networked.rs` - Benchmarks driving a live multi-node cluster over HTTP
+//! - `scenario.rs` - YAML scenario files describing whole experiments
+//! - `cost_model.rs` - Cost-of-attack models for the security metrics
+//! - `fork_choice.rs` - Fork storage, longest-chain fork choice, stale blocks
+//! - `finality.rs` - Committed vs finalized blocks as reported to consumers
+//! - `event_log.rs` - Recording and replaying consensus runs
+//! - `quorum.rs` - Pluggable quorum systems for PBFT (classic, weighted, grid)
+//! - `shard.rs` - Per-shard PBFT instances and message routing
+//! - `cross_shard.rs` - Two-phase commit for blocks spanning several shards
+//! - `demo.rs` - Slowed-down, narrated rounds for teaching
+//! - `drill.rs` - Primary failover drill exercising PBFT view changes
 //! - `tests.rs` - Unit tests
 
 // Re-export public API
+pub use error::ConsensusError;
 pub use traits::ConsensusAlgorithm;
-pub use types::{ConsensusMessage, ConsensusRequirements, ConsensusResult};
+pub use types::{ConsensusMessage, ConsensusRequirements, ConsensusResult, PendingDetails};
 
 // Algorithm implementations
 pub mod algorithms;
@@ -27,11 +41,48 @@ pub use comparison::*;
 // Consensus comparison framework
 pub mod comparison;
 
+// Simulated network latency for benchmarks
+pub mod network_model;
+
+// Benchmarks against a running cluster
+pub mod networked;
+
+// Experiments loaded from scenario files
+pub mod scenario;
+
+// Cost-of-attack models for benchmarks
+pub mod cost_model;
+
+// Fork storage and stale block tracking
+pub mod fork_choice;
+
+// When committed blocks become final
+pub mod finality;
+
+// Replayable consensus event log
+pub mod event_log;
+
+// Quorum systems consulted by PBFT
+pub mod quorum;
+
+// Independent PBFT instances per shard
+pub mod shard;
+
+// Atomic commit across shards
+pub mod cross_shard;
+
+// Narrated, slowed-down rounds for live demos
+pub mod demo;
+
+// Silencing the primary to check view changes
+pub mod drill;
+
 // Tests
 #[cfg(test)]
 #[path = "tests.rs"]
 mod tests;
 
 // Internal modules
+mod error;
 mod traits;
 mod types;
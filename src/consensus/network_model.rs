@@ -0,0 +1,237 @@
+//! Simulated network latency for consensus benchmarks
+//!
+//! Benchmarks run every node in one process, so message delays are zero and
+//! geographic spread is meaningless. `NetworkModel` assigns each node to a
+//! region and derives link latency from a named `LatencyProfile`;
+//! `SimulatedNetworkStrategy` wraps a strategy so every round waits for the
+//! quorum round trips the model predicts and reports the cluster's
+//! geographical diversity.
+
+use crate::consensus::comparison::ConsensusStrategy;
+use crate::consensus::{ConsensusError, ConsensusRequirements};
+use crate::etl::Block;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Named deployment shapes with typical one-way latencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyProfile {
+    /// Single data center
+    Lan,
+    /// One cloud region spread over availability zones
+    SameRegionCloud,
+    /// Nodes on different continents
+    CrossContinent,
+}
+
+impl LatencyProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyProfile::Lan => "lan",
+            LatencyProfile::SameRegionCloud => "same-region",
+            LatencyProfile::CrossContinent => "cross-continent",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lan" => Some(LatencyProfile::Lan),
+            "same-region" | "same_region" | "cloud" => Some(LatencyProfile::SameRegionCloud),
+            "cross-continent" | "cross_continent" | "wan" => Some(LatencyProfile::CrossContinent),
+            _ => None,
+        }
+    }
+
+    /// One-way latency between nodes in the same region
+    pub fn intra_region_ms(&self) -> f64 {
+        match self {
+            LatencyProfile::Lan => 0.2,
+            LatencyProfile::SameRegionCloud => 0.5,
+            LatencyProfile::CrossContinent => 0.5,
+        }
+    }
+
+    /// One-way latency between nodes in different regions
+    pub fn inter_region_ms(&self) -> f64 {
+        match self {
+            LatencyProfile::Lan => 0.2,
+            LatencyProfile::SameRegionCloud => 1.5,
+            LatencyProfile::CrossContinent => 90.0,
+        }
+    }
+
+    /// Regions the profile spreads nodes over by default
+    pub fn default_regions(&self) -> &'static [&'static str] {
+        match self {
+            LatencyProfile::Lan => &["dc-1"],
+            LatencyProfile::SameRegionCloud => &["us-east-1a", "us-east-1b", "us-east-1c"],
+            LatencyProfile::CrossContinent => &["us-east", "eu-west", "ap-southeast", "sa-east"],
+        }
+    }
+}
+
+/// Node-to-region placement plus the latency profile between them
+#[derive(Debug, Clone)]
+pub struct NetworkModel {
+    profile: LatencyProfile,
+    node_regions: Vec<String>,
+}
+
+impl NetworkModel {
+    /// Place `node_regions[i]` as the region of node `i`
+    pub fn new(profile: LatencyProfile, node_regions: Vec<String>) -> Self {
+        NetworkModel {
+            profile,
+            node_regions,
+        }
+    }
+
+    /// Spread `total_nodes` round-robin over the profile's default regions
+    pub fn with_default_regions(profile: LatencyProfile, total_nodes: usize) -> Self {
+        let regions = profile.default_regions();
+        let node_regions = (0..total_nodes)
+            .map(|i| regions[i % regions.len()].to_string())
+            .collect();
+        NetworkModel::new(profile, node_regions)
+    }
+
+    pub fn profile(&self) -> LatencyProfile {
+        self.profile
+    }
+
+    pub fn total_nodes(&self) -> usize {
+        self.node_regions.len()
+    }
+
+    pub fn region_of(&self, node_id: usize) -> Option<&str> {
+        self.node_regions.get(node_id).map(String::as_str)
+    }
+
+    /// One-way latency between two nodes
+    pub fn link_latency_ms(&self, from: usize, to: usize) -> f64 {
+        if from == to {
+            0.0
+        } else if self.region_of(from) == self.region_of(to) {
+            self.profile.intra_region_ms()
+        } else {
+            self.profile.inter_region_ms()
+        }
+    }
+
+    /// Time for `proposer` to hear back from enough peers to form a quorum of
+    /// `quorum` nodes (itself included), i.e. the round trip to its
+    /// `quorum - 1` closest peer
+    pub fn quorum_round_trip_ms(&self, proposer: usize, quorum: usize) -> f64 {
+        let mut round_trips: Vec<f64> = (0..self.total_nodes())
+            .filter(|&node| node != proposer)
+            .map(|node| 2.0 * self.link_latency_ms(proposer, node))
+            .collect();
+        round_trips.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        match quorum.saturating_sub(1) {
+            0 => 0.0,
+            needed => round_trips
+                .get(needed - 1)
+                .or(round_trips.last())
+                .copied()
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// Normalized Shannon entropy of the node-per-region distribution:
+    /// 0 when every node shares a region, 1 when every node has its own
+    pub fn geographical_diversity(&self) -> f64 {
+        let total = self.total_nodes();
+        if total < 2 {
+            return 0.0;
+        }
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for region in &self.node_regions {
+            *counts.entry(region.as_str()).or_insert(0) += 1;
+        }
+
+        let entropy: f64 = counts
+            .values()
+            .map(|&count| {
+                let p = count as f64 / total as f64;
+                -p * p.ln()
+            })
+            .sum();
+        entropy / (total as f64).ln()
+    }
+}
+
+/// Runs a strategy as if its messages crossed the modelled network
+pub struct SimulatedNetworkStrategy {
+    inner: Arc<dyn ConsensusStrategy>,
+    model: NetworkModel,
+    proposer: usize,
+    phases: u32,
+}
+
+impl SimulatedNetworkStrategy {
+    /// Wrap `inner`, proposing from node 0 with one quorum round trip per block
+    pub fn new(inner: Arc<dyn ConsensusStrategy>, model: NetworkModel) -> Self {
+        SimulatedNetworkStrategy {
+            inner,
+            model,
+            proposer: 0,
+            phases: 1,
+        }
+    }
+
+    pub fn with_proposer(mut self, proposer: usize) -> Self {
+        self.proposer = proposer;
+        self
+    }
+
+    /// Quorum round trips per block (e.g. 2 for PBFT's prepare and commit)
+    pub fn with_phases(mut self, phases: u32) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    /// Simulated network delay added to each block
+    pub fn round_delay_ms(&self) -> f64 {
+        let quorum = self.inner.requirements().min_nodes.unwrap_or(1);
+        self.phases as f64 * self.model.quorum_round_trip_ms(self.proposer, quorum)
+    }
+}
+
+#[async_trait]
+impl ConsensusStrategy for SimulatedNetworkStrategy {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
+        let delay = self.round_delay_ms();
+        if delay > 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(delay / 1000.0)).await;
+        }
+        self.inner.execute(block).await
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn requirements(&self) -> ConsensusRequirements {
+        self.inner.requirements()
+    }
+
+    fn is_committed(&self, block_index: u64) -> bool {
+        self.inner.is_committed(block_index)
+    }
+
+    fn geographical_diversity(&self) -> Option<f64> {
+        Some(self.model.geographical_diversity())
+    }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        self.inner.cost_of_attack()
+    }
+
+    fn stale_blocks(&self) -> Option<usize> {
+        self.inner.stale_blocks()
+    }
+}
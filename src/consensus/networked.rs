@@ -0,0 +1,456 @@
+//! Benchmarks against a live cluster
+//!
+//! The other strategies run every node in one process, so their latency is
+//! whatever the simulation adds (see `network_model`). `NetworkedClusterStrategy`
+//! drives a running cluster instead: each block's entries are submitted to
+//! every node through `POST /tenant/submit`, and the block counts as
+//! committed once a quorum of nodes serve them from `GET /tenant/blocks`.
+//! The measured latency therefore covers HTTP, the consensus rounds between
+//! processes and persistence. Metrics from it are tagged
+//! `BenchmarkMode::Networked`. A committed block comes back with the fees
+//! the cluster charged the benchmark's tenant for its entries.
+//!
+//! The cluster needs tenancy enabled with a key for the benchmark (see
+//! `network::tenancy`). Configured from the environment with
+//! `BENCH_CLUSTER_NODES` (`host:port,...`), `BENCH_API_KEY`, `BENCH_QUORUM`
+//! (default: majority) and `BENCH_TIMEOUT_MS`.
+
+use crate::consensus::comparison::{BenchmarkMode, ConsensusStrategy};
+use crate::consensus::{ConsensusError, ConsensusRequirements};
+use crate::etl::accounting::FeeRecord;
+use crate::etl::{Block, MarketData};
+use crate::network::pagination::MAX_PAGE_SIZE;
+use crate::network::tenancy::API_KEY_HEADER;
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use reqwest::header::{HeaderMap, LINK};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+/// Time to wait for a quorum unless `BENCH_TIMEOUT_MS` is set
+pub const DEFAULT_COMMIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The part of a `/tenant/blocks` entry the benchmark reads
+#[derive(Deserialize)]
+struct ServedBlock {
+    index: u64,
+    data: Vec<MarketData>,
+    #[serde(default)]
+    fees: Vec<FeeRecord>,
+}
+
+/// Proposes blocks to a running cluster over HTTP
+pub struct NetworkedClusterStrategy {
+    nodes: Vec<String>,
+    api_key: String,
+    quorum: usize,
+    timeout: Duration,
+    poll_interval: Duration,
+    client: reqwest::Client,
+    /// Milliseconds at the start of the run; block `i`'s entries are stamped
+    /// `run_started + i` so they are not confused with earlier runs
+    run_started: i64,
+    /// Lowest block index worth scanning per node
+    cursors: Mutex<HashMap<String, u64>>,
+    committed: RwLock<HashSet<u64>>,
+}
+
+impl NetworkedClusterStrategy {
+    /// Benchmark the nodes at `nodes` (`host:port`), authenticating as a
+    /// tenant with `api_key`
+    pub fn new(nodes: Vec<String>, api_key: impl Into<String>) -> Self {
+        let quorum = nodes.len() / 2 + 1;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        NetworkedClusterStrategy {
+            nodes,
+            api_key: api_key.into(),
+            quorum,
+            timeout: DEFAULT_COMMIT_TIMEOUT,
+            poll_interval: Duration::from_millis(100),
+            client,
+            run_started: chrono::Utc::now().timestamp_millis(),
+            cursors: Mutex::new(HashMap::new()),
+            committed: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Read `BENCH_CLUSTER_NODES` and `BENCH_API_KEY`; `None` unless both
+    /// are set
+    pub fn from_env() -> Option<Self> {
+        let nodes: Vec<String> = std::env::var("BENCH_CLUSTER_NODES")
+            .ok()?
+            .split(',')
+            .map(|node| node.trim().to_string())
+            .filter(|node| !node.is_empty())
+            .collect();
+        let api_key = std::env::var("BENCH_API_KEY").ok()?;
+        if nodes.is_empty() {
+            return None;
+        }
+
+        let mut strategy = NetworkedClusterStrategy::new(nodes, api_key);
+        if let Some(quorum) = std::env::var("BENCH_QUORUM")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            strategy = strategy.with_quorum(quorum);
+        }
+        if let Some(ms) = std::env::var("BENCH_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            strategy = strategy.with_timeout(Duration::from_millis(ms));
+        }
+        Some(strategy)
+    }
+
+    /// Nodes that must serve a block before it counts as committed, between
+    /// 1 and the cluster size
+    pub fn with_quorum(mut self, quorum: usize) -> Self {
+        self.quorum = quorum.clamp(1, self.nodes.len().max(1));
+        self
+    }
+
+    /// Give up on a block (counted as not committed) after this long
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// Timestamp marking the entries proposed for `block_index`; nodes
+    /// record the tenant as the source, so the source cannot carry it
+    fn marker(&self, block_index: u64) -> i64 {
+        self.run_started + block_index as i64
+    }
+
+    /// Submit the block's entries to every node; fails only when no node
+    /// accepted them
+    async fn propose(&self, block: &Block, marker: i64) -> Result<usize, ConsensusError> {
+        let entries: Vec<_> = block
+            .data
+            .iter()
+            .map(|item| {
+                json!({
+                    "asset": item.asset,
+                    "price": item.price,
+                    "timestamp": marker,
+                })
+            })
+            .collect();
+
+        let mut submissions = JoinSet::new();
+        for node in &self.nodes {
+            let request = self
+                .client
+                .post(format!("http://{}/tenant/submit", node))
+                .header(API_KEY_HEADER, &self.api_key)
+                .json(&entries);
+            let node = node.clone();
+            submissions.spawn(async move {
+                let result = match request.send().await {
+                    Ok(response) => response.error_for_status().map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                (node, result)
+            });
+        }
+
+        let mut accepted = 0;
+        let mut last_error = None;
+        while let Some(joined) = submissions.join_next().await {
+            let (node, result) =
+                joined.map_err(|e| ConsensusError::Internal(format!("submit task: {}", e)))?;
+            match result {
+                Ok(()) => accepted += 1,
+                Err(e) => {
+                    warn!(node = %node, error = %e, "Bench: Node rejected proposal");
+                    last_error = Some(format!("{}: {}", node, e));
+                }
+            }
+        }
+        if accepted == 0 {
+            return Err(ConsensusError::Network(
+                last_error.unwrap_or_else(|| "no nodes configured".to_string()),
+            ));
+        }
+        Ok(accepted)
+    }
+
+    /// Fees of the block in which `node` serves an entry stamped `marker`,
+    /// paging from its cursor to the head of its ledger and advancing the
+    /// cursor past the blocks scanned
+    async fn node_has(
+        &self,
+        node: &str,
+        marker: i64,
+    ) -> Result<Option<Vec<FeeRecord>>, reqwest::Error> {
+        let mut from = self.cursors.lock().get(node).copied().unwrap_or(1);
+        loop {
+            let response = self
+                .client
+                .get(format!(
+                    "http://{}/tenant/blocks?from={}&limit={}",
+                    node, from, MAX_PAGE_SIZE
+                ))
+                .header(API_KEY_HEADER, &self.api_key)
+                .send()
+                .await?
+                .error_for_status()?;
+            let next = next_page(response.headers());
+            let blocks: Vec<ServedBlock> = response.json().await?;
+
+            let found = blocks
+                .iter()
+                .find(|block| block.data.iter().any(|item| item.timestamp == marker));
+            // Pages skip blocks without our entries, so only the `next` link
+            // tells how far a page reached
+            let cursor = match next {
+                Some(next) => next,
+                // Re-scan the newest block next time; it may still grow
+                None => blocks.last().map_or(from, |last| last.index),
+            };
+            self.cursors.lock().insert(node.to_string(), cursor);
+            if let Some(block) = found {
+                return Ok(Some(block.fees.clone()));
+            }
+            match next {
+                Some(next) if next > from => from = next,
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Poll the nodes until `quorum` of them serve `marker` or the timeout
+    /// passes; the fees charged for the entries once they do
+    async fn await_quorum(&self, marker: i64) -> Option<Vec<FeeRecord>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut served: HashSet<&str> = HashSet::new();
+        let mut fees = Vec::new();
+
+        loop {
+            for node in &self.nodes {
+                if served.contains(node.as_str()) {
+                    continue;
+                }
+                match self.node_has(node, marker).await {
+                    Ok(Some(charged)) => {
+                        served.insert(node);
+                        fees = charged;
+                    }
+                    Ok(None) => {}
+                    Err(e) => debug!(node = %node, error = %e, "Bench: Poll failed"),
+                }
+            }
+            if served.len() >= self.quorum {
+                return Some(fees);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+/// `from` of the `next` page in a listing's `Link` header
+fn next_page(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(LINK)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find(|link| link.contains("rel=\"next\""))?
+        .split(['?', '&', '>'])
+        .find_map(|param| param.strip_prefix("from="))?
+        .parse()
+        .ok()
+}
+
+#[async_trait]
+impl ConsensusStrategy for NetworkedClusterStrategy {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
+        let marker = self.marker(block.index);
+        self.propose(block, marker).await?;
+
+        let Some(fees) = self.await_quorum(marker).await else {
+            warn!(
+                block_index = block.index,
+                quorum = self.quorum,
+                timeout_ms = self.timeout.as_millis() as u64,
+                "Bench: Block not served by a quorum in time"
+            );
+            return Ok(None);
+        };
+        self.committed.write().insert(block.index);
+        // The cluster charges the benchmark's tenant like any other
+        Ok(Some(Block {
+            fees,
+            ..block.clone()
+        }))
+    }
+
+    fn name(&self) -> &str {
+        "Live Cluster"
+    }
+
+    fn requirements(&self) -> ConsensusRequirements {
+        ConsensusRequirements {
+            requires_majority: self.quorum > self.nodes.len() / 2,
+            min_nodes: Some(self.quorum),
+            description: format!(
+                "Served by {} of {} live nodes",
+                self.quorum,
+                self.nodes.len()
+            ),
+        }
+    }
+
+    fn is_committed(&self, block_index: u64) -> bool {
+        self.committed.read().contains(&block_index)
+    }
+
+    fn benchmark_mode(&self) -> BenchmarkMode {
+        BenchmarkMode::Networked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::comparison::benchmark_consensus_strategy;
+    use crate::etl::price::Decimal;
+    use crate::testing;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::Arc;
+
+    /// Commits every submission straight into a new block, after `others`
+    /// blocks holding no entries of the benchmark's tenant
+    #[derive(Default)]
+    struct FakeNode {
+        others: u64,
+        blocks: Mutex<Vec<serde_json::Value>>,
+    }
+
+    async fn submit(
+        entries: web::Json<Vec<serde_json::Value>>,
+        node: web::Data<FakeNode>,
+    ) -> HttpResponse {
+        let mut blocks = node.blocks.lock();
+        let index = node.others + blocks.len() as u64 + 1;
+        blocks.push(json!({
+            "index": index,
+            "timestamp": 0,
+            "hash": "",
+            "data": entries.iter().map(|entry| json!({
+                "asset": entry["asset"],
+                "price": entry["price"],
+                "source": "key",
+                "timestamp": entry["timestamp"],
+            })).collect::<Vec<_>>(),
+            "fees": [{ "submitter": "key", "entries": entries.len(), "amount": 3 }],
+        }));
+        HttpResponse::Accepted().finish()
+    }
+
+    /// Pages like a real node: the tenant's blocks in `from..from + limit`,
+    /// with a `next` link while the ledger goes further
+    async fn blocks(
+        query: web::Query<HashMap<String, u64>>,
+        node: web::Data<FakeNode>,
+    ) -> HttpResponse {
+        let blocks = node.blocks.lock();
+        let (from, limit) = (query["from"], query["limit"]);
+        let page: Vec<_> = blocks
+            .iter()
+            .filter(|block| (from..from + limit).contains(&block["index"].as_u64().unwrap()))
+            .collect();
+        let mut response = HttpResponse::Ok();
+        if from + limit <= node.others + blocks.len() as u64 {
+            response.insert_header((
+                "Link",
+                format!(
+                    "</tenant/blocks?from={}&limit={}>; rel=\"next\"",
+                    from + limit,
+                    limit
+                ),
+            ));
+        }
+        response.json(page)
+    }
+
+    fn start_fake_node(others: u64) -> String {
+        let node = web::Data::new(FakeNode {
+            others,
+            ..FakeNode::default()
+        });
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(node.clone())
+                .route("/tenant/submit", web::post().to(submit))
+                .route("/tenant/blocks", web::get().to(blocks))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        tokio::spawn(server.run());
+        addr.to_string()
+    }
+
+    fn block(index: u64) -> Block {
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        testing::block(index, "0", vec![entry])
+    }
+
+    #[actix_web::test]
+    async fn test_networked_benchmark_waits_for_quorum() {
+        let live = vec![start_fake_node(0), start_fake_node(0)];
+
+        let strategy = Arc::new(NetworkedClusterStrategy::new(live.clone(), "key"));
+        let metrics = benchmark_consensus_strategy(strategy, &[block(1), block(2)]).await;
+        assert_eq!(metrics.mode, BenchmarkMode::Networked);
+        assert_eq!(metrics.committed_blocks, 2);
+
+        // A third node that never answers leaves the quorum of all three
+        // unreachable
+        let mut nodes = live;
+        nodes.push("127.0.0.1:1".to_string());
+        let strategy = NetworkedClusterStrategy::new(nodes, "key")
+            .with_quorum(3)
+            .with_timeout(Duration::from_millis(200))
+            .with_poll_interval(Duration::from_millis(20));
+        assert!(strategy.execute(&block(3)).await.unwrap().is_none());
+        assert!(!strategy.is_committed(3));
+    }
+
+    #[actix_web::test]
+    async fn test_networked_benchmark_pages_past_other_blocks() {
+        // Several pages of other tenants' blocks ahead of the benchmark's
+        let node = start_fake_node(3 * MAX_PAGE_SIZE + 7);
+        let strategy = NetworkedClusterStrategy::new(vec![node], "key")
+            .with_timeout(Duration::from_secs(5))
+            .with_poll_interval(Duration::from_millis(20));
+
+        let committed = strategy.execute(&block(1)).await.unwrap().unwrap();
+        assert_eq!(committed.fees.len(), 1);
+        assert_eq!(committed.fees[0].amount, 3);
+        assert!(strategy.execute(&block(2)).await.unwrap().is_some());
+        assert!(strategy.is_committed(2));
+    }
+}
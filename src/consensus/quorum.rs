@@ -0,0 +1,446 @@
+//! Quorum systems for PBFT
+//!
+//! `PBFTManager` asks its `QuorumPolicy` whether the nodes that voted in a
+//! phase form a quorum. The classic policy is PBFT's 2f+1 of 3f+1; the
+//! weighted and grid policies let experiments swap in other quorum systems
+//! without touching the protocol.
+//!
+//! Policies are configured with `PBFT_QUORUM_POLICY`:
+//!
+//! ```text
+//! classic                       2f+1 votes (default)
+//! weighted[:F]:ID=W,ID=W,...    votes carrying more than F of the total weight
+//!                               (0 < F < 1, default 2/3; weights are at least
+//!                               0 and unlisted nodes weigh 1)
+//! grid:RxC                      a full row plus one node from every row of an
+//!                               R x C grid laid out by node id, row-major;
+//!                               R x C must equal the number of voting nodes
+//! ```
+//!
+//! Any policy can also be made failure-domain aware. `NODE_FAILURE_DOMAINS`
+//! tags nodes with a `region/zone/rack` path (`ID=TAG,...`; shorter tags
+//! are fine) and `QUORUM_MIN_DOMAINS` (`[LEVEL:]K`, level `region`, `zone`
+//! or `rack`, default `zone:2`) makes a quorum additionally span at least K
+//! distinct domains at that level, so nodes sharing a zone cannot form one
+//! on their own. Untagged nodes count towards the policy but not towards
+//! any domain. `DomainQuorum` applies the rule to PBFT;
+//! `FlexiblePaxos::with_failure_domains` to both Paxos phases.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides whether a set of voters is a quorum
+pub trait QuorumPolicy: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// `voters` are distinct node ids out of `total_nodes`
+    fn is_quorum(&self, voters: &[usize], total_nodes: usize) -> bool;
+
+    /// The policy fits a cluster of `total_nodes`
+    fn check(&self, _total_nodes: usize) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// PBFT's 2f+1 out of 3f+1
+#[derive(Debug, Clone, Default)]
+pub struct ClassicQuorum;
+
+impl ClassicQuorum {
+    pub fn quorum_size(total_nodes: usize) -> usize {
+        let f = total_nodes.saturating_sub(1) / 3;
+        (2 * f) + 1
+    }
+}
+
+impl QuorumPolicy for ClassicQuorum {
+    fn name(&self) -> &str {
+        "classic"
+    }
+
+    fn is_quorum(&self, voters: &[usize], total_nodes: usize) -> bool {
+        voters.len() >= Self::quorum_size(total_nodes)
+    }
+}
+
+/// Voters must carry more than `threshold` of the cluster's total weight
+#[derive(Debug, Clone)]
+pub struct WeightedQuorum {
+    weights: HashMap<usize, f64>,
+    threshold: f64,
+}
+
+impl WeightedQuorum {
+    /// Nodes missing from `weights` weigh 1.0; weights must be finite and
+    /// not negative
+    pub fn new(weights: HashMap<usize, f64>) -> Result<Self, String> {
+        let mut invalid: Vec<_> = weights
+            .iter()
+            .filter(|(_, weight)| !weight.is_finite() || **weight < 0.0)
+            .collect();
+        invalid.sort_by_key(|(id, _)| **id);
+        if let Some((id, weight)) = invalid.first() {
+            return Err(format!(
+                "invalid weight {} for node {}: expected a finite number of at least 0",
+                weight, id
+            ));
+        }
+        Ok(WeightedQuorum {
+            weights,
+            threshold: 2.0 / 3.0,
+        })
+    }
+
+    /// `threshold` is a fraction of the total weight, strictly between 0
+    /// and 1
+    pub fn with_threshold(mut self, threshold: f64) -> Result<Self, String> {
+        if !(threshold > 0.0 && threshold < 1.0) {
+            return Err(format!(
+                "invalid weighted threshold {}: expected a fraction between 0 and 1",
+                threshold
+            ));
+        }
+        self.threshold = threshold;
+        Ok(self)
+    }
+
+    pub fn weight(&self, node_id: usize) -> f64 {
+        self.weights.get(&node_id).copied().unwrap_or(1.0)
+    }
+}
+
+impl QuorumPolicy for WeightedQuorum {
+    fn name(&self) -> &str {
+        "weighted"
+    }
+
+    fn is_quorum(&self, voters: &[usize], total_nodes: usize) -> bool {
+        let total: f64 = (0..total_nodes).map(|id| self.weight(id)).sum();
+        let voted: f64 = voters.iter().map(|&id| self.weight(id)).sum();
+        total > 0.0 && voted > self.threshold * total
+    }
+
+    fn check(&self, total_nodes: usize) -> Result<(), String> {
+        if (0..total_nodes).map(|id| self.weight(id)).sum::<f64>() > 0.0 {
+            Ok(())
+        } else {
+            Err(format!("the {} nodes carry no weight", total_nodes))
+        }
+    }
+}
+
+/// Nodes laid out row-major on a `rows x cols` grid; a quorum is one complete
+/// row plus at least one node from every row, so any two quorums intersect
+/// while a quorum can be far smaller than a majority. The grid must hold
+/// exactly the cluster's nodes: one that leaves nodes out or has empty cells
+/// forms no quorum.
+#[derive(Debug, Clone)]
+pub struct GridQuorum {
+    rows: usize,
+    cols: usize,
+}
+
+impl GridQuorum {
+    pub fn new(rows: usize, cols: usize) -> Result<Self, String> {
+        if rows == 0 || cols == 0 {
+            return Err(format!(
+                "invalid grid {}x{}: rows and columns must be at least 1",
+                rows, cols
+            ));
+        }
+        Ok(GridQuorum { rows, cols })
+    }
+
+    pub fn size(&self) -> usize {
+        self.rows * self.cols
+    }
+}
+
+impl QuorumPolicy for GridQuorum {
+    fn name(&self) -> &str {
+        "grid"
+    }
+
+    fn is_quorum(&self, voters: &[usize], total_nodes: usize) -> bool {
+        if self.size() != total_nodes {
+            return false;
+        }
+        let mut per_row = vec![0usize; self.rows];
+        for &id in voters {
+            if id < total_nodes {
+                per_row[id / self.cols] += 1;
+            }
+        }
+        per_row.iter().all(|&n| n > 0) && per_row.contains(&self.cols)
+    }
+
+    fn check(&self, total_nodes: usize) -> Result<(), String> {
+        if self.size() != total_nodes {
+            return Err(format!(
+                "a {}x{} grid holds {} nodes but the cluster has {}",
+                self.rows,
+                self.cols,
+                self.size(),
+                total_nodes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Tier of a `region/zone/rack` failure domain tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainLevel {
+    Region,
+    Zone,
+    Rack,
+}
+
+impl DomainLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "region" => Ok(DomainLevel::Region),
+            "zone" => Ok(DomainLevel::Zone),
+            "rack" => Ok(DomainLevel::Rack),
+            other => Err(format!(
+                "unknown failure domain level '{}' (expected region, zone or rack)",
+                other
+            )),
+        }
+    }
+
+    /// Leading tag components that name a domain at this level
+    fn depth(self) -> usize {
+        match self {
+            DomainLevel::Region => 1,
+            DomainLevel::Zone => 2,
+            DomainLevel::Rack => 3,
+        }
+    }
+}
+
+impl fmt::Display for DomainLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DomainLevel::Region => "region",
+            DomainLevel::Zone => "zone",
+            DomainLevel::Rack => "rack",
+        })
+    }
+}
+
+/// Failure domain of each node and how many distinct domains a quorum must
+/// span
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureDomains {
+    /// `region/zone/rack` tag by node id
+    tags: HashMap<usize, String>,
+    level: DomainLevel,
+    min_domains: usize,
+}
+
+impl FailureDomains {
+    /// Quorums must span `min_domains` zones
+    pub fn new(tags: HashMap<usize, String>, min_domains: usize) -> Self {
+        FailureDomains {
+            tags,
+            level: DomainLevel::Zone,
+            min_domains,
+        }
+    }
+
+    pub fn with_level(mut self, level: DomainLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Parse the `NODE_FAILURE_DOMAINS` tags and the `QUORUM_MIN_DOMAINS`
+    /// requirement (default `zone:2`)
+    pub fn parse(tags: &str, min_domains: Option<&str>) -> Result<Self, String> {
+        let mut parsed = HashMap::new();
+        for entry in tags.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, tag) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("invalid domain '{}': expected ID=TAG", entry))?;
+            let id = id
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid node id '{}'", id.trim()))?;
+            let tag = tag.trim().trim_matches('/');
+            if tag.is_empty() || tag.split('/').any(|part| part.trim().is_empty()) {
+                return Err(format!(
+                    "invalid domain '{}': expected region/zone/rack",
+                    entry
+                ));
+            }
+            parsed.insert(id, tag.to_string());
+        }
+        let (level, count) = match min_domains.map(str::trim) {
+            None | Some("") => (DomainLevel::Zone, "2"),
+            Some(spec) => match spec.split_once(':') {
+                Some((level, count)) => (DomainLevel::parse(level)?, count),
+                None => (DomainLevel::Zone, spec),
+            },
+        };
+        let count = count
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| format!("invalid domain count '{}'", count.trim()))?;
+        Ok(Self::new(parsed, count).with_level(level))
+    }
+
+    /// `None` unless `NODE_FAILURE_DOMAINS` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(tags) = std::env::var("NODE_FAILURE_DOMAINS") else {
+            return Ok(None);
+        };
+        Self::parse(&tags, std::env::var("QUORUM_MIN_DOMAINS").ok().as_deref())
+            .map(Some)
+            .map_err(|e| format!("invalid failure domains: {}", e))
+    }
+
+    pub fn level(&self) -> DomainLevel {
+        self.level
+    }
+
+    pub fn min_domains(&self) -> usize {
+        self.min_domains
+    }
+
+    /// `node_id`'s domain at the configured level; a tag with fewer
+    /// components than the level is a domain as a whole
+    pub fn domain_of(&self, node_id: usize) -> Option<&str> {
+        let tag = self.tags.get(&node_id)?;
+        let end = tag
+            .match_indices('/')
+            .nth(self.level.depth() - 1)
+            .map_or(tag.len(), |(i, _)| i);
+        Some(&tag[..end])
+    }
+
+    /// Distinct domains among `nodes`
+    pub fn domains(&self, nodes: &[usize]) -> BTreeSet<&str> {
+        nodes.iter().filter_map(|&id| self.domain_of(id)).collect()
+    }
+
+    /// Whether `voters` span at least `min_domains` domains
+    pub fn spans(&self, voters: &[usize]) -> bool {
+        self.domains(voters).len() >= self.min_domains
+    }
+
+    /// The `total_nodes` of the cluster can span enough domains at all
+    pub fn check(&self, total_nodes: usize) -> Result<(), String> {
+        let all: Vec<usize> = (0..total_nodes).collect();
+        let available = self.domains(&all).len();
+        if available < self.min_domains {
+            return Err(format!(
+                "quorums must span {} {}s but the {} nodes are tagged with {}",
+                self.min_domains, self.level, total_nodes, available
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Another policy's quorums that also span enough failure domains
+pub struct DomainQuorum {
+    inner: Arc<dyn QuorumPolicy>,
+    domains: FailureDomains,
+    name: String,
+}
+
+impl DomainQuorum {
+    pub fn new(inner: Arc<dyn QuorumPolicy>, domains: FailureDomains) -> Self {
+        DomainQuorum {
+            name: format!("{}+domains", inner.name()),
+            inner,
+            domains,
+        }
+    }
+
+    pub fn domains(&self) -> &FailureDomains {
+        &self.domains
+    }
+}
+
+impl QuorumPolicy for DomainQuorum {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_quorum(&self, voters: &[usize], total_nodes: usize) -> bool {
+        self.domains.spans(voters) && self.inner.is_quorum(voters, total_nodes)
+    }
+
+    fn check(&self, total_nodes: usize) -> Result<(), String> {
+        self.inner.check(total_nodes)?;
+        self.domains.check(total_nodes)
+    }
+}
+
+/// Parse a policy in the `PBFT_QUORUM_POLICY` format
+pub fn parse_policy(spec: &str) -> Result<Arc<dyn QuorumPolicy>, String> {
+    let spec = spec.trim();
+    let (kind, params) = spec.split_once(':').unwrap_or((spec, ""));
+
+    match kind.to_ascii_lowercase().as_str() {
+        "classic" | "" => Ok(Arc::new(ClassicQuorum)),
+        "weighted" => {
+            // An optional leading threshold, e.g. `weighted:0.5:0=2,1=1`
+            let (threshold, weights_spec) = match params.split_once(':') {
+                Some((t, rest)) => (
+                    Some(
+                        t.parse::<f64>()
+                            .map_err(|_| format!("invalid weighted threshold '{}'", t))?,
+                    ),
+                    rest,
+                ),
+                None => (None, params),
+            };
+            let mut weights = HashMap::new();
+            for entry in weights_spec.split(',').filter(|e| !e.trim().is_empty()) {
+                let (id, weight) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid weight '{}': expected ID=WEIGHT", entry))?;
+                let id = id
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid node id '{}'", id))?;
+                let weight = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid weight '{}'", weight))?;
+                weights.insert(id, weight);
+            }
+            let policy = WeightedQuorum::new(weights)?;
+            Ok(Arc::new(match threshold {
+                Some(t) => policy.with_threshold(t)?,
+                None => policy,
+            }))
+        }
+        "grid" => {
+            let (rows, cols) = params
+                .split_once('x')
+                .and_then(|(r, c)| Some((r.parse().ok()?, c.parse().ok()?)))
+                .ok_or_else(|| format!("invalid grid '{}': expected ROWSxCOLS", params))?;
+            Ok(Arc::new(GridQuorum::new(rows, cols)?))
+        }
+        other => Err(format!("unknown quorum policy '{}'", other)),
+    }
+}
+
+/// Load `PBFT_QUORUM_POLICY`, defaulting to the classic 2f+1 policy, made
+/// domain aware when `NODE_FAILURE_DOMAINS` is set
+pub fn policy_from_env() -> Result<Arc<dyn QuorumPolicy>, String> {
+    let policy = match std::env::var("PBFT_QUORUM_POLICY") {
+        Ok(spec) => parse_policy(&spec)?,
+        Err(_) => Arc::new(ClassicQuorum),
+    };
+    Ok(match FailureDomains::from_env()? {
+        Some(domains) => Arc::new(DomainQuorum::new(policy, domains)),
+        None => policy,
+    })
+}
@@ -0,0 +1,898 @@
+//! Data-driven consensus experiments
+//!
+//! A scenario file (YAML) describes a whole experiment: the simulated
+//! nodes and their network, the consensus strategies to compare, the
+//! workload of blocks, faults to inject and where to write the results.
+//! `Scenario::run` builds everything from that description and benchmarks
+//! each strategy with `benchmark_consensus_strategy`, so a new experiment is
+//! a new file under `scenarios/` rather than another example binary.
+//!
+//! ```yaml
+//! name: lan-baseline
+//! nodes: { count: 4, profile: lan }
+//! workload: { blocks: 50, rounds: 3 }
+//! consensus:
+//!   - algorithm: pbft
+//!   - algorithm: gossip
+//!     fanout: 2
+//! faults:
+//!   - kind: delay
+//!     delay_ms: 20
+//!     from_block: 10
+//!     to_block: 20
+//! storage:
+//!   backends: [sqlite, sqlite_wal, memory]
+//! outputs: { json: results/lan-baseline.json }
+//! ```
+//!
+//! The optional `storage` section also times the load stage on each listed
+//! backend with the same blocks (see `etl::storage_bench`).
+//!
+//! Run one with `cargo run --example run_scenario -- scenarios/<file>.yaml`.
+
+use crate::consensus::algorithms::{
+    eventual, flexible_paxos, gossip, pbft, quorumless, PBFTManager,
+};
+use crate::consensus::comparison::{
+    benchmark_consensus_strategy, print_metrics_comparison, BenchmarkMode,
+    ConsensusAlgorithmAdapter, ConsensusMetrics, ConsensusStrategy, NoConsensusStrategy,
+    SimpleMajorityStrategy,
+};
+use crate::consensus::cost_model::{QuorumCollusionCost, SingleIdentityCost, StakeCost};
+use crate::consensus::network_model::{LatencyProfile, NetworkModel, SimulatedNetworkStrategy};
+use crate::consensus::networked::NetworkedClusterStrategy;
+use crate::consensus::quorum::{ClassicQuorum, QuorumPolicy, WeightedQuorum};
+use crate::consensus::{ConsensusError, ConsensusRequirements};
+use crate::etl::storage_bench::{
+    benchmark_storage, print_storage_comparison, StorageBackend, StorageMetrics,
+};
+use crate::etl::{price, Block, MarketData, BLOCK_FORMAT_VERSION};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// A complete experiment, as read from a scenario file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub nodes: NodesConfig,
+    #[serde(default)]
+    pub workload: WorkloadConfig,
+    pub consensus: Vec<ConsensusConfig>,
+    #[serde(default)]
+    pub faults: Vec<FaultConfig>,
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    #[serde(default)]
+    pub outputs: OutputConfig,
+}
+
+/// The simulated cluster the in-process strategies run on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodesConfig {
+    pub count: usize,
+    /// `lan`, `same-region` or `cross-continent`; no simulated network
+    /// delay when unset
+    pub profile: Option<String>,
+    /// Region of each node; defaults to the profile's regions round-robin
+    pub regions: Option<Vec<String>>,
+    /// Node proposing every block
+    pub proposer: usize,
+}
+
+impl Default for NodesConfig {
+    fn default() -> Self {
+        NodesConfig {
+            count: 4,
+            profile: None,
+            regions: None,
+            proposer: 0,
+        }
+    }
+}
+
+/// The blocks every strategy is benchmarked on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkloadConfig {
+    pub blocks: usize,
+    /// Times each strategy runs over the blocks
+    pub rounds: usize,
+    /// One entry per asset in every block
+    pub assets: Vec<String>,
+    pub base_price: f32,
+    /// Price increase per block
+    pub price_step: f32,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            blocks: 100,
+            rounds: 1,
+            assets: vec!["BTC".to_string()],
+            base_price: 50000.0,
+            price_step: 100.0,
+        }
+    }
+}
+
+/// One strategy to benchmark; `name` overrides the label in the results
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ConsensusConfig {
+    NoConsensus {
+        name: Option<String>,
+    },
+    SimpleMajority {
+        name: Option<String>,
+    },
+    Pbft {
+        name: Option<String>,
+        /// Votes a phase needs, between 1 and the node count; defaults to
+        /// 2f+1
+        quorum: Option<usize>,
+    },
+    Gossip {
+        name: Option<String>,
+        #[serde(default = "default_gossip_rounds")]
+        rounds: usize,
+        #[serde(default = "default_fanout")]
+        fanout: usize,
+    },
+    Eventual {
+        name: Option<String>,
+        #[serde(default = "default_eventual_delay_ms")]
+        delay_ms: u64,
+        #[serde(default = "default_eventual_threshold")]
+        threshold: usize,
+    },
+    Quorumless {
+        name: Option<String>,
+        #[serde(default = "default_weight_threshold")]
+        threshold: f64,
+    },
+    FlexiblePaxos {
+        name: Option<String>,
+        q1: usize,
+        q2: usize,
+    },
+    /// A running cluster (see `networked`); the API key falls back to
+    /// `BENCH_API_KEY`
+    Networked {
+        name: Option<String>,
+        nodes: Vec<String>,
+        api_key: Option<String>,
+        quorum: Option<usize>,
+        timeout_ms: Option<u64>,
+    },
+}
+
+impl ConsensusConfig {
+    /// The algorithm called `name` with default parameters for `nodes`
+    /// nodes; `None` for unknown names and for `networked`, which needs a
+    /// cluster address
+    pub fn from_name(name: &str, nodes: usize) -> Option<Self> {
+        let majority = nodes / 2 + 1;
+        Some(match name.to_ascii_lowercase().replace('-', "_").as_str() {
+            "no_consensus" | "none" => ConsensusConfig::NoConsensus { name: None },
+            "simple_majority" | "majority" => ConsensusConfig::SimpleMajority { name: None },
+            "pbft" => ConsensusConfig::Pbft {
+                name: None,
+                quorum: None,
+            },
+            "gossip" => ConsensusConfig::Gossip {
+                name: None,
+                rounds: default_gossip_rounds(),
+                fanout: default_fanout(),
+            },
+            "eventual" => ConsensusConfig::Eventual {
+                name: None,
+                delay_ms: default_eventual_delay_ms(),
+                threshold: default_eventual_threshold(),
+            },
+            "quorumless" | "quorum_less" => ConsensusConfig::Quorumless {
+                name: None,
+                threshold: default_weight_threshold(),
+            },
+            "flexible_paxos" | "fpaxos" => ConsensusConfig::FlexiblePaxos {
+                name: None,
+                q1: majority,
+                q2: nodes + 1 - majority,
+            },
+            _ => return None,
+        })
+    }
+
+    /// The strategy on `cluster`, its label and its quorum round trips per
+    /// block
+    pub fn build(
+        &self,
+        cluster: &NodesConfig,
+    ) -> Result<(String, Arc<dyn ConsensusStrategy>, u32), String> {
+        let nodes = cluster.count;
+        let node_id = cluster.proposer;
+        let label = |name: &Option<String>, default: &str| {
+            name.clone().unwrap_or_else(|| default.to_string())
+        };
+
+        Ok(match self {
+            ConsensusConfig::NoConsensus { name } => (
+                label(name, "No-Consensus"),
+                Arc::new(NoConsensusStrategy::new()),
+                1,
+            ),
+            ConsensusConfig::SimpleMajority { name } => (
+                label(name, "Simple Majority"),
+                Arc::new(SimpleMajorityStrategy::new(node_id, nodes)),
+                1,
+            ),
+            ConsensusConfig::Pbft { name, quorum } => {
+                let addresses: Vec<String> = (0..nodes)
+                    .map(|i| format!("127.0.0.1:{}", 8000 + i))
+                    .collect();
+                let mut manager = PBFTManager::new(node_id, nodes, addresses.clone());
+                if let Some(quorum) = quorum {
+                    manager = manager.with_quorum_policy(vote_count_quorum(*quorum, nodes)?);
+                }
+                let quorum = quorum.unwrap_or(ClassicQuorum::quorum_size(nodes));
+                let manager = Arc::new(manager);
+                let consensus = pbft::PBFTConsensus::new(manager, addresses, 8000);
+                (
+                    label(name, "PBFT"),
+                    Arc::new(
+                        ConsensusAlgorithmAdapter::new(Arc::new(consensus)).with_cost_model(
+                            Arc::new(QuorumCollusionCost::new(nodes, quorum, quorum)),
+                        ),
+                    ),
+                    // Prepare and commit
+                    2,
+                )
+            }
+            ConsensusConfig::Gossip {
+                name,
+                rounds,
+                fanout,
+            } => (
+                label(name, "Gossip"),
+                Arc::new(
+                    ConsensusAlgorithmAdapter::new(Arc::new(gossip::GossipConsensus::new(
+                        node_id, *rounds, *fanout,
+                    )))
+                    .with_cost_model(Arc::new(SingleIdentityCost::default())),
+                ),
+                1,
+            ),
+            ConsensusConfig::Eventual {
+                name,
+                delay_ms,
+                threshold,
+            } => (
+                label(name, "Eventual"),
+                Arc::new(
+                    ConsensusAlgorithmAdapter::new(Arc::new(eventual::EventualConsensus::new(
+                        node_id, *delay_ms, *threshold,
+                    )))
+                    .with_cost_model(Arc::new(QuorumCollusionCost::new(
+                        nodes, *threshold, *threshold,
+                    ))),
+                ),
+                1,
+            ),
+            ConsensusConfig::Quorumless { name, threshold } => (
+                label(name, "Quorum-less"),
+                Arc::new(
+                    ConsensusAlgorithmAdapter::new(Arc::new(quorumless::QuorumlessConsensus::new(
+                        node_id, *threshold,
+                    )))
+                    .with_cost_model(Arc::new(StakeCost::new(*threshold))),
+                ),
+                1,
+            ),
+            ConsensusConfig::FlexiblePaxos { name, q1, q2 } => (
+                label(name, "Flexible Paxos"),
+                Arc::new(
+                    ConsensusAlgorithmAdapter::new(Arc::new(flexible_paxos::FlexiblePaxos::new(
+                        node_id, nodes, *q1, *q2,
+                    )))
+                    .with_cost_model(Arc::new(QuorumCollusionCost::new(nodes, *q1, *q2))),
+                ),
+                1,
+            ),
+            ConsensusConfig::Networked {
+                name,
+                nodes,
+                api_key,
+                quorum,
+                timeout_ms,
+            } => {
+                let api_key = api_key
+                    .clone()
+                    .or_else(|| std::env::var("BENCH_API_KEY").ok())
+                    .ok_or("networked strategy needs api_key or BENCH_API_KEY")?;
+                let mut strategy = NetworkedClusterStrategy::new(nodes.clone(), api_key);
+                if let Some(quorum) = quorum {
+                    strategy = strategy.with_quorum(*quorum);
+                }
+                if let Some(ms) = timeout_ms {
+                    strategy = strategy.with_timeout(Duration::from_millis(*ms));
+                }
+                (label(name, "Live Cluster"), Arc::new(strategy), 1)
+            }
+        })
+    }
+}
+
+/// Any `quorum` of the `nodes` equally weighted nodes
+fn vote_count_quorum(quorum: usize, nodes: usize) -> Result<Arc<dyn QuorumPolicy>, String> {
+    if quorum == 0 || quorum > nodes {
+        return Err(format!(
+            "pbft quorum {} is not between 1 and the {} nodes",
+            quorum, nodes
+        ));
+    }
+    // More than quorum - 1/2 of the weight is exactly `quorum` votes
+    let threshold = (quorum as f64 - 0.5) / nodes as f64;
+    Ok(Arc::new(
+        WeightedQuorum::new(HashMap::new())?.with_threshold(threshold)?,
+    ))
+}
+
+fn default_gossip_rounds() -> usize {
+    3
+}
+
+fn default_fanout() -> usize {
+    2
+}
+
+fn default_eventual_delay_ms() -> u64 {
+    500
+}
+
+fn default_eventual_threshold() -> usize {
+    2
+}
+
+fn default_weight_threshold() -> f64 {
+    5.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    /// Add `delay_ms` before the strategy sees the block
+    Delay,
+    /// The proposal is lost; the block is not committed
+    Drop,
+    /// The proposer cannot reach its peers; the strategy reports an error
+    Error,
+}
+
+/// A fault applied to a range of blocks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultConfig {
+    pub kind: FaultKind,
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// First affected block index; from the start when unset
+    pub from_block: Option<u64>,
+    /// Last affected block index; to the end when unset
+    pub to_block: Option<u64>,
+    /// Names of the affected strategies; all when unset
+    pub strategies: Option<Vec<String>>,
+}
+
+impl FaultConfig {
+    fn applies_to(&self, strategy: &str, block_index: u64) -> bool {
+        self.from_block.is_none_or(|from| block_index >= from)
+            && self.to_block.is_none_or(|to| block_index <= to)
+            && self
+                .strategies
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|name| name == strategy))
+    }
+}
+
+/// Storage backends to time the load stage on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backends: Vec<StorageBackend>,
+    /// Directory for the SQLite files; the system temp dir when unset
+    pub dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Print the metrics table to stdout
+    pub table: bool,
+    pub json: Option<String>,
+    pub csv: Option<String>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            table: true,
+            json: None,
+            csv: None,
+        }
+    }
+}
+
+/// A strategy with the label it is reported under
+pub type NamedStrategy = (String, Arc<dyn ConsensusStrategy>);
+
+/// Metrics of one strategy over one round of the workload
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioRun {
+    pub strategy: String,
+    pub round: usize,
+    pub metrics: ConsensusMetrics,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioReport {
+    pub scenario: String,
+    pub runs: Vec<ScenarioRun>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub storage: Vec<StorageMetrics>,
+}
+
+impl Scenario {
+    pub fn from_yaml(yaml: &str) -> Result<Self, Box<dyn Error>> {
+        let scenario: Scenario = serde_yaml::from_str(yaml)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read scenario {}: {}", path.display(), e))?;
+        Scenario::from_yaml(&yaml).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Reject settings that would only fail once the experiment is running
+    pub fn validate(&self) -> Result<(), String> {
+        if self.consensus.is_empty() {
+            return Err("no consensus strategies listed".to_string());
+        }
+        if self.nodes.count == 0 {
+            return Err("nodes.count must be at least 1".to_string());
+        }
+        if self.nodes.proposer >= self.nodes.count {
+            return Err(format!(
+                "nodes.proposer {} is not one of the {} nodes",
+                self.nodes.proposer, self.nodes.count
+            ));
+        }
+        if let Some(profile) = &self.nodes.profile {
+            if LatencyProfile::from_name(profile).is_none() {
+                return Err(format!("unknown network profile '{}'", profile));
+            }
+        }
+        if let Some(regions) = &self.nodes.regions {
+            if regions.len() != self.nodes.count {
+                return Err(format!(
+                    "{} regions given for {} nodes",
+                    regions.len(),
+                    self.nodes.count
+                ));
+            }
+        }
+        if self.workload.blocks == 0 || self.workload.rounds == 0 {
+            return Err("workload needs at least one block and one round".to_string());
+        }
+        if self.workload.assets.is_empty() {
+            return Err("workload.assets is empty".to_string());
+        }
+        for config in &self.consensus {
+            match config {
+                ConsensusConfig::FlexiblePaxos { q1, q2, .. } if q1 + q2 <= self.nodes.count => {
+                    return Err(format!(
+                        "flexible_paxos quorums {} + {} do not intersect over {} nodes",
+                        q1, q2, self.nodes.count
+                    ));
+                }
+                ConsensusConfig::Pbft {
+                    quorum: Some(quorum),
+                    ..
+                } => {
+                    vote_count_quorum(*quorum, self.nodes.count)?;
+                }
+                ConsensusConfig::Networked {
+                    nodes,
+                    quorum: Some(quorum),
+                    ..
+                } if *quorum == 0 || *quorum > nodes.len() => {
+                    return Err(format!(
+                        "networked quorum {} is not between 1 and the {} nodes",
+                        quorum,
+                        nodes.len()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The simulated network, when a profile is configured
+    pub fn network_model(&self) -> Option<NetworkModel> {
+        let profile = LatencyProfile::from_name(self.nodes.profile.as_deref()?)?;
+        Some(match &self.nodes.regions {
+            Some(regions) => NetworkModel::new(profile, regions.clone()),
+            None => NetworkModel::with_default_regions(profile, self.nodes.count),
+        })
+    }
+
+    /// A hash-linked chain of `workload.blocks` blocks
+    pub fn blocks(&self) -> Vec<Block> {
+        let workload = &self.workload;
+        let start = chrono::Utc::now().timestamp_millis();
+        let mut blocks: Vec<Block> = Vec::with_capacity(workload.blocks);
+
+        for i in 1..=workload.blocks {
+            let timestamp = start + i as i64;
+            let price = price::from_f32(workload.base_price + workload.price_step * i as f32)
+                .unwrap_or_default();
+            let mut block = Block {
+                index: i as u64,
+                timestamp,
+                data: workload
+                    .assets
+                    .iter()
+                    .map(|asset| MarketData {
+                        asset: asset.clone(),
+                        price,
+                        source: format!("scenario:{}", self.name),
+                        timestamp,
+                        provenance: None,
+                        indicators: None,
+                        conversion: None,
+                        bucket: None,
+                    })
+                    .collect(),
+                previous_hash: blocks
+                    .last()
+                    .map_or_else(|| "0000_genesis".to_string(), |b| b.hash.clone()),
+                hash: String::new(),
+                nonce: 0,
+                format_version: BLOCK_FORMAT_VERSION,
+                fees: Vec::new(),
+                divergences: Vec::new(),
+                hlc: None,
+                annotations: Default::default(),
+                order_books: Vec::new(),
+            };
+            block.calculate_hash_with_nonce();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    /// Build every configured strategy, wrapped in the simulated network and
+    /// the fault injector
+    ///
+    /// Strategies keep their state between calls, so each round builds them
+    /// afresh.
+    pub fn strategies(&self) -> Result<Vec<NamedStrategy>, String> {
+        let model = self.network_model();
+        self.consensus
+            .iter()
+            .map(|config| {
+                let (name, strategy, phases) = config.build(&self.nodes)?;
+                let strategy = match &model {
+                    Some(model) if !matches!(config, ConsensusConfig::Networked { .. }) => {
+                        Arc::new(
+                            SimulatedNetworkStrategy::new(strategy, model.clone())
+                                .with_proposer(self.nodes.proposer)
+                                .with_phases(phases),
+                        )
+                    }
+                    _ => strategy,
+                };
+                let faults: Vec<FaultConfig> = self
+                    .faults
+                    .iter()
+                    .filter(|fault| {
+                        fault
+                            .strategies
+                            .as_ref()
+                            .is_none_or(|names| names.contains(&name))
+                    })
+                    .cloned()
+                    .collect();
+                let strategy: Arc<dyn ConsensusStrategy> = if faults.is_empty() {
+                    strategy
+                } else {
+                    Arc::new(FaultInjectingStrategy::new(strategy, name.clone(), faults))
+                };
+                Ok((name, strategy))
+            })
+            .collect()
+    }
+
+    /// Benchmark every strategy for `workload.rounds` rounds
+    pub async fn run(&self) -> Result<ScenarioReport, Box<dyn Error>> {
+        let blocks = self.blocks();
+        let mut runs = Vec::new();
+
+        for round in 1..=self.workload.rounds {
+            for (name, strategy) in self.strategies()? {
+                let metrics = benchmark_consensus_strategy(strategy, &blocks).await;
+                runs.push(ScenarioRun {
+                    strategy: name,
+                    round,
+                    metrics,
+                });
+            }
+        }
+
+        let mut storage = Vec::new();
+        if let Some(config) = &self.storage {
+            let dir = config
+                .dir
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir);
+            for backend in &config.backends {
+                match benchmark_storage(backend, &blocks, &dir).await {
+                    Ok(metrics) => storage.push(metrics),
+                    Err(e) => warn!(
+                        backend = backend.name(),
+                        error = %e,
+                        "Scenario: Storage backend skipped"
+                    ),
+                }
+            }
+        }
+
+        Ok(ScenarioReport {
+            scenario: self.name.clone(),
+            runs,
+            storage,
+        })
+    }
+
+    /// Write `report` to the configured outputs
+    pub fn write_outputs(&self, report: &ScenarioReport) -> Result<(), Box<dyn Error>> {
+        if self.outputs.table {
+            let metrics: Vec<ConsensusMetrics> = report
+                .runs
+                .iter()
+                .map(|run| ConsensusMetrics {
+                    strategy_name: format!("{} #{}", run.strategy, run.round),
+                    ..run.metrics.clone()
+                })
+                .collect();
+            print_metrics_comparison(&metrics);
+            if !report.storage.is_empty() {
+                print_storage_comparison(&report.storage);
+            }
+        }
+        if let Some(path) = &self.outputs.json {
+            write_file(path, &serde_json::to_string_pretty(report)?)?;
+        }
+        if let Some(path) = &self.outputs.csv {
+            write_file(path, &report.to_csv())?;
+        }
+        Ok(())
+    }
+}
+
+impl ScenarioReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "strategy,mode,round,total_blocks,committed_blocks,failed_blocks,error_blocks,\
+             min_latency_ms,max_latency_ms,avg_latency_ms,throughput_blocks_per_sec,\
+             error_rate,commit_rate\n",
+        );
+        for run in &self.runs {
+            let m = &run.metrics;
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.2},{:.2}\n",
+                run.strategy,
+                m.mode.name(),
+                run.round,
+                m.total_blocks,
+                m.committed_blocks,
+                m.failed_blocks,
+                m.error_blocks,
+                m.min_latency_ms,
+                m.max_latency_ms,
+                m.avg_latency_ms,
+                m.throughput_blocks_per_sec,
+                m.error_rate,
+                m.commit_rate
+            ));
+        }
+        csv
+    }
+}
+
+fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+/// Applies a scenario's faults to the blocks a strategy sees
+pub struct FaultInjectingStrategy {
+    inner: Arc<dyn ConsensusStrategy>,
+    /// Label the faults select on, which may differ from `inner.name()`
+    label: String,
+    faults: Vec<FaultConfig>,
+}
+
+impl FaultInjectingStrategy {
+    pub fn new(inner: Arc<dyn ConsensusStrategy>, label: String, faults: Vec<FaultConfig>) -> Self {
+        FaultInjectingStrategy {
+            inner,
+            label,
+            faults,
+        }
+    }
+}
+
+#[async_trait]
+impl ConsensusStrategy for FaultInjectingStrategy {
+    async fn execute(&self, block: &Block) -> Result<Option<Block>, ConsensusError> {
+        let active = self
+            .faults
+            .iter()
+            .filter(|fault| fault.applies_to(&self.label, block.index));
+        let mut delay = 0;
+        for fault in active {
+            match fault.kind {
+                FaultKind::Delay => delay += fault.delay_ms,
+                FaultKind::Drop => return Ok(None),
+                FaultKind::Error => {
+                    return Err(ConsensusError::Network(format!(
+                        "injected fault at block {}",
+                        block.index
+                    )))
+                }
+            }
+        }
+        if delay > 0 {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+        self.inner.execute(block).await
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn requirements(&self) -> ConsensusRequirements {
+        self.inner.requirements()
+    }
+
+    fn is_committed(&self, block_index: u64) -> bool {
+        self.inner.is_committed(block_index)
+    }
+
+    fn geographical_diversity(&self) -> Option<f64> {
+        self.inner.geographical_diversity()
+    }
+
+    fn cost_of_attack(&self) -> Option<f64> {
+        self.inner.cost_of_attack()
+    }
+
+    fn stale_blocks(&self) -> Option<usize> {
+        self.inner.stale_blocks()
+    }
+
+    fn benchmark_mode(&self) -> BenchmarkMode {
+        self.inner.benchmark_mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+name: faults
+nodes:
+  count: 3
+workload:
+  blocks: 10
+  rounds: 2
+  assets: [BTC, ETH]
+consensus:
+  - algorithm: no_consensus
+  - algorithm: simple_majority
+    name: Majority
+faults:
+  - kind: drop
+    from_block: 3
+    to_block: 4
+    strategies: [Majority]
+  - kind: error
+    from_block: 10
+outputs:
+  table: false
+"#;
+
+    #[tokio::test]
+    async fn test_scenario_runs_strategies_with_faults() {
+        let scenario = Scenario::from_yaml(SCENARIO).unwrap();
+        let blocks = scenario.blocks();
+        assert_eq!(blocks.len(), 10);
+        assert_eq!(blocks[1].previous_hash, blocks[0].hash);
+        assert_eq!(blocks[0].data.len(), 2);
+
+        let report = scenario.run().await.unwrap();
+        assert_eq!(report.runs.len(), 4);
+
+        let no_consensus = &report.runs[0];
+        assert_eq!(no_consensus.strategy, "No-Consensus");
+        assert_eq!(no_consensus.metrics.committed_blocks, 9);
+        assert_eq!(no_consensus.metrics.error_blocks, 1);
+
+        let majority = &report.runs[1];
+        assert_eq!(majority.strategy, "Majority");
+        assert_eq!(majority.metrics.failed_blocks, 2);
+        assert_eq!(majority.metrics.error_blocks, 1);
+        assert_eq!(report.runs[3].round, 2);
+
+        let csv = report.to_csv();
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("Majority,simulated,1,10,7,2,1,"));
+    }
+
+    #[test]
+    fn test_scenario_rejects_invalid_files() {
+        let invalid = |yaml: &str| Scenario::from_yaml(yaml).unwrap_err().to_string();
+
+        assert!(invalid("name: x\nconsensus: []\n").contains("no consensus strategies"));
+        assert!(invalid("name: x\nconsensus:\n  - algorithm: raft\n").contains("raft"));
+        assert!(invalid(
+            "name: x\nnodes: { count: 4, profile: moon }\nconsensus:\n  - algorithm: pbft\n"
+        )
+        .contains("unknown network profile"));
+        assert!(invalid(
+            "name: x\nconsensus:\n  - algorithm: flexible_paxos\n    q1: 2\n    q2: 2\n"
+        )
+        .contains("do not intersect"));
+        assert!(
+            invalid("name: x\nconsensus:\n  - algorithm: pbft\n    quorom: 3\n").contains("quorom")
+        );
+        assert!(
+            invalid("name: x\nconsensus:\n  - algorithm: pbft\n    quorum: 5\n")
+                .contains("pbft quorum 5")
+        );
+    }
+
+    #[test]
+    fn test_pbft_quorum_counts_votes() {
+        let policy = vote_count_quorum(2, 4).unwrap();
+        assert!(!policy.is_quorum(&[3], 4));
+        assert!(policy.is_quorum(&[0, 3], 4));
+        assert!(vote_count_quorum(4, 4).unwrap().is_quorum(&[0, 1, 2, 3], 4));
+        assert!(!vote_count_quorum(4, 4).unwrap().is_quorum(&[0, 1, 2], 4));
+        assert!(vote_count_quorum(0, 4).is_err());
+    }
+}
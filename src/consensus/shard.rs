@@ -0,0 +1,118 @@
+//! Independent PBFT instances per shard
+//!
+//! A node can run one `PBFTManager` per shard (e.g. per asset), each with its
+//! own sequence space (see `PBFTManager::sequence_for`) and a primary
+//! rotation offset by the shard's position, next to the default unsharded
+//! instance. `ShardRouter` hands incoming messages to the instance named by
+//! `PBFTMessage::shard`.
+//!
+//! Shards are configured with `PBFT_SHARDS`, a comma-separated list of shard
+//! ids; a block whose entries all belong to one shard's asset is ordered by
+//! that shard, everything else by the default instance.
+
+use crate::consensus::algorithms::{PBFTManager, PBFTMessage};
+use crate::etl::Block;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Dispatches PBFT messages to per-shard instances
+pub struct ShardRouter {
+    default: Arc<PBFTManager>,
+    shards: BTreeMap<String, Arc<PBFTManager>>,
+}
+
+impl ShardRouter {
+    pub fn new(default: Arc<PBFTManager>) -> Self {
+        ShardRouter {
+            default,
+            shards: BTreeMap::new(),
+        }
+    }
+
+    /// Build one instance per shard id, offsetting each shard's primary
+    /// rotation by its position in `shards`
+    pub fn with_shards(
+        default: Arc<PBFTManager>,
+        shards: &[String],
+        build: impl Fn() -> PBFTManager,
+    ) -> Self {
+        let mut router = ShardRouter::new(default);
+        for (i, shard) in shards.iter().enumerate() {
+            let manager = build().with_shard(shard.clone(), i + 1);
+            router.shards.insert(shard.clone(), Arc::new(manager));
+        }
+        router
+    }
+
+    pub fn default_instance(&self) -> &Arc<PBFTManager> {
+        &self.default
+    }
+
+    pub fn shard(&self, shard: &str) -> Option<&Arc<PBFTManager>> {
+        self.shards.get(shard)
+    }
+
+    pub fn shard_ids(&self) -> impl Iterator<Item = &str> {
+        self.shards.keys().map(String::as_str)
+    }
+
+    /// The instance a message belongs to; `None` for an unknown shard
+    pub fn route(&self, msg: &PBFTMessage) -> Option<&Arc<PBFTManager>> {
+        match &msg.shard {
+            None => Some(&self.default),
+            Some(shard) => self.shards.get(shard),
+        }
+    }
+
+    /// Hand a message to its instance; messages for unknown shards are dropped
+    pub fn handle_message(&self, msg: &PBFTMessage) -> bool {
+        match self.route(msg) {
+            Some(manager) => manager.handle_message(msg),
+            None => {
+                warn!(
+                    shard = ?msg.shard,
+                    node_id = msg.node_id,
+                    "Shard: Dropping message for unknown shard"
+                );
+                false
+            }
+        }
+    }
+
+    /// Shard id for an asset, if it has its own shard
+    pub fn shard_for_asset(&self, asset: &str) -> Option<&str> {
+        self.shards
+            .keys()
+            .find(|shard| shard.eq_ignore_ascii_case(asset))
+            .map(String::as_str)
+    }
+
+    /// The instance that orders `block`: its asset's shard when every entry
+    /// is for that asset, otherwise the default instance
+    pub fn instance_for_block(&self, block: &Block) -> &Arc<PBFTManager> {
+        let mut assets = block.data.iter().map(|d| d.asset.as_str());
+        let shard = match assets.next() {
+            Some(first) if assets.all(|a| a.eq_ignore_ascii_case(first)) => {
+                self.shard_for_asset(first)
+            }
+            _ => None,
+        };
+        shard
+            .and_then(|s| self.shards.get(s))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Shard ids from `PBFT_SHARDS`; empty when sharding is off
+pub fn shards_from_env() -> Vec<String> {
+    std::env::var("PBFT_SHARDS")
+        .map(|spec| {
+            spec.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_uppercase)
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -4,7 +4,10 @@
 mod consensus_tests {
     use crate::consensus::algorithms::*;
     use crate::consensus::*;
+    use crate::etl::price::Decimal;
     use crate::etl::{Block, MarketData};
+    use crate::network::protocol::PROTOCOL_VERSION;
+    use crate::testing;
     use std::sync::Arc;
     use tokio::time::Duration;
 
@@ -24,25 +27,18 @@ mod consensus_tests {
     }
 
     fn create_test_block(index: u64) -> Block {
-        let mut block = Block {
-            index,
-            timestamp: chrono::Utc::now().timestamp(),
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0 + index as f32,
-                source: "Test".to_string(),
-                timestamp: chrono::Utc::now().timestamp(),
-            }],
-            previous_hash: if index == 1 {
-                "0000_genesis".to_string()
-            } else {
-                format!("hash_{}", index - 1)
-            },
-            hash: String::new(),
-            nonce: 0,
+        let previous_hash = if index == 1 {
+            "0000_genesis".to_string()
+        } else {
+            format!("hash_{}", index - 1)
         };
-        block.calculate_hash_with_nonce();
-        block
+        let price = Decimal::from(50000) + Decimal::from(index);
+        let now = chrono::Utc::now().timestamp_millis();
+        testing::block(
+            index,
+            &previous_hash,
+            vec![testing::entry("BTC", "Test", price, now)],
+        )
     }
 
     #[tokio::test]
@@ -92,8 +88,12 @@ mod consensus_tests {
         let result = consensus.propose(&block).await.unwrap();
 
         match result {
-            ConsensusResult::Pending => {
-                // Expected - need more votes
+            ConsensusResult::Pending(details) => {
+                // Two of the three units of weight needed
+                let stalled = details.stalled_phase().unwrap();
+                assert_eq!(stalled.phase, "weight");
+                assert_eq!(details.missing_votes(), Some(1.0));
+                assert!(details.to_string().starts_with("weight 2/3 after"));
             }
             _ => panic!("Expected pending result, got {:?}", result),
         }
@@ -115,6 +115,664 @@ mod consensus_tests {
         assert_eq!(req.min_nodes, None);
     }
 
+    fn assert_send_sync_static<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_consensus_traits_are_thread_safe() {
+        assert_send_sync_static::<Arc<dyn ConsensusAlgorithm>>();
+        assert_send_sync_static::<Arc<dyn ConsensusStrategy>>();
+        assert_send_sync_static::<ConsensusError>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_propose_from_spawned_tasks() {
+        init();
+        let algorithms: Vec<Arc<dyn ConsensusAlgorithm>> = vec![
+            Arc::new(gossip::GossipConsensus::new(0, 1, 2)),
+            Arc::new(eventual::EventualConsensus::new(0, 10, 1)),
+            Arc::new(quorumless::QuorumlessConsensus::new(0, 1.0)),
+            Arc::new(flexible_paxos::FlexiblePaxos::new(0, 3, 2, 2)),
+        ];
+
+        let handles: Vec<_> = algorithms
+            .into_iter()
+            .map(|algorithm| {
+                tokio::spawn(async move {
+                    let block = create_test_block(1);
+                    algorithm.propose(&block).await
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let result = handle.await.unwrap();
+            assert!(matches!(result, Ok(ConsensusResult::Committed(_))));
+        }
+    }
+
+    #[test]
+    fn test_consensus_error_display() {
+        let err = ConsensusError::from(serde_json::from_str::<Block>("{").unwrap_err());
+        assert!(err.to_string().starts_with("Serialization error:"));
+        assert_eq!(
+            ConsensusError::Network("peer down".to_string()).to_string(),
+            "Network error: peer down"
+        );
+    }
+
+    #[test]
+    fn test_network_model_latency_and_diversity() {
+        use crate::consensus::network_model::{LatencyProfile, NetworkModel};
+
+        let lan = NetworkModel::with_default_regions(LatencyProfile::Lan, 4);
+        assert_eq!(lan.geographical_diversity(), 0.0);
+        assert_eq!(lan.quorum_round_trip_ms(0, 3), 0.4);
+
+        let wan = NetworkModel::with_default_regions(LatencyProfile::CrossContinent, 4);
+        assert!((wan.geographical_diversity() - 1.0).abs() < 1e-9);
+        assert_eq!(wan.link_latency_ms(0, 1), 90.0);
+        assert_eq!(wan.quorum_round_trip_ms(0, 1), 0.0);
+        assert_eq!(wan.quorum_round_trip_ms(0, 3), 180.0);
+
+        // Two regions with two nodes each sit between the extremes
+        let split = NetworkModel::new(
+            LatencyProfile::SameRegionCloud,
+            vec!["a".into(), "a".into(), "b".into(), "b".into()],
+        );
+        assert!((split.geographical_diversity() - 0.5).abs() < 1e-9);
+        // The closest peer shares our zone; the second closest does not
+        assert_eq!(split.quorum_round_trip_ms(0, 2), 1.0);
+        assert_eq!(split.quorum_round_trip_ms(0, 3), 3.0);
+
+        assert_eq!(
+            LatencyProfile::from_name("cross-continent"),
+            Some(LatencyProfile::CrossContinent)
+        );
+        assert_eq!(LatencyProfile::from_name("moon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_simulated_network_feeds_geographical_diversity() {
+        use crate::consensus::network_model::{
+            LatencyProfile, NetworkModel, SimulatedNetworkStrategy,
+        };
+
+        let inner: Arc<dyn ConsensusStrategy> = Arc::new(NoConsensusStrategy::new());
+        let plain = benchmark_consensus_strategy(inner.clone(), &[create_test_block(1)]).await;
+        assert_eq!(plain.geographical_diversity, None);
+
+        let model = NetworkModel::with_default_regions(LatencyProfile::SameRegionCloud, 3);
+        let simulated: Arc<dyn ConsensusStrategy> =
+            Arc::new(SimulatedNetworkStrategy::new(inner, model));
+        let metrics = benchmark_consensus_strategy(simulated, &[create_test_block(1)]).await;
+
+        assert_eq!(metrics.committed_blocks, 1);
+        assert!((metrics.geographical_diversity.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cost_models_follow_parameters() {
+        use crate::consensus::cost_model::*;
+
+        // PBFT with 3-of-4 quorums: any two quorums share 2 nodes
+        let pbft = QuorumCollusionCost::new(4, 3, 3);
+        assert_eq!(pbft.colluding_identities(), 2);
+        assert_eq!(pbft.with_identity_weight(10.0).cost_of_attack(), 20.0);
+
+        // Non-intersecting quorums still need one identity to propose
+        assert_eq!(QuorumCollusionCost::new(4, 2, 2).cost_of_attack(), 1.0);
+
+        assert_eq!(StakeCost::new(5.0).cost_of_attack(), 5.0);
+
+        let easy = HashPowerCost::for_difficulty(1).cost_of_attack();
+        let hard = HashPowerCost::for_difficulty(2).cost_of_attack();
+        assert!((hard / easy - 16.0).abs() < 1e-9);
+        assert!(easy > 16.0);
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_reports_cost_of_attack() {
+        use crate::consensus::cost_model::QuorumCollusionCost;
+
+        let plain: Arc<dyn ConsensusStrategy> = Arc::new(ConsensusAlgorithmAdapter::new(Arc::new(
+            gossip::GossipConsensus::new(0, 4, 2),
+        )));
+        let metrics = benchmark_consensus_strategy(plain, &[create_test_block(1)]).await;
+        assert_eq!(metrics.cost_of_attack, None);
+
+        let modelled: Arc<dyn ConsensusStrategy> = Arc::new(
+            ConsensusAlgorithmAdapter::new(Arc::new(gossip::GossipConsensus::new(0, 4, 2)))
+                .with_cost_model(Arc::new(QuorumCollusionCost::new(4, 3, 3))),
+        );
+        let metrics = benchmark_consensus_strategy(modelled, &[create_test_block(1)]).await;
+        assert_eq!(metrics.cost_of_attack, Some(2.0));
+
+        let majority: Arc<dyn ConsensusStrategy> = Arc::new(SimpleMajorityStrategy::new(0, 5));
+        assert_eq!(majority.cost_of_attack(), Some(1.0));
+    }
+
+    fn child_of(parent: &Block, index: u64, nonce: u64) -> Block {
+        let mut block = create_test_block(index);
+        block.previous_hash = parent.hash.clone();
+        block.nonce = nonce;
+        block.hash = block.calculate_hash();
+        block
+    }
+
+    #[test]
+    fn test_fork_tree_longest_chain_wins() {
+        use crate::consensus::fork_choice::ForkTree;
+
+        let genesis = create_test_block(1);
+        let ours = child_of(&genesis, 2, 1);
+        let theirs = child_of(&genesis, 2, 2);
+        let theirs_next = child_of(&theirs, 3, 0);
+
+        let mut tree = ForkTree::new();
+        assert_eq!(tree.insert(&genesis, false), Ok(true));
+        assert_eq!(tree.insert(&ours, true), Ok(true));
+        assert_eq!(tree.insert(&ours, true), Ok(false));
+
+        // Ties keep the first tip
+        tree.insert(&theirs, false).unwrap();
+        assert_eq!(tree.canonical_tip(), Some(ours.hash.as_str()));
+        assert_eq!(tree.stale_local_blocks(), 0);
+
+        tree.insert(&theirs_next, false).unwrap();
+        assert_eq!(tree.canonical_tip(), Some(theirs_next.hash.as_str()));
+        assert!(!tree.is_canonical(&ours.hash));
+        assert!(tree.is_canonical(&genesis.hash));
+        assert_eq!(tree.canonical_chain().len(), 3);
+        assert_eq!(tree.stale_local_blocks(), 1);
+    }
+
+    #[test]
+    fn test_fork_tree_refuses_reorgs_past_finality_window() {
+        use crate::consensus::fork_choice::ForkTree;
+
+        let genesis = create_test_block(1);
+        let mut ours = vec![genesis.clone()];
+        for index in 2..=4 {
+            ours.push(child_of(ours.last().unwrap(), index, 1));
+        }
+        let mut tree = ForkTree::new().with_max_reorg_depth(2);
+        for block in &ours {
+            tree.insert(block, true).unwrap();
+        }
+        assert_eq!(tree.finalized_height(), Some(2));
+        assert!(tree.is_final(&ours[1].hash));
+        assert!(!tree.is_final(&ours[2].hash));
+
+        // A branch from block 2 abandons blocks 3 and 4: within the window
+        let short = child_of(&ours[1], 3, 2);
+        let short_next = child_of(&short, 4, 0);
+        let short_tip = child_of(&short_next, 5, 0);
+        for block in [&short, &short_next, &short_tip] {
+            tree.insert(block, false).unwrap();
+        }
+        assert_eq!(tree.canonical_tip(), Some(short_tip.hash.as_str()));
+        assert!(tree.refused_reorgs().is_empty());
+
+        // A branch from genesis would abandon four blocks and is rejected
+        // however long it grows
+        let mut long = vec![child_of(&genesis, 2, 3)];
+        for index in 3..=7 {
+            long.push(child_of(long.last().unwrap(), index, 0));
+        }
+        let rejected: Vec<String> = long
+            .iter()
+            .filter_map(|block| tree.insert(block, false).err())
+            .map(|refused| refused.hash)
+            .collect();
+        assert_eq!(rejected, vec![long[4].hash.clone(), long[5].hash.clone()]);
+        assert!(tree.insert(&long[4], false).is_err());
+        assert_eq!(tree.canonical_tip(), Some(short_tip.hash.as_str()));
+        assert_eq!(tree.refused_reorgs().len(), 2);
+        assert_eq!(tree.refused_reorgs()[0].hash, long[4].hash);
+        assert_eq!(tree.refused_reorgs()[0].depth, 4);
+        assert!(tree.is_final(&short.hash));
+        assert!(!tree.is_final(&long[0].hash));
+
+        // Without a window the longest chain always wins
+        let mut unbounded = ForkTree::new();
+        for block in ours.iter().chain(&long) {
+            unbounded.insert(block, false).unwrap();
+        }
+        assert_eq!(unbounded.canonical_tip(), Some(long[5].hash.as_str()));
+        assert_eq!(unbounded.finalized_height(), None);
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_reports_stale_blocks() {
+        use crate::consensus::fork_choice::ForkTrackingStrategy;
+
+        let genesis = create_test_block(1);
+        let ours = child_of(&genesis, 2, 1);
+
+        let tracked = Arc::new(ForkTrackingStrategy::new(Arc::new(
+            NoConsensusStrategy::new(),
+        )));
+        let strategy: Arc<dyn ConsensusStrategy> = tracked.clone();
+
+        let metrics =
+            benchmark_consensus_strategy(strategy.clone(), &[genesis.clone(), ours.clone()]).await;
+        assert_eq!(metrics.stale_block_rate, 0.0);
+
+        // A peer's longer branch orphans our block at index 2
+        let theirs = child_of(&genesis, 2, 2);
+        tracked.observe_peer_block(&theirs).unwrap();
+        tracked
+            .observe_peer_block(&child_of(&theirs, 3, 0))
+            .unwrap();
+        assert_eq!(strategy.stale_blocks(), Some(1));
+
+        let metrics = benchmark_consensus_strategy(strategy, &[genesis, ours]).await;
+        assert_eq!(metrics.stale_block_rate, 50.0);
+
+        let untracked: Arc<dyn ConsensusStrategy> = Arc::new(NoConsensusStrategy::new());
+        let metrics = benchmark_consensus_strategy(untracked, &[create_test_block(1)]).await;
+        assert_eq!(metrics.stale_block_rate, 0.0);
+    }
+
+    #[test]
+    fn test_quorum_policies() {
+        use crate::consensus::quorum::*;
+        use std::collections::HashMap;
+
+        assert!(ClassicQuorum.is_quorum(&[0, 1, 2], 4));
+        assert!(!ClassicQuorum.is_quorum(&[0, 1], 4));
+
+        // Node 0 carries 7 of 10 weight; nodes 1, 2 and 3 together only 3
+        let weighted = WeightedQuorum::new(HashMap::from([(0, 7.0)])).unwrap();
+        assert!(weighted.is_quorum(&[0], 4));
+        assert!(!weighted.is_quorum(&[1, 2, 3], 4));
+
+        // 3x3 grid: rows {0,1,2} {3,4,5} {6,7,8}
+        let grid = GridQuorum::new(3, 3).unwrap();
+        assert!(grid.is_quorum(&[0, 1, 2, 4, 8], 9));
+        assert!(!grid.is_quorum(&[0, 1, 2, 4], 9));
+        assert!(!grid.is_quorum(&[0, 3, 6, 4, 8], 9));
+        // The grid must lay out exactly the cluster
+        assert!(!grid.is_quorum(&[0, 1, 2, 4, 8], 12));
+        assert!(grid.check(9).is_ok());
+        assert!(grid.check(12).is_err());
+        assert!(GridQuorum::new(0, 3).is_err());
+        assert!(WeightedQuorum::new(HashMap::from([(1, -1.0)])).is_err());
+        assert!(WeightedQuorum::new(HashMap::from([(1, f64::NAN)])).is_err());
+        assert!(WeightedQuorum::new(HashMap::new())
+            .unwrap()
+            .with_threshold(1.5)
+            .is_err());
+        assert!(WeightedQuorum::new(HashMap::from([(0, 0.0), (1, 0.0)]))
+            .unwrap()
+            .check(2)
+            .is_err());
+
+        assert_eq!(parse_policy("classic").unwrap().name(), "classic");
+        assert_eq!(parse_policy("grid:2x2").unwrap().name(), "grid");
+        let parsed = parse_policy("weighted:0.5:0=4").unwrap();
+        assert!(parsed.is_quorum(&[0], 4));
+        assert!(parse_policy("grid:2").is_err());
+        assert!(parse_policy("weighted:0=x").is_err());
+        assert!(parse_policy("weighted:0=-2").is_err());
+        assert!(parse_policy("weighted:0:0=1").is_err());
+        assert!(parse_policy("grid:0x4").is_err());
+        assert!(parse_policy("raft").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failure_domain_quorums() {
+        use crate::consensus::quorum::*;
+
+        // Nodes 0-2 share a zone; node 3 is in another zone of the same region
+        let tags = "0=us-east/a/r1, 1=us-east/a/r2, 2=us-east/a/r1, 3=us-east/b";
+        let domains = FailureDomains::parse(tags, None).unwrap();
+        assert_eq!(domains.domain_of(1), Some("us-east/a"));
+        assert_eq!(domains.domain_of(3), Some("us-east/b"));
+        let policy = DomainQuorum::new(Arc::new(ClassicQuorum), domains.clone());
+        assert_eq!(policy.name(), "classic+domains");
+        // 3 of 4 votes is a classic quorum, but not from one zone alone
+        assert!(ClassicQuorum.is_quorum(&[0, 1, 2], 4));
+        assert!(!policy.is_quorum(&[0, 1, 2], 4));
+        assert!(policy.is_quorum(&[0, 1, 3], 4));
+
+        let racks = FailureDomains::parse(tags, Some("rack:3")).unwrap();
+        assert!(racks.spans(&[0, 1, 3]) && !racks.spans(&[0, 2, 3]));
+        assert!(FailureDomains::parse(tags, Some("region:2"))
+            .unwrap()
+            .check(4)
+            .is_err());
+        assert!(FailureDomains::parse("0=", None).is_err());
+        assert!(FailureDomains::parse(tags, Some("continent:2")).is_err());
+
+        // Every Paxos acceptor sits in one zone, so no phase can complete
+        let one_zone = FailureDomains::parse("0=eu/a,1=eu/a,2=eu/a", None).unwrap();
+        let paxos = flexible_paxos::FlexiblePaxos::new(0, 3, 2, 2).with_failure_domains(one_zone);
+        let block = create_test_block(1);
+        assert!(matches!(
+            paxos.propose(&block).await,
+            Ok(ConsensusResult::Pending(_))
+        ));
+        let spread = FailureDomains::parse("0=eu/a,1=eu/b,2=eu/c", Some("3")).unwrap();
+        let paxos = flexible_paxos::FlexiblePaxos::new(0, 3, 2, 2).with_failure_domains(spread);
+        assert!(matches!(
+            paxos.propose(&block).await,
+            Ok(ConsensusResult::Committed(_))
+        ));
+    }
+
+    #[test]
+    fn test_pbft_manager_consults_quorum_policy() {
+        use crate::consensus::quorum::GridQuorum;
+
+        let commit = |node_id: usize| PBFTMessage {
+            msg_type: MessageType::Commit,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: None,
+            node_id,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+
+        // On a 4x4 grid of 16 nodes, a full row plus one node from each other
+        // row is 7 votes, where classic PBFT would need 11
+        let pbft = PBFTManager::new(0, 16, Vec::new())
+            .with_quorum_policy(Arc::new(GridQuorum::new(4, 4).unwrap()));
+        assert_eq!(pbft.quorum_policy().name(), "grid");
+        for node in [0, 1, 2, 3, 4, 8] {
+            assert!(!pbft.handle_commit(&commit(node)));
+        }
+        assert!(!pbft.is_committed(1));
+        assert!(pbft.handle_commit(&commit(12)));
+        assert!(pbft.is_committed(1));
+    }
+
+    #[test]
+    fn test_observers_never_vote_or_propose() {
+        let message = |msg_type: MessageType, node_id: usize, block: &Block| PBFTMessage {
+            msg_type,
+            view: 0,
+            sequence: block.index,
+            block_hash: block.content_id(),
+            block_data_json: Some(serde_json::to_string(block).unwrap()),
+            node_id,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+
+        // Nodes 0-3 vote, 4 and 5 observe: quorum is 3 of the 4 voters
+        let observer = PBFTManager::new(4, 6, Vec::new()).with_observers([4, 5]);
+        assert!(observer.observes_only());
+        assert_eq!(observer.voting_members(), vec![0, 1, 2, 3]);
+        assert!((0..8).all(|sequence| !observer.is_primary(sequence)));
+        assert_eq!(observer.primary_for(5), Some(1));
+
+        let block = create_test_block(5);
+        assert!(!observer.handle_message(&message(MessageType::Commit, 4, &block)));
+        assert!(!observer.handle_message(&message(MessageType::Commit, 5, &block)));
+        assert!(!observer.handle_message(&message(MessageType::Commit, 0, &block)));
+        assert!(!observer.handle_message(&message(MessageType::Commit, 1, &block)));
+        assert!(!observer.is_committed(5));
+        assert!(observer.handle_message(&message(MessageType::Commit, 2, &block)));
+        assert!(observer.is_committed(5));
+
+        // Observers audit pre-prepares: only the primary, with matching content
+        assert!(observer
+            .validate_pre_prepare(&message(MessageType::PrePrepare, 1, &block))
+            .is_ok());
+        assert!(!observer.handle_message(&message(MessageType::PrePrepare, 2, &block)));
+        let mut tampered = message(MessageType::PrePrepare, 1, &block);
+        tampered.block_hash = "forged".to_string();
+        assert!(observer.validate_pre_prepare(&tampered).is_err());
+
+        // Without observers the rotation covers every node as before
+        let voter = PBFTManager::new(1, 4, Vec::new());
+        assert!(voter.is_primary(5));
+        assert_eq!(voter.voting_members().len(), 4);
+    }
+
+    #[test]
+    fn test_shard_router_isolates_instances() {
+        use crate::consensus::shard::ShardRouter;
+
+        let shards = vec!["BTC".to_string(), "ETH".to_string()];
+        let router = ShardRouter::with_shards(
+            Arc::new(PBFTManager::new(0, 4, Vec::new())),
+            &shards,
+            || PBFTManager::new(0, 4, Vec::new()),
+        );
+        assert_eq!(router.shard_ids().collect::<Vec<_>>(), vec!["BTC", "ETH"]);
+
+        // Each shard rotates its primary from a different offset
+        assert!(router.default_instance().is_primary(4));
+        assert!(router.shard("BTC").unwrap().is_primary(3));
+        assert!(router.shard("ETH").unwrap().is_primary(2));
+
+        let commit = |node_id: usize, shard: Option<&str>| PBFTMessage {
+            msg_type: MessageType::Commit,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: None,
+            node_id,
+            timestamp: 1_234_567_890_000,
+            shard: shard.map(str::to_string),
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+        for node in 0..3 {
+            router.handle_message(&commit(node, Some("BTC")));
+        }
+        assert!(!router.handle_message(&commit(0, Some("DOGE"))));
+
+        // Sequence 1 committed on BTC only
+        assert!(router.shard("BTC").unwrap().is_committed(1));
+        assert!(!router.shard("ETH").unwrap().is_committed(1));
+        assert!(!router.default_instance().is_committed(1));
+
+        // Shards number their own blocks; the default instance follows the
+        // chain index
+        let btc = router.shard("BTC").unwrap();
+        assert_eq!(btc.sequence_for(&create_test_block(7)), 2);
+        assert_eq!(btc.sequence_for(&create_test_block(8)), 3);
+        assert_eq!(
+            router
+                .shard("ETH")
+                .unwrap()
+                .sequence_for(&create_test_block(9)),
+            1
+        );
+        assert_eq!(
+            router
+                .default_instance()
+                .sequence_for(&create_test_block(9)),
+            9
+        );
+
+        // Messages created by a shard instance are tagged with it
+        let msg = router.shard("ETH").unwrap().create_prepare("abc", 1);
+        assert_eq!(msg.shard.as_deref(), Some("ETH"));
+        assert_eq!(router.route(&msg).unwrap().shard(), Some("ETH"));
+    }
+
+    #[test]
+    fn test_shard_router_picks_instance_for_block() {
+        use crate::consensus::shard::ShardRouter;
+
+        let router = ShardRouter::with_shards(
+            Arc::new(PBFTManager::new(0, 4, Vec::new())),
+            &["BTC".to_string()],
+            || PBFTManager::new(0, 4, Vec::new()),
+        );
+
+        let btc = create_test_block(1);
+        assert_eq!(router.instance_for_block(&btc).shard(), Some("BTC"));
+
+        let mut mixed = create_test_block(2);
+        mixed.data.push(MarketData {
+            asset: "ETH".to_string(),
+            price: Decimal::from(3000),
+            source: "Test".to_string(),
+            timestamp: 1_234_567_890_000,
+            provenance: None,
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        });
+        assert_eq!(router.instance_for_block(&mixed).shard(), None);
+    }
+
+    fn btc_eth_block(index: u64) -> Block {
+        let mut block = create_test_block(index);
+        block.data.push(MarketData {
+            asset: "ETH".to_string(),
+            price: Decimal::from(3000),
+            source: "Test".to_string(),
+            timestamp: 1_234_567_890_000,
+            provenance: None,
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        });
+        block
+    }
+
+    #[tokio::test]
+    async fn test_cross_shard_commit_is_atomic() {
+        use crate::consensus::cross_shard::{CrossShardCoordinator, Decision};
+        use crate::consensus::shard::ShardRouter;
+
+        let router = Arc::new(ShardRouter::with_shards(
+            Arc::new(PBFTManager::new(0, 4, Vec::new())),
+            &["BTC".to_string(), "ETH".to_string()],
+            || PBFTManager::new(0, 4, Vec::new()),
+        ));
+        let coordinator = CrossShardCoordinator::new(router);
+
+        // Each participant votes yes by collecting a commit quorum locally
+        let vote = |pbft: Arc<PBFTManager>, block: Block| async move {
+            for node in 0..3 {
+                let mut commit = pbft.create_commit(&block.hash, block.index);
+                commit.node_id = node;
+                pbft.handle_commit(&commit);
+            }
+            pbft.is_committed(block.index).then_some(block.index)
+        };
+
+        let block = btc_eth_block(1);
+        let participants = coordinator.participants(&block);
+        assert_eq!(participants.keys().collect::<Vec<_>>(), vec!["BTC", "ETH"]);
+
+        let outcome = coordinator.execute(&block, vote).await;
+        assert!(outcome.is_cross_shard());
+        assert_eq!(outcome.decision, Decision::Committed);
+        assert!(coordinator.is_committed(1));
+
+        // ETH never reaches quorum, so the block is aborted everywhere
+        let block = btc_eth_block(2);
+        let outcome = coordinator
+            .execute(&block, move |pbft, block| async move {
+                if pbft.shard() == Some("ETH") {
+                    return None;
+                }
+                vote(pbft, block).await
+            })
+            .await;
+        assert_eq!(
+            outcome.decision,
+            Decision::Aborted {
+                rejected_by: vec!["ETH".to_string()]
+            }
+        );
+        assert!(!coordinator.is_committed(2));
+        // BTC had committed the block and rolled it back
+        let btc = coordinator.router().shard("BTC").unwrap();
+        assert!(btc.is_committed(1));
+        assert!(!btc.is_committed(2));
+
+        // A single-shard block skips the protocol and sets the baseline
+        let outcome = coordinator.execute(&create_test_block(3), vote).await;
+        assert!(!outcome.is_cross_shard());
+        assert!(outcome.is_committed());
+
+        let metrics = coordinator.metrics();
+        assert_eq!(metrics.cross_shard_commits, 1);
+        assert_eq!(metrics.cross_shard_aborts, 1);
+        assert_eq!(metrics.single_shard_commits, 1);
+        assert!(metrics.overhead_ms().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cross_shard_prepare_timeout_aborts() {
+        use crate::consensus::cross_shard::{CrossShardCoordinator, Decision};
+        use crate::consensus::shard::ShardRouter;
+
+        let router = Arc::new(ShardRouter::with_shards(
+            Arc::new(PBFTManager::new(0, 4, Vec::new())),
+            &["BTC".to_string()],
+            || PBFTManager::new(0, 4, Vec::new()),
+        ));
+        let coordinator =
+            CrossShardCoordinator::new(router).with_prepare_timeout(Duration::from_millis(20));
+
+        // ETH has no shard, so the default instance participates and stalls
+        let outcome = coordinator
+            .execute(&btc_eth_block(1), |pbft, _| async move {
+                if pbft.shard().is_none() {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                Some(1)
+            })
+            .await;
+        assert_eq!(outcome.participants, vec!["BTC", "default"]);
+        assert_eq!(
+            outcome.decision,
+            Decision::Aborted {
+                rejected_by: vec!["default".to_string()]
+            }
+        );
+        assert!(outcome.latency < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_cross_shard_prunes_old_decisions() {
+        use crate::consensus::cross_shard::CrossShardCoordinator;
+        use crate::consensus::shard::ShardRouter;
+
+        let router = Arc::new(ShardRouter::new(Arc::new(PBFTManager::new(
+            0,
+            4,
+            Vec::new(),
+        ))));
+        let coordinator = CrossShardCoordinator::new(router).with_decision_retention(2);
+        for index in 1..=3 {
+            coordinator
+                .execute(
+                    &create_test_block(index),
+                    move |_, _| async move { Some(index) },
+                )
+                .await;
+        }
+        assert_eq!(coordinator.decision(1), None);
+        assert!(coordinator.is_committed(2));
+        assert!(coordinator.is_committed(3));
+    }
+
     #[test]
     fn test_consensus_names() {
         init();
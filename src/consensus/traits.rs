@@ -1,26 +1,30 @@
 //! Consensus algorithm trait definition
 
+use crate::consensus::error::ConsensusError;
 use crate::consensus::types::{ConsensusMessage, ConsensusRequirements, ConsensusResult};
 use crate::etl::Block;
 use async_trait::async_trait;
-use std::error::Error;
 
 /// Consensus algorithm trait - allows plugging in different consensus mechanisms
 ///
 /// Note: This trait is defined for demonstration purposes and future extensibility.
 /// Currently, main.rs uses PBFT directly, but this trait allows switching between
 /// different consensus algorithms.
+///
+/// Implementations must be `Send + Sync + 'static` and their futures `Send`,
+/// so an `Arc<dyn ConsensusAlgorithm>` can be driven from `tokio::spawn`. Do
+/// not hold `parking_lot` guards across an `.await`.
 #[allow(dead_code)] // Reserved for future use or examples
 #[async_trait]
-pub trait ConsensusAlgorithm: Send + Sync {
+pub trait ConsensusAlgorithm: Send + Sync + 'static {
     /// Propose a block for consensus
-    async fn propose(&self, block: &Block) -> Result<ConsensusResult, Box<dyn Error>>;
+    async fn propose(&self, block: &Block) -> Result<ConsensusResult, ConsensusError>;
 
     /// Handle incoming consensus message
     async fn handle_message(
         &self,
         message: ConsensusMessage,
-    ) -> Result<ConsensusResult, Box<dyn Error>>;
+    ) -> Result<ConsensusResult, ConsensusError>;
 
     /// Check if a block has reached consensus
     fn is_committed(&self, block_index: u64) -> bool;
@@ -2,6 +2,8 @@
 
 use crate::etl::Block;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
 /// Consensus result
 ///
@@ -13,12 +15,84 @@ use serde::{Deserialize, Serialize};
 pub enum ConsensusResult {
     /// Consensus reached
     Committed(Block),
-    /// Consensus pending (for eventual consistency algorithms)
-    Pending,
+    /// Consensus pending, with how far the round got
+    Pending(PendingDetails),
     /// Consensus failed
     Rejected(String),
 }
 
+/// Votes gathered in one phase of a round against the quorum it needs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseProgress {
+    pub phase: String,
+    /// Votes received; weighted algorithms report the summed weight
+    pub votes: f64,
+    pub required: f64,
+}
+
+impl PhaseProgress {
+    pub fn is_complete(&self) -> bool {
+        self.votes >= self.required
+    }
+}
+
+/// How close a round that did not commit came to committing
+///
+/// Phases are listed in the order the algorithm runs them; phases the round
+/// never reached are left out. Empty when the algorithm does not track
+/// votes for the call, e.g. when a single message was handled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PendingDetails {
+    pub phases: Vec<PhaseProgress>,
+    /// Time from the start of the round until it returned
+    pub elapsed_ms: u64,
+}
+
+impl PendingDetails {
+    pub fn new(elapsed: Duration) -> Self {
+        PendingDetails {
+            phases: Vec::new(),
+            elapsed_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    pub fn with_phase(mut self, phase: impl Into<String>, votes: f64, required: f64) -> Self {
+        self.phases.push(PhaseProgress {
+            phase: phase.into(),
+            votes,
+            required,
+        });
+        self
+    }
+
+    /// First phase that did not reach its quorum
+    pub fn stalled_phase(&self) -> Option<&PhaseProgress> {
+        self.phases.iter().find(|phase| !phase.is_complete())
+    }
+
+    /// Votes the stalled phase was short of; `None` if no phase stalled
+    pub fn missing_votes(&self) -> Option<f64> {
+        self.stalled_phase()
+            .map(|phase| phase.required - phase.votes)
+    }
+}
+
+impl fmt::Display for PendingDetails {
+    /// e.g. `prepare 3/3, commit 2/3 after 1012ms`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.phases.is_empty() {
+            f.write_str("no votes tracked")?;
+        }
+        for (i, phase) in self.phases.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {}/{}", phase.phase, phase.votes, phase.required)?;
+        }
+        write!(f, " after {}ms", self.elapsed_ms)
+    }
+}
+
 /// Consensus requirements
 ///
 /// Note: This is used in the ConsensusAlgorithm trait and tests.
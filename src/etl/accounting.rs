@@ -0,0 +1,326 @@
+//! Fee and credit accounting for data submissions
+//!
+//! When enabled, every submitter (the `source` of a `MarketData` entry)
+//! holds a credit balance. Before a block is proposed its entries are priced
+//! with the `FeeSchedule`; the resulting `FeeRecord`s are stored in the block
+//! (and covered by its hash), and the balances are debited once the block is
+//! committed. A block whose submitters cannot cover their fees is refused.
+//!
+//! Balances and usage are persisted in the `accounts` table and reported on
+//! `GET /accounts`. Configured with `FEE_PER_ENTRY` (enables accounting) and
+//! `ACCOUNT_CREDITS`, which seeds balances for submitters not yet on file.
+
+use crate::etl::load::{DatabaseManager, DbResult};
+use crate::etl::MarketData;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Fees charged to one submitter in a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeRecord {
+    pub submitter: String,
+    /// Entries the fee covers
+    pub entries: u64,
+    /// Credits debited
+    pub amount: u64,
+}
+
+/// A submitter's balance and lifetime usage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub submitter: String,
+    pub balance: u64,
+    /// Entries accepted into committed blocks
+    pub entries: u64,
+    pub fees_paid: u64,
+}
+
+/// Price of a submission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub fee_per_entry: u64,
+    /// Per-submitter fees that replace `fee_per_entry`
+    pub overrides: HashMap<String, u64>,
+}
+
+impl FeeSchedule {
+    pub fn new(fee_per_entry: u64) -> Self {
+        FeeSchedule {
+            fee_per_entry,
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn with_override(mut self, submitter: impl Into<String>, fee_per_entry: u64) -> Self {
+        self.overrides.insert(submitter.into(), fee_per_entry);
+        self
+    }
+
+    pub fn fee_for(&self, submitter: &str) -> u64 {
+        self.overrides
+            .get(submitter)
+            .copied()
+            .unwrap_or(self.fee_per_entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountingError {
+    InsufficientCredit {
+        submitter: String,
+        balance: u64,
+        required: u64,
+    },
+}
+
+impl fmt::Display for AccountingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountingError::InsufficientCredit {
+                submitter,
+                balance,
+                required,
+            } => write!(
+                f,
+                "submitter '{}' has {} credit(s) but the block needs {}",
+                submitter, balance, required
+            ),
+        }
+    }
+}
+
+impl AccountingError {
+    /// Submitter whose entries caused the error
+    pub fn submitter(&self) -> &str {
+        match self {
+            AccountingError::InsufficientCredit { submitter, .. } => submitter,
+        }
+    }
+}
+
+impl std::error::Error for AccountingError {}
+
+/// Balances of every submitter, backed by the `accounts` table
+pub struct AccountBook {
+    db: Arc<DatabaseManager>,
+    schedule: FeeSchedule,
+    accounts: RwLock<BTreeMap<String, Account>>,
+}
+
+impl AccountBook {
+    /// Load accounts from `db`
+    pub fn open(db: Arc<DatabaseManager>, schedule: FeeSchedule) -> DbResult<Self> {
+        let accounts = db
+            .load_accounts()?
+            .into_iter()
+            .map(|account| (account.submitter.clone(), account))
+            .collect();
+        Ok(AccountBook {
+            db,
+            schedule,
+            accounts: RwLock::new(accounts),
+        })
+    }
+
+    /// Accounting from `FEE_PER_ENTRY`, with `ACCOUNT_CREDITS`
+    /// (`SUBMITTER=CREDITS,...`) seeding new submitters; `None` when unset
+    pub fn from_env(db: Arc<DatabaseManager>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Ok(fee) = std::env::var("FEE_PER_ENTRY") else {
+            return Ok(None);
+        };
+        let fee = fee
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid FEE_PER_ENTRY '{}'", fee))?;
+        let book = Self::open(db, FeeSchedule::new(fee))?;
+
+        if let Ok(credits) = std::env::var("ACCOUNT_CREDITS") {
+            for entry in credits.split(',').filter(|e| !e.trim().is_empty()) {
+                let (submitter, amount) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid ACCOUNT_CREDITS entry '{}'", entry))?;
+                let amount = amount
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid credit amount '{}'", amount))?;
+                if book.account(submitter.trim()).is_none() {
+                    book.credit(submitter.trim(), amount)?;
+                }
+            }
+        }
+        Ok(Some(book))
+    }
+
+    pub fn schedule(&self) -> &FeeSchedule {
+        &self.schedule
+    }
+
+    pub fn account(&self, submitter: &str) -> Option<Account> {
+        self.accounts.read().get(submitter).cloned()
+    }
+
+    /// Every account, ordered by submitter
+    pub fn accounts(&self) -> Vec<Account> {
+        self.accounts.read().values().cloned().collect()
+    }
+
+    /// Add credits to a submitter, opening the account if needed
+    pub fn credit(&self, submitter: &str, amount: u64) -> DbResult<Account> {
+        let mut accounts = self.accounts.write();
+        let account = accounts
+            .entry(submitter.to_string())
+            .or_insert_with(|| Account {
+                submitter: submitter.to_string(),
+                ..Default::default()
+            });
+        account.balance += amount;
+        self.db.save_account(account)?;
+        Ok(account.clone())
+    }
+
+    /// Price `data`, one record per submitter, failing if any submitter
+    /// cannot cover its share. Balances are not touched.
+    pub fn quote(&self, data: &[MarketData]) -> Result<Vec<FeeRecord>, AccountingError> {
+        let mut entries: BTreeMap<&str, u64> = BTreeMap::new();
+        for item in data {
+            *entries.entry(item.source.as_str()).or_default() += 1;
+        }
+
+        let accounts = self.accounts.read();
+        entries
+            .into_iter()
+            .map(|(submitter, entries)| {
+                let amount = entries * self.schedule.fee_for(submitter);
+                let balance = accounts.get(submitter).map_or(0, |a| a.balance);
+                if balance < amount {
+                    return Err(AccountingError::InsufficientCredit {
+                        submitter: submitter.to_string(),
+                        balance,
+                        required: amount,
+                    });
+                }
+                Ok(FeeRecord {
+                    submitter: submitter.to_string(),
+                    entries,
+                    amount,
+                })
+            })
+            .collect()
+    }
+
+    /// Debit the fees recorded in a committed block
+    ///
+    /// Balances never go below zero: if a submitter spent its credit on a
+    /// concurrent block since the quote, the shortfall is absorbed.
+    pub fn settle(&self, fees: &[FeeRecord]) -> DbResult<()> {
+        let mut accounts = self.accounts.write();
+        for fee in fees {
+            let account = accounts
+                .entry(fee.submitter.clone())
+                .or_insert_with(|| Account {
+                    submitter: fee.submitter.clone(),
+                    ..Default::default()
+                });
+            account.balance = account.balance.saturating_sub(fee.amount);
+            account.entries += fee.entries;
+            account.fees_paid += fee.amount;
+            self.db.save_account(account)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing;
+    use std::fs;
+
+    fn entry(source: &str) -> MarketData {
+        testing::entry("BTC", source, Decimal::from(50000), 1_234_567_890_000)
+    }
+
+    #[test]
+    fn test_quote_and_settle_debit_submitters() {
+        let path = "test_accounting.db";
+        fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+
+        let schedule = FeeSchedule::new(2).with_override("Kraken", 5);
+        let book = AccountBook::open(db.clone(), schedule.clone()).unwrap();
+        book.credit("CoinGecko", 10).unwrap();
+        book.credit("Kraken", 5).unwrap();
+
+        let data = vec![entry("CoinGecko"), entry("Kraken"), entry("CoinGecko")];
+        let fees = book.quote(&data).unwrap();
+        assert_eq!(
+            fees,
+            vec![
+                FeeRecord {
+                    submitter: "CoinGecko".to_string(),
+                    entries: 2,
+                    amount: 4
+                },
+                FeeRecord {
+                    submitter: "Kraken".to_string(),
+                    entries: 1,
+                    amount: 5
+                },
+            ]
+        );
+        // Quoting does not spend anything
+        assert_eq!(book.account("Kraken").unwrap().balance, 5);
+
+        book.settle(&fees).unwrap();
+        assert_eq!(
+            book.account("CoinGecko").unwrap(),
+            Account {
+                submitter: "CoinGecko".to_string(),
+                balance: 6,
+                entries: 2,
+                fees_paid: 4
+            }
+        );
+
+        // Kraken is out of credit; an unknown submitter has none
+        let err = book.quote(&[entry("Kraken")]).unwrap_err();
+        assert!(err.to_string().contains("'Kraken' has 0 credit(s)"));
+        assert!(book.quote(&[entry("Unknown")]).is_err());
+
+        // Fees are stored with the block and covered by its hash
+        let mut block = crate::etl::Block {
+            index: 1,
+            timestamp: 1_234_567_890_000,
+            data,
+            previous_hash: "0000_genesis".to_string(),
+            hash: String::new(),
+            nonce: 0,
+            format_version: crate::etl::BLOCK_FORMAT_VERSION,
+            fees: Vec::new(),
+            divergences: Vec::new(),
+            hlc: None,
+            annotations: BTreeMap::new(),
+            order_books: Vec::new(),
+        };
+        block.calculate_hash_with_nonce();
+        let unpriced_hash = block.hash.clone();
+        block.fees = fees;
+        block.calculate_hash_with_nonce();
+        assert_ne!(block.hash, unpriced_hash);
+        db.save_block(&block).unwrap();
+        let stored = db.get_block_by_index(1).unwrap();
+        assert_eq!(stored.fees, block.fees);
+        assert_eq!(stored.calculate_hash(), block.hash);
+
+        // Balances survive a restart
+        let reopened = AccountBook::open(db, schedule).unwrap();
+        assert_eq!(reopened.accounts(), book.accounts());
+
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,342 @@
+//! Price aggregation across several sources
+//!
+//! `AggregatingExtractor` is a `DataSource` that queries every configured
+//! source concurrently and reports one price for the round: the median of
+//! the quotes that came back, or a trimmed mean that drops the most extreme
+//! quotes on each side first. A single feed that fails or reports a wild
+//! price therefore cannot set the price on its own. Every source's raw quote
+//! is kept in `ExtractResult::quotes`, from which the transform layer can
+//! derive a weighted reference price (`consolidate::ConsolidateStage`).
+//!
+//! Enabled by naming several sources in `MARKET_DATA_SOURCE`, separated by
+//! commas (e.g. `coingecko,kraken,coinbase`); see `sources::SourceRegistry`.
+//! Configured with `AGGREGATION_METHOD` (`median`, the default, or
+//! `trimmed-mean:<pct>`) and `AGGREGATION_MIN_SOURCES` (default 1). At most
+//! `EXTRACT_MAX_CONCURRENCY` sources are queried at a time, independently of
+//! the asset fetches the extractor runs alongside.
+
+use crate::etl::divergence::{median_of_sorted, SourceQuote};
+use crate::etl::extract::{
+    max_concurrency_from_env, DataSource, ExtractResult, SourceError, DEFAULT_MAX_CONCURRENCY,
+};
+use crate::etl::price::Decimal;
+use crate::etl::{now_millis, DEFAULT_ASSET};
+use crate::retry::RetryClass;
+use async_trait::async_trait;
+use futures_util::stream::{self, StreamExt};
+use std::fmt;
+use std::sync::Arc;
+
+/// How quotes from several sources are combined into one price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregationMethod {
+    Median,
+    /// Mean after dropping `trim_pct` percent of the quotes from each end
+    TrimmedMean {
+        trim_pct: f64,
+    },
+}
+
+impl AggregationMethod {
+    /// Parse `median` or `trimmed-mean:<pct>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim().to_ascii_lowercase();
+        if value == "median" {
+            return Ok(AggregationMethod::Median);
+        }
+        let pct = value
+            .strip_prefix("trimmed-mean:")
+            .ok_or_else(|| {
+                format!(
+                    "unknown aggregation method '{}' (expected median or trimmed-mean:<pct>)",
+                    value
+                )
+            })?
+            .parse::<f64>()
+            .map_err(|e| format!("invalid trim percentage: {}", e))?;
+        if !(0.0..50.0).contains(&pct) {
+            return Err(format!("trim percentage {} must be in [0, 50)", pct));
+        }
+        Ok(AggregationMethod::TrimmedMean { trim_pct: pct })
+    }
+
+    /// Combine non-empty `prices`
+    pub fn apply(&self, prices: &[Decimal]) -> Decimal {
+        let mut sorted = prices.to_vec();
+        sorted.sort();
+        match *self {
+            AggregationMethod::Median => median_of_sorted(&sorted),
+            AggregationMethod::TrimmedMean { trim_pct } => {
+                let trim = (sorted.len() as f64 * trim_pct / 100.0).floor() as usize;
+                let kept = &sorted[trim..sorted.len() - trim];
+                kept.iter().sum::<Decimal>() / Decimal::from(kept.len())
+            }
+        }
+    }
+}
+
+impl fmt::Display for AggregationMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregationMethod::Median => f.write_str("median"),
+            AggregationMethod::TrimmedMean { trim_pct } => write!(f, "trimmed-mean:{}", trim_pct),
+        }
+    }
+}
+
+/// Queries several sources at once and reports their aggregate price
+pub struct AggregatingExtractor {
+    sources: Vec<Arc<dyn DataSource>>,
+    method: AggregationMethod,
+    min_sources: usize,
+    max_concurrency: usize,
+}
+
+impl AggregatingExtractor {
+    pub fn new(sources: Vec<Arc<dyn DataSource>>) -> Self {
+        AggregatingExtractor {
+            sources,
+            method: AggregationMethod::Median,
+            min_sources: 1,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+
+    /// Apply `AGGREGATION_METHOD`, `AGGREGATION_MIN_SOURCES` and
+    /// `EXTRACT_MAX_CONCURRENCY`
+    pub fn with_env_overrides(mut self) -> Result<Self, String> {
+        self = self.with_max_concurrency(max_concurrency_from_env()?);
+        if let Ok(method) = std::env::var("AGGREGATION_METHOD") {
+            self.method = AggregationMethod::parse(&method)?;
+        }
+        if let Ok(min) = std::env::var("AGGREGATION_MIN_SOURCES") {
+            let min = min
+                .parse()
+                .map_err(|e| format!("invalid AGGREGATION_MIN_SOURCES: {}", e))?;
+            self = self.with_min_sources(min);
+        }
+        Ok(self)
+    }
+
+    pub fn with_method(mut self, method: AggregationMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Fail the round unless at least `min_sources` sources answer
+    pub fn with_min_sources(mut self, min_sources: usize) -> Self {
+        self.min_sources = min_sources.max(1);
+        self
+    }
+
+    /// Query at most `limit` sources at once
+    pub fn with_max_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrency = limit.max(1);
+        self
+    }
+
+    pub fn method(&self) -> AggregationMethod {
+        self.method
+    }
+
+    pub fn source_names(&self) -> Vec<&str> {
+        self.sources.iter().map(|s| s.name()).collect()
+    }
+}
+
+#[async_trait]
+impl DataSource for AggregatingExtractor {
+    fn name(&self) -> &str {
+        "Aggregate"
+    }
+
+    fn asset(&self) -> &str {
+        self.sources
+            .first()
+            .map_or(DEFAULT_ASSET, |source| source.asset())
+    }
+
+    /// Sources that fail or report a non-positive price are left out; the
+    /// round fails only when fewer than `min_sources` remain. The failure
+    /// is retryable unless every source failed fatally. Sources quoting
+    /// different assets are a configuration error and fail the round.
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let fetches: Vec<_> = self.sources.iter().map(|source| source.fetch()).collect();
+        let results: Vec<_> = stream::iter(fetches)
+            .buffered(self.max_concurrency)
+            .collect()
+            .await;
+
+        let mut quotes = Vec::new();
+        let mut assets = Vec::new();
+        let mut errors = Vec::new();
+        for (source, result) in self.sources.iter().zip(results) {
+            match result {
+                Ok(quote) if quote.price > Decimal::ZERO => {
+                    assets.push(quote.asset);
+                    quotes.push(SourceQuote {
+                        source: quote.source,
+                        price: quote.price,
+                        timestamp: quote.timestamp,
+                        volume: quote.volume,
+                    })
+                }
+                Ok(quote) => errors.push(SourceError::retryable(format!(
+                    "{}: invalid price {}",
+                    source.name(),
+                    quote.price
+                ))),
+                Err(e) => errors.push(SourceError {
+                    message: format!("{}: {}", source.name(), e),
+                    class: e.class,
+                    status: e.status,
+                }),
+            }
+        }
+
+        if quotes.len() < self.min_sources {
+            let message = format!(
+                "{} of {} sources answered, {} required ({})",
+                quotes.len(),
+                self.sources.len(),
+                self.min_sources,
+                errors
+                    .iter()
+                    .map(|e| e.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+            return Err(if errors.iter().all(|e| e.class == RetryClass::Fatal) {
+                SourceError::fatal(message)
+            } else {
+                SourceError::retryable(message)
+            });
+        }
+
+        assets.dedup();
+        if assets.len() > 1 {
+            return Err(SourceError::fatal(format!(
+                "sources quote different assets ({})",
+                assets.join(", ")
+            )));
+        }
+
+        let prices: Vec<Decimal> = quotes.iter().map(|q| q.price).collect();
+        let sources: Vec<&str> = quotes.iter().map(|q| q.source.as_str()).collect();
+        // Volume reported across the sources, when any reported one
+        let volume = quotes
+            .iter()
+            .filter_map(|q| q.volume)
+            .fold(None, |total: Option<Decimal>, v| {
+                Some(total.unwrap_or_default() + v)
+            });
+        Ok(ExtractResult {
+            asset: assets.pop().unwrap_or_else(|| DEFAULT_ASSET.to_string()),
+            price: self.method.apply(&prices),
+            timestamp: now_millis(),
+            source: format!("{}({})", self.name(), sources.join(",")),
+            quotes,
+            cache_hit: false,
+            volume,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedSource {
+        name: &'static str,
+        price: Result<i64, SourceError>,
+    }
+
+    #[async_trait]
+    impl DataSource for FixedSource {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+            Ok(ExtractResult {
+                asset: DEFAULT_ASSET.to_string(),
+                price: Decimal::from(self.price.clone()?),
+                timestamp: now_millis(),
+                source: self.name.to_string(),
+                quotes: Vec::new(),
+                cache_hit: false,
+                volume: None,
+            })
+        }
+    }
+
+    fn source(name: &'static str, price: Result<i64, SourceError>) -> Arc<dyn DataSource> {
+        Arc::new(FixedSource { name, price })
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_ignores_bad_feed() {
+        let sources = vec![
+            source("CoinGecko", Ok(64_000)),
+            source("Kraken", Ok(64_010)),
+            // One feed reporting a wild price
+            source("Coinbase", Ok(6_401)),
+            source("Bitstamp", Err(SourceError::retryable("timeout"))),
+        ];
+
+        let median = AggregatingExtractor::new(sources.clone());
+        let result = median.fetch().await.unwrap();
+        assert_eq!(result.price, Decimal::from(64_000));
+        assert_eq!(result.source, "Aggregate(CoinGecko,Kraken,Coinbase)");
+        let raw: Vec<(&str, Decimal)> = result
+            .quotes
+            .iter()
+            .map(|q| (q.source.as_str(), q.price))
+            .collect();
+        assert_eq!(
+            raw,
+            vec![
+                ("CoinGecko", Decimal::from(64_000)),
+                ("Kraken", Decimal::from(64_010)),
+                ("Coinbase", Decimal::from(6_401))
+            ]
+        );
+
+        let trimmed = AggregatingExtractor::new(sources.clone())
+            .with_method(AggregationMethod::parse("trimmed-mean:34").unwrap());
+        assert_eq!(trimmed.fetch().await.unwrap().price, Decimal::from(64_000));
+
+        let serial = AggregatingExtractor::new(sources.clone()).with_max_concurrency(1);
+        assert_eq!(serial.fetch().await.unwrap().price, Decimal::from(64_000));
+
+        let strict = AggregatingExtractor::new(sources).with_min_sources(4);
+        let err = strict.fetch().await.unwrap_err();
+        assert_eq!(err.class, RetryClass::Retry);
+        assert!(err.message.contains("Bitstamp: timeout"), "{}", err);
+
+        let all_fatal =
+            AggregatingExtractor::new(vec![source("Kraken", Err(SourceError::fatal("bad pair")))]);
+        assert_eq!(
+            all_fatal.fetch().await.unwrap_err().class,
+            RetryClass::Fatal
+        );
+    }
+
+    #[test]
+    fn test_aggregation_method_parse() {
+        assert_eq!(
+            AggregationMethod::parse("Median").unwrap(),
+            AggregationMethod::Median
+        );
+        assert_eq!(
+            AggregationMethod::parse("trimmed-mean:20").unwrap(),
+            AggregationMethod::TrimmedMean { trim_pct: 20.0 }
+        );
+        assert!(AggregationMethod::parse("trimmed-mean:50").is_err());
+        assert!(AggregationMethod::parse("mean").is_err());
+        assert_eq!(
+            AggregationMethod::TrimmedMean { trim_pct: 0.0 }.apply(&[1, 2, 6].map(Decimal::from)),
+            Decimal::from(3)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Rollups over the ledger for simple reporting
+//!
+//! `DatabaseManager::get_analytics` streams the chain through an
+//! `AnalyticsBuilder`, which keeps only the running totals: blocks per UTC
+//! day, each source's share of the entries, and the daily min/max/avg price
+//! per asset. Days follow the block timestamp. The same report is served on `GET /analytics?from=&to=`
+//! (milliseconds, both optional), so common questions about the ledger do
+//! not need an export to external tools.
+
+use crate::etl::{price, timestamp_to_millis, Block};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Inclusive block timestamp bounds in milliseconds; `None` leaves a side
+/// open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AnalyticsRange {
+    pub from_timestamp: Option<i64>,
+    pub to_timestamp: Option<i64>,
+}
+
+impl AnalyticsRange {
+    /// The whole ledger
+    pub fn all() -> Self {
+        AnalyticsRange::default()
+    }
+
+    pub fn between(from_timestamp: i64, to_timestamp: i64) -> Self {
+        AnalyticsRange {
+            from_timestamp: Some(from_timestamp),
+            to_timestamp: Some(to_timestamp),
+        }
+    }
+}
+
+/// Blocks committed on one UTC day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyBlocks {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub blocks: u64,
+    pub entries: u64,
+}
+
+/// How much of the ledger's data came from one source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceShare {
+    pub source: String,
+    pub entries: u64,
+    /// Fraction of all entries in the range, 0.0 to 1.0
+    pub share: f64,
+}
+
+/// Price summary of one asset on one UTC day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyPrice {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub asset: String,
+    pub samples: u64,
+    pub min_price: f64,
+    pub max_price: f64,
+    pub avg_price: f64,
+}
+
+/// Result of `DatabaseManager::get_analytics`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainAnalytics {
+    pub range: AnalyticsRange,
+    pub total_blocks: u64,
+    pub total_entries: u64,
+    /// Oldest day first
+    pub blocks_per_day: Vec<DailyBlocks>,
+    /// Largest share first
+    pub sources: Vec<SourceShare>,
+    /// Ordered by day, then asset
+    pub daily_prices: Vec<DailyPrice>,
+}
+
+/// Running totals for one (day, asset) pair
+struct PriceTotals {
+    samples: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+/// Accumulates `ChainAnalytics` one block at a time
+///
+/// Memory grows with the number of days, sources and assets seen, not with
+/// the number of blocks.
+pub struct AnalyticsBuilder {
+    range: AnalyticsRange,
+    days: BTreeMap<String, (u64, u64)>,
+    sources: BTreeMap<String, u64>,
+    prices: BTreeMap<(String, String), PriceTotals>,
+}
+
+impl AnalyticsBuilder {
+    pub fn new(range: AnalyticsRange) -> Self {
+        AnalyticsBuilder {
+            range,
+            days: BTreeMap::new(),
+            sources: BTreeMap::new(),
+            prices: BTreeMap::new(),
+        }
+    }
+
+    /// Count `block` if its timestamp is in the range
+    pub fn add(&mut self, block: &Block) {
+        let timestamp = timestamp_to_millis(block.timestamp);
+        let in_range = self
+            .range
+            .from_timestamp
+            .is_none_or(|from| timestamp >= from)
+            && self.range.to_timestamp.is_none_or(|to| timestamp <= to);
+        if !in_range {
+            return;
+        }
+        let day = chrono::DateTime::from_timestamp_millis(timestamp)
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+
+        let totals = self.days.entry(day.clone()).or_default();
+        totals.0 += 1;
+        totals.1 += block.data.len() as u64;
+        for item in &block.data {
+            *self.sources.entry(item.source.clone()).or_default() += 1;
+            let price = price::to_f64(item.price);
+            self.prices
+                .entry((day.clone(), item.asset.clone()))
+                .and_modify(|totals| {
+                    totals.samples += 1;
+                    totals.min = totals.min.min(price);
+                    totals.max = totals.max.max(price);
+                    totals.sum += price;
+                })
+                .or_insert(PriceTotals {
+                    samples: 1,
+                    min: price,
+                    max: price,
+                    sum: price,
+                });
+        }
+    }
+
+    pub fn finish(self) -> ChainAnalytics {
+        let blocks_per_day: Vec<DailyBlocks> = self
+            .days
+            .into_iter()
+            .map(|(day, (blocks, entries))| DailyBlocks {
+                day,
+                blocks,
+                entries,
+            })
+            .collect();
+        let total_blocks = blocks_per_day.iter().map(|d| d.blocks).sum();
+        let total_entries: u64 = blocks_per_day.iter().map(|d| d.entries).sum();
+
+        let mut sources: Vec<SourceShare> = self
+            .sources
+            .into_iter()
+            .map(|(source, entries)| SourceShare {
+                source,
+                entries,
+                share: entries as f64 / total_entries.max(1) as f64,
+            })
+            .collect();
+        // Stable sort keeps equal counts in source order
+        sources.sort_by_key(|s| std::cmp::Reverse(s.entries));
+
+        let daily_prices = self
+            .prices
+            .into_iter()
+            .map(|((day, asset), totals)| DailyPrice {
+                day,
+                asset,
+                samples: totals.samples,
+                min_price: totals.min,
+                max_price: totals.max,
+                avg_price: totals.sum / totals.samples as f64,
+            })
+            .collect();
+
+        ChainAnalytics {
+            range: self.range,
+            total_blocks,
+            total_entries,
+            blocks_per_day,
+            sources,
+            daily_prices,
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Statistical anomaly detection
+//!
+//! The validator only knows a static price range per asset class, which a
+//! feed glitch of a few percent passes easily. `AnomalyStage` compares each
+//! quote with the asset's recent window instead: by z-score (distance from
+//! the mean in standard deviations) or, more robust to the outliers it is
+//! looking for, by the modified z-score over the median absolute deviation
+//! (`0.6745 * |x - median| / MAD`). A quote scoring above the threshold is
+//! either flagged, with a `CustodyStep::Flagged` in its provenance, or
+//! rejected.
+//!
+//! Every quote joins the window, rejected ones included, so a lasting move
+//! becomes the new normal once it fills enough of the window rather than
+//! being rejected for good. Nothing is judged until the window holds
+//! `MIN_OBSERVATIONS` quotes, or while it has no spread at all.
+//!
+//! Configured with `ANOMALY_DETECTION` (`mad` or `zscore`),
+//! `ANOMALY_THRESHOLD` (default 3.5), `ANOMALY_WINDOW` (default 20 quotes)
+//! and `ANOMALY_ACTION` (`flag`, the default, or `reject`).
+
+use crate::etl::pipeline::{StageContext, TransformStage};
+use crate::etl::price::{self, Decimal};
+use crate::etl::provenance::CustodyStep;
+use crate::etl::transform::TransformResult;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+
+pub const DEFAULT_THRESHOLD: f64 = 3.5;
+pub const DEFAULT_WINDOW: usize = 20;
+
+/// Quotes an asset's window needs before quotes are judged
+pub const MIN_OBSERVATIONS: usize = 5;
+
+/// Scales the MAD to the standard deviation of a normal distribution
+const MAD_SCALE: f64 = 0.6745;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyMethod {
+    ZScore,
+    Mad,
+}
+
+impl AnomalyMethod {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "zscore" | "z-score" => Ok(AnomalyMethod::ZScore),
+            "mad" => Ok(AnomalyMethod::Mad),
+            other => Err(format!(
+                "unknown anomaly method '{}' (expected mad or zscore)",
+                other
+            )),
+        }
+    }
+
+    /// Score of `value` against `window`; `None` when the window has no
+    /// spread to measure against
+    pub fn score(&self, window: &[f64], value: f64) -> Option<f64> {
+        let n = window.len() as f64;
+        match self {
+            AnomalyMethod::ZScore => {
+                let mean = window.iter().sum::<f64>() / n;
+                let variance = window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+                let std_dev = variance.sqrt();
+                (std_dev > 0.0).then(|| (value - mean).abs() / std_dev)
+            }
+            AnomalyMethod::Mad => {
+                let center = median(window.to_vec());
+                let mad = median(window.iter().map(|x| (x - center).abs()).collect());
+                (mad > 0.0).then(|| MAD_SCALE * (value - center).abs() / mad)
+            }
+        }
+    }
+}
+
+impl fmt::Display for AnomalyMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AnomalyMethod::ZScore => "zscore",
+            AnomalyMethod::Mad => "mad",
+        })
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// What happens to a quote that scores above the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyAction {
+    Flag,
+    Reject,
+}
+
+/// A quote that deviated from its asset's recent window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub asset: String,
+    pub price: Decimal,
+    pub method: AnomalyMethod,
+    pub score: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} price {} is an anomaly ({} score {:.2} > {})",
+            self.asset, self.price, self.method, self.score, self.threshold
+        )
+    }
+}
+
+impl Error for Anomaly {}
+
+/// Flags or rejects quotes far from their asset's recent window
+#[derive(Debug, Clone)]
+pub struct AnomalyStage {
+    method: AnomalyMethod,
+    threshold: f64,
+    window: usize,
+    action: AnomalyAction,
+    /// Recent prices per asset, oldest first; shared by clones
+    history: Arc<Mutex<HashMap<String, VecDeque<f64>>>>,
+}
+
+impl AnomalyStage {
+    pub fn new(method: AnomalyMethod) -> Self {
+        AnomalyStage {
+            method,
+            threshold: DEFAULT_THRESHOLD,
+            window: DEFAULT_WINDOW,
+            action: AnomalyAction::Flag,
+            history: Arc::default(),
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(MIN_OBSERVATIONS);
+        self
+    }
+
+    pub fn with_action(mut self, action: AnomalyAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// `None` unless `ANOMALY_DETECTION` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(method) = std::env::var("ANOMALY_DETECTION") else {
+            return Ok(None);
+        };
+        let mut stage = Self::new(
+            AnomalyMethod::parse(&method)
+                .map_err(|e| format!("invalid ANOMALY_DETECTION: {}", e))?,
+        );
+        if let Ok(threshold) = std::env::var("ANOMALY_THRESHOLD") {
+            let threshold = threshold
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|t| t.is_finite() && *t > 0.0)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid ANOMALY_THRESHOLD: '{}' is not a positive number",
+                        threshold
+                    )
+                })?;
+            stage = stage.with_threshold(threshold);
+        }
+        if let Ok(window) = std::env::var("ANOMALY_WINDOW") {
+            let window = window
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid ANOMALY_WINDOW: {}", e))?;
+            stage = stage.with_window(window);
+        }
+        if let Ok(action) = std::env::var("ANOMALY_ACTION") {
+            stage = stage.with_action(match action.trim().to_ascii_lowercase().as_str() {
+                "flag" => AnomalyAction::Flag,
+                "reject" => AnomalyAction::Reject,
+                other => {
+                    return Err(format!(
+                        "invalid ANOMALY_ACTION: '{}' (expected flag or reject)",
+                        other
+                    ))
+                }
+            });
+        }
+        Ok(Some(stage))
+    }
+
+    pub fn method(&self) -> AnomalyMethod {
+        self.method
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn action(&self) -> AnomalyAction {
+        self.action
+    }
+
+    /// Score `price` against `asset`'s window, then add it to the window;
+    /// `Some` when it is an anomaly
+    pub fn check(&self, asset: &str, price: Decimal) -> Option<Anomaly> {
+        let value = price::to_f64(price);
+        let mut history = self.history.lock();
+        let window = history.entry(asset.to_string()).or_default();
+        let score = (window.len() >= MIN_OBSERVATIONS)
+            .then(|| self.method.score(window.make_contiguous(), value))
+            .flatten();
+        window.push_back(value);
+        while window.len() > self.window {
+            window.pop_front();
+        }
+        score
+            .filter(|score| *score > self.threshold)
+            .map(|score| Anomaly {
+                asset: asset.to_string(),
+                price,
+                method: self.method,
+                score,
+                threshold: self.threshold,
+            })
+    }
+}
+
+impl TransformStage for AnomalyStage {
+    fn name(&self) -> &str {
+        "anomaly"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        let Some(anomaly) = self.check(&record.asset, record.price) else {
+            return Ok(());
+        };
+        warn!(
+            asset = %anomaly.asset,
+            price = %anomaly.price,
+            score = anomaly.score,
+            action = ?self.action,
+            "Transform: Price deviates from the recent window"
+        );
+        match self.action {
+            AnomalyAction::Reject => Err(Box::new(anomaly)),
+            AnomalyAction::Flag => {
+                record.provenance.push(CustodyStep::Flagged {
+                    check: "anomaly".to_string(),
+                    detail: format!(
+                        "{} score {:.2} > {}",
+                        anomaly.method, anomaly.score, anomaly.threshold
+                    ),
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_and_rejects_outliers() {
+        let quotes = [100.0, 101.0, 99.0, 100.5, 99.5, 100.0];
+        let stage = AnomalyStage::new(AnomalyMethod::Mad).with_action(AnomalyAction::Reject);
+        for price in quotes {
+            assert!(stage
+                .check("BTC", price::from_f32(price).unwrap())
+                .is_none());
+        }
+        // A 10% jump is far outside a window moving by cents
+        let anomaly = stage.check("BTC", Decimal::from(110)).unwrap();
+        assert_eq!(anomaly.method, AnomalyMethod::Mad);
+        assert!(anomaly.score > 10.0, "{}", anomaly);
+        assert!(stage.check("BTC", Decimal::from(101)).is_none());
+        // Other assets keep their own window
+        assert!(stage.check("ETH", Decimal::from(3000)).is_none());
+
+        let mut record = TransformResult::raw("BTC", Decimal::from(90), "Kraken".into(), 0);
+        let error = stage
+            .apply(&mut record, &StageContext::default())
+            .unwrap_err();
+        assert!(error.to_string().contains("is an anomaly"), "{}", error);
+
+        let flagging = AnomalyStage::new(AnomalyMethod::ZScore).with_threshold(3.0);
+        for price in quotes {
+            flagging.check("BTC", price::from_f32(price).unwrap());
+        }
+        let mut record = TransformResult::raw("BTC", Decimal::from(104), "Kraken".into(), 0);
+        flagging
+            .apply(&mut record, &StageContext::default())
+            .unwrap();
+        assert_eq!(record.provenance.step_names(), vec!["flagged:anomaly"]);
+
+        // A flat window has no spread to judge against
+        let flat = AnomalyStage::new(AnomalyMethod::ZScore);
+        for _ in 0..MIN_OBSERVATIONS {
+            flat.check("EURUSD", Decimal::ONE);
+        }
+        assert!(flat.check("EURUSD", Decimal::TWO).is_none());
+        assert!(AnomalyMethod::parse("iqr").is_err());
+    }
+}
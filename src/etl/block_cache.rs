@@ -0,0 +1,247 @@
+//! In-memory LRU cache of recently read blocks
+//!
+//! `DatabaseManager` answers `get_block_by_index`, `get_block_by_hash`,
+//! `get_latest_block` and `query_latest_blocks` from this cache when it can,
+//! so hot API reads (the tip, the latest N blocks, blocks an explorer keeps
+//! fetching) skip SQLite. Blocks are cached by index, with a hash → index
+//! map for hash lookups, and the least recently used block is evicted once
+//! `capacity` is reached.
+//!
+//! The manager invalidates on every write: saving a block drops it and every
+//! cached block above it (a reorg replaces a suffix of the chain), deleting
+//! or pruning drops the affected indexes, and any write forgets the tip.
+//!
+//! Sized with `BLOCK_CACHE_BLOCKS` (default 1024, 0 disables it).
+
+use crate::etl::Block;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_BLOCK_CACHE_BLOCKS: usize = 1024;
+
+/// Capacity from `BLOCK_CACHE_BLOCKS`
+pub fn capacity_from_env() -> usize {
+    std::env::var("BLOCK_CACHE_BLOCKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BLOCK_CACHE_BLOCKS)
+}
+
+/// Hit and miss counters, served on `/stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl BlockCacheStats {
+    /// Fraction of lookups served from the cache, 0.0 to 1.0
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockCache {
+    capacity: usize,
+    /// index -> (block, last use)
+    blocks: HashMap<u64, (Block, u64)>,
+    by_hash: HashMap<String, u64>,
+    /// last use -> index, oldest first
+    recency: BTreeMap<u64, u64>,
+    clock: u64,
+    /// Index of the newest block, while known
+    tip: Option<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            blocks: HashMap::new(),
+            by_hash: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            tip: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.blocks.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    fn touch(&mut self, index: u64) -> Option<Block> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (block, used) = self.blocks.get_mut(&index)?;
+        self.recency.remove(used);
+        *used = clock;
+        self.recency.insert(clock, index);
+        Some(block.clone())
+    }
+
+    fn count(&mut self, found: Option<Block>) -> Option<Block> {
+        if found.is_some() {
+            self.hits += 1;
+        } else if self.is_enabled() {
+            self.misses += 1;
+        }
+        found
+    }
+
+    pub fn get_by_index(&mut self, index: u64) -> Option<Block> {
+        let found = self.touch(index);
+        self.count(found)
+    }
+
+    pub fn get_by_hash(&mut self, hash: &str) -> Option<Block> {
+        let found = match self.by_hash.get(hash) {
+            Some(&index) => self.touch(index),
+            None => None,
+        };
+        self.count(found)
+    }
+
+    pub fn get_latest(&mut self) -> Option<Block> {
+        let found = self.tip.and_then(|tip| self.touch(tip));
+        self.count(found)
+    }
+
+    /// The newest `limit` blocks, newest first, if the tip and the blocks
+    /// directly below it are all cached
+    pub fn get_latest_n(&mut self, limit: u64) -> Option<Vec<Block>> {
+        let found = self.tip.and_then(|tip| {
+            let oldest = tip.checked_sub(limit.checked_sub(1)?)?;
+            if !(oldest..=tip).all(|index| self.blocks.contains_key(&index)) {
+                return None;
+            }
+            (oldest..=tip)
+                .rev()
+                .map(|index| self.touch(index))
+                .collect()
+        });
+        match found {
+            Some(blocks) => {
+                self.hits += 1;
+                Some(blocks)
+            }
+            None => {
+                if self.is_enabled() {
+                    self.misses += 1;
+                }
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, block: &Block) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.remove(block.index);
+        while self.blocks.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.remove(oldest);
+        }
+        self.clock += 1;
+        self.by_hash.insert(block.hash.clone(), block.index);
+        self.recency.insert(self.clock, block.index);
+        self.blocks.insert(block.index, (block.clone(), self.clock));
+    }
+
+    /// Cache `block` as the newest block of the chain
+    pub fn insert_latest(&mut self, block: &Block) {
+        if self.is_enabled() {
+            self.insert(block);
+            self.tip = Some(block.index);
+        }
+    }
+
+    fn remove(&mut self, index: u64) {
+        if let Some((block, used)) = self.blocks.remove(&index) {
+            self.recency.remove(&used);
+            if self.by_hash.get(&block.hash) == Some(&index) {
+                self.by_hash.remove(&block.hash);
+            }
+        }
+    }
+
+    /// Drop the blocks whose index is in `range` and forget the tip
+    pub fn invalidate(&mut self, range: impl std::ops::RangeBounds<u64>) {
+        let stale: Vec<u64> = self
+            .blocks
+            .keys()
+            .copied()
+            .filter(|index| range.contains(index))
+            .collect();
+        for index in stale {
+            self.remove(index);
+        }
+        self.tip = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.invalidate(..);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestChainBuilder;
+
+    #[test]
+    fn test_lru_eviction_and_invalidation() {
+        let chain = TestChainBuilder::new().with_blocks(4).build();
+        let mut cache = BlockCache::new(3);
+        for block in &chain[..3] {
+            cache.insert(block);
+        }
+        // Using block 1 makes block 2 the least recently used
+        assert!(cache.get_by_index(1).is_some());
+        cache.insert_latest(&chain[3]);
+        assert!(cache.get_by_hash(&chain[1].hash).is_none());
+        assert_eq!(cache.get_latest().unwrap().index, 4);
+        assert_eq!(
+            cache
+                .get_latest_n(2)
+                .unwrap()
+                .iter()
+                .map(|b| b.index)
+                .collect::<Vec<_>>(),
+            [4, 3]
+        );
+        assert!(cache.get_latest_n(4).is_none());
+
+        cache.invalidate(3..);
+        assert!(cache.get_latest().is_none());
+        assert!(cache.get_by_index(3).is_none());
+        assert_eq!(cache.get_by_hash(&chain[0].hash).unwrap().index, 1);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.entries, stats.capacity), (4, 1, 3));
+        assert_eq!(stats.misses, 4);
+
+        let mut disabled = BlockCache::new(0);
+        disabled.insert_latest(&chain[0]);
+        assert!(disabled.get_latest().is_none());
+        assert_eq!(disabled.stats(), BlockCacheStats::default());
+    }
+}
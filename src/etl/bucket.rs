@@ -0,0 +1,254 @@
+//! Time-bucket aggregation
+//!
+//! A source polled or streamed every few seconds would otherwise put every
+//! quote in a block of its own. With `TIME_BUCKET_SECS` set, `BucketStage`
+//! holds each asset's quotes until their bucket (`TIME_BUCKET_SECS` wide,
+//! aligned to the Unix epoch) is over and then lets one record through for
+//! the whole bucket: priced at the mean of its quotes, stamped with the
+//! bucket's start and carrying open, high, low and close in
+//! `MarketData::bucket`. Held quotes come back with `is_buffered` set and
+//! produce no entry.
+//!
+//! A bucket is closed by the asset's first quote in a later bucket, so the
+//! entry for a minute is written once the next minute's first quote is in.
+//! The emitted record keeps the source, volume sum and provenance of the
+//! bucket's last quote, with a `bucketed` step replacing its price by the
+//! mean. Deduplication runs first, so high-frequency sources want a short
+//! deduplication window or `DEDUP_STRATEGY=content`.
+
+use crate::etl::pipeline::{StageContext, TransformStage};
+use crate::etl::price::{self, Decimal};
+use crate::etl::provenance::CustodyStep;
+use crate::etl::transform::TransformResult;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Decimal places a bucket's mean is rounded to before normalization
+pub const BUCKET_DECIMALS: u32 = 8;
+
+/// Quotes one entry stands for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Unix milliseconds, inclusive
+    pub start: i64,
+    /// Unix milliseconds, exclusive
+    pub end: i64,
+    pub count: u64,
+    #[serde(with = "price::serde_number")]
+    pub open: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub high: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub low: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub close: Decimal,
+    /// Mean of the quotes, rounded to `BUCKET_DECIMALS`
+    #[serde(with = "price::serde_number")]
+    pub mean: Decimal,
+}
+
+impl Bucket {
+    /// Summary of `prices` in arrival order; `None` when there are none or
+    /// their sum overflows
+    pub fn summarize(start: i64, end: i64, prices: &[Decimal]) -> Option<Self> {
+        let (open, close) = (*prices.first()?, *prices.last()?);
+        let mut sum = Decimal::ZERO;
+        for price in prices {
+            sum = sum.checked_add(*price)?;
+        }
+        Some(Bucket {
+            start,
+            end,
+            count: prices.len() as u64,
+            open,
+            high: prices.iter().copied().max()?,
+            low: prices.iter().copied().min()?,
+            close,
+            mean: price::round(
+                sum.checked_div(Decimal::from(prices.len()))?,
+                BUCKET_DECIMALS,
+            ),
+        })
+    }
+}
+
+/// An asset's quotes in the bucket still open
+struct OpenBucket {
+    start: i64,
+    prices: Vec<Decimal>,
+    volume: Option<Decimal>,
+    last: TransformResult,
+}
+
+impl OpenBucket {
+    fn new(start: i64, record: &TransformResult) -> Self {
+        OpenBucket {
+            start,
+            prices: vec![record.price],
+            volume: record.volume,
+            last: record.clone(),
+        }
+    }
+
+    fn add(&mut self, record: &TransformResult) {
+        self.prices.push(record.price);
+        if let Some(volume) = record.volume {
+            self.volume = Some(self.volume.unwrap_or_default().saturating_add(volume));
+        }
+        self.last = record.clone();
+    }
+
+    /// The record standing for the bucket
+    fn close(self, width_ms: i64) -> Result<TransformResult, String> {
+        let bucket = Bucket::summarize(self.start, self.start + width_ms, &self.prices)
+            .ok_or_else(|| format!("{} bucket at {} overflows", self.last.asset, self.start))?;
+        let mut record = self.last;
+        record.provenance.push(CustodyStep::Bucketed {
+            start: bucket.start,
+            count: bucket.count,
+            before: record.price,
+            after: bucket.mean,
+        });
+        record.price = bucket.mean;
+        record.timestamp = bucket.start;
+        record.volume = self.volume;
+        record.bucket = Some(bucket);
+        Ok(record)
+    }
+}
+
+/// Holds quotes until their time bucket closes, then emits one record per
+/// bucket
+#[derive(Clone)]
+pub struct BucketStage {
+    width_ms: i64,
+    /// Open bucket per asset; shared by clones
+    open: Arc<Mutex<HashMap<String, OpenBucket>>>,
+}
+
+impl BucketStage {
+    pub fn new(seconds: u64) -> Self {
+        BucketStage {
+            width_ms: (seconds.max(1) as i64).saturating_mul(1000),
+            open: Arc::default(),
+        }
+    }
+
+    /// `None` unless `TIME_BUCKET_SECS` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(seconds) = std::env::var("TIME_BUCKET_SECS") else {
+            return Ok(None);
+        };
+        let seconds = seconds
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .ok_or_else(|| {
+                format!(
+                    "invalid TIME_BUCKET_SECS: '{}' is not a positive number of seconds",
+                    seconds
+                )
+            })?;
+        Ok(Some(Self::new(seconds)))
+    }
+
+    pub fn width_seconds(&self) -> i64 {
+        self.width_ms / 1000
+    }
+
+    /// Start of the bucket `timestamp` falls in
+    pub fn bucket_start(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.width_ms)
+    }
+}
+
+impl TransformStage for BucketStage {
+    fn name(&self) -> &str {
+        "bucket"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        let start = self.bucket_start(record.timestamp);
+        let mut open = self.open.lock();
+        match open.get_mut(&record.asset) {
+            Some(bucket) if bucket.start == start => {
+                bucket.add(record);
+                record.is_buffered = true;
+            }
+            Some(bucket) if start < bucket.start => {
+                return Err(format!(
+                    "{} quote at {} is older than its open bucket at {}",
+                    record.asset, record.timestamp, bucket.start
+                )
+                .into());
+            }
+            _ => match open.insert(record.asset.clone(), OpenBucket::new(start, record)) {
+                Some(closed) => *record = closed.close(self.width_ms)?,
+                None => record.is_buffered = true,
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emits_one_record_per_bucket() {
+        let stage = BucketStage::new(60);
+        let quote = |price: i64, timestamp: i64| {
+            let mut record =
+                TransformResult::raw("BTC", Decimal::from(price), "Kraken".into(), timestamp);
+            record.volume = Some(Decimal::ONE);
+            stage.apply(&mut record, &StageContext::default()).unwrap();
+            record
+        };
+        assert!(quote(100, 60_000).is_buffered);
+        assert!(quote(104, 75_000).is_buffered);
+        assert!(quote(99, 119_999).is_buffered);
+
+        // The next minute's first quote closes the first minute
+        let closed = quote(120, 120_000);
+        assert!(!closed.is_buffered);
+        assert_eq!(
+            (closed.price, closed.timestamp),
+            (Decimal::from(101), 60_000)
+        );
+        assert_eq!(closed.volume, Some(Decimal::from(3)));
+        let bucket = closed.bucket.unwrap();
+        assert_eq!(
+            (bucket.start, bucket.end, bucket.count),
+            (60_000, 120_000, 3)
+        );
+        assert_eq!(
+            [bucket.open, bucket.high, bucket.low, bucket.close],
+            [100, 104, 99, 99].map(Decimal::from)
+        );
+        assert_eq!(closed.provenance.step_names(), vec!["bucketed:3"]);
+        let entry = crate::etl::MarketData {
+            asset: closed.asset.clone(),
+            price: closed.price,
+            source: closed.source.clone(),
+            timestamp: closed.timestamp,
+            provenance: Some(closed.provenance.clone()),
+            indicators: None,
+            conversion: None,
+            bucket: Some(bucket),
+        };
+        assert_eq!(closed.provenance.verify(&entry), Ok(()));
+
+        // A late quote cannot reopen a closed bucket
+        let mut late = TransformResult::raw("BTC", Decimal::from(1), "Kraken".into(), 90_000);
+        assert!(stage.apply(&mut late, &StageContext::default()).is_err());
+        // Each asset has buckets of its own
+        let mut eth = TransformResult::raw("ETH", Decimal::from(3), "Kraken".into(), 130_000);
+        stage.apply(&mut eth, &StageContext::default()).unwrap();
+        assert!(eth.is_buffered);
+    }
+}
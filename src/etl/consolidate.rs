@@ -0,0 +1,284 @@
+//! Weighted consolidation of multi-source quotes
+//!
+//! An aggregated price is the median (or trimmed mean) of whatever each
+//! source quoted, however little traded there. Index providers instead build
+//! reference rates by weighting each venue by its volume or liquidity;
+//! `ConsolidateStage` does the same in the transform layer. When at least
+//! two of a record's source quotes fall within the consolidation window of
+//! the newest one and carry a weight, the record's price becomes their
+//! weighted mean, and the quotes with their weights are recorded in the
+//! provenance (`CustodyStep::Consolidated`) next to the aggregated quotes.
+//! Otherwise the aggregated price stands.
+//!
+//! Weights are either the 24h volume each source reported in the base
+//! asset (`volume`; see `sources` for which APIs report one, sources
+//! without one are left out) or fixed per-source liquidity weights
+//! (`liquidity`). Configured with `CONSOLIDATION_METHOD`,
+//! `CONSOLIDATION_WEIGHTS` (`Source=weight,...`, for `liquidity`) and
+//! `CONSOLIDATION_WINDOW_MS` (default 5000).
+
+use crate::etl::divergence::SourceQuote;
+use crate::etl::pipeline::{StageContext, TransformStage};
+use crate::etl::price::{self, Decimal};
+use crate::etl::provenance::CustodyStep;
+use crate::etl::transform::TransformResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Quotes further than this behind the newest one are left out
+pub const DEFAULT_WINDOW_MS: i64 = 5_000;
+
+/// Decimal places a consolidated price is rounded to before normalization
+pub const CONSOLIDATED_DECIMALS: u32 = 8;
+
+/// How each source's quote is weighted
+#[derive(Debug, Clone, PartialEq)]
+pub enum Weighting {
+    /// By the 24h volume the source reported with its quote
+    Volume,
+    /// By a fixed weight per source (keyed by lower-cased source name);
+    /// sources without one are left out
+    Liquidity(BTreeMap<String, Decimal>),
+}
+
+impl Weighting {
+    /// Parse `volume` or `liquidity`, the latter with `weights`
+    /// (`Source=weight,...`)
+    pub fn parse(method: &str, weights: Option<&str>) -> Result<Self, String> {
+        match method.trim().to_ascii_lowercase().as_str() {
+            "volume" => Ok(Weighting::Volume),
+            "liquidity" => {
+                let weights = parse_weights(weights.unwrap_or(""))?;
+                if weights.is_empty() {
+                    return Err("liquidity weighting needs CONSOLIDATION_WEIGHTS".to_string());
+                }
+                Ok(Weighting::Liquidity(weights))
+            }
+            other => Err(format!(
+                "unknown consolidation method '{}' (expected volume or liquidity)",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weighting::Volume => "volume",
+            Weighting::Liquidity(_) => "liquidity",
+        }
+    }
+
+    fn weight(&self, quote: &SourceQuote) -> Option<Decimal> {
+        match self {
+            Weighting::Volume => quote.volume,
+            Weighting::Liquidity(weights) => {
+                weights.get(&quote.source.to_ascii_lowercase()).copied()
+            }
+        }
+    }
+}
+
+/// `Source=weight` pairs with non-negative weights
+fn parse_weights(spec: &str) -> Result<BTreeMap<String, Decimal>, String> {
+    let mut weights = BTreeMap::new();
+    for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let (source, weight) = item
+            .split_once('=')
+            .filter(|(source, _)| !source.trim().is_empty())
+            .ok_or_else(|| format!("'{}' is not Source=weight", item))?;
+        let weight = price::parse(weight)
+            .ok()
+            .filter(|weight| !weight.is_sign_negative())
+            .ok_or_else(|| {
+                format!(
+                    "{} needs a non-negative weight, not '{}'",
+                    source.trim(),
+                    weight.trim()
+                )
+            })?;
+        weights.insert(source.trim().to_ascii_lowercase(), weight);
+    }
+    Ok(weights)
+}
+
+/// One source's quote and the weight it received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedQuote {
+    pub source: String,
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub weight: Decimal,
+}
+
+/// Weighted mean of `quotes` rounded to `CONSOLIDATED_DECIMALS`; `None`
+/// when the weights sum to zero or the sums overflow
+pub fn weighted_price(quotes: &[WeightedQuote]) -> Option<Decimal> {
+    let mut turnover = Decimal::ZERO;
+    let mut total = Decimal::ZERO;
+    for quote in quotes {
+        turnover = turnover.checked_add(quote.price.checked_mul(quote.weight)?)?;
+        total = total.checked_add(quote.weight)?;
+    }
+    if total.is_zero() {
+        return None;
+    }
+    Some(price::round(
+        turnover.checked_div(total)?,
+        CONSOLIDATED_DECIMALS,
+    ))
+}
+
+/// Replaces an aggregated price with the weighted mean of its source quotes
+#[derive(Debug, Clone)]
+pub struct ConsolidateStage {
+    weighting: Weighting,
+    window_ms: i64,
+}
+
+impl ConsolidateStage {
+    pub fn new(weighting: Weighting) -> Self {
+        ConsolidateStage {
+            weighting,
+            window_ms: DEFAULT_WINDOW_MS,
+        }
+    }
+
+    /// Only quotes at most `window_ms` older than the newest one count
+    pub fn with_window_ms(mut self, window_ms: i64) -> Self {
+        self.window_ms = window_ms.max(0);
+        self
+    }
+
+    /// `None` unless `CONSOLIDATION_METHOD` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(method) = std::env::var("CONSOLIDATION_METHOD") else {
+            return Ok(None);
+        };
+        let weighting = Weighting::parse(
+            &method,
+            std::env::var("CONSOLIDATION_WEIGHTS").ok().as_deref(),
+        )
+        .map_err(|e| format!("invalid CONSOLIDATION_METHOD: {}", e))?;
+        let mut stage = Self::new(weighting);
+        if let Ok(ms) = std::env::var("CONSOLIDATION_WINDOW_MS") {
+            let ms = ms
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid CONSOLIDATION_WINDOW_MS: {}", e))?;
+            stage = stage.with_window_ms(ms);
+        }
+        Ok(Some(stage))
+    }
+
+    pub fn weighting(&self) -> &Weighting {
+        &self.weighting
+    }
+
+    pub fn window_ms(&self) -> i64 {
+        self.window_ms
+    }
+
+    /// The weighted quotes within the window and their consolidated price;
+    /// `None` unless at least two quotes have a positive weight
+    pub fn consolidate(&self, quotes: &[SourceQuote]) -> Option<(Vec<WeightedQuote>, Decimal)> {
+        let newest = quotes.iter().map(|q| q.timestamp).max()?;
+        let weighted: Vec<WeightedQuote> = quotes
+            .iter()
+            .filter(|q| newest - q.timestamp <= self.window_ms)
+            .filter_map(|q| {
+                let weight = self.weighting.weight(q).filter(|w| *w > Decimal::ZERO)?;
+                Some(WeightedQuote {
+                    source: q.source.clone(),
+                    price: q.price,
+                    weight,
+                })
+            })
+            .collect();
+        if weighted.len() < 2 {
+            return None;
+        }
+        let price = weighted_price(&weighted)?;
+        Some((weighted, price))
+    }
+}
+
+impl TransformStage for ConsolidateStage {
+    fn name(&self) -> &str {
+        "consolidate"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        if let Some((quotes, price)) = self.consolidate(&record.quotes) {
+            let before = record.price;
+            record.price = price;
+            record.provenance.push(CustodyStep::Consolidated {
+                method: self.weighting.name().to_string(),
+                quotes,
+                before,
+                after: price,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(source: &str, price: i64, timestamp: i64, volume: Option<i64>) -> SourceQuote {
+        SourceQuote {
+            source: source.to_string(),
+            price: Decimal::from(price),
+            timestamp,
+            volume: volume.map(Decimal::from),
+        }
+    }
+
+    #[test]
+    fn test_volume_and_liquidity_weighted_consolidation() {
+        // As the sources report them: Kraken and Coinbase with a 24h
+        // volume, a mock without one
+        let quotes = [
+            quote("Kraken", 100, 10_000, Some(3)),
+            quote("Coinbase", 104, 9_000, Some(1)),
+            quote("MockData", 90, 10_000, None),
+            // Outside the window of the newest quote
+            quote("Stale", 50, 1_000, Some(100)),
+        ];
+        let stage = ConsolidateStage::new(Weighting::Volume).with_window_ms(2_000);
+        let (weighted, price) = stage.consolidate(&quotes).unwrap();
+        assert_eq!(
+            weighted
+                .iter()
+                .map(|q| q.source.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Kraken", "Coinbase"]
+        );
+        // (100 * 3 + 104 * 1) / 4
+        assert_eq!(price, Decimal::from(101));
+        assert_eq!(weighted_price(&weighted), Some(price));
+        // A single weighted quote leaves the aggregated price alone
+        assert!(stage.consolidate(&quotes[1..]).is_none());
+
+        let weighting =
+            Weighting::parse("liquidity", Some("kraken=1, MockData=2, coinbase=0")).unwrap();
+        let (weighted, price) = ConsolidateStage::new(weighting)
+            .consolidate(&quotes)
+            .unwrap();
+        assert_eq!(weighted.len(), 2);
+        assert_eq!(price, price::parse("93.33333333").unwrap());
+
+        let mut record = TransformResult::raw("BTC", Decimal::from(100), "Aggregate".into(), 0);
+        record.quotes = quotes.to_vec();
+        stage.apply(&mut record, &StageContext::default()).unwrap();
+        assert_eq!(record.price, Decimal::from(101));
+        assert_eq!(record.provenance.step_names(), vec!["consolidated:volume"]);
+
+        assert!(Weighting::parse("liquidity", None).is_err());
+        assert!(Weighting::parse("liquidity", Some("kraken=-1")).is_err());
+        assert!(Weighting::parse("vwap", None).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Quote currency conversion
+//!
+//! Sources quote in USD. A ledger kept in another currency sets
+//! `CONVERSION_CURRENCY` (e.g. `EUR`), and `ConversionStage` converts each
+//! record's price after deduplication, before anomaly detection and
+//! normalization, so both see the converted price. The entry stores the
+//! converted price and keeps the original one with the rate in
+//! `MarketData::conversion`; its provenance records a `converted` step.
+//!
+//! Rates come from a `RateTable`: fixed rates from `CONVERSION_RATES`
+//! (`EUR=0.92,...`, units per `CONVERSION_FROM`, default USD), updated by
+//! the FX pairs the node quotes itself (an `ASSET_SOURCES` entry such as
+//! `EURUSD:alphavantage` with `EURUSD=fx:EUR/USD` in `ASSET_SYMBOLS`).
+//! A record without a rate is rejected rather than stored in the wrong
+//! currency. FX pairs are never converted.
+
+use crate::etl::pipeline::{StageContext, TransformStage};
+use crate::etl::price::{self, Decimal};
+use crate::etl::provenance::CustodyStep;
+use crate::etl::symbols::{AssetClass, SymbolMap};
+use crate::etl::transform::TransformResult;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Currency sources quote in unless `CONVERSION_FROM` says otherwise
+pub const DEFAULT_FROM: &str = "USD";
+
+/// Decimal places a converted price is rounded to before normalization
+pub const CONVERTED_DECIMALS: u32 = 8;
+
+/// Source name of rates given in `CONVERSION_RATES`
+pub const FIXED_RATE_SOURCE: &str = "fixed";
+
+/// Original price and the rate an entry's price was converted with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub from: String,
+    pub to: String,
+    #[serde(with = "price::serde_number")]
+    pub original_price: Decimal,
+    /// Units of `to` per unit of `from`
+    #[serde(with = "price::serde_number")]
+    pub rate: Decimal,
+    /// `fixed`, or the FX asset and source the rate was quoted by
+    pub rate_source: String,
+    /// When the rate was quoted, in Unix milliseconds; 0 for fixed rates
+    pub rate_timestamp: i64,
+}
+
+/// `before * rate` rounded to `CONVERTED_DECIMALS`; `None` on overflow
+pub fn convert(before: Decimal, rate: Decimal) -> Option<Decimal> {
+    Some(price::round(before.checked_mul(rate)?, CONVERTED_DECIMALS))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub rate: Decimal,
+    pub source: String,
+    pub timestamp: i64,
+}
+
+/// Latest rate from the base currency into each other currency; shared by
+/// clones
+#[derive(Debug, Clone)]
+pub struct RateTable {
+    base: String,
+    rates: Arc<RwLock<BTreeMap<String, Rate>>>,
+}
+
+impl RateTable {
+    pub fn new(base: &str) -> Self {
+        RateTable {
+            base: base.trim().to_ascii_uppercase(),
+            rates: Arc::default(),
+        }
+    }
+
+    /// Fixed rates from `CURRENCY=rate,...`
+    pub fn with_fixed_rates(self, spec: &str) -> Result<Self, String> {
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (currency, rate) = item
+                .split_once('=')
+                .filter(|(currency, _)| !currency.trim().is_empty())
+                .ok_or_else(|| format!("'{}' is not CURRENCY=rate", item))?;
+            let rate = price::parse(rate)
+                .ok()
+                .filter(|rate| *rate > Decimal::ZERO)
+                .ok_or_else(|| {
+                    format!(
+                        "{} needs a positive rate, not '{}'",
+                        currency.trim(),
+                        rate.trim()
+                    )
+                })?;
+            self.set(currency, rate, FIXED_RATE_SOURCE, 0);
+        }
+        Ok(self)
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn set(&self, currency: &str, rate: Decimal, source: &str, timestamp: i64) {
+        self.rates.write().insert(
+            currency.trim().to_ascii_uppercase(),
+            Rate {
+                rate,
+                source: source.to_string(),
+                timestamp,
+            },
+        );
+    }
+
+    /// Units of `currency` per unit of the base currency
+    pub fn get(&self, currency: &str) -> Option<Rate> {
+        let currency = currency.trim().to_ascii_uppercase();
+        if currency == self.base {
+            return Some(Rate {
+                rate: Decimal::ONE,
+                source: "identity".to_string(),
+                timestamp: 0,
+            });
+        }
+        self.rates.read().get(&currency).cloned()
+    }
+
+    /// Take the rate from a quote of `asset` when `symbols` maps it to an
+    /// FX pair against the base currency, e.g. `EUR/USD` at 1.08 sets EUR
+    /// to 1/1.08; returns whether it did
+    pub fn observe(
+        &self,
+        symbols: &SymbolMap,
+        asset: &str,
+        quote: Decimal,
+        source: &str,
+        timestamp: i64,
+    ) -> bool {
+        let mapping = symbols.resolve(asset);
+        let Some((base, counter)) = mapping.currency_pair() else {
+            return false;
+        };
+        if mapping.class != AssetClass::Fx || quote <= Decimal::ZERO {
+            return false;
+        }
+        let source = format!("{}@{}", asset.to_ascii_uppercase(), source);
+        let (currency, rate) = if counter.eq_ignore_ascii_case(&self.base) {
+            match Decimal::ONE.checked_div(quote) {
+                Some(rate) => (base, rate),
+                None => return false,
+            }
+        } else if base.eq_ignore_ascii_case(&self.base) {
+            (counter, quote)
+        } else {
+            return false;
+        };
+        self.set(currency, rate, &source, timestamp);
+        true
+    }
+}
+
+/// Converts prices into the configured quote currency
+#[derive(Debug, Clone)]
+pub struct ConversionStage {
+    to: String,
+    rates: RateTable,
+    symbols: SymbolMap,
+}
+
+impl ConversionStage {
+    pub fn new(to: &str, rates: RateTable) -> Self {
+        ConversionStage {
+            to: to.trim().to_ascii_uppercase(),
+            rates,
+            symbols: SymbolMap::new(),
+        }
+    }
+
+    /// How FX pairs, which are left alone, are told from other assets
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// `None` unless `CONVERSION_CURRENCY` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(to) = std::env::var("CONVERSION_CURRENCY") else {
+            return Ok(None);
+        };
+        if to.trim().is_empty() {
+            return Err("invalid CONVERSION_CURRENCY: empty currency".to_string());
+        }
+        let from = std::env::var("CONVERSION_FROM").unwrap_or_else(|_| DEFAULT_FROM.to_string());
+        let rates = RateTable::new(&from)
+            .with_fixed_rates(&std::env::var("CONVERSION_RATES").unwrap_or_default())
+            .map_err(|e| format!("invalid CONVERSION_RATES: {}", e))?;
+        Ok(Some(Self::new(&to, rates)))
+    }
+
+    pub fn source_currency(&self) -> &str {
+        self.rates.base()
+    }
+
+    pub fn to_currency(&self) -> &str {
+        &self.to
+    }
+
+    /// The table rates are read from; FX quotes observed into it take
+    /// effect for the next record
+    pub fn rates(&self) -> &RateTable {
+        &self.rates
+    }
+
+    pub fn symbols(&self) -> &SymbolMap {
+        &self.symbols
+    }
+}
+
+impl TransformStage for ConversionStage {
+    fn name(&self) -> &str {
+        "convert"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        if self.symbols.class_of(&record.asset) == AssetClass::Fx {
+            return Ok(());
+        }
+        let from = self.rates.base().to_string();
+        let rate = self.rates.get(&self.to).ok_or_else(|| {
+            format!(
+                "no {} to {} rate to convert {}",
+                from, self.to, record.asset
+            )
+        })?;
+        let before = record.price;
+        let after = convert(before, rate.rate)
+            .ok_or_else(|| format!("{} {} overflows at rate {}", before, from, rate.rate))?;
+        record.price = after;
+        record.provenance.push(CustodyStep::Converted {
+            from: from.clone(),
+            to: self.to.clone(),
+            rate: rate.rate,
+            before,
+            after,
+        });
+        record.conversion = Some(Conversion {
+            from,
+            to: self.to.clone(),
+            original_price: before,
+            rate: rate.rate,
+            rate_source: rate.source,
+            rate_timestamp: rate.timestamp,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converts_with_fixed_and_quoted_rates() {
+        let rates = RateTable::new("usd")
+            .with_fixed_rates("EUR=0.9, GBP=0.8")
+            .unwrap();
+        let symbols = SymbolMap::parse("EURUSD=fx:EUR/USD,USDJPY=fx:USD/JPY").unwrap();
+        let stage = ConversionStage::new("eur", rates.clone()).with_symbols(symbols.clone());
+
+        let mut record = TransformResult::raw("BTC", Decimal::from(50_000), "Kraken".into(), 0);
+        stage.apply(&mut record, &StageContext::default()).unwrap();
+        assert_eq!(record.price, Decimal::from(45_000));
+        let conversion = record.conversion.clone().unwrap();
+        assert_eq!(
+            (conversion.original_price, conversion.rate_source.as_str()),
+            (Decimal::from(50_000), FIXED_RATE_SOURCE)
+        );
+        assert_eq!(record.provenance.step_names(), vec!["converted:USD/EUR"]);
+
+        // A quoted pair replaces the fixed rate
+        assert!(rates.observe(&symbols, "EURUSD", Decimal::from(2), "AlphaVantage", 7));
+        assert!(rates.observe(&symbols, "USDJPY", Decimal::from(150), "AlphaVantage", 7));
+        assert!(!rates.observe(&symbols, "BTC", Decimal::from(50_000), "Kraken", 7));
+        assert_eq!(rates.get("JPY").unwrap().rate, Decimal::from(150));
+        let mut record = TransformResult::raw("ETH", Decimal::from(3_000), "Kraken".into(), 0);
+        stage.apply(&mut record, &StageContext::default()).unwrap();
+        assert_eq!(record.price, Decimal::from(1_500));
+        assert_eq!(
+            record.conversion.unwrap().rate_source,
+            "EURUSD@AlphaVantage"
+        );
+
+        // FX pairs stay as quoted
+        let mut pair = TransformResult::raw("EURUSD", Decimal::from(2), "Alpha".into(), 0);
+        stage.apply(&mut pair, &StageContext::default()).unwrap();
+        assert!(pair.conversion.is_none());
+
+        // No rate, no entry
+        let chf = ConversionStage::new("CHF", rates);
+        let mut record = TransformResult::raw("BTC", Decimal::from(50_000), "Kraken".into(), 0);
+        assert!(chf.apply(&mut record, &StageContext::default()).is_err());
+        assert!(RateTable::new("USD").with_fixed_rates("EUR=-1").is_err());
+    }
+}
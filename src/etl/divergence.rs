@@ -0,0 +1,204 @@
+//! Detection of sources disagreeing on a price
+//!
+//! When a block carries quotes for the same asset from several sources,
+//! `DivergenceDetector` compares them. If the spread between the lowest and
+//! highest quote exceeds the threshold (as a percentage of the median), a
+//! `DivergenceEvent` listing every source's quote is stored in the block
+//! (and covered by its hash), so consumers of the ledger can see when and how
+//! the feeds disagreed.
+//!
+//! Configured with `DIVERGENCE_THRESHOLD_PCT` (enables detection).
+
+use crate::etl::price::{self, Decimal};
+use crate::etl::MarketData;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// One source's price for an asset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceQuote {
+    pub source: String,
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    /// Traded volume behind the quote, for sources that report one
+    #[serde(
+        default,
+        with = "price::serde_number_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub volume: Option<Decimal>,
+}
+
+/// Median of non-empty, ascending `prices`
+pub fn median_of_sorted(prices: &[Decimal]) -> Decimal {
+    let mid = prices.len() / 2;
+    if prices.len().is_multiple_of(2) {
+        (prices[mid - 1] + prices[mid]) / Decimal::TWO
+    } else {
+        prices[mid]
+    }
+}
+
+/// Sources that disagreed on an asset's price beyond the threshold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DivergenceEvent {
+    pub asset: String,
+    /// Every quote seen for the asset, ordered by source
+    pub quotes: Vec<SourceQuote>,
+    #[serde(with = "price::serde_number")]
+    pub median_price: Decimal,
+    /// (max - min) / median, in percent
+    pub spread_pct: f64,
+    /// Threshold in force when the event was recorded
+    pub threshold_pct: f64,
+}
+
+impl DivergenceEvent {
+    /// Quote furthest from the median
+    pub fn outlier(&self) -> Option<&SourceQuote> {
+        self.quotes.iter().max_by(|a, b| {
+            let da = (a.price - self.median_price).abs();
+            let db = (b.price - self.median_price).abs();
+            da.cmp(&db)
+        })
+    }
+}
+
+/// Flags assets whose sources disagree by more than `threshold_pct`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DivergenceDetector {
+    pub threshold_pct: f64,
+}
+
+impl DivergenceDetector {
+    pub fn new(threshold_pct: f64) -> Self {
+        DivergenceDetector { threshold_pct }
+    }
+
+    /// Read `DIVERGENCE_THRESHOLD_PCT`; `None` when unset or not positive
+    pub fn from_env() -> Option<Self> {
+        std::env::var("DIVERGENCE_THRESHOLD_PCT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&pct: &f64| pct > 0.0)
+            .map(DivergenceDetector::new)
+    }
+
+    /// Compare the quotes for one asset; needs at least two sources
+    pub fn check(&self, asset: &str, quotes: &[SourceQuote]) -> Option<DivergenceEvent> {
+        let mut quotes = quotes.to_vec();
+        quotes.sort_by(|a, b| a.source.cmp(&b.source).then(a.timestamp.cmp(&b.timestamp)));
+        let sources: BTreeSet<&str> = quotes.iter().map(|q| q.source.as_str()).collect();
+        if sources.len() < 2 {
+            return None;
+        }
+
+        let mut prices: Vec<Decimal> = quotes.iter().map(|q| q.price).collect();
+        prices.sort();
+        let median = median_of_sorted(&prices);
+        if median <= Decimal::ZERO {
+            return None;
+        }
+        let spread = price::to_f64(prices[prices.len() - 1] - prices[0]);
+        let spread_pct = spread / price::to_f64(median) * 100.0;
+        if spread_pct <= self.threshold_pct {
+            return None;
+        }
+
+        Some(DivergenceEvent {
+            asset: asset.to_string(),
+            quotes,
+            median_price: median,
+            spread_pct,
+            threshold_pct: self.threshold_pct,
+        })
+    }
+
+    /// Check every asset in a block's entries, in asset order
+    pub fn scan(&self, data: &[MarketData]) -> Vec<DivergenceEvent> {
+        let mut by_asset: BTreeMap<&str, Vec<SourceQuote>> = BTreeMap::new();
+        for item in data {
+            by_asset
+                .entry(item.asset.as_str())
+                .or_default()
+                .push(SourceQuote {
+                    source: item.source.clone(),
+                    price: item.price,
+                    timestamp: item.timestamp,
+                    volume: None,
+                });
+        }
+        by_asset
+            .into_iter()
+            .filter_map(|(asset, quotes)| self.check(asset, &quotes))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn entry(asset: &str, source: &str, price: i64) -> MarketData {
+        testing::entry(
+            asset,
+            source,
+            Decimal::from(price),
+            testing::BASE_TIMESTAMP_MS,
+        )
+    }
+
+    #[test]
+    fn test_scan_flags_only_assets_beyond_threshold() {
+        let detector = DivergenceDetector::new(1.0);
+        let data = vec![
+            entry("BTC", "CoinGecko", 50_000),
+            entry("BTC", "Kraken", 50_100),
+            entry("BTC", "Feed", 52_000),
+            entry("ETH", "CoinGecko", 3_000),
+            entry("ETH", "Kraken", 3_010),
+            // One source alone cannot diverge
+            entry("SOL", "Feed", 100),
+            entry("SOL", "Feed", 200),
+        ];
+
+        let events = detector.scan(&data);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.asset, "BTC");
+        assert_eq!(event.median_price, Decimal::from(50_100));
+        assert!((event.spread_pct - 3.992).abs() < 0.01);
+        let sources: Vec<_> = event.quotes.iter().map(|q| q.source.as_str()).collect();
+        assert_eq!(sources, ["CoinGecko", "Feed", "Kraken"]);
+        assert_eq!(event.outlier().unwrap().source, "Feed");
+
+        assert!(DivergenceDetector::new(5.0).scan(&data).is_empty());
+    }
+
+    #[test]
+    fn test_divergences_are_hashed_and_stored() {
+        use crate::etl::load::DatabaseManager;
+
+        let data = vec![
+            entry("BTC", "CoinGecko", 50_000),
+            entry("BTC", "Feed", 55_000),
+        ];
+        let divergences = DivergenceDetector::new(1.0).scan(&data);
+        let mut block = testing::block(1, testing::GENESIS_PARENT_HASH, data);
+        block.divergences = divergences;
+        block.calculate_hash_with_nonce();
+        let mut stripped = block.clone();
+        stripped.divergences.clear();
+        assert_ne!(stripped.calculate_hash(), block.hash);
+
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        db.save_block(&block).unwrap();
+        let stored = db.get_block_by_index(1).unwrap();
+        assert_eq!(stored.divergences, block.divergences);
+        assert_eq!(stored.calculate_hash(), block.hash);
+    }
+}
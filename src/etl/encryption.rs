@@ -0,0 +1,196 @@
+//! Encryption of block payloads at rest
+//!
+//! With `PAYLOAD_ENCRYPTION_KEYS` set, every payload column of the ledger
+//! (entries, fees, divergence events and order books) and each quarantined
+//! block hold AES-256-GCM ciphertext instead of JSON, so price data can sit
+//! on shared infrastructure. Only storage changes: block hashes are
+//! computed over the plaintext, so encrypted and plaintext nodes agree on
+//! every hash, and `DatabaseManager` decrypts on read.
+//!
+//! The variable is a comma-separated keyring of `id:hex` pairs (32-byte
+//! keys). The first key encrypts new blocks; the others only decrypt. To
+//! rotate, put the new key first and keep the old one after it: on startup
+//! `DatabaseManager::reencrypt_payloads` rewrites every row under the new
+//! key, after which the old key can be dropped.
+//!
+//! Stored values are `enc1:<key id>:<hex nonce + ciphertext>`, with the
+//! block hash as associated data so a payload cannot be moved to another
+//! block. Rows without the prefix are plaintext and read as before.
+//!
+//! Consensus messages queued in the outbox carry block data as well, so
+//! their payloads are encrypted the same way, with the peer they are
+//! addressed to as associated data.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::fmt;
+
+pub const ENCRYPTED_PREFIX: &str = "enc1:";
+
+const NONCE_LEN: usize = 12;
+
+pub struct PayloadCipher {
+    /// Active key first
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<&str> = self.keys.iter().map(|(id, _)| id.as_str()).collect();
+        f.debug_struct("PayloadCipher").field("keys", &ids).finish()
+    }
+}
+
+fn check_key_id(key_id: &str) -> Result<(), String> {
+    if key_id.is_empty() || key_id.contains([':', ',']) {
+        return Err(format!("invalid payload key id '{}'", key_id));
+    }
+    Ok(())
+}
+
+impl PayloadCipher {
+    /// Encrypt new payloads with `key`
+    pub fn new(key_id: &str, key: [u8; 32]) -> Result<Self, String> {
+        check_key_id(key_id)?;
+        Ok(PayloadCipher {
+            keys: vec![(
+                key_id.to_string(),
+                Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            )],
+        })
+    }
+
+    /// Also decrypt payloads written under an older key
+    pub fn with_retired_key(mut self, key_id: &str, key: [u8; 32]) -> Result<Self, String> {
+        check_key_id(key_id)?;
+        if self.keys.iter().any(|(id, _)| id == key_id) {
+            return Err(format!("payload key id '{}' is listed twice", key_id));
+        }
+        self.keys.push((
+            key_id.to_string(),
+            Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        ));
+        Ok(self)
+    }
+
+    /// Keyring from `PAYLOAD_ENCRYPTION_KEYS`; `None` when unset
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("PAYLOAD_ENCRYPTION_KEYS") {
+            Ok(keys) if !keys.trim().is_empty() => Self::parse_keyring(&keys).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse `id:hex,id:hex`, active key first
+    pub fn parse_keyring(keyring: &str) -> Result<Self, String> {
+        let mut cipher: Option<Self> = None;
+        for entry in keyring.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key_id, key_hex) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("payload key '{}' is not id:hex", entry))?;
+            let key: [u8; 32] = hex::decode(key_hex.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("payload key '{}' must be 64 hex characters", key_id))?;
+            cipher = Some(match cipher {
+                None => Self::new(key_id.trim(), key)?,
+                Some(cipher) => cipher.with_retired_key(key_id.trim(), key)?,
+            });
+        }
+        cipher.ok_or_else(|| "PAYLOAD_ENCRYPTION_KEYS lists no keys".to_string())
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.keys[0].0
+    }
+
+    pub fn is_encrypted(stored: &str) -> bool {
+        stored.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    /// Id of the key a stored payload was encrypted under
+    pub fn key_id(stored: &str) -> Option<&str> {
+        stored
+            .strip_prefix(ENCRYPTED_PREFIX)?
+            .split_once(':')
+            .map(|(id, _)| id)
+    }
+
+    /// Encrypt `plaintext` under the active key, bound to `aad`
+    pub fn encrypt(&self, plaintext: &str, aad: &[u8]) -> Result<String, String> {
+        let (key_id, cipher) = &self.keys[0];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad,
+                },
+            )
+            .map_err(|_| "payload encryption failed".to_string())?;
+        Ok(format!(
+            "{}{}:{}{}",
+            ENCRYPTED_PREFIX,
+            key_id,
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        ))
+    }
+
+    pub fn decrypt(&self, stored: &str, aad: &[u8]) -> Result<String, String> {
+        let key_id = Self::key_id(stored).ok_or("payload is not encrypted")?;
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .ok_or_else(|| format!("payload key '{}' is not in the keyring", key_id))?;
+        let bytes = hex::decode(&stored[ENCRYPTED_PREFIX.len() + key_id.len() + 1..])
+            .map_err(|_| "encrypted payload is not hex".to_string())?;
+        if bytes.len() < NONCE_LEN {
+            return Err("encrypted payload is truncated".to_string());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| format!("payload does not decrypt under key '{}'", key_id))?;
+        String::from_utf8(plaintext).map_err(|_| "decrypted payload is not UTF-8".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyring_round_trip_and_rotation() {
+        let old = PayloadCipher::parse_keyring(&format!("k1:{}", "11".repeat(32))).unwrap();
+        let stored = old.encrypt("[1,2,3]", b"hash").unwrap();
+        assert!(PayloadCipher::is_encrypted(&stored));
+        assert_eq!(PayloadCipher::key_id(&stored), Some("k1"));
+        assert_eq!(old.decrypt(&stored, b"hash").unwrap(), "[1,2,3]");
+        // Bound to the block it was written for
+        assert!(old.decrypt(&stored, b"other").is_err());
+
+        let rotated = PayloadCipher::parse_keyring(&format!(
+            "k2:{}, k1:{}",
+            "22".repeat(32),
+            "11".repeat(32)
+        ))
+        .unwrap();
+        assert_eq!(rotated.active_key_id(), "k2");
+        assert_eq!(rotated.decrypt(&stored, b"hash").unwrap(), "[1,2,3]");
+        let reencrypted = rotated.encrypt("[1,2,3]", b"hash").unwrap();
+        assert!(old.decrypt(&reencrypted, b"hash").is_err());
+
+        assert!(PayloadCipher::parse_keyring("k1:abcd").is_err());
+        assert!(PayloadCipher::parse_keyring(&format!("k1:{0},k1:{0}", "11".repeat(32))).is_err());
+        assert!(!PayloadCipher::is_encrypted("[]"));
+    }
+}
@@ -1,9 +1,60 @@
+//! Market data extraction
+//!
+//! An `Extractor` fetches one price per round from its `DataSource`, retrying
+//! transient failures with its `RetryPolicy` and validating the result. The
+//! default source is CoinGecko's simple price endpoint (`CoinGeckoSource`);
+//! register another with `Extractor::with_source`, e.g. an internal API or a
+//! recorded tick file (`FileSource`), or pick one by name from a
+//! `sources::SourceRegistry`.
+//! `aggregate::AggregatingExtractor` combines several sources into one price.
+//! `extract_offline` uses `MockSource` instead, or a scripted
+//! `stress::StressSource`. Rather than polling, an
+//! extractor given a `stream::StreamingSource` can `stream` ticks from an
+//! exchange WebSocket feed.
+//!
+//! With a cache TTL (`with_cache_ttl`, `EXTRACT_CACHE_TTL_MS`), rounds that
+//! come within the TTL of the last successful fetch from a source reuse that
+//! result instead of calling the API again; such results have `cache_hit`
+//! set.
+//!
+//! Besides its main source, an extractor can quote further assets, each from
+//! its own source (`with_asset_source`, `MARKET_DATA_ASSETS`). `extract_assets`
+//! fetches them concurrently, at most `EXTRACT_MAX_CONCURRENCY` (default 4) at
+//! a time; a fetch holds its slot while it retries. With no more assets than
+//! the limit, the fetches take about as long as the slowest source; beyond
+//! it, they queue. The limit applies to each set of fetches on its own: the
+//! node fetches the main source alongside the assets, and an aggregated main
+//! source has a limit of its own, so up to twice the limit can be in flight.
+//!
+//! Every fetch is reported to the extractor's `ExtractionTracker`, whose
+//! `ExtractorStatus` (`Extractor::status`) tells whether ingestion is
+//! healthy; see `extract_status`.
+//!
+//! The HTTP client the built-in sources share is configured with an
+//! `HttpClientConfig`: a proxy and extra headers (`MARKET_DATA_PROXY`,
+//! `MARKET_DATA_HEADERS`). An API key for paid tiers such as CoinGecko Pro
+//! belongs to its source alone (`COINGECKO_API_KEY`), so it is never sent to
+//! the other exchanges sharing the client.
+
+use crate::etl::divergence::SourceQuote;
+use crate::etl::extract_status::{ExtractionTracker, ExtractorStatus};
+use crate::etl::price::{self, Decimal};
+use crate::etl::stream::{self, PriceStream, StreamingSource};
 use crate::etl::validator::Validator;
-use chrono::prelude::*;
-use reqwest::Client;
+use crate::etl::{now_millis, timestamp_to_millis, DEFAULT_ASSET};
+use crate::retry::{classify_reqwest, classify_status, RetryClass, RetryPolicy};
+use async_trait::async_trait;
+use futures_util::stream::{self as futures_stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy, StatusCode};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::error::Error;
-use std::time::Duration;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 #[derive(Deserialize, Debug)]
 struct CoinGeckoResponse {
@@ -12,129 +63,949 @@ struct CoinGeckoResponse {
 
 #[derive(Deserialize, Debug)]
 struct PriceDetail {
-    usd: f32,
+    #[serde(with = "price::serde_number")]
+    usd: Decimal,
+    /// Quote (USD) volume over the last 24 hours, with `include_24hr_vol`
+    #[serde(default, with = "price::serde_number_option")]
+    usd_24h_vol: Option<Decimal>,
+}
+
+/// Why one fetch attempt failed, and whether retrying can help
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceError {
+    pub message: String,
+    pub class: RetryClass,
+    /// HTTP status behind the failure, which the extractor's `RetryPolicy`
+    /// may classify differently from `class`
+    pub status: Option<u16>,
+}
+
+impl SourceError {
+    /// Transient; the extractor retries after backing off
+    pub fn retryable(message: impl Into<String>) -> Self {
+        SourceError {
+            message: message.into(),
+            class: RetryClass::Retry,
+            status: None,
+        }
+    }
+
+    /// Retrying cannot help
+    pub fn fatal(message: impl Into<String>) -> Self {
+        SourceError {
+            message: message.into(),
+            class: RetryClass::Fatal,
+            status: None,
+        }
+    }
+
+    /// The source asked to slow down; retry after at least `delay`
+    pub fn throttled(message: impl Into<String>, delay: Duration) -> Self {
+        SourceError {
+            message: message.into(),
+            class: RetryClass::Throttled(delay),
+            status: None,
+        }
+    }
+
+    /// Non-success HTTP status, classified by `retry::classify_status`
+    pub fn from_status(status: StatusCode) -> Self {
+        SourceError {
+            message: format!("HTTP status: {}", status),
+            class: classify_status(status.as_u16()),
+            status: Some(status.as_u16()),
+        }
+    }
+
+    /// The message leaves out the URL, whose query may carry an API key
+    pub fn from_request(err: reqwest::Error) -> Self {
+        SourceError {
+            class: classify_reqwest(&err),
+            status: err.status().map(|s| s.as_u16()),
+            message: format!("Request error: {}", err.without_url()),
+        }
+    }
+
+    /// Like `from_request`, without the URL
+    pub fn from_decode(err: reqwest::Error) -> Self {
+        SourceError::retryable(format!("JSON decode error: {}", err.without_url()))
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for SourceError {}
+
+/// Where an `Extractor` gets its prices
+///
+/// `fetch` makes one attempt; the extractor handles retries and validation.
+#[async_trait]
+pub trait DataSource: Send + Sync {
+    /// Recorded as the `source` of the market data
+    fn name(&self) -> &str;
+
+    /// Ledger asset symbol the source quotes
+    fn asset(&self) -> &str {
+        DEFAULT_ASSET
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError>;
+}
+
+/// Lets a caller keep a handle on a source it registers
+#[async_trait]
+impl<T: DataSource + ?Sized> DataSource for Arc<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn asset(&self) -> &str {
+        (**self).asset()
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        (**self).fetch().await
+    }
+}
+
+/// BTC/USD from CoinGecko's simple price endpoint, with the 24h volume
+/// when the URL asks for `include_24hr_vol`
+pub struct CoinGeckoSource {
+    client: Client,
+    url: String,
+    /// Header and key sent with each request, marked sensitive so it is not
+    /// logged
+    api_key: Option<(HeaderName, HeaderValue)>,
+}
+
+impl CoinGeckoSource {
+    pub const DEFAULT_URL: &'static str =
+        "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd&include_24hr_vol=true";
+
+    /// Uses `COINGECKO_API_URL` when set
+    pub fn new(client: Client) -> Self {
+        CoinGeckoSource {
+            client,
+            url: std::env::var("COINGECKO_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+            api_key: None,
+        }
+    }
+
+    /// `new`, plus the key from `COINGECKO_API_KEY`, sent in
+    /// `COINGECKO_API_KEY_HEADER` (default `x-cg-pro-api-key`)
+    pub fn from_env(client: Client) -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let source = Self::new(client);
+        match var("COINGECKO_API_KEY") {
+            Some(key) => {
+                let header = var("COINGECKO_API_KEY_HEADER")
+                    .unwrap_or_else(|| DEFAULT_API_KEY_HEADER.into());
+                source.with_api_key(header.trim(), key.trim())
+            }
+            None => Ok(source),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Send `key` in the `header` header with every request
+    pub fn with_api_key(mut self, header: &str, key: &str) -> Result<Self, String> {
+        let name = HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| format!("invalid header name '{}'", header))?;
+        let mut value = HeaderValue::from_str(key)
+            .map_err(|_| format!("invalid value for header '{}'", header))?;
+        value.set_sensitive(true);
+        self.api_key = Some((name, value));
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl DataSource for CoinGeckoSource {
+    fn name(&self) -> &str {
+        "CoinGecko"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let mut request = self.client.get(&self.url);
+        if let Some((name, value)) = &self.api_key {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(SourceError::from_request)?;
+        let status = response.status();
+        // CoinGecko answers 403 as well as 429 when rate limiting
+        if status == StatusCode::FORBIDDEN {
+            return Err(SourceError::throttled(
+                format!("HTTP status: {}", status),
+                Duration::from_secs(1),
+            ));
+        }
+        if !status.is_success() {
+            return Err(SourceError::from_status(status));
+        }
+        let body: CoinGeckoResponse = response.json().await.map_err(SourceError::from_decode)?;
+        let detail = body.bitcoin;
+        // CoinGecko counts volume in the quote currency; the other sources
+        // in the base asset
+        let volume = detail
+            .usd_24h_vol
+            .filter(|volume| *volume >= Decimal::ZERO && detail.usd > Decimal::ZERO)
+            .and_then(|volume| volume.checked_div(detail.usd));
+        Ok(ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price: detail.usd,
+            timestamp: now_millis(),
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume,
+        })
+    }
+}
+
+/// Synthetic prices around 50,000 for offline runs
+pub struct MockSource;
+
+#[async_trait]
+impl DataSource for MockSource {
+    fn name(&self) -> &str {
+        "MockData"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let timestamp = now_millis();
+        let base_price = Decimal::from(50000);
+        let variation = Decimal::new(timestamp % 1000, 1);
+        Ok(ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price: base_price + variation,
+            timestamp,
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume: None,
+        })
+    }
+}
+
+/// Layout of a tick file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl FileFormat {
+    /// `Csv` for `.csv` files, `Jsonl` otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => FileFormat::Csv,
+            _ => FileFormat::Jsonl,
+        }
+    }
+}
+
+/// Which column (CSV) or key (JSONL) holds each tick field
+///
+/// JSONL keys may be dotted paths into nested objects, e.g. `data.p`.
+/// Without a timestamp column ticks are stamped when they are replayed;
+/// without a source column they are attributed to `File`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSchema {
+    pub price: String,
+    pub timestamp: Option<String>,
+    pub source: Option<String>,
+}
+
+impl Default for FileSchema {
+    fn default() -> Self {
+        FileSchema {
+            price: "price".to_string(),
+            timestamp: Some("timestamp".to_string()),
+            source: Some("source".to_string()),
+        }
+    }
+}
+
+impl FileSchema {
+    /// Parse overrides such as `price=close,timestamp=time,source=-`, where
+    /// `-` drops an optional column
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut schema = FileSchema::default();
+        for pair in spec.split(',').filter(|p| !p.trim().is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected field=column, got '{}'", pair.trim()))?;
+            let column = column.trim();
+            let optional = (column != "-").then(|| column.to_string());
+            match field.trim() {
+                "price" if optional.is_some() => schema.price = column.to_string(),
+                "timestamp" => schema.timestamp = optional,
+                "source" => schema.source = optional,
+                field => return Err(format!("cannot map '{}' to '{}'", field, column)),
+            }
+        }
+        Ok(schema)
+    }
+}
+
+/// Replays ticks recorded in a CSV or JSONL file, one per fetch
+///
+/// The file is read on the first fetch (or by `load`), and a malformed
+/// row fails the whole file with its line number. Once every tick has been
+/// served, fetches fail fatally unless the source repeats. Recorded
+/// timestamps are kept by default, so the validator's drift limit rejects
+/// old files unless `with_current_timestamps` restamps them.
+pub struct FileSource {
+    path: PathBuf,
+    format: FileFormat,
+    schema: FileSchema,
+    current_timestamps: bool,
+    repeat: bool,
+    ticks: OnceLock<Result<Vec<ExtractResult>, String>>,
+    position: AtomicUsize,
+}
+
+impl FileSource {
+    /// Format chosen by the file extension, columns by `FileSchema::default`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        FileSource {
+            format: FileFormat::from_path(&path),
+            path,
+            schema: FileSchema::default(),
+            current_timestamps: false,
+            repeat: false,
+            ticks: OnceLock::new(),
+            position: AtomicUsize::new(0),
+        }
+    }
+
+    /// `MARKET_DATA_FILE`, with `MARKET_DATA_FILE_COLUMNS` (a
+    /// `FileSchema::parse` spec), `MARKET_DATA_FILE_TIMESTAMPS`
+    /// (`recorded` or `now`) and `MARKET_DATA_FILE_REPEAT`
+    pub fn from_env() -> Result<Self, String> {
+        let path = std::env::var("MARKET_DATA_FILE")
+            .ok()
+            .filter(|p| !p.trim().is_empty())
+            .ok_or("MARKET_DATA_FILE is not set")?;
+        let mut source = FileSource::new(path.trim());
+        if let Ok(spec) = std::env::var("MARKET_DATA_FILE_COLUMNS") {
+            source = source.with_schema(
+                FileSchema::parse(&spec)
+                    .map_err(|e| format!("invalid MARKET_DATA_FILE_COLUMNS: {}", e))?,
+            );
+        }
+        match std::env::var("MARKET_DATA_FILE_TIMESTAMPS").as_deref() {
+            Err(_) | Ok("recorded") => {}
+            Ok("now") => source = source.with_current_timestamps(),
+            Ok(other) => {
+                return Err(format!(
+                    "invalid MARKET_DATA_FILE_TIMESTAMPS '{}' (expected recorded or now)",
+                    other
+                ))
+            }
+        }
+        if std::env::var("MARKET_DATA_FILE_REPEAT").is_ok_and(|v| v == "true" || v == "1") {
+            source = source.with_repeat();
+        }
+        Ok(source)
+    }
+
+    pub fn with_format(mut self, format: FileFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_schema(mut self, schema: FileSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Stamp each tick with the time it is replayed
+    pub fn with_current_timestamps(mut self) -> Self {
+        self.current_timestamps = true;
+        self
+    }
+
+    /// Start over from the first tick after the last one
+    pub fn with_repeat(mut self) -> Self {
+        self.repeat = true;
+        self
+    }
+
+    /// Read and parse the file if not done yet, returning the tick count
+    pub fn load(&self) -> Result<usize, String> {
+        self.ticks
+            .get_or_init(|| {
+                let content = std::fs::read_to_string(&self.path)
+                    .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+                let ticks = match self.format {
+                    FileFormat::Csv => self.parse_csv(&content),
+                    FileFormat::Jsonl => self.parse_jsonl(&content),
+                }
+                .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+                if ticks.is_empty() {
+                    return Err(format!("{}: no ticks", self.path.display()));
+                }
+                Ok(ticks)
+            })
+            .as_ref()
+            .map(Vec::len)
+            .map_err(Clone::clone)
+    }
+
+    fn tick(
+        &self,
+        price: Decimal,
+        timestamp: Option<i64>,
+        source: Option<String>,
+    ) -> ExtractResult {
+        ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price,
+            timestamp: timestamp.map_or(0, timestamp_to_millis),
+            source: source.unwrap_or_else(|| "File".to_string()),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume: None,
+        }
+    }
+
+    fn parse_csv(&self, content: &str) -> Result<Vec<ExtractResult>, String> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or("missing header row")?;
+        let header = split_csv_line(header);
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| format!("no column '{}' in header", name))
+        };
+        let price_col = column(&self.schema.price)?;
+        let timestamp_col = self.schema.timestamp.as_deref().map(column).transpose()?;
+        let source_col = self.schema.source.as_deref().map(column).transpose()?;
+
+        lines
+            .map(|(n, line)| {
+                let fields = split_csv_line(line);
+                let field = |i: usize| {
+                    fields
+                        .get(i)
+                        .map(String::as_str)
+                        .ok_or_else(|| format!("line {}: missing column {}", n + 1, i + 1))
+                };
+                let price =
+                    parse_price(field(price_col)?).map_err(|e| format!("line {}: {}", n + 1, e))?;
+                let timestamp = timestamp_col
+                    .map(|i| {
+                        parse_timestamp(field(i)?).map_err(|e| format!("line {}: {}", n + 1, e))
+                    })
+                    .transpose()?;
+                let source = source_col
+                    .map(|i| field(i).map(str::to_string))
+                    .transpose()?;
+                Ok(self.tick(price, timestamp, source))
+            })
+            .collect()
+    }
+
+    fn parse_jsonl(&self, content: &str) -> Result<Vec<ExtractResult>, String> {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                let at = |e: String| format!("line {}: {}", n + 1, e);
+                let value: serde_json::Value =
+                    serde_json::from_str(line).map_err(|e| at(e.to_string()))?;
+                let lookup = |key: &str| {
+                    key.split('.')
+                        .try_fold(&value, |v, part| v.get(part))
+                        .ok_or_else(|| at(format!("missing key '{}'", key)))
+                };
+                let text = |v: &serde_json::Value| match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                let price = parse_price(&text(lookup(&self.schema.price)?)).map_err(at)?;
+                let timestamp = match &self.schema.timestamp {
+                    Some(key) => Some(parse_timestamp(&text(lookup(key)?)).map_err(at)?),
+                    None => None,
+                };
+                let source = match &self.schema.source {
+                    Some(key) => Some(text(lookup(key)?)),
+                    None => None,
+                };
+                Ok(self.tick(price, timestamp, source))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl DataSource for FileSource {
+    fn name(&self) -> &str {
+        "File"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        self.load().map_err(SourceError::fatal)?;
+        let ticks = self
+            .ticks
+            .get()
+            .and_then(|t| t.as_ref().ok())
+            .ok_or_else(|| SourceError::fatal("tick file not loaded"))?;
+        let position = self.position.fetch_add(1, Ordering::SeqCst);
+        let index = if self.repeat {
+            position % ticks.len()
+        } else {
+            position
+        };
+        let mut tick = ticks.get(index).cloned().ok_or_else(|| {
+            SourceError::fatal(format!(
+                "{}: all {} ticks replayed",
+                self.path.display(),
+                ticks.len()
+            ))
+        })?;
+        if self.current_timestamps || self.schema.timestamp.is_none() {
+            tick.timestamp = now_millis();
+        }
+        Ok(tick)
+    }
+}
+
+/// Split one CSV line, honouring double-quoted fields with `""` escapes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+fn parse_price(value: &str) -> Result<Decimal, String> {
+    price::parse(value).map_err(|_| format!("invalid price '{}'", value))
+}
+
+/// Unix seconds or milliseconds, or an RFC 3339 date
+fn parse_timestamp(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    if let Ok(ts) = value.parse::<i64>() {
+        return Ok(ts);
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.timestamp_millis())
+        .map_err(|_| format!("invalid timestamp '{}'", value))
+}
+
+/// Header CoinGecko Pro reads its API key from
+pub const DEFAULT_API_KEY_HEADER: &str = "x-cg-pro-api-key";
+
+/// How the HTTP client used for market data requests is built
+///
+/// Without a proxy, reqwest's default applies: `HTTPS_PROXY`/`HTTP_PROXY`
+/// from the environment are honored. Every source sharing the client sends
+/// its headers, so credentials belong to a source instead, see
+/// `CoinGeckoSource::with_api_key`.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientConfig {
+    /// Proxy for every request, e.g. `http://proxy.corp:3128`
+    pub proxy: Option<String>,
+    /// Sent with every request
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// `MARKET_DATA_PROXY` and `MARKET_DATA_HEADERS` (`Name=value,...`)
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let mut config = Self::new();
+        if let Some(proxy) = var("MARKET_DATA_PROXY") {
+            config = config.with_proxy(proxy.trim());
+        }
+        if let Some(headers) = var("MARKET_DATA_HEADERS") {
+            for pair in headers.split(',').filter(|p| !p.trim().is_empty()) {
+                let (name, value) = pair.split_once('=').ok_or_else(|| {
+                    format!(
+                        "invalid MARKET_DATA_HEADERS entry '{}': expected Name=value",
+                        pair
+                    )
+                })?;
+                config = config.with_header(name.trim(), value.trim());
+            }
+        }
+        config.default_headers()?;
+        Ok(config)
+    }
+
+    fn default_headers(&self) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        let header = |name: &str, value: &str| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{}'", name))?;
+            Ok::<_, String>((name, value))
+        };
+        for (name, value) in &self.headers {
+            let (name, value) = header(name, value)?;
+            headers.append(name, value);
+        }
+        Ok(headers)
+    }
+
+    /// The client: 10 second timeout, this crate's user agent, plus the
+    /// configured proxy and headers
+    pub fn build(&self) -> Result<Client, Box<dyn Error>> {
+        let mut builder = Client::builder()
+            .user_agent("rust-market-ledger/0.1.0")
+            .timeout(Duration::from_secs(10))
+            .default_headers(self.default_headers()?);
+        if let Some(proxy) = &self.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).map_err(|e| format!("invalid proxy '{}': {}", proxy, e))?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Concurrent fetches when `EXTRACT_MAX_CONCURRENCY` is unset
+pub const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// `EXTRACT_MAX_CONCURRENCY`, or `DEFAULT_MAX_CONCURRENCY`
+pub fn max_concurrency_from_env() -> Result<usize, String> {
+    match std::env::var("EXTRACT_MAX_CONCURRENCY") {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(limit) if limit > 0 => Ok(limit),
+            _ => Err(format!(
+                "invalid EXTRACT_MAX_CONCURRENCY '{}' (expected a positive integer)",
+                value
+            )),
+        },
+        Err(_) => Ok(DEFAULT_MAX_CONCURRENCY),
+    }
+}
+
+/// Retry policy for source requests, with the `RETRY_*` and
+/// `EXTRACT_RETRY_*` overrides
+pub fn retry_policy_from_env() -> Result<RetryPolicy, String> {
+    RetryPolicy::new(3, Duration::from_millis(500))
+        .with_max_delay(Duration::from_secs(8))
+        .with_env_overrides("EXTRACT")
+}
+
+/// `EXTRACT_CACHE_TTL_MS`, or `Duration::ZERO` (no cache)
+pub fn cache_ttl_from_env() -> Result<Duration, String> {
+    match std::env::var("EXTRACT_CACHE_TTL_MS") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| {
+                format!(
+                    "invalid EXTRACT_CACHE_TTL_MS '{}' (expected milliseconds)",
+                    value
+                )
+            }),
+        Err(_) => Ok(Duration::ZERO),
+    }
 }
 
 pub struct Extractor {
     client: Client,
+    source: Arc<dyn DataSource>,
+    /// Sources of further assets quoted each round, see `extract_assets`
+    asset_sources: Vec<Arc<dyn DataSource>>,
+    max_concurrency: usize,
+    stream_source: Option<Arc<dyn StreamingSource>>,
+    /// Source of `extract_offline`
+    offline_source: Arc<dyn DataSource>,
     validator: Validator,
-    max_retries: u32,
+    retry: RetryPolicy,
+    cache_ttl: Duration,
+    /// Last successful result per source name (and position, for asset
+    /// sources)
+    cache: parking_lot::Mutex<HashMap<String, (Instant, ExtractResult)>>,
+    tracker: Arc<ExtractionTracker>,
 }
 
+#[derive(Debug, Clone)]
 pub struct ExtractResult {
-    pub price: f32,
+    /// Ledger asset symbol, e.g. `BTC` or `EURUSD`
+    pub asset: String,
+    pub price: Decimal,
     pub timestamp: i64,
     pub source: String,
+    /// Raw quote from each source behind an aggregated price; empty for a
+    /// single source. See `aggregate::AggregatingExtractor`.
+    pub quotes: Vec<SourceQuote>,
+    /// Reused from the extractor's response cache rather than fetched
+    pub cache_hit: bool,
+    /// Base-asset volume traded over the last 24 hours, for sources that
+    /// report one
+    pub volume: Option<Decimal>,
 }
 
 impl Extractor {
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        let client = Client::builder()
-            .user_agent("rust-market-ledger/0.1.0")
-            .timeout(Duration::from_secs(10))
-            .build()?;
+        Self::from_http_config(&HttpClientConfig::new())
+    }
+
+    /// An extractor whose HTTP client, shared with the default CoinGecko
+    /// source and exposed through `client`, is built from `config`
+    pub fn from_http_config(config: &HttpClientConfig) -> Result<Self, Box<dyn Error>> {
+        let client = config.build()?;
 
         Ok(Extractor {
+            source: Arc::new(CoinGeckoSource::new(client.clone())),
+            asset_sources: Vec::new(),
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            stream_source: None,
+            offline_source: Arc::new(MockSource),
             client,
             validator: Validator::new(),
-            max_retries: 3,
+            retry: retry_policy_from_env()?,
+            cache_ttl: Duration::ZERO,
+            cache: Default::default(),
+            tracker: Arc::new(ExtractionTracker::new()),
         })
     }
 
+    /// Fetch from `source` instead of CoinGecko
+    pub fn with_source(mut self, source: impl DataSource + 'static) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    /// Also quote the asset `source` reports each round, in `extract_assets`
+    pub fn with_asset_source(mut self, source: Arc<dyn DataSource>) -> Self {
+        self.asset_sources.push(source);
+        self
+    }
+
+    /// Fetch at most `limit` asset sources at once in `extract_assets`
+    pub fn with_max_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrency = limit.max(1);
+        self
+    }
+
+    /// Feed to subscribe to in `stream`
+    pub fn with_stream_source(mut self, source: impl StreamingSource + 'static) -> Self {
+        self.stream_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Fetch offline rounds from `source` instead of `MockSource`, e.g. a
+    /// `stress::StressSource`
+    pub fn with_offline_source(mut self, source: impl DataSource + 'static) -> Self {
+        self.offline_source = Arc::new(source);
+        self
+    }
+
     pub fn with_validator(mut self, validator: Validator) -> Self {
         self.validator = validator;
         self
     }
 
     pub fn with_max_retries(mut self, retries: u32) -> Self {
-        self.max_retries = retries;
+        self.retry = self.retry.with_max_attempts(retries);
         self
     }
 
-    pub async fn extract_from_api(&self) -> Result<ExtractResult, Box<dyn Error>> {
-        let url = std::env::var("COINGECKO_API_URL").unwrap_or_else(|_| {
-            "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd"
-                .to_string()
-        });
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
 
-        let mut last_error = None;
-
-        for attempt in 1..=self.max_retries {
-            match self.client.get(&url).send().await {
-                Ok(response) => {
-                    let status = response.status();
-                    if !status.is_success() {
-                        last_error = Some(format!("HTTP status: {}", status));
-                        if status == 429 || status == 403 {
-                            let delay_ms = 1000 * attempt as u64;
-                            if attempt < self.max_retries {
-                                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
-                                continue;
-                            }
-                        } else if attempt < self.max_retries {
-                            tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
-                            continue;
-                        }
-                        return Err(format!("API returned status: {}", status).into());
-                    }
+    /// Reuse a source's last result for `ttl` after it was fetched;
+    /// `Duration::ZERO` (the default) disables the cache
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
 
-                    match response.json::<CoinGeckoResponse>().await {
-                        Ok(resp) => {
-                            let price = resp.bitcoin.usd;
-                            let timestamp = Utc::now().timestamp();
-
-                            self.validator.validate_price(price)?;
-                            self.validator.validate_timestamp(timestamp)?;
-
-                            return Ok(ExtractResult {
-                                price,
-                                timestamp,
-                                source: "CoinGecko".to_string(),
-                            });
-                        }
-                        Err(e) => {
-                            last_error = Some(format!("JSON decode error: {}", e));
-                            if attempt < self.max_retries {
-                                tokio::time::sleep(Duration::from_millis(500 * attempt as u64))
-                                    .await;
-                                continue;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    last_error = Some(format!("Request error: {}", e));
-                    if attempt < self.max_retries {
-                        tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
-                        continue;
-                    }
-                }
-            }
-        }
+    /// Report fetches to `tracker`, e.g. one shared with the HTTP server
+    pub fn with_tracker(mut self, tracker: Arc<ExtractionTracker>) -> Self {
+        self.tracker = tracker;
+        self
+    }
 
-        Err(format!(
-            "Failed after {} attempts. Last error: {}",
-            self.max_retries,
-            last_error.unwrap_or_default()
-        )
-        .into())
+    pub fn tracker(&self) -> &Arc<ExtractionTracker> {
+        &self.tracker
     }
 
-    pub async fn extract_offline(&self) -> Result<ExtractResult, Box<dyn Error>> {
-        let timestamp = Utc::now().timestamp();
-        let base_price = 50000.0;
-        let variation = (timestamp % 1000) as f32 / 10.0;
-        let price = base_price + variation;
+    /// Whether ingestion is healthy, with each source's recent history
+    pub fn status(&self) -> ExtractorStatus {
+        self.tracker.status()
+    }
+
+    /// HTTP client the built-in sources use, for sources registered with
+    /// `with_source` to share
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
 
-        self.validator.validate_price(price)?;
-        self.validator.validate_timestamp(timestamp)?;
+    pub fn source_name(&self) -> &str {
+        self.source.name()
+    }
 
-        Ok(ExtractResult {
-            price,
-            timestamp,
-            source: "MockData".to_string(),
+    /// Asset quoted by the main source
+    pub fn asset(&self) -> &str {
+        self.source.asset()
+    }
+
+    pub fn asset_source_names(&self) -> Vec<&str> {
+        self.asset_sources.iter().map(|s| s.name()).collect()
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    /// Fetch from the registered source
+    pub async fn extract(&self) -> Result<ExtractResult, Box<dyn Error>> {
+        self.extract_from(self.source.as_ref(), self.source.name())
+            .await
+    }
+
+    /// Fetch from every asset source, at most `max_concurrency` at a time.
+    /// Results are in the order the sources were added; one source failing
+    /// does not affect the others.
+    pub async fn extract_assets(&self) -> Vec<Result<ExtractResult, Box<dyn Error>>> {
+        let fetches = self.asset_sources.iter().enumerate().map(|(i, source)| {
+            let key = format!("{}#{}", source.name(), i);
+            async move { self.extract_from(source.as_ref(), &key).await }
+        });
+        futures_stream::iter(fetches)
+            .buffered(self.max_concurrency)
+            .collect()
+            .await
+    }
+
+    /// Subscribe to the stream source, delivering validated ticks until the
+    /// returned stream is dropped; must be called on a tokio runtime
+    pub fn stream(&self) -> Result<PriceStream, Box<dyn Error>> {
+        let source = self
+            .stream_source
+            .clone()
+            .ok_or("no streaming source registered")?;
+        Ok(stream::spawn(
+            source,
+            self.validator.clone(),
+            self.retry.clone(),
+        ))
+    }
+
+    pub fn stream_source_name(&self) -> Option<&str> {
+        self.stream_source.as_ref().map(|source| source.name())
+    }
+
+    pub async fn extract_offline(&self) -> Result<ExtractResult, Box<dyn Error>> {
+        self.extract_from(self.offline_source.as_ref(), self.offline_source.name())
+            .await
+    }
+
+    fn cached(&self, source: &str) -> Option<ExtractResult> {
+        if self.cache_ttl.is_zero() {
+            return None;
+        }
+        let cache = self.cache.lock();
+        let (fetched_at, result) = cache.get(source)?;
+        (fetched_at.elapsed() < self.cache_ttl).then(|| ExtractResult {
+            cache_hit: true,
+            ..result.clone()
         })
     }
+
+    async fn extract_from(
+        &self,
+        source: &dyn DataSource,
+        cache_key: &str,
+    ) -> Result<ExtractResult, Box<dyn Error>> {
+        if let Some(result) = self.cached(cache_key) {
+            // Still validated: the cached timestamp ages like any other
+            self.validator.validate_timestamp(result.timestamp)?;
+            return Ok(result);
+        }
+        let started = Instant::now();
+        let mut attempts = 0;
+        let fetched = self
+            .retry
+            .run(
+                |attempt| {
+                    attempts = attempt;
+                    source.fetch()
+                },
+                |err: &SourceError| {
+                    err.status
+                        .map_or(err.class, |s| self.retry.classify_status(s))
+                },
+            )
+            .await
+            .map_err(|e| format!("Failed after {} attempt(s). Last error: {}", attempts, e));
+        // Data the validator rejects counts against the source too
+        let checked = fetched.map_err(Box::<dyn Error>::from).and_then(|result| {
+            self.validator
+                .validate_asset_price(&result.asset, result.price)?;
+            self.validator.validate_timestamp(result.timestamp)?;
+            Ok(result)
+        });
+        match &checked {
+            Ok(_) => self
+                .tracker
+                .record_success(cache_key, Some(started.elapsed())),
+            Err(e) => self
+                .tracker
+                .record_failure(cache_key, started.elapsed(), &e.to_string()),
+        }
+        let result = checked?;
+        if !self.cache_ttl.is_zero() {
+            self.cache
+                .lock()
+                .insert(cache_key.to_string(), (Instant::now(), result.clone()));
+        }
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
@@ -174,7 +1045,7 @@ mod tests {
     #[tokio::test]
     async fn test_extractor_with_validator() {
         init();
-        let validator = Validator::new().with_price_range(0.0, 100000.0);
+        let validator = Validator::new().with_price_range(Decimal::ZERO, Decimal::from(100000));
         let extractor = Extractor::new().unwrap();
         let extractor = extractor.with_validator(validator);
         assert!(extractor.extract_offline().await.is_ok());
@@ -189,15 +1060,15 @@ mod tests {
         assert!(result.is_ok());
         let data = result.unwrap();
         assert_eq!(data.source, "MockData");
-        assert!(data.price >= 50000.0);
-        assert!(data.price < 50100.0); // base_price + max variation
+        assert!(data.price >= Decimal::from(50000));
+        assert!(data.price < Decimal::from(50100)); // base_price + max variation
         assert!(data.timestamp > 0);
     }
 
     #[tokio::test]
     async fn test_extract_offline_validation() {
         init();
-        let validator = Validator::new().with_price_range(0.0, 100.0);
+        let validator = Validator::new().with_price_range(Decimal::ZERO, Decimal::from(100));
         let extractor = Extractor::new().unwrap().with_validator(validator);
 
         // Offline extraction generates prices around 50000, which exceeds max of 100
@@ -212,7 +1083,379 @@ mod tests {
         let result = extractor.extract_offline().await.unwrap();
 
         assert!(!result.source.is_empty());
-        assert!(result.price > 0.0);
+        assert!(result.price > Decimal::ZERO);
         assert!(result.timestamp > 0);
     }
+
+    /// Fails with `errors` in turn, then returns a fixed price
+    struct ScriptedSource {
+        errors: std::sync::Mutex<Vec<SourceError>>,
+        calls: std::sync::atomic::AtomicU32,
+    }
+
+    #[async_trait]
+    impl DataSource for ScriptedSource {
+        fn name(&self) -> &str {
+            "Internal"
+        }
+
+        async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if let Some(err) = self.errors.lock().unwrap().pop() {
+                return Err(err);
+            }
+            Ok(ExtractResult {
+                asset: DEFAULT_ASSET.to_string(),
+                price: Decimal::from(42),
+                timestamp: now_millis(),
+                source: self.name().to_string(),
+                quotes: Vec::new(),
+                cache_hit: false,
+                volume: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_source_is_retried_and_validated() {
+        init();
+        let source = Arc::new(ScriptedSource {
+            errors: std::sync::Mutex::new(vec![SourceError::retryable("timeout")]),
+            calls: Default::default(),
+        });
+
+        let extractor = Extractor::new()
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new(3, Duration::ZERO))
+            .with_source(source.clone());
+        assert_eq!(extractor.source_name(), "Internal");
+        let result = extractor.extract().await.unwrap();
+        assert_eq!(
+            (result.price, result.source.as_str()),
+            (Decimal::from(42), "Internal")
+        );
+        assert_eq!(source.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Fatal errors are not retried
+        source
+            .errors
+            .lock()
+            .unwrap()
+            .push(SourceError::fatal("bad credentials"));
+        let err = extractor.extract().await.unwrap_err().to_string();
+        assert!(
+            err.contains("1 attempt(s)") && err.contains("bad credentials"),
+            "{}",
+            err
+        );
+
+        // The policy decides which HTTP statuses are worth another attempt
+        let conflict = || SourceError::from_status(StatusCode::CONFLICT);
+        source.errors.lock().unwrap().push(conflict());
+        assert!(extractor.extract().await.is_err());
+        source.errors.lock().unwrap().push(conflict());
+        let lenient = Extractor::new()
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new(3, Duration::ZERO).with_retryable_statuses([409]))
+            .with_source(source.clone());
+        assert!(lenient.extract().await.is_ok());
+
+        // Within the cache TTL the source is not called again
+        let cached = Extractor::new()
+            .unwrap()
+            .with_cache_ttl(Duration::from_secs(60))
+            .with_source(source.clone());
+        let calls = source.calls.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(!cached.extract().await.unwrap().cache_hit);
+        let hit = cached.extract().await.unwrap();
+        assert!(hit.cache_hit);
+        assert_eq!(hit.price, Decimal::from(42));
+        assert_eq!(
+            source.calls.load(std::sync::atomic::Ordering::SeqCst),
+            calls + 1
+        );
+
+        // Registered sources are validated like the built-in ones
+        let strict = Extractor::new()
+            .unwrap()
+            .with_validator(
+                Validator::new().with_price_range(Decimal::from(100), Decimal::from(200)),
+            )
+            .with_source(source);
+        assert!(strict.extract().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_status_tracks_failures_and_latency() {
+        init();
+        let source = Arc::new(ScriptedSource {
+            errors: std::sync::Mutex::new(vec![
+                SourceError::fatal("bad credentials"),
+                SourceError::fatal("bad credentials"),
+            ]),
+            calls: Default::default(),
+        });
+        let tracker = Arc::new(ExtractionTracker::new().with_failure_threshold(2));
+        let extractor = Extractor::new()
+            .unwrap()
+            .with_source(source.clone())
+            .with_tracker(tracker.clone());
+        assert!(extractor.status().healthy);
+
+        assert!(extractor.extract().await.is_err());
+        assert!(extractor.extract().await.is_err());
+        let status = tracker.status();
+        assert!(!status.healthy);
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.last_success_ms, None);
+        let internal = &status.sources[0];
+        assert_eq!(
+            (internal.source.as_str(), internal.failures),
+            ("Internal", 2)
+        );
+        assert!(internal
+            .last_error
+            .as_deref()
+            .unwrap()
+            .contains("bad credentials"));
+
+        // One success ends the streak; rejected data counts as a failure
+        extractor.extract().await.unwrap();
+        let status = extractor.status();
+        assert!(status.healthy);
+        assert!(status.last_success_ms.is_some());
+        assert!(status.sources[0].avg_latency_ms.is_some());
+        let strict = Extractor::new()
+            .unwrap()
+            .with_validator(
+                Validator::new().with_price_range(Decimal::from(100), Decimal::from(200)),
+            )
+            .with_source(source)
+            .with_tracker(tracker.clone());
+        assert!(strict.extract().await.is_err());
+        assert_eq!(tracker.status().sources[0].consecutive_failures, 1);
+    }
+
+    /// Quotes `asset` after `delay`, tracking how many fetches overlap
+    struct SlowSource {
+        asset: &'static str,
+        delay: Duration,
+        in_flight: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl DataSource for SlowSource {
+        fn name(&self) -> &str {
+            "Slow"
+        }
+
+        async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if self.asset.is_empty() {
+                return Err(SourceError::fatal("no such symbol"));
+            }
+            Ok(ExtractResult {
+                asset: self.asset.to_string(),
+                price: Decimal::from(42),
+                timestamp: now_millis(),
+                source: self.name().to_string(),
+                quotes: Vec::new(),
+                cache_hit: false,
+                volume: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extract_assets_concurrently_in_order() {
+        init();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut extractor = Extractor::new()
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new(1, Duration::ZERO))
+            .with_cache_ttl(Duration::from_secs(60))
+            .with_max_concurrency(2);
+        // Slowest first, so a sequential or unordered fetch would show
+        for (i, asset) in ["EURUSD", "GBPUSD", "", "AAPL"].into_iter().enumerate() {
+            extractor = extractor.with_asset_source(Arc::new(SlowSource {
+                asset,
+                delay: Duration::from_millis(80 - 20 * i as u64),
+                in_flight: in_flight.clone(),
+                peak: peak.clone(),
+            }));
+        }
+
+        let results = extractor.extract_assets().await;
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let assets: Vec<Option<String>> = results
+            .iter()
+            .map(|r| r.as_ref().ok().map(|d| d.asset.clone()))
+            .collect();
+        assert_eq!(
+            assets,
+            vec![
+                Some("EURUSD".to_string()),
+                Some("GBPUSD".to_string()),
+                None,
+                Some("AAPL".to_string())
+            ]
+        );
+
+        // Sources sharing a name keep separate cache entries
+        let cached = extractor.extract_assets().await;
+        assert!(cached[0].as_ref().unwrap().cache_hit);
+        assert_eq!(cached[3].as_ref().unwrap().asset, "AAPL");
+    }
+
+    #[actix_web::test]
+    async fn test_http_config_sends_headers_through_proxy() {
+        use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+        // Stands in for both the proxy and CoinGecko Pro behind it
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/price",
+                web::get().to(|req: HttpRequest| async move {
+                    let header = |name: &str| {
+                        req.headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    if header(DEFAULT_API_KEY_HEADER) != "pro-key" || header("x-team") != "ledger" {
+                        return HttpResponse::Unauthorized().finish();
+                    }
+                    HttpResponse::Ok().json(serde_json::json!({
+                        "bitcoin": { "usd": 64000.5, "usd_24h_vol": 32000250.0 }
+                    }))
+                }),
+            )
+        })
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let config = HttpClientConfig::new()
+            .with_header("x-team", "ledger")
+            .with_proxy(format!("http://{}", addr));
+        let extractor = Extractor::from_http_config(&config).unwrap();
+        let source = CoinGeckoSource::new(extractor.client().clone())
+            .with_url("http://coingecko.invalid/price")
+            .with_api_key(DEFAULT_API_KEY_HEADER, "pro-key")
+            .unwrap();
+        let quote = source.fetch().await.unwrap();
+        // 32,000,250 USD at 64,000.5 is 500 BTC
+        assert_eq!(
+            (quote.price, quote.volume),
+            (price::parse("64000.5").unwrap(), Some(Decimal::from(500)))
+        );
+
+        // The shared client carries the headers but not the key
+        let keyless = CoinGeckoSource::new(extractor.client().clone())
+            .with_url("http://coingecko.invalid/price");
+        assert_eq!(keyless.fetch().await.unwrap_err().class, RetryClass::Fatal);
+
+        let bare = Extractor::new().unwrap();
+        let source =
+            CoinGeckoSource::new(bare.client().clone()).with_url(format!("http://{}/price", addr));
+        assert_eq!(source.fetch().await.unwrap_err().class, RetryClass::Fatal);
+
+        assert!(HttpClientConfig::new()
+            .with_header("bad header", "x")
+            .build()
+            .is_err());
+        assert!(CoinGeckoSource::new(Client::new())
+            .with_api_key("bad header", "pro-key")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_source_replays_csv_and_jsonl() {
+        init();
+        let dir = std::env::temp_dir().join(format!(
+            "test_file_source_{}",
+            crate::logger::new_trace_id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("ticks.csv");
+        std::fs::write(
+            &csv,
+            "time,venue,close\n\
+             2024-01-02T00:00:00Z,\"Kraken, spot\",42000.5\n\
+             1704153660,Coinbase,42010\n",
+        )
+        .unwrap();
+        let source = FileSource::new(&csv)
+            .with_schema(FileSchema::parse("price=close,timestamp=time,source=venue").unwrap());
+        assert_eq!(source.load(), Ok(2));
+        let first = source.fetch().await.unwrap();
+        assert_eq!(
+            (first.price, first.timestamp, first.source.as_str()),
+            (
+                price::parse("42000.5").unwrap(),
+                1_704_153_600_000,
+                "Kraken, spot"
+            )
+        );
+        // Second-precision timestamps are read as seconds
+        assert_eq!(source.fetch().await.unwrap().timestamp, 1_704_153_660_000);
+        let err = source.fetch().await.unwrap_err();
+        assert_eq!(err.class, RetryClass::Fatal);
+        assert!(err.message.contains("all 2 ticks replayed"), "{}", err);
+
+        let jsonl = dir.join("ticks.jsonl");
+        std::fs::write(
+            &jsonl,
+            "{\"data\": {\"p\": \"64000.25\", \"T\": 1704153600000}}\n\n{\"data\": {\"p\": 64001}}\n",
+        )
+        .unwrap();
+        let nested = FileSource::new(&jsonl)
+            .with_schema(FileSchema {
+                price: "data.p".to_string(),
+                timestamp: None,
+                source: None,
+            })
+            .with_repeat();
+        let extractor = Extractor::new()
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new(1, Duration::ZERO))
+            .with_source(nested);
+        let prices: Vec<Decimal> = [
+            extractor.extract().await.unwrap(),
+            extractor.extract().await.unwrap(),
+            extractor.extract().await.unwrap(),
+        ]
+        .iter()
+        .map(|tick| {
+            assert_eq!(tick.source, "File");
+            tick.price
+        })
+        .collect();
+        let expected = ["64000.25", "64001", "64000.25"].map(|p| price::parse(p).unwrap());
+        assert_eq!(prices, expected);
+
+        // Recorded timestamps are validated like any other
+        let stale = Extractor::new()
+            .unwrap()
+            .with_source(FileSource::new(&jsonl).with_schema(FileSchema {
+                price: "data.p".to_string(),
+                timestamp: Some("data.T".to_string()),
+                source: None,
+            }));
+        assert!(stale.extract().await.is_err());
+
+        let broken =
+            FileSource::new(&jsonl).with_schema(FileSchema::parse("timestamp=data.T").unwrap());
+        let err = broken.load().unwrap_err();
+        assert!(err.contains("line 1: missing key 'price'"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
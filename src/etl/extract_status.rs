@@ -0,0 +1,146 @@
+//! Health of market data ingestion
+//!
+//! An `Extractor` reports every fetch to its `ExtractionTracker`: when each
+//! source last answered, how many times in a row it has failed since, and
+//! how long fetches take (retries included). `Extractor::status` returns an
+//! `ExtractorStatus` snapshot; a node shares the tracker with its HTTP
+//! server, which serves it on `GET /extraction` and marks `/health`
+//! degraded once a source has failed `failure_threshold` rounds in a row.
+//!
+//! Results served from the extractor's cache are not fetches and are not
+//! recorded. Ticks taken from a stream are recorded as successes without a
+//! latency.
+
+use crate::etl::now_millis;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Consecutive failures of one source after which ingestion is unhealthy
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Weight of the newest sample in `SourceStatus::avg_latency_ms`
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Fetch history of one source
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SourceStatus {
+    /// Source name; an asset source also carries its position, e.g.
+    /// `AlphaVantage#0`
+    pub source: String,
+    /// When the source last answered (milliseconds)
+    pub last_success_ms: Option<i64>,
+    pub last_failure_ms: Option<i64>,
+    pub last_error: Option<String>,
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+    pub successes: u64,
+    pub failures: u64,
+    pub last_latency_ms: Option<u64>,
+    /// Exponentially weighted mean of the fetch latency
+    pub avg_latency_ms: Option<f64>,
+}
+
+/// Snapshot of ingestion health
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExtractorStatus {
+    /// No source has failed `failure_threshold` times in a row
+    pub healthy: bool,
+    /// Most recent success of any source (milliseconds)
+    pub last_success_ms: Option<i64>,
+    /// Longest current failure streak among the sources
+    pub consecutive_failures: u32,
+    pub failure_threshold: u32,
+    /// By source name
+    pub sources: Vec<SourceStatus>,
+}
+
+/// Collects fetch outcomes for `ExtractorStatus`
+#[derive(Debug)]
+pub struct ExtractionTracker {
+    failure_threshold: u32,
+    sources: RwLock<BTreeMap<String, SourceStatus>>,
+}
+
+impl ExtractionTracker {
+    pub fn new() -> Self {
+        ExtractionTracker {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            sources: RwLock::default(),
+        }
+    }
+
+    /// Report ingestion unhealthy once a source fails `failures` times in a
+    /// row
+    pub fn with_failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// `source` answered; `latency` is `None` for a streamed tick
+    pub fn record_success(&self, source: &str, latency: Option<Duration>) {
+        let mut sources = self.sources.write();
+        let status = Self::entry(&mut sources, source);
+        status.last_success_ms = Some(now_millis());
+        status.consecutive_failures = 0;
+        status.successes += 1;
+        if let Some(latency) = latency {
+            Self::record_latency(status, latency);
+        }
+    }
+
+    /// `source` failed after `latency`
+    pub fn record_failure(&self, source: &str, latency: Duration, error: &str) {
+        let mut sources = self.sources.write();
+        let status = Self::entry(&mut sources, source);
+        status.last_failure_ms = Some(now_millis());
+        status.last_error = Some(error.to_string());
+        status.consecutive_failures += 1;
+        status.failures += 1;
+        Self::record_latency(status, latency);
+    }
+
+    pub fn status(&self) -> ExtractorStatus {
+        let sources: Vec<SourceStatus> = self.sources.read().values().cloned().collect();
+        let consecutive_failures = sources
+            .iter()
+            .map(|s| s.consecutive_failures)
+            .max()
+            .unwrap_or_default();
+        ExtractorStatus {
+            healthy: consecutive_failures < self.failure_threshold,
+            last_success_ms: sources.iter().filter_map(|s| s.last_success_ms).max(),
+            consecutive_failures,
+            failure_threshold: self.failure_threshold,
+            sources,
+        }
+    }
+
+    fn entry<'a>(
+        sources: &'a mut BTreeMap<String, SourceStatus>,
+        source: &str,
+    ) -> &'a mut SourceStatus {
+        sources
+            .entry(source.to_string())
+            .or_insert_with(|| SourceStatus {
+                source: source.to_string(),
+                ..Default::default()
+            })
+    }
+
+    fn record_latency(status: &mut SourceStatus, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        status.last_latency_ms = Some(ms);
+        status.avg_latency_ms = Some(match status.avg_latency_ms {
+            Some(avg) => avg + LATENCY_SMOOTHING * (ms as f64 - avg),
+            None => ms as f64,
+        });
+    }
+}
+
+impl Default for ExtractionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
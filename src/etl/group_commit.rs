@@ -0,0 +1,353 @@
+//! Group commit for block persistence
+//!
+//! Each `DatabaseManager::save_block` call is its own SQLite transaction and
+//! pays for its own fsync. Under sustained load `GroupCommitter` instead
+//! buffers blocks for up to `max_delay_ms` (or until `max_batch_size` blocks
+//! are waiting), writes the whole batch in one transaction and then
+//! acknowledges every caller.
+//!
+//! A node commits one block per round, so on their own its commits rarely
+//! meet in the buffer. Chain sync hands the committer a whole page of peer
+//! blocks at once through `commit_all`, which queues every block before
+//! waiting, so a page is written in as few transactions as the batch size
+//! allows, together with any block the node commits meanwhile.
+//!
+//! Durability trade-off: a caller's `commit` returns only once its block is
+//! durable, exactly as with `save_block`, but it may wait up to
+//! `max_delay_ms` longer. Blocks still buffered when the process dies were
+//! never acknowledged and are lost. With `SyncPolicy::Normal` a committed
+//! batch may additionally be lost on power failure (not on a process crash).
+
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult, SyncPolicy};
+use crate::etl::Block;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Batching limits for `GroupCommitter`
+#[derive(Debug, Clone)]
+pub struct GroupCommitConfig {
+    /// Longest a block waits in the buffer before the batch is written
+    pub max_delay_ms: u64,
+    /// Write as soon as this many blocks are buffered
+    pub max_batch_size: usize,
+    /// SQLite `synchronous` setting applied to the database
+    pub sync_policy: SyncPolicy,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        GroupCommitConfig {
+            max_delay_ms: 10,
+            max_batch_size: 64,
+            sync_policy: SyncPolicy::Full,
+        }
+    }
+}
+
+impl GroupCommitConfig {
+    /// Read `GROUP_COMMIT_MAX_DELAY_MS`, `GROUP_COMMIT_MAX_BATCH` and
+    /// `DB_SYNC_POLICY`; group commit is off unless the delay is set. Values
+    /// that do not parse, and zero, are errors naming the variable.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let positive = |name: &str, expected: &str| -> Result<Option<u64>, String> {
+            lookup(name)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|&n: &u64| n > 0)
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid {} '{}' (expected {}, at least 1)",
+                                name, value, expected
+                            )
+                        })
+                })
+                .transpose()
+        };
+        let Some(max_delay_ms) = positive("GROUP_COMMIT_MAX_DELAY_MS", "milliseconds")? else {
+            return Ok(None);
+        };
+        let defaults = GroupCommitConfig::default();
+        let max_batch_size = positive("GROUP_COMMIT_MAX_BATCH", "a number of blocks")?
+            .map_or(defaults.max_batch_size, |n| n as usize);
+        let sync_policy = match lookup("DB_SYNC_POLICY") {
+            Some(name) => SyncPolicy::from_name(name.trim()).ok_or_else(|| {
+                format!(
+                    "invalid DB_SYNC_POLICY '{}' (expected full or normal)",
+                    name
+                )
+            })?,
+            None => defaults.sync_policy,
+        };
+        Ok(Some(GroupCommitConfig {
+            max_delay_ms,
+            max_batch_size,
+            sync_policy,
+        }))
+    }
+}
+
+type Pending = (Block, oneshot::Sender<DbResult<()>>);
+
+/// Handle for submitting blocks to a background group-commit writer
+#[derive(Clone)]
+pub struct GroupCommitter {
+    tx: mpsc::Sender<Pending>,
+    /// Transactions the writer has run
+    batches: Arc<AtomicU64>,
+}
+
+impl GroupCommitter {
+    /// Start the writer task on the current tokio runtime
+    pub fn spawn(db: Arc<DatabaseManager>, config: GroupCommitConfig) -> DbResult<Self> {
+        db.set_sync_policy(config.sync_policy)?;
+        let (tx, rx) = mpsc::channel(config.max_batch_size.max(1) * 4);
+        let batches = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run_writer(db, config, rx, batches.clone()));
+        Ok(GroupCommitter { tx, batches })
+    }
+
+    /// Queue `block` and wait until the batch containing it is written
+    pub async fn commit(&self, block: Block) -> DbResult<()> {
+        let ack = self.enqueue(block).await?;
+        Self::acknowledged(ack).await
+    }
+
+    /// Queue all of `blocks` before waiting, so they share batches; returns
+    /// each block's result in order
+    pub async fn commit_all(&self, blocks: Vec<Block>) -> Vec<DbResult<()>> {
+        let mut acks = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            acks.push(self.enqueue(block).await);
+        }
+        let mut results = Vec::with_capacity(acks.len());
+        for ack in acks {
+            results.push(match ack {
+                Ok(ack) => Self::acknowledged(ack).await,
+                Err(e) => Err(e),
+            });
+        }
+        results
+    }
+
+    /// Transactions written so far; fewer than the blocks committed when
+    /// commits were batched
+    pub fn batches(&self) -> u64 {
+        self.batches.load(Ordering::Relaxed)
+    }
+
+    async fn enqueue(&self, block: Block) -> DbResult<oneshot::Receiver<DbResult<()>>> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx
+            .send((block, ack_tx))
+            .await
+            .map_err(|_| DatabaseError::InvalidData("group commit writer stopped".to_string()))?;
+        Ok(ack_rx)
+    }
+
+    async fn acknowledged(ack: oneshot::Receiver<DbResult<()>>) -> DbResult<()> {
+        ack.await.map_err(|_| {
+            DatabaseError::InvalidData("group commit writer dropped the block".to_string())
+        })?
+    }
+}
+
+async fn run_writer(
+    db: Arc<DatabaseManager>,
+    config: GroupCommitConfig,
+    mut rx: mpsc::Receiver<Pending>,
+    batches: Arc<AtomicU64>,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + Duration::from_millis(config.max_delay_ms);
+
+        while batch.len() < max_batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let db = db.clone();
+        batches.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = tokio::task::spawn_blocking(move || write_batch(&db, batch)).await {
+            warn!(error = %e, "Database: Group commit writer task failed");
+        }
+    }
+}
+
+/// Write `batch` in one transaction and acknowledge each caller
+///
+/// If the transaction fails (e.g. one block is a duplicate), blocks are
+/// retried one at a time so every caller gets its own result.
+fn write_batch(db: &DatabaseManager, batch: Vec<Pending>) {
+    let blocks: Vec<Block> = batch.iter().map(|(block, _)| block.clone()).collect();
+
+    match db.save_blocks(&blocks) {
+        Ok(count) => {
+            debug!(blocks = count, "Database: Group commit written");
+            for (_, ack) in batch {
+                let _ = ack.send(Ok(()));
+            }
+        }
+        Err(e) => {
+            warn!(
+                error = %e,
+                blocks = blocks.len(),
+                "Database: Group commit failed, retrying blocks individually"
+            );
+            for (block, ack) in batch {
+                let _ = ack.send(db.save_block(&block));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing;
+    use std::fs;
+
+    fn create_test_block(index: u64) -> Block {
+        let timestamp = 1_234_567_890_000 + index as i64 * 1000;
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), timestamp);
+        testing::block(index, &format!("hash_{}", index - 1), vec![entry])
+    }
+
+    fn open_db(path: &str) -> Arc<DatabaseManager> {
+        fs::remove_file(path).ok();
+        let db = DatabaseManager::new(path).unwrap();
+        db.init().unwrap();
+        Arc::new(db)
+    }
+
+    #[test]
+    fn test_config_from_vars_rejects_invalid_values() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(GroupCommitConfig::from_vars(vars(&[])).unwrap().is_none());
+        let config = GroupCommitConfig::from_vars(vars(&[
+            ("GROUP_COMMIT_MAX_DELAY_MS", "10"),
+            ("DB_SYNC_POLICY", "normal"),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.max_delay_ms, 10);
+        assert_eq!(
+            config.max_batch_size,
+            GroupCommitConfig::default().max_batch_size
+        );
+        assert_eq!(config.sync_policy, SyncPolicy::Normal);
+
+        for (pairs, name) in [
+            (
+                &[("GROUP_COMMIT_MAX_DELAY_MS", "0")][..],
+                "GROUP_COMMIT_MAX_DELAY_MS",
+            ),
+            (
+                &[("GROUP_COMMIT_MAX_DELAY_MS", "10ms")][..],
+                "GROUP_COMMIT_MAX_DELAY_MS",
+            ),
+            (
+                &[
+                    ("GROUP_COMMIT_MAX_DELAY_MS", "10"),
+                    ("GROUP_COMMIT_MAX_BATCH", "0"),
+                ][..],
+                "GROUP_COMMIT_MAX_BATCH",
+            ),
+            (
+                &[
+                    ("GROUP_COMMIT_MAX_DELAY_MS", "10"),
+                    ("DB_SYNC_POLICY", "off"),
+                ][..],
+                "DB_SYNC_POLICY",
+            ),
+        ] {
+            let err = GroupCommitConfig::from_vars(vars(pairs)).unwrap_err();
+            assert!(err.contains(name), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commits_share_a_batch() {
+        let test_db = "test_group_commit.db";
+        let db = open_db(test_db);
+        let committer = GroupCommitter::spawn(
+            db.clone(),
+            GroupCommitConfig {
+                max_delay_ms: 50,
+                max_batch_size: 8,
+                sync_policy: SyncPolicy::Normal,
+            },
+        )
+        .unwrap();
+
+        let handles: Vec<_> = (1..=5)
+            .map(|i| {
+                let committer = committer.clone();
+                tokio::spawn(async move { committer.commit(create_test_block(i)).await })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        assert_eq!(db.get_block_count().unwrap(), 5);
+        fs::remove_file(test_db).ok();
+    }
+
+    #[tokio::test]
+    async fn test_commit_all_writes_one_batch() {
+        let test_db = "test_group_commit_all.db";
+        let db = open_db(test_db);
+        let committer = GroupCommitter::spawn(db.clone(), GroupCommitConfig::default()).unwrap();
+
+        let results = committer
+            .commit_all((1..=20).map(create_test_block).collect())
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(db.get_block_count().unwrap(), 20);
+        assert_eq!(committer.batches(), 1);
+        fs::remove_file(test_db).ok();
+    }
+
+    #[tokio::test]
+    async fn test_failed_block_does_not_fail_its_batch() {
+        let test_db = "test_group_commit_dup.db";
+        let db = open_db(test_db);
+        db.save_block(&create_test_block(2)).unwrap();
+        let committer = GroupCommitter::spawn(db.clone(), GroupCommitConfig::default()).unwrap();
+
+        let ok = tokio::spawn({
+            let committer = committer.clone();
+            async move { committer.commit(create_test_block(1)).await }
+        });
+        let duplicate = committer.commit(create_test_block(2)).await;
+
+        assert!(ok.await.unwrap().is_ok());
+        assert!(matches!(duplicate, Err(DatabaseError::Sqlite(_))));
+        assert_eq!(db.get_block_count().unwrap(), 2);
+        fs::remove_file(test_db).ok();
+    }
+}
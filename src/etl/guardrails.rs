@@ -0,0 +1,574 @@
+//! Disk-space and database-size guardrails
+//!
+//! `StorageGuard` is consulted before each block is produced. It measures the
+//! ledger file and the free space on the disk holding it, then:
+//!
+//! - above `DB_MAX_SIZE_MB` or below `DISK_MIN_FREE_MB`, archives the oldest
+//!   blocks to JSON-lines files (when `ARCHIVE_DIR` is set) and prunes them,
+//!   keeping the newest `PRUNE_RETAIN_BLOCKS`;
+//! - below `DISK_HARD_FLOOR_MB` even after pruning, refuses block production
+//!   until space is freed.
+//!
+//! Pruning only frees pages inside the database file. `VACUUM` hands them
+//! back to the disk, but it rewrites the whole file while holding the
+//! ledger's connection, so it runs at most once per
+//! `VACUUM_MIN_INTERVAL_SECS`; a prune in between leaves a VACUUM pending
+//! until the interval has passed.
+//!
+//! The latest measurement and state are reported on `/health`. Pruned blocks
+//! can no longer be served to syncing peers from this node. Redacting a
+//! block rewrites its archived copy (`redact_archived`).
+
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult};
+use crate::etl::Block;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Blocks kept when pruning unless `PRUNE_RETAIN_BLOCKS` is set
+pub const DEFAULT_RETAIN_BLOCKS: u64 = 1000;
+
+/// Shortest time between two VACUUMs unless `VACUUM_MIN_INTERVAL_SECS` is set
+pub const DEFAULT_VACUUM_INTERVAL: Duration = Duration::from_secs(3600);
+
+const MB: u64 = 1024 * 1024;
+
+/// Configured thresholds; unset limits are not enforced
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageLimits {
+    /// Prune once the database (plus WAL) grows beyond this size
+    pub max_db_bytes: Option<u64>,
+    /// Prune once free disk space drops below this
+    pub min_free_bytes: Option<u64>,
+    /// Refuse block production below this much free space
+    pub hard_floor_bytes: Option<u64>,
+    /// Newest blocks that pruning never removes
+    pub retain_blocks: u64,
+    /// Where pruned blocks are written before deletion; `None` discards them
+    pub archive_dir: Option<PathBuf>,
+    /// Shortest time between two VACUUMs
+    pub min_vacuum_interval: Duration,
+}
+
+impl StorageLimits {
+    pub fn new() -> Self {
+        StorageLimits {
+            retain_blocks: DEFAULT_RETAIN_BLOCKS,
+            min_vacuum_interval: DEFAULT_VACUUM_INTERVAL,
+            ..Default::default()
+        }
+    }
+
+    /// Read the `DB_MAX_SIZE_MB`, `DISK_MIN_FREE_MB`, `DISK_HARD_FLOOR_MB`,
+    /// `PRUNE_RETAIN_BLOCKS`, `ARCHIVE_DIR` and `VACUUM_MIN_INTERVAL_SECS`
+    /// variables, naming the one that does not parse
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let number = |name: &str, expected: &str| -> Result<Option<u64>, String> {
+            lookup(name)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse::<u64>()
+                        .map_err(|_| format!("invalid {} '{}' ({})", name, value, expected))
+                })
+                .transpose()
+        };
+        let megabytes = |name: &str| -> Result<Option<u64>, String> {
+            number(name, "expected megabytes")?
+                .map(|mb| {
+                    mb.checked_mul(MB)
+                        .ok_or_else(|| format!("invalid {} '{}' (too large)", name, mb))
+                })
+                .transpose()
+        };
+        let limits = StorageLimits {
+            max_db_bytes: megabytes("DB_MAX_SIZE_MB")?,
+            min_free_bytes: megabytes("DISK_MIN_FREE_MB")?,
+            hard_floor_bytes: megabytes("DISK_HARD_FLOOR_MB")?,
+            retain_blocks: number("PRUNE_RETAIN_BLOCKS", "expected a number of blocks")?
+                .unwrap_or(DEFAULT_RETAIN_BLOCKS),
+            archive_dir: lookup("ARCHIVE_DIR")
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from),
+            min_vacuum_interval: number("VACUUM_MIN_INTERVAL_SECS", "expected seconds")?
+                .map_or(DEFAULT_VACUUM_INTERVAL, Duration::from_secs),
+        };
+        if let (Some(floor), Some(min_free)) = (limits.hard_floor_bytes, limits.min_free_bytes) {
+            if floor > min_free {
+                return Err(format!(
+                    "invalid DISK_HARD_FLOOR_MB: {} MB is above DISK_MIN_FREE_MB ({} MB)",
+                    floor / MB,
+                    min_free / MB
+                ));
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_db_bytes.is_some()
+            || self.min_free_bytes.is_some()
+            || self.hard_floor_bytes.is_some()
+    }
+
+    /// What the guard should do for a measurement
+    pub fn evaluate(&self, usage: &StorageUsage) -> StorageState {
+        let free = usage.free_bytes;
+        if let (Some(floor), Some(free)) = (self.hard_floor_bytes, free) {
+            if free < floor {
+                return StorageState::Critical;
+            }
+        }
+        let over_size = self.max_db_bytes.is_some_and(|max| usage.db_bytes > max);
+        let low_disk = match (self.min_free_bytes, free) {
+            (Some(min), Some(free)) => free < min,
+            _ => false,
+        };
+        if over_size || low_disk {
+            StorageState::Pressure
+        } else {
+            StorageState::Ok
+        }
+    }
+}
+
+/// Measured storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StorageUsage {
+    pub db_bytes: u64,
+    /// Free space on the ledger's disk; `None` when it cannot be determined
+    pub free_bytes: Option<u64>,
+}
+
+impl StorageUsage {
+    /// Size of the database and its WAL/journal files plus free disk space
+    pub fn measure(db_path: &Path) -> Self {
+        let db_bytes = ["", "-wal", "-journal"]
+            .iter()
+            .filter_map(|suffix| {
+                let mut path = db_path.as_os_str().to_owned();
+                path.push(suffix);
+                fs::metadata(path).ok()
+            })
+            .map(|meta| meta.len())
+            .sum();
+        StorageUsage {
+            db_bytes,
+            free_bytes: free_space(db_path),
+        }
+    }
+}
+
+/// Available space on the disk whose mount point contains `path`
+fn free_space(path: &Path) -> Option<u64> {
+    let path = fs::canonicalize(path)
+        .or_else(|_| std::env::current_dir())
+        .ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Guardrail state, from least to most severe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageState {
+    #[default]
+    Ok,
+    /// Over a soft limit; old blocks are pruned
+    Pressure,
+    /// Below the hard floor; block production is refused
+    Critical,
+}
+
+/// Latest guardrail check, as reported on `/health`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageHealth {
+    pub state: StorageState,
+    pub usage: StorageUsage,
+    /// Blocks pruned over the node's lifetime
+    pub pruned_blocks: u64,
+    /// VACUUMs run over the node's lifetime
+    pub vacuums: u64,
+    pub last_error: Option<String>,
+}
+
+/// When the guard last ran a VACUUM and whether pruning left one pending
+#[derive(Debug, Default)]
+struct VacuumState {
+    last: Option<Instant>,
+    pending: bool,
+}
+
+/// Enforces `StorageLimits` for one ledger file
+pub struct StorageGuard {
+    db_path: PathBuf,
+    limits: StorageLimits,
+    health: RwLock<StorageHealth>,
+    vacuum: Mutex<VacuumState>,
+}
+
+impl StorageGuard {
+    pub fn new(db_path: impl Into<PathBuf>, limits: StorageLimits) -> Self {
+        StorageGuard {
+            db_path: db_path.into(),
+            limits,
+            health: RwLock::new(StorageHealth::default()),
+            vacuum: Mutex::new(VacuumState::default()),
+        }
+    }
+
+    pub fn limits(&self) -> &StorageLimits {
+        &self.limits
+    }
+
+    pub fn health(&self) -> StorageHealth {
+        self.health.read().clone()
+    }
+
+    /// Measure, prune if over a soft limit, and return an error when block
+    /// production must stop
+    pub fn check(&self, db: &DatabaseManager) -> Result<StorageHealth, String> {
+        self.check_with(db, || StorageUsage::measure(&self.db_path))
+    }
+
+    /// `check` with a custom measurement, so limits can be exercised without
+    /// filling a disk
+    pub fn check_with(
+        &self,
+        db: &DatabaseManager,
+        measure: impl Fn() -> StorageUsage,
+    ) -> Result<StorageHealth, String> {
+        let mut usage = measure();
+        let mut state = self.limits.evaluate(&usage);
+        let mut pruned = 0;
+        let mut vacuumed = false;
+        let mut last_error = None;
+
+        if state != StorageState::Ok {
+            match self.prune(db) {
+                Ok(removed) => pruned = removed as u64,
+                Err(e) => {
+                    warn!(error = %e, "Storage: Pruning failed");
+                    last_error = Some(e.to_string());
+                }
+            }
+        }
+        match self.vacuum_if_due(db) {
+            Ok(ran) => vacuumed = ran,
+            Err(e) => {
+                warn!(error = %e, "Storage: VACUUM failed");
+                last_error = Some(e.to_string());
+            }
+        }
+        if pruned > 0 || vacuumed {
+            usage = measure();
+            state = self.limits.evaluate(&usage);
+            if pruned > 0 {
+                state = state.max(StorageState::Pressure);
+            }
+        }
+
+        if state == StorageState::Critical {
+            last_error = Some(format!(
+                "free disk space {} MB is below the hard floor of {} MB",
+                usage.free_bytes.unwrap_or(0) / MB,
+                self.limits.hard_floor_bytes.unwrap_or(0) / MB
+            ));
+        }
+
+        let health = {
+            let mut health = self.health.write();
+            health.state = state;
+            health.usage = usage;
+            health.pruned_blocks += pruned;
+            health.vacuums += u64::from(vacuumed);
+            health.last_error = last_error.clone();
+            health.clone()
+        };
+
+        match (state, last_error) {
+            (StorageState::Critical, Some(reason)) => Err(reason),
+            _ => Ok(health),
+        }
+    }
+
+    /// Archive and delete everything but the newest `retain_blocks` blocks
+    fn prune(&self, db: &DatabaseManager) -> DbResult<usize> {
+        let Some(tip) = db.get_latest_block()? else {
+            return Ok(0);
+        };
+        let keep_from = tip.index.saturating_sub(self.limits.retain_blocks) + 1;
+        let stats = db.get_stats()?;
+        let Some(oldest) = stats.min_index.filter(|&oldest| oldest < keep_from) else {
+            return Ok(0);
+        };
+
+        if let Some(dir) = &self.limits.archive_dir {
+            let path = dir.join(format!("blocks_{}_{}.jsonl", oldest, keep_from - 1));
+            let cannot_archive = |e: String| {
+                DatabaseError::InvalidData(format!("cannot archive to {}: {}", path.display(), e))
+            };
+            let out = archive_file(&path).map_err(|e| cannot_archive(e.to_string()))?;
+            let archived = db
+                .export_chain(oldest..keep_from, out)
+                .map_err(|e| cannot_archive(e.to_string()))?;
+            info!(path = %path.display(), blocks = archived, "Storage: Archived blocks");
+        }
+
+        let removed = db.prune_blocks_below(keep_from)?;
+        self.vacuum.lock().pending |= removed > 0;
+        info!(
+            removed = removed,
+            oldest_kept = keep_from,
+            "Storage: Pruned old blocks"
+        );
+        Ok(removed)
+    }
+
+    /// VACUUM if pruning freed pages and the last VACUUM is at least
+    /// `min_vacuum_interval` ago; returns whether it ran
+    fn vacuum_if_due(&self, db: &DatabaseManager) -> DbResult<bool> {
+        let mut vacuum = self.vacuum.lock();
+        if !vacuum.pending {
+            return Ok(false);
+        }
+        if let Some(last) = vacuum.last {
+            if last.elapsed() < self.limits.min_vacuum_interval {
+                debug!(
+                    since_secs = last.elapsed().as_secs(),
+                    "Storage: VACUUM deferred until the minimum interval has passed"
+                );
+                return Ok(false);
+            }
+        }
+        db.vacuum()?;
+        vacuum.last = Some(Instant::now());
+        vacuum.pending = false;
+        Ok(true)
+    }
+}
+
+/// Replace the archived copy of a redacted block with `tombstone` in every
+/// JSON-lines archive in `dir`; returns how many copies were replaced
+pub fn redact_archived(dir: &Path, tombstone: &Block) -> std::io::Result<usize> {
+    let mut replaced = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)?;
+        let mut found = false;
+        let mut rewritten = String::with_capacity(contents.len());
+        for line in contents.lines() {
+            match serde_json::from_str::<Block>(line) {
+                Ok(block) if block.index == tombstone.index && block.hash == tombstone.hash => {
+                    rewritten.push_str(&serde_json::to_string(tombstone)?);
+                    found = true;
+                    replaced += 1;
+                }
+                _ => rewritten.push_str(line),
+            }
+            rewritten.push('\n');
+        }
+        if found {
+            let staging = path.with_extension("jsonl.tmp");
+            fs::write(&staging, rewritten)?;
+            fs::rename(&staging, &path)?;
+        }
+    }
+    Ok(replaced)
+}
+
+fn archive_file(path: &Path) -> std::io::Result<BufWriter<File>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestChainBuilder};
+
+    fn save_chain(db: &DatabaseManager, indices: std::ops::RangeInclusive<u64>) {
+        let chain = TestChainBuilder::new()
+            .starting_after(indices.start() - 1, testing::GENESIS_PARENT_HASH)
+            .with_blocks(indices.count() as u64)
+            .build();
+        db.save_blocks(&chain).unwrap();
+    }
+
+    #[test]
+    fn test_storage_limits_evaluate() {
+        let limits = StorageLimits {
+            max_db_bytes: Some(100),
+            min_free_bytes: Some(50 * MB),
+            hard_floor_bytes: Some(10 * MB),
+            ..StorageLimits::new()
+        };
+        let usage = |db_bytes, free_mb: u64| StorageUsage {
+            db_bytes,
+            free_bytes: Some(free_mb * MB),
+        };
+
+        assert_eq!(limits.evaluate(&usage(10, 100)), StorageState::Ok);
+        assert_eq!(limits.evaluate(&usage(200, 100)), StorageState::Pressure);
+        assert_eq!(limits.evaluate(&usage(10, 20)), StorageState::Pressure);
+        assert_eq!(limits.evaluate(&usage(10, 5)), StorageState::Critical);
+        // Unknown free space only enforces the size limit
+        let unknown = StorageUsage {
+            db_bytes: 10,
+            free_bytes: None,
+        };
+        assert_eq!(limits.evaluate(&unknown), StorageState::Ok);
+        assert!(!StorageLimits::new().is_enabled());
+    }
+
+    #[test]
+    fn test_storage_limits_from_vars() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let limits = StorageLimits::from_vars(vars(&[
+            ("DISK_MIN_FREE_MB", "100"),
+            ("DISK_HARD_FLOOR_MB", "20"),
+        ]))
+        .unwrap();
+        assert_eq!(limits.min_free_bytes, Some(100 * MB));
+        assert_eq!(limits.hard_floor_bytes, Some(20 * MB));
+        assert_eq!(limits.retain_blocks, DEFAULT_RETAIN_BLOCKS);
+
+        for (pairs, name) in [
+            (&[("DB_MAX_SIZE_MB", "1gb")][..], "DB_MAX_SIZE_MB"),
+            (
+                &[("DB_MAX_SIZE_MB", "18446744073709551615")][..],
+                "DB_MAX_SIZE_MB",
+            ),
+            (&[("PRUNE_RETAIN_BLOCKS", "-1")][..], "PRUNE_RETAIN_BLOCKS"),
+            (
+                &[("DISK_MIN_FREE_MB", "20"), ("DISK_HARD_FLOOR_MB", "100")][..],
+                "DISK_HARD_FLOOR_MB",
+            ),
+        ] {
+            let err = StorageLimits::from_vars(vars(pairs)).unwrap_err();
+            assert!(err.contains(name), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_storage_guard_prunes_and_refuses_below_floor() {
+        let path = "test_storage_guard.db";
+        let archive_dir = PathBuf::from("test_storage_guard_archive");
+        fs::remove_file(path).ok();
+        fs::remove_dir_all(&archive_dir).ok();
+        let db = DatabaseManager::new(path).unwrap();
+        db.init().unwrap();
+        save_chain(&db, 1..=5);
+
+        let guard = StorageGuard::new(
+            path,
+            StorageLimits {
+                max_db_bytes: Some(1),
+                hard_floor_bytes: Some(10 * MB),
+                retain_blocks: 2,
+                archive_dir: Some(archive_dir.clone()),
+                ..StorageLimits::new()
+            },
+        );
+        let plenty = || StorageUsage {
+            db_bytes: 1000,
+            free_bytes: Some(100 * MB),
+        };
+
+        let health = guard.check_with(&db, plenty).unwrap();
+        assert_eq!(health.state, StorageState::Pressure);
+        assert_eq!(health.pruned_blocks, 3);
+        assert_eq!(health.vacuums, 1);
+        assert_eq!(db.get_stats().unwrap().min_index, Some(4));
+        let archived = fs::read_to_string(archive_dir.join("blocks_1_3.jsonl")).unwrap();
+        assert_eq!(archived.lines().count(), 3);
+
+        // A redacted block's archived copy becomes the tombstone
+        let mut tombstone: Block = serde_json::from_str(archived.lines().nth(1).unwrap()).unwrap();
+        tombstone.data.clear();
+        assert_eq!(redact_archived(&archive_dir, &tombstone).unwrap(), 1);
+        let archived = fs::read_to_string(archive_dir.join("blocks_1_3.jsonl")).unwrap();
+        assert_eq!(archived.lines().count(), 3);
+        let rewritten: Block = serde_json::from_str(archived.lines().nth(1).unwrap()).unwrap();
+        assert!(rewritten.data.is_empty());
+        assert_eq!(rewritten.hash, tombstone.hash);
+
+        // Nothing left to prune, and the disk is below the hard floor
+        let full = || StorageUsage {
+            db_bytes: 1000,
+            free_bytes: Some(MB),
+        };
+        let err = guard.check_with(&db, full).unwrap_err();
+        assert!(err.contains("below the hard floor"));
+        assert_eq!(guard.health().state, StorageState::Critical);
+        assert_eq!(db.get_block_count().unwrap(), 2);
+
+        fs::remove_file(path).ok();
+        fs::remove_dir_all(&archive_dir).ok();
+    }
+
+    #[test]
+    fn test_storage_guard_rate_limits_vacuum() {
+        let path = "test_storage_guard_vacuum.db";
+        fs::remove_file(path).ok();
+        let db = DatabaseManager::new(path).unwrap();
+        db.init().unwrap();
+        save_chain(&db, 1..=5);
+
+        let guard = StorageGuard::new(
+            path,
+            StorageLimits {
+                max_db_bytes: Some(1),
+                retain_blocks: 2,
+                ..StorageLimits::new()
+            },
+        );
+        let over = || StorageUsage {
+            db_bytes: 1000,
+            free_bytes: None,
+        };
+        assert_eq!(guard.check_with(&db, over).unwrap().vacuums, 1);
+
+        // A second prune within the interval leaves the VACUUM pending
+        save_chain(&db, 6..=8);
+        let health = guard.check_with(&db, over).unwrap();
+        assert_eq!(health.pruned_blocks, 6);
+        assert_eq!(health.vacuums, 1);
+        assert!(guard.vacuum.lock().pending);
+
+        // and it runs once the interval has passed, with nothing to prune
+        guard.vacuum.lock().last = Some(Instant::now() - DEFAULT_VACUUM_INTERVAL);
+        let under = || StorageUsage {
+            db_bytes: 0,
+            free_bytes: None,
+        };
+        assert_eq!(guard.check_with(&db, under).unwrap().vacuums, 2);
+        assert!(!guard.vacuum.lock().pending);
+
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,216 @@
+//! Hybrid logical clocks
+//!
+//! Wall-clock timestamps from different nodes cannot be compared when the
+//! clocks are skewed: a block proposed by a node running behind can carry an
+//! earlier time than its parent. An `HlcTimestamp` pairs the largest wall
+//! time a node has seen (its own or a peer's) with a logical counter, so a
+//! timestamp issued after receiving a message always orders after that
+//! message, whatever the local clock says. Ties are broken by node id, which
+//! makes the order total.
+//!
+//! Every node keeps one `HybridClock`: it stamps outgoing consensus messages
+//! and new blocks with `now`, and feeds the timestamps of incoming messages
+//! to `observe`. A remote timestamp further ahead of the local clock than
+//! the configured drift is rejected rather than adopted, so one node with a
+//! runaway clock cannot drag the cluster's time forward.
+
+use crate::etl::now_millis;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How far ahead of the local clock a remote timestamp may be
+pub const DEFAULT_MAX_HLC_DRIFT_MS: i64 = 60_000;
+
+/// A point in hybrid logical time, ordered by wall time, then counter, then
+/// node id
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct HlcTimestamp {
+    /// Largest unix time in milliseconds the issuing node had seen
+    pub wall_ms: i64,
+    /// Events issued within the same `wall_ms`
+    pub logical: u32,
+    pub node_id: usize,
+}
+
+impl HlcTimestamp {
+    /// Timestamp for a plain wall-clock time, e.g. a block written before
+    /// blocks carried one
+    pub fn from_wall(wall_ms: i64) -> Self {
+        HlcTimestamp {
+            wall_ms,
+            logical: 0,
+            node_id: 0,
+        }
+    }
+}
+
+impl fmt::Display for HlcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}@{}", self.wall_ms, self.logical, self.node_id)
+    }
+}
+
+/// A remote timestamp too far ahead of the local clock to adopt
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockDriftError {
+    pub remote: HlcTimestamp,
+    pub local_ms: i64,
+    pub max_drift_ms: i64,
+}
+
+impl fmt::Display for ClockDriftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HLC {} is {} ms ahead of the local clock (max {} ms)",
+            self.remote,
+            self.remote.wall_ms - self.local_ms,
+            self.max_drift_ms
+        )
+    }
+}
+
+impl std::error::Error for ClockDriftError {}
+
+/// A node's hybrid logical clock; timestamps it issues strictly increase
+pub struct HybridClock {
+    node_id: usize,
+    max_drift_ms: i64,
+    last: Mutex<HlcTimestamp>,
+}
+
+impl HybridClock {
+    pub fn new(node_id: usize) -> Self {
+        HybridClock {
+            node_id,
+            max_drift_ms: DEFAULT_MAX_HLC_DRIFT_MS,
+            last: Mutex::new(HlcTimestamp {
+                node_id,
+                ..HlcTimestamp::default()
+            }),
+        }
+    }
+
+    pub fn with_max_drift(mut self, max_drift_ms: i64) -> Self {
+        self.max_drift_ms = max_drift_ms;
+        self
+    }
+
+    pub fn node_id(&self) -> usize {
+        self.node_id
+    }
+
+    /// The last timestamp issued or observed
+    pub fn last(&self) -> HlcTimestamp {
+        *self.last.lock()
+    }
+
+    /// Timestamp for a local event, e.g. a message about to be sent
+    pub fn now(&self) -> HlcTimestamp {
+        self.now_at(now_millis())
+    }
+
+    fn now_at(&self, physical_ms: i64) -> HlcTimestamp {
+        let mut last = self.last.lock();
+        let next = if physical_ms > last.wall_ms {
+            HlcTimestamp {
+                wall_ms: physical_ms,
+                logical: 0,
+                node_id: self.node_id,
+            }
+        } else {
+            HlcTimestamp {
+                wall_ms: last.wall_ms,
+                logical: last.logical + 1,
+                node_id: self.node_id,
+            }
+        };
+        *last = next;
+        next
+    }
+
+    /// Merge a timestamp received from a peer; the returned timestamp
+    /// orders after both `remote` and everything this clock issued before
+    pub fn observe(&self, remote: &HlcTimestamp) -> Result<HlcTimestamp, ClockDriftError> {
+        self.observe_at(remote, now_millis())
+    }
+
+    fn observe_at(
+        &self,
+        remote: &HlcTimestamp,
+        physical_ms: i64,
+    ) -> Result<HlcTimestamp, ClockDriftError> {
+        if remote.wall_ms - physical_ms > self.max_drift_ms {
+            return Err(ClockDriftError {
+                remote: *remote,
+                local_ms: physical_ms,
+                max_drift_ms: self.max_drift_ms,
+            });
+        }
+        let mut last = self.last.lock();
+        let wall_ms = physical_ms.max(last.wall_ms).max(remote.wall_ms);
+        let logical = match (wall_ms == last.wall_ms, wall_ms == remote.wall_ms) {
+            (true, true) => last.logical.max(remote.logical) + 1,
+            (true, false) => last.logical + 1,
+            (false, true) => remote.logical + 1,
+            (false, false) => 0,
+        };
+        let next = HlcTimestamp {
+            wall_ms,
+            logical,
+            node_id: self.node_id,
+        };
+        *last = next;
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hlc_orders_across_skewed_clocks() {
+        // Node 1's wall clock runs 5 s behind node 0's
+        let fast = HybridClock::new(0);
+        let slow = HybridClock::new(1);
+
+        let sent = fast.now_at(10_000);
+        let received = slow.observe_at(&sent, 5_000).unwrap();
+        assert!(received > sent);
+        assert_eq!((received.wall_ms, received.logical), (10_000, 1));
+
+        // Later local events on the slow node still order after the message
+        let reply = slow.now_at(5_100);
+        assert!(reply > received);
+        assert_eq!((reply.wall_ms, reply.logical), (10_000, 2));
+
+        // Once physical time passes the adopted wall time, the counter resets
+        assert_eq!(
+            slow.now_at(10_500),
+            HlcTimestamp {
+                wall_ms: 10_500,
+                logical: 0,
+                node_id: 1,
+            }
+        );
+
+        // A clock that jumps backwards does not move the HLC back
+        let after_jump = fast.now_at(1_000);
+        assert!(after_jump > sent);
+
+        let runaway = HlcTimestamp {
+            wall_ms: 200_000,
+            logical: 0,
+            node_id: 2,
+        };
+        let clock = HybridClock::new(3).with_max_drift(1_000);
+        let before = clock.now_at(100_000);
+        let err = clock.observe_at(&runaway, 100_000).unwrap_err();
+        assert_eq!(err.max_drift_ms, 1_000);
+        assert_eq!(clock.last(), before);
+    }
+}
@@ -0,0 +1,263 @@
+//! Rolling indicators over recent quotes
+//!
+//! `IndicatorStage` enriches each record after `normalize` with a simple
+//! and an exponential moving average of its asset's last N normalized
+//! prices, and a volume-weighted average price over those that came with
+//! the source's 24h volume. The ledger entry carries them in
+//! `MarketData::indicators` next to the price itself.
+//!
+//! The window holds committed entries only. Transforming a record computes
+//! its indicators over the window as if the record were added, but leaves
+//! the window alone; `record_block` adds a block's entries once it is
+//! committed, so records of a block consensus rejects, or quotes dropped
+//! later in the round, never count. Clones of the stage (each
+//! `Transformer::pipeline`) share the window, and a node seeds it from the
+//! entries of its latest blocks on start, so a restart does not reset the
+//! averages. Quotes flagged as duplicates never reach the stage.
+//!
+//! Enabled with `INDICATOR_WINDOW` (the N observations; unset or 0 leaves
+//! entries without indicators).
+
+use crate::etl::pipeline::{StageContext, TransformStage};
+use crate::etl::price::{self, Decimal};
+use crate::etl::transform::TransformResult;
+use crate::etl::Block;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Decimal places indicator values are rounded to
+pub const INDICATOR_DECIMALS: u32 = 8;
+
+/// Rolling values recorded with an entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Indicators {
+    /// Configured window; the EMA smoothing factor is `2 / (window + 1)`
+    pub window: u32,
+    /// Observations the values cover, this entry's included; below
+    /// `window` until the window fills
+    pub observations: u32,
+    #[serde(with = "price::serde_number")]
+    pub sma: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub ema: Decimal,
+    /// Missing when no observation in the window had a volume
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "price::serde_number_option"
+    )]
+    pub vwap: Option<Decimal>,
+    /// 24h volume the source reported with this entry's quote
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "price::serde_number_option"
+    )]
+    pub volume: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    price: Decimal,
+    volume: Option<Decimal>,
+}
+
+/// Attaches `Indicators` to every record it sees
+#[derive(Debug, Clone)]
+pub struct IndicatorStage {
+    window: usize,
+    /// Most recent observations per asset, oldest first
+    history: Arc<Mutex<HashMap<String, VecDeque<Observation>>>>,
+}
+
+impl IndicatorStage {
+    pub fn new(window: usize) -> Self {
+        IndicatorStage {
+            window: window.max(1),
+            history: Arc::default(),
+        }
+    }
+
+    /// `None` unless `INDICATOR_WINDOW` is set to a positive count
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("INDICATOR_WINDOW") {
+            Ok(window) => {
+                let window: usize = window
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("invalid INDICATOR_WINDOW: {}", e))?;
+                Ok((window > 0).then(|| Self::new(window)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Indicators over the window of `asset` with an observation added,
+    /// without adding it
+    pub fn preview(&self, asset: &str, price: Decimal, volume: Option<Decimal>) -> Indicators {
+        let history = self.history.lock();
+        let mut observations = history.get(asset).cloned().unwrap_or_default();
+        Self::push(
+            &mut observations,
+            Observation { price, volume },
+            self.window,
+        );
+        self.indicators(&observations, volume)
+    }
+
+    /// Add the entries of a committed block that were recorded with
+    /// indicators to the window
+    pub fn record_block(&self, block: &Block) {
+        let mut history = self.history.lock();
+        for item in block.data.iter() {
+            if let Some(indicators) = &item.indicators {
+                let observation = Observation {
+                    price: item.price,
+                    volume: indicators.volume,
+                };
+                let observations = history.entry(item.asset.clone()).or_default();
+                Self::push(observations, observation, self.window);
+            }
+        }
+    }
+
+    /// `record_block` for each of `blocks`, in ascending index order
+    pub fn seed(&self, blocks: &[Block]) {
+        for block in blocks {
+            self.record_block(block);
+        }
+    }
+
+    fn push(observations: &mut VecDeque<Observation>, observation: Observation, window: usize) {
+        observations.push_back(observation);
+        while observations.len() > window {
+            observations.pop_front();
+        }
+    }
+
+    fn indicators(
+        &self,
+        observations: &VecDeque<Observation>,
+        volume: Option<Decimal>,
+    ) -> Indicators {
+        let count = Decimal::from(observations.len() as u64);
+        let sum: Decimal = observations.iter().map(|o| o.price).sum();
+        let alpha = Decimal::TWO / Decimal::from(self.window as u64 + 1);
+        let ema = observations
+            .iter()
+            .skip(1)
+            .fold(observations[0].price, |ema, o| {
+                ema + alpha * (o.price - ema)
+            });
+        let (turnover, traded) = observations
+            .iter()
+            .filter_map(|o| o.volume.map(|volume| (o.price * volume, volume)))
+            .fold((Decimal::ZERO, Decimal::ZERO), |(t, v), (ot, ov)| {
+                (t + ot, v + ov)
+            });
+        Indicators {
+            window: self.window as u32,
+            observations: observations.len() as u32,
+            sma: price::round(sum / count, INDICATOR_DECIMALS),
+            ema: price::round(ema, INDICATOR_DECIMALS),
+            vwap: (!traded.is_zero()).then(|| price::round(turnover / traded, INDICATOR_DECIMALS)),
+            volume,
+        }
+    }
+}
+
+impl TransformStage for IndicatorStage {
+    fn name(&self) -> &str {
+        "indicators"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        record.indicators = Some(self.preview(&record.asset, record.price, record.volume));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::MarketData;
+
+    fn entry(price: i64, volume: Option<i64>) -> MarketData {
+        let indicators =
+            IndicatorStage::new(1).preview("BTC", Decimal::from(price), volume.map(Decimal::from));
+        MarketData {
+            asset: "BTC".to_string(),
+            price: Decimal::from(price),
+            source: "Kraken".to_string(),
+            timestamp: 1_700_000_000_000,
+            provenance: None,
+            indicators: Some(indicators),
+            conversion: None,
+            bucket: None,
+        }
+    }
+
+    fn block(index: u64, data: Vec<MarketData>) -> Block {
+        crate::testing::block(index, "parent", data)
+    }
+
+    #[test]
+    fn test_rolling_averages_over_window() {
+        let stage = IndicatorStage::new(3);
+        let first = stage.preview("BTC", Decimal::from(100), Some(Decimal::ONE));
+        assert_eq!(first.observations, 1);
+        assert_eq!(
+            (first.sma, first.ema),
+            (Decimal::from(100), Decimal::from(100))
+        );
+        // Previews leave the window alone until a block is committed
+        assert_eq!(
+            stage.preview("BTC", Decimal::from(120), None).observations,
+            1
+        );
+        stage.record_block(&block(1, vec![entry(100, Some(1))]));
+        stage.record_block(&block(2, vec![entry(110, None)]));
+        let third = stage.preview("BTC", Decimal::from(130), Some(Decimal::from(3)));
+        assert_eq!(third.observations, 3);
+        assert_eq!(third.sma, price::parse("113.33333333").unwrap());
+        // alpha = 0.5: 100 -> 105 -> 117.5
+        assert_eq!(third.ema, price::parse("117.5").unwrap());
+        // (100 * 1 + 130 * 3) / 4
+        assert_eq!(third.vwap, Some(price::parse("122.5").unwrap()));
+        stage.record_block(&block(3, vec![entry(130, Some(3))]));
+
+        // The oldest observation leaves the window
+        let fourth = stage.preview("BTC", Decimal::from(140), None);
+        assert_eq!(fourth.observations, 3);
+        assert_eq!(fourth.sma, price::parse("126.66666667").unwrap());
+        // 110 -> 120 -> 130
+        assert_eq!(fourth.ema, Decimal::from(130));
+        assert_eq!(fourth.vwap, Some(Decimal::from(130)));
+        let eth = stage.preview("ETH", Decimal::from(3100), None);
+        assert_eq!((eth.observations, eth.vwap), (1, None));
+
+        // A restarted stage seeded from the ledger picks up where it was
+        let restarted = IndicatorStage::new(3);
+        restarted.seed(&[
+            block(2, vec![entry(110, None)]),
+            block(3, vec![entry(130, Some(3))]),
+            block(4, vec![entry(140, None)]),
+        ]);
+        let next = restarted.preview("BTC", Decimal::from(150), None);
+        assert_eq!(next.sma, Decimal::from(140));
+        assert_eq!(next.vwap, Some(Decimal::from(130)));
+
+        let json = serde_json::to_value(&fourth).unwrap();
+        assert_eq!(json["sma"], 126.66666667);
+        assert!(json.get("volume").is_none());
+        let parsed: Indicators = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, fourth);
+    }
+}
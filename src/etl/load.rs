@@ -1,6 +1,16 @@
+use crate::etl::accounting::Account;
+use crate::etl::analytics::{AnalyticsBuilder, AnalyticsRange, ChainAnalytics};
+use crate::etl::block_cache::{BlockCache, BlockCacheStats, DEFAULT_BLOCK_CACHE_BLOCKS};
+use crate::etl::encryption::PayloadCipher;
 use crate::etl::Block;
+use crate::network::redaction::{self, SignedRedaction};
 use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+use std::ops::{Bound, RangeBounds};
 use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tracing::{debug, info};
 
 #[derive(Debug)]
@@ -32,8 +42,313 @@ impl From<rusqlite::Error> for DatabaseError {
 
 pub type DbResult<T> = Result<T, DatabaseError>;
 
+/// Latest schema version; see `DatabaseManager::migrate`
+const SCHEMA_VERSION: i64 = 14;
+
+fn blockchain_table_sql(table: &str) -> String {
+    format!(
+        "CREATE TABLE {} (
+            id            INTEGER PRIMARY KEY AUTOINCREMENT,
+            block_index   INTEGER NOT NULL UNIQUE,
+            timestamp     INTEGER NOT NULL,
+            data_json     TEXT NOT NULL,
+            prev_hash     TEXT NOT NULL,
+            hash          TEXT NOT NULL UNIQUE,
+            nonce         INTEGER NOT NULL,
+            format_version INTEGER NOT NULL DEFAULT 0,
+            fees_json     TEXT NOT NULL DEFAULT '[]',
+            divergences_json TEXT NOT NULL DEFAULT '[]',
+            hlc_json      TEXT,
+            annotations_json TEXT NOT NULL DEFAULT '{{}}',
+            order_books_json TEXT NOT NULL DEFAULT '[]',
+            created_at    INTEGER NOT NULL
+                          DEFAULT (CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER))
+        )",
+        table
+    )
+}
+
+/// Blocks received from peers that failed verification during sync (v2)
+const QUARANTINE_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS quarantined_blocks (
+        id             INTEGER PRIMARY KEY AUTOINCREMENT,
+        block_index    INTEGER NOT NULL,
+        hash           TEXT NOT NULL,
+        block_json     TEXT NOT NULL,
+        peer           TEXT NOT NULL,
+        reason         TEXT NOT NULL,
+        quarantined_at INTEGER NOT NULL
+                       DEFAULT (CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER))
+    )";
+
+/// Secondary indexes on the blockchain table, also recreated by
+/// `rebuild_derived`
+const BLOCKCHAIN_INDEXES_SQL: &str = "
+    CREATE INDEX IF NOT EXISTS idx_block_index ON blockchain(block_index);
+    CREATE INDEX IF NOT EXISTS idx_hash ON blockchain(hash);
+    CREATE INDEX IF NOT EXISTS idx_timestamp ON blockchain(timestamp);";
+
+/// Last tip confirmed by the rolling verifier; a single row (v4)
+const VERIFICATION_CHECKPOINT_TABLE_SQL: &str =
+    "CREATE TABLE IF NOT EXISTS verification_checkpoint (
+        id          INTEGER PRIMARY KEY CHECK (id = 1),
+        block_index INTEGER NOT NULL,
+        hash        TEXT NOT NULL,
+        verified_at INTEGER NOT NULL
+    )";
+
+/// Submitter balances and usage for fee accounting (v5)
+const ACCOUNTS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS accounts (
+        submitter  TEXT PRIMARY KEY,
+        balance    INTEGER NOT NULL,
+        entries    INTEGER NOT NULL,
+        fees_paid  INTEGER NOT NULL
+    )";
+
+/// When each committed block's data was observed and when it committed (v6)
+const COMMIT_LATENCY_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS commit_latency (
+        block_index  INTEGER PRIMARY KEY,
+        observed_at  INTEGER NOT NULL,
+        committed_at INTEGER NOT NULL
+    )";
+
+/// Outbound consensus messages, one row per peer, kept until the peer
+/// acknowledges them (v8)
+const OUTBOX_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS outbox (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        peer         TEXT NOT NULL,
+        payload      TEXT NOT NULL,
+        trace_id     TEXT,
+        attempts     INTEGER NOT NULL DEFAULT 0,
+        created_at   INTEGER NOT NULL
+                     DEFAULT (CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)),
+        delivered_at INTEGER
+    )";
+
+/// Signed statements of the chain head produced for auditors (v9)
+const ATTESTATIONS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS attestations (
+        id            INTEGER PRIMARY KEY AUTOINCREMENT,
+        height        INTEGER NOT NULL,
+        head_hash     TEXT NOT NULL,
+        attested_at   INTEGER NOT NULL,
+        document_json TEXT NOT NULL,
+        notarized_at  INTEGER
+    )";
+
+/// Chain heads submitted to an external timestamping service, with the
+/// proof it returned (v10)
+const ANCHORS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS anchors (
+        id             INTEGER PRIMARY KEY AUTOINCREMENT,
+        height         INTEGER NOT NULL,
+        head_hash      TEXT NOT NULL,
+        calendar       TEXT NOT NULL,
+        proof          BLOB NOT NULL,
+        submitted_at   INTEGER NOT NULL,
+        bitcoin_height INTEGER,
+        confirmed_at   INTEGER
+    )";
+
+/// Signed records of block payloads removed from the store; the block keeps
+/// its hash (v11)
+const REDACTIONS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS redactions (
+        block_index INTEGER PRIMARY KEY,
+        block_hash  TEXT NOT NULL,
+        reason      TEXT NOT NULL,
+        redacted_by INTEGER NOT NULL,
+        redacted_at INTEGER NOT NULL,
+        public_key  TEXT NOT NULL,
+        signature   TEXT NOT NULL
+    )";
+
+/// Block timestamp normalized to milliseconds, for range filters that must
+/// also match rows written before the millisecond migration
+fn timestamp_millis_sql() -> String {
+    format!(
+        "(CASE WHEN abs(timestamp) < {0} THEN timestamp * 1000 ELSE timestamp END)",
+        crate::etl::LEGACY_SECONDS_THRESHOLD
+    )
+}
+
+/// `hlc_json` column value for `block`
+fn encode_hlc(block: &Block) -> DbResult<Option<String>> {
+    block
+        .hlc
+        .map(|hlc| serde_json::to_string(&hlc))
+        .transpose()
+        .map_err(|e| DatabaseError::Serialization(e.to_string()))
+}
+
+/// Column list shared by every block query; must match `row_to_block`
+const BLOCK_COLUMNS: &str = "block_index, timestamp, data_json, prev_hash, hash, nonce, \
+     format_version, fees_json, divergences_json, hlc_json, annotations_json, \
+     order_books_json";
+
+/// Payload column `column` of block `index`, decrypted with `cipher` when
+/// it was stored encrypted; `hash` is the associated data
+fn decrypt_column(
+    stored: String,
+    column: usize,
+    index: u64,
+    hash: &str,
+    cipher: Option<&PayloadCipher>,
+) -> rusqlite::Result<String> {
+    if !PayloadCipher::is_encrypted(&stored) {
+        return Ok(stored);
+    }
+    cipher
+        .ok_or_else(|| "no payload key is configured".to_string())
+        .and_then(|cipher| cipher.decrypt(&stored, hash.as_bytes()))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                column,
+                rusqlite::types::Type::Text,
+                format!("block {}: {}", index, e).into(),
+            )
+        })
+}
+
+///
+/// Encrypted payloads are decrypted with `cipher`
+fn row_to_block(
+    row: &rusqlite::Row<'_>,
+    cipher: Option<&PayloadCipher>,
+) -> rusqlite::Result<Block> {
+    let idx: u64 = row.get(0)?;
+    let timestamp: i64 = row.get(1)?;
+    let prev_hash: String = row.get(3)?;
+    let hash: String = row.get(4)?;
+    let payload = |column: usize| decrypt_column(row.get(column)?, column, idx, &hash, cipher);
+    let data_json = payload(2)?;
+    let nonce: u64 = row.get(5)?;
+    let format_version: u32 = row.get(6)?;
+    let fees_json = payload(7)?;
+    let divergences_json = payload(8)?;
+    let hlc_json: Option<String> = row.get(9)?;
+    let annotations_json: String = row.get(10)?;
+    let order_books_json = payload(11)?;
+
+    let data: Vec<crate::etl::MarketData> = serde_json::from_str(&data_json).map_err(|_e| {
+        rusqlite::Error::InvalidColumnType(2, "data_json".to_string(), rusqlite::types::Type::Text)
+    })?;
+    let fees = serde_json::from_str(&fees_json).map_err(|_e| {
+        rusqlite::Error::InvalidColumnType(7, "fees_json".to_string(), rusqlite::types::Type::Text)
+    })?;
+    let divergences = serde_json::from_str(&divergences_json).map_err(|_e| {
+        rusqlite::Error::InvalidColumnType(
+            8,
+            "divergences_json".to_string(),
+            rusqlite::types::Type::Text,
+        )
+    })?;
+    let hlc = hlc_json
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|_e| {
+            rusqlite::Error::InvalidColumnType(
+                9,
+                "hlc_json".to_string(),
+                rusqlite::types::Type::Text,
+            )
+        })?;
+    let annotations = serde_json::from_str(&annotations_json).map_err(|_e| {
+        rusqlite::Error::InvalidColumnType(
+            10,
+            "annotations_json".to_string(),
+            rusqlite::types::Type::Text,
+        )
+    })?;
+    let order_books = serde_json::from_str(&order_books_json).map_err(|_e| {
+        rusqlite::Error::InvalidColumnType(
+            11,
+            "order_books_json".to_string(),
+            rusqlite::types::Type::Text,
+        )
+    })?;
+
+    Ok(Block {
+        index: idx,
+        timestamp,
+        data,
+        previous_hash: prev_hash,
+        hash,
+        nonce,
+        format_version,
+        fees,
+        divergences,
+        hlc,
+        annotations,
+        order_books,
+    })
+}
+
+/// Filters for `DatabaseManager::search_blocks`
+///
+/// Every filter is optional; an empty query returns the first `limit` blocks.
+#[derive(Debug, Clone, Default)]
+pub struct BlockQuery {
+    /// Only blocks containing at least one entry for this asset
+    pub asset: Option<String>,
+    /// Only blocks containing at least one entry from this source
+    pub source: Option<String>,
+    /// Inclusive lower bound on the block timestamp (milliseconds)
+    pub from_timestamp: Option<i64>,
+    /// Inclusive upper bound on the block timestamp (milliseconds)
+    pub to_timestamp: Option<i64>,
+    /// Only blocks annotated with this key and value
+    pub annotation: Option<(String, String)>,
+    /// Maximum number of blocks returned (None means unbounded)
+    pub limit: Option<u64>,
+}
+
+/// How often SQLite fsyncs (`PRAGMA synchronous`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// fsync on every commit; survives power loss (SQLite default)
+    Full,
+    /// Fewer fsyncs; survives process crashes but may lose the most recent
+    /// commits on power loss
+    Normal,
+}
+
+impl SyncPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "full" => Some(SyncPolicy::Full),
+            "normal" => Some(SyncPolicy::Normal),
+            _ => None,
+        }
+    }
+}
+
+/// How SQLite journals writes (`PRAGMA journal_mode`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Rollback journal deleted after each transaction (SQLite default)
+    Delete,
+    /// Write-ahead log; readers do not block the writer and commits append
+    /// to the log instead of rewriting pages
+    Wal,
+}
+
+impl JournalMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "delete" => Some(JournalMode::Delete),
+            "wal" => Some(JournalMode::Wal),
+            _ => None,
+        }
+    }
+}
+
 pub struct DatabaseManager {
     conn: Arc<Mutex<Connection>>,
+    /// Recently read blocks; always locked after `conn` when both are held
+    cache: Mutex<BlockCache>,
+    /// Encrypts block payloads at rest when set
+    cipher: Option<PayloadCipher>,
+    /// Public keys whose redaction records verification accepts
+    redaction_keys: Vec<String>,
+    /// Receivers of every block saved, see `subscribe_commits`
+    commit_subscribers: Mutex<Vec<UnboundedSender<Block>>>,
 }
 
 impl DatabaseManager {
@@ -41,62 +356,522 @@ impl DatabaseManager {
         let conn = Connection::open(path)?;
         Ok(DatabaseManager {
             conn: Arc::new(Mutex::new(conn)),
+            cache: Mutex::new(BlockCache::new(DEFAULT_BLOCK_CACHE_BLOCKS)),
+            cipher: None,
+            redaction_keys: Vec::new(),
+            commit_subscribers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// A private database that lives only as long as this manager
+    pub fn in_memory() -> DbResult<Self> {
+        let conn = Connection::open_in_memory()?;
+        Ok(DatabaseManager {
+            conn: Arc::new(Mutex::new(conn)),
+            cache: Mutex::new(BlockCache::new(DEFAULT_BLOCK_CACHE_BLOCKS)),
+            cipher: None,
+            redaction_keys: Vec::new(),
+            commit_subscribers: Mutex::new(Vec::new()),
         })
     }
 
+    /// Cache up to `capacity` recently read blocks (0 disables the cache)
+    pub fn with_block_cache(self, capacity: usize) -> Self {
+        *self.cache.lock().unwrap() = BlockCache::new(capacity);
+        self
+    }
+
+    /// Encrypt block payloads written from now on and decrypt stored ones
+    pub fn with_payload_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Accept redacted blocks whose record is signed with one of `keys`
+    /// (hex Ed25519 public keys); without any, no redaction is accepted
+    pub fn with_redaction_keys(mut self, keys: Vec<String>) -> Self {
+        self.redaction_keys = keys;
+        self
+    }
+
+    /// Every block saved from now on, in the order saved, including one
+    /// that replaces a block already saved at its index
+    pub fn subscribe_commits(&self) -> UnboundedReceiver<Block> {
+        let (sender, receiver) = unbounded_channel();
+        self.commit_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Hand saved blocks to the subscribers still listening
+    fn announce_commits(&self, blocks: &[Block]) {
+        let mut subscribers = self.commit_subscribers.lock().unwrap();
+        subscribers.retain(|sender| {
+            blocks
+                .iter()
+                .all(|block| sender.send(block.clone()).is_ok())
+        });
+    }
+
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
     /// Initialize the database schema with indexes for better performance
+    ///
+    /// Fresh databases get the latest schema directly; existing ones are
+    /// upgraded through `migrate` based on SQLite's `user_version` pragma.
     pub fn init(&self) -> DbResult<()> {
         let conn = self.conn.lock().unwrap();
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS blockchain (
-                id            INTEGER PRIMARY KEY AUTOINCREMENT,
-                block_index   INTEGER NOT NULL UNIQUE,
-                timestamp     INTEGER NOT NULL,
-                data_json     TEXT NOT NULL,
-                prev_hash     TEXT NOT NULL,
-                hash          TEXT NOT NULL UNIQUE,
-                nonce         INTEGER NOT NULL,
-                created_at    INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
-            )",
+        let existing: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'blockchain'",
             [],
+            |row| row.get(0),
         )?;
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_block_index ON blockchain(block_index)",
-            [],
-        )?;
+        if existing == 0 {
+            conn.execute(&blockchain_table_sql("blockchain"), [])?;
+            conn.execute(QUARANTINE_TABLE_SQL, [])?;
+            conn.execute(VERIFICATION_CHECKPOINT_TABLE_SQL, [])?;
+            conn.execute(ACCOUNTS_TABLE_SQL, [])?;
+            conn.execute(COMMIT_LATENCY_TABLE_SQL, [])?;
+            conn.execute(OUTBOX_TABLE_SQL, [])?;
+            conn.execute(ATTESTATIONS_TABLE_SQL, [])?;
+            conn.execute(ANCHORS_TABLE_SQL, [])?;
+            conn.execute(REDACTIONS_TABLE_SQL, [])?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        } else {
+            Self::migrate(&conn)?;
+        }
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_hash ON blockchain(hash)",
-            [],
-        )?;
+        conn.execute_batch(BLOCKCHAIN_INDEXES_SQL)?;
+        self.cache.lock().unwrap().clear();
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_timestamp ON blockchain(timestamp)",
-            [],
-        )?;
+        Ok(())
+    }
 
+    pub fn set_sync_policy(&self, policy: SyncPolicy) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let value = match policy {
+            SyncPolicy::Full => "FULL",
+            SyncPolicy::Normal => "NORMAL",
+        };
+        conn.pragma_update(None, "synchronous", value)?;
         Ok(())
     }
 
+    /// Switch the journal mode; returns the mode SQLite reports afterwards
+    /// (in-memory databases stay in `memory` mode)
+    pub fn set_journal_mode(&self, mode: JournalMode) -> DbResult<String> {
+        let conn = self.conn.lock().unwrap();
+        let value = match mode {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Wal => "WAL",
+        };
+        let applied = conn
+            .pragma_update_and_check(None, "journal_mode", value, |row| row.get::<_, String>(0))?;
+        Ok(applied)
+    }
+
+    /// Current schema version of the database file
+    pub fn schema_version(&self) -> DbResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(version)
+    }
+
+    fn migrate(conn: &Connection) -> DbResult<()> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        if version < 1 {
+            // v1: `created_at` moves from seconds to milliseconds. Block and
+            // MarketData timestamps are covered by the block hash, so rows
+            // written before v1 keep their second-precision values; readers
+            // upgrade them with `etl::timestamp_to_millis`.
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 INSERT INTO blockchain_v1
+                     (id, block_index, timestamp, data_json, prev_hash, hash, nonce, created_at)
+                 SELECT id, block_index, timestamp, data_json, prev_hash, hash, nonce,
+                        created_at * 1000
+                 FROM blockchain;
+                 DROP TABLE blockchain;
+                 ALTER TABLE blockchain_v1 RENAME TO blockchain;
+                 PRAGMA user_version = 1;
+                 COMMIT;",
+                blockchain_table_sql("blockchain_v1")
+            ))?;
+            info!("Database: Migrated schema to v1 (millisecond created_at)");
+        }
+
+        if version < 2 {
+            // v2: quarantine table for peer blocks rejected during sync
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 2;
+                 COMMIT;",
+                QUARANTINE_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v2 (quarantined_blocks)");
+        }
+
+        if version < 3 {
+            // v3: per-block hash format version. Existing rows were hashed
+            // with the legacy encoding (0). Tables rebuilt by the v1 step
+            // above already have the column.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'format_version'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN format_version INTEGER NOT NULL DEFAULT 0;"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 PRAGMA user_version = 3;
+                 COMMIT;",
+                add_column
+            ))?;
+            info!("Database: Migrated schema to v3 (format_version)");
+        }
+
+        if version < 4 {
+            // v4: checkpoint of the rolling verifier's progress
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 4;
+                 COMMIT;",
+                VERIFICATION_CHECKPOINT_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v4 (verification_checkpoint)");
+        }
+
+        if version < 5 {
+            // v5: fee accounting. Tables rebuilt by the v1 step above
+            // already have the fees column.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'fees_json'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN fees_json TEXT NOT NULL DEFAULT '[]';"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 {};
+                 PRAGMA user_version = 5;
+                 COMMIT;",
+                add_column, ACCOUNTS_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v5 (fee accounting)");
+        }
+
+        if version < 6 {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 6;
+                 COMMIT;",
+                COMMIT_LATENCY_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v6 (commit_latency)");
+        }
+
+        if version < 7 {
+            // v7: source divergence events. Tables rebuilt by the v1 step
+            // above already have the column.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'divergences_json'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN divergences_json TEXT NOT NULL DEFAULT '[]';"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 PRAGMA user_version = 7;
+                 COMMIT;",
+                add_column
+            ))?;
+            info!("Database: Migrated schema to v7 (divergence events)");
+        }
+
+        if version < 8 {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 8;
+                 COMMIT;",
+                OUTBOX_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v8 (outbox)");
+        }
+
+        if version < 9 {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 9;
+                 COMMIT;",
+                ATTESTATIONS_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v9 (attestations)");
+        }
+
+        if version < 10 {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 10;
+                 COMMIT;",
+                ANCHORS_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v10 (anchors)");
+        }
+
+        if version < 11 {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {};
+                 PRAGMA user_version = 11;
+                 COMMIT;",
+                REDACTIONS_TABLE_SQL
+            ))?;
+            info!("Database: Migrated schema to v11 (redactions)");
+        }
+
+        if version < 12 {
+            // v12: hybrid logical timestamps. Tables rebuilt by the v1 step
+            // above already have the column; existing blocks have none.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'hlc_json'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN hlc_json TEXT;"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 PRAGMA user_version = 12;
+                 COMMIT;",
+                add_column
+            ))?;
+            info!("Database: Migrated schema to v12 (hybrid logical timestamps)");
+        }
+
+        if version < 13 {
+            // v13: proposer annotations. Tables rebuilt by the v1 step above
+            // already have the column.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'annotations_json'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN annotations_json TEXT NOT NULL DEFAULT '{}';"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 PRAGMA user_version = 13;
+                 COMMIT;",
+                add_column
+            ))?;
+            info!("Database: Migrated schema to v13 (block annotations)");
+        }
+
+        if version < 14 {
+            // v14: order book snapshots. Tables rebuilt by the v1 step above
+            // already have the column.
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('blockchain') WHERE name = 'order_books_json'",
+                [],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            let add_column = if has_column {
+                ""
+            } else {
+                "ALTER TABLE blockchain ADD COLUMN order_books_json TEXT NOT NULL DEFAULT '[]';"
+            };
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 {}
+                 PRAGMA user_version = 14;
+                 COMMIT;",
+                add_column
+            ))?;
+            info!("Database: Migrated schema to v14 (order book snapshots)");
+        }
+
+        Ok(())
+    }
+
+    /// Column value for `payload`, part of `block` (its entries, fees,
+    /// divergence events or order books) or the whole of a quarantined one,
+    /// encrypted when a cipher is set; the block hash is the associated data
+    fn encode_payload<T: Serialize + ?Sized>(
+        &self,
+        block: &Block,
+        payload: &T,
+    ) -> DbResult<String> {
+        let json = serde_json::to_string(payload)
+            .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        match &self.cipher {
+            Some(cipher) => cipher
+                .encrypt(&json, block.hash.as_bytes())
+                .map_err(DatabaseError::Serialization),
+            None => Ok(json),
+        }
+    }
+
+    /// `payload` column value of an outbox message to `peer`, encrypted
+    /// when a cipher is set; the peer is the associated data
+    fn encode_outbox_payload(&self, peer: &str, payload: &str) -> DbResult<String> {
+        match &self.cipher {
+            Some(cipher) => cipher
+                .encrypt(payload, peer.as_bytes())
+                .map_err(DatabaseError::Serialization),
+            None => Ok(payload.to_string()),
+        }
+    }
+
+    /// Rewrite every block, quarantined block and pending outbox message
+    /// with a payload column not stored under the active key (plaintext
+    /// values and values under retired keys); returns how many rows
+    pub fn reencrypt_payloads(&self) -> DbResult<usize> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(0);
+        };
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut rewritten = 0;
+        for (table, columns, aad_column, filter) in [
+            (
+                "blockchain",
+                &[
+                    "data_json",
+                    "fees_json",
+                    "divergences_json",
+                    "order_books_json",
+                ][..],
+                "hash",
+                "",
+            ),
+            ("quarantined_blocks", &["block_json"][..], "hash", ""),
+            (
+                "outbox",
+                &["payload"][..],
+                "peer",
+                " WHERE delivered_at IS NULL",
+            ),
+        ] {
+            let rows: Vec<(i64, String, Vec<String>)> = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT id, {}, {} FROM {}{}",
+                    aad_column,
+                    columns.join(", "),
+                    table,
+                    filter
+                ))?;
+                let rows = stmt.query_map([], |row| {
+                    let values = (0..columns.len())
+                        .map(|i| row.get(i + 2))
+                        .collect::<Result<_, _>>()?;
+                    Ok((row.get(0)?, row.get(1)?, values))
+                })?;
+                rows.collect::<Result<_, _>>()?
+            };
+            for (id, aad, values) in rows {
+                let mut row_rewritten = false;
+                for (column, stored) in columns.iter().zip(values) {
+                    if PayloadCipher::key_id(&stored) == Some(cipher.active_key_id()) {
+                        continue;
+                    }
+                    let plaintext = if PayloadCipher::is_encrypted(&stored) {
+                        cipher
+                            .decrypt(&stored, aad.as_bytes())
+                            .map_err(DatabaseError::InvalidData)?
+                    } else {
+                        stored
+                    };
+                    let encrypted = cipher
+                        .encrypt(&plaintext, aad.as_bytes())
+                        .map_err(DatabaseError::Serialization)?;
+                    tx.execute(
+                        &format!("UPDATE {} SET {} = ?2 WHERE id = ?1", table, column),
+                        params![id, encrypted],
+                    )?;
+                    row_rewritten = true;
+                }
+                rewritten += usize::from(row_rewritten);
+            }
+        }
+        tx.commit()?;
+        if rewritten > 0 {
+            info!(
+                rows = rewritten,
+                key_id = cipher.active_key_id(),
+                "Database: Re-encrypted block payloads"
+            );
+        }
+        Ok(rewritten)
+    }
+
     pub fn save_block(&self, block: &Block) -> DbResult<()> {
         let conn = self.conn.lock().unwrap();
-        let data_json = serde_json::to_string(&block.data)
+        let data_json = self.encode_payload(block, &block.data)?;
+        let fees_json = self.encode_payload(block, &block.fees)?;
+        let divergences_json = self.encode_payload(block, &block.divergences)?;
+        let hlc_json = encode_hlc(block)?;
+        let annotations_json = serde_json::to_string(&block.annotations)
             .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        let order_books_json = self.encode_payload(block, &block.order_books)?;
 
         conn.execute(
-            "INSERT INTO blockchain (block_index, timestamp, data_json, prev_hash, hash, nonce)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO blockchain
+                 (block_index, timestamp, data_json, prev_hash, hash, nonce, format_version,
+                  fees_json, divergences_json, hlc_json, annotations_json, order_books_json)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
             params![
                 block.index,
                 block.timestamp,
                 data_json,
                 block.previous_hash,
                 block.hash,
-                block.nonce
+                block.nonce,
+                block.format_version,
+                fees_json,
+                divergences_json,
+                hlc_json,
+                annotations_json,
+                order_books_json
             ],
         )?;
+        // A block saved below the tip replaces the chain from there on
+        self.cache.lock().unwrap().invalidate(block.index..);
+        drop(conn);
+        self.announce_commits(std::slice::from_ref(block));
 
         info!(block_index = block.index, "Database: Block saved to SQLite");
         Ok(())
@@ -109,65 +884,64 @@ impl DatabaseManager {
 
         let mut count = 0;
         for block in blocks {
-            let data_json = serde_json::to_string(&block.data)
+            let data_json = self.encode_payload(block, &block.data)?;
+            let fees_json = self.encode_payload(block, &block.fees)?;
+            let divergences_json = self.encode_payload(block, &block.divergences)?;
+            let hlc_json = encode_hlc(block)?;
+            let annotations_json = serde_json::to_string(&block.annotations)
                 .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+            let order_books_json = self.encode_payload(block, &block.order_books)?;
 
             tx.execute(
-                "INSERT INTO blockchain (block_index, timestamp, data_json, prev_hash, hash, nonce)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                "INSERT INTO blockchain
+                     (block_index, timestamp, data_json, prev_hash, hash, nonce, format_version,
+                      fees_json, divergences_json, hlc_json, annotations_json, order_books_json)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
                 params![
                     block.index,
                     block.timestamp,
                     data_json,
                     block.previous_hash,
                     block.hash,
-                    block.nonce
+                    block.nonce,
+                    block.format_version,
+                    fees_json,
+                    divergences_json,
+                    hlc_json,
+                    annotations_json,
+                    order_books_json
                 ],
             )?;
             count += 1;
         }
 
         tx.commit()?;
+        if let Some(lowest) = blocks.iter().map(|block| block.index).min() {
+            self.cache.lock().unwrap().invalidate(lowest..);
+        }
+        drop(conn);
+        self.announce_commits(blocks);
         info!(block_count = count, "Database: Saved blocks in batch");
         Ok(count)
     }
 
     pub fn get_block_by_index(&self, index: u64) -> DbResult<Block> {
+        if let Some(block) = self.cache.lock().unwrap().get_by_index(index) {
+            return Ok(block);
+        }
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT block_index, timestamp, data_json, prev_hash, hash, nonce 
-             FROM blockchain WHERE block_index = ?",
-        )?;
-
-        let block_result = stmt.query_row([index], |row| {
-            let idx: u64 = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let data_json: String = row.get(2)?;
-            let prev_hash: String = row.get(3)?;
-            let hash: String = row.get(4)?;
-            let nonce: u64 = row.get(5)?;
-
-            let data: Vec<crate::etl::MarketData> =
-                serde_json::from_str(&data_json).map_err(|_e| {
-                    rusqlite::Error::InvalidColumnType(
-                        2,
-                        "data_json".to_string(),
-                        rusqlite::types::Type::Text,
-                    )
-                })?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain WHERE block_index = ?",
+            BLOCK_COLUMNS
+        ))?;
 
-            Ok(Block {
-                index: idx,
-                timestamp,
-                data,
-                previous_hash: prev_hash,
-                hash,
-                nonce,
-            })
-        });
+        let block_result = stmt.query_row([index], |row| row_to_block(row, self.cipher.as_ref()));
 
         match block_result {
-            Ok(block) => Ok(block),
+            Ok(block) => {
+                self.cache.lock().unwrap().insert(&block);
+                Ok(block)
+            }
             Err(rusqlite::Error::QueryReturnedNoRows) => Err(DatabaseError::NotFound(format!(
                 "Block with index {} not found",
                 index
@@ -177,41 +951,22 @@ impl DatabaseManager {
     }
 
     pub fn get_block_by_hash(&self, hash: &str) -> DbResult<Block> {
+        if let Some(block) = self.cache.lock().unwrap().get_by_hash(hash) {
+            return Ok(block);
+        }
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT block_index, timestamp, data_json, prev_hash, hash, nonce 
-             FROM blockchain WHERE hash = ?",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain WHERE hash = ?",
+            BLOCK_COLUMNS
+        ))?;
 
-        let block_result = stmt.query_row([hash], |row| {
-            let idx: u64 = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let data_json: String = row.get(2)?;
-            let prev_hash: String = row.get(3)?;
-            let hash: String = row.get(4)?;
-            let nonce: u64 = row.get(5)?;
-
-            let data: Vec<crate::etl::MarketData> =
-                serde_json::from_str(&data_json).map_err(|_e| {
-                    rusqlite::Error::InvalidColumnType(
-                        2,
-                        "data_json".to_string(),
-                        rusqlite::types::Type::Text,
-                    )
-                })?;
-
-            Ok(Block {
-                index: idx,
-                timestamp,
-                data,
-                previous_hash: prev_hash,
-                hash,
-                nonce,
-            })
-        });
+        let block_result = stmt.query_row([hash], |row| row_to_block(row, self.cipher.as_ref()));
 
         match block_result {
-            Ok(block) => Ok(block),
+            Ok(block) => {
+                self.cache.lock().unwrap().insert(&block);
+                Ok(block)
+            }
             Err(rusqlite::Error::QueryReturnedNoRows) => Err(DatabaseError::NotFound(format!(
                 "Block with hash {} not found",
                 hash
@@ -221,41 +976,22 @@ impl DatabaseManager {
     }
 
     pub fn get_latest_block(&self) -> DbResult<Option<Block>> {
+        if let Some(block) = self.cache.lock().unwrap().get_latest() {
+            return Ok(Some(block));
+        }
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT block_index, timestamp, data_json, prev_hash, hash, nonce 
-             FROM blockchain ORDER BY block_index DESC LIMIT 1",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain ORDER BY block_index DESC LIMIT 1",
+            BLOCK_COLUMNS
+        ))?;
 
-        let block_result = stmt.query_row([], |row| {
-            let idx: u64 = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let data_json: String = row.get(2)?;
-            let prev_hash: String = row.get(3)?;
-            let hash: String = row.get(4)?;
-            let nonce: u64 = row.get(5)?;
-
-            let data: Vec<crate::etl::MarketData> =
-                serde_json::from_str(&data_json).map_err(|_e| {
-                    rusqlite::Error::InvalidColumnType(
-                        2,
-                        "data_json".to_string(),
-                        rusqlite::types::Type::Text,
-                    )
-                })?;
-
-            Ok(Block {
-                index: idx,
-                timestamp,
-                data,
-                previous_hash: prev_hash,
-                hash,
-                nonce,
-            })
-        });
+        let block_result = stmt.query_row([], |row| row_to_block(row, self.cipher.as_ref()));
 
         match block_result {
-            Ok(block) => Ok(Some(block)),
+            Ok(block) => {
+                self.cache.lock().unwrap().insert_latest(&block);
+                Ok(Some(block))
+            }
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(DatabaseError::Sqlite(e)),
         }
@@ -264,44 +1000,32 @@ impl DatabaseManager {
     /// Query latest blocks and return them (instead of just printing)
     pub fn query_latest_blocks(&self, limit: u64) -> DbResult<Vec<Block>> {
         let limit_i64 = limit.min(i64::MAX as u64) as i64;
+        if let Some(blocks) = self.cache.lock().unwrap().get_latest_n(limit) {
+            return Ok(blocks);
+        }
 
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT block_index, timestamp, data_json, prev_hash, hash, nonce 
-             FROM blockchain ORDER BY block_index DESC LIMIT ?",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain ORDER BY block_index DESC LIMIT ?",
+            BLOCK_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([limit_i64], |row| {
-            let idx: u64 = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let data_json: String = row.get(2)?;
-            let prev_hash: String = row.get(3)?;
-            let hash: String = row.get(4)?;
-            let nonce: u64 = row.get(5)?;
-
-            let data: Vec<crate::etl::MarketData> =
-                serde_json::from_str(&data_json).map_err(|_e| {
-                    rusqlite::Error::InvalidColumnType(
-                        2,
-                        "data_json".to_string(),
-                        rusqlite::types::Type::Text,
-                    )
-                })?;
-
-            Ok(Block {
-                index: idx,
-                timestamp,
-                data,
-                previous_hash: prev_hash,
-                hash,
-                nonce,
-            })
-        })?;
+        let rows = stmt.query_map([limit_i64], |row| row_to_block(row, self.cipher.as_ref()))?;
 
         let mut blocks = Vec::new();
         for row in rows {
             blocks.push(row?);
         }
+        let mut cache = self.cache.lock().unwrap();
+        if blocks.len() <= cache.stats().capacity {
+            // Oldest first, so the tip ends up most recently used
+            for block in blocks.iter().skip(1).rev() {
+                cache.insert(block);
+            }
+            if let Some(tip) = blocks.first() {
+                cache.insert_latest(tip);
+            }
+        }
         Ok(blocks)
     }
 
@@ -334,38 +1058,113 @@ impl DatabaseManager {
         let end_i64 = end_index as i64;
 
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT block_index, timestamp, data_json, prev_hash, hash, nonce 
-             FROM blockchain WHERE block_index >= ? AND block_index <= ? 
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain WHERE block_index >= ? AND block_index <= ?
              ORDER BY block_index ASC",
-        )?;
+            BLOCK_COLUMNS
+        ))?;
 
         let rows = stmt.query_map(params![start_i64, end_i64], |row| {
-            let idx: u64 = row.get(0)?;
-            let timestamp: i64 = row.get(1)?;
-            let data_json: String = row.get(2)?;
-            let prev_hash: String = row.get(3)?;
-            let hash: String = row.get(4)?;
-            let nonce: u64 = row.get(5)?;
-
-            let data: Vec<crate::etl::MarketData> =
-                serde_json::from_str(&data_json).map_err(|_e| {
-                    rusqlite::Error::InvalidColumnType(
-                        2,
-                        "data_json".to_string(),
-                        rusqlite::types::Type::Text,
-                    )
-                })?;
+            row_to_block(row, self.cipher.as_ref())
+        })?;
 
-            Ok(Block {
-                index: idx,
-                timestamp,
-                data,
-                previous_hash: prev_hash,
-                hash,
-                nonce,
+        let mut blocks = Vec::new();
+        for row in rows {
+            blocks.push(row?);
+        }
+        Ok(blocks)
+    }
+
+    /// Search blocks by asset, source and timestamp range, ordered by index
+    ///
+    /// SQLite cannot look inside encrypted payloads, so with a cipher set the
+    /// asset and source filters cannot use the index: blocks matching the
+    /// other filters are read `BLOCK_PAGE_SIZE` at a time, decrypted and
+    /// filtered, until `limit` blocks match or the ledger is exhausted.
+    pub fn search_blocks(&self, query: &BlockQuery) -> DbResult<Vec<Block>> {
+        let limit = query.limit.unwrap_or(u64::MAX);
+        if self.cipher.is_none() || (query.asset.is_none() && query.source.is_none()) {
+            return self.search_page(query, None, limit);
+        }
+        let has = |block: &Block| {
+            block.data.iter().any(|entry| {
+                query
+                    .asset
+                    .as_ref()
+                    .is_none_or(|asset| &entry.asset == asset)
+            }) && block.data.iter().any(|entry| {
+                query
+                    .source
+                    .as_ref()
+                    .is_none_or(|source| &entry.source == source)
             })
-        })?;
+        };
+        let unfiltered = BlockQuery {
+            asset: None,
+            source: None,
+            ..query.clone()
+        };
+        let mut blocks = Vec::new();
+        let mut after = None;
+        while (blocks.len() as u64) < limit {
+            let page = self.search_page(&unfiltered, after, BLOCK_PAGE_SIZE as u64)?;
+            let exhausted = page.len() < BLOCK_PAGE_SIZE;
+            after = page.last().map(|block| block.index);
+            blocks.extend(
+                page.into_iter()
+                    .filter(has)
+                    .take((limit - blocks.len() as u64).min(usize::MAX as u64) as usize),
+            );
+            if exhausted {
+                break;
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Up to `limit` blocks matching `query` with an index above `after`
+    fn search_page(
+        &self,
+        query: &BlockQuery,
+        after: Option<u64>,
+        limit: u64,
+    ) -> DbResult<Vec<Block>> {
+        let limit_i64 = limit.min(i64::MAX as u64) as i64;
+        let after_i64 = after.map(|index| index.min(i64::MAX as u64) as i64);
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {0} FROM blockchain
+             WHERE (?1 IS NULL OR EXISTS (
+                       SELECT 1 FROM json_each(data_json)
+                       WHERE json_extract(value, '$.asset') = ?1))
+               AND (?2 IS NULL OR EXISTS (
+                       SELECT 1 FROM json_each(data_json)
+                       WHERE json_extract(value, '$.source') = ?2))
+               AND (?3 IS NULL OR {1} >= ?3)
+               AND (?4 IS NULL OR {1} <= ?4)
+               AND (?5 IS NULL OR EXISTS (
+                       SELECT 1 FROM json_each(annotations_json)
+                       WHERE key = ?5 AND value = ?6))
+               AND (?8 IS NULL OR block_index > ?8)
+             ORDER BY block_index ASC LIMIT ?7",
+            BLOCK_COLUMNS,
+            timestamp_millis_sql()
+        ))?;
+
+        let rows = stmt.query_map(
+            params![
+                query.asset,
+                query.source,
+                query.from_timestamp,
+                query.to_timestamp,
+                query.annotation.as_ref().map(|(key, _)| key),
+                query.annotation.as_ref().map(|(_, value)| value),
+                limit_i64,
+                after_i64
+            ],
+            |row| row_to_block(row, self.cipher.as_ref()),
+        )?;
 
         let mut blocks = Vec::new();
         for row in rows {
@@ -374,33 +1173,639 @@ impl DatabaseManager {
         Ok(blocks)
     }
 
+    /// Newest block with at least one entry for `asset`
+    pub fn get_latest_block_for_asset(&self, asset: &str) -> DbResult<Option<Block>> {
+        if self.cipher.is_some() {
+            // Scan down from the tip a page at a time
+            let mut end = match self.get_latest_block()? {
+                Some(tip) => tip.index,
+                None => return Ok(None),
+            };
+            loop {
+                let start = end.saturating_sub(BLOCK_PAGE_SIZE as u64 - 1);
+                let page = self.blocks_page(start, end, BLOCK_PAGE_SIZE)?;
+                if let Some(block) = page
+                    .into_iter()
+                    .rev()
+                    .find(|block| block.data.iter().any(|entry| entry.asset == asset))
+                {
+                    return Ok(Some(block));
+                }
+                if start == 0 {
+                    return Ok(None);
+                }
+                end = start - 1;
+            }
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain
+             WHERE EXISTS (
+                 SELECT 1 FROM json_each(data_json)
+                 WHERE json_extract(value, '$.asset') = ?1)
+             ORDER BY block_index DESC LIMIT 1",
+            BLOCK_COLUMNS
+        ))?;
+        match stmt.query_row([asset], |row| row_to_block(row, self.cipher.as_ref())) {
+            Ok(block) => Ok(Some(block)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Walk `previous_hash` links backwards from `block`, returning up to
+    /// `depth` ancestors (nearest first). Stops early at genesis.
+    pub fn get_ancestors(&self, block: &Block, depth: usize) -> DbResult<Vec<Block>> {
+        let mut ancestors = Vec::new();
+        let mut prev_hash = block.previous_hash.clone();
+
+        while ancestors.len() < depth {
+            match self.get_block_by_hash(&prev_hash) {
+                Ok(parent) => {
+                    prev_hash = parent.previous_hash.clone();
+                    ancestors.push(parent);
+                }
+                Err(DatabaseError::NotFound(_)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(ancestors)
+    }
+
     /// Verify blockchain integrity by checking hash chain
+    ///
+    /// Streams the chain, so memory use does not grow with its length.
+    ///
+    /// A redacted block cannot be re-hashed; it passes when its stored hash
+    /// is the one its redaction record covers.
     pub fn verify_chain(&self) -> DbResult<bool> {
-        let limit = i64::MAX as u64;
-        let blocks = self.query_latest_blocks(limit)?;
+        let redacted = self.get_redacted_hashes()?;
+        let mut previous: Option<Block> = None;
+        for block in self.iter_blocks(..) {
+            let block = block?;
+            if let Some(prev_block) = &previous {
+                if block.previous_hash != prev_block.hash {
+                    return Ok(false);
+                }
+                let tombstone =
+                    redacted.get(&block.index) == Some(&block.hash) && block.has_empty_payload();
+                if !tombstone && block.calculate_hash() != block.hash {
+                    return Ok(false);
+                }
+            }
+            previous = Some(block);
+        }
+        Ok(true)
+    }
+
+    /// Blocks whose index falls in `range`, in index order, read from the
+    /// database one page at a time
+    ///
+    /// Only the current page is held in memory and the connection is locked
+    /// only while a page is read, so writers are not blocked for the whole
+    /// iteration. Blocks written behind the cursor are not seen.
+    pub fn iter_blocks(&self, range: impl RangeBounds<u64>) -> BlockIter<'_> {
+        BlockIter::new(self, &range)
+    }
+
+    /// `iter_blocks` as an async stream, for use on a Tokio runtime
+    ///
+    /// Blocks are read on a blocking thread and handed over through a
+    /// bounded channel, so at most a page is buffered ahead of the consumer.
+    pub fn stream_blocks(self: &Arc<Self>, range: impl RangeBounds<u64>) -> BlockStream {
+        let (start, end) = index_bounds(&range);
+        let (sender, receiver) = tokio::sync::mpsc::channel(BLOCK_PAGE_SIZE);
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for block in db.iter_blocks(start..=end) {
+                let failed = block.is_err();
+                // The consumer dropped the stream
+                if sender.blocking_send(block).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        BlockStream { receiver }
+    }
+
+    /// Write the blocks in `range` to `out` as JSON lines, oldest first;
+    /// returns how many were written
+    pub fn export_chain(
+        &self,
+        range: impl RangeBounds<u64>,
+        mut out: impl Write,
+    ) -> DbResult<usize> {
+        let io_error = |e: std::io::Error| DatabaseError::InvalidData(format!("export: {}", e));
+        let mut count = 0;
+        for block in self.iter_blocks(range) {
+            serde_json::to_writer(&mut out, &block?)
+                .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+            out.write_all(b"\n").map_err(io_error)?;
+            count += 1;
+        }
+        out.flush().map_err(io_error)?;
+        Ok(count)
+    }
+
+    /// Up to `limit` blocks with an index in `start..=end`, oldest first
+    fn blocks_page(&self, start: u64, end: u64, limit: usize) -> DbResult<Vec<Block>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM blockchain WHERE block_index >= ?1 AND block_index <= ?2
+             ORDER BY block_index ASC LIMIT ?3",
+            BLOCK_COLUMNS
+        ))?;
+        let rows = stmt.query_map(
+            params![
+                start.min(i64::MAX as u64) as i64,
+                end.min(i64::MAX as u64) as i64,
+                limit as i64
+            ],
+            |row| row_to_block(row, self.cipher.as_ref()),
+        )?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Record a peer block that failed verification instead of appending it
+    pub fn quarantine_block(&self, block: &Block, peer: &str, reason: &str) -> DbResult<()> {
+        let block_json = self.encode_payload(block, block)?;
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO quarantined_blocks (block_index, hash, block_json, peer, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![block.index, block.hash, block_json, peer, reason],
+        )?;
+
+        info!(
+            block_index = block.index,
+            peer = %peer,
+            reason = %reason,
+            "Database: Block quarantined"
+        );
+        Ok(())
+    }
+
+    /// Most recently quarantined blocks, newest first
+    pub fn get_quarantined_blocks(&self, limit: u64) -> DbResult<Vec<QuarantinedBlock>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT block_json, peer, reason, quarantined_at, block_index, hash
+             FROM quarantined_blocks ORDER BY id DESC LIMIT ?",
+        )?;
+
+        let rows = stmt.query_map([limit], |row| {
+            let hash: String = row.get(5)?;
+            let block_json =
+                decrypt_column(row.get(0)?, 0, row.get(4)?, &hash, self.cipher.as_ref())?;
+            Ok((block_json, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
 
-        if blocks.is_empty() {
-            return Ok(true);
+        let mut quarantined = Vec::new();
+        for row in rows {
+            let (block_json, peer, reason, quarantined_at) = row?;
+            let block = serde_json::from_str(&block_json)
+                .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+            quarantined.push(QuarantinedBlock {
+                block,
+                peer,
+                reason,
+                quarantined_at,
+            });
         }
 
-        let mut sorted_blocks = blocks;
-        sorted_blocks.sort_by_key(|b| b.index);
+        Ok(quarantined)
+    }
+
+    /// Record the tip the rolling verifier last confirmed
+    pub fn save_verification_checkpoint(
+        &self,
+        checkpoint: &VerificationCheckpoint,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO verification_checkpoint (id, block_index, hash, verified_at)
+             VALUES (1, ?1, ?2, ?3)",
+            params![
+                checkpoint.block_index,
+                checkpoint.hash,
+                checkpoint.verified_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_verification_checkpoint(&self) -> DbResult<Option<VerificationCheckpoint>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT block_index, hash, verified_at FROM verification_checkpoint WHERE id = 1",
+        )?;
+        let mut rows = stmt.query_map([], |row| {
+            Ok(VerificationCheckpoint {
+                block_index: row.get(0)?,
+                hash: row.get(1)?,
+                verified_at: row.get(2)?,
+            })
+        })?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Create or update a submitter's account
+    pub fn save_account(&self, account: &Account) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO accounts (submitter, balance, entries, fees_paid)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                account.submitter,
+                account.balance,
+                account.entries,
+                account.fees_paid
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Every submitter account, ordered by submitter
+    pub fn load_accounts(&self) -> DbResult<Vec<Account>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT submitter, balance, entries, fees_paid FROM accounts ORDER BY submitter",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Account {
+                submitter: row.get(0)?,
+                balance: row.get(1)?,
+                entries: row.get(2)?,
+                fees_paid: row.get(3)?,
+            })
+        })?;
+        let mut accounts = Vec::new();
+        for row in rows {
+            accounts.push(row?);
+        }
+        Ok(accounts)
+    }
+
+    /// Record how long a block's data took to be committed
+    pub fn save_commit_latency(&self, latency: &CommitLatency) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO commit_latency (block_index, observed_at, committed_at)
+             VALUES (?1, ?2, ?3)",
+            params![
+                latency.block_index,
+                latency.observed_at,
+                latency.committed_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Queue `(peer, payload)` messages in one transaction; returns the row
+    /// ids in the order of `messages`
+    pub fn enqueue_outbox(
+        &self,
+        messages: &[(&str, &str)],
+        trace_id: Option<&str>,
+    ) -> DbResult<Vec<i64>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut ids = Vec::with_capacity(messages.len());
+        for (peer, payload) in messages {
+            let payload = self.encode_outbox_payload(peer, payload)?;
+            tx.execute(
+                "INSERT INTO outbox (peer, payload, trace_id) VALUES (?1, ?2, ?3)",
+                params![peer, payload, trace_id],
+            )?;
+            ids.push(tx.last_insert_rowid());
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
 
-        for i in 1..sorted_blocks.len() {
-            let prev_block = &sorted_blocks[i - 1];
-            let curr_block = &sorted_blocks[i];
+    /// Record a send attempt; `delivered` marks the peer's acknowledgement
+    pub fn record_outbox_attempt(&self, id: i64, delivered: bool) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE outbox SET attempts = attempts + 1,
+                 delivered_at = CASE WHEN ?2 THEN ?3 ELSE delivered_at END
+             WHERE id = ?1",
+            params![id, delivered, crate::etl::now_millis()],
+        )?;
+        Ok(())
+    }
 
-            if curr_block.previous_hash != prev_block.hash {
-                return Ok(false);
+    /// Messages not yet acknowledged, oldest first
+    pub fn get_pending_outbox(&self) -> DbResult<Vec<OutboxEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, peer, payload, trace_id, attempts, created_at FROM outbox
+             WHERE delivered_at IS NULL ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let id: i64 = row.get(0)?;
+            let peer: String = row.get(1)?;
+            let mut payload: String = row.get(2)?;
+            if PayloadCipher::is_encrypted(&payload) {
+                let cipher = self
+                    .cipher
+                    .as_ref()
+                    .ok_or_else(|| "no payload key is configured".to_string());
+                payload = cipher
+                    .and_then(|cipher| cipher.decrypt(&payload, peer.as_bytes()))
+                    .map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            2,
+                            rusqlite::types::Type::Text,
+                            format!("outbox message {}: {}", id, e).into(),
+                        )
+                    })?;
             }
+            Ok(OutboxEntry {
+                id,
+                peer,
+                payload,
+                trace_id: row.get(3)?,
+                attempts: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Delete delivered messages and ones that ran out of attempts; returns
+    /// how many rows were removed
+    pub fn prune_outbox(&self, max_attempts: u32) -> DbResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM outbox WHERE delivered_at IS NOT NULL OR attempts >= ?1",
+            [max_attempts],
+        )?;
+        Ok(removed)
+    }
+
+    /// Store a signed attestation document; returns its id
+    pub fn save_attestation(&self, attestation: &StoredAttestation) -> DbResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO attestations (height, head_hash, attested_at, document_json, notarized_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                attestation.height,
+                attestation.head_hash,
+                attestation.attested_at,
+                attestation.document_json,
+                attestation.notarized_at
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn mark_attestation_notarized(&self, id: i64, notarized_at: i64) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE attestations SET notarized_at = ?2 WHERE id = ?1",
+            params![id, notarized_at],
+        )?;
+        Ok(())
+    }
+
+    /// The `limit` most recent attestations, newest first
+    pub fn get_attestations(&self, limit: u64) -> DbResult<Vec<StoredAttestation>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, height, head_hash, attested_at, document_json, notarized_at
+             FROM attestations ORDER BY id DESC LIMIT ?",
+        )?;
+        let rows = stmt.query_map([limit.min(i64::MAX as u64) as i64], |row| {
+            Ok(StoredAttestation {
+                id: row.get(0)?,
+                height: row.get(1)?,
+                head_hash: row.get(2)?,
+                attested_at: row.get(3)?,
+                document_json: row.get(4)?,
+                notarized_at: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Store an anchor submission; returns its id
+    pub fn save_anchor(&self, anchor: &StoredAnchor) -> DbResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO anchors
+                 (height, head_hash, calendar, proof, submitted_at, bitcoin_height, confirmed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                anchor.height,
+                anchor.head_hash,
+                anchor.calendar,
+                anchor.proof,
+                anchor.submitted_at,
+                anchor.bitcoin_height,
+                anchor.confirmed_at
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
 
-            let calculated_hash = curr_block.calculate_hash();
-            if calculated_hash != curr_block.hash {
-                return Ok(false);
+    /// Replace an anchor's proof with its upgraded form
+    pub fn update_anchor_proof(
+        &self,
+        id: i64,
+        proof: &[u8],
+        bitcoin_height: Option<u64>,
+        confirmed_at: Option<i64>,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE anchors SET proof = ?2, bitcoin_height = ?3, confirmed_at = ?4 WHERE id = ?1",
+            params![id, proof, bitcoin_height, confirmed_at],
+        )?;
+        Ok(())
+    }
+
+    fn query_anchors(&self, filter: &str, limit: u64) -> DbResult<Vec<StoredAnchor>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, height, head_hash, calendar, proof, submitted_at, bitcoin_height,
+                    confirmed_at
+             FROM anchors {} ORDER BY id DESC LIMIT ?",
+            filter
+        ))?;
+        let rows = stmt.query_map([limit.min(i64::MAX as u64) as i64], |row| {
+            Ok(StoredAnchor {
+                id: row.get(0)?,
+                height: row.get(1)?,
+                head_hash: row.get(2)?,
+                calendar: row.get(3)?,
+                proof: row.get(4)?,
+                submitted_at: row.get(5)?,
+                bitcoin_height: row.get(6)?,
+                confirmed_at: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// The `limit` most recent anchors, newest first
+    pub fn get_anchors(&self, limit: u64) -> DbResult<Vec<StoredAnchor>> {
+        self.query_anchors("", limit)
+    }
+
+    /// Anchors whose proof is not yet confirmed on Bitcoin, newest first
+    pub fn get_unconfirmed_anchors(&self) -> DbResult<Vec<StoredAnchor>> {
+        self.query_anchors("WHERE bitcoin_height IS NULL", u64::MAX)
+    }
+
+    pub fn get_anchor(&self, id: i64) -> DbResult<StoredAnchor> {
+        self.query_anchors(&format!("WHERE id = {}", id), 1)?
+            .pop()
+            .ok_or_else(|| DatabaseError::NotFound(format!("anchor {}", id)))
+    }
+
+    /// Remove a block's payload (entries, fees and divergence events), and
+    /// the outbox messages carrying it, and store the signed record of it;
+    /// the block keeps its hash and links
+    pub fn redact_block(&self, redaction: &StoredRedaction) -> DbResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE blockchain SET data_json = '[]', fees_json = '[]', divergences_json = '[]',
+                 order_books_json = '[]'
+             WHERE block_index = ?1 AND hash = ?2",
+            params![redaction.block_index, redaction.block_hash],
+        )?;
+        if updated == 0 {
+            return Err(DatabaseError::NotFound(format!(
+                "Block {} with hash {} not found",
+                redaction.block_index, redaction.block_hash
+            )));
+        }
+        // Queued consensus messages for the block carry its payload too
+        let queued: Vec<(i64, String, String)> = {
+            let mut stmt = tx.prepare("SELECT id, peer, payload FROM outbox")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let mut purged = 0;
+        for (id, peer, payload) in queued {
+            let payload = match (&self.cipher, PayloadCipher::is_encrypted(&payload)) {
+                (Some(cipher), true) => cipher
+                    .decrypt(&payload, peer.as_bytes())
+                    .map_err(DatabaseError::InvalidData)?,
+                _ => payload,
+            };
+            if payload.contains(&redaction.block_hash) {
+                purged += tx.execute("DELETE FROM outbox WHERE id = ?1", [id])?;
             }
         }
+        tx.execute(
+            "INSERT OR REPLACE INTO redactions
+                 (block_index, block_hash, reason, redacted_by, redacted_at, public_key, signature)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                redaction.block_index,
+                redaction.block_hash,
+                redaction.reason,
+                redaction.redacted_by,
+                redaction.redacted_at,
+                redaction.public_key,
+                redaction.signature
+            ],
+        )?;
+        tx.commit()?;
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(redaction.block_index..=redaction.block_index);
+        info!(
+            block_index = redaction.block_index,
+            outbox_purged = purged,
+            "Database: Block payload redacted"
+        );
+        Ok(())
+    }
+
+    /// Every redaction record, by block index
+    pub fn get_redactions(&self) -> DbResult<Vec<StoredRedaction>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT block_index, block_hash, reason, redacted_by, redacted_at, public_key,
+                    signature
+             FROM redactions ORDER BY block_index",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(StoredRedaction {
+                block_index: row.get(0)?,
+                block_hash: row.get(1)?,
+                reason: row.get(2)?,
+                redacted_by: row.get(3)?,
+                redacted_at: row.get(4)?,
+                public_key: row.get(5)?,
+                signature: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Block index -> hash covered by its redaction record, for records
+    /// signed with one of the trusted redaction keys
+    pub fn get_redacted_hashes(&self) -> DbResult<HashMap<u64, String>> {
+        if self.redaction_keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+        Ok(self
+            .get_redactions()?
+            .into_iter()
+            .map(SignedRedaction::from)
+            .filter(|signed| {
+                self.redaction_keys
+                    .iter()
+                    .any(|key| redaction::verify(signed, key).is_ok())
+            })
+            .map(|signed| (signed.redaction.block_index, signed.redaction.block_hash))
+            .collect())
+    }
+
+    /// Commit latencies of the `limit` most recent blocks, newest first
+    pub fn get_commit_latencies(&self, limit: u64) -> DbResult<Vec<CommitLatency>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT block_index, observed_at, committed_at FROM commit_latency
+             ORDER BY block_index DESC LIMIT ?",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(CommitLatency {
+                block_index: row.get(0)?,
+                observed_at: row.get(1)?,
+                committed_at: row.get(2)?,
+            })
+        })?;
+        let mut latencies = Vec::new();
+        for row in rows {
+            latencies.push(row?);
+        }
+        Ok(latencies)
+    }
 
-        Ok(true)
+    /// Delete every block with an index below `index`, oldest history first;
+    /// used by storage guardrails after the blocks have been archived
+    pub fn prune_blocks_below(&self, index: u64) -> DbResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute("DELETE FROM blockchain WHERE block_index < ?", [index])?;
+        self.cache.lock().unwrap().invalidate(..index);
+        Ok(removed)
+    }
+
+    /// Return freed pages to the filesystem so the file actually shrinks
+    pub fn vacuum(&self) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch("VACUUM")?;
+        Ok(())
     }
 
     /// Delete a block by index (use with caution)
@@ -408,6 +1813,7 @@ impl DatabaseManager {
         let conn = self.conn.lock().unwrap();
         let rows_affected =
             conn.execute("DELETE FROM blockchain WHERE block_index = ?", [index])?;
+        self.cache.lock().unwrap().invalidate(index..=index);
 
         Ok(rows_affected > 0)
     }
@@ -438,10 +1844,350 @@ impl DatabaseManager {
             max_timestamp,
         })
     }
+
+    /// Block counts per day, source mix and daily prices per asset for the
+    /// blocks in `range`; see `etl::analytics`
+    pub fn get_analytics(&self, range: &AnalyticsRange) -> DbResult<ChainAnalytics> {
+        let mut analytics = AnalyticsBuilder::new(*range);
+        for block in self.iter_blocks(..) {
+            analytics.add(&block?);
+        }
+        Ok(analytics.finish())
+    }
+
+    /// Regenerate every piece of derived state from the blockchain table,
+    /// then check it against the blocks
+    ///
+    /// - the table's indexes are recreated if missing and rebuilt;
+    /// - account usage (`entries`, `fees_paid`) is recomputed from the fee
+    ///   records in the blocks. Credits are not on chain, so each account
+    ///   keeps the total it was credited and its balance is that total less
+    ///   the recomputed fees;
+    /// - the verification checkpoint moves to the tip if the chain verifies,
+    ///   and is cleared otherwise so the rolling verifier starts over;
+    /// - the block cache is emptied.
+    ///
+    /// Analytics rollups are computed from the blocks on every request and
+    /// need no rebuild. Fails with `InvalidData` if the rebuilt state does
+    /// not match the blocks.
+    pub fn rebuild_derived(&self) -> DbResult<RebuildReport> {
+        let mut report = RebuildReport::default();
+        let mut usage: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        let mut tip: Option<(u64, String)> = None;
+        for block in self.iter_blocks(..) {
+            let block = block?;
+            report.blocks += 1;
+            report.entries += block.data.len() as u64;
+            for fee in &block.fees {
+                let (entries, amount) = usage.entry(fee.submitter.clone()).or_default();
+                *entries += fee.entries;
+                *amount += fee.amount;
+            }
+            tip = Some((block.index, block.hash));
+        }
+        report.chain_verified = self.verify_chain()?;
+
+        let accounts = self.load_accounts()?;
+        {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            tx.execute_batch(BLOCKCHAIN_INDEXES_SQL)?;
+            tx.execute_batch("REINDEX blockchain")?;
+
+            let mut credited: BTreeMap<String, u64> = accounts
+                .into_iter()
+                .map(|a| (a.submitter, a.balance + a.fees_paid))
+                .collect();
+            for submitter in usage.keys() {
+                credited.entry(submitter.clone()).or_default();
+            }
+            tx.execute("DELETE FROM accounts", [])?;
+            for (submitter, credited) in &credited {
+                let (entries, fees_paid) = usage.get(submitter).copied().unwrap_or_default();
+                tx.execute(
+                    "INSERT INTO accounts (submitter, balance, entries, fees_paid)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        submitter,
+                        credited.saturating_sub(fees_paid),
+                        entries,
+                        fees_paid
+                    ],
+                )?;
+                report.fees_paid += fees_paid;
+            }
+            report.accounts = credited.len();
+
+            tx.execute("DELETE FROM verification_checkpoint", [])?;
+            if let Some((index, hash)) = tip.filter(|_| report.chain_verified) {
+                tx.execute(
+                    "INSERT INTO verification_checkpoint (id, block_index, hash, verified_at)
+                     VALUES (1, ?1, ?2, ?3)",
+                    params![index, hash, crate::etl::now_millis()],
+                )?;
+                report.checkpoint_index = Some(index);
+            }
+            tx.commit()?;
+
+            let mut stmt = conn.prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'blockchain'
+                 ORDER BY name",
+            )?;
+            report.indexes = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            drop(stmt);
+
+            // Every index must hold exactly the table's rows
+            let integrity: String =
+                conn.query_row("PRAGMA integrity_check(blockchain)", [], |row| row.get(0))?;
+            if integrity != "ok" {
+                return Err(DatabaseError::InvalidData(format!(
+                    "rebuilt indexes fail the integrity check: {}",
+                    integrity
+                )));
+            }
+            let (blocks, entries, fees_paid): (u64, u64, u64) = conn.query_row(
+                "SELECT (SELECT COUNT(*) FROM blockchain),
+                        (SELECT COALESCE(SUM(entries), 0) FROM accounts),
+                        (SELECT COALESCE(SUM(fees_paid), 0) FROM accounts)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            let fee_entries: u64 = usage.values().map(|(entries, _)| entries).sum();
+            if (blocks, entries, fees_paid) != (report.blocks, fee_entries, report.fees_paid) {
+                return Err(DatabaseError::InvalidData(format!(
+                    "rebuilt state does not match the chain: {} blocks, {} billed entries and {} fees on file; {}, {} and {} in the blocks",
+                    blocks, entries, fees_paid, report.blocks, fee_entries, report.fees_paid
+                )));
+            }
+        }
+        self.cache.lock().unwrap().clear();
+        info!(
+            blocks = report.blocks,
+            accounts = report.accounts,
+            chain_verified = report.chain_verified,
+            "Database: Rebuilt derived state"
+        );
+        Ok(report)
+    }
 }
 
-/// Database statistics structure
+/// Blocks read per query by `BlockIter`
+const BLOCK_PAGE_SIZE: usize = 256;
+
+/// Inclusive index bounds of `range`; empty ranges come back with
+/// `start > end`
+fn index_bounds(range: &impl RangeBounds<u64>) -> (u64, u64) {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end,
+        Bound::Excluded(&0) => return (1, 0),
+        Bound::Excluded(&end) => end - 1,
+        Bound::Unbounded => u64::MAX,
+    };
+    (start, end)
+}
+
+/// Iterator returned by `DatabaseManager::iter_blocks`
+///
+/// Pages through the chain by block index (keyset pagination), so each page
+/// is an index range scan regardless of how far the cursor has advanced.
+pub struct BlockIter<'a> {
+    db: &'a DatabaseManager,
+    next_index: u64,
+    end: u64,
+    page: VecDeque<Block>,
+    done: bool,
+}
+
+impl<'a> BlockIter<'a> {
+    fn new(db: &'a DatabaseManager, range: &impl RangeBounds<u64>) -> Self {
+        let (start, end) = index_bounds(range);
+        BlockIter {
+            db,
+            next_index: start,
+            end,
+            page: VecDeque::new(),
+            done: start > end,
+        }
+    }
+}
+
+impl Iterator for BlockIter<'_> {
+    type Item = DbResult<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            match self
+                .db
+                .blocks_page(self.next_index, self.end, BLOCK_PAGE_SIZE)
+            {
+                Ok(page) => {
+                    match page.last() {
+                        Some(last) if last.index < self.end && page.len() == BLOCK_PAGE_SIZE => {
+                            self.next_index = last.index + 1;
+                        }
+                        _ => self.done = true,
+                    }
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+/// Async counterpart of `BlockIter`, from `DatabaseManager::stream_blocks`
+pub struct BlockStream {
+    receiver: tokio::sync::mpsc::Receiver<DbResult<Block>>,
+}
+
+impl BlockStream {
+    /// The next block, or `None` once the range is exhausted
+    pub async fn next(&mut self) -> Option<DbResult<Block>> {
+        self.receiver.recv().await
+    }
+}
+
+/// A peer block held back from the chain because it failed verification
 #[derive(Debug, Clone)]
+pub struct QuarantinedBlock {
+    pub block: Block,
+    /// Address of the peer the block was pulled from
+    pub peer: String,
+    pub reason: String,
+    /// When the block was quarantined (milliseconds)
+    pub quarantined_at: i64,
+}
+
+/// Progress of the rolling verifier: the tip it last confirmed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerificationCheckpoint {
+    pub block_index: u64,
+    pub hash: String,
+    /// When the tip was verified (milliseconds)
+    pub verified_at: i64,
+}
+
+/// Time from a block's oldest data point to its commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitLatency {
+    pub block_index: u64,
+    /// Timestamp of the block's oldest entry (milliseconds)
+    pub observed_at: i64,
+    /// When the block was persisted (milliseconds)
+    pub committed_at: i64,
+}
+
+impl CommitLatency {
+    /// `None` for a block without data
+    pub fn for_block(block: &Block, committed_at: i64) -> Option<Self> {
+        let observed_at = block
+            .data
+            .iter()
+            .map(|item| crate::etl::timestamp_to_millis(item.timestamp))
+            .min()?;
+        Some(CommitLatency {
+            block_index: block.index,
+            observed_at,
+            committed_at,
+        })
+    }
+
+    pub fn latency_ms(&self) -> i64 {
+        (self.committed_at - self.observed_at).max(0)
+    }
+}
+
+/// An attestation as stored; the signed document is kept verbatim
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredAttestation {
+    /// Assigned by `save_attestation`
+    pub id: i64,
+    pub height: u64,
+    pub head_hash: String,
+    /// Milliseconds
+    pub attested_at: i64,
+    pub document_json: String,
+    /// When the notary acknowledged it (milliseconds)
+    pub notarized_at: Option<i64>,
+}
+
+/// Signed record of a redacted block payload; see `network::redaction`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRedaction {
+    pub block_index: u64,
+    /// Hash the block kept; still verifies its links
+    pub block_hash: String,
+    pub reason: String,
+    /// Node that signed the record
+    pub redacted_by: usize,
+    /// Milliseconds
+    pub redacted_at: i64,
+    /// Hex-encoded Ed25519 key and signature
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// A chain head submitted to a timestamping calendar
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredAnchor {
+    /// Assigned by `save_anchor`
+    pub id: i64,
+    pub height: u64,
+    pub head_hash: String,
+    /// Calendar the head was submitted to
+    pub calendar: String,
+    /// Serialized OpenTimestamps proof from the head hash
+    pub proof: Vec<u8>,
+    /// Milliseconds
+    pub submitted_at: i64,
+    /// Bitcoin block the proof commits to, once upgraded
+    pub bitcoin_height: Option<u64>,
+    /// When the confirmed proof was fetched (milliseconds)
+    pub confirmed_at: Option<i64>,
+}
+
+/// A consensus message waiting to be acknowledged by one peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub peer: String,
+    /// The encoded `PBFTMessage`
+    pub payload: String,
+    pub trace_id: Option<String>,
+    pub attempts: u32,
+    /// When the message was queued (milliseconds)
+    pub created_at: i64,
+}
+
+/// Outcome of `DatabaseManager::rebuild_derived`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RebuildReport {
+    pub blocks: u64,
+    pub entries: u64,
+    /// Indexes on the blockchain table after the rebuild
+    pub indexes: Vec<String>,
+    pub accounts: usize,
+    /// Fees recorded in the blocks, as now reflected in the accounts
+    pub fees_paid: u64,
+    pub chain_verified: bool,
+    /// Tip the verification checkpoint now records; `None` when the chain
+    /// is empty or does not verify
+    pub checkpoint_index: Option<u64>,
+}
+
+/// Database statistics structure
+#[derive(Debug, Clone, Serialize)]
 pub struct DatabaseStats {
     pub total_blocks: u64,
     pub min_index: Option<u64>,
@@ -453,7 +2199,9 @@ pub struct DatabaseStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::etl::{Block, MarketData};
+    use crate::etl::price::Decimal;
+    use crate::etl::{Block, MarketData, LEGACY_BLOCK_FORMAT_VERSION};
+    use crate::testing;
     use std::fs;
 
     static INIT: std::sync::Once = std::sync::Once::new();
@@ -471,21 +2219,10 @@ mod tests {
     }
 
     fn create_test_block(index: u64, previous_hash: &str) -> Block {
-        let mut block = Block {
-            index,
-            timestamp: 1234567890 + index as i64,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0 + index as f32,
-                source: "Test".to_string(),
-                timestamp: 1234567890 + index as i64,
-            }],
-            previous_hash: previous_hash.to_string(),
-            hash: String::new(),
-            nonce: 0,
-        };
-        block.calculate_hash_with_nonce();
-        block
+        let timestamp = 1_234_567_890_000 + index as i64 * 1000;
+        let price = Decimal::from(50000) + Decimal::from(index);
+        let entry = testing::entry("BTC", "Test", price, timestamp);
+        testing::block(index, previous_hash, vec![entry])
     }
 
     #[test]
@@ -653,6 +2390,219 @@ mod tests {
         fs::remove_file(test_db).ok();
     }
 
+    #[test]
+    fn test_search_blocks() {
+        init();
+        let test_db = "test_search.db";
+        fs::remove_file(test_db).ok();
+
+        let db = DatabaseManager::new(test_db).unwrap();
+        db.init().unwrap();
+
+        let mut prev_hash = "0000_genesis".to_string();
+        for i in 1..=4 {
+            let mut block = create_test_block(i, &prev_hash);
+            if i % 2 == 0 {
+                block.data[0].asset = "ETH".to_string();
+                block.calculate_hash_with_nonce();
+            }
+            prev_hash = block.hash.clone();
+            db.save_block(&block).unwrap();
+        }
+
+        let eth = db
+            .search_blocks(&BlockQuery {
+                asset: Some("ETH".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(eth.iter().map(|b| b.index).collect::<Vec<_>>(), vec![2, 4]);
+
+        let ranged = db
+            .search_blocks(&BlockQuery {
+                from_timestamp: Some(1_234_567_892_000),
+                to_timestamp: Some(1_234_567_893_000),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            ranged.iter().map(|b| b.index).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let limited = db
+            .search_blocks(&BlockQuery {
+                source: Some("Test".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].index, 1);
+
+        let none = db
+            .search_blocks(&BlockQuery {
+                source: Some("Elsewhere".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(none.is_empty());
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_get_ancestors() {
+        init();
+        let test_db = "test_ancestors.db";
+        fs::remove_file(test_db).ok();
+
+        let db = DatabaseManager::new(test_db).unwrap();
+        db.init().unwrap();
+
+        let mut prev_hash = "0000_genesis".to_string();
+        let mut last = None;
+        for i in 1..=3 {
+            let block = create_test_block(i, &prev_hash);
+            prev_hash = block.hash.clone();
+            db.save_block(&block).unwrap();
+            last = Some(block);
+        }
+        let head = last.unwrap();
+
+        let ancestors = db.get_ancestors(&head, 1).unwrap();
+        assert_eq!(ancestors.len(), 1);
+        assert_eq!(ancestors[0].index, 2);
+
+        // Stops at genesis even when more are requested
+        let ancestors = db.get_ancestors(&head, 10).unwrap();
+        assert_eq!(
+            ancestors.iter().map(|b| b.index).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_migrate_legacy_schema() {
+        init();
+        let test_db = "test_migrate_v0.db";
+        fs::remove_file(test_db).ok();
+
+        // Schema and data as written before the millisecond migration
+        let legacy_block = {
+            let mut block = create_test_block(1, "0000_genesis");
+            block.timestamp = 1234567891;
+            block.data[0].timestamp = 1234567891;
+            block.format_version = LEGACY_BLOCK_FORMAT_VERSION;
+            block.calculate_hash_with_nonce();
+            block
+        };
+        {
+            let conn = Connection::open(test_db).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE blockchain (
+                    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+                    block_index   INTEGER NOT NULL UNIQUE,
+                    timestamp     INTEGER NOT NULL,
+                    data_json     TEXT NOT NULL,
+                    prev_hash     TEXT NOT NULL,
+                    hash          TEXT NOT NULL UNIQUE,
+                    nonce         INTEGER NOT NULL,
+                    created_at    INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+                )",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO blockchain (block_index, timestamp, data_json, prev_hash, hash, nonce, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 0, 1234567891)",
+                params![
+                    legacy_block.index,
+                    legacy_block.timestamp,
+                    serde_json::to_string(&legacy_block.data).unwrap(),
+                    legacy_block.previous_hash,
+                    legacy_block.hash
+                ],
+            )
+            .unwrap();
+        }
+
+        let db = DatabaseManager::new(test_db).unwrap();
+        db.init().unwrap();
+        assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+
+        let created_at: i64 = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT created_at FROM blockchain", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(created_at, 1_234_567_891_000);
+
+        // Hashed fields are untouched, so the legacy block still verifies
+        let block = db.get_block_by_index(1).unwrap();
+        assert_eq!(block.timestamp, 1234567891);
+        assert_eq!(block.calculate_hash(), block.hash);
+
+        // Millisecond range filters still match the legacy row
+        let found = db
+            .search_blocks(&BlockQuery {
+                from_timestamp: Some(1_234_567_891_000),
+                to_timestamp: Some(1_234_567_891_000),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(found.len(), 1);
+
+        // v2 adds the quarantine table
+        assert!(db.get_quarantined_blocks(1).unwrap().is_empty());
+
+        // v4 adds the verification checkpoint
+        assert_eq!(db.get_verification_checkpoint().unwrap(), None);
+
+        // v5 adds fee accounting; legacy blocks carry no fees
+        assert!(db.load_accounts().unwrap().is_empty());
+        assert!(db.get_block_by_index(1).unwrap().fees.is_empty());
+
+        // v6 adds commit latency tracking
+        assert!(db.get_commit_latencies(10).unwrap().is_empty());
+
+        // Re-running init on a migrated database is a no-op
+        db.init().unwrap();
+        assert_eq!(db.get_block_count().unwrap(), 1);
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_quarantine_block() {
+        init();
+        let test_db = "test_quarantine.db";
+        fs::remove_file(test_db).ok();
+
+        let db = DatabaseManager::new(test_db).unwrap();
+        db.init().unwrap();
+
+        let mut block = create_test_block(7, "0000_genesis");
+        block.hash = "forged".to_string();
+        db.quarantine_block(&block, "127.0.0.1:8001", "hash mismatch")
+            .unwrap();
+
+        let quarantined = db.get_quarantined_blocks(10).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].block.index, 7);
+        assert_eq!(quarantined[0].block.hash, "forged");
+        assert_eq!(quarantined[0].peer, "127.0.0.1:8001");
+        assert_eq!(quarantined[0].reason, "hash mismatch");
+        assert!(quarantined[0].quarantined_at > crate::etl::LEGACY_SECONDS_THRESHOLD);
+
+        // Quarantined blocks never reach the chain itself
+        assert_eq!(db.get_block_count().unwrap(), 0);
+
+        fs::remove_file(test_db).ok();
+    }
+
     #[test]
     fn test_verify_chain_valid() {
         init();
@@ -753,6 +2703,601 @@ mod tests {
         fs::remove_file(test_db).ok();
     }
 
+    #[test]
+    fn test_get_analytics() {
+        use crate::etl::analytics::AnalyticsRange;
+
+        init();
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+
+        let day = 86_400_000;
+        let mut prev_hash = "0000_genesis".to_string();
+        for i in 1..=3 {
+            let mut block = create_test_block(i, &prev_hash);
+            if i == 3 {
+                block.timestamp += day;
+                block.data.push(MarketData {
+                    asset: "ETH".to_string(),
+                    price: Decimal::from(3000),
+                    source: "Other".to_string(),
+                    timestamp: block.timestamp,
+                    provenance: None,
+                    indicators: None,
+                    conversion: None,
+                    bucket: None,
+                });
+                block.calculate_hash_with_nonce();
+            }
+            prev_hash = block.hash.clone();
+            db.save_block(&block).unwrap();
+        }
+
+        let analytics = db.get_analytics(&AnalyticsRange::all()).unwrap();
+        assert_eq!((analytics.total_blocks, analytics.total_entries), (3, 4));
+        let per_day: Vec<_> = analytics
+            .blocks_per_day
+            .iter()
+            .map(|d| (d.day.as_str(), d.blocks, d.entries))
+            .collect();
+        assert_eq!(per_day, [("2009-02-13", 2, 2), ("2009-02-14", 1, 2)]);
+        assert_eq!(analytics.sources[0].source, "Test");
+        assert_eq!(analytics.sources[0].share, 0.75);
+        assert_eq!(analytics.sources[1].entries, 1);
+
+        let btc = &analytics.daily_prices[0];
+        assert_eq!(
+            (btc.day.as_str(), btc.asset.as_str()),
+            ("2009-02-13", "BTC")
+        );
+        assert_eq!(btc.samples, 2);
+        assert_eq!((btc.min_price, btc.max_price), (50001.0, 50002.0));
+        assert_eq!(btc.avg_price, 50001.5);
+        assert_eq!(analytics.daily_prices.len(), 3);
+
+        // The range filters on the block timestamp
+        let first_day = AnalyticsRange::between(0, 1_234_567_890_000 + day / 2);
+        let analytics = db.get_analytics(&first_day).unwrap();
+        assert_eq!(analytics.total_blocks, 2);
+        assert_eq!(analytics.sources.len(), 1);
+        assert_eq!(analytics.sources[0].share, 1.0);
+    }
+
+    fn save_test_chain(db: &DatabaseManager, len: u64) {
+        let mut prev_hash = "0000_genesis".to_string();
+        let blocks: Vec<Block> = (1..=len)
+            .map(|i| {
+                let block = create_test_block(i, &prev_hash);
+                prev_hash = block.hash.clone();
+                block
+            })
+            .collect();
+        db.save_blocks(&blocks).unwrap();
+    }
+
+    #[test]
+    fn test_iter_blocks_pages_through_range() {
+        init();
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        // More than one page
+        let len = BLOCK_PAGE_SIZE as u64 * 2 + 10;
+        save_test_chain(&db, len);
+
+        let indexes: Vec<u64> = db.iter_blocks(..).map(|b| b.unwrap().index).collect();
+        assert_eq!(indexes, (1..=len).collect::<Vec<_>>());
+        assert_eq!(db.iter_blocks(250..260).count(), 10);
+        assert_eq!(db.iter_blocks(len - 1..).count(), 2);
+        assert_eq!(db.iter_blocks(..=3).count(), 3);
+        assert_eq!(db.iter_blocks(..0).count(), 0);
+        assert!(db.verify_chain().unwrap());
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_chain(5..=7, &mut out).unwrap(), 3);
+        let exported: Vec<Block> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(exported[0].hash, db.get_block_by_index(5).unwrap().hash);
+        assert_eq!(exported[2].index, 7);
+    }
+
+    #[tokio::test]
+    async fn test_stream_blocks() {
+        init();
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+        save_test_chain(&db, 20);
+
+        let mut stream = db.stream_blocks(11..);
+        let mut indexes = Vec::new();
+        while let Some(block) = stream.next().await {
+            indexes.push(block.unwrap().index);
+        }
+        assert_eq!(indexes, (11..=20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_block_cache_serves_reads_and_follows_writes() {
+        init();
+        let db = DatabaseManager::in_memory().unwrap().with_block_cache(16);
+        db.init().unwrap();
+        save_test_chain(&db, 5);
+
+        assert_eq!(db.query_latest_blocks(3).unwrap().len(), 3);
+        let misses = db.block_cache_stats().misses;
+        // Served from the cache from now on
+        assert_eq!(db.get_latest_block().unwrap().unwrap().index, 5);
+        assert_eq!(db.query_latest_blocks(3).unwrap()[2].index, 3);
+        let tip_hash = db.get_block_by_index(5).unwrap().hash;
+        assert_eq!(db.get_block_by_hash(&tip_hash).unwrap().index, 5);
+        let stats = db.block_cache_stats();
+        assert_eq!((stats.hits, stats.misses), (4, misses));
+
+        // A reorg replaces block 5; the cached one must not be served
+        db.delete_block(5).unwrap();
+        let mut replacement = create_test_block(5, &db.get_block_by_index(4).unwrap().hash);
+        replacement.nonce = 1;
+        replacement.calculate_hash_with_nonce();
+        db.save_block(&replacement).unwrap();
+        assert_eq!(
+            db.get_latest_block().unwrap().unwrap().hash,
+            replacement.hash
+        );
+        assert!(db.get_block_by_hash(&tip_hash).is_err());
+
+        db.prune_blocks_below(3).unwrap();
+        assert!(db.get_block_by_index(1).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_payloads_and_key_rotation() {
+        init();
+        let test_db = "test_encrypted_payloads.db";
+        fs::remove_file(test_db).ok();
+        let key = |byte: &str| byte.repeat(32);
+        let stored_payloads = |db: &DatabaseManager| -> Vec<String> {
+            let conn = db.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT data_json FROM blockchain").unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+
+        // A ledger written before encryption was enabled
+        let plain = DatabaseManager::new(test_db).unwrap();
+        plain.init().unwrap();
+        save_test_chain(&plain, 3);
+        drop(plain);
+
+        let cipher = PayloadCipher::parse_keyring(&format!("k1:{}", key("11"))).unwrap();
+        let db = DatabaseManager::new(test_db)
+            .unwrap()
+            .with_payload_cipher(cipher);
+        db.init().unwrap();
+        assert_eq!(db.reencrypt_payloads().unwrap(), 3);
+        db.save_block(&create_test_block(
+            4,
+            &db.get_block_by_index(3).unwrap().hash,
+        ))
+        .unwrap();
+        assert!(stored_payloads(&db)
+            .iter()
+            .all(|p| PayloadCipher::key_id(p) == Some("k1") && !p.contains("BTC")));
+        // Queued consensus messages carry block data too
+        let message = r#"{"block_data_json":"[{\"asset\":\"BTC\"}]"}"#;
+        db.enqueue_outbox(&[("127.0.0.1:8001", message)], None)
+            .unwrap();
+        let stored_message: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT payload FROM outbox", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(PayloadCipher::key_id(&stored_message), Some("k1"));
+        assert!(!stored_message.contains("BTC"));
+        assert_eq!(db.get_pending_outbox().unwrap()[0].payload, message);
+        // Hashes cover the plaintext, so the chain still verifies
+        assert!(db.verify_chain().unwrap());
+        assert_eq!(db.get_block_by_index(2).unwrap().data[0].asset, "BTC");
+        let query = BlockQuery {
+            asset: Some("BTC".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(db.search_blocks(&query).unwrap().len(), 2);
+        assert_eq!(
+            db.get_latest_block_for_asset("BTC").unwrap().unwrap().index,
+            4
+        );
+        assert!(db.get_latest_block_for_asset("ETH").unwrap().is_none());
+        drop(db);
+
+        // Without the key the payloads cannot be read
+        let keyless = DatabaseManager::new(test_db).unwrap();
+        assert!(keyless.get_block_by_index(1).is_err());
+
+        // Rotation: the new key first, the old one kept to decrypt
+        let rotated = PayloadCipher::parse_keyring(&format!("k2:{},k1:{}", key("22"), key("11")));
+        let db = DatabaseManager::new(test_db)
+            .unwrap()
+            .with_payload_cipher(rotated.unwrap());
+        assert_eq!(db.reencrypt_payloads().unwrap(), 5);
+        assert_eq!(db.reencrypt_payloads().unwrap(), 0);
+        assert!(stored_payloads(&db)
+            .iter()
+            .all(|p| PayloadCipher::key_id(p) == Some("k2")));
+        let retired = PayloadCipher::parse_keyring(&format!("k2:{}", key("22"))).unwrap();
+        let db = DatabaseManager::new(test_db)
+            .unwrap()
+            .with_payload_cipher(retired);
+        assert!(db.verify_chain().unwrap());
+        assert_eq!(db.get_pending_outbox().unwrap()[0].payload, message);
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_encrypted_ledger_file_holds_no_prices() {
+        init();
+        let test_db = "test_encrypted_columns.db";
+        fs::remove_file(test_db).ok();
+        let price = |s: &str| crate::etl::price::parse(s).unwrap();
+        let cipher = PayloadCipher::parse_keyring(&format!("k1:{}", "33".repeat(32))).unwrap();
+        let db = DatabaseManager::new(test_db)
+            .unwrap()
+            .with_payload_cipher(cipher);
+        db.init().unwrap();
+
+        let entry = testing::entry("BTC", "Kraken", price("50001.25"), 1_700_000_000_000);
+        let mut block = testing::block(1, "0000_genesis", vec![entry]);
+        block.fees.push(crate::etl::accounting::FeeRecord {
+            submitter: "Kraken".to_string(),
+            entries: 1,
+            amount: 2,
+        });
+        block
+            .divergences
+            .push(crate::etl::divergence::DivergenceEvent {
+                asset: "BTC".to_string(),
+                quotes: vec![crate::etl::divergence::SourceQuote {
+                    source: "Coinbase".to_string(),
+                    price: price("50123.45"),
+                    timestamp: 1_700_000_000_000,
+                    volume: None,
+                }],
+                median_price: price("50123.45"),
+                spread_pct: 0.0,
+                threshold_pct: 1.0,
+            });
+        block
+            .order_books
+            .push(crate::etl::order_book::OrderBookData {
+                asset: "BTC".to_string(),
+                source: "Kraken".to_string(),
+                timestamp: 1_700_000_000_000,
+                bids: vec![crate::etl::order_book::PriceLevel {
+                    price: price("50099.5"),
+                    size: price("0.25"),
+                }],
+                asks: Vec::new(),
+            });
+        block.calculate_hash_with_nonce();
+        db.save_block(&block).unwrap();
+        let entry = testing::entry("BTC", "Kraken", price("50002.75"), 1_700_000_000_000);
+        let mut forged = testing::block(2, &block.hash, vec![entry]);
+        forged.hash = "forged".to_string();
+        db.quarantine_block(&forged, "127.0.0.1:8001", "hash mismatch")
+            .unwrap();
+        let stored = db.get_block_by_index(1).unwrap();
+        assert_eq!(stored.calculate_hash(), block.hash);
+        assert_eq!(stored.order_books, block.order_books);
+        assert_eq!(
+            db.get_quarantined_blocks(1).unwrap()[0].block.data[0].price,
+            price("50002.75")
+        );
+        drop(db);
+
+        // Every value in every table, read without the key
+        let keyless = DatabaseManager::new(test_db).unwrap();
+        assert!(keyless.get_quarantined_blocks(1).is_err());
+        let conn = keyless.conn.lock().unwrap();
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let mut stored = String::new();
+        for table in tables {
+            let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table)).unwrap();
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([]).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                for column in 0..columns {
+                    let value: rusqlite::types::Value = row.get(column).unwrap();
+                    stored.push_str(&format!("{:?}\n", value));
+                }
+            }
+        }
+        assert!(stored.contains("forged"));
+        for price in ["50001.25", "50123.45", "50099.5", "50002.75"] {
+            assert!(!stored.contains(price), "{} is stored in plaintext", price);
+        }
+        drop(conn);
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_encrypted_search_pages_until_the_limit() {
+        let cipher = PayloadCipher::parse_keyring(&format!("k1:{}", "44".repeat(32))).unwrap();
+        let db = DatabaseManager::in_memory()
+            .unwrap()
+            .with_payload_cipher(cipher);
+        db.init().unwrap();
+        // Blocks alternate BTC and ETH, so matches span several pages
+        let generator = testing::MarketDataGenerator::new(1).with_assets([
+            ("BTC", Decimal::from(50_000)),
+            ("ETH", Decimal::from(3_000)),
+        ]);
+        let chain = testing::TestChainBuilder::new()
+            .with_blocks(3 * BLOCK_PAGE_SIZE as u64)
+            .with_generator(generator)
+            .build();
+        db.save_blocks(&chain).unwrap();
+
+        let query = |limit| BlockQuery {
+            asset: Some("ETH".to_string()),
+            limit,
+            ..Default::default()
+        };
+        let limited = db
+            .search_blocks(&query(Some(BLOCK_PAGE_SIZE as u64)))
+            .unwrap();
+        assert_eq!(limited.len(), BLOCK_PAGE_SIZE);
+        assert!(limited.iter().all(|block| block.data[0].asset == "ETH"));
+        assert!(limited.windows(2).all(|w| w[0].index < w[1].index));
+        assert_eq!(limited.last().unwrap().index, 2 * BLOCK_PAGE_SIZE as u64);
+        let all = db.search_blocks(&query(None)).unwrap();
+        assert_eq!(all.len(), 3 * BLOCK_PAGE_SIZE / 2);
+    }
+
+    #[test]
+    fn test_redacted_block_passes_verify_chain() {
+        init();
+        let signer = crate::network::oracle::OracleSigner::new(1, [7; 32]);
+        let db = DatabaseManager::in_memory()
+            .unwrap()
+            .with_block_cache(16)
+            .with_redaction_keys(vec![signer.public_key()]);
+        db.init().unwrap();
+        save_test_chain(&db, 3);
+        let target = db.get_block_by_index(2).unwrap();
+        let record = |hash: &str| StoredRedaction {
+            block_index: 2,
+            block_hash: hash.to_string(),
+            reason: "erasure request".to_string(),
+            redacted_by: 1,
+            redacted_at: 1_700_000_000_000,
+            public_key: "ab".to_string(),
+            signature: "cd".to_string(),
+        };
+
+        // The record must name the block's current hash
+        assert!(matches!(
+            db.redact_block(&record("other")),
+            Err(DatabaseError::NotFound(_))
+        ));
+        assert!(db.get_redactions().unwrap().is_empty());
+
+        // A queued message carrying the block is purged with it
+        let message = format!(r#"{{"block_hash":"{}"}}"#, target.hash);
+        db.enqueue_outbox(
+            &[("127.0.0.1:8001", &message), ("127.0.0.1:8001", "{}")],
+            None,
+        )
+        .unwrap();
+
+        crate::network::redaction::redact(&db, &signer, 2, "erasure request").unwrap();
+        let redacted = db.get_block_by_index(2).unwrap();
+        assert!(redacted.data.is_empty());
+        assert_eq!(redacted.hash, target.hash);
+        assert!(db.verify_chain().unwrap());
+        assert_eq!(
+            db.get_redacted_hashes().unwrap().get(&2),
+            Some(&target.hash)
+        );
+        let queued = db.get_pending_outbox().unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].payload, "{}");
+
+        // A record nobody trusted signed does not exempt the block
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE redactions SET reason = 'forged' WHERE block_index = 2",
+                [],
+            )
+            .unwrap();
+        }
+        assert!(db.get_redacted_hashes().unwrap().is_empty());
+        assert!(!db.verify_chain().unwrap());
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE redactions SET reason = 'erasure request' WHERE block_index = 2",
+                [],
+            )
+            .unwrap();
+        }
+        assert!(db.verify_chain().unwrap());
+
+        // Nor does it exempt a redacted block that carries a payload again
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE blockchain SET fees_json = ?1 WHERE block_index = 2",
+                [r#"[{"submitter":"x","entries":1,"amount":1}]"#],
+            )
+            .unwrap();
+        }
+        db.cache.lock().unwrap().invalidate(2..=2);
+        assert!(!db.verify_chain().unwrap());
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE blockchain SET fees_json = '[]' WHERE block_index = 2",
+                [],
+            )
+            .unwrap();
+        }
+        db.cache.lock().unwrap().invalidate(2..=2);
+        assert!(db.verify_chain().unwrap());
+
+        // Only the redacted block is exempt from re-hashing
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE blockchain SET data_json = '[]' WHERE block_index = 3",
+                [],
+            )
+            .unwrap();
+        }
+        assert!(!db.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_hlc_round_trips_and_is_hashed() {
+        init();
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        let genesis = create_test_block(0, "0");
+        let mut block = create_test_block(1, &genesis.hash);
+        let unstamped_hash = block.hash.clone();
+        block.hlc = Some(crate::etl::hlc::HlcTimestamp {
+            wall_ms: block.timestamp,
+            logical: 3,
+            node_id: 2,
+        });
+        block.calculate_hash_with_nonce();
+        assert_ne!(block.hash, unstamped_hash);
+        db.save_blocks(&[genesis, block.clone()]).unwrap();
+
+        let stored = db.get_block_by_index(1).unwrap();
+        assert_eq!(stored.hlc, block.hlc);
+        assert_eq!(stored.ordering_timestamp(), block.hlc.unwrap());
+        assert!(db.get_block_by_index(0).unwrap().hlc.is_none());
+        assert!(db.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_annotations_are_hashed_and_searchable() {
+        init();
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        let genesis = create_test_block(0, "0");
+        let mut block = create_test_block(1, &genesis.hash);
+        let plain_hash = block.hash.clone();
+        let plain_id = block.content_id();
+        block.annotations =
+            crate::etl::parse_annotations("experiment=fx-7, operator=ops-eu").unwrap();
+        block.calculate_hash_with_nonce();
+        assert_ne!(block.hash, plain_hash);
+        assert_eq!(block.content_id(), plain_id);
+        db.save_blocks(&[genesis, block.clone()]).unwrap();
+
+        let stored = db.get_block_by_index(1).unwrap();
+        assert_eq!(stored.annotations, block.annotations);
+        assert!(db.get_block_by_index(0).unwrap().annotations.is_empty());
+        assert!(db.verify_chain().unwrap());
+
+        let found = db
+            .search_blocks(&BlockQuery {
+                annotation: Some(("experiment".to_string(), "fx-7".to_string())),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(found.iter().map(|b| b.index).collect::<Vec<_>>(), vec![1]);
+        let none = db
+            .search_blocks(&BlockQuery {
+                annotation: Some(("experiment".to_string(), "fx-8".to_string())),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(none.is_empty());
+
+        assert!(crate::etl::parse_annotations("=x").is_err());
+        assert!(crate::etl::parse_annotations("novalue").is_err());
+    }
+
+    #[test]
+    fn test_rebuild_derived_restores_indexes_and_accounts() {
+        use crate::etl::accounting::FeeRecord;
+
+        init();
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        let mut prev_hash = "0000_genesis".to_string();
+        for i in 1..=3 {
+            let mut block = create_test_block(i, &prev_hash);
+            block.fees = vec![FeeRecord {
+                submitter: "Test".to_string(),
+                entries: 1,
+                amount: 5,
+            }];
+            block.calculate_hash_with_nonce();
+            prev_hash = block.hash.clone();
+            db.save_block(&block).unwrap();
+        }
+        // Credited 100 but usage drifted; an index went missing
+        db.save_account(&Account {
+            submitter: "Test".to_string(),
+            balance: 90,
+            entries: 2,
+            fees_paid: 10,
+        })
+        .unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch("DROP INDEX idx_timestamp")
+            .unwrap();
+
+        let report = db.rebuild_derived().unwrap();
+        assert_eq!((report.blocks, report.entries), (3, 3));
+        assert!(report.indexes.contains(&"idx_timestamp".to_string()));
+        assert_eq!(
+            db.load_accounts().unwrap(),
+            vec![Account {
+                submitter: "Test".to_string(),
+                balance: 85,
+                entries: 3,
+                fees_paid: 15,
+            }]
+        );
+        assert!(report.chain_verified);
+        assert_eq!(report.checkpoint_index, Some(3));
+        assert_eq!(
+            db.get_verification_checkpoint().unwrap().unwrap().hash,
+            prev_hash
+        );
+
+        // A chain that no longer verifies loses its checkpoint
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE blockchain SET prev_hash = 'x' WHERE block_index = 2",
+                [],
+            )
+            .unwrap();
+        let report = db.rebuild_derived().unwrap();
+        assert!(!report.chain_verified);
+        assert_eq!(db.get_verification_checkpoint().unwrap(), None);
+    }
+
     #[test]
     fn test_database_error_display() {
         init();
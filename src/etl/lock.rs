@@ -0,0 +1,275 @@
+//! Single-writer protection for a ledger file
+//!
+//! A node holds `<ledger>.lock` for as long as it runs. The lock file records
+//! the owner's PID, host and start time, so a second process pointed at the
+//! same ledger fails at startup with a message naming the owner instead of
+//! interleaving writes with it. The owner is written to a temporary file
+//! that is then hard-linked into place, so the lock file never exists
+//! without its contents.
+//!
+//! A lock left behind by a process that is no longer running on this host is
+//! reclaimed automatically. When liveness cannot be judged (another host, a
+//! recycled PID, or a lock file that cannot be read), `--force-takeover`
+//! replaces the lock after a crash.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{info, warn};
+
+/// Contents of a lock file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub hostname: String,
+    /// When the owner acquired the lock (milliseconds)
+    pub acquired_at: i64,
+}
+
+impl LockOwner {
+    fn current() -> Self {
+        LockOwner {
+            pid: std::process::id(),
+            hostname: crate::logger::get_hostname().to_string(),
+            acquired_at: crate::etl::now_millis(),
+        }
+    }
+
+    /// Whether the owner is known to have exited
+    fn is_gone(&self) -> bool {
+        if self.hostname != crate::logger::get_hostname() {
+            return false;
+        }
+        let pid = sysinfo::Pid::from_u32(self.pid);
+        let mut system = sysinfo::System::new();
+        !system.refresh_process(pid)
+    }
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another live process holds the ledger
+    InUse {
+        path: PathBuf,
+        owner: LockOwner,
+    },
+    /// A lock file exists but its owner cannot be read
+    Unreadable {
+        path: PathBuf,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::InUse { path, owner } => write!(
+                f,
+                "ledger already in use by PID {} on {} (lock file {}); if that process \
+                 has crashed, restart with --force-takeover",
+                owner.pid,
+                owner.hostname,
+                path.display()
+            ),
+            LockError::Unreadable { path } => write!(
+                f,
+                "ledger lock file {} exists but names no owner; if no other process \
+                 uses the ledger, restart with --force-takeover",
+                path.display()
+            ),
+            LockError::Io(e) => write!(f, "cannot lock ledger: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(err: io::Error) -> Self {
+        LockError::Io(err)
+    }
+}
+
+/// Exclusive hold on a ledger, released on drop
+#[derive(Debug)]
+pub struct LedgerLock {
+    path: PathBuf,
+    owner: LockOwner,
+}
+
+impl LedgerLock {
+    /// Lock file guarding `ledger_path`
+    pub fn lock_path(ledger_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = ledger_path.as_ref().as_os_str().to_owned();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Take the lock for `ledger_path`. With `force_takeover`, an existing
+    /// lock is replaced regardless of its owner.
+    pub fn acquire(ledger_path: impl AsRef<Path>, force_takeover: bool) -> Result<Self, LockError> {
+        let path = Self::lock_path(ledger_path);
+        let owner = LockOwner::current();
+
+        for _ in 0..2 {
+            match Self::create(&path, &owner) {
+                Ok(()) => return Ok(LedgerLock { path, owner }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            // Lock files are complete once they exist, so one that cannot be
+            // read is not assumed stale
+            let existing = Self::read_owner(&path);
+            match existing {
+                Some(existing) if force_takeover => warn!(
+                    pid = existing.pid,
+                    host = %existing.hostname,
+                    "Ledger: Forcing takeover of lock"
+                ),
+                Some(existing) if !existing.is_gone() => {
+                    return Err(LockError::InUse {
+                        path,
+                        owner: existing,
+                    })
+                }
+                Some(existing) => info!(
+                    pid = existing.pid,
+                    "Ledger: Reclaiming lock from a process that is no longer running"
+                ),
+                None if force_takeover => {
+                    warn!(path = %path.display(), "Ledger: Replacing unreadable lock file")
+                }
+                None => return Err(LockError::Unreadable { path }),
+            }
+            fs::remove_file(&path).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })?;
+        }
+
+        // Lost a race with another process that took the lock meanwhile
+        match Self::read_owner(&path) {
+            Some(owner) => Err(LockError::InUse { path, owner }),
+            None => Err(LockError::Unreadable { path }),
+        }
+    }
+
+    /// Write `owner` to a temporary file and link it to `path`, failing with
+    /// `AlreadyExists` if `path` is taken
+    fn create(path: &Path, owner: &LockOwner) -> io::Result<()> {
+        static ATTEMPT: AtomicU64 = AtomicU64::new(0);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(
+            ".{}.{}.tmp",
+            owner.pid,
+            ATTEMPT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp = PathBuf::from(temp);
+        let written = (|| {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+            file.write_all(serde_json::to_string(owner)?.as_bytes())?;
+            file.sync_all()?;
+            fs::hard_link(&temp, path)
+        })();
+        fs::remove_file(&temp).ok();
+        written
+    }
+
+    fn read_owner(path: &Path) -> Option<LockOwner> {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn owner(&self) -> &LockOwner {
+        &self.owner
+    }
+}
+
+impl Drop for LedgerLock {
+    fn drop(&mut self) {
+        // Leave the file alone if another process has forced a takeover
+        if Self::read_owner(&self.path).as_ref() == Some(&self.owner) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!(error = %e, path = %self.path.display(), "Ledger: Failed to release lock");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_writer_is_refused_until_takeover() {
+        let ledger = "test_ledger_lock.db";
+        let lock_path = LedgerLock::lock_path(ledger);
+        fs::remove_file(&lock_path).ok();
+
+        let first = LedgerLock::acquire(ledger, false).unwrap();
+        assert!(lock_path.exists());
+
+        let err = LedgerLock::acquire(ledger, false).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains(&format!("PID {}", std::process::id())));
+        assert!(message.contains("--force-takeover"));
+
+        // Owners within one process differ only by acquisition time
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = LedgerLock::acquire(ledger, true).unwrap();
+        // The displaced holder must not delete the new owner's lock
+        drop(first);
+        assert!(lock_path.exists());
+        drop(second);
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_lock_from_exited_process_is_reclaimed() {
+        let ledger = "test_ledger_lock_stale.db";
+        let lock_path = LedgerLock::lock_path(ledger);
+        let stale = LockOwner {
+            pid: u32::MAX - 1,
+            hostname: crate::logger::get_hostname().to_string(),
+            acquired_at: 0,
+        };
+        fs::write(&lock_path, serde_json::to_string(&stale).unwrap()).unwrap();
+
+        let lock = LedgerLock::acquire(ledger, false).unwrap();
+        assert_eq!(lock.owner().pid, std::process::id());
+        drop(lock);
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_unreadable_lock_is_only_replaced_by_takeover() {
+        let ledger = "test_ledger_lock_empty.db";
+        let lock_path = LedgerLock::lock_path(ledger);
+        fs::write(&lock_path, "").unwrap();
+
+        let err = LedgerLock::acquire(ledger, false).unwrap_err();
+        assert!(matches!(err, LockError::Unreadable { .. }));
+        assert!(err.to_string().contains("--force-takeover"));
+        assert!(lock_path.exists());
+
+        let lock = LedgerLock::acquire(ledger, true).unwrap();
+        assert_eq!(
+            LedgerLock::read_owner(&lock_path).as_ref(),
+            Some(lock.owner())
+        );
+        drop(lock);
+        assert!(!lock_path.exists());
+    }
+}
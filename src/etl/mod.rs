@@ -1,42 +1,603 @@
+pub mod accounting;
+pub mod aggregate;
+pub mod analytics;
+pub mod anomaly;
+pub mod block_cache;
+pub mod bucket;
+pub mod consolidate;
+pub mod conversion;
+pub mod divergence;
+pub mod encryption;
 pub mod extract;
+pub mod extract_status;
+pub mod group_commit;
+pub mod guardrails;
+pub mod hlc;
+pub mod indicators;
 pub mod load;
+pub mod lock;
+pub mod order_book;
+pub mod pg_notify;
+pub mod pipeline;
+pub mod price;
+pub mod profile;
+pub mod provenance;
+pub mod sanitizer;
+pub mod schedule;
+pub mod setup;
+pub mod sla;
+pub mod sources;
+#[cfg(feature = "analytics")]
+pub mod sql;
+pub mod storage_bench;
+pub mod stream;
+pub mod stress;
+pub mod symbols;
 pub mod transform;
+pub mod transform_stats;
 pub mod validator;
 
+use accounting::FeeRecord;
+use bucket::Bucket;
+use chrono::Utc;
+use conversion::Conversion;
+use divergence::DivergenceEvent;
+use hlc::HlcTimestamp;
+use indicators::Indicators;
+use order_book::{OrderBookData, PriceLevel};
+use price::Decimal;
+use provenance::{CustodyStep, Provenance};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Timestamps below this magnitude are second-precision values written before
+/// the pipeline moved to milliseconds (10^11 ms is 1973, 10^11 s is year 5138).
+pub const LEGACY_SECONDS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Current wall-clock time as a unix timestamp in milliseconds
+pub fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// Interpret a stored timestamp as milliseconds, upgrading legacy
+/// second-precision values
+pub fn timestamp_to_millis(timestamp: i64) -> i64 {
+    if timestamp.abs() < LEGACY_SECONDS_THRESHOLD {
+        timestamp * 1000
+    } else {
+        timestamp
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MarketData {
     pub asset: String,
-    pub price: f32,
+    /// Exact decimal price; a JSON number, or a string when an `f64` cannot
+    /// hold it (see `price::serde_number`)
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
     pub source: String,
+    /// Unix timestamp in milliseconds
     pub timestamp: i64,
+    /// How the extracted quote became this entry; missing for submitted
+    /// entries and entries written before provenance was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Rolling averages over the asset's recent entries, when the proposer
+    /// runs `indicators::IndicatorStage`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indicators: Option<Indicators>,
+    /// Price before `conversion::ConversionStage` converted it into the
+    /// ledger's quote currency, and the rate used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<Conversion>,
+    /// Open, high, low, close and count of the quotes the entry averages,
+    /// when the proposer runs `bucket::BucketStage`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<Bucket>,
+}
+
+/// Hash input encoding used by blocks written before format versioning; the
+/// data vec is hashed as serde_json output, so hashes depend on field order
+/// and float formatting
+pub const LEGACY_BLOCK_FORMAT_VERSION: u32 = 0;
+
+/// Binary hash input with prices encoded by their `f32` bits, used by
+/// blocks written before prices became decimal
+pub const FLOAT_PRICE_BLOCK_FORMAT_VERSION: u32 = 1;
+
+/// Decimal entry prices, but source quotes and divergence medians still
+/// encoded by their `f32` bits
+pub const FLOAT_QUOTE_BLOCK_FORMAT_VERSION: u32 = 2;
+
+/// Format version for new blocks; see `Block::canonical_hash_input`
+pub const BLOCK_FORMAT_VERSION: u32 = 3;
+
+/// Asset quoted by sources that do not name one
+pub const DEFAULT_ASSET: &str = "BTC";
+
+/// Parse block annotations from `key=value,key=value`
+pub fn parse_annotations(spec: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut annotations = BTreeMap::new();
+    for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("annotation '{}' is not key=value", pair))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("annotation '{}' has an empty key", pair));
+        }
+        annotations.insert(key.to_string(), value.trim().to_string());
+    }
+    Ok(annotations)
+}
+
+/// Annotations a proposer stamps on every block it builds, from
+/// `BLOCK_ANNOTATIONS` (empty when unset)
+pub fn annotations_from_env() -> Result<BTreeMap<String, String>, String> {
+    match std::env::var("BLOCK_ANNOTATIONS") {
+        Ok(spec) => {
+            parse_annotations(&spec).map_err(|e| format!("invalid BLOCK_ANNOTATIONS: {}", e))
+        }
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u64).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// A price as `price::canonical_bytes`, or for blocks before
+/// `BLOCK_FORMAT_VERSION` 2 as the bits of the nearest `f32` with `-0.0`
+/// folded into `0.0`
+fn put_price(buf: &mut Vec<u8>, price: Decimal, format_version: u32) {
+    if format_version <= FLOAT_PRICE_BLOCK_FORMAT_VERSION {
+        let price = price::to_f32(price);
+        let price = if price == 0.0 { 0.0f32 } else { price };
+        buf.extend_from_slice(&price.to_bits().to_be_bytes());
+    } else {
+        buf.extend_from_slice(&price::canonical_bytes(price));
+    }
+}
+
+/// A source quote or divergence median as `price::canonical_bytes`, or for
+/// blocks before `BLOCK_FORMAT_VERSION` 3 as the bits of the nearest `f32`
+fn put_quote_price(buf: &mut Vec<u8>, price: Decimal, format_version: u32) {
+    if format_version <= FLOAT_QUOTE_BLOCK_FORMAT_VERSION {
+        buf.extend_from_slice(&price::to_f32(price).to_bits().to_be_bytes());
+    } else {
+        buf.extend_from_slice(&price::canonical_bytes(price));
+    }
+}
+
+/// Entry count followed by each entry's asset, price, source, timestamp
+fn put_entries(buf: &mut Vec<u8>, data: &[MarketData], format_version: u32) {
+    buf.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    for item in data {
+        put_str(buf, &item.asset);
+        put_price(buf, item.price, format_version);
+        put_str(buf, &item.source);
+        buf.extend_from_slice(&item.timestamp.to_be_bytes());
+    }
+}
+
+/// Fee count, then each fee's submitter, entries and amount
+fn put_fees(buf: &mut Vec<u8>, fees: &[FeeRecord]) {
+    buf.extend_from_slice(&(fees.len() as u64).to_be_bytes());
+    for fee in fees {
+        put_str(buf, &fee.submitter);
+        buf.extend_from_slice(&fee.entries.to_be_bytes());
+        buf.extend_from_slice(&fee.amount.to_be_bytes());
+    }
+}
+
+/// Snapshot count, then each snapshot's asset, source, timestamp and levels
+fn put_order_books(buf: &mut Vec<u8>, books: &[OrderBookData]) {
+    let put_levels = |buf: &mut Vec<u8>, levels: &[PriceLevel]| {
+        buf.extend_from_slice(&(levels.len() as u64).to_be_bytes());
+        for level in levels {
+            buf.extend_from_slice(&price::canonical_bytes(level.price));
+            buf.extend_from_slice(&price::canonical_bytes(level.size));
+        }
+    };
+    buf.extend_from_slice(&(books.len() as u64).to_be_bytes());
+    for book in books {
+        put_str(buf, &book.asset);
+        put_str(buf, &book.source);
+        buf.extend_from_slice(&book.timestamp.to_be_bytes());
+        put_levels(buf, &book.bids);
+        put_levels(buf, &book.asks);
+    }
+}
+
+/// Presence flag, then the raw quote, each step and the validator version
+fn put_provenance(buf: &mut Vec<u8>, provenance: Option<&Provenance>, format_version: u32) {
+    let Some(provenance) = provenance else {
+        buf.push(0);
+        return;
+    };
+    buf.push(1);
+    put_price(buf, provenance.raw_price, format_version);
+    put_str(buf, &provenance.raw_source);
+    buf.extend_from_slice(&(provenance.steps.len() as u64).to_be_bytes());
+    for step in &provenance.steps {
+        match step {
+            CustodyStep::Aggregated { quotes } => {
+                buf.push(0);
+                buf.extend_from_slice(&(quotes.len() as u64).to_be_bytes());
+                for quote in quotes {
+                    put_str(buf, &quote.source);
+                    put_quote_price(buf, quote.price, format_version);
+                    buf.extend_from_slice(&quote.timestamp.to_be_bytes());
+                }
+            }
+            CustodyStep::Sanitized(change) => {
+                buf.push(1);
+                put_str(buf, &change.field.to_string());
+                put_str(buf, &change.sanitizer);
+                put_str(buf, &change.before);
+                put_str(buf, &change.after);
+            }
+            CustodyStep::Consolidated {
+                method,
+                quotes,
+                before,
+                after,
+            } => {
+                buf.push(3);
+                put_str(buf, method);
+                buf.extend_from_slice(&(quotes.len() as u64).to_be_bytes());
+                for quote in quotes {
+                    put_str(buf, &quote.source);
+                    put_price(buf, quote.price, format_version);
+                    put_price(buf, quote.weight, format_version);
+                }
+                put_price(buf, *before, format_version);
+                put_price(buf, *after, format_version);
+            }
+            CustodyStep::Flagged { check, detail } => {
+                buf.push(4);
+                put_str(buf, check);
+                put_str(buf, detail);
+            }
+            CustodyStep::Converted {
+                from,
+                to,
+                rate,
+                before,
+                after,
+            } => {
+                buf.push(5);
+                put_str(buf, from);
+                put_str(buf, to);
+                put_price(buf, *rate, format_version);
+                put_price(buf, *before, format_version);
+                put_price(buf, *after, format_version);
+            }
+            CustodyStep::Bucketed {
+                start,
+                count,
+                before,
+                after,
+            } => {
+                buf.push(6);
+                buf.extend_from_slice(&start.to_be_bytes());
+                buf.extend_from_slice(&count.to_be_bytes());
+                put_price(buf, *before, format_version);
+                put_price(buf, *after, format_version);
+            }
+            CustodyStep::Normalized {
+                method,
+                before,
+                after,
+            } => {
+                buf.push(2);
+                put_str(buf, method);
+                put_price(buf, *before, format_version);
+                put_price(buf, *after, format_version);
+            }
+        }
+    }
+    put_str(buf, &provenance.validator);
+}
+
+/// Presence flag, then the window, observation count, SMA, EMA, VWAP and
+/// volume (each optional value behind its own flag)
+fn put_indicators(buf: &mut Vec<u8>, indicators: Option<&Indicators>, format_version: u32) {
+    let Some(indicators) = indicators else {
+        buf.push(0);
+        return;
+    };
+    buf.push(1);
+    buf.extend_from_slice(&indicators.window.to_be_bytes());
+    buf.extend_from_slice(&indicators.observations.to_be_bytes());
+    put_price(buf, indicators.sma, format_version);
+    put_price(buf, indicators.ema, format_version);
+    for value in [indicators.vwap, indicators.volume] {
+        match value {
+            Some(value) => {
+                buf.push(1);
+                put_price(buf, value, format_version);
+            }
+            None => buf.push(0),
+        }
+    }
 }
 
+/// Presence flag, then the currencies, original price, rate and the rate's
+/// source and timestamp
+fn put_conversion(buf: &mut Vec<u8>, conversion: Option<&Conversion>, format_version: u32) {
+    let Some(conversion) = conversion else {
+        buf.push(0);
+        return;
+    };
+    buf.push(1);
+    put_str(buf, &conversion.from);
+    put_str(buf, &conversion.to);
+    put_price(buf, conversion.original_price, format_version);
+    put_price(buf, conversion.rate, format_version);
+    put_str(buf, &conversion.rate_source);
+    buf.extend_from_slice(&conversion.rate_timestamp.to_be_bytes());
+}
+
+/// Presence flag, then the bucket's bounds, count, open, high, low, close
+/// and mean
+fn put_bucket(buf: &mut Vec<u8>, bucket: Option<&Bucket>, format_version: u32) {
+    let Some(bucket) = bucket else {
+        buf.push(0);
+        return;
+    };
+    buf.push(1);
+    buf.extend_from_slice(&bucket.start.to_be_bytes());
+    buf.extend_from_slice(&bucket.end.to_be_bytes());
+    buf.extend_from_slice(&bucket.count.to_be_bytes());
+    for price in [
+        bucket.open,
+        bucket.high,
+        bucket.low,
+        bucket.close,
+        bucket.mean,
+    ] {
+        put_price(buf, price, format_version);
+    }
+}
+
+/// A ledger block
+///
+/// `hash` seals the block as stored, including the proposer's wall-clock
+/// `timestamp` and `nonce`, so two nodes building a block from the same data
+/// still produce different hashes. Cross-node agreement therefore uses
+/// `content_id`, which covers only what nodes can agree on; the timestamp and
+/// HLC are metadata outside that identity.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
     pub index: u64,
+    /// Unix timestamp in milliseconds; creation metadata, not part of
+    /// `content_id`
     pub timestamp: i64,
     pub data: Vec<MarketData>,
     pub previous_hash: String,
     pub hash: String,
     pub nonce: u64,
+    /// Hash input encoding; missing in blocks serialized before versioning
+    #[serde(default)]
+    pub format_version: u32,
+    /// Fees charged for the entries when accounting is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fees: Vec<FeeRecord>,
+    /// Assets whose sources disagreed when the block was built
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub divergences: Vec<DivergenceEvent>,
+    /// Hybrid logical time at which the proposer built the block; orders
+    /// blocks across nodes whose wall clocks disagree. Missing in blocks
+    /// built before nodes kept a `HybridClock`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<HlcTimestamp>,
+    /// Labels set by the proposer, e.g. pipeline version or experiment id;
+    /// covered by the hash but not by `content_id`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Order book depth snapshots taken by the proposer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order_books: Vec<OrderBookData>,
 }
 
 impl Block {
+    /// Hash of the block using the encoding selected by `format_version`
     pub fn calculate_hash(&self) -> String {
-        let data_str = serde_json::to_string(&self.data).unwrap_or_default();
-        let input = format!(
-            "{}{}{}{}{}",
-            self.index, self.timestamp, data_str, self.previous_hash, self.nonce
-        );
         let mut hasher = Sha256::new();
-        hasher.update(input);
+        match self.format_version {
+            LEGACY_BLOCK_FORMAT_VERSION => hasher.update(self.legacy_hash_input()),
+            _ => hasher.update(self.canonical_hash_input()),
+        }
         format!("{:x}", hasher.finalize())
     }
 
     pub fn calculate_hash_with_nonce(&mut self) {
         self.hash = self.calculate_hash();
     }
+
+    /// No entries, fees, divergence events or order books: what redaction
+    /// leaves of a block
+    pub fn has_empty_payload(&self) -> bool {
+        self.data.is_empty()
+            && self.fees.is_empty()
+            && self.divergences.is_empty()
+            && self.order_books.is_empty()
+    }
+
+    fn legacy_hash_input(&self) -> Vec<u8> {
+        let data_str = serde_json::to_string(&self.data).unwrap_or_default();
+        format!(
+            "{}{}{}{}{}",
+            self.index, self.timestamp, data_str, self.previous_hash, self.nonce
+        )
+        .into_bytes()
+    }
+
+    /// Hash input from version 1 on: a fixed binary layout independent of
+    /// serde
+    ///
+    /// Integers are big-endian, strings are length-prefixed UTF-8, and each
+    /// `MarketData` entry is encoded as asset, price, source, timestamp (sorted
+    /// field order). Version 2 encodes a price as the mantissa (`i128`) and
+    /// scale (`u32`) of its normalized decimal, so `1.50` and `1.5` hash
+    /// alike; version 1 used the IEEE-754 bits of the `f32` price with `-0.0`
+    /// folded into `0.0`. Version 3 encodes source quotes and divergence
+    /// medians the same way, which version 2 still hashed by their `f32`
+    /// bits. None involves decimal formatting.
+    pub fn canonical_hash_input(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(128 + self.data.len() * 64);
+        buf.extend_from_slice(b"rml-block");
+        buf.extend_from_slice(&self.format_version.to_be_bytes());
+        buf.extend_from_slice(&self.index.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        put_entries(&mut buf, &self.data, self.format_version);
+        put_str(&mut buf, &self.previous_hash);
+        buf.extend_from_slice(&self.nonce.to_be_bytes());
+        // Appended only when present, so blocks without fees, divergences,
+        // an HLC, provenance, annotations, order books, indicators,
+        // conversions or buckets hash as before
+        if !self.fees.is_empty() {
+            buf.extend_from_slice(b"fees");
+            put_fees(&mut buf, &self.fees);
+        }
+        if !self.divergences.is_empty() {
+            buf.extend_from_slice(b"divergences");
+            buf.extend_from_slice(&(self.divergences.len() as u64).to_be_bytes());
+            for event in &self.divergences {
+                put_str(&mut buf, &event.asset);
+                buf.extend_from_slice(&(event.quotes.len() as u64).to_be_bytes());
+                for quote in &event.quotes {
+                    put_str(&mut buf, &quote.source);
+                    put_quote_price(&mut buf, quote.price, self.format_version);
+                    buf.extend_from_slice(&quote.timestamp.to_be_bytes());
+                }
+                put_quote_price(&mut buf, event.median_price, self.format_version);
+                buf.extend_from_slice(&event.spread_pct.to_bits().to_be_bytes());
+                buf.extend_from_slice(&event.threshold_pct.to_bits().to_be_bytes());
+            }
+        }
+        if let Some(hlc) = &self.hlc {
+            buf.extend_from_slice(b"hlc");
+            buf.extend_from_slice(&hlc.wall_ms.to_be_bytes());
+            buf.extend_from_slice(&hlc.logical.to_be_bytes());
+            buf.extend_from_slice(&(hlc.node_id as u64).to_be_bytes());
+        }
+        if self.data.iter().any(|item| item.provenance.is_some()) {
+            buf.extend_from_slice(b"provenance");
+            for item in &self.data {
+                put_provenance(&mut buf, item.provenance.as_ref(), self.format_version);
+            }
+        }
+        if !self.annotations.is_empty() {
+            buf.extend_from_slice(b"annotations");
+            buf.extend_from_slice(&(self.annotations.len() as u64).to_be_bytes());
+            for (key, value) in &self.annotations {
+                put_str(&mut buf, key);
+                put_str(&mut buf, value);
+            }
+        }
+        if !self.order_books.is_empty() {
+            buf.extend_from_slice(b"order_books");
+            put_order_books(&mut buf, &self.order_books);
+        }
+        if self.data.iter().any(|item| item.indicators.is_some()) {
+            buf.extend_from_slice(b"indicators");
+            for item in &self.data {
+                put_indicators(&mut buf, item.indicators.as_ref(), self.format_version);
+            }
+        }
+        if self.data.iter().any(|item| item.conversion.is_some()) {
+            buf.extend_from_slice(b"conversions");
+            for item in &self.data {
+                put_conversion(&mut buf, item.conversion.as_ref(), self.format_version);
+            }
+        }
+        if self.data.iter().any(|item| item.bucket.is_some()) {
+            buf.extend_from_slice(b"buckets");
+            for item in &self.data {
+                put_bucket(&mut buf, item.bucket.as_ref(), self.format_version);
+            }
+        }
+        buf
+    }
+
+    /// Position of the block in cross-node time: its `hlc`, or its wall
+    /// clock `timestamp` for blocks that predate hybrid clocks
+    pub fn ordering_timestamp(&self) -> HlcTimestamp {
+        self.hlc
+            .unwrap_or_else(|| HlcTimestamp::from_wall(timestamp_to_millis(self.timestamp)))
+    }
+
+    /// Deterministic identifier over height, data and parent hash; what
+    /// PBFT replicas vote on
+    ///
+    /// Independent of when or by whom the block was built (timestamp and
+    /// nonce are excluded), so every node that assembles the same entries on
+    /// the same parent derives the same id. Data entries use the
+    /// `canonical_hash_input` encoding, whose prices follow the format
+    /// version. Order books, fees, provenance and conversions, when present,
+    /// are covered too: they are stored with the block and follow from its
+    /// inputs, so a primary cannot change them under an id replicas agreed
+    /// to.
+    ///
+    /// The HLC and annotations describe the proposer, and divergence events,
+    /// indicators and buckets are derived from each node's own recent
+    /// quotes, so replicas that fetched the same entries can disagree on
+    /// them. They stay outside consensus: each node stores its own, and only
+    /// the block hash covers them.
+    pub fn content_id(&self) -> String {
+        let mut buf = Vec::with_capacity(96 + self.data.len() * 64);
+        buf.extend_from_slice(b"rml-content");
+        buf.extend_from_slice(&self.index.to_be_bytes());
+        put_entries(&mut buf, &self.data, self.format_version);
+        put_str(&mut buf, &self.previous_hash);
+        if !self.order_books.is_empty() {
+            put_order_books(&mut buf, &self.order_books);
+        }
+        // Tagged and appended only when present, so ids of blocks without
+        // them are unchanged
+        if !self.fees.is_empty() {
+            buf.extend_from_slice(b"fees");
+            put_fees(&mut buf, &self.fees);
+        }
+        if self.data.iter().any(|item| item.provenance.is_some()) {
+            buf.extend_from_slice(b"provenance");
+            for item in &self.data {
+                put_provenance(&mut buf, item.provenance.as_ref(), self.format_version);
+            }
+        }
+        if self.data.iter().any(|item| item.conversion.is_some()) {
+            buf.extend_from_slice(b"conversions");
+            for item in &self.data {
+                put_conversion(&mut buf, item.conversion.as_ref(), self.format_version);
+            }
+        }
+
+        format!("{:x}", Sha256::digest(&buf))
+    }
+
+    /// Re-encode a chain under the current format version
+    ///
+    /// Every hash changes, so each block is relinked to its re-hashed parent.
+    /// `blocks` must be contiguous and in ascending index order. Returns the
+    /// `(old_hash, new_hash)` pairs so references to old hashes can be mapped.
+    pub fn upgrade_chain_format(blocks: &mut [Block]) -> Vec<(String, String)> {
+        let mut mapping: Vec<(String, String)> = Vec::with_capacity(blocks.len());
+        for block in blocks.iter_mut() {
+            if let Some((old, new)) = mapping.last() {
+                if &block.previous_hash == old {
+                    block.previous_hash = new.clone();
+                }
+            }
+            let old_hash = block.hash.clone();
+            block.format_version = BLOCK_FORMAT_VERSION;
+            block.calculate_hash_with_nonce();
+            mapping.push((old_hash, block.hash.clone()));
+        }
+        mapping
+    }
 }
@@ -0,0 +1,502 @@
+//! Order book depth snapshots
+//!
+//! Besides prices, a node can notarize the top of an exchange's order book.
+//! With `ORDER_BOOK_SOURCE` set (`kraken` or `coinbase`), the proposer fetches
+//! the best `ORDER_BOOK_DEPTH` (default 10) bid and ask levels of
+//! `ORDER_BOOK_ASSET` (default BTC) each round and stores them in the block
+//! as an `OrderBookData`, covered by its hash. Levels are exact decimals, as
+//! the exchanges send them. A snapshot that fails `OrderBookData::validate`
+//! (unsorted, crossed or non-positive levels) is left out rather than
+//! stored. A failed fetch only leaves the snapshot out; the block is still
+//! proposed.
+//!
+//! The asset is resolved through `ASSET_SYMBOLS` like the price sources: an
+//! FX-style mapping such as `ETHEUR=crypto:ETH/EUR` names the quote
+//! currency, otherwise the book is quoted in USD. Snapshots are fetched with
+//! their own client, built from the `HttpClientConfig` in the config rather
+//! than shared with the price sources. Each exchange endpoint can be
+//! overridden with `KRAKEN_DEPTH_API_URL` / `COINBASE_BOOK_API_URL`.
+
+use crate::etl::extract::{HttpClientConfig, SourceError};
+use crate::etl::price::{self, Decimal};
+use crate::etl::sources::get_json;
+use crate::etl::symbols::SymbolMap;
+use crate::etl::{now_millis, DEFAULT_ASSET};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Levels per side when `ORDER_BOOK_DEPTH` is unset
+pub const DEFAULT_DEPTH: usize = 10;
+
+/// Quote currency when `ASSET_SYMBOLS` does not name one
+pub const DEFAULT_QUOTE: &str = "USD";
+
+/// One price level: the price and the quantity resting at it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
+    #[serde(with = "price::serde_number")]
+    pub size: Decimal,
+}
+
+/// Top-of-book snapshot from one exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderBookData {
+    pub asset: String,
+    pub source: String,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    /// Best first, by descending price
+    pub bids: Vec<PriceLevel>,
+    /// Best first, by ascending price
+    pub asks: Vec<PriceLevel>,
+}
+
+impl OrderBookData {
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.first().map(|level| level.price)
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.first().map(|level| level.price)
+    }
+
+    /// Best ask minus best bid
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()? - self.best_bid()?)
+    }
+
+    /// Keep the best `depth` levels per side
+    pub fn truncate(&mut self, depth: usize) {
+        self.bids.truncate(depth);
+        self.asks.truncate(depth);
+    }
+
+    /// Check that both sides are non-empty, positive, sorted best first and
+    /// that the book is not crossed
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bids.is_empty() || self.asks.is_empty() {
+            return Err(format!("{} order book has an empty side", self.source));
+        }
+        let positive =
+            |level: &PriceLevel| level.price > Decimal::ZERO && level.size > Decimal::ZERO;
+        if !self.bids.iter().chain(&self.asks).all(positive) {
+            return Err(format!(
+                "{} order book has a non-positive level",
+                self.source
+            ));
+        }
+        if self.bids.windows(2).any(|w| w[0].price < w[1].price) {
+            return Err(format!("{} bids are not in descending order", self.source));
+        }
+        if self.asks.windows(2).any(|w| w[0].price > w[1].price) {
+            return Err(format!("{} asks are not in ascending order", self.source));
+        }
+        if self.spread().is_some_and(|spread| spread < Decimal::ZERO) {
+            return Err(format!("{} order book is crossed", self.source));
+        }
+        Ok(())
+    }
+}
+
+/// An exchange that serves order book depth
+#[async_trait]
+pub trait OrderBookSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The best `depth` levels per side; one attempt
+    async fn fetch_depth(&self, depth: usize) -> Result<OrderBookData, SourceError>;
+}
+
+fn parse_levels(source: &str, levels: &[(String, String)]) -> Result<Vec<PriceLevel>, SourceError> {
+    let parse = |value: &str| {
+        price::parse(value).map_err(|_| {
+            SourceError::retryable(format!("{} sent unparseable level '{}'", source, value))
+        })
+    };
+    levels
+        .iter()
+        .map(|(price, size)| {
+            Ok(PriceLevel {
+                price: parse(price)?,
+                size: parse(size)?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct KrakenDepthResponse {
+    error: Vec<String>,
+    #[serde(default)]
+    result: BTreeMap<String, KrakenBook>,
+}
+
+#[derive(Deserialize)]
+struct KrakenBook {
+    /// price, volume, timestamp
+    bids: Vec<(String, String, i64)>,
+    asks: Vec<(String, String, i64)>,
+}
+
+/// Depth of one pair from Kraken's public order book
+pub struct KrakenOrderBook {
+    client: Client,
+    asset: String,
+    url: String,
+}
+
+impl KrakenOrderBook {
+    pub const DEFAULT_URL: &'static str = "https://api.kraken.com/0/public/Depth";
+
+    /// `asset` priced in `quote`, e.g. `BTC` and `USD` for the `XBTUSD`
+    /// pair; the endpoint is `KRAKEN_DEPTH_API_URL` when set
+    pub fn new(client: Client, asset: &str, base: &str, quote: &str) -> Self {
+        // Kraken calls bitcoin XBT
+        let base = if base.eq_ignore_ascii_case("BTC") {
+            "XBT"
+        } else {
+            base
+        };
+        let endpoint =
+            std::env::var("KRAKEN_DEPTH_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into());
+        KrakenOrderBook {
+            client,
+            asset: asset.to_string(),
+            url: format!(
+                "{}?pair={}{}",
+                endpoint,
+                base.to_ascii_uppercase(),
+                quote.to_ascii_uppercase()
+            ),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl OrderBookSource for KrakenOrderBook {
+    fn name(&self) -> &str {
+        "Kraken"
+    }
+
+    async fn fetch_depth(&self, depth: usize) -> Result<OrderBookData, SourceError> {
+        let url = format!("{}&count={}", self.url, depth);
+        let body: KrakenDepthResponse = get_json(&self.client, &url).await?;
+        if let Some(error) = body.error.first() {
+            return Err(SourceError::retryable(format!("Kraken: {}", error)));
+        }
+        let book = body
+            .result
+            .into_values()
+            .next()
+            .ok_or_else(|| SourceError::retryable("Kraken returned no order book"))?;
+        let side = |levels: Vec<(String, String, i64)>| {
+            let levels: Vec<_> = levels.into_iter().map(|(p, s, _)| (p, s)).collect();
+            parse_levels("Kraken", &levels)
+        };
+        let mut snapshot = OrderBookData {
+            asset: self.asset.clone(),
+            source: self.name().to_string(),
+            timestamp: now_millis(),
+            bids: side(book.bids)?,
+            asks: side(book.asks)?,
+        };
+        snapshot.truncate(depth);
+        Ok(snapshot)
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbaseBook {
+    /// price, size, order count
+    bids: Vec<(String, String, serde_json::Value)>,
+    asks: Vec<(String, String, serde_json::Value)>,
+}
+
+/// Aggregated (level 2) book of one product from Coinbase Exchange
+pub struct CoinbaseOrderBook {
+    client: Client,
+    asset: String,
+    url: String,
+}
+
+impl CoinbaseOrderBook {
+    pub const DEFAULT_URL: &'static str = "https://api.exchange.coinbase.com/products";
+
+    /// `asset` priced in `quote`, e.g. the `BTC-USD` product; the endpoint
+    /// is `COINBASE_BOOK_API_URL` when set
+    pub fn new(client: Client, asset: &str, base: &str, quote: &str) -> Self {
+        let endpoint =
+            std::env::var("COINBASE_BOOK_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into());
+        CoinbaseOrderBook {
+            client,
+            asset: asset.to_string(),
+            url: format!(
+                "{}/{}-{}/book?level=2",
+                endpoint,
+                base.to_ascii_uppercase(),
+                quote.to_ascii_uppercase()
+            ),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl OrderBookSource for CoinbaseOrderBook {
+    fn name(&self) -> &str {
+        "Coinbase"
+    }
+
+    async fn fetch_depth(&self, depth: usize) -> Result<OrderBookData, SourceError> {
+        let book: CoinbaseBook = get_json(&self.client, &self.url).await?;
+        let side = |levels: Vec<(String, String, serde_json::Value)>| {
+            let levels: Vec<_> = levels
+                .into_iter()
+                .take(depth)
+                .map(|(p, s, _)| (p, s))
+                .collect();
+            parse_levels("Coinbase", &levels)
+        };
+        Ok(OrderBookData {
+            asset: self.asset.clone(),
+            source: self.name().to_string(),
+            timestamp: now_millis(),
+            bids: side(book.bids)?,
+            asks: side(book.asks)?,
+        })
+    }
+}
+
+/// Which exchange to snapshot, for which asset and how deep
+#[derive(Debug, Clone)]
+pub struct OrderBookConfig {
+    pub source: String,
+    /// Ledger asset symbol, resolved through `symbols`
+    pub asset: String,
+    pub depth: usize,
+    pub symbols: SymbolMap,
+    /// Builds the client snapshots are fetched with
+    pub http: HttpClientConfig,
+}
+
+impl OrderBookConfig {
+    /// BTC, `DEFAULT_DEPTH` levels, no symbol mappings and a default client
+    pub fn new(source: impl Into<String>) -> Self {
+        OrderBookConfig {
+            source: source.into(),
+            asset: DEFAULT_ASSET.to_string(),
+            depth: DEFAULT_DEPTH,
+            symbols: SymbolMap::new(),
+            http: HttpClientConfig::new(),
+        }
+    }
+
+    pub fn with_asset(mut self, asset: impl Into<String>) -> Self {
+        self.asset = asset.into();
+        self
+    }
+
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn with_http_config(mut self, http: HttpClientConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// `None` unless `ORDER_BOOK_SOURCE` is set; `ORDER_BOOK_ASSET` and
+    /// `ORDER_BOOK_DEPTH`, with `ASSET_SYMBOLS` and the market data HTTP
+    /// settings
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(source) = std::env::var("ORDER_BOOK_SOURCE") else {
+            return Ok(None);
+        };
+        let mut config = Self::new(source.trim().to_ascii_lowercase())
+            .with_symbols(SymbolMap::from_env()?)
+            .with_http_config(HttpClientConfig::from_env()?);
+        if let Ok(asset) = std::env::var("ORDER_BOOK_ASSET") {
+            if asset.trim().is_empty() {
+                return Err("invalid ORDER_BOOK_ASSET: empty".to_string());
+            }
+            config.asset = asset.trim().to_ascii_uppercase();
+        }
+        if let Ok(depth) = std::env::var("ORDER_BOOK_DEPTH") {
+            config.depth = depth
+                .parse()
+                .ok()
+                .filter(|&depth: &usize| depth > 0)
+                .ok_or_else(|| format!("invalid ORDER_BOOK_DEPTH '{}'", depth))?;
+        }
+        config.create()?;
+        Ok(Some(config))
+    }
+
+    /// Base and quote currency the exchange is asked for
+    pub fn pair(&self) -> (String, String) {
+        let mapping = self.symbols.resolve(&self.asset);
+        match mapping.currency_pair() {
+            Some((base, quote)) => (base.to_string(), quote.to_string()),
+            None => (mapping.symbol.clone(), DEFAULT_QUOTE.to_string()),
+        }
+    }
+
+    /// The source, with a client of its own built from `http`
+    pub fn create(&self) -> Result<Arc<dyn OrderBookSource>, String> {
+        let client = self.http.build().map_err(|e| e.to_string())?;
+        let (base, quote) = self.pair();
+        match self.source.as_str() {
+            "kraken" => Ok(Arc::new(KrakenOrderBook::new(
+                client,
+                &self.asset,
+                &base,
+                &quote,
+            ))),
+            "coinbase" => Ok(Arc::new(CoinbaseOrderBook::new(
+                client,
+                &self.asset,
+                &base,
+                &quote,
+            ))),
+            other => Err(format!(
+                "unknown order book source '{}' (expected kraken or coinbase)",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::symbols::AssetClass;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    fn start_exchange() -> String {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route(
+                    "/kraken",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "error": [],
+                            "result": { "XXBTZUSD": {
+                                "bids": [["64000.0", "1.5", 1700000000], ["63990.0", "0.2", 1700000000]],
+                                "asks": [["64010.0", "0.7", 1700000000], ["64020.0", "2.0", 1700000000]]
+                            } }
+                        }))
+                    }),
+                )
+                .route(
+                    "/coinbase",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "sequence": 1,
+                            "bids": [["64001.5", "0.3", 4], ["64001.0", "1.1", 2], ["64000.0", "5", 9]],
+                            "asks": [["63999.0", "0.4", 1]]
+                        }))
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    #[actix_web::test]
+    async fn test_order_book_sources_and_validation() {
+        let base = start_exchange();
+        let client = Client::new();
+
+        let dec = |s: &str| price::parse(s).unwrap();
+
+        let kraken = KrakenOrderBook::new(client.clone(), "BTC", "BTC", "USD")
+            .with_url(format!("{}/kraken?pair=XBTUSD", base));
+        let book = kraken.fetch_depth(1).await.unwrap();
+        assert_eq!(book.source, "Kraken");
+        assert_eq!(
+            book.bids,
+            vec![PriceLevel {
+                price: dec("64000.0"),
+                size: dec("1.5")
+            }]
+        );
+        assert_eq!(book.best_ask(), Some(dec("64010")));
+        assert_eq!(book.spread(), Some(dec("10")));
+        assert_eq!(book.validate(), Ok(()));
+
+        let coinbase = CoinbaseOrderBook::new(client.clone(), "BTC", "BTC", "USD")
+            .with_url(format!("{}/coinbase", base));
+        let book = coinbase.fetch_depth(2).await.unwrap();
+        assert_eq!(book.bids.len(), 2);
+        assert!(book.validate().unwrap_err().contains("crossed"));
+
+        let mut unsorted = kraken.fetch_depth(2).await.unwrap();
+        unsorted.bids.reverse();
+        assert!(unsorted.validate().unwrap_err().contains("descending"));
+
+        assert!(OrderBookConfig::new("binance").create().is_err());
+
+        // The asset and its quote currency come from the config
+        let config = OrderBookConfig::new("kraken")
+            .with_asset("ETHEUR")
+            .with_symbols(SymbolMap::new().with_mapping("ETHEUR", AssetClass::Crypto, "ETH/EUR"));
+        assert_eq!(config.pair(), ("ETH".to_string(), "EUR".to_string()));
+        assert_eq!(
+            OrderBookConfig::new("coinbase").pair(),
+            ("BTC".to_string(), DEFAULT_QUOTE.to_string())
+        );
+        let url = |asset: &str, base: &str, quote: &str| {
+            KrakenOrderBook::new(client.clone(), asset, base, quote).url
+        };
+        assert!(url("BTC", "BTC", "USD").ends_with("?pair=XBTUSD"));
+        assert!(url("ETHEUR", "ETH", "EUR").ends_with("?pair=ETHEUR"));
+        assert!(
+            CoinbaseOrderBook::new(client.clone(), "ETHEUR", "ETH", "EUR")
+                .url
+                .ends_with("/ETH-EUR/book?level=2")
+        );
+
+        // Snapshots are sealed by the block hash and stored with the block
+        let mut block = crate::testing::TestChainBuilder::new()
+            .with_blocks(1)
+            .build()
+            .remove(0);
+        let (bare_hash, bare_id) = (block.hash.clone(), block.content_id());
+        block.order_books.push(kraken.fetch_depth(2).await.unwrap());
+        block.calculate_hash_with_nonce();
+        assert_ne!(block.hash, bare_hash);
+        assert_ne!(block.content_id(), bare_id);
+
+        let db = crate::etl::load::DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        db.save_block(&block).unwrap();
+        let stored = db.get_block_by_index(block.index).unwrap();
+        assert_eq!(stored.order_books, block.order_books);
+        assert_eq!(stored.calculate_hash(), block.hash);
+    }
+}
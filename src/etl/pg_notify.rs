@@ -0,0 +1,389 @@
+//! Commit events over Postgres LISTEN/NOTIFY
+//!
+//! Services that already hold a Postgres connection can react to new ledger
+//! entries with `LISTEN ledger_commits` instead of polling the node. The
+//! publisher subscribes to the ledger's commits
+//! (`DatabaseManager::subscribe_commits`) and sends one
+//! `pg_notify(channel, payload)` per block as it is saved, in the order
+//! saved. A block that replaces one at an index already announced, e.g.
+//! after a sync rewrote the chain, is announced again; listeners keep the
+//! latest hash per index. On a connection error the publisher reconnects and
+//! resends the block that failed, so a listener may see a block twice but
+//! never misses one while the node runs.
+//!
+//! The payload is a `CommitEvent` as JSON. Postgres caps payloads at 8000
+//! bytes, so a block with too many entries is announced without them
+//! (`entries_omitted`) and listeners fetch it from the node.
+//!
+//! Configured with `POSTGRES_NOTIFY_URL` (a `tokio-postgres` connection
+//! string), `POSTGRES_NOTIFY_CHANNEL` (default `ledger_commits`) and
+//! `POSTGRES_NOTIFY_TLS` (`disable`, the default, or `require`, which
+//! verifies the server certificate against the system roots); needs the
+//! `postgres` feature, and the configuration is refused without it.
+
+use crate::etl::price::{self, Decimal};
+use crate::etl::Block;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub const DEFAULT_CHANNEL: &str = "ledger_commits";
+
+/// Largest payload Postgres accepts in a notification
+const MAX_PAYLOAD_BYTES: usize = 7999;
+
+/// First wait before reconnecting; doubled up to `MAX_BACKOFF`
+#[cfg(feature = "postgres")]
+const INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Longest wait between reconnection attempts
+#[cfg(feature = "postgres")]
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Whether the connection to Postgres is encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotifyTls {
+    #[default]
+    Disable,
+    /// TLS with the server certificate verified
+    Require,
+}
+
+impl std::str::FromStr for NotifyTls {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "disable" => Ok(NotifyTls::Disable),
+            "require" => Ok(NotifyTls::Require),
+            other => Err(format!("'{}' (expected disable or require)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyConfig {
+    pub url: String,
+    pub channel: String,
+    pub tls: NotifyTls,
+}
+
+impl NotifyConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        NotifyConfig {
+            url: url.into(),
+            channel: DEFAULT_CHANNEL.to_string(),
+            tls: NotifyTls::Disable,
+        }
+    }
+
+    pub fn with_tls(mut self, tls: NotifyTls) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// `None` unless `POSTGRES_NOTIFY_URL` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(url) = std::env::var("POSTGRES_NOTIFY_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let mut config = Self::new(url);
+        if let Ok(channel) = std::env::var("POSTGRES_NOTIFY_CHANNEL") {
+            config.channel = parse_channel(&channel)
+                .map_err(|e| format!("invalid POSTGRES_NOTIFY_CHANNEL: {}", e))?;
+        }
+        if let Ok(tls) = std::env::var("POSTGRES_NOTIFY_TLS") {
+            config.tls = tls
+                .parse()
+                .map_err(|e| format!("invalid POSTGRES_NOTIFY_TLS: {}", e))?;
+        }
+        config
+            .validate()
+            .map_err(|e| format!("invalid POSTGRES_NOTIFY_URL: {}", e))?;
+        Ok(Some(config))
+    }
+
+    /// Check that the URL parses, and that this build can publish at all
+    pub fn validate(&self) -> Result<(), String> {
+        postgres::validate(self)
+    }
+}
+
+/// A channel name listeners can `LISTEN` to without quoting: a lower-case
+/// identifier of at most 63 bytes
+fn parse_channel(channel: &str) -> Result<String, String> {
+    let channel = channel.trim();
+    let valid = !channel.is_empty()
+        && channel.len() <= 63
+        && !channel.starts_with(|c: char| c.is_ascii_digit())
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(channel.to_string())
+    } else {
+        Err(format!(
+            "'{}' is not a lower-case identifier of at most 63 characters",
+            channel
+        ))
+    }
+}
+
+/// One committed entry in a `CommitEvent`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommittedEntry {
+    pub asset: String,
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
+    pub source: String,
+    /// Milliseconds
+    pub timestamp: i64,
+}
+
+/// Notification payload for one committed block
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommitEvent {
+    pub index: u64,
+    pub hash: String,
+    pub previous_hash: String,
+    /// Milliseconds
+    pub timestamp: i64,
+    pub entry_count: usize,
+    /// Empty when `entries_omitted`
+    pub entries: Vec<CommittedEntry>,
+    /// The entries did not fit in a notification
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub entries_omitted: bool,
+}
+
+impl CommitEvent {
+    pub fn new(block: &Block) -> Self {
+        CommitEvent {
+            index: block.index,
+            hash: block.hash.clone(),
+            previous_hash: block.previous_hash.clone(),
+            timestamp: block.timestamp,
+            entry_count: block.data.len(),
+            entries: block
+                .data
+                .iter()
+                .map(|item| CommittedEntry {
+                    asset: item.asset.clone(),
+                    price: item.price,
+                    source: item.source.clone(),
+                    timestamp: item.timestamp,
+                })
+                .collect(),
+            entries_omitted: false,
+        }
+    }
+
+    /// JSON for `pg_notify`, without the entries when they would exceed
+    /// the payload limit
+    pub fn payload(mut self) -> String {
+        let json = serde_json::to_string(&self).unwrap_or_default();
+        if json.len() <= MAX_PAYLOAD_BYTES {
+            return json;
+        }
+        self.entries.clear();
+        self.entries_omitted = true;
+        serde_json::to_string(&self).unwrap_or_default()
+    }
+}
+
+/// Publish a notification for every block `commits` delivers until the
+/// ledger is dropped; returns early only when the publisher cannot run
+pub async fn publish(
+    commits: UnboundedReceiver<Block>,
+    config: NotifyConfig,
+) -> Result<(), String> {
+    postgres::publish(commits, config).await
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use super::{CommitEvent, NotifyConfig, NotifyTls, INITIAL_BACKOFF, MAX_BACKOFF};
+    use crate::etl::Block;
+    use postgres_native_tls::MakeTlsConnector;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio_postgres::config::SslMode;
+    use tokio_postgres::{Client, Config, NoTls};
+    use tracing::{debug, info, warn};
+
+    pub fn validate(config: &NotifyConfig) -> Result<(), String> {
+        config
+            .url
+            .parse::<Config>()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn connect(config: &NotifyConfig) -> Result<Client, String> {
+        let mut pg = config.url.parse::<Config>().map_err(|e| e.to_string())?;
+        let client = match config.tls {
+            NotifyTls::Disable => {
+                let (client, connection) = pg.connect(NoTls).await.map_err(|e| e.to_string())?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        debug!(error = %e, "Notify: Postgres connection closed");
+                    }
+                });
+                client
+            }
+            NotifyTls::Require => {
+                let connector = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+                pg.ssl_mode(SslMode::Require);
+                let (client, connection) = pg
+                    .connect(MakeTlsConnector::new(connector))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        debug!(error = %e, "Notify: Postgres connection closed");
+                    }
+                });
+                client
+            }
+        };
+        Ok(client)
+    }
+
+    pub async fn publish(
+        mut commits: UnboundedReceiver<Block>,
+        config: NotifyConfig,
+    ) -> Result<(), String> {
+        validate(&config)?;
+        // Received but not yet sent, resent after reconnecting
+        let mut unsent: Option<Block> = None;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let client = match connect(&config).await {
+                Ok(client) => {
+                    info!(channel = %config.channel, tls = ?config.tls, "Notify: Publishing commits to Postgres");
+                    backoff = INITIAL_BACKOFF;
+                    client
+                }
+                Err(e) => {
+                    warn!(error = %e, retry_in = ?backoff, "Notify: Cannot connect to Postgres");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+            loop {
+                let block = match unsent.take() {
+                    Some(block) => block,
+                    None => match commits.recv().await {
+                        Some(block) => block,
+                        None => return Ok(()),
+                    },
+                };
+                let payload = CommitEvent::new(&block).payload();
+                match client
+                    .execute("SELECT pg_notify($1, $2)", &[&config.channel, &payload])
+                    .await
+                {
+                    Ok(_) => debug!(block_index = block.index, "Notify: Commit published"),
+                    Err(e) => {
+                        warn!(block_index = block.index, error = %e, "Notify: Publishing failed, reconnecting");
+                        unsent = Some(block);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+mod postgres {
+    use super::NotifyConfig;
+    use crate::etl::Block;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    pub fn validate(_config: &NotifyConfig) -> Result<(), String> {
+        Err("commit notifications need a build with the `postgres` feature".to_string())
+    }
+
+    pub async fn publish(
+        _commits: UnboundedReceiver<Block>,
+        config: NotifyConfig,
+    ) -> Result<(), String> {
+        validate(&config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::load::DatabaseManager;
+    use crate::testing::{self, TestChainBuilder};
+
+    #[test]
+    fn test_commit_event_payload_fits_notification() {
+        let entry = |i: usize| {
+            let price = price::parse("64012.37").unwrap();
+            testing::entry(
+                "BTC",
+                &format!("Source{}", i),
+                price,
+                testing::BASE_TIMESTAMP_MS,
+            )
+        };
+        let mut block = testing::block(9, "parent", vec![entry(0)]);
+        let payload: serde_json::Value =
+            serde_json::from_str(&CommitEvent::new(&block).payload()).unwrap();
+        assert_eq!(payload["index"], 9);
+        assert_eq!(payload["entries"][0]["price"], 64012.37);
+        assert!(payload.get("entries_omitted").is_none());
+
+        block.data = (0..200).map(entry).collect();
+        let payload = CommitEvent::new(&block).payload();
+        assert!(payload.len() <= MAX_PAYLOAD_BYTES);
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["entry_count"], 200);
+        assert_eq!(payload["entries_omitted"], true);
+
+        assert_eq!(parse_channel(" ledger_v2 ").unwrap(), "ledger_v2");
+        assert!(parse_channel("Ledger").is_err());
+        assert!(parse_channel("1ledger").is_err());
+        assert!(parse_channel("ledger; DROP TABLE x").is_err());
+
+        assert_eq!("REQUIRE".parse(), Ok(NotifyTls::Require));
+        assert!("verify-full".parse::<NotifyTls>().is_err());
+        let config = NotifyConfig::new("host=localhost user=ledger").with_tls(NotifyTls::Require);
+        if cfg!(feature = "postgres") {
+            assert_eq!(config.validate(), Ok(()));
+            assert!(NotifyConfig::new("postgres://[::1").validate().is_err());
+        } else {
+            assert!(config.validate().unwrap_err().contains("postgres"));
+        }
+    }
+
+    #[test]
+    fn test_commits_are_announced_as_saved() {
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        let mut commits = db.subscribe_commits();
+        let chain = TestChainBuilder::new().with_blocks(2).build();
+        db.save_blocks(&chain).unwrap();
+        assert_eq!(commits.try_recv().unwrap().hash, chain[0].hash);
+        assert_eq!(commits.try_recv().unwrap().hash, chain[1].hash);
+
+        // A replacement at an announced index is announced again
+        let mut replacement = chain[1].clone();
+        replacement.timestamp += 1;
+        replacement.calculate_hash_with_nonce();
+        assert_ne!(replacement.hash, chain[1].hash);
+        db.delete_block(replacement.index).unwrap();
+        db.save_block(&replacement).unwrap();
+        let event = CommitEvent::new(&commits.try_recv().unwrap());
+        assert_eq!(
+            (event.index, event.hash),
+            (chain[1].index, replacement.hash)
+        );
+        assert!(commits.try_recv().is_err());
+    }
+}
@@ -0,0 +1,851 @@
+//! Composable transform stages
+//!
+//! A `Pipeline` runs a quote through an ordered list of `TransformStage`s,
+//! each of which may rewrite the record, reject it with an error, or mark it
+//! as a duplicate. `Transformer::pipeline` returns the standard one:
+//!
+//! ```text
+//! sanitize → validate → dedupe → normalize
+//! ```
+//!
+//! with `consolidate::ConsolidateStage` after `sanitize` when
+//! `CONSOLIDATION_METHOD` is set, `conversion::ConversionStage` after
+//! `dedupe` when `CONVERSION_CURRENCY` is, `anomaly::AnomalyStage` and then
+//! `bucket::BucketStage` before `normalize` when `ANOMALY_DETECTION` and
+//! `TIME_BUCKET_SECS` are, and `indicators::IndicatorStage` at the end when
+//! `INDICATOR_WINDOW` is.
+//!
+//! `dedupe` compares a quote's timestamp with the last block's by default;
+//! with `DEDUP_STRATEGY=content` it compares the hash of its asset, price and
+//! source with the quotes let through recently instead, so a new price
+//! arriving quickly is kept.
+//!
+//! Further stages, e.g. enrichment after `normalize` or an extra check ahead
+//! of `dedupe`, are added by implementing `TransformStage` and inserting it
+//! by name:
+//!
+//! ```ignore
+//! let mut pipeline = transformer.pipeline().with_stage(Enrich);
+//! pipeline.insert_before("dedupe", RejectSource("Stale"))?;
+//! let record = pipeline.run("BTC", Decimal::from(50_000), now_millis(), "CoinGecko".into(), None)?;
+//! ```
+//!
+//! Stages record what they change in the record's `provenance`, so an entry
+//! built from the result can still be audited with `Provenance::verify`. A
+//! pipeline with a `transform_stats::TransformTracker` also counts what
+//! became of each record it runs.
+
+use crate::etl::extract::ExtractResult;
+use crate::etl::price::{self, Decimal};
+use crate::etl::provenance::{CustodyStep, Provenance};
+use crate::etl::sanitizer::{Field, Sanitizers};
+use crate::etl::transform::TransformResult;
+use crate::etl::transform_stats::TransformTracker;
+use crate::etl::validator::{Candidate, Validator};
+use crate::etl::Block;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// What a stage knows besides the record itself
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageContext {
+    /// `Block::ordering_timestamp` wall time of the last block carrying the
+    /// record's asset (see `AssetTimestamps`)
+    pub last_timestamp: Option<i64>,
+}
+
+/// `Block::ordering_timestamp` wall time of the last block carrying each
+/// asset, so the dedupe window of one asset is not reset by blocks that only
+/// carry others
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssetTimestamps {
+    last: HashMap<String, i64>,
+}
+
+impl AssetTimestamps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note `block` as the last one for every asset it carries
+    pub fn record_block(&mut self, block: &Block) {
+        let at = block.ordering_timestamp().wall_ms;
+        for entry in &block.data {
+            self.last.insert(entry.asset.to_ascii_uppercase(), at);
+        }
+    }
+
+    /// `None` until a block carrying `asset` was recorded
+    pub fn get(&self, asset: &str) -> Option<i64> {
+        self.last.get(&asset.to_ascii_uppercase()).copied()
+    }
+}
+
+/// One step of a `Pipeline`
+pub trait TransformStage: Send + Sync {
+    /// Unique within a pipeline; used to insert stages around it
+    fn name(&self) -> &str;
+
+    /// Rewrite `record`, or fail to reject it
+    fn apply(
+        &self,
+        record: &mut TransformResult,
+        context: &StageContext,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Ordered transform stages
+///
+/// The first error rejects the record. Once a stage marks the record as a
+/// duplicate, or holds it back (`is_buffered`), the remaining stages are
+/// skipped and it is returned as is.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn TransformStage>>,
+    tracker: Option<Arc<TransformTracker>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `stage`
+    pub fn with_stage(mut self, stage: impl TransformStage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Count every record run, rejected or not, in `tracker`
+    pub fn with_tracker(mut self, tracker: Arc<TransformTracker>) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    /// Insert `stage` ahead of the stage called `name`
+    pub fn insert_before(
+        &mut self,
+        name: &str,
+        stage: impl TransformStage + 'static,
+    ) -> Result<(), String> {
+        let position = self.position(name)?;
+        self.stages.insert(position, Box::new(stage));
+        Ok(())
+    }
+
+    /// Insert `stage` right after the stage called `name`
+    pub fn insert_after(
+        &mut self,
+        name: &str,
+        stage: impl TransformStage + 'static,
+    ) -> Result<(), String> {
+        let position = self.position(name)?;
+        self.stages.insert(position + 1, Box::new(stage));
+        Ok(())
+    }
+
+    /// Drop the stage called `name`; returns whether there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.stages.len();
+        self.stages.retain(|stage| stage.name() != name);
+        self.stages.len() != before
+    }
+
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Run a quote extracted from `source` through every stage
+    pub fn run(
+        &self,
+        asset: &str,
+        price: Decimal,
+        timestamp: i64,
+        source: String,
+        last_timestamp: Option<i64>,
+    ) -> Result<TransformResult, Box<dyn Error>> {
+        let mut record = TransformResult::raw(asset, price, source, timestamp);
+        self.apply(&mut record, &StageContext { last_timestamp })?;
+        Ok(record)
+    }
+
+    /// `run` for an extracted quote, keeping the volume and source quotes
+    /// it came with
+    pub fn run_extracted(
+        &self,
+        extracted: &ExtractResult,
+        last_timestamp: Option<i64>,
+    ) -> Result<TransformResult, Box<dyn Error>> {
+        let mut record = TransformResult::raw(
+            &extracted.asset,
+            extracted.price,
+            extracted.source.clone(),
+            extracted.timestamp,
+        );
+        record.volume = extracted.volume;
+        record.quotes = extracted.quotes.clone();
+        self.apply(&mut record, &StageContext { last_timestamp })?;
+        Ok(record)
+    }
+
+    /// Run an existing record through every stage
+    pub fn apply(
+        &self,
+        record: &mut TransformResult,
+        context: &StageContext,
+    ) -> Result<(), Box<dyn Error>> {
+        let outcome = self.apply_stages(record, context);
+        if let Some(tracker) = &self.tracker {
+            tracker.record(record, outcome.as_ref().map(|_| ()).map_err(|e| e.as_ref()));
+        }
+        outcome
+    }
+
+    fn apply_stages(
+        &self,
+        record: &mut TransformResult,
+        context: &StageContext,
+    ) -> Result<(), Box<dyn Error>> {
+        for stage in &self.stages {
+            stage.apply(record, context)?;
+            if record.is_deduplicated || record.is_buffered {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, String> {
+        self.stages
+            .iter()
+            .position(|stage| stage.name() == name)
+            .ok_or_else(|| format!("no transform stage named '{}'", name))
+    }
+}
+
+/// Runs `Sanitizers` over the asset, source and price
+#[derive(Clone, Default)]
+pub struct SanitizeStage {
+    sanitizers: Sanitizers,
+}
+
+impl SanitizeStage {
+    pub fn new(sanitizers: Sanitizers) -> Self {
+        SanitizeStage { sanitizers }
+    }
+}
+
+impl TransformStage for SanitizeStage {
+    fn name(&self) -> &str {
+        "sanitize"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        let applied = record.sanitized.modifications.len();
+        record.asset =
+            self.sanitizers
+                .sanitize_text(Field::Asset, &record.asset, &mut record.sanitized);
+        record.source =
+            self.sanitizers
+                .sanitize_text(Field::Source, &record.source, &mut record.sanitized);
+        record.price = self
+            .sanitizers
+            .sanitize_price(record.price, &mut record.sanitized);
+        record.provenance.steps.extend(
+            record.sanitized.modifications[applied..]
+                .iter()
+                .cloned()
+                .map(CustodyStep::Sanitized),
+        );
+        Ok(())
+    }
+}
+
+/// Checks the record against a `Validator` and stamps its version on the
+/// record's provenance
+#[derive(Debug, Clone)]
+pub struct ValidateStage {
+    validator: Validator,
+}
+
+impl ValidateStage {
+    pub fn new(validator: Validator) -> Self {
+        ValidateStage { validator }
+    }
+
+    pub fn validator(&self) -> &Validator {
+        &self.validator
+    }
+}
+
+impl TransformStage for ValidateStage {
+    fn name(&self) -> &str {
+        "validate"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        self.validator.validate(&Candidate {
+            asset: &record.asset,
+            price: record.price,
+            timestamp: record.timestamp,
+            source: &record.source,
+        })?;
+        record.provenance.validator = self.validator.version();
+        Ok(())
+    }
+}
+
+/// How `DedupeStage` tells a duplicate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupStrategy {
+    /// Any quote within the window of the last block, whatever its price
+    #[default]
+    Timestamp,
+    /// A quote with the same asset, price and source as one let through
+    /// within the window; a different price is never a duplicate
+    ContentHash,
+}
+
+impl DedupStrategy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "timestamp" => Ok(DedupStrategy::Timestamp),
+            "content" | "content-hash" => Ok(DedupStrategy::ContentHash),
+            other => Err(format!(
+                "unknown deduplication strategy '{}' (expected timestamp or content)",
+                other
+            )),
+        }
+    }
+
+    /// `None` unless `DEDUP_STRATEGY` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        std::env::var("DEDUP_STRATEGY")
+            .ok()
+            .map(|value| Self::parse(&value).map_err(|e| format!("invalid DEDUP_STRATEGY: {}", e)))
+            .transpose()
+    }
+}
+
+impl fmt::Display for DedupStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DedupStrategy::Timestamp => "timestamp",
+            DedupStrategy::ContentHash => "content",
+        })
+    }
+}
+
+/// SHA-256 of a quote's asset, price and source
+fn content_hash(record: &TransformResult) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(record.asset.as_bytes());
+    hasher.update([0]);
+    hasher.update(price::canonical_bytes(record.price));
+    hasher.update(record.source.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Marks a record as a duplicate when it falls within its asset's window:
+/// of the last block, or of an identical quote with
+/// `DedupStrategy::ContentHash`
+#[derive(Debug, Clone)]
+pub struct DedupeStage {
+    window_seconds: i64,
+    /// Windows replacing `window_seconds` for some assets
+    asset_windows: BTreeMap<String, i64>,
+    strategy: DedupStrategy,
+    /// Content hashes of the quotes let through and when they were quoted;
+    /// shared by clones
+    seen: Arc<Mutex<HashMap<[u8; 32], i64>>>,
+}
+
+impl DedupeStage {
+    pub fn new(window_seconds: i64) -> Self {
+        DedupeStage {
+            window_seconds,
+            asset_windows: BTreeMap::new(),
+            strategy: DedupStrategy::default(),
+            seen: Arc::default(),
+        }
+    }
+
+    pub fn with_asset_window(mut self, asset: &str, seconds: i64) -> Self {
+        self.asset_windows
+            .insert(asset.to_ascii_uppercase(), seconds);
+        self
+    }
+
+    /// Window for assets without one of their own, keeping theirs
+    pub fn with_window_seconds(mut self, seconds: i64) -> Self {
+        self.window_seconds = seconds;
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: DedupStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn window_seconds(&self) -> i64 {
+        self.window_seconds
+    }
+
+    /// Window applied to quotes of `asset`
+    pub fn window_for(&self, asset: &str) -> i64 {
+        self.asset_windows
+            .get(&asset.to_ascii_uppercase())
+            .copied()
+            .unwrap_or(self.window_seconds)
+    }
+
+    /// Longest window of any asset
+    pub fn longest_window(&self) -> i64 {
+        self.asset_windows
+            .values()
+            .copied()
+            .fold(self.window_seconds, i64::max)
+    }
+
+    pub fn strategy(&self) -> DedupStrategy {
+        self.strategy
+    }
+
+    /// Whether an identical quote was let through within the window, the
+    /// record's hash being remembered if not; hashes that slid out of every
+    /// window are forgotten
+    fn seen_within_window(&self, record: &TransformResult) -> bool {
+        let longest = self.longest_window() * 1000;
+        let window = self.window_for(&record.asset) * 1000;
+        let mut seen = self.seen.lock();
+        seen.retain(|_, at| (record.timestamp - *at).abs() < longest);
+        let hash = content_hash(record);
+        match seen.get(&hash) {
+            Some(at) if (record.timestamp - at).abs() < window => true,
+            _ => {
+                seen.insert(hash, record.timestamp);
+                false
+            }
+        }
+    }
+}
+
+impl TransformStage for DedupeStage {
+    fn name(&self) -> &str {
+        "dedupe"
+    }
+
+    fn apply(
+        &self,
+        record: &mut TransformResult,
+        context: &StageContext,
+    ) -> Result<(), Box<dyn Error>> {
+        match self.strategy {
+            DedupStrategy::Timestamp => {
+                if let Some(last_ts) = context.last_timestamp {
+                    record.is_deduplicated =
+                        (record.timestamp - last_ts).abs() < self.window_for(&record.asset) * 1000;
+                }
+            }
+            DedupStrategy::ContentHash => {
+                record.is_deduplicated = self.seen_within_window(record);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decimal places prices are rounded to unless configured per asset
+pub const DEFAULT_DECIMALS: u32 = 2;
+
+/// Most decimal places a price can be normalized to
+pub const MAX_DECIMALS: u32 = 8;
+
+/// How `NormalizeStage` settles a price
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Half away from zero to this many decimal places
+    Round(u32),
+    /// Half to even to this many decimal places, so ties do not drift up
+    Bankers(u32),
+    /// Nearest multiple of the tick size, half away from zero
+    Tick(Decimal),
+    /// The price as it comes
+    None,
+}
+
+impl Normalization {
+    /// `round:N`, `bankers:N`, `tick:SIZE` or `none`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim().to_ascii_lowercase();
+        let (method, value) = spec.split_once(':').unwrap_or((spec.as_str(), ""));
+        let places = || {
+            value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|places| *places <= MAX_DECIMALS)
+                .ok_or_else(|| {
+                    format!(
+                        "{} needs 0 to {} places, not '{}'",
+                        method, MAX_DECIMALS, value
+                    )
+                })
+        };
+        match method.trim() {
+            "round" => Ok(Normalization::Round(places()?)),
+            "bankers" => Ok(Normalization::Bankers(places()?)),
+            "tick" => price::parse(value)
+                .ok()
+                .filter(|tick| *tick > Decimal::ZERO)
+                .map(Normalization::Tick)
+                .ok_or_else(|| format!("tick needs a positive size, not '{}'", value)),
+            "none" if value.is_empty() => Ok(Normalization::None),
+            _ => Err(format!(
+                "'{}' is not round:N, bankers:N, tick:SIZE or none",
+                spec
+            )),
+        }
+    }
+
+    /// `None` unless `NORMALIZATION` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("NORMALIZATION") {
+            Ok(spec) => Self::parse(&spec)
+                .map(Some)
+                .map_err(|e| format!("invalid NORMALIZATION: {}", e)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// `price` normalized; `None` when snapping to the tick overflows
+    pub fn apply(&self, price: Decimal) -> Option<Decimal> {
+        match self {
+            Normalization::Round(decimals) => Some(price::round(price, *decimals)),
+            Normalization::Bankers(decimals) => Some(price::round_half_even(price, *decimals)),
+            Normalization::Tick(tick) => price::snap_to_tick(price, *tick),
+            Normalization::None => Some(price),
+        }
+    }
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::Round(DEFAULT_DECIMALS)
+    }
+}
+
+/// As recorded in provenance, e.g. `round(2)` or `tick(0.25)`
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Normalization::Round(decimals) => write!(f, "round({})", decimals),
+            Normalization::Bankers(decimals) => write!(f, "bankers({})", decimals),
+            Normalization::Tick(tick) => write!(f, "tick({})", tick.normalize()),
+            Normalization::None => f.write_str("none"),
+        }
+    }
+}
+
+/// Settles the price with its asset's `Normalization`; by default it is
+/// rounded half away from zero to cents
+#[derive(Debug, Clone)]
+pub struct NormalizeStage {
+    normalization: Normalization,
+    /// Normalizations replacing `normalization` for some assets
+    assets: BTreeMap<String, Normalization>,
+}
+
+impl NormalizeStage {
+    pub fn new() -> Self {
+        NormalizeStage {
+            normalization: Normalization::default(),
+            assets: BTreeMap::new(),
+        }
+    }
+
+    /// Normalization of assets without one of their own
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn with_asset_normalization(mut self, asset: &str, normalization: Normalization) -> Self {
+        self.assets
+            .insert(asset.to_ascii_uppercase(), normalization);
+        self
+    }
+
+    /// `Normalization::Round(decimals)` for `asset`
+    pub fn with_asset_decimals(self, asset: &str, decimals: u32) -> Self {
+        self.with_asset_normalization(asset, Normalization::Round(decimals))
+    }
+
+    /// Normalization of quotes of `asset`
+    pub fn normalization_for(&self, asset: &str) -> Normalization {
+        self.assets
+            .get(&asset.to_ascii_uppercase())
+            .copied()
+            .unwrap_or(self.normalization)
+    }
+
+    /// `price` with the default normalization; unchanged if snapping it
+    /// overflows
+    pub fn normalize_price(&self, price: Decimal) -> Decimal {
+        self.normalization.apply(price).unwrap_or(price)
+    }
+}
+
+impl Default for NormalizeStage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransformStage for NormalizeStage {
+    fn name(&self) -> &str {
+        "normalize"
+    }
+
+    fn apply(&self, record: &mut TransformResult, _: &StageContext) -> Result<(), Box<dyn Error>> {
+        let normalization = self.normalization_for(&record.asset);
+        if normalization == Normalization::None {
+            return Ok(());
+        }
+        let before = record.price;
+        record.price = normalization.apply(before).ok_or_else(|| {
+            format!(
+                "{} price {} overflows {}",
+                record.asset, before, normalization
+            )
+        })?;
+        record.provenance.push(CustodyStep::Normalized {
+            method: normalization.to_string(),
+            before,
+            after: record.price,
+        });
+        Ok(())
+    }
+}
+
+impl TransformResult {
+    /// A quote as extracted, before any stage has run
+    pub fn raw(asset: &str, price: Decimal, source: String, timestamp: i64) -> Self {
+        TransformResult {
+            asset: asset.to_string(),
+            price,
+            provenance: Provenance::new(price, source.clone(), ""),
+            source,
+            timestamp,
+            is_deduplicated: false,
+            is_buffered: false,
+            sanitized: Default::default(),
+            volume: None,
+            quotes: Vec::new(),
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::now_millis;
+    use crate::etl::transform::Transformer;
+
+    /// Rejects quotes from one source
+    struct Blocklist(&'static str);
+
+    impl TransformStage for Blocklist {
+        fn name(&self) -> &str {
+            "blocklist"
+        }
+
+        fn apply(
+            &self,
+            record: &mut TransformResult,
+            _: &StageContext,
+        ) -> Result<(), Box<dyn Error>> {
+            if record.source == self.0 {
+                return Err(format!("source '{}' is blocked", self.0).into());
+            }
+            Ok(())
+        }
+    }
+
+    /// Tags the source with the price band
+    struct Enrich;
+
+    impl TransformStage for Enrich {
+        fn name(&self) -> &str {
+            "enrich"
+        }
+
+        fn apply(
+            &self,
+            record: &mut TransformResult,
+            _: &StageContext,
+        ) -> Result<(), Box<dyn Error>> {
+            let band = (record.price / Decimal::from(1000)).trunc();
+            record.source = format!("{}/{}k", record.source, band);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_runs_custom_stages_in_order() {
+        let transformer = Transformer::new().with_sanitizers(Sanitizers::standard());
+        let mut pipeline = transformer.pipeline().with_stage(Enrich);
+        pipeline
+            .insert_before("dedupe", Blocklist("Stale"))
+            .unwrap();
+        assert_eq!(
+            pipeline.stage_names(),
+            vec![
+                "sanitize",
+                "validate",
+                "blocklist",
+                "dedupe",
+                "normalize",
+                "enrich"
+            ]
+        );
+        assert!(pipeline.insert_after("missing", Enrich).is_err());
+
+        let now = now_millis();
+        let record = pipeline
+            .run(
+                " btc ",
+                price::parse("50000.126").unwrap(),
+                now,
+                "CoinGecko".to_string(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(record.asset, "BTC");
+        assert_eq!(record.price, price::parse("50000.13").unwrap());
+        assert_eq!(record.source, "CoinGecko/50k");
+        assert_eq!(record.provenance.validator, Validator::new().version());
+        assert_eq!(
+            record.provenance.step_names().last().map(String::as_str),
+            Some("normalized:round(2)")
+        );
+        assert!(pipeline
+            .run("BTC", Decimal::from(50_000), now, "Stale".to_string(), None)
+            .is_err());
+
+        // A duplicate skips the stages after the one that flagged it
+        let duplicate = pipeline
+            .run(
+                "BTC",
+                price::parse("50000.126").unwrap(),
+                now,
+                "CoinGecko".to_string(),
+                Some(now),
+            )
+            .unwrap();
+        assert!(duplicate.is_deduplicated);
+        assert_eq!(duplicate.source, "CoinGecko");
+
+        assert!(pipeline.remove("blocklist"));
+        assert!(!pipeline.remove("blocklist"));
+        assert!(pipeline
+            .run("BTC", Decimal::from(50_000), now, "Stale".to_string(), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_content_hash_deduplication() {
+        let transformer = Transformer::new()
+            .with_deduplication_strategy(DedupStrategy::ContentHash)
+            .with_deduplication_window(60);
+        assert_eq!(
+            transformer.deduplication_strategy(),
+            DedupStrategy::ContentHash
+        );
+        let pipeline = transformer.pipeline();
+        let now = now_millis();
+        let run = |price: i64, source: &str, timestamp: i64| {
+            pipeline
+                .run(
+                    "BTC",
+                    Decimal::from(price),
+                    timestamp,
+                    source.to_string(),
+                    Some(now),
+                )
+                .unwrap()
+                .is_deduplicated
+        };
+        // Timestamps alone would make every quote after the first a duplicate
+        assert!(!run(50_000, "Kraken", now));
+        assert!(!run(50_010, "Kraken", now + 1_000));
+        assert!(!run(50_000, "Coinbase", now + 2_000));
+        assert!(run(50_000, "Kraken", now + 3_000));
+        // Pipelines built by the same transformer share what was seen
+        assert!(
+            transformer
+                .transform(
+                    Decimal::from(50_010),
+                    now + 4_000,
+                    "Kraken".to_string(),
+                    None
+                )
+                .unwrap()
+                .is_deduplicated
+        );
+        // Once the window slid past it, the same quote counts again
+        assert!(!run(50_000, "Kraken", now + 61_000));
+
+        assert_eq!(
+            DedupStrategy::parse("content").unwrap(),
+            DedupStrategy::ContentHash
+        );
+        assert!(DedupStrategy::parse("price").is_err());
+    }
+
+    #[test]
+    fn test_normalization_strategies() {
+        let price = |p: &str| price::parse(p).unwrap();
+        let normalize = |spec: &str, p: &str| Normalization::parse(spec).unwrap().apply(price(p));
+        assert_eq!(normalize("round:2", "1.005"), Some(price("1.01")));
+        // Ties go to the even digit either way
+        assert_eq!(normalize("bankers:2", "1.005"), Some(price("1")));
+        assert_eq!(normalize("bankers:2", "1.015"), Some(price("1.02")));
+        assert_eq!(normalize("tick:0.25", "100.37"), Some(price("100.25")));
+        assert_eq!(normalize("tick:0.25", "100.375"), Some(price("100.5")));
+        assert_eq!(normalize("none", "1.23456789"), Some(price("1.23456789")));
+        assert!(Normalization::parse("tick:0").is_err());
+        assert!(Normalization::parse("round:12").is_err());
+        assert!(Normalization::parse("floor:2").is_err());
+
+        let transformer = Transformer::new()
+            .with_normalization(Normalization::Bankers(1))
+            .with_asset_normalization("ES", Normalization::Tick(price("0.25")))
+            .with_asset_normalization("ETH", Normalization::None);
+        let pipeline = transformer.pipeline();
+        let now = now_millis();
+        let run = |asset: &str, p: &str| {
+            pipeline
+                .run(asset, price(p), now, "CME".into(), None)
+                .unwrap()
+        };
+        let future = run("ES", "5012.4");
+        assert_eq!(future.price, price("5012.5"));
+        assert_eq!(
+            future.provenance.step_names(),
+            vec!["normalized:tick(0.25)"]
+        );
+        assert_eq!(run("BTC", "50000.25").price, price("50000.2"));
+        // Left as extracted, without a normalization step
+        let eth = run("ETH", "3000.125");
+        assert_eq!(eth.price, price("3000.125"));
+        assert!(eth.provenance.steps.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Decimal prices
+//!
+//! Ledger prices are `rust_decimal::Decimal`: exact to 28 digits, with the
+//! same value on every platform. Sources parse the digits they are sent
+//! with `parse`, so quotes, source quotes and order-book levels are decimal
+//! from extraction on. `from_f32` remains for the few inputs that are still
+//! floats, such as generated workloads; it keeps the shortest digits that
+//! round-trip, so `64012.37f32` becomes exactly `64012.37`.
+//!
+//! In JSON a price is written as a number whenever an `f64` carries it
+//! exactly, which keeps the API and the legacy block hash (serde_json
+//! output) unchanged for every price that came from an `f32`; longer
+//! decimals are written as strings. Both forms are accepted when reading.
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::RoundingStrategy;
+use std::str::FromStr;
+
+pub use rust_decimal::Decimal;
+
+/// `price` with the shortest digits that round-trip to the same `f32`;
+/// `None` for NaN, infinities and magnitudes beyond `Decimal::MAX`
+pub fn from_f32(price: f32) -> Option<Decimal> {
+    if !price.is_finite() {
+        return None;
+    }
+    Decimal::from_str(&price.to_string()).ok()
+}
+
+/// Nearest `f32`; exact for every price that came from `from_f32`
+pub fn to_f32(price: Decimal) -> f32 {
+    price.to_string().parse().unwrap_or(f32::NAN)
+}
+
+/// Nearest `f64`, for statistics and storage backends without a decimal type
+pub fn to_f64(price: Decimal) -> f64 {
+    price.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Parse a decimal price such as `64012.37`, `1e-8` or `-0.5`
+pub fn parse(s: &str) -> Result<Decimal, String> {
+    let s = s.trim();
+    Decimal::from_str(s)
+        .or_else(|_| Decimal::from_scientific(s))
+        .map_err(|_| format!("'{}' is not a decimal price", s))
+}
+
+/// `price` rounded half away from zero to `decimals` places, without
+/// trailing zeros
+pub fn round(price: Decimal, decimals: u32) -> Decimal {
+    price
+        .round_dp_with_strategy(decimals, RoundingStrategy::MidpointAwayFromZero)
+        .normalize()
+}
+
+/// `price` rounded half to even (bankers rounding) to `decimals` places,
+/// without trailing zeros
+pub fn round_half_even(price: Decimal, decimals: u32) -> Decimal {
+    price
+        .round_dp_with_strategy(decimals, RoundingStrategy::MidpointNearestEven)
+        .normalize()
+}
+
+/// Nearest multiple of `tick`, half away from zero; `None` when `tick` is
+/// not positive or the result overflows
+pub fn snap_to_tick(price: Decimal, tick: Decimal) -> Option<Decimal> {
+    if tick <= Decimal::ZERO {
+        return None;
+    }
+    let ticks = price
+        .checked_div(tick)?
+        .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+    Some(ticks.checked_mul(tick)?.normalize())
+}
+
+/// Big-endian mantissa (`i128`) and scale (`u32`) of the normalized price,
+/// so `1.50` and `1.5` encode alike and zero has a single encoding; hash and
+/// signing inputs use it
+pub fn canonical_bytes(price: Decimal) -> [u8; 20] {
+    let price = if price.is_zero() {
+        Decimal::ZERO
+    } else {
+        price.normalize()
+    };
+    let mut bytes = [0u8; 20];
+    bytes[..16].copy_from_slice(&price.mantissa().to_be_bytes());
+    bytes[16..].copy_from_slice(&price.scale().to_be_bytes());
+    bytes
+}
+
+/// Serde adapter writing a price as a JSON number when an `f64` holds it
+/// exactly and as a string otherwise; use with `#[serde(with = ...)]`
+pub mod serde_number {
+    use super::Decimal;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(price: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        let digits = price.normalize().to_string();
+        match digits.parse::<f64>() {
+            Ok(value) if super::from_f64_exact(value) == Some(price.normalize()) => {
+                serializer.serialize_f64(value)
+            }
+            _ => serializer.serialize_str(&digits),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        <Decimal as Deserialize>::deserialize(deserializer)
+    }
+}
+
+/// `serde_number` for an optional price; pair with `#[serde(default)]`
+pub mod serde_number_option {
+    use super::Decimal;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    struct Number(#[serde(with = "super::serde_number")] Decimal);
+
+    pub fn serialize<S: Serializer>(
+        price: &Option<Decimal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        price.map(Number).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Decimal>, D::Error> {
+        <Option<Decimal> as Deserialize>::deserialize(deserializer)
+    }
+}
+
+/// The decimal an `f64` prints as, which is what a JSON reader recovers
+fn from_f64_exact(value: f64) -> Option<Decimal> {
+    Decimal::from_str(&value.to_string())
+        .ok()
+        .map(|d| d.normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Quote {
+        #[serde(with = "serde_number")]
+        price: Decimal,
+    }
+
+    #[test]
+    fn test_f32_round_trip_and_json_form() {
+        for value in [64012.37f32, 0.1, 1e-7, 3.4e10, -0.5, 0.0] {
+            let price = from_f32(value).unwrap();
+            assert_eq!(to_f32(price).to_bits(), value.to_bits(), "{}", value);
+        }
+        assert_eq!(from_f32(64012.37).unwrap().to_string(), "64012.37");
+        assert_eq!(from_f32(f32::NAN), None);
+        assert_eq!(from_f32(f32::MAX), None);
+        assert_eq!(round(parse("2.345").unwrap(), 2), parse("2.35").unwrap());
+        assert_eq!(round(parse("64012.97").unwrap(), 1).to_string(), "64013");
+
+        // f32 prices serialize as the same number serde_json wrote for the
+        // f32, so legacy JSON hash inputs are unchanged
+        let json = serde_json::to_string(&Quote {
+            price: from_f32(64012.37).unwrap(),
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"price":{}}}"#,
+                serde_json::to_string(&64012.37f32).unwrap()
+            )
+        );
+
+        let precise = parse("0.123456789012345678901").unwrap();
+        let json = serde_json::to_string(&Quote { price: precise }).unwrap();
+        assert_eq!(json, r#"{"price":"0.123456789012345678901"}"#);
+        for json in [json.as_str(), r#"{"price":64012.37}"#, r#"{"price":7.0}"#] {
+            let quote: Quote = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&quote).unwrap(), json);
+        }
+    }
+}
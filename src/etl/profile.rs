@@ -0,0 +1,134 @@
+//! Named validation profiles
+//!
+//! A production node wants tight checks: quotes must be fresh, anything
+//! close to the last block is a duplicate and anomalous prices are rejected.
+//! A backfill or demo wants the opposite, replaying quotes that are hours or
+//! days old. A `ValidationProfile` bundles the thresholds that differ between
+//! the two so an operator switches them together: with `VALIDATION_PROFILE`
+//! at start, or at runtime with `POST /admin/reconfigure` and a
+//! `validation_profile` field.
+//!
+//! The ETL loop applies a switch before its next block, and every block
+//! proposed under a profile carries its name in the `validation_profile`
+//! annotation, so an audit can tell which checks the entries passed. The
+//! validator version in each entry's provenance records the drift as well.
+//! Without a profile the individual settings stand.
+
+use crate::etl::anomaly::AnomalyAction;
+
+/// Block annotation naming the profile a block was proposed under
+pub const PROFILE_ANNOTATION: &str = "validation_profile";
+
+/// Thresholds applied together by `Transformer::with_profile`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationProfile {
+    pub name: &'static str,
+    /// Largest distance of a quote's timestamp from the node's clock
+    pub timestamp_drift_seconds: i64,
+    /// Deduplication window of assets without one of their own
+    pub dedup_window_seconds: i64,
+    pub anomaly_threshold: f64,
+    pub anomaly_action: AnomalyAction,
+}
+
+/// Fresh quotes only, one per minute, anomalies rejected
+pub const STRICT: ValidationProfile = ValidationProfile {
+    name: "strict",
+    timestamp_drift_seconds: 300,
+    dedup_window_seconds: 60,
+    anomaly_threshold: 3.5,
+    anomaly_action: AnomalyAction::Reject,
+};
+
+/// Quotes up to 30 days old, no deduplication window, only gross
+/// anomalies flagged
+pub const LENIENT: ValidationProfile = ValidationProfile {
+    name: "lenient",
+    timestamp_drift_seconds: 30 * 86_400,
+    dedup_window_seconds: 0,
+    anomaly_threshold: 10.0,
+    anomaly_action: AnomalyAction::Flag,
+};
+
+impl ValidationProfile {
+    pub const ALL: [ValidationProfile; 2] = [STRICT, LENIENT];
+
+    /// The profile called `name`, ignoring case
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::named(name).ok_or_else(|| {
+            format!(
+                "unknown validation profile '{}' (expected strict or lenient)",
+                name.trim()
+            )
+        })
+    }
+
+    /// `None` unless `VALIDATION_PROFILE` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        std::env::var("VALIDATION_PROFILE")
+            .ok()
+            .map(|name| {
+                Self::parse(&name).map_err(|e| format!("invalid VALIDATION_PROFILE: {}", e))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::now_millis;
+    use crate::etl::price::Decimal;
+    use crate::etl::transform::Transformer;
+    use crate::etl::validator::Validator;
+
+    #[test]
+    fn test_profiles_bundle_thresholds() {
+        assert_eq!(ValidationProfile::named(" Lenient "), Some(LENIENT));
+        assert!(ValidationProfile::parse("paranoid").is_err());
+
+        let now = now_millis();
+        let price = Decimal::from(50_000);
+        let day_old = now - 86_400_000;
+        let transformer = Transformer::new()
+            .with_validator(Validator::new())
+            .with_asset_settings(
+                "EURUSD",
+                crate::etl::transform::AssetSettings {
+                    normalization: None,
+                    dedup_window_seconds: Some(10),
+                },
+            );
+        assert!(transformer
+            .transform(price, day_old, "Kraken".to_string(), None)
+            .is_err());
+
+        let lenient = transformer.with_profile(&LENIENT);
+        let backfilled = lenient
+            .transform(price, day_old, "Kraken".to_string(), Some(day_old))
+            .unwrap();
+        assert!(!backfilled.is_deduplicated);
+        assert!(backfilled.provenance.validator.ends_with("drift=2592000s"));
+        // Per-asset windows stay
+        assert_eq!(lenient.deduplication_window_for("EURUSD"), 10);
+        assert!(lenient.pipeline().stage_names().contains(&"anomaly"));
+
+        let strict = lenient.with_profile(&STRICT);
+        assert_eq!(strict.deduplication_window_seconds(), 60);
+        assert!(strict
+            .transform(price, now - 600_000, "Kraken".to_string(), None)
+            .is_err());
+        assert!(
+            strict
+                .transform(price, now, "Kraken".to_string(), Some(now - 30_000))
+                .unwrap()
+                .is_deduplicated
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Chain of custody for ledger entries
+//!
+//! Each entry the node builds from a market quote carries a `Provenance`:
+//! the quote as extracted, every step the pipeline applied to it in order
+//! (source quotes behind an aggregated price, sanitizer rewrites, weighted
+//! consolidation, currency conversion, time-bucket means, price
+//! normalization) and the version
+//! of the validation rules it passed. An
+//! auditor can replay the steps from the raw quote and arrive at the stored
+//! value, which `Provenance::verify` does.
+//!
+//! Provenance is stored inside the entry, so it is covered by the block hash
+//! and removed along with the payload when a block is redacted. Entries
+//! submitted by tenants and entries written before provenance was recorded
+//! have none.
+
+use crate::etl::consolidate::{self, WeightedQuote};
+use crate::etl::conversion;
+use crate::etl::divergence::SourceQuote;
+use crate::etl::price::{self, Decimal};
+use crate::etl::sanitizer::{Field, Modification};
+use crate::etl::MarketData;
+use serde::{Deserialize, Serialize};
+
+/// One transformation applied to a quote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CustodyStep {
+    /// The raw price was combined from several sources' quotes
+    Aggregated { quotes: Vec<SourceQuote> },
+    /// A sanitizer rewrote a field
+    Sanitized(Modification),
+    /// The price was replaced by the weighted mean of the source quotes
+    Consolidated {
+        method: String,
+        quotes: Vec<WeightedQuote>,
+        #[serde(with = "price::serde_number")]
+        before: Decimal,
+        #[serde(with = "price::serde_number")]
+        after: Decimal,
+    },
+    /// The price was converted into another currency at `rate`
+    Converted {
+        from: String,
+        to: String,
+        #[serde(with = "price::serde_number")]
+        rate: Decimal,
+        #[serde(with = "price::serde_number")]
+        before: Decimal,
+        #[serde(with = "price::serde_number")]
+        after: Decimal,
+    },
+    /// A check let the quote through but marked it, e.g. an anomalous
+    /// price; values are unchanged
+    Flagged { check: String, detail: String },
+    /// The price was replaced by the mean of the `count` quotes in the time
+    /// bucket starting at `start`, summarized in `MarketData::bucket`
+    Bucketed {
+        start: i64,
+        count: u64,
+        #[serde(with = "price::serde_number")]
+        before: Decimal,
+        #[serde(with = "price::serde_number")]
+        after: Decimal,
+    },
+    /// The price was normalized, e.g. rounded to cents
+    Normalized {
+        method: String,
+        #[serde(with = "price::serde_number")]
+        before: Decimal,
+        #[serde(with = "price::serde_number")]
+        after: Decimal,
+    },
+}
+
+/// How an entry's stored values were derived from the extracted quote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Price as extracted, before any step
+    #[serde(with = "price::serde_number")]
+    pub raw_price: Decimal,
+    pub raw_source: String,
+    /// Steps applied, in order
+    pub steps: Vec<CustodyStep>,
+    /// `Validator::version` of the rules the entry passed
+    pub validator: String,
+}
+
+impl Provenance {
+    pub fn new(
+        raw_price: Decimal,
+        raw_source: impl Into<String>,
+        validator: impl Into<String>,
+    ) -> Self {
+        Provenance {
+            raw_price,
+            raw_source: raw_source.into(),
+            steps: Vec::new(),
+            validator: validator.into(),
+        }
+    }
+
+    pub fn push(&mut self, step: CustodyStep) {
+        self.steps.push(step);
+    }
+
+    /// Record the per-source quotes an aggregated raw price was computed
+    /// from, ahead of every other step; no-op for a single-source quote
+    pub fn with_quotes(mut self, quotes: &[SourceQuote]) -> Self {
+        if !quotes.is_empty() {
+            self.steps.insert(
+                0,
+                CustodyStep::Aggregated {
+                    quotes: quotes.to_vec(),
+                },
+            );
+        }
+        self
+    }
+
+    /// Names of the steps applied, e.g. `["sanitized:trim", "normalized:round(2)"]`
+    pub fn step_names(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                CustodyStep::Aggregated { quotes } => format!("aggregated:{}", quotes.len()),
+                CustodyStep::Sanitized(change) => format!("sanitized:{}", change.sanitizer),
+                CustodyStep::Consolidated { method, .. } => format!("consolidated:{}", method),
+                CustodyStep::Converted { from, to, .. } => format!("converted:{}/{}", from, to),
+                CustodyStep::Flagged { check, .. } => format!("flagged:{}", check),
+                CustodyStep::Bucketed { count, .. } => format!("bucketed:{}", count),
+                CustodyStep::Normalized { method, .. } => format!("normalized:{}", method),
+            })
+            .collect()
+    }
+
+    /// Check that replaying the steps from the raw quote yields `entry`'s
+    /// price and source, and that each step starts from the previous one's
+    /// result
+    pub fn verify(&self, entry: &MarketData) -> Result<(), String> {
+        let mut price = self.raw_price;
+        let mut source = self.raw_source.clone();
+        for (i, step) in self.steps.iter().enumerate() {
+            match step {
+                CustodyStep::Aggregated { quotes } if quotes.is_empty() => {
+                    return Err(format!("step {}: aggregation without quotes", i));
+                }
+                CustodyStep::Aggregated { .. } => {}
+                CustodyStep::Sanitized(change) => {
+                    let (current, chained) = match change.field {
+                        // Prices are compared by value, so `1.50` follows `1.5`
+                        Field::Price => (
+                            price.to_string(),
+                            price::parse(&change.before).is_ok_and(|before| before == price),
+                        ),
+                        Field::Source => (source.clone(), change.before == source),
+                        // The asset is not extracted, so there is no raw
+                        // value to chain from
+                        Field::Asset => (change.before.clone(), true),
+                    };
+                    if !chained {
+                        return Err(format!(
+                            "step {}: {} starts from '{}' but the value was '{}'",
+                            i, change.sanitizer, change.before, current
+                        ));
+                    }
+                    match change.field {
+                        Field::Price => {
+                            price = price::parse(&change.after)
+                                .map_err(|e| format!("step {}: {}", i, e))?
+                        }
+                        Field::Source => source = change.after.clone(),
+                        Field::Asset => {}
+                    }
+                }
+                CustodyStep::Consolidated {
+                    quotes,
+                    before,
+                    after,
+                    ..
+                } => {
+                    if *before != price {
+                        return Err(format!(
+                            "step {}: consolidation starts from {} but the price was {}",
+                            i, before, price
+                        ));
+                    }
+                    if consolidate::weighted_price(quotes) != Some(*after) {
+                        return Err(format!(
+                            "step {}: the weighted quotes do not yield {}",
+                            i, after
+                        ));
+                    }
+                    price = *after;
+                }
+                CustodyStep::Converted {
+                    rate,
+                    before,
+                    after,
+                    ..
+                } => {
+                    if *before != price {
+                        return Err(format!(
+                            "step {}: conversion starts from {} but the price was {}",
+                            i, before, price
+                        ));
+                    }
+                    if conversion::convert(*before, *rate) != Some(*after) {
+                        return Err(format!(
+                            "step {}: {} at rate {} does not yield {}",
+                            i, before, rate, after
+                        ));
+                    }
+                    price = *after;
+                }
+                CustodyStep::Flagged { .. } => {}
+                CustodyStep::Bucketed {
+                    start,
+                    count,
+                    before,
+                    after,
+                } => {
+                    if *before != price {
+                        return Err(format!(
+                            "step {}: bucketing starts from {} but the price was {}",
+                            i, before, price
+                        ));
+                    }
+                    let summarized = entry.bucket.as_ref().is_some_and(|bucket| {
+                        (bucket.start, bucket.count, bucket.mean) == (*start, *count, *after)
+                            && bucket.low <= *after
+                            && *after <= bucket.high
+                    });
+                    if !summarized {
+                        return Err(format!(
+                            "step {}: the entry's bucket does not average to {}",
+                            i, after
+                        ));
+                    }
+                    price = *after;
+                }
+                CustodyStep::Normalized { before, after, .. } => {
+                    if *before != price {
+                        return Err(format!(
+                            "step {}: normalization starts from {} but the price was {}",
+                            i, before, price
+                        ));
+                    }
+                    price = *after;
+                }
+            }
+        }
+        if price != entry.price {
+            return Err(format!(
+                "steps yield price {} but the entry stores {}",
+                price, entry.price
+            ));
+        }
+        if source != entry.source {
+            return Err(format!(
+                "steps yield source '{}' but the entry stores '{}'",
+                source, entry.source
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::sanitizer::Sanitizers;
+    use crate::etl::transform::Transformer;
+
+    #[test]
+    fn test_provenance_replays_to_stored_entry() {
+        let transformer =
+            Transformer::new().with_sanitizers(Sanitizers::standard().with_precision(1));
+        let result = transformer
+            .transform(
+                price::parse("64012.37").unwrap(),
+                crate::etl::now_millis(),
+                " Kraken ".into(),
+                None,
+            )
+            .unwrap();
+        let (price, provenance) = transformer.normalize(&result);
+        let mut entry = MarketData {
+            asset: result.asset,
+            price,
+            source: result.source,
+            timestamp: result.timestamp,
+            provenance: Some(provenance.clone()),
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        };
+
+        assert_eq!(provenance.raw_price, price::parse("64012.37").unwrap());
+        assert_eq!(
+            provenance.step_names(),
+            vec![
+                "sanitized:trim",
+                "sanitized:precision(1)",
+                "normalized:round(2)"
+            ]
+        );
+        assert!(provenance.validator.starts_with("v1:"));
+        assert_eq!(provenance.verify(&entry), Ok(()));
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains(r#""step":"sanitized""#), "{}", json);
+        let decoded: MarketData = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.provenance, entry.provenance);
+
+        entry.price += Decimal::ONE;
+        assert!(provenance
+            .verify(&entry)
+            .unwrap_err()
+            .contains("yield price"));
+    }
+
+    #[test]
+    fn test_provenance_is_sealed_by_block_hash() {
+        let entry = MarketData {
+            asset: "BTC".to_string(),
+            price: Decimal::from(64_005),
+            source: "Aggregate(Kraken,Coinbase)".to_string(),
+            timestamp: 1_700_000_000_000,
+            provenance: None,
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        };
+        let mut block = crate::etl::Block {
+            index: 1,
+            timestamp: 1_700_000_000_000,
+            data: vec![entry.clone()],
+            previous_hash: "0".to_string(),
+            hash: String::new(),
+            nonce: 0,
+            format_version: crate::etl::BLOCK_FORMAT_VERSION,
+            fees: Vec::new(),
+            divergences: Vec::new(),
+            hlc: None,
+            annotations: Default::default(),
+            order_books: Vec::new(),
+        };
+        let bare_hash = block.calculate_hash();
+        let bare_id = block.content_id();
+
+        let quotes = [
+            SourceQuote {
+                source: "Kraken".to_string(),
+                price: Decimal::from(64_000),
+                timestamp: 1_700_000_000_000,
+                volume: None,
+            },
+            SourceQuote {
+                source: "Coinbase".to_string(),
+                price: Decimal::from(64_010),
+                timestamp: 1_700_000_000_000,
+                volume: None,
+            },
+        ];
+        let provenance =
+            Provenance::new(entry.price, &entry.source, "v1:test").with_quotes(&quotes);
+        assert_eq!(provenance.step_names(), vec!["aggregated:2"]);
+        assert_eq!(provenance.verify(&entry), Ok(()));
+        block.data[0].provenance = Some(provenance);
+
+        // Editing the recorded chain of custody breaks the seal and changes
+        // the content replicas vote on
+        assert_ne!(block.calculate_hash(), bare_hash);
+        assert_ne!(block.content_id(), bare_id);
+    }
+}
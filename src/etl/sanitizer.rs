@@ -0,0 +1,249 @@
+//! Per-field sanitizers run by the `Transformer` before validation
+//!
+//! Sanitizers rewrite a value into its canonical form (trimmed, upper-cased,
+//! a known source spelling, a fixed number of decimals) rather than rejecting
+//! it. Every change is recorded in a `SanitizeReport` so callers can see what
+//! the pipeline altered.
+
+use crate::etl::price::{self, Decimal};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Field a sanitizer is registered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Asset,
+    Source,
+    Price,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Asset => "asset",
+            Field::Source => "source",
+            Field::Price => "price",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+type TextFn = Arc<dyn Fn(&str) -> String + Send + Sync>;
+type PriceFn = Arc<dyn Fn(Decimal) -> Decimal + Send + Sync>;
+
+/// One value a sanitizer changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Modification {
+    pub field: Field,
+    pub sanitizer: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// Everything sanitizers changed in one record
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SanitizeReport {
+    pub modifications: Vec<Modification>,
+}
+
+impl SanitizeReport {
+    pub fn is_empty(&self) -> bool {
+        self.modifications.is_empty()
+    }
+
+    /// Whether any sanitizer changed `field`
+    pub fn modified(&self, field: Field) -> bool {
+        self.modifications.iter().any(|m| m.field == field)
+    }
+}
+
+/// Ordered sanitizers per field; an empty set changes nothing
+#[derive(Clone, Default)]
+pub struct Sanitizers {
+    text: Vec<(Field, String, TextFn)>,
+    price: Vec<(String, PriceFn)>,
+}
+
+impl Sanitizers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trim and upper-case asset symbols, trim source names
+    pub fn standard() -> Self {
+        Self::new()
+            .with_trim(Field::Asset)
+            .with_uppercase(Field::Asset)
+            .with_trim(Field::Source)
+    }
+
+    /// Register a custom sanitizer for a text field (`Asset` or `Source`)
+    pub fn with_text(
+        mut self,
+        field: Field,
+        name: impl Into<String>,
+        sanitize: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.text.push((field, name.into(), Arc::new(sanitize)));
+        self
+    }
+
+    /// Register a custom sanitizer for the price
+    pub fn with_price(
+        mut self,
+        name: impl Into<String>,
+        sanitize: impl Fn(Decimal) -> Decimal + Send + Sync + 'static,
+    ) -> Self {
+        self.price.push((name.into(), Arc::new(sanitize)));
+        self
+    }
+
+    pub fn with_trim(self, field: Field) -> Self {
+        self.with_text(field, "trim", |s| s.trim().to_string())
+    }
+
+    pub fn with_uppercase(self, field: Field) -> Self {
+        self.with_text(field, "uppercase", |s| s.to_uppercase())
+    }
+
+    /// Replace known spellings of source names, matched case-insensitively,
+    /// with their canonical form (e.g. "coingecko" -> "CoinGecko")
+    pub fn with_canonical_sources<I, K, V>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let names: HashMap<String, String> = names
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_lowercase(), v.into()))
+            .collect();
+        self.with_text(Field::Source, "canonical_source", move |s| {
+            names
+                .get(&s.to_lowercase())
+                .cloned()
+                .unwrap_or_else(|| s.to_string())
+        })
+    }
+
+    /// Round prices half away from zero to `decimals` places
+    pub fn with_precision(self, decimals: u32) -> Self {
+        self.with_price(format!("precision({})", decimals), move |p| {
+            price::round(p, decimals)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.price.is_empty()
+    }
+
+    /// Run the registered sanitizers for a text field, recording changes
+    pub fn sanitize_text(&self, field: Field, value: &str, report: &mut SanitizeReport) -> String {
+        let mut value = value.to_string();
+        for (_, name, sanitize) in self.text.iter().filter(|(f, _, _)| *f == field) {
+            let after = sanitize(&value);
+            if after != value {
+                report.modifications.push(Modification {
+                    field,
+                    sanitizer: name.clone(),
+                    before: value,
+                    after: after.clone(),
+                });
+            }
+            value = after;
+        }
+        value
+    }
+
+    /// Run the price sanitizers, recording changes
+    pub fn sanitize_price(&self, price: Decimal, report: &mut SanitizeReport) -> Decimal {
+        let mut price = price;
+        for (name, sanitize) in &self.price {
+            let after = sanitize(price);
+            // Compared by value, so `1.50` and `1.5` are not a change
+            if after != price {
+                report.modifications.push(Modification {
+                    field: Field::Price,
+                    sanitizer: name.clone(),
+                    before: price.to_string(),
+                    after: after.to_string(),
+                });
+            }
+            price = after;
+        }
+        price
+    }
+}
+
+impl fmt::Debug for Sanitizers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text: Vec<String> = self
+            .text
+            .iter()
+            .map(|(field, name, _)| format!("{}:{}", field, name))
+            .collect();
+        let price: Vec<&str> = self.price.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("Sanitizers")
+            .field("text", &text)
+            .field("price", &price)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitizers_record_modifications() {
+        let sanitizers = Sanitizers::standard()
+            .with_canonical_sources([("coingecko", "CoinGecko")])
+            .with_precision(2);
+        let mut report = SanitizeReport::default();
+
+        assert_eq!(
+            sanitizers.sanitize_text(Field::Asset, " btc ", &mut report),
+            "BTC"
+        );
+        assert_eq!(
+            sanitizers.sanitize_text(Field::Source, " COINGECKO", &mut report),
+            "CoinGecko"
+        );
+        assert_eq!(
+            sanitizers.sanitize_price(price::parse("50000.126").unwrap(), &mut report),
+            price::parse("50000.13").unwrap()
+        );
+
+        let applied: Vec<(Field, &str)> = report
+            .modifications
+            .iter()
+            .map(|m| (m.field, m.sanitizer.as_str()))
+            .collect();
+        assert_eq!(
+            applied,
+            vec![
+                (Field::Asset, "trim"),
+                (Field::Asset, "uppercase"),
+                (Field::Source, "trim"),
+                (Field::Source, "canonical_source"),
+                (Field::Price, "precision(2)"),
+            ]
+        );
+        assert_eq!(report.modifications[0].before, " btc ");
+        assert_eq!(report.modifications[1].after, "BTC");
+    }
+
+    #[test]
+    fn test_unchanged_values_are_not_reported() {
+        let sanitizers = Sanitizers::standard().with_precision(2);
+        let mut report = SanitizeReport::default();
+
+        sanitizers.sanitize_text(Field::Asset, "ETH", &mut report);
+        sanitizers.sanitize_price(price::parse("42.50").unwrap(), &mut report);
+        assert!(report.is_empty());
+        assert!(!report.modified(Field::Asset));
+    }
+}
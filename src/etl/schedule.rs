@@ -0,0 +1,621 @@
+//! When the node extracts market data
+//!
+//! By default a node starts the next round one block interval (3 s, or what
+//! `POST /admin/reconfigure` set) after the previous one ends. An
+//! `ExtractionSchedule` replaces that with a cadence and, optionally, the
+//! hours a market trades:
+//!
+//! ```text
+//! EXTRACT_SCHEDULE=30s                       # fixed interval (ms, s, m or h)
+//! EXTRACT_SCHEDULE=*/5 * * * *               # cron: minute hour day month weekday, UTC
+//! MARKET_HOURS=mon-fri 09:30-16:00 -05:00    # only extract while the market is open
+//! ```
+//!
+//! Both settings can be given per source by suffixing the source's name as
+//! logged at startup, e.g. `EXTRACT_SCHEDULE_ALPHAVANTAGE` or
+//! `MARKET_HOURS_ALPHAVANTAGE`; the plain variables apply to every other
+//! source. A round that would fall outside market hours waits for the next
+//! open instead.
+//!
+//! Market hours take a fixed UTC offset, which does not follow daylight
+//! saving time, or `local` for the host's time zone, which does. A session
+//! whose close is earlier than its open runs past midnight, e.g.
+//! `sun-fri 17:00-17:00 local` for FX.
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, FixedOffset, Local, NaiveDateTime};
+use chrono::{NaiveTime, TimeZone, Timelike, Utc};
+use std::fmt;
+use std::time::Duration;
+
+/// How often rounds run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cadence {
+    /// A fixed delay after each round
+    Interval(Duration),
+    /// The start of every minute a cron expression matches
+    Cron(CronExpr),
+}
+
+impl Cadence {
+    /// `30s`/`500ms`/`5m`/`1h`, or a five-field cron expression
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec.split_whitespace().count() > 1 {
+            return CronExpr::parse(spec).map(Cadence::Cron);
+        }
+        parse_interval(spec).map(Cadence::Interval)
+    }
+}
+
+fn parse_interval(spec: &str) -> Result<Duration, String> {
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("interval '{}' needs a unit (ms, s, m or h)", spec))?;
+    let (value, unit) = spec.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is neither an interval nor a cron expression", spec))?;
+    let interval = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 3600),
+        _ => {
+            return Err(format!(
+                "unknown interval unit '{}' (expected ms, s, m or h)",
+                unit
+            ))
+        }
+    };
+    if interval.is_zero() {
+        return Err("interval must be positive".to_string());
+    }
+    Ok(interval)
+}
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Weekday number (Sunday is 0, as in cron) from a number or `mon`-style name
+fn parse_weekday(value: &str) -> Result<u32, String> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(day) = WEEKDAYS.iter().position(|d| lower.starts_with(d)) {
+        return Ok(day as u32);
+    }
+    match value.parse::<u32>() {
+        Ok(day) if day <= 7 => Ok(day),
+        _ => Err(format!("'{}' is not a weekday", value)),
+    }
+}
+
+/// Weekdays a field allows, as a bit per day with Sunday first. Sunday is
+/// 0 or 7, so `mon-sun` and `fri-0` run to the end of the week.
+fn parse_weekdays(field: &str) -> Result<u8, String> {
+    let parts: Vec<String> = field
+        .split(',')
+        .map(|part| {
+            let (range, step) = part.split_once('/').unwrap_or((part, ""));
+            let range = match range.split_once('-') {
+                Some((start, end)) if parse_weekday(end) == Ok(0) => format!("{}-7", start),
+                _ => range.to_string(),
+            };
+            match step {
+                "" => range,
+                step => format!("{}/{}", range, step),
+            }
+        })
+        .collect();
+    let bits = parse_field(&parts.join(","), 0, 7, parse_weekday)?;
+    Ok(((bits | bits >> 7) & 0x7f) as u8)
+}
+
+/// Values a cron field allows, as a bit set
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    value: impl Fn(&str) -> Result<u32, String>,
+) -> Result<u64, String> {
+    let mut allowed = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|&s| s > 0)
+                    .ok_or_else(|| format!("bad step in '{}'", part))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let single = value(range)?;
+                    (single, single)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            allowed |= 1 << v;
+        }
+    }
+    Ok(allowed)
+}
+
+/// A five-field cron expression (`minute hour day-of-month month
+/// day-of-week`), evaluated in UTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    spec: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u8,
+    /// Day of month and day of week both restricted: either may match
+    either_day: bool,
+}
+
+impl CronExpr {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "cron expression '{}' needs five fields: minute hour day month weekday",
+                spec
+            ));
+        };
+        let number = |v: &str| {
+            v.parse::<u32>()
+                .map_err(|_| format!("'{}' is not a number", v))
+        };
+        Ok(CronExpr {
+            spec: fields.join(" "),
+            minutes: parse_field(minute, 0, 59, number)?,
+            hours: parse_field(hour, 0, 23, number)?,
+            days: parse_field(day, 1, 31, number)?,
+            months: parse_field(month, 1, 12, number)?,
+            weekdays: parse_weekdays(weekday)?,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+
+    fn day_matches(&self, t: &DateTime<Utc>) -> bool {
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// First matching minute at or after `t`
+    pub fn next_at_or_after(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let minute = t.with_second(0)?.with_nanosecond(0)?;
+        let mut t = if minute < t {
+            minute + ChronoDuration::minutes(1)
+        } else {
+            minute
+        };
+        // Every expression matches within eight years (Feb 29 on a weekday)
+        let limit = t + ChronoDuration::days(366 * 8);
+        while t < limit {
+            if self.months & (1 << t.month()) == 0 {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.day_matches(&t) {
+                t = (t + ChronoDuration::days(1)).with_hour(0)?.with_minute(0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = (t + ChronoDuration::hours(1)).with_minute(0)?;
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += ChronoDuration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Time zone market hours are given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketZone {
+    Fixed(FixedOffset),
+    /// The host's time zone, following daylight saving time
+    Local,
+}
+
+impl MarketZone {
+    fn parse(spec: &str) -> Result<Self, String> {
+        match spec.to_ascii_lowercase().as_str() {
+            "utc" | "z" => Ok(MarketZone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            "local" => Ok(MarketZone::Local),
+            _ => {
+                let (sign, rest) = match spec.as_bytes().first() {
+                    Some(b'+') => (1, &spec[1..]),
+                    Some(b'-') => (-1, &spec[1..]),
+                    _ => return Err(format!("'{}' is not utc, local or +HH:MM", spec)),
+                };
+                let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+                let seconds = match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+                    (Ok(h), Ok(m)) if h <= 14 && m < 60 => (h * 60 + m) * 60,
+                    _ => return Err(format!("'{}' is not a UTC offset", spec)),
+                };
+                FixedOffset::east_opt(sign * seconds)
+                    .map(MarketZone::Fixed)
+                    .ok_or_else(|| format!("'{}' is not a UTC offset", spec))
+            }
+        }
+    }
+
+    fn to_local(self, t: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            MarketZone::Fixed(offset) => t.with_timezone(&offset).naive_local(),
+            MarketZone::Local => t.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    /// A wall-clock time skipped by a daylight saving jump has no instant
+    fn instant_of(self, t: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            MarketZone::Fixed(offset) => offset
+                .from_local_datetime(&t)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+            MarketZone::Local => Local
+                .from_local_datetime(&t)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// The days and hours a market trades
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketHours {
+    /// Bit per weekday, Sunday first
+    days: u8,
+    /// Minutes after midnight; a close at or before the open is the next day
+    open: u32,
+    close: u32,
+    zone: MarketZone,
+}
+
+impl MarketHours {
+    /// `DAYS HH:MM-HH:MM [ZONE]`, e.g. `mon-fri 09:30-16:00 -05:00`; days
+    /// are a range, a list or `daily`, and the zone defaults to UTC
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let (days, hours, zone) = match fields[..] {
+            [days, hours] => (days, hours, "utc"),
+            [days, hours, zone] => (days, hours, zone),
+            _ => {
+                return Err(format!(
+                    "market hours '{}' are not DAYS HH:MM-HH:MM [ZONE]",
+                    spec
+                ))
+            }
+        };
+        let days = match days {
+            "daily" | "*" => 0x7f,
+            _ => parse_weekdays(days)?,
+        };
+        let (open, close) = hours
+            .split_once('-')
+            .ok_or_else(|| format!("'{}' is not HH:MM-HH:MM", hours))?;
+        Ok(MarketHours {
+            days,
+            open: parse_minutes(open)?,
+            close: parse_minutes(close)?,
+            zone: MarketZone::parse(zone)?,
+        })
+    }
+
+    fn trades_on(&self, weekday: u32) -> bool {
+        self.days & (1 << weekday) != 0
+    }
+
+    pub fn is_open(&self, t: DateTime<Utc>) -> bool {
+        let local = self.zone.to_local(t);
+        let weekday = local.weekday().num_days_from_sunday();
+        let minute = local.hour() * 60 + local.minute();
+        if self.open < self.close {
+            self.trades_on(weekday) && (self.open..self.close).contains(&minute)
+        } else {
+            (self.trades_on(weekday) && minute >= self.open)
+                || (self.trades_on((weekday + 6) % 7) && minute < self.close)
+        }
+    }
+
+    /// `t` if the market is open then, else the next open
+    pub fn next_open(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.is_open(t) {
+            return Some(t);
+        }
+        let today = self.zone.to_local(t).date();
+        let open = NaiveTime::from_hms_opt(self.open / 60, self.open % 60, 0)?;
+        (0..=8)
+            .map(|days| today + ChronoDuration::days(days))
+            .filter(|date| self.trades_on(date.weekday().num_days_from_sunday()))
+            .filter_map(|date| self.zone.instant_of(date.and_time(open)))
+            .find(|&opens| opens > t)
+    }
+}
+
+fn parse_minutes(spec: &str) -> Result<u32, String> {
+    let parsed = spec
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)));
+    match parsed {
+        Some((h, m)) if (h < 24 && m < 60) || (h == 24 && m == 0) => Ok(h * 60 + m),
+        _ => Err(format!("'{}' is not a time of day HH:MM", spec)),
+    }
+}
+
+/// When the next extraction round starts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractionSchedule {
+    /// `None` keeps the node's block interval
+    cadence: Option<Cadence>,
+    market_hours: Option<MarketHours>,
+}
+
+impl ExtractionSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cadence(mut self, cadence: Cadence) -> Self {
+        self.cadence = Some(cadence);
+        self
+    }
+
+    pub fn with_market_hours(mut self, hours: MarketHours) -> Self {
+        self.market_hours = Some(hours);
+        self
+    }
+
+    /// `EXTRACT_SCHEDULE_<SOURCE>` / `MARKET_HOURS_<SOURCE>`, falling back
+    /// to `EXTRACT_SCHEDULE` / `MARKET_HOURS`; `source` is the name the
+    /// extractor reports
+    pub fn from_env(source: &str) -> Result<Self, String> {
+        let suffix: String = source
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let read = |var: &str| -> Option<(String, String)> {
+            let specific = format!("{}_{}", var, suffix);
+            [specific, var.to_string()]
+                .into_iter()
+                .find_map(|name| match std::env::var(&name) {
+                    Ok(value) if !value.trim().is_empty() => Some((name, value)),
+                    _ => None,
+                })
+        };
+
+        let mut schedule = ExtractionSchedule::new();
+        if let Some((var, spec)) = read("EXTRACT_SCHEDULE") {
+            let cadence = Cadence::parse(&spec).map_err(|e| format!("invalid {}: {}", var, e))?;
+            schedule = schedule.with_cadence(cadence);
+        }
+        if let Some((var, spec)) = read("MARKET_HOURS") {
+            let hours = MarketHours::parse(&spec).map_err(|e| format!("invalid {}: {}", var, e))?;
+            schedule = schedule.with_market_hours(hours);
+        }
+        Ok(schedule)
+    }
+
+    /// Parse every `EXTRACT_SCHEDULE*` and `MARKET_HOURS*` variable, for
+    /// `config validate`, which does not know which source will run
+    pub fn validate_env() -> Result<(), String> {
+        for (var, spec) in std::env::vars() {
+            let parsed = if var.starts_with("EXTRACT_SCHEDULE") {
+                Cadence::parse(&spec).map(|_| ())
+            } else if var.starts_with("MARKET_HOURS") {
+                MarketHours::parse(&spec).map(|_| ())
+            } else {
+                continue;
+            };
+            parsed.map_err(|e| format!("invalid {}: {}", var, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.cadence.is_none() && self.market_hours.is_none()
+    }
+
+    /// Whether the market is open at `t`; always without market hours
+    pub fn is_market_open(&self, t: DateTime<Utc>) -> bool {
+        self.market_hours.as_ref().is_none_or(|h| h.is_open(t))
+    }
+
+    /// Start of the round after one ending at `now`; `block_interval` is
+    /// the delay without a cadence of its own
+    pub fn next_run(&self, now: DateTime<Utc>, block_interval: Duration) -> DateTime<Utc> {
+        let after_interval = |interval: Duration| {
+            ChronoDuration::from_std(interval)
+                .ok()
+                .and_then(|interval| now.checked_add_signed(interval))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+        let mut next = match &self.cadence {
+            None => after_interval(block_interval),
+            Some(Cadence::Interval(interval)) => after_interval(*interval),
+            Some(Cadence::Cron(cron)) => cron
+                .next_at_or_after(now + ChronoDuration::seconds(1))
+                .unwrap_or_else(|| after_interval(block_interval)),
+        };
+        let Some(hours) = &self.market_hours else {
+            return next;
+        };
+        // Alternate between the next open and the next cron match until
+        // they agree; cron and market hours that never meet give up
+        for _ in 0..64 {
+            let Some(open) = hours.next_open(next) else {
+                return next;
+            };
+            next = match &self.cadence {
+                Some(Cadence::Cron(cron)) => match cron.next_at_or_after(open) {
+                    Some(matched) => matched,
+                    None => return open,
+                },
+                _ => open,
+            };
+            if hours.is_open(next) {
+                break;
+            }
+        }
+        next
+    }
+
+    /// How long to wait after a round ending at `now`
+    pub fn delay(&self, now: DateTime<Utc>, block_interval: Duration) -> Duration {
+        (self.next_run(now, block_interval) - now)
+            .to_std()
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ExtractionSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cadence {
+            None => f.write_str("every block interval")?,
+            Some(Cadence::Interval(interval)) => write!(f, "every {:?}", interval)?,
+            Some(Cadence::Cron(cron)) => write!(f, "cron '{}' (UTC)", cron)?,
+        }
+        if self.market_hours.is_some() {
+            f.write_str(" during market hours")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(spec: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(spec)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_cadence_and_cron() {
+        assert_eq!(
+            Cadence::parse("500ms"),
+            Ok(Cadence::Interval(Duration::from_millis(500)))
+        );
+        assert_eq!(
+            Cadence::parse("2m"),
+            Ok(Cadence::Interval(Duration::from_secs(120)))
+        );
+        assert!(Cadence::parse("0s").is_err());
+        assert!(Cadence::parse("30").is_err());
+        assert!(Cadence::parse("* * *").is_err());
+        assert!(Cadence::parse("61 * * * *").is_err());
+
+        let every_five = CronExpr::parse("*/5 * * * *").unwrap();
+        assert_eq!(
+            every_five.next_at_or_after(utc("2024-03-04T10:02:30Z")),
+            Some(utc("2024-03-04T10:05:00Z"))
+        );
+        // Weekdays at 14:30; Friday evening rolls over to Monday
+        let weekdays = CronExpr::parse("30 14 * * mon-fri").unwrap();
+        assert_eq!(
+            weekdays.next_at_or_after(utc("2024-03-08T15:00:00Z")),
+            Some(utc("2024-03-11T14:30:00Z"))
+        );
+        // Day of month and weekday both restricted: either matches
+        let either = CronExpr::parse("0 0 1 * sun").unwrap();
+        assert_eq!(
+            either.next_at_or_after(utc("2024-03-02T12:00:00Z")),
+            Some(utc("2024-03-03T00:00:00Z"))
+        );
+        assert_eq!(
+            CronExpr::parse("0 0 29 2 *")
+                .unwrap()
+                .next_at_or_after(utc("2024-03-01T00:00:00Z")),
+            Some(utc("2028-02-29T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_market_hours_gate_the_schedule() {
+        let nyse = MarketHours::parse("mon-fri 09:30-16:00 -05:00").unwrap();
+        assert!(nyse.is_open(utc("2024-03-04T15:00:00Z")));
+        assert!(!nyse.is_open(utc("2024-03-04T21:00:00Z")));
+        assert!(!nyse.is_open(utc("2024-03-09T15:00:00Z")));
+        assert_eq!(
+            nyse.next_open(utc("2024-03-08T21:30:00Z")),
+            Some(utc("2024-03-11T14:30:00Z"))
+        );
+
+        // A session past midnight: Sunday 17:00 through Friday 17:00
+        let fx = MarketHours::parse("sun-thu 17:00-17:00").unwrap();
+        assert!(fx.is_open(utc("2024-03-08T16:59:00Z")));
+        assert!(!fx.is_open(utc("2024-03-08T17:00:00Z")));
+        assert!(!fx.is_open(utc("2024-03-09T12:00:00Z")));
+        assert_eq!(
+            MarketHours::parse("sat-sun 10:00-12:00").unwrap().days,
+            0b100_0001
+        );
+        assert!(MarketHours::parse("mon-fri 09:30").is_err());
+        assert!(MarketHours::parse("mon-fri 09:30-25:00").is_err());
+        assert!(MarketHours::parse("mon-fri 09:30-16:00 EST").is_err());
+
+        let default = ExtractionSchedule::new();
+        let now = utc("2024-03-08T21:30:00Z");
+        assert_eq!(
+            default.delay(now, Duration::from_secs(3)),
+            Duration::from_secs(3)
+        );
+
+        // An interval that lands after the close waits for Monday's open
+        let schedule = ExtractionSchedule::new()
+            .with_cadence(Cadence::Interval(Duration::from_secs(60)))
+            .with_market_hours(nyse.clone());
+        assert_eq!(
+            schedule.next_run(now, Duration::from_secs(3)),
+            utc("2024-03-11T14:30:00Z")
+        );
+        let during = utc("2024-03-11T15:00:00Z");
+        assert_eq!(
+            schedule.delay(during, Duration::from_secs(3)),
+            Duration::from_secs(60)
+        );
+
+        // Cron matches are taken only while the market is open
+        let hourly = ExtractionSchedule::new()
+            .with_cadence(Cadence::parse("0 * * * *").unwrap())
+            .with_market_hours(nyse);
+        assert_eq!(
+            hourly.next_run(now, Duration::from_secs(3)),
+            utc("2024-03-11T15:00:00Z")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! ETL stages of a running node, built from the environment
+//!
+//! `extractor_from_env` and `transformer_from_env` read the variables each
+//! stage documents and log what they enable, so the node's block loop only
+//! drives the stages. Settings that do not parse are configuration errors
+//! (`ExitError::config`).
+
+use super::anomaly::AnomalyStage;
+use super::bucket::BucketStage;
+use super::consolidate::ConsolidateStage;
+use super::conversion::{ConversionStage, RateTable};
+use super::divergence::DivergenceDetector;
+use super::extract::{cache_ttl_from_env, max_concurrency_from_env, Extractor, HttpClientConfig};
+use super::extract_status::ExtractionTracker;
+use super::indicators::IndicatorStage;
+use super::load::DatabaseManager;
+use super::order_book::{OrderBookConfig, OrderBookSource};
+use super::pipeline::{DedupStrategy, Normalization};
+use super::sanitizer::Sanitizers;
+use super::sources::SourceRegistry;
+use super::stress::StressSource;
+use super::transform::{AssetSettings, Transformer};
+use super::transform_stats::TransformTracker;
+use super::validator::Validator;
+use crate::supervisor::ExitError;
+use std::error::Error;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// The extractor for `MARKET_DATA_SOURCE`, `MARKET_DATA_ASSETS`,
+/// `MARKET_DATA_STREAM` and `OFFLINE_SCENARIO`, checking quotes with
+/// `validator`
+pub fn extractor_from_env(
+    validator: Validator,
+    tracker: Arc<ExtractionTracker>,
+    offline: bool,
+) -> Result<Extractor, Box<dyn Error>> {
+    let extractor =
+        Extractor::from_http_config(&HttpClientConfig::from_env().map_err(ExitError::config)?)?
+            .with_validator(validator)
+            .with_cache_ttl(cache_ttl_from_env().map_err(ExitError::config)?)
+            .with_tracker(tracker);
+    let registry = SourceRegistry::with_builtin();
+    let source = registry
+        .source_from_env(extractor.client().clone())
+        .map_err(ExitError::config)?;
+    let asset_sources = registry
+        .assets_from_env(extractor.client().clone())
+        .map_err(ExitError::config)?;
+    let mut extractor = extractor
+        .with_source(source)
+        .with_max_concurrency(max_concurrency_from_env().map_err(ExitError::config)?);
+    for source in asset_sources {
+        extractor = extractor.with_asset_source(source);
+    }
+    info!(
+        source = extractor.source_name(),
+        "Extract: Market data source"
+    );
+    if !extractor.asset_source_names().is_empty() {
+        info!(
+            sources = ?extractor.asset_source_names(),
+            max_concurrency = extractor.max_concurrency(),
+            "Extract: Quoting further assets concurrently"
+        );
+    }
+    if let Some(stream_source) = super::stream::from_env().map_err(ExitError::config)? {
+        extractor = extractor.with_stream_source(stream_source);
+    }
+    if let Some(source) = StressSource::from_env().map_err(ExitError::config)? {
+        if offline {
+            info!(
+                scenario = %source.scenario(),
+                onset = source.onset(),
+                "Extract: Playing a stress scenario offline"
+            );
+        } else {
+            warn!("Extract: OFFLINE_SCENARIO only applies with --offline");
+        }
+        extractor = extractor.with_offline_source(source);
+    }
+    Ok(extractor)
+}
+
+/// The transform stages a node runs, and the parts of them its block loop
+/// feeds
+pub struct TransformSetup {
+    pub transformer: Transformer,
+    /// FX rates to update from the pairs quoted each round, with
+    /// `CONVERSION_CURRENCY` set
+    pub conversion_rates: Option<RateTable>,
+    /// Kept to add committed blocks to the window the pipelines read
+    pub indicators: Option<IndicatorStage>,
+    pub divergence: Option<DivergenceDetector>,
+}
+
+/// The transformer for `asset`, with the per-asset settings, normalization,
+/// deduplication, conversion, consolidation, anomaly, bucket and indicator
+/// stages the environment enables. Indicator windows are refilled from the
+/// latest blocks in `db`.
+pub fn transformer_from_env(
+    asset: &str,
+    validator: Validator,
+    tracker: Arc<TransformTracker>,
+    db: &DatabaseManager,
+) -> Result<TransformSetup, Box<dyn Error>> {
+    let divergence = DivergenceDetector::from_env();
+    if let Some(detector) = &divergence {
+        info!(
+            threshold_pct = detector.threshold_pct,
+            "Transform: Recording source divergence in blocks"
+        );
+    }
+    let symbols = validator.symbols().clone();
+    let mut transformer = AssetSettings::from_env()
+        .map_err(ExitError::config)?
+        .into_iter()
+        .fold(
+            Transformer::new()
+                .with_tracker(tracker)
+                .with_validator(validator)
+                .with_sanitizers(Sanitizers::standard())
+                .with_asset(asset),
+            |transformer, (asset, settings)| transformer.with_asset_settings(&asset, settings),
+        );
+    if let Some(normalization) = Normalization::from_env().map_err(ExitError::config)? {
+        info!(%normalization, "Transform: Price normalization");
+        transformer = transformer.with_normalization(normalization);
+    }
+    if let Some(strategy) = DedupStrategy::from_env().map_err(ExitError::config)? {
+        info!(%strategy, "Transform: Deduplication strategy");
+        transformer = transformer.with_deduplication_strategy(strategy);
+    }
+    let conversion_rates = match ConversionStage::from_env().map_err(ExitError::config)? {
+        Some(stage) => {
+            info!(
+                from = stage.source_currency(),
+                to = stage.to_currency(),
+                "Transform: Converting prices into the ledger currency"
+            );
+            let stage = stage.with_symbols(symbols);
+            let rates = stage.rates().clone();
+            transformer = transformer.with_conversion(stage);
+            Some(rates)
+        }
+        None => None,
+    };
+    if let Some(stage) = ConsolidateStage::from_env().map_err(ExitError::config)? {
+        info!(
+            method = stage.weighting().name(),
+            window_ms = stage.window_ms(),
+            "Transform: Consolidating source quotes by weight"
+        );
+        transformer = transformer.with_consolidation(stage);
+    }
+    if let Some(stage) = AnomalyStage::from_env().map_err(ExitError::config)? {
+        info!(
+            method = %stage.method(),
+            threshold = stage.threshold(),
+            action = ?stage.action(),
+            "Transform: Checking prices against their recent window"
+        );
+        transformer = transformer.with_anomaly_detection(stage);
+    }
+    if let Some(stage) = BucketStage::from_env().map_err(ExitError::config)? {
+        info!(
+            width_seconds = stage.width_seconds(),
+            "Transform: Writing one entry per asset and time bucket"
+        );
+        transformer = transformer.with_time_buckets(stage);
+    }
+    let indicators = IndicatorStage::from_env().map_err(ExitError::config)?;
+    if let Some(stage) = indicators.clone() {
+        // Recent entries refill the window, so averages survive a restart
+        if let Ok(Some(head)) = db.get_latest_block() {
+            let from = head.index.saturating_sub(stage.window() as u64 - 1);
+            match db.get_blocks_range(from, head.index) {
+                Ok(blocks) => stage.seed(&blocks),
+                Err(e) => warn!(error = %e, "Transform: Cannot seed indicators from the ledger"),
+            }
+        }
+        info!(
+            window = stage.window(),
+            "Transform: Recording rolling indicators in entries"
+        );
+        transformer = transformer.with_indicators(stage);
+    }
+    Ok(TransformSetup {
+        transformer,
+        conversion_rates,
+        indicators,
+        divergence,
+    })
+}
+
+/// An order book source and the depth to snapshot from it
+pub type OrderBookFeed = (Arc<dyn OrderBookSource>, usize);
+
+/// The `ORDER_BOOK_SOURCE` to snapshot into each block, with its depth
+pub fn order_book_from_env() -> Result<Option<OrderBookFeed>, String> {
+    let Some(config) = OrderBookConfig::from_env()? else {
+        return Ok(None);
+    };
+    info!(
+        source = %config.source,
+        asset = %config.asset,
+        depth = config.depth,
+        "Extract: Recording order book snapshots in blocks"
+    );
+    Ok(Some((config.create()?, config.depth)))
+}
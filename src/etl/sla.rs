@@ -0,0 +1,132 @@
+//! Commit latency tracking against a freshness SLA
+//!
+//! Every committed block records when its data was observed (the oldest
+//! entry's timestamp) and when it was persisted; see `CommitLatency`. The
+//! percentiles over recent blocks are served on `GET /stats`, so operators
+//! can state how fresh the ledgered prices are. With `COMMIT_SLA_MS` set,
+//! the report also gives the share of blocks committed within that target,
+//! and slower commits are logged as warnings.
+
+use crate::etl::load::{CommitLatency, DatabaseManager, DbResult};
+use serde::Serialize;
+
+/// Blocks summarized by a report unless the caller asks for another window
+pub const DEFAULT_REPORT_WINDOW: u64 = 1000;
+
+/// Latency target for data observed → block committed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommitSla {
+    pub target_ms: Option<i64>,
+}
+
+impl CommitSla {
+    pub fn new(target_ms: i64) -> Self {
+        CommitSla {
+            target_ms: Some(target_ms),
+        }
+    }
+
+    /// Read `COMMIT_SLA_MS`; no target when unset
+    pub fn from_env() -> Self {
+        CommitSla {
+            target_ms: std::env::var("COMMIT_SLA_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&ms: &i64| ms > 0),
+        }
+    }
+
+    /// Whether `latency` misses the target
+    pub fn is_breached(&self, latency: &CommitLatency) -> bool {
+        self.target_ms
+            .is_some_and(|target| latency.latency_ms() > target)
+    }
+
+    /// Summarize the `window` most recent commits
+    pub fn report(&self, db: &DatabaseManager, window: u64) -> DbResult<LatencyReport> {
+        let latencies = db.get_commit_latencies(window)?;
+        Ok(self.summarize(&latencies))
+    }
+
+    pub fn summarize(&self, latencies: &[CommitLatency]) -> LatencyReport {
+        let mut millis: Vec<i64> = latencies.iter().map(CommitLatency::latency_ms).collect();
+        millis.sort_unstable();
+
+        let within_sla = self.target_ms.filter(|_| !millis.is_empty()).map(|target| {
+            let within = millis.iter().filter(|&&ms| ms <= target).count();
+            within as f64 / millis.len() as f64
+        });
+        LatencyReport {
+            blocks: millis.len(),
+            from_index: latencies.iter().map(|l| l.block_index).min(),
+            to_index: latencies.iter().map(|l| l.block_index).max(),
+            p50_ms: percentile(&millis, 50.0),
+            p90_ms: percentile(&millis, 90.0),
+            p99_ms: percentile(&millis, 99.0),
+            max_ms: millis.last().copied(),
+            mean_ms: (!millis.is_empty())
+                .then(|| millis.iter().sum::<i64>() as f64 / millis.len() as f64),
+            sla_ms: self.target_ms,
+            within_sla,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[i64], pct: f64) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Commit latency percentiles over a window of recent blocks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyReport {
+    pub blocks: usize,
+    pub from_index: Option<u64>,
+    pub to_index: Option<u64>,
+    pub p50_ms: Option<i64>,
+    pub p90_ms: Option<i64>,
+    pub p99_ms: Option<i64>,
+    pub max_ms: Option<i64>,
+    pub mean_ms: Option<f64>,
+    pub sla_ms: Option<i64>,
+    /// Fraction of blocks committed within `sla_ms`
+    pub within_sla: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latency(block_index: u64, latency_ms: i64) -> CommitLatency {
+        CommitLatency {
+            block_index,
+            observed_at: 1_000_000,
+            committed_at: 1_000_000 + latency_ms,
+        }
+    }
+
+    #[test]
+    fn test_report_percentiles_and_sla_share() {
+        let latencies: Vec<CommitLatency> = (1..=100).map(|i| latency(i, i as i64 * 10)).collect();
+        let sla = CommitSla::new(900);
+        let report = sla.summarize(&latencies);
+
+        assert_eq!(report.blocks, 100);
+        assert_eq!((report.from_index, report.to_index), (Some(1), Some(100)));
+        assert_eq!(report.p50_ms, Some(500));
+        assert_eq!(report.p90_ms, Some(900));
+        assert_eq!(report.p99_ms, Some(990));
+        assert_eq!(report.max_ms, Some(1000));
+        assert_eq!(report.within_sla, Some(0.9));
+        assert!(sla.is_breached(&latencies[95]));
+        assert!(!sla.is_breached(&latencies[0]));
+
+        let empty = CommitSla::default().summarize(&[]);
+        assert_eq!(empty.p50_ms, None);
+        assert_eq!(empty.within_sla, None);
+    }
+}
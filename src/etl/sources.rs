@@ -0,0 +1,758 @@
+//! Exchange data sources and the registry that picks one by name
+//!
+//! Besides CoinGecko (`extract::CoinGeckoSource`), BTC/USD can come from
+//! Kraken's public ticker (`KrakenSource`) or Coinbase's spot price
+//! (`CoinbaseSource`). `SourceRegistry` maps a source name to a constructor,
+//! so the node picks its source from `MARKET_DATA_SOURCE` (`coingecko`,
+//! `kraken`, `coinbase` or `mock`; default `coingecko`) instead of having it
+//! wired in at compile time. Each exchange URL can be overridden with
+//! `KRAKEN_API_URL` / `COINBASE_API_URL`, like `COINGECKO_API_URL`.
+//! Naming several sources, separated by commas, queries them all and
+//! aggregates their prices (`aggregate::AggregatingExtractor`). `file`
+//! replays a recorded tick file configured by `MARKET_DATA_FILE`
+//! (`extract::FileSource::from_env`).
+//!
+//! Each source reports the base-asset volume traded over the last 24 hours
+//! with its quote where its API has one (`ExtractResult::volume`): Kraken's
+//! ticker, Coinbase Exchange's product stats (`COINBASE_STATS_URL`) and
+//! CoinGecko's `include_24hr_vol`. Alpha Vantage reports the latest trading
+//! day's volume of an equity, and none for FX pairs.
+//!
+//! `alphavantage` quotes one FX pair or equity, named by
+//! `ALPHAVANTAGE_ASSET` and resolved through `ASSET_SYMBOLS` (see
+//! `symbols`), with the key from `ALPHAVANTAGE_API_KEY`.
+//!
+//! Sources that can quote any asset also register a per-asset constructor
+//! (`register_asset`). `MARKET_DATA_ASSETS` lists further assets the node
+//! records each round with the source for each, e.g.
+//! `EURUSD:alphavantage,AAPL:alphavantage`; see `Extractor::extract_assets`.
+
+use crate::etl::aggregate::AggregatingExtractor;
+use crate::etl::extract::{
+    CoinGeckoSource, DataSource, ExtractResult, FileSource, MockSource, SourceError,
+};
+use crate::etl::price::{self, Decimal};
+use crate::etl::symbols::{AssetClass, SymbolMap, SymbolMapping};
+use crate::etl::{now_millis, DEFAULT_ASSET};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+/// Source used when `MARKET_DATA_SOURCE` is unset
+pub const DEFAULT_SOURCE: &str = "coingecko";
+
+/// Fetch `url` and decode its JSON body, classifying failures for retry
+pub(crate) async fn get_json<T: for<'de> Deserialize<'de>>(
+    client: &Client,
+    url: &str,
+) -> Result<T, SourceError> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(SourceError::from_request)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(SourceError::from_status(status));
+    }
+    response.json().await.map_err(SourceError::from_decode)
+}
+
+pub(crate) fn parse_price(source: &str, price: &str) -> Result<Decimal, SourceError> {
+    price::parse(price).map_err(|_| {
+        SourceError::retryable(format!("{} sent unparseable price '{}'", source, price))
+    })
+}
+
+/// A reported volume; a missing or malformed one only costs the volume
+pub(crate) fn parse_volume(volume: &str) -> Option<Decimal> {
+    price::parse(volume)
+        .ok()
+        .filter(|volume| *volume >= Decimal::ZERO)
+}
+
+#[derive(Deserialize)]
+struct KrakenResponse {
+    error: Vec<String>,
+    #[serde(default)]
+    result: BTreeMap<String, KrakenTicker>,
+}
+
+#[derive(Deserialize)]
+struct KrakenTicker {
+    /// Last trade: price, lot volume
+    c: Vec<String>,
+    /// Base volume: today, last 24 hours
+    #[serde(default)]
+    v: Vec<String>,
+}
+
+/// BTC/USD last trade from Kraken's public ticker
+pub struct KrakenSource {
+    client: Client,
+    url: String,
+}
+
+impl KrakenSource {
+    pub const DEFAULT_URL: &'static str = "https://api.kraken.com/0/public/Ticker?pair=XBTUSD";
+
+    /// Uses `KRAKEN_API_URL` when set
+    pub fn new(client: Client) -> Self {
+        KrakenSource {
+            client,
+            url: std::env::var("KRAKEN_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl DataSource for KrakenSource {
+    fn name(&self) -> &str {
+        "Kraken"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let body: KrakenResponse = get_json(&self.client, &self.url).await?;
+        // Kraken reports failures in the body with HTTP 200
+        if let Some(error) = body.error.first() {
+            return Err(
+                if error.contains("Rate limit") || error.contains("Too many") {
+                    SourceError::throttled(format!("Kraken: {}", error), Duration::from_secs(1))
+                } else if error.starts_with("EService") {
+                    SourceError::retryable(format!("Kraken: {}", error))
+                } else {
+                    SourceError::fatal(format!("Kraken: {}", error))
+                },
+            );
+        }
+        let ticker = body
+            .result
+            .values()
+            .next()
+            .filter(|ticker| !ticker.c.is_empty())
+            .ok_or_else(|| SourceError::retryable("Kraken returned no ticker"))?;
+        Ok(ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price: parse_price("Kraken", &ticker.c[0])?,
+            timestamp: now_millis(),
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            // The last trade's lot size says nothing about how much traded
+            volume: ticker.v.get(1).and_then(|volume| parse_volume(volume)),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbaseResponse {
+    data: CoinbasePrice,
+}
+
+#[derive(Deserialize)]
+struct CoinbasePrice {
+    amount: String,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseStats {
+    /// Base volume over the last 24 hours
+    volume: String,
+}
+
+/// BTC/USD spot price from Coinbase, with the 24h volume from Coinbase
+/// Exchange's product stats
+pub struct CoinbaseSource {
+    client: Client,
+    url: String,
+    stats_url: String,
+}
+
+impl CoinbaseSource {
+    pub const DEFAULT_URL: &'static str = "https://api.coinbase.com/v2/prices/BTC-USD/spot";
+    pub const DEFAULT_STATS_URL: &'static str =
+        "https://api.exchange.coinbase.com/products/BTC-USD/stats";
+
+    /// Uses `COINBASE_API_URL` and `COINBASE_STATS_URL` when set
+    pub fn new(client: Client) -> Self {
+        CoinbaseSource {
+            client,
+            url: std::env::var("COINBASE_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+            stats_url: std::env::var("COINBASE_STATS_URL")
+                .unwrap_or_else(|_| Self::DEFAULT_STATS_URL.into()),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    pub fn with_stats_url(mut self, url: impl Into<String>) -> Self {
+        self.stats_url = url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl DataSource for CoinbaseSource {
+    fn name(&self) -> &str {
+        "Coinbase"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let body: CoinbaseResponse = get_json(&self.client, &self.url).await?;
+        let price = parse_price("Coinbase", &body.data.amount)?;
+        // The spot price has no volume; failed stats only cost the volume
+        let volume = match get_json::<CoinbaseStats>(&self.client, &self.stats_url).await {
+            Ok(stats) => parse_volume(&stats.volume),
+            Err(e) => {
+                debug!(error = %e, "Coinbase: No 24h volume");
+                None
+            }
+        };
+        Ok(ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price,
+            timestamp: now_millis(),
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume,
+        })
+    }
+}
+
+/// Alpha Vantage response: the quote under a function-specific key, or a
+/// throttling `Note`/`Information` or an `Error Message`, all with HTTP 200
+#[derive(Deserialize)]
+struct AlphaVantageResponse {
+    #[serde(rename = "Realtime Currency Exchange Rate")]
+    exchange_rate: Option<BTreeMap<String, String>>,
+    #[serde(rename = "Global Quote")]
+    global_quote: Option<BTreeMap<String, String>>,
+    #[serde(rename = "Note")]
+    note: Option<String>,
+    #[serde(rename = "Information")]
+    information: Option<String>,
+    #[serde(rename = "Error Message")]
+    error: Option<String>,
+}
+
+/// One FX pair or equity from Alpha Vantage
+///
+/// FX pairs use `CURRENCY_EXCHANGE_RATE`, equities `GLOBAL_QUOTE`; crypto
+/// assets are served by the exchange sources instead.
+pub struct AlphaVantageSource {
+    client: Client,
+    url: String,
+    api_key: String,
+    asset: String,
+    mapping: SymbolMapping,
+}
+
+impl AlphaVantageSource {
+    pub const DEFAULT_URL: &'static str = "https://www.alphavantage.co/query";
+
+    /// Quote `asset` as `symbols` maps it; uses `ALPHAVANTAGE_API_URL` when
+    /// set
+    pub fn new(
+        client: Client,
+        api_key: impl Into<String>,
+        asset: &str,
+        symbols: &SymbolMap,
+    ) -> Result<Self, String> {
+        let mapping = symbols.resolve(asset);
+        if mapping.class == AssetClass::Crypto {
+            return Err(format!(
+                "Alpha Vantage quotes FX pairs and equities; map {} in ASSET_SYMBOLS",
+                asset
+            ));
+        }
+        Ok(AlphaVantageSource {
+            client,
+            url: std::env::var("ALPHAVANTAGE_API_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+            api_key: api_key.into(),
+            asset: asset.to_ascii_uppercase(),
+            mapping,
+        })
+    }
+
+    /// `ALPHAVANTAGE_API_KEY`, `ALPHAVANTAGE_ASSET` and `ASSET_SYMBOLS`
+    pub fn from_env(client: Client) -> Result<Self, String> {
+        let asset = std::env::var("ALPHAVANTAGE_ASSET")
+            .map_err(|_| "ALPHAVANTAGE_ASSET is not set".to_string())?;
+        Self::from_env_for(client, asset.trim())
+    }
+
+    /// `asset` with the key from `ALPHAVANTAGE_API_KEY` and `ASSET_SYMBOLS`
+    pub fn from_env_for(client: Client, asset: &str) -> Result<Self, String> {
+        let api_key = std::env::var("ALPHAVANTAGE_API_KEY")
+            .map_err(|_| "ALPHAVANTAGE_API_KEY is not set".to_string())?;
+        Self::new(client, api_key, asset, &SymbolMap::from_env()?)
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    fn query_url(&self) -> String {
+        match (self.mapping.class, self.mapping.currency_pair()) {
+            (AssetClass::Fx, Some((base, quote))) => format!(
+                "{}?function=CURRENCY_EXCHANGE_RATE&from_currency={}&to_currency={}&apikey={}",
+                self.url, base, quote, self.api_key
+            ),
+            _ => format!(
+                "{}?function=GLOBAL_QUOTE&symbol={}&apikey={}",
+                self.url, self.mapping.symbol, self.api_key
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl DataSource for AlphaVantageSource {
+    fn name(&self) -> &str {
+        "AlphaVantage"
+    }
+
+    fn asset(&self) -> &str {
+        &self.asset
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let body: AlphaVantageResponse = get_json(&self.client, &self.query_url()).await?;
+        if let Some(note) = body.note.or(body.information) {
+            return Err(SourceError::throttled(
+                format!("AlphaVantage: {}", note),
+                Duration::from_secs(60),
+            ));
+        }
+        if let Some(error) = body.error {
+            return Err(SourceError::fatal(format!("AlphaVantage: {}", error)));
+        }
+        let (price, volume) = match self.mapping.class {
+            AssetClass::Fx => (
+                body.exchange_rate
+                    .and_then(|rate| rate.get("5. Exchange Rate").cloned()),
+                None,
+            ),
+            _ => match body.global_quote {
+                // The latest trading day's volume; FX rates come with none
+                Some(quote) => (
+                    quote.get("05. price").cloned(),
+                    quote
+                        .get("06. volume")
+                        .and_then(|volume| parse_volume(volume)),
+                ),
+                None => (None, None),
+            },
+        };
+        let price = price.ok_or_else(|| {
+            SourceError::retryable(format!("AlphaVantage returned no quote for {}", self.asset))
+        })?;
+        Ok(ExtractResult {
+            asset: self.asset.clone(),
+            price: parse_price("AlphaVantage", &price)?,
+            timestamp: now_millis(),
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume,
+        })
+    }
+}
+
+type SourceFactory = Arc<dyn Fn(Client) -> Result<Arc<dyn DataSource>, String> + Send + Sync>;
+type AssetSourceFactory =
+    Arc<dyn Fn(Client, &str) -> Result<Arc<dyn DataSource>, String> + Send + Sync>;
+
+/// Source constructors by name (case-insensitive)
+#[derive(Clone, Default)]
+pub struct SourceRegistry {
+    factories: HashMap<String, SourceFactory>,
+    asset_factories: HashMap<String, AssetSourceFactory>,
+}
+
+impl SourceRegistry {
+    /// CoinGecko, Kraken, Coinbase, Alpha Vantage, the offline mock and a
+    /// tick file
+    pub fn with_builtin() -> Self {
+        SourceRegistry::default()
+            .register("coingecko", |client| {
+                Ok(Arc::new(CoinGeckoSource::from_env(client)?))
+            })
+            .register("kraken", |client| Ok(Arc::new(KrakenSource::new(client))))
+            .register("coinbase", |client| {
+                Ok(Arc::new(CoinbaseSource::new(client)))
+            })
+            .register("alphavantage", |client| {
+                Ok(Arc::new(AlphaVantageSource::from_env(client)?))
+            })
+            .register_asset("alphavantage", |client, asset| {
+                Ok(Arc::new(AlphaVantageSource::from_env_for(client, asset)?))
+            })
+            .register("mock", |_| Ok(Arc::new(MockSource)))
+            .register("file", |_| Ok(Arc::new(FileSource::from_env()?)))
+    }
+
+    /// Add or replace the source called `name`; the factory fails when the
+    /// source's configuration is invalid
+    pub fn register(
+        mut self,
+        name: &str,
+        factory: impl Fn(Client) -> Result<Arc<dyn DataSource>, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.factories
+            .insert(name.to_ascii_lowercase(), Arc::new(factory));
+        self
+    }
+
+    /// Add or replace the constructor for `name` quoting a given asset
+    pub fn register_asset(
+        mut self,
+        name: &str,
+        factory: impl Fn(Client, &str) -> Result<Arc<dyn DataSource>, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.asset_factories
+            .insert(name.to_ascii_lowercase(), Arc::new(factory));
+        self
+    }
+
+    /// Registered names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn create(&self, name: &str, client: Client) -> Result<Arc<dyn DataSource>, String> {
+        let factory = self
+            .factories
+            .get(&name.trim().to_ascii_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "unknown data source '{}' (expected one of: {})",
+                    name,
+                    self.names().join(", ")
+                )
+            })?;
+        factory(client).map_err(|e| format!("data source '{}': {}", name.trim(), e))
+    }
+
+    /// Source `name` quoting `asset`
+    pub fn create_for_asset(
+        &self,
+        name: &str,
+        asset: &str,
+        client: Client,
+    ) -> Result<Arc<dyn DataSource>, String> {
+        let factory = self
+            .asset_factories
+            .get(&name.trim().to_ascii_lowercase())
+            .ok_or_else(|| {
+                let mut names: Vec<&str> =
+                    self.asset_factories.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                format!(
+                    "data source '{}' cannot quote {} (sources quoting any asset: {})",
+                    name,
+                    asset,
+                    names.join(", ")
+                )
+            })?;
+        factory(client, asset.trim())
+    }
+
+    /// Sources for `ASSET:source` pairs separated by commas
+    pub fn create_assets(
+        &self,
+        spec: &str,
+        client: Client,
+    ) -> Result<Vec<Arc<dyn DataSource>>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (asset, name) = entry.split_once(':').ok_or_else(|| {
+                    format!("invalid asset entry '{}' (expected ASSET:source)", entry)
+                })?;
+                self.create_for_asset(name, asset, client.clone())
+            })
+            .collect()
+    }
+
+    /// Sources for the assets in `MARKET_DATA_ASSETS`; none when unset
+    pub fn assets_from_env(&self, client: Client) -> Result<Vec<Arc<dyn DataSource>>, String> {
+        match std::env::var("MARKET_DATA_ASSETS") {
+            Ok(spec) => self
+                .create_assets(&spec, client)
+                .map_err(|e| format!("invalid MARKET_DATA_ASSETS: {}", e)),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// One source, or an `AggregatingExtractor` over each of several names
+    /// separated by commas
+    pub fn create_list(&self, names: &str, client: Client) -> Result<Arc<dyn DataSource>, String> {
+        let names: Vec<&str> = names.split(',').filter(|n| !n.trim().is_empty()).collect();
+        if names.len() == 1 {
+            return self.create(names[0], client);
+        }
+        let sources = names
+            .into_iter()
+            .map(|name| self.create(name, client.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Arc::new(
+            AggregatingExtractor::new(sources).with_env_overrides()?,
+        ))
+    }
+
+    /// The source(s) named by `MARKET_DATA_SOURCE`, or `DEFAULT_SOURCE`
+    pub fn source_from_env(&self, client: Client) -> Result<Arc<dyn DataSource>, String> {
+        match std::env::var("MARKET_DATA_SOURCE") {
+            Ok(names) if !names.trim().is_empty() => self.create_list(&names, client),
+            _ => self.create(DEFAULT_SOURCE, client),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    fn dec(price: &str) -> Decimal {
+        price::parse(price).unwrap()
+    }
+
+    #[test]
+    fn test_non_finite_quotes_are_refused() {
+        for text in ["NaN", "inf", "-infinity", ""] {
+            assert!(parse_price("Kraken", text).is_err(), "{}", text);
+            assert_eq!(parse_volume(text), None);
+        }
+        assert_eq!(parse_volume("-1"), None);
+        assert_eq!(parse_price("Kraken", "1e-8").unwrap(), dec("0.00000001"));
+    }
+
+    fn start_exchange() -> String {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route(
+                    "/kraken",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "error": [],
+                            "result": { "XXBTZUSD": {
+                                "c": ["64012.5", "0.01"],
+                                "v": ["812.4", "1530.25"]
+                            } }
+                        }))
+                    }),
+                )
+                .route(
+                    "/kraken-limited",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "error": ["EAPI:Rate limit exceeded"]
+                        }))
+                    }),
+                )
+                .route(
+                    "/alphavantage",
+                    web::get().to(|query: web::Query<HashMap<String, String>>| async move {
+                        HttpResponse::Ok().json(match query.get("function").map(String::as_str) {
+                            Some("CURRENCY_EXCHANGE_RATE") => json!({
+                                "Realtime Currency Exchange Rate": {
+                                    "1. From_Currency Code": query["from_currency"],
+                                    "5. Exchange Rate": "1.08450000"
+                                }
+                            }),
+                            _ if query["symbol"] == "THROTTLED" => json!({
+                                "Note": "API call frequency is 5 calls per minute"
+                            }),
+                            _ => json!({ "Global Quote": {
+                                "05. price": "189.9500",
+                                "06. volume": "52164535"
+                            } }),
+                        })
+                    }),
+                )
+                .route(
+                    "/coinbase",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "data": { "amount": "64010.25", "base": "BTC", "currency": "USD" }
+                        }))
+                    }),
+                )
+                .route(
+                    "/coinbase-stats",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "open": "63500.00", "last": "64010.25", "volume": "9810.5"
+                        }))
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    #[actix_web::test]
+    async fn test_exchange_sources_and_registry() {
+        let base = start_exchange();
+        let client = Client::new();
+
+        let kraken = KrakenSource::new(client.clone()).with_url(format!("{}/kraken", base));
+        let quote = kraken.fetch().await.unwrap();
+        assert_eq!(
+            (quote.price, quote.source.as_str()),
+            (dec("64012.5"), "Kraken")
+        );
+        assert_eq!(quote.volume, Some(dec("1530.25")));
+
+        let limited = kraken.with_url(format!("{}/kraken-limited", base));
+        let err = limited.fetch().await.unwrap_err();
+        assert!(matches!(err.class, crate::retry::RetryClass::Throttled(_)));
+
+        let coinbase = CoinbaseSource::new(client.clone())
+            .with_url(format!("{}/coinbase", base))
+            .with_stats_url(format!("{}/coinbase-stats", base));
+        let quote = coinbase.fetch().await.unwrap();
+        assert_eq!(
+            (quote.price, quote.volume),
+            (dec("64010.25"), Some(dec("9810.5")))
+        );
+        // Without the stats the price still comes through
+        let coinbase = coinbase.with_stats_url(format!("{}/missing", base));
+        assert_eq!(coinbase.fetch().await.unwrap().volume, None);
+
+        let symbols =
+            SymbolMap::parse("EURUSD=fx:EUR/USD,AAPL=equity:AAPL,T=equity:THROTTLED").unwrap();
+        let alpha = |asset: &str| {
+            AlphaVantageSource::new(client.clone(), "demo", asset, &symbols)
+                .map(|source| source.with_url(format!("{}/alphavantage", base)))
+        };
+        let fx = alpha("EURUSD").unwrap().fetch().await.unwrap();
+        assert_eq!((fx.asset.as_str(), fx.price), ("EURUSD", dec("1.0845")));
+        assert_eq!(alpha("EURUSD").unwrap().asset(), "EURUSD");
+        let equity = alpha("AAPL").unwrap().fetch().await.unwrap();
+        assert_eq!(
+            (equity.asset.as_str(), equity.price),
+            ("AAPL", dec("189.95"))
+        );
+        assert_eq!(
+            (fx.volume, equity.volume),
+            (None, Some(Decimal::from(52_164_535)))
+        );
+        let err = alpha("T").unwrap().fetch().await.unwrap_err();
+        assert!(matches!(err.class, crate::retry::RetryClass::Throttled(_)));
+        assert!(alpha("BTC").is_err());
+
+        // The key travels in the query, which errors must not repeat
+        let unreachable = AlphaVantageSource::new(client.clone(), "secret-key", "AAPL", &symbols)
+            .unwrap()
+            .with_url("http://127.0.0.1:1/query");
+        let err = unreachable.fetch().await.unwrap_err();
+        assert!(err.message.starts_with("Request error"));
+        assert!(!err.message.contains("secret-key"));
+
+        let registry = SourceRegistry::with_builtin();
+        assert_eq!(
+            registry.names(),
+            vec![
+                "alphavantage",
+                "coinbase",
+                "coingecko",
+                "file",
+                "kraken",
+                "mock"
+            ]
+        );
+        assert_eq!(
+            registry.create("Kraken", client.clone()).unwrap().name(),
+            "Kraken"
+        );
+        assert!(registry
+            .create("binance", client.clone())
+            .map(|_| ())
+            .unwrap_err()
+            .contains("alphavantage, coinbase, coingecko, file, kraken, mock"));
+        // A source without its settings is refused when it is created
+        assert!(registry
+            .create("file", client.clone())
+            .map(|_| ())
+            .unwrap_err()
+            .contains("MARKET_DATA_FILE is not set"));
+
+        // Sources outside the crate register the same way
+        let kraken_url = format!("{}/kraken", base);
+        let registry = registry.register("internal", move |client| {
+            Ok(Arc::new(
+                KrakenSource::new(client).with_url(kraken_url.clone()),
+            ))
+        });
+        assert_eq!(
+            registry.create("internal", client.clone()).unwrap().name(),
+            "Kraken"
+        );
+
+        // Further assets name a source that can quote them
+        let alpha_url = format!("{}/alphavantage", base);
+        let registry = registry.register_asset("alpha", move |client, asset| {
+            let symbols = SymbolMap::parse("EURUSD=fx:EUR/USD,AAPL=equity:AAPL")?;
+            Ok(Arc::new(
+                AlphaVantageSource::new(client, "demo", asset, &symbols)?
+                    .with_url(alpha_url.clone()),
+            ))
+        });
+        let assets = registry
+            .create_assets("EURUSD:alpha, AAPL:Alpha", client.clone())
+            .unwrap();
+        let mut prices = Vec::new();
+        for source in &assets {
+            let quote = source.fetch().await.unwrap();
+            prices.push((quote.asset, quote.price));
+        }
+        assert_eq!(
+            prices,
+            vec![
+                ("EURUSD".to_string(), dec("1.0845")),
+                ("AAPL".to_string(), dec("189.95"))
+            ]
+        );
+        assert!(registry
+            .create_assets("EURUSD:kraken", client.clone())
+            .map(|_| ())
+            .unwrap_err()
+            .contains("cannot quote EURUSD"));
+        assert!(registry.create_assets("EURUSD", client.clone()).is_err());
+
+        // A list of names aggregates the sources
+        let aggregate = registry.create_list("internal, mock", client).unwrap();
+        assert_eq!(aggregate.name(), "Aggregate");
+        let quote = aggregate.fetch().await.unwrap();
+        assert_eq!(quote.source, "Aggregate(Kraken,MockData)");
+        assert_eq!(quote.quotes.len(), 2);
+        assert!(registry
+            .create_list("kraken,binance", Client::new())
+            .is_err());
+    }
+}
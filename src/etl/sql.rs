@@ -0,0 +1,339 @@
+//! Ad-hoc SQL over market history
+//!
+//! Enabled with the `analytics` feature. `LedgerQuery::attach` copies the
+//! ledger into an in-memory SQLite database, decrypting payloads on the way,
+//! as two tables:
+//!
+//! ```text
+//! blocks(block_index, timestamp, hash, previous_hash, entries, format_version)
+//! market_data(block_index, position, asset, price, source, timestamp)
+//! ```
+//!
+//! `query_sql` runs any statement against that copy and returns the rows as
+//! Arrow record batches, so a query can neither slow down nor modify the
+//! ledger itself. `export_parquet` writes a query's rows as Parquet for
+//! DuckDB, Polars or Spark:
+//!
+//! ```ignore
+//! let query = LedgerQuery::attach(&db)?;
+//! let batches = query.query_sql(
+//!     "SELECT asset, date(timestamp / 1000, 'unixepoch') AS day, avg(price) AS avg_price
+//!      FROM market_data GROUP BY asset, day ORDER BY day",
+//! )?;
+//! query.export_parquet("SELECT * FROM market_data", File::create("history.parquet")?)?;
+//! ```
+//!
+//! Timestamps are milliseconds, as in the ledger. Column types follow the
+//! values a query returns: integers become `Int64`, numbers `Float64`, blobs
+//! `Binary`; a column holding any text is `Utf8`.
+
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult};
+use crate::etl::price;
+use arrow_array::builder::{BinaryBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::io::Write;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+/// Rows per record batch unless set with `with_batch_size`
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+const SCHEMA_SQL: &str = "
+    CREATE TABLE blocks (
+        block_index    INTEGER PRIMARY KEY,
+        timestamp      INTEGER NOT NULL,
+        hash           TEXT NOT NULL,
+        previous_hash  TEXT NOT NULL,
+        entries        INTEGER NOT NULL,
+        format_version INTEGER NOT NULL
+    );
+    CREATE TABLE market_data (
+        block_index INTEGER NOT NULL,
+        position    INTEGER NOT NULL,
+        asset       TEXT NOT NULL,
+        price       REAL NOT NULL,
+        source      TEXT NOT NULL,
+        timestamp   INTEGER NOT NULL,
+        PRIMARY KEY (block_index, position)
+    );
+    CREATE INDEX market_data_asset ON market_data (asset, timestamp);";
+
+/// A queryable copy of the ledger's market history
+pub struct LedgerQuery {
+    conn: Connection,
+    batch_size: usize,
+}
+
+impl LedgerQuery {
+    /// Copy the whole ledger
+    pub fn attach(db: &DatabaseManager) -> DbResult<Self> {
+        Self::attach_range(db, ..)
+    }
+
+    /// Copy the blocks whose index falls in `range`
+    pub fn attach_range(db: &DatabaseManager, range: impl RangeBounds<u64>) -> DbResult<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA_SQL)?;
+        let tx = conn.transaction()?;
+        {
+            let mut insert_block = tx.prepare(
+                "INSERT INTO blocks (block_index, timestamp, hash, previous_hash, entries, format_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            let mut insert_entry = tx.prepare(
+                "INSERT INTO market_data (block_index, position, asset, price, source, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for block in db.iter_blocks(range) {
+                let block = block?;
+                let index = block.index as i64;
+                insert_block.execute(params![
+                    index,
+                    block.timestamp,
+                    block.hash,
+                    block.previous_hash,
+                    block.data.len() as i64,
+                    block.format_version,
+                ])?;
+                for (position, entry) in block.data.iter().enumerate() {
+                    insert_entry.execute(params![
+                        index,
+                        position as i64,
+                        entry.asset,
+                        price::to_f64(entry.price),
+                        entry.source,
+                        entry.timestamp,
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(LedgerQuery {
+            conn,
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Split results into batches of at most `rows` rows
+    pub fn with_batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    /// Run `sql` against the copy. A query with no rows returns one empty
+    /// batch carrying its columns; a statement without columns returns none.
+    pub fn query_sql(&self, sql: &str) -> DbResult<Vec<RecordBatch>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut columns: Vec<Vec<Value>> = vec![Vec::new(); names.len()];
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            for (i, column) in columns.iter_mut().enumerate() {
+                column.push(row.get(i)?);
+            }
+        }
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let types: Vec<DataType> = columns.iter().map(|values| column_type(values)).collect();
+        let schema: SchemaRef = Arc::new(Schema::new(
+            names
+                .iter()
+                .zip(&types)
+                .map(|(name, data_type)| Field::new(name, data_type.clone(), true))
+                .collect::<Vec<_>>(),
+        ));
+        let row_count = columns[0].len();
+        let mut batches = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + self.batch_size).min(row_count);
+            let arrays: Vec<ArrayRef> = columns
+                .iter()
+                .zip(&types)
+                .map(|(values, data_type)| build_array(&values[start..end], data_type))
+                .collect();
+            batches.push(RecordBatch::try_new(schema.clone(), arrays).map_err(arrow_error)?);
+            if end == row_count {
+                break;
+            }
+            start = end;
+        }
+        Ok(batches)
+    }
+
+    /// Write the rows of `sql` to `out` as a Parquet file; returns how many
+    /// were written
+    pub fn export_parquet(&self, sql: &str, out: impl Write + Send) -> DbResult<usize> {
+        let batches = self.query_sql(sql)?;
+        let schema = batches
+            .first()
+            .map(|batch| batch.schema())
+            .ok_or_else(|| DatabaseError::InvalidData("export: query returns no columns".into()))?;
+        let parquet_error =
+            |e: parquet::errors::ParquetError| DatabaseError::InvalidData(format!("export: {}", e));
+        let mut writer = ArrowWriter::try_new(out, schema, None).map_err(parquet_error)?;
+        let mut rows = 0;
+        for batch in &batches {
+            writer.write(batch).map_err(parquet_error)?;
+            rows += batch.num_rows();
+        }
+        writer.close().map_err(parquet_error)?;
+        Ok(rows)
+    }
+}
+
+fn arrow_error(err: ArrowError) -> DatabaseError {
+    DatabaseError::InvalidData(format!("arrow: {}", err))
+}
+
+/// Narrowest Arrow type holding every value of a result column
+fn column_type(values: &[Value]) -> DataType {
+    let (mut integer, mut real, mut blob) = (false, false, false);
+    for value in values {
+        match value {
+            Value::Null => {}
+            Value::Integer(_) => integer = true,
+            Value::Real(_) => real = true,
+            Value::Blob(_) => blob = true,
+            Value::Text(_) => return DataType::Utf8,
+        }
+    }
+    match (integer, real, blob) {
+        (false, false, true) => DataType::Binary,
+        (_, _, true) => DataType::Utf8,
+        (_, true, _) => DataType::Float64,
+        (true, _, _) => DataType::Int64,
+        // Only NULLs
+        _ => DataType::Utf8,
+    }
+}
+
+fn build_array(values: &[Value], data_type: &DataType) -> ArrayRef {
+    match data_type {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Value::Integer(n) => builder.append_value(*n),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Value::Integer(n) => builder.append_value(*n as f64),
+                    Value::Real(x) => builder.append_value(*x),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => {
+            let mut builder = BinaryBuilder::new();
+            for value in values {
+                match value {
+                    Value::Blob(bytes) => builder.append_value(bytes),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        _ => {
+            let mut builder = StringBuilder::new();
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    Value::Integer(n) => builder.append_value(n.to_string()),
+                    Value::Real(x) => builder.append_value(x.to_string()),
+                    Value::Text(text) => builder.append_value(text),
+                    Value::Blob(bytes) => builder.append_value(hex::encode(bytes)),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing::{MarketDataGenerator, TestChainBuilder};
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray};
+
+    #[test]
+    fn test_query_sql_returns_record_batches() {
+        let db = TestChainBuilder::new()
+            .with_blocks(4)
+            .with_entries_per_block(2)
+            .with_generator(MarketDataGenerator::new(7).with_assets([
+                ("BTC", Decimal::from(50_000)),
+                ("ETH", Decimal::from(3_000)),
+            ]))
+            .build_in_memory()
+            .unwrap();
+        let query = LedgerQuery::attach(&db).unwrap().with_batch_size(3);
+
+        let batches = query
+            .query_sql("SELECT asset, count(*) AS n, avg(price) AS avg_price FROM market_data GROUP BY asset ORDER BY asset")
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let schema = batch.schema();
+        let types: Vec<&DataType> = schema.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            vec![&DataType::Utf8, &DataType::Int64, &DataType::Float64]
+        );
+        let assets = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let counts = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        let prices = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!((assets.value(0), counts.value(0)), ("BTC", 4));
+        assert_eq!((assets.value(1), counts.value(1)), ("ETH", 4));
+        assert!((prices.value(1) - 3_000.0).abs() < 300.0);
+
+        // Large results are split into batches of the configured size
+        let batches = query
+            .query_sql("SELECT * FROM market_data ORDER BY block_index, position")
+            .unwrap();
+        let sizes: Vec<usize> = batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(sizes, vec![3, 3, 2]);
+
+        // Empty results keep their columns; the copy is isolated from the ledger
+        let empty = query
+            .query_sql("SELECT hash FROM blocks WHERE entries > 2")
+            .unwrap();
+        assert_eq!((empty.len(), empty[0].num_rows()), (1, 0));
+        assert!(query.query_sql("DELETE FROM blocks").unwrap().is_empty());
+        assert_eq!(db.get_block_count().unwrap(), 4);
+        assert!(query.query_sql("SELECT nonsense FROM blocks").is_err());
+
+        let mut parquet = Vec::new();
+        let rows = query
+            .export_parquet("SELECT * FROM market_data", &mut parquet)
+            .unwrap();
+        assert_eq!(rows, 8);
+        assert_eq!(&parquet[..4], b"PAR1");
+    }
+}
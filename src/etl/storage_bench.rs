@@ -0,0 +1,347 @@
+//! Write benchmarks for the load stage's storage backends
+//!
+//! Persistence is often slower than consensus, so the benchmark harness
+//! also times the load stage on its own: every block is written with one
+//! call, as `save_block` does in the node, and each backend reports write
+//! latency percentiles and throughput.
+//!
+//! Backends:
+//! - `sqlite` - a database file with SQLite's defaults (rollback journal,
+//!   `synchronous=FULL`)
+//! - `sqlite_wal` - a database file in WAL mode with `synchronous=NORMAL`
+//! - `memory` - an in-memory SQLite database, a lower bound with no I/O
+//! - `postgres` - a `bench_blocks_<id>` table on the server at the given
+//!   URL, named per run so concurrent runs and existing tables are left
+//!   alone and dropped afterwards; needs the `postgres` cargo feature
+
+use crate::etl::load::{DatabaseManager, DbResult, JournalMode, SyncPolicy};
+use crate::etl::Block;
+use crate::system_metrics::{SystemMetrics, SystemMonitor};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    Sqlite,
+    SqliteWal,
+    Memory,
+    /// Connection string, e.g. `host=localhost user=postgres`
+    Postgres(String),
+}
+
+impl StorageBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageBackend::Sqlite => "sqlite",
+            StorageBackend::SqliteWal => "sqlite_wal",
+            StorageBackend::Memory => "memory",
+            StorageBackend::Postgres(_) => "postgres",
+        }
+    }
+}
+
+/// Write performance of one backend
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageMetrics {
+    pub backend: String,
+    pub blocks: usize,
+    pub failed_writes: usize,
+    pub min_write_ms: f64,
+    pub p50_write_ms: f64,
+    pub p99_write_ms: f64,
+    pub max_write_ms: f64,
+    pub avg_write_ms: f64,
+    pub throughput_blocks_per_sec: f64,
+    /// This process's memory and CPU over the run; for `postgres` the
+    /// server's own usage is not included
+    pub system: SystemMetrics,
+}
+
+impl StorageMetrics {
+    fn from_latencies(backend: &str, mut latencies: Vec<f64>, failed: usize, secs: f64) -> Self {
+        latencies.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |pct: f64| {
+            if latencies.is_empty() {
+                return 0.0;
+            }
+            let rank = (pct / 100.0 * latencies.len() as f64).ceil() as usize;
+            latencies[rank.clamp(1, latencies.len()) - 1]
+        };
+        let written = latencies.len();
+        StorageMetrics {
+            backend: backend.to_string(),
+            blocks: written + failed,
+            failed_writes: failed,
+            min_write_ms: latencies.first().copied().unwrap_or(0.0),
+            p50_write_ms: percentile(50.0),
+            p99_write_ms: percentile(99.0),
+            max_write_ms: latencies.last().copied().unwrap_or(0.0),
+            avg_write_ms: if written > 0 {
+                latencies.iter().sum::<f64>() / written as f64
+            } else {
+                0.0
+            },
+            throughput_blocks_per_sec: if secs > 0.0 {
+                written as f64 / secs
+            } else {
+                0.0
+            },
+            system: SystemMetrics::default(),
+        }
+    }
+}
+
+/// Write `blocks` to a fresh store of `backend`; SQLite files are created in
+/// `dir` and removed afterwards
+pub async fn benchmark_storage(
+    backend: &StorageBackend,
+    blocks: &[Block],
+    dir: &Path,
+) -> Result<StorageMetrics, Box<dyn Error>> {
+    let monitor = SystemMonitor::new();
+    let mut metrics = match backend {
+        StorageBackend::Postgres(url) => postgres::benchmark(url, blocks).await?,
+        _ => {
+            let backend = backend.clone();
+            let blocks = blocks.to_vec();
+            let dir = dir.to_path_buf();
+            tokio::task::spawn_blocking(move || benchmark_sqlite(&backend, &blocks, &dir)).await??
+        }
+    };
+    metrics.system = monitor.sample();
+    Ok(metrics)
+}
+
+fn benchmark_sqlite(
+    backend: &StorageBackend,
+    blocks: &[Block],
+    dir: &Path,
+) -> DbResult<StorageMetrics> {
+    let path = dir.join(format!(
+        "storage-bench-{}-{}.db",
+        backend.name(),
+        std::process::id()
+    ));
+    remove_sqlite_files(&path);
+
+    let db = match backend {
+        StorageBackend::Memory => DatabaseManager::in_memory()?,
+        _ => DatabaseManager::new(&path.to_string_lossy())?,
+    };
+    db.init()?;
+    if *backend == StorageBackend::SqliteWal {
+        db.set_journal_mode(JournalMode::Wal)?;
+        db.set_sync_policy(SyncPolicy::Normal)?;
+    }
+
+    let mut latencies = Vec::with_capacity(blocks.len());
+    let mut failed = 0;
+    let start = Instant::now();
+    for block in blocks {
+        let write = Instant::now();
+        match db.save_block(block) {
+            Ok(()) => latencies.push(write.elapsed().as_secs_f64() * 1000.0),
+            Err(_) => failed += 1,
+        }
+    }
+    let secs = start.elapsed().as_secs_f64();
+
+    drop(db);
+    remove_sqlite_files(&path);
+    Ok(StorageMetrics::from_latencies(
+        backend.name(),
+        latencies,
+        failed,
+        secs,
+    ))
+}
+
+fn remove_sqlite_files(path: &Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        std::fs::remove_file(file).ok();
+    }
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use super::StorageMetrics;
+    use crate::etl::Block;
+    use std::error::Error;
+    use std::time::Instant;
+    use tokio_postgres::{Client, NoTls};
+
+    pub async fn benchmark(url: &str, blocks: &[Block]) -> Result<StorageMetrics, Box<dyn Error>> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        let driver = tokio::spawn(connection);
+
+        // Hex digits only, so the name needs no quoting
+        let table = format!("bench_blocks_{}", &crate::logger::new_trace_id()[..16]);
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE {} (
+                    block_index BIGINT PRIMARY KEY,
+                    timestamp   BIGINT NOT NULL,
+                    data_json   TEXT NOT NULL,
+                    prev_hash   TEXT NOT NULL,
+                    hash        TEXT NOT NULL UNIQUE,
+                    nonce       BIGINT NOT NULL,
+                    fees_json   TEXT NOT NULL
+                )",
+                table
+            ))
+            .await?;
+        let result = write_blocks(&client, &table, blocks).await;
+        client
+            .batch_execute(&format!("DROP TABLE {}", table))
+            .await?;
+        drop(client);
+        driver.await.ok();
+        result
+    }
+
+    async fn write_blocks(
+        client: &Client,
+        table: &str,
+        blocks: &[Block],
+    ) -> Result<StorageMetrics, Box<dyn Error>> {
+        let insert = client
+            .prepare(&format!(
+                "INSERT INTO {}
+                 (block_index, timestamp, data_json, prev_hash, hash, nonce, fees_json)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                table
+            ))
+            .await?;
+
+        let mut latencies = Vec::with_capacity(blocks.len());
+        let mut failed = 0;
+        let start = Instant::now();
+        for block in blocks {
+            let write = Instant::now();
+            let data_json = serde_json::to_string(&block.data)?;
+            let fees_json = serde_json::to_string(&block.fees)?;
+            let result = client
+                .execute(
+                    &insert,
+                    &[
+                        &(block.index as i64),
+                        &block.timestamp,
+                        &data_json,
+                        &block.previous_hash,
+                        &block.hash,
+                        &(block.nonce as i64),
+                        &fees_json,
+                    ],
+                )
+                .await;
+            match result {
+                Ok(_) => latencies.push(write.elapsed().as_secs_f64() * 1000.0),
+                Err(_) => failed += 1,
+            }
+        }
+        let secs = start.elapsed().as_secs_f64();
+        Ok(StorageMetrics::from_latencies(
+            "postgres", latencies, failed, secs,
+        ))
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+mod postgres {
+    use super::StorageMetrics;
+    use crate::etl::Block;
+    use std::error::Error;
+
+    pub async fn benchmark(
+        _url: &str,
+        _blocks: &[Block],
+    ) -> Result<StorageMetrics, Box<dyn Error>> {
+        Err("the postgres backend needs the `postgres` feature".into())
+    }
+}
+
+/// Print storage results in a formatted table
+pub fn print_storage_comparison(metrics: &[StorageMetrics]) {
+    println!("\n{}", "=".repeat(130));
+    println!("  Storage Backend Write Comparison (load stage)");
+    println!("{}", "=".repeat(130));
+    println!();
+    println!(
+        "{:<12} | {:<8} | {:<8} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<10} | {:<8} | {:<6}",
+        "Backend",
+        "Blocks",
+        "Failed",
+        "Min(ms)",
+        "p50(ms)",
+        "p99(ms)",
+        "Max(ms)",
+        "Avg(ms)",
+        "Blocks/sec",
+        "RSS",
+        "CPU%"
+    );
+    println!("{}", "-".repeat(130));
+
+    for metric in metrics {
+        println!(
+            "{:<12} | {:<8} | {:<8} | {:<10.3} | {:<10.3} | {:<10.3} | {:<10.3} | {:<10.3} | {:<10.2} | {:<8} | {}",
+            metric.backend,
+            metric.blocks,
+            metric.failed_writes,
+            metric.min_write_ms,
+            metric.p50_write_ms,
+            metric.p99_write_ms,
+            metric.max_write_ms,
+            metric.avg_write_ms,
+            metric.throughput_blocks_per_sec,
+            metric.system.memory_display(),
+            metric
+                .system
+                .cpu_percent
+                .map_or("N/A".to_string(), |cpu| format!("{:.1}", cpu))
+        );
+    }
+
+    println!("{}", "=".repeat(130));
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestChainBuilder;
+
+    #[tokio::test]
+    async fn test_sqlite_backends_write_every_block() {
+        let dir = std::env::temp_dir();
+        let blocks = TestChainBuilder::new().with_blocks(20).build();
+
+        for backend in [
+            StorageBackend::Sqlite,
+            StorageBackend::SqliteWal,
+            StorageBackend::Memory,
+        ] {
+            let metrics = benchmark_storage(&backend, &blocks, &dir).await.unwrap();
+            assert_eq!(metrics.backend, backend.name());
+            assert_eq!((metrics.blocks, metrics.failed_writes), (20, 0));
+            assert!(metrics.min_write_ms <= metrics.p50_write_ms);
+            assert!(metrics.p99_write_ms <= metrics.max_write_ms);
+            assert!(metrics.throughput_blocks_per_sec > 0.0);
+            assert!(!dir
+                .join(format!(
+                    "storage-bench-{}-{}.db",
+                    backend.name(),
+                    std::process::id()
+                ))
+                .exists());
+        }
+
+        let db = DatabaseManager::in_memory().unwrap();
+        assert_eq!(db.set_journal_mode(JournalMode::Wal).unwrap(), "memory");
+    }
+}
@@ -0,0 +1,484 @@
+//! Streaming extraction over exchange WebSocket feeds
+//!
+//! `Extractor::extract` polls its `DataSource` once per round. An extractor
+//! with a `StreamingSource` (`Extractor::with_stream_source`) can instead
+//! `stream`: a background task subscribes to the exchange's WebSocket feed
+//! and delivers every tick as an `ExtractResult` on a `PriceStream`. Ticks
+//! are validated like polled prices, and invalid ones are dropped. A lost
+//! connection is re-established after the extractor's `RetryPolicy` backoff;
+//! the stream ends once `max_attempts` connections in a row fail without
+//! delivering a tick, or on a fatal error such as a rejected subscription.
+//!
+//! The node streams when `MARKET_DATA_STREAM` names a feed (`kraken` or
+//! `coinbase`), building each block from the latest tick instead of polling.
+//! `KRAKEN_WS_URL` / `COINBASE_WS_URL` override the endpoints.
+
+use crate::etl::extract::{ExtractResult, SourceError};
+use crate::etl::price::{self, Decimal};
+use crate::etl::validator::Validator;
+use crate::etl::{now_millis, DEFAULT_ASSET};
+use crate::retry::{RetryClass, RetryPolicy};
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde::Deserialize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// Ticks buffered for a slow consumer before the feed waits for it
+const STREAM_BUFFER: usize = 256;
+
+/// An exchange WebSocket feed
+pub trait StreamingSource: Send + Sync {
+    /// Recorded as the `source` of the market data
+    fn name(&self) -> &str;
+
+    fn url(&self) -> &str;
+
+    /// Frames sent once connected, e.g. a ticker subscription
+    fn subscribe_messages(&self) -> Vec<String>;
+
+    /// Price carried by a text frame; `Ok(None)` for frames without one,
+    /// such as heartbeats and subscription acknowledgements
+    fn parse(&self, frame: &str) -> Result<Option<Decimal>, SourceError>;
+}
+
+/// Lets a caller keep a handle on a feed it registers
+impl<T: StreamingSource + ?Sized> StreamingSource for Arc<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn url(&self) -> &str {
+        (**self).url()
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        (**self).subscribe_messages()
+    }
+
+    fn parse(&self, frame: &str) -> Result<Option<Decimal>, SourceError> {
+        (**self).parse(frame)
+    }
+}
+
+#[derive(Deserialize)]
+struct KrakenFrame {
+    #[serde(default)]
+    channel: String,
+    #[serde(default)]
+    success: Option<bool>,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    data: Vec<KrakenTick>,
+}
+
+#[derive(Deserialize)]
+struct KrakenTick {
+    #[serde(with = "price::serde_number")]
+    last: Decimal,
+}
+
+/// BTC/USD last trade from Kraken's v2 ticker channel
+pub struct KrakenStream {
+    url: String,
+}
+
+impl KrakenStream {
+    pub const DEFAULT_URL: &'static str = "wss://ws.kraken.com/v2";
+
+    /// Uses `KRAKEN_WS_URL` when set
+    pub fn new() -> Self {
+        KrakenStream {
+            url: std::env::var("KRAKEN_WS_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+}
+
+impl Default for KrakenStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingSource for KrakenStream {
+    fn name(&self) -> &str {
+        "Kraken"
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![serde_json::json!({
+            "method": "subscribe",
+            "params": { "channel": "ticker", "symbol": ["BTC/USD"] }
+        })
+        .to_string()]
+    }
+
+    fn parse(&self, frame: &str) -> Result<Option<Decimal>, SourceError> {
+        let frame: KrakenFrame = serde_json::from_str(frame)
+            .map_err(|e| SourceError::retryable(format!("Kraken sent invalid JSON: {}", e)))?;
+        if frame.success == Some(false) {
+            return Err(SourceError::fatal(format!(
+                "Kraken: {}",
+                frame
+                    .error
+                    .unwrap_or_else(|| "subscription rejected".to_string())
+            )));
+        }
+        if frame.channel != "ticker" {
+            return Ok(None);
+        }
+        Ok(frame.data.last().map(|tick| tick.last))
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbaseFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    price: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// BTC/USD trades from Coinbase Exchange's ticker channel
+pub struct CoinbaseStream {
+    url: String,
+}
+
+impl CoinbaseStream {
+    pub const DEFAULT_URL: &'static str = "wss://ws-feed.exchange.coinbase.com";
+
+    /// Uses `COINBASE_WS_URL` when set
+    pub fn new() -> Self {
+        CoinbaseStream {
+            url: std::env::var("COINBASE_WS_URL").unwrap_or_else(|_| Self::DEFAULT_URL.into()),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+}
+
+impl Default for CoinbaseStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingSource for CoinbaseStream {
+    fn name(&self) -> &str {
+        "Coinbase"
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![serde_json::json!({
+            "type": "subscribe",
+            "product_ids": ["BTC-USD"],
+            "channels": ["ticker"]
+        })
+        .to_string()]
+    }
+
+    fn parse(&self, frame: &str) -> Result<Option<Decimal>, SourceError> {
+        let frame: CoinbaseFrame = serde_json::from_str(frame)
+            .map_err(|e| SourceError::retryable(format!("Coinbase sent invalid JSON: {}", e)))?;
+        match frame.kind.as_str() {
+            "error" => Err(SourceError::fatal(format!(
+                "Coinbase: {}",
+                frame.message.unwrap_or_default()
+            ))),
+            "ticker" => {
+                let price = frame.price.unwrap_or_default();
+                price::parse(&price).map(Some).map_err(|_| {
+                    SourceError::retryable(format!("Coinbase sent unparseable price '{}'", price))
+                })
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The feed named by `MARKET_DATA_STREAM`; `Ok(None)` when it is unset
+pub fn from_env() -> Result<Option<Arc<dyn StreamingSource>>, String> {
+    let Ok(name) = std::env::var("MARKET_DATA_STREAM") else {
+        return Ok(None);
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+        "" => Ok(None),
+        "kraken" => Ok(Some(Arc::new(KrakenStream::new()))),
+        "coinbase" => Ok(Some(Arc::new(CoinbaseStream::new()))),
+        other => Err(format!(
+            "unknown market data stream '{}' (expected kraken or coinbase)",
+            other
+        )),
+    }
+}
+
+/// Ticks from a streaming feed, in arrival order
+///
+/// Also a `futures::Stream`. Dropping it stops the feed.
+pub struct PriceStream {
+    source: String,
+    receiver: mpsc::Receiver<ExtractResult>,
+}
+
+impl PriceStream {
+    pub fn source_name(&self) -> &str {
+        &self.source
+    }
+
+    /// The next tick; `None` once the feed has ended
+    pub async fn next_tick(&mut self) -> Option<ExtractResult> {
+        self.receiver.recv().await
+    }
+
+    /// The most recent tick, skipping any older ones already buffered;
+    /// waits for a tick when none is buffered
+    pub async fn latest(&mut self) -> Option<ExtractResult> {
+        let mut latest = self.receiver.recv().await?;
+        while let Ok(tick) = self.receiver.try_recv() {
+            latest = tick;
+        }
+        Some(latest)
+    }
+}
+
+impl Stream for PriceStream {
+    type Item = ExtractResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Start streaming `source` on the current tokio runtime
+pub fn spawn(
+    source: Arc<dyn StreamingSource>,
+    validator: Validator,
+    retry: RetryPolicy,
+) -> PriceStream {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let name = source.name().to_string();
+    tokio::spawn(run_feed(source, validator, retry, sender));
+    PriceStream {
+        source: name,
+        receiver,
+    }
+}
+
+/// How a connection to the feed ended
+enum SessionEnd {
+    /// The `PriceStream` was dropped
+    Closed,
+    Failed {
+        ticks: u64,
+        error: SourceError,
+    },
+}
+
+async fn run_feed(
+    source: Arc<dyn StreamingSource>,
+    validator: Validator,
+    retry: RetryPolicy,
+    sender: mpsc::Sender<ExtractResult>,
+) {
+    // Connections in a row that ended without delivering a tick
+    let mut failures = 0;
+    loop {
+        let (ticks, error) = match run_session(source.as_ref(), &validator, &sender).await {
+            SessionEnd::Closed => return,
+            SessionEnd::Failed { ticks, error } => (ticks, error),
+        };
+        failures = if ticks > 0 { 1 } else { failures + 1 };
+        let wait = match error.class {
+            RetryClass::Fatal => None,
+            _ if failures >= retry.max_attempts => None,
+            RetryClass::Retry => Some(retry.delay(failures)),
+            RetryClass::Throttled(min) => Some(retry.delay(failures).max(min)),
+        };
+        let Some(wait) = wait else {
+            warn!(
+                source = source.name(),
+                attempts = failures,
+                error = %error,
+                "Extract: Market data stream stopped"
+            );
+            return;
+        };
+        warn!(
+            source = source.name(),
+            ticks = ticks,
+            error = %error,
+            delay_ms = wait.as_millis() as u64,
+            "Extract: Market data stream disconnected, reconnecting"
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Connect, subscribe and forward ticks until the connection fails or the
+/// receiver goes away
+async fn run_session(
+    source: &dyn StreamingSource,
+    validator: &Validator,
+    sender: &mpsc::Sender<ExtractResult>,
+) -> SessionEnd {
+    let failed = |ticks, error| SessionEnd::Failed { ticks, error };
+    let (mut socket, _) = match tokio_tungstenite::connect_async(source.url()).await {
+        Ok(connected) => connected,
+        Err(e) => return failed(0, SourceError::retryable(format!("connect failed: {}", e))),
+    };
+    for message in source.subscribe_messages() {
+        if let Err(e) = socket.send(Message::text(message)).await {
+            return failed(
+                0,
+                SourceError::retryable(format!("subscribe failed: {}", e)),
+            );
+        }
+    }
+    info!(
+        source = source.name(),
+        "Extract: Subscribed to market data stream"
+    );
+
+    let mut ticks = 0;
+    loop {
+        let frame = tokio::select! {
+            frame = socket.next() => frame,
+            _ = sender.closed() => return SessionEnd::Closed,
+        };
+        let text = match frame {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => {
+                return failed(ticks, SourceError::retryable("connection closed"))
+            }
+            // Pings are answered by the socket itself
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return failed(ticks, SourceError::retryable(e.to_string())),
+        };
+        let price = match source.parse(&text) {
+            Ok(Some(price)) => price,
+            Ok(None) => continue,
+            Err(e) if e.class == RetryClass::Fatal => return failed(ticks, e),
+            Err(e) => {
+                debug!(source = source.name(), error = %e, "Extract: Skipping stream frame");
+                continue;
+            }
+        };
+        let tick = ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price,
+            timestamp: now_millis(),
+            source: source.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume: None,
+        };
+        if let Err(e) = validator
+            .validate_price(tick.price)
+            .and_then(|_| validator.validate_timestamp(tick.timestamp))
+        {
+            warn!(source = source.name(), error = %e, "Extract: Dropping invalid tick");
+            continue;
+        }
+        if sender.send(tick).await.is_err() {
+            return SessionEnd::Closed;
+        }
+        ticks += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    /// Serves one WebSocket connection at a time: expects a subscription,
+    /// then sends `frames` and closes. Returns the `ws://` URL.
+    async fn start_feed(frames: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let mut socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                let subscribe = socket.next().await.unwrap().unwrap();
+                assert!(subscribe.to_text().unwrap().contains("subscribe"));
+                for frame in &frames {
+                    socket.send(Message::text(*frame)).await.unwrap();
+                }
+                socket.close(None).await.ok();
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_stream_delivers_valid_ticks_and_reconnects() {
+        let url = start_feed(vec![
+            r#"{"channel":"status","type":"update","data":[]}"#,
+            r#"{"channel":"ticker","type":"snapshot","data":[{"symbol":"BTC/USD","last":64000.5}]}"#,
+            // Outside the validator's range: dropped
+            r#"{"channel":"ticker","type":"update","data":[{"symbol":"BTC/USD","last":-1.0}]}"#,
+            r#"{"channel":"ticker","type":"update","data":[{"symbol":"BTC/USD","last":64001.0}]}"#,
+        ])
+        .await;
+        let mut stream = spawn(
+            Arc::new(KrakenStream::new().with_url(url)),
+            Validator::new(),
+            RetryPolicy::new(3, Duration::from_millis(10)),
+        );
+        assert_eq!(stream.source_name(), "Kraken");
+
+        // The server closes after each batch; the feed reconnects
+        let prices: Vec<Decimal> = stream
+            .by_ref()
+            .take(4)
+            .map(|tick| tick.price)
+            .collect()
+            .await;
+        let expected = ["64000.5", "64001", "64000.5", "64001"].map(|p| price::parse(p).unwrap());
+        assert_eq!(prices, expected);
+        assert_eq!(stream.latest().await.unwrap().source, "Kraken");
+    }
+
+    #[tokio::test]
+    async fn test_stream_stops_on_rejected_subscription() {
+        let url = start_feed(vec![r#"{"type":"error","message":"Failed to subscribe"}"#]).await;
+        let mut stream = spawn(
+            Arc::new(CoinbaseStream::new().with_url(url)),
+            Validator::new(),
+            RetryPolicy::new(3, Duration::from_millis(10)),
+        );
+        assert!(stream.next_tick().await.is_none());
+
+        let coinbase = CoinbaseStream::new();
+        assert_eq!(
+            coinbase
+                .parse(r#"{"type":"ticker","price":"64010.25"}"#)
+                .unwrap(),
+            Some(price::parse("64010.25").unwrap())
+        );
+        assert_eq!(coinbase.parse(r#"{"type":"subscriptions"}"#).unwrap(), None);
+    }
+}
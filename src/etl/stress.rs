@@ -0,0 +1,229 @@
+//! Scripted market stress for offline runs
+//!
+//! `MockSource` wiggles around 50,000 by the clock, which never trips a
+//! validator, anomaly detector or alert. A `StressSource` plays a
+//! `StressScenario` instead: the same calm price path every run, with one
+//! scripted event starting at a given tick (round), so a demo or test can
+//! rely on what the extractor returns when:
+//!
+//! - `flash_crash`: the price drops 30% for one tick, rebounds halfway the
+//!   next and is back to normal after
+//! - `gap_up`: the price jumps 20% and stays there
+//! - `stale_feed`: the price freezes at its last value before the onset
+//! - `outage`: three ticks fail without a price
+//!
+//! Selected for a run with `OFFLINE_SCENARIO` (with `--offline`), starting
+//! at tick `OFFLINE_SCENARIO_ONSET` (default 2, the last of a demo run's
+//! three rounds; raise it to give an anomaly window history first).
+
+use crate::etl::extract::{DataSource, ExtractResult, SourceError};
+use crate::etl::price::Decimal;
+use crate::etl::{now_millis, DEFAULT_ASSET};
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Tick the scripted event starts at unless configured
+pub const DEFAULT_ONSET: u64 = 2;
+
+/// Ticks a simulated outage lasts
+pub const OUTAGE_TICKS: u64 = 3;
+
+/// Calm price path: 50,000 stepping up by 25 and back every four ticks
+fn calm_price(tick: u64) -> Decimal {
+    Decimal::from(50_000 + 25 * (tick % 4))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StressScenario {
+    FlashCrash,
+    GapUp,
+    StaleFeed,
+    Outage,
+}
+
+impl StressScenario {
+    pub const ALL: [StressScenario; 4] = [
+        StressScenario::FlashCrash,
+        StressScenario::GapUp,
+        StressScenario::StaleFeed,
+        StressScenario::Outage,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StressScenario::FlashCrash => "flash_crash",
+            StressScenario::GapUp => "gap_up",
+            StressScenario::StaleFeed => "stale_feed",
+            StressScenario::Outage => "outage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim().to_ascii_lowercase().replace('-', "_");
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.name() == value)
+            .ok_or_else(|| {
+                format!(
+                    "unknown scenario '{}' (expected flash_crash, gap_up, stale_feed or outage)",
+                    value
+                )
+            })
+    }
+
+    /// Price at `tick` for an event starting at `onset`; `None` while the
+    /// feed is out
+    pub fn price_at(&self, tick: u64, onset: u64) -> Option<Decimal> {
+        let calm = calm_price(tick);
+        if tick < onset {
+            return Some(calm);
+        }
+        match self {
+            StressScenario::FlashCrash => Some(match tick - onset {
+                0 => calm * Decimal::new(7, 1),
+                1 => calm * Decimal::new(85, 2),
+                _ => calm,
+            }),
+            StressScenario::GapUp => Some(calm * Decimal::new(12, 1)),
+            StressScenario::StaleFeed => Some(calm_price(onset.saturating_sub(1))),
+            StressScenario::Outage => (tick - onset >= OUTAGE_TICKS).then_some(calm),
+        }
+    }
+}
+
+impl fmt::Display for StressScenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Plays a `StressScenario`, one tick per fetch
+pub struct StressSource {
+    scenario: StressScenario,
+    onset: u64,
+    tick: AtomicU64,
+}
+
+impl StressSource {
+    pub fn new(scenario: StressScenario) -> Self {
+        StressSource {
+            scenario,
+            onset: DEFAULT_ONSET,
+            tick: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_onset(mut self, onset: u64) -> Self {
+        self.onset = onset;
+        self
+    }
+
+    /// `None` unless `OFFLINE_SCENARIO` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(scenario) = std::env::var("OFFLINE_SCENARIO") else {
+            return Ok(None);
+        };
+        let mut source = Self::new(
+            StressScenario::parse(&scenario)
+                .map_err(|e| format!("invalid OFFLINE_SCENARIO: {}", e))?,
+        );
+        if let Ok(onset) = std::env::var("OFFLINE_SCENARIO_ONSET") {
+            source = source.with_onset(
+                onset
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("invalid OFFLINE_SCENARIO_ONSET: {}", e))?,
+            );
+        }
+        Ok(Some(source))
+    }
+
+    pub fn scenario(&self) -> StressScenario {
+        self.scenario
+    }
+
+    pub fn onset(&self) -> u64 {
+        self.onset
+    }
+}
+
+#[async_trait]
+impl DataSource for StressSource {
+    fn name(&self) -> &str {
+        "MockData"
+    }
+
+    async fn fetch(&self) -> Result<ExtractResult, SourceError> {
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed);
+        // Fatal, so the retry policy does not stretch the outage
+        let price = self.scenario.price_at(tick, self.onset).ok_or_else(|| {
+            SourceError::fatal(format!("simulated {} at tick {}", self.scenario, tick))
+        })?;
+        Ok(ExtractResult {
+            asset: DEFAULT_ASSET.to_string(),
+            price,
+            timestamp: now_millis(),
+            source: self.name().to_string(),
+            quotes: Vec::new(),
+            cache_hit: false,
+            volume: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::extract::Extractor;
+
+    #[tokio::test]
+    async fn test_scenarios_are_scripted() {
+        let prices = |scenario: StressScenario| {
+            (0..8)
+                .map(|tick| scenario.price_at(tick, 3))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            prices(StressScenario::FlashCrash)[2..6],
+            [
+                Some(Decimal::from(50_050)),
+                Some(Decimal::new(350_525, 1)),
+                Some(Decimal::from(42_500)),
+                Some(Decimal::from(50_025))
+            ]
+        );
+        assert_eq!(
+            prices(StressScenario::GapUp)[3],
+            Some(Decimal::from(60_090))
+        );
+        assert!(prices(StressScenario::StaleFeed)[2..]
+            .iter()
+            .all(|price| *price == Some(Decimal::from(50_050))));
+        assert_eq!(
+            prices(StressScenario::Outage)
+                .iter()
+                .filter(|price| price.is_none())
+                .count(),
+            OUTAGE_TICKS as usize
+        );
+
+        // Rounds through the extractor follow the script
+        let extractor = Extractor::new()
+            .unwrap()
+            .with_offline_source(StressSource::new(StressScenario::Outage).with_onset(1));
+        assert!(extractor.extract_offline().await.is_ok());
+        let outage = extractor.extract_offline().await.unwrap_err();
+        assert!(
+            outage.to_string().contains("simulated outage"),
+            "{}",
+            outage
+        );
+
+        assert_eq!(
+            StressScenario::parse("Flash-Crash"),
+            Ok(StressScenario::FlashCrash)
+        );
+        assert!(StressScenario::parse("meltdown").is_err());
+    }
+}
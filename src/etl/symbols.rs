@@ -0,0 +1,178 @@
+//! Asset classes and provider symbol mapping
+//!
+//! The ledger names assets by its own symbols (`BTC`, `EURUSD`, `AAPL`).
+//! A `SymbolMap` tells, for each, which `AssetClass` it belongs to and what a
+//! provider calls it, e.g. `EURUSD` is the FX pair `EUR/USD`. It is read from
+//! `ASSET_SYMBOLS`:
+//!
+//! ```text
+//! ASSET_SYMBOLS=EURUSD=fx:EUR/USD,AAPL=equity:AAPL,SAP=equity:SAP.DEX
+//! ```
+//!
+//! Unmapped assets are crypto and keep their own symbol. The class selects
+//! the `Validator` price range an asset is checked against (see
+//! `Validator::with_class_range`), since a plausible EUR/USD rate and a
+//! plausible BTC price differ by five orders of magnitude.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetClass {
+    Crypto,
+    Fx,
+    Equity,
+}
+
+impl AssetClass {
+    pub const ALL: [AssetClass; 3] = [AssetClass::Crypto, AssetClass::Fx, AssetClass::Equity];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AssetClass::Crypto => "crypto",
+            AssetClass::Fx => "fx",
+            AssetClass::Equity => "equity",
+        }
+    }
+}
+
+impl fmt::Display for AssetClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AssetClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "crypto" => Ok(AssetClass::Crypto),
+            "fx" | "forex" => Ok(AssetClass::Fx),
+            "equity" | "stock" => Ok(AssetClass::Equity),
+            other => Err(format!(
+                "unknown asset class '{}' (expected crypto, fx or equity)",
+                other
+            )),
+        }
+    }
+}
+
+/// How a ledger asset is classified and named by providers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMapping {
+    pub class: AssetClass,
+    /// Provider symbol, e.g. `EUR/USD` for an FX pair or `SAP.DEX` for a
+    /// listing outside the US
+    pub symbol: String,
+}
+
+impl SymbolMapping {
+    /// Base and quote currency of an FX pair written `EUR/USD`
+    pub fn currency_pair(&self) -> Option<(&str, &str)> {
+        self.symbol.split_once('/')
+    }
+}
+
+/// Ledger asset to `SymbolMapping`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolMap {
+    mappings: BTreeMap<String, SymbolMapping>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mapping(
+        mut self,
+        asset: impl Into<String>,
+        class: AssetClass,
+        symbol: impl Into<String>,
+    ) -> Self {
+        self.mappings.insert(
+            asset.into().to_ascii_uppercase(),
+            SymbolMapping {
+                class,
+                symbol: symbol.into(),
+            },
+        );
+        self
+    }
+
+    /// Parse `ASSET=class:SYMBOL,...`; an FX symbol must be `BASE/QUOTE`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut map = SymbolMap::new();
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (asset, target) = item
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not ASSET=class:SYMBOL", item))?;
+            let (class, symbol) = target
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' is not ASSET=class:SYMBOL", item))?;
+            let class: AssetClass = class.parse()?;
+            let (asset, symbol) = (asset.trim(), symbol.trim());
+            if asset.is_empty() || symbol.is_empty() {
+                return Err(format!("'{}' has an empty asset or symbol", item));
+            }
+            if class == AssetClass::Fx && !symbol.contains('/') {
+                return Err(format!("FX symbol '{}' must be BASE/QUOTE", symbol));
+            }
+            map = map.with_mapping(asset, class, symbol);
+        }
+        Ok(map)
+    }
+
+    /// `ASSET_SYMBOLS`, empty when unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("ASSET_SYMBOLS") {
+            Ok(spec) => Self::parse(&spec).map_err(|e| format!("invalid ASSET_SYMBOLS: {}", e)),
+            Err(_) => Ok(Self::new()),
+        }
+    }
+
+    /// The mapping for `asset`; unmapped assets are crypto under their own
+    /// symbol
+    pub fn resolve(&self, asset: &str) -> SymbolMapping {
+        self.mappings
+            .get(&asset.to_ascii_uppercase())
+            .cloned()
+            .unwrap_or_else(|| SymbolMapping {
+                class: AssetClass::Crypto,
+                symbol: asset.to_string(),
+            })
+    }
+
+    pub fn class_of(&self, asset: &str) -> AssetClass {
+        self.resolve(asset).class
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_symbol_map() {
+        let map = SymbolMap::parse("eurusd=fx:EUR/USD, AAPL=equity:AAPL").unwrap();
+        let eurusd = map.resolve("EURUSD");
+        assert_eq!(eurusd.class, AssetClass::Fx);
+        assert_eq!(eurusd.currency_pair(), Some(("EUR", "USD")));
+        assert_eq!(map.class_of("aapl"), AssetClass::Equity);
+        assert_eq!(map.resolve("BTC").class, AssetClass::Crypto);
+        assert_eq!(map.resolve("BTC").symbol, "BTC");
+
+        assert!(SymbolMap::parse("EURUSD=fx:EURUSD").is_err());
+        assert!(SymbolMap::parse("AAPL=bond:AAPL").is_err());
+        assert!(SymbolMap::parse("AAPL").is_err());
+        assert!(SymbolMap::parse("").unwrap().is_empty());
+    }
+}
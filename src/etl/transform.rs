@@ -1,75 +1,418 @@
+use crate::etl::anomaly::{AnomalyMethod, AnomalyStage};
+use crate::etl::bucket::{Bucket, BucketStage};
+use crate::etl::consolidate::ConsolidateStage;
+use crate::etl::conversion::{Conversion, ConversionStage};
+use crate::etl::divergence::SourceQuote;
+use crate::etl::indicators::{IndicatorStage, Indicators};
+use crate::etl::pipeline::{
+    DedupStrategy, DedupeStage, Normalization, NormalizeStage, Pipeline, SanitizeStage,
+    StageContext, TransformStage, ValidateStage, MAX_DECIMALS,
+};
+use crate::etl::price::Decimal;
+use crate::etl::profile::ValidationProfile;
+use crate::etl::provenance::Provenance;
+use crate::etl::sanitizer::{SanitizeReport, Sanitizers};
+use crate::etl::transform_stats::{TransformStats, TransformTracker};
 use crate::etl::validator::Validator;
+use crate::etl::DEFAULT_ASSET;
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::sync::Arc;
 
+/// Transform settings of one asset; unset fields keep the transformer's
+/// defaults (its normalization, 2 decimal places unless set, and its
+/// deduplication window)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AssetSettings {
+    pub normalization: Option<Normalization>,
+    pub dedup_window_seconds: Option<i64>,
+}
+
+impl AssetSettings {
+    /// Settings by asset from `ASSET_DECIMALS`, `ASSET_DEDUP_WINDOWS` and
+    /// `ASSET_NORMALIZATION` (`ASSET=value,...`, windows in seconds); empty
+    /// when all are unset
+    pub fn from_env() -> Result<BTreeMap<String, AssetSettings>, String> {
+        Self::parse(
+            std::env::var("ASSET_DECIMALS").ok().as_deref(),
+            std::env::var("ASSET_DEDUP_WINDOWS").ok().as_deref(),
+            std::env::var("ASSET_NORMALIZATION").ok().as_deref(),
+        )
+    }
+
+    /// Parse the decimals, deduplication window and normalization specs;
+    /// `ASSET=N` decimals are short for `ASSET=round:N`
+    pub fn parse(
+        decimals: Option<&str>,
+        dedup_windows: Option<&str>,
+        normalizations: Option<&str>,
+    ) -> Result<BTreeMap<String, AssetSettings>, String> {
+        let mut settings: BTreeMap<String, AssetSettings> = BTreeMap::new();
+        for (asset, value) in parse_asset_values(decimals.unwrap_or(""))
+            .map_err(|e| format!("invalid ASSET_DECIMALS: {}", e))?
+        {
+            let places = value
+                .parse::<u32>()
+                .ok()
+                .filter(|places| *places <= MAX_DECIMALS)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid ASSET_DECIMALS: {} needs 0 to {} places, not '{}'",
+                        asset, MAX_DECIMALS, value
+                    )
+                })?;
+            settings.entry(asset).or_default().normalization = Some(Normalization::Round(places));
+        }
+        for (asset, value) in parse_asset_values(dedup_windows.unwrap_or(""))
+            .map_err(|e| format!("invalid ASSET_DEDUP_WINDOWS: {}", e))?
+        {
+            let seconds = value
+                .parse::<i64>()
+                .ok()
+                .filter(|seconds| *seconds >= 0)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid ASSET_DEDUP_WINDOWS: {} needs a number of seconds, not '{}'",
+                        asset, value
+                    )
+                })?;
+            settings.entry(asset).or_default().dedup_window_seconds = Some(seconds);
+        }
+        for (asset, value) in parse_asset_values(normalizations.unwrap_or(""))
+            .map_err(|e| format!("invalid ASSET_NORMALIZATION: {}", e))?
+        {
+            let normalization = Normalization::parse(&value)
+                .map_err(|e| format!("invalid ASSET_NORMALIZATION: {}: {}", asset, e))?;
+            let entry = settings.entry(asset.clone()).or_default();
+            if entry.normalization.is_some() {
+                return Err(format!(
+                    "invalid ASSET_NORMALIZATION: {} already has ASSET_DECIMALS",
+                    asset
+                ));
+            }
+            entry.normalization = Some(normalization);
+        }
+        Ok(settings)
+    }
+}
+
+/// `ASSET=value` pairs with upper-cased assets
+fn parse_asset_values(spec: &str) -> Result<Vec<(String, String)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.split_once('=') {
+            Some((asset, value)) if !asset.trim().is_empty() => {
+                Ok((asset.trim().to_ascii_uppercase(), value.trim().to_string()))
+            }
+            _ => Err(format!("'{}' is not ASSET=value", item)),
+        })
+        .collect()
+}
+
+/// The standard transform stages; see `crate::etl::pipeline`
 pub struct Transformer {
-    validator: Validator,
-    deduplication_window_seconds: i64,
+    sanitize: SanitizeStage,
+    consolidate: Option<ConsolidateStage>,
+    validate: ValidateStage,
+    dedupe: DedupeStage,
+    conversion: Option<ConversionStage>,
+    anomaly: Option<AnomalyStage>,
+    bucket: Option<BucketStage>,
+    normalize: NormalizeStage,
+    indicators: Option<IndicatorStage>,
+    /// Shared by every pipeline the transformer builds
+    tracker: Arc<TransformTracker>,
+    /// Asset of the quotes passed to `transform`
+    asset: String,
 }
 
+#[derive(Debug, Clone)]
 pub struct TransformResult {
     pub asset: String,
-    pub price: f32,
+    pub price: Decimal,
     pub source: String,
     pub timestamp: i64,
     pub is_deduplicated: bool,
+    /// Held by `BucketStage` until its time bucket closes; no entry is
+    /// built from it
+    pub is_buffered: bool,
+    /// Values the sanitizers changed before validation
+    pub sanitized: SanitizeReport,
+    /// Raw quote and the steps applied so far; completed by
+    /// `Transformer::normalize`
+    pub provenance: Provenance,
+    /// Traded volume the source reported with the quote
+    pub volume: Option<Decimal>,
+    /// Per-source quotes behind an aggregated price
+    pub quotes: Vec<SourceQuote>,
+    /// Set by `IndicatorStage` when the pipeline has one
+    pub indicators: Option<Indicators>,
+    /// Set by `ConversionStage` when the pipeline has one
+    pub conversion: Option<Conversion>,
+    /// Set by `BucketStage` on the record standing for a closed bucket
+    pub bucket: Option<Bucket>,
 }
 
 impl Transformer {
     pub fn new() -> Self {
         Transformer {
-            validator: Validator::new(),
-            deduplication_window_seconds: 60,
+            sanitize: SanitizeStage::new(Sanitizers::new()),
+            consolidate: None,
+            validate: ValidateStage::new(Validator::new()),
+            dedupe: DedupeStage::new(60),
+            conversion: None,
+            anomaly: None,
+            bucket: None,
+            normalize: NormalizeStage::new(),
+            indicators: None,
+            tracker: Arc::default(),
+            asset: DEFAULT_ASSET.to_string(),
         }
     }
 
+    /// Asset of the quotes passed to `transform`, e.g. the one the main
+    /// source quotes; `DEFAULT_ASSET` unless set
+    pub fn with_asset(mut self, asset: impl Into<String>) -> Self {
+        self.asset = asset.into();
+        self
+    }
+
     pub fn with_validator(mut self, validator: Validator) -> Self {
-        self.validator = validator;
+        self.validate = ValidateStage::new(validator);
+        self
+    }
+
+    /// Sanitizers applied to each record before it is validated
+    pub fn with_sanitizers(mut self, sanitizers: Sanitizers) -> Self {
+        self.sanitize = SanitizeStage::new(sanitizers);
         self
     }
 
+    /// Deduplication window for assets without one of their own; replaces
+    /// any set with `with_asset_settings`
     pub fn with_deduplication_window(mut self, seconds: i64) -> Self {
-        self.deduplication_window_seconds = seconds;
+        self.dedupe = DedupeStage::new(seconds).with_strategy(self.dedupe.strategy());
+        self
+    }
+
+    /// How duplicates are told apart: by the last block's timestamp (the
+    /// default) or by the content hash of recent quotes
+    pub fn with_deduplication_strategy(mut self, strategy: DedupStrategy) -> Self {
+        self.dedupe = self.dedupe.with_strategy(strategy);
         self
     }
 
+    /// How prices of assets without a normalization of their own are
+    /// settled; rounded to 2 decimal places unless set
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalize = self.normalize.with_normalization(normalization);
+        self
+    }
+
+    /// How prices of `asset` are settled, e.g. `Normalization::Tick` for an
+    /// instrument quoted in quarter points
+    pub fn with_asset_normalization(mut self, asset: &str, normalization: Normalization) -> Self {
+        self.normalize = self
+            .normalize
+            .with_asset_normalization(asset, normalization);
+        self
+    }
+
+    /// Normalization and deduplication window for quotes of `asset`
+    pub fn with_asset_settings(mut self, asset: &str, settings: AssetSettings) -> Self {
+        if let Some(normalization) = settings.normalization {
+            self.normalize = self
+                .normalize
+                .with_asset_normalization(asset, normalization);
+        }
+        if let Some(seconds) = settings.dedup_window_seconds {
+            self.dedupe = self.dedupe.with_asset_window(asset, seconds);
+        }
+        self
+    }
+
+    /// Weighted consolidation of aggregated quotes, run after the
+    /// sanitizers so the consolidated price is validated
+    pub fn with_consolidation(mut self, stage: ConsolidateStage) -> Self {
+        self.consolidate = Some(stage);
+        self
+    }
+
+    /// Conversion into another quote currency, run on quotes that are not
+    /// duplicates, ahead of anomaly detection and normalization
+    pub fn with_conversion(mut self, stage: ConversionStage) -> Self {
+        self.conversion = Some(stage);
+        self
+    }
+
+    /// Statistical check against each asset's recent quotes, run on quotes
+    /// that are not duplicates, before normalization
+    pub fn with_anomaly_detection(mut self, stage: AnomalyStage) -> Self {
+        self.anomaly = Some(stage);
+        self
+    }
+
+    /// The timestamp drift, default deduplication window and anomaly
+    /// threshold and action of `profile`; per-asset windows stay, and an
+    /// anomaly detector keeps its method and window (MAD when there was
+    /// none)
+    pub fn with_profile(mut self, profile: &ValidationProfile) -> Self {
+        self.validate = ValidateStage::new(
+            self.validate
+                .validator()
+                .clone()
+                .with_timestamp_drift(profile.timestamp_drift_seconds),
+        );
+        self.dedupe = self
+            .dedupe
+            .with_window_seconds(profile.dedup_window_seconds);
+        let anomaly = self
+            .anomaly
+            .take()
+            .unwrap_or_else(|| AnomalyStage::new(AnomalyMethod::Mad));
+        self.anomaly = Some(
+            anomaly
+                .with_threshold(profile.anomaly_threshold)
+                .with_action(profile.anomaly_action),
+        );
+        self
+    }
+
+    /// One record per asset and time bucket, priced at the mean of the
+    /// bucket's quotes; runs after anomaly detection, before normalization
+    pub fn with_time_buckets(mut self, stage: BucketStage) -> Self {
+        self.bucket = Some(stage);
+        self
+    }
+
+    /// Rolling indicators attached after normalization; the stage's window
+    /// is shared by every pipeline the transformer builds
+    pub fn with_indicators(mut self, stage: IndicatorStage) -> Self {
+        self.indicators = Some(stage);
+        self
+    }
+
+    /// Count the outcomes of its pipelines in `tracker`, e.g. one also
+    /// served over HTTP
+    pub fn with_tracker(mut self, tracker: Arc<TransformTracker>) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    /// The stages as a `Pipeline` (sanitize, validate, dedupe, normalize,
+    /// with consolidation after sanitize, currency conversion, anomaly
+    /// detection and time buckets before normalize and indicators at the
+    /// end when configured) that further stages can be inserted into
+    pub fn pipeline(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new()
+            .with_tracker(self.tracker.clone())
+            .with_stage(self.sanitize.clone());
+        if let Some(stage) = &self.consolidate {
+            pipeline = pipeline.with_stage(stage.clone());
+        }
+        pipeline = pipeline
+            .with_stage(self.validate.clone())
+            .with_stage(self.dedupe.clone());
+        if let Some(stage) = &self.conversion {
+            pipeline = pipeline.with_stage(stage.clone());
+        }
+        if let Some(stage) = &self.anomaly {
+            pipeline = pipeline.with_stage(stage.clone());
+        }
+        if let Some(stage) = &self.bucket {
+            pipeline = pipeline.with_stage(stage.clone());
+        }
+        let pipeline = pipeline.with_stage(self.normalize.clone());
+        match &self.indicators {
+            Some(stage) => pipeline.with_stage(stage.clone()),
+            None => pipeline,
+        }
+    }
+
+    /// Sanitize and validate one record of the transformer's asset (see
+    /// `with_asset`)
+    ///
+    /// `last_timestamp` is the wall time of the last block's
+    /// `Block::ordering_timestamp`, so the deduplication window is measured
+    /// against the chain's hybrid logical time rather than whichever node's
+    /// wall clock stamped that block.
     pub fn transform(
         &self,
-        price: f32,
+        price: Decimal,
         timestamp: i64,
         source: String,
         last_timestamp: Option<i64>,
     ) -> Result<TransformResult, Box<dyn Error>> {
-        self.validator.validate_price(price)?;
-        self.validator.validate_timestamp(timestamp)?;
-        self.validator.validate_source(&source)?;
-
-        let is_deduplicated = if let Some(last_ts) = last_timestamp {
-            (timestamp - last_ts).abs() < self.deduplication_window_seconds
-        } else {
-            false
-        };
-
-        Ok(TransformResult {
-            asset: "BTC".to_string(),
-            price,
-            source,
-            timestamp,
-            is_deduplicated,
-        })
+        self.transform_asset(&self.asset, price, timestamp, source, last_timestamp)
+    }
+
+    /// `transform` for a quote of `asset`, whose price is checked against
+    /// the range of its asset class
+    pub fn transform_asset(
+        &self,
+        asset: &str,
+        price: Decimal,
+        timestamp: i64,
+        source: String,
+        last_timestamp: Option<i64>,
+    ) -> Result<TransformResult, Box<dyn Error>> {
+        let mut record = TransformResult::raw(asset, price, source, timestamp);
+        let context = StageContext { last_timestamp };
+        self.sanitize.apply(&mut record, &context)?;
+        self.validate.apply(&mut record, &context)?;
+        self.dedupe.apply(&mut record, &context)?;
+        Ok(record)
     }
 
-    pub fn normalize_price(&self, price: f32) -> f32 {
-        (price * 100.0).round() / 100.0
+    pub fn normalize_price(&self, price: Decimal) -> Decimal {
+        self.normalize.normalize_price(price)
+    }
+
+    /// Normalize a transformed price, returning it with the record's
+    /// provenance including the normalization step
+    pub fn normalize(&self, result: &TransformResult) -> (Decimal, Provenance) {
+        let mut record = result.clone();
+        // Only a tick snap that overflows fails, leaving the price as is
+        let _ = self.normalize.apply(&mut record, &StageContext::default());
+        (record.price, record.provenance)
+    }
+
+    /// What became of the records run through the transformer's pipelines
+    pub fn stats(&self) -> TransformStats {
+        self.tracker.stats()
     }
 
     pub fn deduplication_window_seconds(&self) -> i64 {
-        self.deduplication_window_seconds
+        self.dedupe.window_seconds()
+    }
+
+    pub fn deduplication_strategy(&self) -> DedupStrategy {
+        self.dedupe.strategy()
+    }
+
+    /// Deduplication window applied to quotes of `asset`
+    pub fn deduplication_window_for(&self, asset: &str) -> i64 {
+        self.dedupe.window_for(asset)
+    }
+
+    /// Longest deduplication window of any asset
+    pub fn longest_deduplication_window(&self) -> i64 {
+        self.dedupe.longest_window()
+    }
+
+    /// How prices of `asset` are normalized
+    pub fn normalization_for(&self, asset: &str) -> Normalization {
+        self.normalize.normalization_for(asset)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::etl::pipeline::AssetTimestamps;
+    use crate::etl::price;
+    use crate::etl::sanitizer::Field;
     use crate::etl::validator::Validator;
 
     static INIT: std::sync::Once = std::sync::Once::new();
@@ -98,12 +441,12 @@ mod tests {
         init();
         use chrono::Utc;
         let validator = Validator::new()
-            .with_price_range(0.0, 100000.0)
+            .with_price_range(Decimal::ZERO, Decimal::from(100000))
             .with_timestamp_drift(86400);
         let transformer = Transformer::new().with_validator(validator);
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp_millis();
         assert!(transformer
-            .transform(50000.0, timestamp, "Test".to_string(), None)
+            .transform(Decimal::from(50000), timestamp, "Test".to_string(), None)
             .is_ok());
     }
 
@@ -119,13 +462,18 @@ mod tests {
         init();
         use chrono::Utc;
         let transformer = Transformer::new();
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp_millis();
         let result = transformer
-            .transform(50000.0, timestamp, "CoinGecko".to_string(), None)
+            .transform(
+                Decimal::from(50000),
+                timestamp,
+                "CoinGecko".to_string(),
+                None,
+            )
             .unwrap();
 
         assert_eq!(result.asset, "BTC");
-        assert_eq!(result.price, 50000.0);
+        assert_eq!(result.price, Decimal::from(50000));
         assert_eq!(result.source, "CoinGecko");
         assert_eq!(result.timestamp, timestamp);
         assert!(!result.is_deduplicated);
@@ -135,7 +483,8 @@ mod tests {
     fn test_transform_invalid_price() {
         init();
         let transformer = Transformer::new();
-        let result = transformer.transform(-100.0, 1234567890, "Test".to_string(), None);
+        let result =
+            transformer.transform(Decimal::from(-100), 1234567890, "Test".to_string(), None);
         assert!(result.is_err());
     }
 
@@ -143,7 +492,7 @@ mod tests {
     fn test_transform_invalid_timestamp() {
         init();
         let transformer = Transformer::new();
-        let result = transformer.transform(50000.0, -1, "Test".to_string(), None);
+        let result = transformer.transform(Decimal::from(50000), -1, "Test".to_string(), None);
         assert!(result.is_err());
     }
 
@@ -151,7 +500,7 @@ mod tests {
     fn test_transform_invalid_source() {
         init();
         let transformer = Transformer::new();
-        let result = transformer.transform(50000.0, 1234567890, "".to_string(), None);
+        let result = transformer.transform(Decimal::from(50000), 1234567890, "".to_string(), None);
         assert!(result.is_err());
     }
 
@@ -163,16 +512,21 @@ mod tests {
         let transformer = Transformer::new()
             .with_validator(validator)
             .with_deduplication_window(60);
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp_millis();
 
         // First transform - no deduplication
         let result1 = transformer
-            .transform(50000.0, timestamp, "Test".to_string(), None)
+            .transform(Decimal::from(50000), timestamp, "Test".to_string(), None)
             .unwrap();
         assert!(!result1.is_deduplicated);
 
         let result2 = transformer
-            .transform(50100.0, timestamp + 30, "Test".to_string(), Some(timestamp))
+            .transform(
+                Decimal::from(50100),
+                timestamp + 30_000,
+                "Test".to_string(),
+                Some(timestamp),
+            )
             .unwrap();
         assert!(result2.is_deduplicated);
     }
@@ -185,12 +539,12 @@ mod tests {
         let transformer = Transformer::new()
             .with_validator(validator)
             .with_deduplication_window(60);
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp_millis();
 
         let result = transformer
             .transform(
-                50000.0,
-                timestamp + 120,
+                Decimal::from(50000),
+                timestamp + 120_000,
                 "Test".to_string(),
                 Some(timestamp),
             )
@@ -198,15 +552,173 @@ mod tests {
         assert!(!result.is_deduplicated);
     }
 
+    #[test]
+    fn test_transform_distinguishes_ticks_within_a_second() {
+        init();
+        use chrono::Utc;
+        let transformer = Transformer::new().with_deduplication_window(0);
+        let timestamp = Utc::now().timestamp_millis();
+
+        let result = transformer
+            .transform(
+                Decimal::from(50000),
+                timestamp + 250,
+                "Test".to_string(),
+                Some(timestamp),
+            )
+            .unwrap();
+        assert!(!result.is_deduplicated);
+        assert_eq!(result.timestamp, timestamp + 250);
+    }
+
+    #[test]
+    fn test_transform_sanitizes_before_validation() {
+        init();
+        use chrono::Utc;
+        let transformer = Transformer::new().with_sanitizers(
+            Sanitizers::standard()
+                .with_canonical_sources([("coingecko", "CoinGecko")])
+                .with_precision(2),
+        );
+        let timestamp = Utc::now().timestamp_millis();
+
+        let result = transformer
+            .transform(
+                price::parse("50000.126").unwrap(),
+                timestamp,
+                "  coingecko ".to_string(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(result.source, "CoinGecko");
+        assert_eq!(result.price, price::parse("50000.13").unwrap());
+        assert!(result.sanitized.modified(Field::Source));
+        assert!(result.sanitized.modified(Field::Price));
+        assert!(!result.sanitized.modified(Field::Asset));
+
+        // A source that is only whitespace is empty once trimmed
+        assert!(transformer
+            .transform(Decimal::from(50000), timestamp, "   ".to_string(), None)
+            .is_err());
+
+        let unsanitized = Transformer::new()
+            .transform(Decimal::from(50000), timestamp, " x ".to_string(), None)
+            .unwrap();
+        assert_eq!(unsanitized.source, " x ");
+        assert!(unsanitized.sanitized.is_empty());
+
+        // The asset comes from the transformer's configuration
+        let eth = Transformer::new()
+            .with_sanitizers(Sanitizers::standard())
+            .with_asset(" eth")
+            .transform(Decimal::from(3000), timestamp, "Kraken".to_string(), None)
+            .unwrap();
+        assert_eq!(eth.asset, "ETH");
+        assert!(eth.sanitized.modified(Field::Asset));
+    }
+
+    #[test]
+    fn test_asset_settings_override_defaults() {
+        init();
+        use chrono::Utc;
+        let settings =
+            AssetSettings::parse(Some("eurusd=5, AAPL=2"), Some("EURUSD=10"), None).unwrap();
+        assert_eq!(
+            settings["EURUSD"],
+            AssetSettings {
+                normalization: Some(Normalization::Round(5)),
+                dedup_window_seconds: Some(10)
+            }
+        );
+        assert!(AssetSettings::parse(Some("EURUSD=12"), None, None).is_err());
+        assert!(AssetSettings::parse(None, Some("EURUSD"), None).is_err());
+        assert!(AssetSettings::parse(None, None, None).unwrap().is_empty());
+
+        let transformer = settings.iter().fold(Transformer::new(), |t, (asset, s)| {
+            t.with_asset_settings(asset, *s)
+        });
+        assert_eq!(
+            transformer.normalization_for("EURUSD"),
+            Normalization::Round(5)
+        );
+        assert_eq!(
+            transformer.normalization_for("BTC"),
+            Normalization::Round(2)
+        );
+        assert_eq!(transformer.deduplication_window_for("EURUSD"), 10);
+        assert_eq!(transformer.deduplication_window_for("AAPL"), 60);
+
+        let timestamp = Utc::now().timestamp_millis();
+        let pipeline = transformer.pipeline();
+        let fx = pipeline
+            .run(
+                "EURUSD",
+                price::parse("1.0823456").unwrap(),
+                timestamp,
+                "AV".to_string(),
+                Some(timestamp - 30_000),
+            )
+            .unwrap();
+        assert!(!fx.is_deduplicated);
+        assert_eq!(fx.price, price::parse("1.08235").unwrap());
+        assert_eq!(
+            fx.provenance.step_names().last().map(String::as_str),
+            Some("normalized:round(5)")
+        );
+        let btc = pipeline
+            .run(
+                "BTC",
+                price::parse("50000.126").unwrap(),
+                timestamp,
+                "CG".to_string(),
+                Some(timestamp - 30_000),
+            )
+            .unwrap();
+        assert!(btc.is_deduplicated);
+
+        // Each asset's window runs from the last block carrying that asset
+        let mut last = AssetTimestamps::new();
+        let mut block = crate::testing::block(
+            1,
+            "0",
+            vec![crate::testing::entry(
+                "EURUSD",
+                "AV",
+                price::parse("1.08").unwrap(),
+                timestamp - 30_000,
+            )],
+        );
+        block.timestamp = timestamp - 30_000;
+        last.record_block(&block);
+        block.data[0].asset = "BTC".to_string();
+        block.timestamp = timestamp - 5_000;
+        last.record_block(&block);
+        assert_eq!(last.get("eurusd"), Some(timestamp - 30_000));
+        let fx = pipeline
+            .run(
+                "EURUSD",
+                price::parse("1.08").unwrap(),
+                timestamp,
+                "AV".to_string(),
+                last.get("EURUSD"),
+            )
+            .unwrap();
+        assert!(!fx.is_deduplicated);
+        assert!(last.get("AAPL").is_none());
+    }
+
     #[test]
     fn test_normalize_price() {
         init();
         let transformer = Transformer::new();
 
-        assert_eq!(transformer.normalize_price(50000.123), 50000.12);
-        assert_eq!(transformer.normalize_price(50000.456), 50000.46);
-        assert_eq!(transformer.normalize_price(50000.0), 50000.0);
-        assert_eq!(transformer.normalize_price(50000.999), 50001.0);
+        let normalize = |p: &str| transformer.normalize_price(price::parse(p).unwrap());
+        assert_eq!(normalize("50000.123"), price::parse("50000.12").unwrap());
+        assert_eq!(normalize("50000.456"), price::parse("50000.46").unwrap());
+        assert_eq!(normalize("50000.0"), Decimal::from(50000));
+        assert_eq!(normalize("50000.999"), Decimal::from(50001));
+        // Half-way cases round away from zero instead of to the nearest f32
+        assert_eq!(normalize("1.005"), price::parse("1.01").unwrap());
     }
 
     #[test]
@@ -214,13 +726,18 @@ mod tests {
         init();
         use chrono::Utc;
         let transformer = Transformer::new();
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp_millis();
         let result = transformer
-            .transform(50000.0, timestamp, "TestSource".to_string(), None)
+            .transform(
+                Decimal::from(50000),
+                timestamp,
+                "TestSource".to_string(),
+                None,
+            )
             .unwrap();
 
         assert_eq!(result.asset, "BTC");
-        assert_eq!(result.price, 50000.0);
+        assert_eq!(result.price, Decimal::from(50000));
         assert_eq!(result.source, "TestSource");
         assert_eq!(result.timestamp, timestamp);
         assert!(!result.is_deduplicated);
@@ -0,0 +1,208 @@
+//! Transform counters and audit trail
+//!
+//! A `Pipeline` built by `Transformer::pipeline` reports every record it
+//! runs to the transformer's `TransformTracker`: quotes that failed
+//! validation or were rejected by another stage, duplicates dropped,
+//! anomalies flagged, quotes held for their time bucket and normalizations
+//! applied. The tracker also keeps the last `AUDIT_TRAIL_LEN` rejections
+//! with the reason, so an operator can see why a quote never made it into a
+//! block without searching the logs.
+//!
+//! `Transformer::stats` returns a `TransformStats` snapshot; a node logs the
+//! difference each round makes and shares the tracker with its HTTP server,
+//! which serves the snapshot on `GET /transform`. The tracker outlives
+//! pipelines rebuilt when the validation profile changes, so counts cover
+//! the node's whole run.
+
+use crate::etl::now_millis;
+use crate::etl::provenance::CustodyStep;
+use crate::etl::transform::TransformResult;
+use crate::etl::validator::{ValidationError, Violations};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Rejections kept in `TransformStats::recent_rejections`
+pub const AUDIT_TRAIL_LEN: usize = 50;
+
+/// A quote the pipeline turned away
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejection {
+    pub asset: String,
+    pub source: String,
+    /// When it was rejected (milliseconds)
+    pub at_ms: i64,
+    /// Failed validation rather than another stage
+    pub validation: bool,
+    pub reason: String,
+}
+
+/// Counts since the tracker was created
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TransformStats {
+    /// Records run through a pipeline
+    pub processed: u64,
+    pub validations_failed: u64,
+    /// Rejected by a stage other than validation, e.g. an anomaly check
+    /// set to reject
+    pub rejected: u64,
+    pub duplicates_dropped: u64,
+    pub anomalies_flagged: u64,
+    /// Held by `BucketStage` until their time bucket closed
+    pub buffered: u64,
+    pub normalizations_applied: u64,
+    /// Oldest first; empty in a difference from `since`
+    pub recent_rejections: Vec<Rejection>,
+}
+
+impl TransformStats {
+    /// Counts added since `earlier`, a snapshot of the same tracker
+    pub fn since(&self, earlier: &TransformStats) -> TransformStats {
+        TransformStats {
+            processed: self.processed.saturating_sub(earlier.processed),
+            validations_failed: self
+                .validations_failed
+                .saturating_sub(earlier.validations_failed),
+            rejected: self.rejected.saturating_sub(earlier.rejected),
+            duplicates_dropped: self
+                .duplicates_dropped
+                .saturating_sub(earlier.duplicates_dropped),
+            anomalies_flagged: self
+                .anomalies_flagged
+                .saturating_sub(earlier.anomalies_flagged),
+            buffered: self.buffered.saturating_sub(earlier.buffered),
+            normalizations_applied: self
+                .normalizations_applied
+                .saturating_sub(earlier.normalizations_applied),
+            recent_rejections: Vec::new(),
+        }
+    }
+}
+
+/// Collects pipeline outcomes for `TransformStats`
+#[derive(Debug, Default)]
+pub struct TransformTracker {
+    processed: AtomicU64,
+    validations_failed: AtomicU64,
+    rejected: AtomicU64,
+    duplicates_dropped: AtomicU64,
+    anomalies_flagged: AtomicU64,
+    buffered: AtomicU64,
+    normalizations_applied: AtomicU64,
+    recent_rejections: Mutex<VecDeque<Rejection>>,
+}
+
+impl TransformTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `record` as the pipeline left it, or rejected with `error`
+    pub fn record(&self, record: &TransformResult, outcome: Result<(), &(dyn Error + 'static)>) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        if let Err(error) = outcome {
+            self.record_rejection(&record.asset, &record.source, error);
+            return;
+        }
+        if record.is_deduplicated {
+            self.duplicates_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if record.is_buffered {
+            self.buffered.fetch_add(1, Ordering::Relaxed);
+        }
+        for step in &record.provenance.steps {
+            match step {
+                CustodyStep::Flagged { .. } => &self.anomalies_flagged,
+                CustodyStep::Normalized { .. } => &self.normalizations_applied,
+                _ => continue,
+            }
+            .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> TransformStats {
+        TransformStats {
+            processed: self.processed.load(Ordering::Relaxed),
+            validations_failed: self.validations_failed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            duplicates_dropped: self.duplicates_dropped.load(Ordering::Relaxed),
+            anomalies_flagged: self.anomalies_flagged.load(Ordering::Relaxed),
+            buffered: self.buffered.load(Ordering::Relaxed),
+            normalizations_applied: self.normalizations_applied.load(Ordering::Relaxed),
+            recent_rejections: self.recent_rejections.lock().iter().cloned().collect(),
+        }
+    }
+
+    fn record_rejection(&self, asset: &str, source: &str, error: &(dyn Error + 'static)) {
+        let validation = error.is::<ValidationError>() || error.is::<Violations>();
+        if validation {
+            &self.validations_failed
+        } else {
+            &self.rejected
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        let mut trail = self.recent_rejections.lock();
+        if trail.len() == AUDIT_TRAIL_LEN {
+            trail.pop_front();
+        }
+        trail.push_back(Rejection {
+            asset: asset.to_string(),
+            source: source.to_string(),
+            at_ms: now_millis(),
+            validation,
+            reason: error.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::anomaly::{AnomalyMethod, AnomalyStage};
+    use crate::etl::price::{self, Decimal};
+    use crate::etl::transform::Transformer;
+
+    #[test]
+    fn test_counts_outcomes_and_keeps_rejections() {
+        let transformer =
+            Transformer::new().with_anomaly_detection(AnomalyStage::new(AnomalyMethod::Mad));
+        let pipeline = transformer.pipeline();
+        let before = transformer.stats();
+        let now = now_millis();
+        for price in ["100", "101", "99", "100.5", "99.5", "100", "500"] {
+            pipeline
+                .run(
+                    "BTC",
+                    price::parse(price).unwrap(),
+                    now,
+                    "Kraken".into(),
+                    None,
+                )
+                .unwrap();
+        }
+        // Within the deduplication window of the last block
+        let duplicate = pipeline.run("BTC", Decimal::from(100), now, "Kraken".into(), Some(now));
+        assert!(duplicate.unwrap().is_deduplicated);
+        assert!(pipeline
+            .run("BTC", Decimal::from(-1), now, "Kraken".into(), None)
+            .is_err());
+
+        let stats = transformer.stats();
+        assert_eq!(
+            (
+                stats.processed,
+                stats.validations_failed,
+                stats.duplicates_dropped,
+                stats.anomalies_flagged,
+                stats.normalizations_applied
+            ),
+            (9, 1, 1, 1, 7)
+        );
+        assert_eq!(stats.recent_rejections.len(), 1);
+        assert!(stats.recent_rejections[0].validation);
+        assert_eq!(stats.since(&before).processed, 9);
+        assert!(stats.since(&stats).recent_rejections.is_empty());
+    }
+}
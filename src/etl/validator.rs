@@ -1,4 +1,34 @@
+//! Quote validation
+//!
+//! A `Validator` is a list of `ValidationRule`s. Every validator starts with
+//! the built-in rules: the asset symbol (`AssetSymbolRule`), the price range
+//! per asset class (`PriceRangeRule`), the timestamp drift
+//! (`TimestampDriftRule`) and a non-empty source (`SourceRule`). Domain rules
+//! are added with `Validator::with_rule`, such as a `SourceAllowlist`, or
+//! with `with_check` for a closure:
+//!
+//! ```ignore
+//! let validator = Validator::new()
+//!     .with_rule(SourceAllowlist::new(["Kraken", "Coinbase"]))
+//!     .with_check("whole-dollars", |quote| {
+//!         if quote.price.fract().is_zero() {
+//!             Ok(())
+//!         } else {
+//!             Err(format!("Price {} is not a whole number", quote.price))
+//!         }
+//!     });
+//! ```
+//!
+//! `Validator::validate` runs every rule and returns all violations, not just
+//! the first. Rules added beyond the built-ins are named in
+//! `Validator::version`, so entry provenance tells which rules it passed.
+
+use crate::etl::price::{self, Decimal};
+use crate::etl::symbols::{AssetClass, SymbolMap};
 use chrono::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct ValidationError {
@@ -14,78 +44,172 @@ impl std::fmt::Display for ValidationError {
 
 impl std::error::Error for ValidationError {}
 
-pub struct Validator {
-    min_price: f32,
-    max_price: f32,
-    max_timestamp_drift_seconds: i64,
-}
+/// Every rule a quote broke, in rule order; never empty
+#[derive(Debug, Clone)]
+pub struct Violations(pub Vec<ValidationError>);
 
-impl Default for Validator {
-    fn default() -> Self {
-        Self::new()
+impl fmt::Display for Violations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
     }
 }
 
-impl Validator {
-    pub fn new() -> Self {
-        Validator {
-            min_price: 0.0,
-            max_price: 1_000_000.0,
-            max_timestamp_drift_seconds: 3600,
-        }
-    }
+impl std::error::Error for Violations {}
 
-    pub fn with_price_range(mut self, min: f32, max: f32) -> Self {
-        self.min_price = min;
-        self.max_price = max;
-        self
+/// The fields of a quote the rules check
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    pub asset: &'a str,
+    pub price: Decimal,
+    /// Unix milliseconds
+    pub timestamp: i64,
+    pub source: &'a str,
+}
+
+/// One check a quote must pass
+pub trait ValidationRule: Send + Sync {
+    /// Short name, recorded in `Validator::version` for added rules
+    fn name(&self) -> &str;
+
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError>;
+}
+
+impl fmt::Debug for dyn ValidationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
     }
+}
 
-    pub fn with_timestamp_drift(mut self, seconds: i64) -> Self {
-        self.max_timestamp_drift_seconds = seconds;
-        self
+/// Asset symbols must be 1 to 10 characters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AssetSymbolRule;
+
+impl ValidationRule for AssetSymbolRule {
+    fn name(&self) -> &str {
+        "symbol"
     }
 
-    pub fn validate_price(&self, price: f32) -> Result<(), ValidationError> {
-        if price < self.min_price {
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        if quote.asset.is_empty() {
             return Err(ValidationError {
-                field: "price".to_string(),
-                reason: format!("Price {} is below minimum {}", price, self.min_price),
+                field: "asset".to_string(),
+                reason: "Asset symbol cannot be empty".to_string(),
+            });
+        }
+
+        if quote.asset.len() > 10 {
+            return Err(ValidationError {
+                field: "asset".to_string(),
+                reason: format!(
+                    "Asset symbol '{}' exceeds maximum length of 10",
+                    quote.asset
+                ),
             });
         }
 
-        if price > self.max_price {
+        Ok(())
+    }
+}
+
+/// Prices must fall in the range of the asset's class, or the general range
+/// when the class has none
+#[derive(Debug, Clone)]
+pub struct PriceRangeRule {
+    min_price: Decimal,
+    max_price: Decimal,
+    /// Price ranges replacing `min_price..max_price` for assets of a class
+    class_ranges: BTreeMap<AssetClass, (Decimal, Decimal)>,
+    symbols: SymbolMap,
+}
+
+impl PriceRangeRule {
+    pub fn new(min: Decimal, max: Decimal) -> Self {
+        PriceRangeRule {
+            min_price: min,
+            max_price: max,
+            class_ranges: BTreeMap::new(),
+            symbols: SymbolMap::new(),
+        }
+    }
+
+    fn check_price(price: Decimal, min: Decimal, max: Decimal) -> Result<(), ValidationError> {
+        if price < min {
             return Err(ValidationError {
                 field: "price".to_string(),
-                reason: format!("Price {} exceeds maximum {}", price, self.max_price),
+                reason: format!("Price {} is below minimum {}", price, min),
             });
         }
 
-        if !price.is_finite() {
+        if price > max {
             return Err(ValidationError {
                 field: "price".to_string(),
-                reason: format!("Price {} is not finite (NaN or Infinity)", price),
+                reason: format!("Price {} exceeds maximum {}", price, max),
             });
         }
 
         Ok(())
     }
+}
 
-    pub fn validate_timestamp(&self, timestamp: i64) -> Result<(), ValidationError> {
-        let now = Utc::now().timestamp();
-        let drift = (timestamp - now).abs();
+impl ValidationRule for PriceRangeRule {
+    fn name(&self) -> &str {
+        "price"
+    }
+
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        let class = self.symbols.class_of(quote.asset);
+        match self.class_ranges.get(&class) {
+            Some(&(min, max)) => {
+                Self::check_price(quote.price, min, max).map_err(|e| ValidationError {
+                    reason: format!("{} ({} {})", e.reason, class, quote.asset),
+                    ..e
+                })
+            }
+            None => Self::check_price(quote.price, self.min_price, self.max_price),
+        }
+    }
+}
+
+/// Timestamps must be non-negative and within a drift of the current time
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampDriftRule {
+    max_drift_seconds: i64,
+}
+
+impl TimestampDriftRule {
+    pub fn new(seconds: i64) -> Self {
+        TimestampDriftRule {
+            max_drift_seconds: seconds,
+        }
+    }
+}
+
+impl ValidationRule for TimestampDriftRule {
+    fn name(&self) -> &str {
+        "drift"
+    }
+
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        let now = Utc::now().timestamp_millis();
+        let drift_ms = (quote.timestamp - now).abs();
 
-        if drift > self.max_timestamp_drift_seconds {
+        if drift_ms > self.max_drift_seconds * 1000 {
             return Err(ValidationError {
                 field: "timestamp".to_string(),
                 reason: format!(
-                    "Timestamp {} drifts {} seconds from current time (max: {})",
-                    timestamp, drift, self.max_timestamp_drift_seconds
+                    "Timestamp {} drifts {} ms from current time (max: {} s)",
+                    quote.timestamp, drift_ms, self.max_drift_seconds
                 ),
             });
         }
 
-        if timestamp < 0 {
+        if quote.timestamp < 0 {
             return Err(ValidationError {
                 field: "timestamp".to_string(),
                 reason: "Timestamp cannot be negative".to_string(),
@@ -94,27 +218,19 @@ impl Validator {
 
         Ok(())
     }
+}
 
-    pub fn validate_asset_symbol(&self, symbol: &str) -> Result<(), ValidationError> {
-        if symbol.is_empty() {
-            return Err(ValidationError {
-                field: "asset".to_string(),
-                reason: "Asset symbol cannot be empty".to_string(),
-            });
-        }
-
-        if symbol.len() > 10 {
-            return Err(ValidationError {
-                field: "asset".to_string(),
-                reason: format!("Asset symbol '{}' exceeds maximum length of 10", symbol),
-            });
-        }
+/// Every quote must name its source
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceRule;
 
-        Ok(())
+impl ValidationRule for SourceRule {
+    fn name(&self) -> &str {
+        "source"
     }
 
-    pub fn validate_source(&self, source: &str) -> Result<(), ValidationError> {
-        if source.is_empty() {
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        if quote.source.is_empty() {
             return Err(ValidationError {
                 field: "source".to_string(),
                 reason: "Source cannot be empty".to_string(),
@@ -125,6 +241,261 @@ impl Validator {
     }
 }
 
+/// Only quotes from the listed sources pass
+///
+/// Sources are compared as written on the quote, so an aggregated quote's
+/// source is e.g. `Median(Kraken,Coinbase)`.
+#[derive(Debug, Clone, Default)]
+pub struct SourceAllowlist {
+    sources: BTreeSet<String>,
+}
+
+impl SourceAllowlist {
+    pub fn new<S: Into<String>>(sources: impl IntoIterator<Item = S>) -> Self {
+        SourceAllowlist {
+            sources: sources.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl ValidationRule for SourceAllowlist {
+    fn name(&self) -> &str {
+        "allowlist"
+    }
+
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        if self.sources.contains(quote.source) {
+            return Ok(());
+        }
+        Err(ValidationError {
+            field: "source".to_string(),
+            reason: format!("Source '{}' is not on the allowlist", quote.source),
+        })
+    }
+}
+
+/// A rule made of a closure; the error's field is the rule's name
+pub struct FnRule<F> {
+    name: String,
+    check: F,
+}
+
+impl<F> FnRule<F>
+where
+    F: Fn(&Candidate) -> Result<(), String> + Send + Sync,
+{
+    pub fn new(name: impl Into<String>, check: F) -> Self {
+        FnRule {
+            name: name.into(),
+            check,
+        }
+    }
+}
+
+impl<F> ValidationRule for FnRule<F>
+where
+    F: Fn(&Candidate) -> Result<(), String> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, quote: &Candidate) -> Result<(), ValidationError> {
+        (self.check)(quote).map_err(|reason| ValidationError {
+            field: self.name.clone(),
+            reason,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Validator {
+    symbol: AssetSymbolRule,
+    price: PriceRangeRule,
+    drift: TimestampDriftRule,
+    source: SourceRule,
+    /// Rules added with `with_rule`, run after the built-in ones
+    rules: Vec<Arc<dyn ValidationRule>>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bumped whenever a validation rule changes meaning
+pub const VALIDATION_RULES_VERSION: u32 = 1;
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator {
+            symbol: AssetSymbolRule,
+            price: PriceRangeRule::new(Decimal::ZERO, Decimal::from(1_000_000)),
+            drift: TimestampDriftRule::new(3600),
+            source: SourceRule,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Defaults overridden by `PRICE_RANGE_CRYPTO`, `PRICE_RANGE_FX` and
+    /// `PRICE_RANGE_EQUITY` (`min..max`), with assets classified by
+    /// `ASSET_SYMBOLS`
+    pub fn from_env() -> Result<Self, String> {
+        let mut validator = Self::new().with_symbols(SymbolMap::from_env()?);
+        for class in AssetClass::ALL {
+            let var = format!("PRICE_RANGE_{}", class.name().to_ascii_uppercase());
+            if let Ok(range) = std::env::var(&var) {
+                let (min, max) = range
+                    .split_once("..")
+                    .and_then(|(min, max)| Some((price::parse(min).ok()?, price::parse(max).ok()?)))
+                    .filter(|(min, max)| min < max)
+                    .ok_or_else(|| format!("invalid {} '{}': expected min..max", var, range))?;
+                validator = validator.with_class_range(class, min, max);
+            }
+        }
+        Ok(validator)
+    }
+
+    pub fn with_price_range(mut self, min: Decimal, max: Decimal) -> Self {
+        self.price.min_price = min;
+        self.price.max_price = max;
+        self
+    }
+
+    pub fn with_timestamp_drift(mut self, seconds: i64) -> Self {
+        self.drift.max_drift_seconds = seconds;
+        self
+    }
+
+    /// Check prices of `class` assets against `min..max` instead of the
+    /// general range
+    pub fn with_class_range(mut self, class: AssetClass, min: Decimal, max: Decimal) -> Self {
+        self.price.class_ranges.insert(class, (min, max));
+        self
+    }
+
+    /// How assets are classified for `validate_asset_price`
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Self {
+        self.price.symbols = symbols;
+        self
+    }
+
+    /// Also check every quote against `rule`
+    pub fn with_rule(mut self, rule: impl ValidationRule + 'static) -> Self {
+        self.rules.push(Arc::new(rule));
+        self
+    }
+
+    /// Also check every quote with `check`, which returns why a quote fails
+    pub fn with_check<F>(self, name: &str, check: F) -> Self
+    where
+        F: Fn(&Candidate) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.with_rule(FnRule::new(name, check))
+    }
+
+    pub fn symbols(&self) -> &SymbolMap {
+        &self.price.symbols
+    }
+
+    /// Built-in rules first, then added ones in the order they were added
+    pub fn rules(&self) -> impl Iterator<Item = &dyn ValidationRule> {
+        [
+            &self.symbol as &dyn ValidationRule,
+            &self.price,
+            &self.drift,
+            &self.source,
+        ]
+        .into_iter()
+        .chain(self.rules.iter().map(|rule| rule.as_ref()))
+    }
+
+    /// Rules version and thresholds, recorded in entry provenance, e.g.
+    /// `v1:price=0..1000000:drift=3600s`, with any class ranges such as
+    /// `:fx=0.5..2` before the drift and any added rules as
+    /// `:rules=allowlist,...` after it
+    pub fn version(&self) -> String {
+        let classes: String = self
+            .price
+            .class_ranges
+            .iter()
+            .map(|(class, (min, max))| format!(":{}={}..{}", class, min, max))
+            .collect();
+        let added = if self.rules.is_empty() {
+            String::new()
+        } else {
+            let names: Vec<&str> = self.rules.iter().map(|rule| rule.name()).collect();
+            format!(":rules={}", names.join(","))
+        };
+        format!(
+            "v{}:price={}..{}{}:drift={}s{}",
+            VALIDATION_RULES_VERSION,
+            self.price.min_price,
+            self.price.max_price,
+            classes,
+            self.drift.max_drift_seconds,
+            added
+        )
+    }
+
+    /// Check `quote` against every rule, returning all it breaks
+    pub fn validate(&self, quote: &Candidate) -> Result<(), Violations> {
+        let violations: Vec<ValidationError> = self
+            .rules()
+            .filter_map(|rule| rule.check(quote).err())
+            .collect();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Violations(violations))
+        }
+    }
+
+    pub fn validate_price(&self, price: Decimal) -> Result<(), ValidationError> {
+        PriceRangeRule::check_price(price, self.price.min_price, self.price.max_price)
+    }
+
+    /// Validate `price` against the range of `asset`'s class, or the
+    /// general range when the class has none
+    pub fn validate_asset_price(&self, asset: &str, price: Decimal) -> Result<(), ValidationError> {
+        self.price.check(&Candidate {
+            asset,
+            price,
+            timestamp: 0,
+            source: "",
+        })
+    }
+
+    /// Validate a unix timestamp in milliseconds against the allowed drift
+    pub fn validate_timestamp(&self, timestamp: i64) -> Result<(), ValidationError> {
+        self.drift.check(&Candidate {
+            asset: "",
+            price: Decimal::ZERO,
+            timestamp,
+            source: "",
+        })
+    }
+
+    pub fn validate_asset_symbol(&self, symbol: &str) -> Result<(), ValidationError> {
+        self.symbol.check(&Candidate {
+            asset: symbol,
+            price: Decimal::ZERO,
+            timestamp: 0,
+            source: "",
+        })
+    }
+
+    pub fn validate_source(&self, source: &str) -> Result<(), ValidationError> {
+        self.source.check(&Candidate {
+            asset: "",
+            price: Decimal::ZERO,
+            timestamp: 0,
+            source,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,38 +503,100 @@ mod tests {
     #[test]
     fn test_validate_price_positive() {
         let validator = Validator::new();
-        assert!(validator.validate_price(50000.0).is_ok());
+        assert!(validator.validate_price(Decimal::from(50000)).is_ok());
     }
 
     #[test]
     fn test_validate_price_negative() {
         let validator = Validator::new();
-        assert!(validator.validate_price(-100.0).is_err());
+        assert!(validator.validate_price(Decimal::from(-100)).is_err());
     }
 
     #[test]
-    fn test_validate_price_nan() {
+    fn test_validate_timestamp_valid() {
         let validator = Validator::new();
-        assert!(validator.validate_price(f32::NAN).is_err());
+        let timestamp = Utc::now().timestamp_millis();
+        assert!(validator.validate_timestamp(timestamp).is_ok());
     }
 
     #[test]
-    fn test_validate_price_infinity() {
-        let validator = Validator::new();
-        assert!(validator.validate_price(f32::INFINITY).is_err());
+    fn test_validate_timestamp_drift_in_millis() {
+        let validator = Validator::new().with_timestamp_drift(1);
+        let now = Utc::now().timestamp_millis();
+        assert!(validator.validate_timestamp(now + 500).is_ok());
+        assert!(validator.validate_timestamp(now + 5_000).is_err());
     }
 
     #[test]
-    fn test_validate_timestamp_valid() {
+    fn test_validate_timestamp_negative() {
         let validator = Validator::new();
-        let timestamp = Utc::now().timestamp();
-        assert!(validator.validate_timestamp(timestamp).is_ok());
+        assert!(validator.validate_timestamp(-1).is_err());
     }
 
     #[test]
-    fn test_validate_timestamp_negative() {
-        let validator = Validator::new();
-        assert!(validator.validate_timestamp(-1).is_err());
+    fn test_validate_price_by_asset_class() {
+        let validator = Validator::new()
+            .with_symbols(SymbolMap::new().with_mapping("EURUSD", AssetClass::Fx, "EUR/USD"))
+            .with_class_range(
+                AssetClass::Fx,
+                price::parse("0.5").unwrap(),
+                Decimal::from(2),
+            );
+        assert!(validator
+            .validate_asset_price("EURUSD", price::parse("1.08").unwrap())
+            .is_ok());
+        let err = validator
+            .validate_asset_price("EURUSD", Decimal::from(50_000))
+            .unwrap_err();
+        assert!(err.reason.contains("fx EURUSD"), "{}", err);
+        // Crypto has no class range, so the general one applies
+        assert!(validator
+            .validate_asset_price("BTC", Decimal::from(50_000))
+            .is_ok());
+        assert_eq!(
+            validator.version(),
+            "v1:price=0..1000000:fx=0.5..2:drift=3600s"
+        );
+    }
+
+    #[test]
+    fn test_added_rules_and_every_violation() {
+        let validator = Validator::new()
+            .with_rule(SourceAllowlist::new(["Kraken", "Coinbase"]))
+            .with_check("whole", |quote| {
+                if quote.price.fract().is_zero() {
+                    Ok(())
+                } else {
+                    Err(format!("Price {} is not whole", quote.price))
+                }
+            });
+        let now = Utc::now().timestamp_millis();
+        let quote = |asset, price: &str, timestamp, source| Candidate {
+            asset,
+            price: price::parse(price).unwrap(),
+            timestamp,
+            source,
+        };
+        assert!(validator
+            .validate(&quote("BTC", "50000", now, "Kraken"))
+            .is_ok());
+
+        let Violations(errors) = validator
+            .validate(&quote("", "50000.5", -1, "Bitstamp"))
+            .unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["asset", "timestamp", "source", "whole"]);
+        assert_eq!(
+            validator.version(),
+            "v1:price=0..1000000:drift=3600s:rules=allowlist,whole"
+        );
+        assert_eq!(
+            validator
+                .rules()
+                .map(|rule| rule.name())
+                .collect::<Vec<_>>(),
+            ["symbol", "price", "drift", "source", "allowlist", "whole"]
+        );
     }
 
     #[test]
@@ -0,0 +1,189 @@
+//! Node feature flags
+//!
+//! Experimental subsystems can be switched off per node, so a cluster can
+//! roll one out a few nodes at a time and back it out without a new build.
+//! `NODE_FEATURES` adjusts the default set, which has every feature on:
+//!
+//! ```text
+//! NODE_FEATURES=-sharding,-anchoring     # everything but these
+//! NODE_FEATURES=none,anchoring           # only anchoring
+//! ```
+//!
+//! `none` and `all` reset the set and may only come first; each following
+//! name turns a feature on and `-name` turns it off. The node logs the
+//! active set at startup and reports it under `features` on `/health`.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A subsystem that can be switched off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    /// One PBFT instance per `PBFT_SHARDS` shard, with cross-shard commits
+    Sharding,
+    /// OpenTimestamps anchoring of the chain head (`ANCHOR_INTERVAL_SECS`)
+    Anchoring,
+    /// Consensus algorithms other than PBFT (gossip, eventual, quorum-less,
+    /// Flexible Paxos)
+    ExperimentalConsensus,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [
+        Feature::Sharding,
+        Feature::Anchoring,
+        Feature::ExperimentalConsensus,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Sharding => "sharding",
+            Feature::Anchoring => "anchoring",
+            Feature::ExperimentalConsensus => "experimental-consensus",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase().replace('_', "-");
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Feature::ALL.iter().map(Feature::name).collect();
+                format!("unknown feature '{}' (expected {})", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Features enabled on this node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureFlags {
+    enabled: BTreeSet<Feature>,
+}
+
+impl FeatureFlags {
+    /// Every feature on; the default
+    pub fn all() -> Self {
+        FeatureFlags {
+            enabled: Feature::ALL.into_iter().collect(),
+        }
+    }
+
+    pub fn none() -> Self {
+        FeatureFlags {
+            enabled: BTreeSet::new(),
+        }
+    }
+
+    pub fn with(mut self, feature: Feature) -> Self {
+        self.enabled.insert(feature);
+        self
+    }
+
+    pub fn without(mut self, feature: Feature) -> Self {
+        self.enabled.remove(&feature);
+        self
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.enabled.contains(&feature)
+    }
+
+    /// Apply `[all|none,][-]name,...` to the default set
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut flags = Self::all();
+        let items = spec.split(',').map(str::trim).filter(|i| !i.is_empty());
+        for (i, item) in items.enumerate() {
+            match item.to_ascii_lowercase().as_str() {
+                "all" | "none" if i > 0 => {
+                    return Err(format!("'{}' must come before any feature", item))
+                }
+                "all" => flags = Self::all(),
+                "none" => flags = Self::none(),
+                _ => match item.strip_prefix('-') {
+                    Some(name) => flags = flags.without(name.parse()?),
+                    None => flags = flags.with(item.trim_start_matches('+').parse()?),
+                },
+            }
+        }
+        Ok(flags)
+    }
+
+    /// `NODE_FEATURES`, or every feature when unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("NODE_FEATURES") {
+            Ok(spec) => Self::parse(&spec).map_err(|e| format!("invalid NODE_FEATURES: {}", e)),
+            Err(_) => Ok(Self::all()),
+        }
+    }
+
+    /// Every feature by name with whether it is on, as `/health` reports it
+    pub fn report(&self) -> BTreeMap<&'static str, bool> {
+        Feature::ALL
+            .into_iter()
+            .map(|feature| (feature.name(), self.is_enabled(feature)))
+            .collect()
+    }
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl fmt::Display for FeatureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.enabled.is_empty() {
+            return f.write_str("none");
+        }
+        let names: Vec<&str> = self.enabled.iter().map(Feature::name).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_feature_flags() {
+        assert_eq!(FeatureFlags::parse("").unwrap(), FeatureFlags::all());
+        let flags = FeatureFlags::parse("-sharding, -ANCHORING").unwrap();
+        assert!(!flags.is_enabled(Feature::Sharding));
+        assert!(!flags.is_enabled(Feature::Anchoring));
+        assert!(flags.is_enabled(Feature::ExperimentalConsensus));
+        assert_eq!(flags.to_string(), "experimental-consensus");
+
+        let flags = FeatureFlags::parse("none,anchoring").unwrap();
+        assert_eq!(flags, FeatureFlags::none().with(Feature::Anchoring));
+        assert_eq!(
+            flags.report(),
+            BTreeMap::from([
+                ("anchoring", true),
+                ("experimental-consensus", false),
+                ("sharding", false)
+            ])
+        );
+        assert_eq!(
+            FeatureFlags::parse("none,experimental_consensus").unwrap(),
+            FeatureFlags::none().with(Feature::ExperimentalConsensus)
+        );
+        assert_eq!(FeatureFlags::parse("none").unwrap().to_string(), "none");
+        assert!(FeatureFlags::parse("sharding,none").is_err());
+        assert!(FeatureFlags::parse("-telepathy").is_err());
+    }
+}
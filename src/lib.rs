@@ -1,4 +1,10 @@
 pub mod consensus;
 pub mod etl;
+pub mod features;
 pub mod logger;
 pub mod network;
+pub mod retry;
+pub mod supervisor;
+pub mod system_metrics;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -1,5 +1,7 @@
 //! Logging configuration
 
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::LazyLock;
 use tracing_subscriber::{
     fmt, fmt::time::ChronoLocal, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
@@ -12,54 +14,9 @@ static HOSTNAME: LazyLock<String> = LazyLock::new(|| {
         .unwrap_or_else(|| "unknown".to_string())
 });
 
+/// Resident memory of this process, e.g. `42.7M`; see `system_metrics`
 fn get_memory_usage() -> String {
-    #[cfg(target_os = "linux")]
-    {
-        use std::fs;
-        if let Ok(status) = fs::read_to_string("/proc/self/status") {
-            for line in status.lines() {
-                if line.starts_with("VmRSS:") {
-                    if let Some(kb_str) = line.split_whitespace().nth(1) {
-                        if let Ok(kb) = kb_str.parse::<f64>() {
-                            let mb = kb / 1024.0;
-                            return format!("{:.1}M", mb);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        use std::process::Command;
-        if let Ok(output) = Command::new("ps")
-            .args(&["-o", "rss=", "-p"])
-            .arg(std::process::id().to_string())
-            .output()
-        {
-            if let Ok(mut rss_str) = String::from_utf8(output.stdout) {
-                rss_str = rss_str.trim().to_string();
-                if let Ok(kb) = rss_str.parse::<f64>() {
-                    let mb = kb / 1024.0;
-                    return format!("{:.1}M", mb);
-                }
-            }
-        }
-    }
-
-    {
-        use sysinfo::System;
-        let mut system = System::new();
-        system.refresh_process(sysinfo::Pid::from_u32(std::process::id()));
-        if let Some(process) = system.process(sysinfo::Pid::from_u32(std::process::id())) {
-            let memory_kb = process.memory() / 1024;
-            let memory_mb = memory_kb as f64 / 1024.0;
-            return format!("{:.1}M", memory_mb);
-        }
-    }
-
-    "N/A".to_string()
+    crate::system_metrics::sample().memory_display()
 }
 
 #[allow(dead_code)]
@@ -166,6 +123,24 @@ macro_rules! log_with_context {
     };
 }
 
+static TRACE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A new correlation id: 16 hex characters, distinct across hosts,
+/// processes and calls
+pub fn new_trace_id() -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(HOSTNAME.as_bytes());
+    hasher.update(std::process::id().to_be_bytes());
+    hasher.update(TRACE_COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    hasher.update(
+        chrono::Utc::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_be_bytes(),
+    );
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
 pub fn get_hostname() -> &'static str {
     &*HOSTNAME
 }
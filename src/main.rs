@@ -1,29 +1,68 @@
+mod cli;
 mod consensus;
 mod etl;
+mod features;
 mod logger;
 mod network;
-
-use actix_rt;
-use chrono::prelude::*;
-use consensus::algorithms::{eventual, flexible_paxos, gossip, pbft::PBFTConsensus, quorumless};
-use consensus::algorithms::{MessageType, PBFTManager, PBFTMessage};
+mod retry;
+mod simulate;
+mod supervisor;
+mod system_metrics;
+#[cfg(test)]
+mod testing;
+
+use consensus::algorithms::pbft::{observers_from_env, view_change_timeout_from_env};
+use consensus::algorithms::{eventual, flexible_paxos, gossip, quorumless};
+use consensus::algorithms::{PBFTManager, PBFTMessage};
+use consensus::cross_shard::CrossShardCoordinator;
+use consensus::demo::{DemoMode, DemoPhase};
+use consensus::event_log::{ConsensusEvent, EventLog};
+use consensus::finality::FinalityRule;
+use consensus::fork_choice::{self, ForkTree};
+use consensus::quorum;
+use consensus::shard::{self, ShardRouter};
 use consensus::{ConsensusAlgorithm, ConsensusResult};
-use etl::extract::Extractor;
-use etl::load::DatabaseManager;
-use etl::transform::Transformer;
-use etl::{Block, MarketData};
-use network::{broadcast_message, start_server, NetworkHandler};
+use etl::encryption::PayloadCipher;
+use etl::extract::{retry_policy_from_env, ExtractResult};
+use etl::extract_status::ExtractionTracker;
+use etl::group_commit::{GroupCommitConfig, GroupCommitter};
+use etl::hlc::HybridClock;
+use etl::load::{CommitLatency, DatabaseError, DatabaseManager};
+use etl::lock::LedgerLock;
+use etl::pipeline::{AssetTimestamps, Pipeline};
+use etl::profile::{ValidationProfile, PROFILE_ANNOTATION};
+use etl::schedule::ExtractionSchedule;
+use etl::setup::{self, TransformSetup};
+use etl::sla::CommitSla;
+use etl::transform_stats::TransformTracker;
+use etl::validator::Validator;
+use etl::{Block, MarketData, BLOCK_FORMAT_VERSION};
+use features::{Feature, FeatureFlags};
+use network::admin::{ControlState, NodeControl};
+use network::clock::ClockSkewMonitor;
+use network::forwarding::{self, Forwarded};
+use network::membership::ClusterMembership;
+use network::outbox::Outbox;
+use network::peer_addr::{bind_ip_from_env, local_address, PeerAddr};
+use network::protocol::PeerVersions;
+use network::redaction;
+use network::services::{NodeServices, ServiceOptions};
+use network::sync::{ChainSyncer, Checkpoint};
+use network::tenancy;
+use network::{HandleOutcome, NetworkHandler, ServerContext};
 use std::env;
 use std::error::Error;
 use std::io::{self, Write};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
-use tracing::{debug, error, info, warn};
+use supervisor::{ExitCode, ExitError, PidFile};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::etl::price::Decimal;
     use std::fs;
 
     static INIT: std::sync::Once = std::sync::Once::new();
@@ -37,19 +76,8 @@ mod tests {
     #[test]
     fn test_block_hash_calculation() {
         init();
-        let block = Block {
-            index: 1,
-            timestamp: 1234567890,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0,
-                source: "Test".to_string(),
-                timestamp: 1234567890,
-            }],
-            previous_hash: "0000_genesis".to_string(),
-            hash: String::new(),
-            nonce: 0,
-        };
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let block = testing::block(1, "0000_genesis", vec![entry]);
 
         let hash = block.calculate_hash();
         assert!(!hash.is_empty());
@@ -59,24 +87,196 @@ mod tests {
     #[test]
     fn test_block_hash_consistency() {
         init();
-        let block1 = Block {
-            index: 1,
-            timestamp: 1234567890,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0,
-                source: "Test".to_string(),
-                timestamp: 1234567890,
-            }],
-            previous_hash: "0000_genesis".to_string(),
-            hash: String::new(),
-            nonce: 0,
-        };
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let block1 = testing::block(1, "0000_genesis", vec![entry]);
 
         let block2 = block1.clone();
         assert_eq!(block1.calculate_hash(), block2.calculate_hash());
     }
 
+    #[test]
+    fn test_content_id_ignores_commit_timing() {
+        init();
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let local = testing::block(3, "parent", vec![entry]);
+
+        // Another node builds the same block later
+        let mut remote = local.clone();
+        remote.timestamp += 750;
+        remote.nonce = 9;
+        remote.calculate_hash_with_nonce();
+        assert_ne!(local.hash, remote.hash);
+        assert_eq!(local.content_id(), remote.content_id());
+        assert_eq!(local.content_id().len(), 64);
+
+        let mut other = local.clone();
+        other.data[0].price = Decimal::from(50001);
+        assert_ne!(other.content_id(), local.content_id());
+        let mut other = local.clone();
+        other.previous_hash = "fork".to_string();
+        assert_ne!(other.content_id(), local.content_id());
+        let mut other = local.clone();
+        other.index = 4;
+        assert_ne!(other.content_id(), local.content_id());
+
+        // Fees are voted on; the proposer's annotations are not
+        let mut other = local.clone();
+        other.fees.push(etl::accounting::FeeRecord {
+            submitter: "Test".to_string(),
+            entries: 1,
+            amount: 2,
+        });
+        assert_ne!(other.content_id(), local.content_id());
+        let mut other = local.clone();
+        other
+            .annotations
+            .insert("pipeline".to_string(), "v2".to_string());
+        assert_eq!(other.content_id(), local.content_id());
+    }
+
+    #[test]
+    fn test_legacy_block_hash_is_unchanged() {
+        init();
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let mut block = testing::block(1, "0000_genesis", vec![entry]);
+        block.format_version = etl::LEGACY_BLOCK_FORMAT_VERSION;
+
+        let legacy_input = format!(
+            "{}{}{}{}{}",
+            block.index,
+            block.timestamp,
+            serde_json::to_string(&block.data).unwrap(),
+            block.previous_hash,
+            block.nonce
+        );
+        let expected = format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(legacy_input));
+        assert_eq!(block.calculate_hash(), expected);
+
+        block.format_version = BLOCK_FORMAT_VERSION;
+        assert_ne!(block.calculate_hash(), expected);
+    }
+
+    #[test]
+    fn test_float_price_blocks_keep_their_hashes() {
+        init();
+        // Written before prices became decimal; hashes pinned from that build
+        let json = r#"{"index":7,"timestamp":1700000000000,"data":[{"asset":"BTC",
+            "price":64012.4,"source":"Kraken","timestamp":1700000000000,
+            "provenance":{"raw_price":64012.37,"raw_source":"Kraken","steps":[
+            {"step":"sanitized","field":"price","sanitizer":"precision(1)",
+            "before":"64012.37","after":"64012.4"},
+            {"step":"normalized","method":"round(2)","before":64012.4,"after":64012.4}],
+            "validator":"v1:test"}}],"previous_hash":"parent","hash":"","nonce":0,
+            "format_version":1}"#;
+        let mut block: Block = serde_json::from_str(json).unwrap();
+        assert_eq!(block.data[0].price, etl::price::parse("64012.4").unwrap());
+        let provenance = block.data[0].provenance.clone().unwrap();
+        assert_eq!(provenance.verify(&block.data[0]), Ok(()));
+        assert_eq!(
+            block.calculate_hash(),
+            "d56cc34238c3171e0cdb0f93c4770b0d9e309d47689d14e3851828bd86057260"
+        );
+        assert_eq!(
+            block.content_id(),
+            "eff5fa1d8181c220a82a97a2a83e8c11259ba29259aa7d82859ba07e8bf49f4c"
+        );
+
+        let mut legacy = block.clone();
+        legacy.data[0].provenance = None;
+        legacy.format_version = etl::LEGACY_BLOCK_FORMAT_VERSION;
+        assert_eq!(
+            legacy.calculate_hash(),
+            "d7d0411bda278385a893e72b09e7a49ca38efa9e4e4b039e489a1f13c71381ae"
+        );
+
+        block.calculate_hash_with_nonce();
+        let v1_hash = block.hash.clone();
+        let mapping = Block::upgrade_chain_format(std::slice::from_mut(&mut block));
+        assert_eq!(mapping, vec![(v1_hash.clone(), block.hash.clone())]);
+        assert_ne!(block.hash, v1_hash);
+        assert_eq!(block.format_version, BLOCK_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn test_source_quotes_hash_by_digits_from_format_3() {
+        init();
+        let quote = |source: &str, price: &str| etl::divergence::SourceQuote {
+            source: source.to_string(),
+            price: etl::price::parse(price).unwrap(),
+            timestamp: 1_700_000_000_000,
+            volume: None,
+        };
+        let mut block = testing::block(1, "0000_genesis", Vec::new());
+        block.divergences = vec![etl::divergence::DivergenceEvent {
+            asset: "BTC".to_string(),
+            quotes: vec![quote("Kraken", "64012.37"), quote("Coinbase", "70000")],
+            median_price: etl::price::parse("67006.185").unwrap(),
+            spread_pct: 9.0,
+            threshold_pct: 1.0,
+        }];
+        let exact = block.calculate_hash();
+        block.format_version = etl::FLOAT_QUOTE_BLOCK_FORMAT_VERSION;
+        let float = block.calculate_hash();
+
+        // Both round to the same f32, which is all format 2 hashed
+        block.divergences[0].quotes[0].price = etl::price::parse("64012.371").unwrap();
+        assert_eq!(block.calculate_hash(), float);
+        block.format_version = BLOCK_FORMAT_VERSION;
+        assert_ne!(block.calculate_hash(), exact);
+    }
+
+    #[test]
+    fn test_canonical_hash_ignores_float_sign_of_zero() {
+        init();
+        let entry = testing::entry("BTC", "Test", Decimal::from(0), 1_234_567_890_000);
+        let mut block = testing::block(1, "0000_genesis", vec![entry]);
+        let positive = block.calculate_hash();
+        block.data[0].price = -Decimal::ZERO;
+        assert_eq!(block.calculate_hash(), positive);
+        // Decimal prices hash by value, whatever their scale
+        block.data[0].price = Decimal::new(0, 2);
+        assert_eq!(block.calculate_hash(), positive);
+        block.data[0].price = Decimal::new(150, 2);
+        let scaled = block.calculate_hash();
+        block.data[0].price = Decimal::new(15, 1);
+        assert_eq!(block.calculate_hash(), scaled);
+        block.data[0].price = Decimal::ZERO;
+
+        // Field boundaries are length-prefixed, so shifting bytes between
+        // adjacent strings changes the hash
+        block.data[0].asset = "BTCT".to_string();
+        block.data[0].source = "est".to_string();
+        assert_ne!(block.calculate_hash(), positive);
+    }
+
+    #[test]
+    fn test_upgrade_chain_format_relinks_blocks() {
+        init();
+        let mut chain = Vec::new();
+        let mut prev_hash = "0000_genesis".to_string();
+        for index in 1..=3 {
+            let mut block = testing::block(index, &prev_hash, Vec::new());
+            block.format_version = etl::LEGACY_BLOCK_FORMAT_VERSION;
+            block.calculate_hash_with_nonce();
+            prev_hash = block.hash.clone();
+            chain.push(block);
+        }
+        let old_hashes: Vec<String> = chain.iter().map(|b| b.hash.clone()).collect();
+
+        let mapping = Block::upgrade_chain_format(&mut chain);
+
+        assert_eq!(mapping.len(), 3);
+        assert_eq!(chain[0].previous_hash, "0000_genesis");
+        for i in 0..3 {
+            assert_eq!(mapping[i].0, old_hashes[i]);
+            assert_eq!(mapping[i].1, chain[i].hash);
+            assert_eq!(chain[i].format_version, BLOCK_FORMAT_VERSION);
+            assert_eq!(chain[i].calculate_hash(), chain[i].hash);
+        }
+        assert_eq!(chain[1].previous_hash, chain[0].hash);
+        assert_eq!(chain[2].previous_hash, chain[1].hash);
+    }
+
     #[test]
     fn test_database_manager_init() {
         init();
@@ -97,19 +297,9 @@ mod tests {
         let db = DatabaseManager::new(test_db).unwrap();
         db.init().unwrap();
 
-        let block = Block {
-            index: 1,
-            timestamp: 1234567890,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0,
-                source: "Test".to_string(),
-                timestamp: 1234567890,
-            }],
-            previous_hash: "0000_genesis".to_string(),
-            hash: "abc123".to_string(),
-            nonce: 0,
-        };
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let mut block = testing::block(1, "0000_genesis", vec![entry]);
+        block.hash = "abc123".to_string();
 
         assert!(db.save_block(&block).is_ok());
 
@@ -141,35 +331,11 @@ mod tests {
         let db = DatabaseManager::new(test_db).unwrap();
         db.init().unwrap();
 
-        let mut block1 = Block {
-            index: 1,
-            timestamp: 1234567890,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50000.0,
-                source: "Test".to_string(),
-                timestamp: 1234567890,
-            }],
-            previous_hash: "0000_genesis".to_string(),
-            hash: String::new(),
-            nonce: 0,
-        };
-        block1.calculate_hash_with_nonce();
-
-        let mut block2 = Block {
-            index: 2,
-            timestamp: 1234567891,
-            data: vec![MarketData {
-                asset: "BTC".to_string(),
-                price: 50100.0,
-                source: "Test".to_string(),
-                timestamp: 1234567891,
-            }],
-            previous_hash: block1.hash.clone(),
-            hash: String::new(),
-            nonce: 0,
-        };
-        block2.calculate_hash_with_nonce();
+        let entry = testing::entry("BTC", "Test", Decimal::from(50000), 1_234_567_890_000);
+        let block1 = testing::block(1, "0000_genesis", vec![entry]);
+
+        let entry = testing::entry("BTC", "Test", Decimal::from(50100), 1_234_567_891_000);
+        let block2 = testing::block(2, &block1.hash, vec![entry]);
 
         let blocks = vec![block1, block2];
 
@@ -214,6 +380,17 @@ impl ConsensusType {
         }
     }
 
+    /// Value `--consensus` takes for this algorithm
+    fn key(&self) -> &'static str {
+        match self {
+            ConsensusType::PBFT => "pbft",
+            ConsensusType::Gossip => "gossip",
+            ConsensusType::Eventual => "eventual",
+            ConsensusType::Quorumless => "quorumless",
+            ConsensusType::FlexiblePaxos => "flexible_paxos",
+        }
+    }
+
     fn name(&self) -> &'static str {
         match self {
             ConsensusType::PBFT => "PBFT",
@@ -266,9 +443,8 @@ fn show_consensus_menu() {
     io::stdout().flush().unwrap();
 }
 
-fn get_consensus_selection() -> ConsensusType {
-    let args: Vec<String> = env::args().collect();
-    for arg in &args {
+fn get_consensus_selection(args: &[String]) -> ConsensusType {
+    for arg in args {
         if arg.starts_with("--consensus=") {
             if let Some(value) = arg.split('=').nth(1) {
                 if let Some(consensus) = ConsensusType::from_str(value) {
@@ -305,62 +481,279 @@ async fn run_pbft_consensus(
     block: Block,
     pbft: Arc<PBFTManager>,
     node_addresses: &[String],
-    port: u16,
+    local: &PeerAddr,
+    trace_id: &str,
+    demo: &DemoMode,
+    outbox: &Outbox,
 ) -> Result<Option<Block>, Box<dyn Error>> {
-    let sequence = block.index;
+    let sequence = pbft.sequence_for(&block);
+    // Vote on the content id so nodes that built the same block at different
+    // times agree on it
+    let local_id = block.content_id();
 
-    if pbft.is_primary(sequence) {
+    let propose = || async {
         info!(
             node_id = pbft.node_id(),
-            block_index = sequence,
+            block_index = block.index,
+            sequence,
+            view = pbft.view(),
             "PBFT: Node is PRIMARY for block"
         );
         let block_json = serde_json::to_string(&block).unwrap_or_default();
-        let pre_prepare_msg = pbft.create_pre_prepare(&block.hash, &block_json, sequence);
+        let pre_prepare_msg = pbft
+            .create_pre_prepare(&local_id, block_json, sequence)
+            .with_trace_id(trace_id);
 
-        broadcast_message(&pre_prepare_msg, node_addresses, port).await;
+        outbox
+            .broadcast(&pre_prepare_msg, node_addresses, local)
+            .await;
         pbft.handle_pre_prepare(&pre_prepare_msg);
+        demo.narrate(
+            DemoPhase::PrePrepare,
+            sequence,
+            "This node is primary and broadcast the pre-prepare",
+        );
+    };
+
+    demo.enter(DemoPhase::PrePrepare, sequence).await;
+    if pbft.is_primary(sequence) {
+        propose().await;
+    } else {
+        demo.narrate(
+            DemoPhase::PrePrepare,
+            sequence,
+            "Waiting for the primary's pre-prepare",
+        );
+        let timeout = pbft.view_change_timeout();
+        if !wait_until(timeout, || pbft.has_pre_prepare(sequence)).await {
+            // The primary is silent: vote to replace it, and propose this
+            // node's block if the new view makes it primary
+            let view_change = pbft.create_view_change(sequence).with_trace_id(trace_id);
+            warn!(
+                sequence,
+                primary = ?pbft.primary_for(sequence),
+                view = view_change.view,
+                "PBFT: No pre-prepare from the primary, voting for a view change"
+            );
+            demo.narrate(
+                DemoPhase::PrePrepare,
+                sequence,
+                "The primary is silent, voting for a view change",
+            );
+            outbox.broadcast(&view_change, node_addresses, local).await;
+            pbft.handle_view_change(&view_change);
+            if !wait_until(timeout, || pbft.view() >= view_change.view).await {
+                warn!(
+                    sequence,
+                    view = view_change.view,
+                    "PBFT: View change did not reach a quorum"
+                );
+                return Ok(None);
+            }
+            if pbft.is_primary(sequence) {
+                // The new view must re-propose any block the voters prepared
+                let block_json = serde_json::to_string(&block).unwrap_or_default();
+                let new_view = match pbft.create_new_view(&local_id, block_json, sequence) {
+                    Ok(new_view) => new_view.with_trace_id(trace_id),
+                    Err(reason) => {
+                        warn!(
+                            sequence,
+                            reason = %reason,
+                            "PBFT: Cannot propose in the new view"
+                        );
+                        return Ok(None);
+                    }
+                };
+                info!(
+                    node_id = pbft.node_id(),
+                    sequence,
+                    view = new_view.view,
+                    block_id = %new_view.block_hash,
+                    "PBFT: Node is PRIMARY for block in the new view"
+                );
+                outbox.broadcast(&new_view, node_addresses, local).await;
+                pbft.handle_new_view(&new_view);
+                demo.narrate(
+                    DemoPhase::PrePrepare,
+                    sequence,
+                    "This node is the new primary and broadcast the NEW-VIEW",
+                );
+            } else {
+                wait_until(timeout, || pbft.has_pre_prepare(sequence)).await;
+            }
+        }
     }
 
-    tokio::time::sleep(Duration::from_millis(500)).await;
+    // Vote for the block the primary proposed, and only if this node built
+    // the same one: its own block is what it stores once committed
+    let block_id = match pbft.pre_prepared_digest(sequence) {
+        Some(proposed) if proposed == local_id => proposed,
+        Some(proposed) => {
+            warn!(
+                sequence,
+                proposed = %proposed,
+                local = %local_id,
+                "PBFT: Primary proposed a different block, not voting"
+            );
+            return Ok(None);
+        }
+        None => {
+            warn!(sequence, "PBFT: No pre-prepare for the block, not voting");
+            return Ok(None);
+        }
+    };
+
+    tokio::time::sleep(demo.delay(Duration::from_millis(500))).await;
 
-    let prepare_msg = pbft.create_prepare(&block.hash, sequence);
-    broadcast_message(&prepare_msg, node_addresses, port).await;
+    demo.enter(DemoPhase::Prepare, sequence).await;
+
+    let prepare_msg = pbft
+        .create_prepare(&block_id, sequence)
+        .with_trace_id(trace_id);
+    outbox.broadcast(&prepare_msg, node_addresses, local).await;
     let prepare_quorum = pbft.handle_prepare(&prepare_msg);
 
-    if !prepare_quorum {
-        debug!(block_index = sequence, "PBFT: Waiting for Prepare quorum");
-        tokio::time::sleep(Duration::from_secs(2)).await;
+    if prepare_quorum {
+        demo.narrate(DemoPhase::Prepare, sequence, "Prepare quorum reached");
+    } else {
+        debug!(
+            block_index = block.index,
+            sequence, "PBFT: Waiting for Prepare quorum"
+        );
+        demo.narrate(
+            DemoPhase::Prepare,
+            sequence,
+            "No prepare quorum yet, waiting for peers' votes",
+        );
+        tokio::time::sleep(demo.delay(Duration::from_secs(2))).await;
     }
 
-    let commit_msg = pbft.create_commit(&block.hash, sequence);
-    broadcast_message(&commit_msg, node_addresses, port).await;
+    demo.enter(DemoPhase::Commit, sequence).await;
+
+    let commit_msg = pbft
+        .create_commit(&block_id, sequence)
+        .with_trace_id(trace_id);
+    outbox.broadcast(&commit_msg, node_addresses, local).await;
     let commit_quorum = pbft.handle_commit(&commit_msg);
 
     if commit_quorum {
-        info!(block_index = sequence, "PBFT: Block reached COMMIT quorum");
-        tokio::time::sleep(Duration::from_millis(300)).await;
+        info!(
+            block_index = block.index,
+            sequence, "PBFT: Block reached COMMIT quorum"
+        );
+        demo.narrate(
+            DemoPhase::Commit,
+            sequence,
+            "Commit quorum reached, the block is final",
+        );
+        tokio::time::sleep(demo.delay(Duration::from_millis(300))).await;
         return Ok(Some(block));
     }
+    demo.narrate(
+        DemoPhase::Commit,
+        sequence,
+        "Not enough commit votes, the block is not final",
+    );
 
     warn!(
-        block_index = sequence,
-        "PBFT: Block failed to reach commit quorum"
+        block_index = block.index,
+        sequence, "PBFT: Block failed to reach commit quorum"
     );
     Ok(None)
 }
 
-async fn run_consensus(
+/// Poll `done` until it holds or `timeout` passes; returns whether it held
+async fn wait_until(timeout: Duration, done: impl Fn() -> bool) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while !done() {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    true
+}
+
+/// Store how long the block's data took to commit, warning past the SLA
+fn record_commit_latency(db: &DatabaseManager, sla: &CommitSla, block: &Block) {
+    let Some(latency) = CommitLatency::for_block(block, etl::now_millis()) else {
+        return;
+    };
+    if sla.is_breached(&latency) {
+        warn!(
+            block_index = block.index,
+            latency_ms = latency.latency_ms(),
+            sla_ms = ?sla.target_ms,
+            "SLA: Block committed later than COMMIT_SLA_MS"
+        );
+    }
+    if let Err(e) = db.save_commit_latency(&latency) {
+        warn!(error = %e, "SLA: Failed to record commit latency");
+    }
+}
+
+/// What a consensus round needs besides its block
+struct ConsensusRound<'a> {
     consensus_type: ConsensusType,
-    block: Block,
     node_id: usize,
     total_nodes: usize,
-    node_addresses: &[String],
-    port: u16,
-    pbft: Arc<PBFTManager>,
+    node_addresses: &'a [String],
+    local: &'a PeerAddr,
+    coordinator: &'a CrossShardCoordinator,
+    trace_id: &'a str,
+    demo: &'a Arc<DemoMode>,
+    outbox: &'a Arc<Outbox>,
+}
+
+async fn run_consensus(
+    round: &ConsensusRound<'_>,
+    block: Block,
 ) -> Result<Option<Block>, Box<dyn Error>> {
+    let ConsensusRound {
+        consensus_type,
+        node_id,
+        total_nodes,
+        node_addresses,
+        local,
+        coordinator,
+        trace_id,
+        demo,
+        outbox,
+    } = *round;
+    demo.enter(DemoPhase::Propose, block.index).await;
     match consensus_type {
-        ConsensusType::PBFT => run_pbft_consensus(block, pbft, node_addresses, port).await,
+        ConsensusType::PBFT => {
+            // Every shard the block touches must commit it, or none does
+            let addresses = node_addresses.to_vec();
+            let outcome = coordinator
+                .execute(&block, |pbft, block| {
+                    let addresses = addresses.clone();
+                    let local = local.clone();
+                    let trace_id = trace_id.to_string();
+                    let demo = demo.clone();
+                    let outbox = outbox.clone();
+                    // Shard instances run on their own tasks; keep the round's span
+                    async move {
+                        match run_pbft_consensus(
+                            block,
+                            pbft.clone(),
+                            &addresses,
+                            &local,
+                            &trace_id,
+                            &demo,
+                            &outbox,
+                        )
+                        .await
+                        {
+                            Ok(Some(_)) => Some(pbft.last_sequence()),
+                            _ => None,
+                        }
+                    }
+                    .in_current_span()
+                })
+                .await;
+            Ok(outcome.is_committed().then_some(block))
+        }
         ConsensusType::Gossip => {
             let consensus = Arc::new(gossip::GossipConsensus::new(node_id, 3, 2));
             match consensus.propose(&block).await {
@@ -368,15 +761,15 @@ async fn run_consensus(
                     info!(block_index = block.index, "Gossip: Block committed");
                     Ok(Some(block))
                 }
-                Ok(ConsensusResult::Pending) => {
-                    warn!(block_index = block.index, "Gossip: Block pending");
+                Ok(ConsensusResult::Pending(progress)) => {
+                    warn!(block_index = block.index, progress = %progress, "Gossip: Block pending");
                     Ok(None)
                 }
                 Ok(ConsensusResult::Rejected(reason)) => {
                     warn!(block_index = block.index, reason = %reason, "Gossip: Block rejected");
                     Ok(None)
                 }
-                Err(e) => Err(e),
+                Err(e) => Err(e.into()),
             }
         }
         ConsensusType::Eventual => {
@@ -386,15 +779,15 @@ async fn run_consensus(
                     info!(block_index = block.index, "Eventual: Block committed");
                     Ok(Some(block))
                 }
-                Ok(ConsensusResult::Pending) => {
-                    warn!(block_index = block.index, "Eventual: Block pending");
+                Ok(ConsensusResult::Pending(progress)) => {
+                    warn!(block_index = block.index, progress = %progress, "Eventual: Block pending");
                     Ok(None)
                 }
                 Ok(ConsensusResult::Rejected(reason)) => {
                     warn!(block_index = block.index, reason = %reason, "Eventual: Block rejected");
                     Ok(None)
                 }
-                Err(e) => Err(e),
+                Err(e) => Err(e.into()),
             }
         }
         ConsensusType::Quorumless => {
@@ -409,9 +802,10 @@ async fn run_consensus(
                     info!(block_index = block.index, "Quorumless: Block committed");
                     Ok(Some(block))
                 }
-                Ok(ConsensusResult::Pending) => {
+                Ok(ConsensusResult::Pending(progress)) => {
                     warn!(
                         block_index = block.index,
+                        progress = %progress,
                         "Quorumless: Block pending (need more votes)"
                     );
                     Ok(None)
@@ -420,18 +814,18 @@ async fn run_consensus(
                     warn!(block_index = block.index, reason = %reason, "Quorumless: Block rejected");
                     Ok(None)
                 }
-                Err(e) => Err(e),
+                Err(e) => Err(e.into()),
             }
         }
         ConsensusType::FlexiblePaxos => {
-            let q1_size = (total_nodes + 1) / 2 + 1;
-            let q2_size = total_nodes / 2;
-            let consensus = Arc::new(flexible_paxos::FlexiblePaxos::new(
-                node_id,
-                total_nodes,
-                q1_size,
-                q2_size,
-            ));
+            let (q1_size, q2_size) = flexible_paxos::quorum_sizes_from_env(total_nodes)?;
+            let mut consensus =
+                flexible_paxos::FlexiblePaxos::new(node_id, total_nodes, q1_size, q2_size);
+            if let Some(domains) = quorum::FailureDomains::from_env()? {
+                domains.check(total_nodes)?;
+                consensus = consensus.with_failure_domains(domains);
+            }
+            let consensus = Arc::new(consensus);
 
             match consensus.propose(&block).await {
                 Ok(ConsensusResult::Committed(committed_block)) => {
@@ -443,9 +837,10 @@ async fn run_consensus(
                     );
                     Ok(Some(committed_block))
                 }
-                Ok(ConsensusResult::Pending) => {
+                Ok(ConsensusResult::Pending(progress)) => {
                     warn!(
                         block_index = block.index,
+                        progress = %progress,
                         "Flexible Paxos: Block pending (quorum not reached)"
                     );
                     Ok(None)
@@ -454,38 +849,205 @@ async fn run_consensus(
                     warn!(block_index = block.index, reason = %reason, "Flexible Paxos: Block rejected");
                     Ok(None)
                 }
-                Err(e) => Err(e),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Pull blocks the peers committed that this node is missing
+async fn sync_with_peers(syncer: &ChainSyncer, node_addresses: &[String], local: &PeerAddr) {
+    for (peer, result) in syncer.sync_from_peers(node_addresses, local).await {
+        match result {
+            Ok(report) => {
+                if let Some((index, reason)) = report.quarantined {
+                    warn!(
+                        peer = %peer,
+                        block_index = index,
+                        reason = %reason,
+                        "Sync: Quarantined block from peer"
+                    );
+                }
+            }
+            Err(e) => debug!(peer = %peer, error = %e, "Sync: Peer unavailable"),
+        }
+    }
+}
+
+/// Save a committed block, through the group committer when enabled
+async fn persist_block(
+    db: &DatabaseManager,
+    committer: Option<&GroupCommitter>,
+    block: &Block,
+) -> Result<(), DatabaseError> {
+    match committer {
+        Some(committer) => committer.commit(block.clone()).await,
+        None => db.save_block(block),
+    }
+}
+
+/// Per-asset dedupe timestamps from the blocks ordered within
+/// `window_seconds` of `head`, so assets the head block does not carry keep
+/// their window across a restart
+fn recent_asset_timestamps(
+    db: &DatabaseManager,
+    head: &Block,
+    window_seconds: i64,
+) -> AssetTimestamps {
+    let since = head.ordering_timestamp().wall_ms - window_seconds.saturating_mul(1000);
+    let mut recent = vec![head.clone()];
+    let mut end = head.index;
+    'scan: while end > 0 {
+        let start = end.saturating_sub(64);
+        let Ok(blocks) = db.get_blocks_range(start, end - 1) else {
+            break;
+        };
+        for block in blocks.into_iter().rev() {
+            if block.ordering_timestamp().wall_ms < since {
+                break 'scan;
+            }
+            recent.push(block);
+        }
+        end = start;
+    }
+    let mut timestamps = AssetTimestamps::new();
+    for block in recent.iter().rev() {
+        timestamps.record_block(block);
+    }
+    timestamps
+}
+
+/// Market data for the further assets fetched this round; failed and
+/// duplicate quotes are logged and left out
+fn asset_entries(
+    pipeline: &Pipeline,
+    results: Vec<Result<ExtractResult, Box<dyn Error>>>,
+    last_timestamps: &AssetTimestamps,
+) -> Vec<MarketData> {
+    let mut entries = Vec::new();
+    for result in results {
+        let extracted = match result {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                warn!(error = %e, "Extract: Asset quote failed, leaving it out of the block");
+                continue;
+            }
+        };
+        match pipeline.run_extracted(&extracted, last_timestamps.get(&extracted.asset)) {
+            Ok(transformed) if transformed.is_deduplicated => {
+                debug!(asset = %transformed.asset, "Transform: Asset quote is a duplicate, skipping");
+            }
+            Ok(transformed) if transformed.is_buffered => {
+                debug!(asset = %transformed.asset, "Transform: Asset quote held for its time bucket");
+            }
+            Ok(transformed) => entries.push(MarketData {
+                asset: transformed.asset,
+                price: transformed.price,
+                source: transformed.source,
+                timestamp: transformed.timestamp,
+                provenance: Some(transformed.provenance.with_quotes(&extracted.quotes)),
+                indicators: transformed.indicators,
+                conversion: transformed.conversion,
+                bucket: transformed.bucket,
+            }),
+            Err(e) => {
+                warn!(asset = %extracted.asset, error = %e, "Transform: Asset quote rejected")
             }
         }
     }
+    entries
+}
+
+/// Sleep until `schedule` starts the next round; `block_interval` is the
+/// delay when it sets no cadence of its own
+async fn wait_for_next_round(schedule: &ExtractionSchedule, block_interval: Duration) {
+    let now = chrono::Utc::now();
+    let next = schedule.next_run(now, block_interval);
+    if schedule.is_market_open(next) && !schedule.is_market_open(now) {
+        info!(next_round = %next, "Schedule: Market closed, waiting for the open");
+    } else {
+        debug!(next_round = %next, "Schedule: Waiting for the next round");
+    }
+    tokio::time::sleep(schedule.delay(now, block_interval)).await;
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Some(result) = cli::dispatch(&args) {
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(ExitCode::Failure.code());
+        }
+        return;
+    }
+
     logger::init_logger_detailed();
+    let result = match simulate::cluster_size(&args) {
+        Ok(Some(size)) => simulate::run(&args, size).await,
+        Ok(None) => run_node(&args).await,
+        Err(e) => Err(ExitError::config(e).into()),
+    };
+    // The ledger lock and PID file are released by the time `run_node`
+    // returns, so exiting here skips no cleanup
+    if let Err(e) = result {
+        error!(error = %e, "Node stopped");
+        supervisor::notify_or_warn(&format!("STOPPING=1\nSTATUS=Failed: {}", e));
+        eprintln!("Error: {}", e);
+        std::process::exit(ExitCode::for_error(e.as_ref()).code());
+    }
+}
 
-    let consensus_type = get_consensus_selection();
+/// Ledger file of `node_id`, in `data_dir` or the working directory
+fn ledger_path(data_dir: Option<&str>, node_id: usize) -> String {
+    let file = format!("blockchain_node_{}.db", node_id);
+    match data_dir {
+        Some(dir) => std::path::Path::new(dir).join(file).display().to_string(),
+        None => file,
+    }
+}
+
+async fn run_node(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let consensus_type = get_consensus_selection(args);
     info!(
         consensus = consensus_type.name(),
         description = consensus_type.description(),
         "Selected consensus algorithm"
     );
+    let features = FeatureFlags::from_env().map_err(ExitError::config)?;
+    info!(features = %features, "Node: Feature flags");
+    if consensus_type != ConsensusType::PBFT && !features.is_enabled(Feature::ExperimentalConsensus)
+    {
+        return Err(ExitError::config(format!(
+            "{} consensus needs the {} feature (NODE_FEATURES)",
+            consensus_type.name(),
+            Feature::ExperimentalConsensus
+        ))
+        .into());
+    }
 
-    let args: Vec<String> = env::args().collect();
     let node_id: usize = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(0);
-    let port: u16 = args
-        .get(2)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(8000 + node_id as u16);
     let use_offline = args.contains(&"--offline".to_string()) || args.contains(&"-o".to_string());
+    let demo = Arc::new(DemoMode::from_env(args.contains(&"--demo".to_string())));
+    if demo.is_enabled() {
+        info!(
+            slowdown = demo.slowdown(),
+            "Demo: Demo mode enabled, rounds are slowed down and narrated"
+        );
+    }
 
-    let node_addresses = vec![
-        "127.0.0.1:8000".to_string(),
-        "127.0.0.1:8001".to_string(),
-        "127.0.0.1:8002".to_string(),
-        "127.0.0.1:8003".to_string(),
-    ];
+    let node_addresses =
+        network::membership::node_addresses_from_env().map_err(ExitError::config)?;
+    let port: u16 = match args.get(2).and_then(|s| s.parse().ok()) {
+        Some(port) => port,
+        None => network::membership::default_port(&node_addresses, node_id).ok_or_else(|| {
+            ExitError::config(format!("node {} has no default port; pass one", node_id))
+        })?,
+    };
+    let local_addr = local_address(&node_addresses, node_id, port);
+    let bind_ip = bind_ip_from_env().map_err(ExitError::config)?;
     let total_nodes = node_addresses.len();
+    network::topology::MESSAGE_FLOW.set_local(node_id, &node_addresses);
 
     let memory = logger::get_memory_usage_public();
     info!(
@@ -495,51 +1057,342 @@ async fn main() -> Result<(), Box<dyn Error>> {
     );
     info!("Network: {} total nodes", total_nodes);
 
-    let db_path = format!("blockchain_node_{}.db", node_id);
-    let db = DatabaseManager::new(&db_path)?;
+    let membership = match ClusterMembership::from_env().map_err(ExitError::config)? {
+        Some(membership) => {
+            membership
+                .verify_cluster(&node_addresses)
+                .map_err(ExitError::config)?;
+            info!(
+                members = membership.len(),
+                "Network: Peer allowlist enabled"
+            );
+            Some(Arc::new(membership))
+        }
+        None => None,
+    };
+
+    let data_dir = args
+        .iter()
+        .position(|arg| arg == "--data-dir")
+        .and_then(|i| args.get(i + 1));
+    let db_path = ledger_path(data_dir.map(String::as_str), node_id);
+    let force_takeover = args.contains(&"--force-takeover".to_string());
+    // Held until the node exits so no second process writes the same ledger
+    let _ledger_lock = LedgerLock::acquire(&db_path, force_takeover)?;
+    // Written only once the lock is held, so a refused second instance never
+    // overwrites the running node's PID
+    let _pid_file = match PidFile::path_from_args(args) {
+        Some(path) => {
+            let pid_file = PidFile::create(&path).map_err(|e| {
+                ExitError::config(format!("cannot write PID file {}: {}", path.display(), e))
+            })?;
+            info!(path = %path.display(), "Supervisor: Wrote PID file");
+            Some(pid_file)
+        }
+        None => None,
+    };
+    let mut db = DatabaseManager::new(&db_path)?
+        .with_block_cache(etl::block_cache::capacity_from_env())
+        .with_redaction_keys(redaction::trusted_keys_from_env().map_err(ExitError::config)?);
+    if let Some(cipher) = PayloadCipher::from_env().map_err(ExitError::config)? {
+        info!(
+            key_id = cipher.active_key_id(),
+            "Database: Encrypting block payloads at rest"
+        );
+        db = db.with_payload_cipher(cipher);
+    }
+    let db = Arc::new(db);
     db.init()?;
+    // Bring plaintext rows and rows under retired keys onto the active key
+    db.reencrypt_payloads()?;
+    supervisor::notify_or_warn("STATUS=Verifying ledger");
+    if !db.verify_chain()? {
+        return Err(ExitError::new(
+            ExitCode::DataErr,
+            format!(
+                "ledger {} failed hash-chain verification; run `verify` to locate \
+                 the damage",
+                db_path
+            ),
+        )
+        .into());
+    }
 
     // Initialize PBFT (always needed for network server, even if not used for consensus)
-    let pbft = Arc::new(PBFTManager::new(
-        node_id,
-        total_nodes,
-        node_addresses.clone(),
+    let quorum_policy = quorum::policy_from_env().map_err(ExitError::config)?;
+    info!(policy = quorum_policy.name(), "PBFT: Quorum policy");
+    let event_log = match EventLog::from_env(node_id)? {
+        Some(event_log) => {
+            event_log.record(ConsensusEvent::Run {
+                node_id,
+                total_nodes,
+                algorithm: consensus_type.name().to_string(),
+                quorum_policy: env::var("PBFT_QUORUM_POLICY").ok(),
+                failure_domains: env::var("NODE_FAILURE_DOMAINS").ok(),
+                min_domains: env::var("QUORUM_MIN_DOMAINS").ok(),
+            });
+            info!("Consensus: Recording events to CONSENSUS_EVENT_LOG");
+            Some(Arc::new(event_log))
+        }
+        None => None,
+    };
+    let observers = observers_from_env().map_err(ExitError::config)?;
+    let is_observer = observers.contains(&node_id);
+    // `PBFTManager` sizes quorums by the voting members once there are
+    // observers
+    let voting_nodes = (0..total_nodes)
+        .filter(|id| !observers.contains(id))
+        .count();
+    quorum_policy
+        .check(if observers.is_empty() {
+            total_nodes
+        } else {
+            voting_nodes
+        })
+        .map_err(|e| ExitError::config(format!("invalid PBFT_QUORUM_POLICY: {}", e)))?;
+    if !observers.is_empty() {
+        info!(
+            observers = ?observers,
+            observer = is_observer,
+            "PBFT: Observer nodes follow consensus without voting"
+        );
+    }
+    let view_change_timeout = view_change_timeout_from_env().map_err(ExitError::config)?;
+    network::peer_retry_policy().map_err(ExitError::config)?;
+    retry_policy_from_env().map_err(ExitError::config)?;
+    // Orders this node's consensus messages and blocks against its peers'
+    let clock = Arc::new(HybridClock::new(node_id));
+    let new_pbft_instance = || {
+        let manager = PBFTManager::new(node_id, total_nodes, node_addresses.clone())
+            .with_clock(clock.clone())
+            .with_quorum_policy(quorum_policy.clone())
+            .with_observers(observers.iter().copied())
+            .with_view_change_timeout(view_change_timeout);
+        match &event_log {
+            Some(log) => manager.with_event_log(log.clone()),
+            None => manager,
+        }
+    };
+    let mut shard_ids = shard::shards_from_env();
+    if !shard_ids.is_empty() && !features.is_enabled(Feature::Sharding) {
+        warn!(shards = ?shard_ids, "PBFT: Sharding feature disabled, ignoring PBFT_SHARDS");
+        shard_ids.clear();
+    }
+    let shards = Arc::new(ShardRouter::with_shards(
+        Arc::new(new_pbft_instance()),
+        &shard_ids,
+        new_pbft_instance,
     ));
-    let pbft_clone = pbft.clone();
+    if !shard_ids.is_empty() {
+        info!(shards = ?shard_ids, "PBFT: Running one instance per shard");
+    }
+    let handler_shards = shards.clone();
+    // Followers forward their entries to this instance's primary
+    let proposer = shards.default_instance().clone();
+    let coordinator = CrossShardCoordinator::new(shards);
+    let validation_profile = ValidationProfile::from_env().map_err(ExitError::config)?;
+    let control = Arc::new(NodeControl::new(ControlState {
+        validation_profile: validation_profile.map(|profile| profile.name.to_string()),
+        ..ControlState::default()
+    }));
+    let handler_control = control.clone();
 
     let network_handler = Arc::new(NetworkHandler::new(move |msg: PBFTMessage| {
-        let pbft = pbft_clone.clone();
-        match msg.msg_type {
-            MessageType::PrePrepare => pbft.handle_pre_prepare(&msg),
-            MessageType::Prepare => pbft.handle_prepare(&msg),
-            MessageType::Commit => pbft.handle_commit(&msg),
+        if !handler_control.participates_in_consensus() {
+            debug!(
+                sequence = msg.sequence,
+                "Consensus: Ignoring message, participation disabled"
+            );
+            return HandleOutcome::Rejected("consensus participation disabled".to_string());
         }
+        handler_shards.handle_message(&msg).into()
     }));
 
-    let server_port = port;
-    let handler_for_server = network_handler.clone();
+    let clock_monitor = Arc::new(ClockSkewMonitor::from_env());
+    let commit_sla = CommitSla::from_env();
+    let extraction_tracker = Arc::new(ExtractionTracker::new());
+    let transform_tracker = Arc::new(TransformTracker::new());
+    // Quorum-based modes never reorganize a committed block
+    let finality = FinalityRule::from_env(matches!(
+        consensus_type,
+        ConsensusType::PBFT | ConsensusType::FlexiblePaxos
+    ))
+    .map_err(ExitError::config)?;
+    info!(finality = %finality.describe(), "Network: Block finality");
+    let mut server_context = ServerContext::new(network_handler.clone())
+        .with_clock_monitor(clock_monitor.clone())
+        .with_database(db.clone())
+        .with_admin(control.clone(), env::var("ADMIN_TOKEN").ok())
+        .with_commit_sla(commit_sla)
+        .with_extraction_tracker(extraction_tracker.clone())
+        .with_transform_tracker(transform_tracker.clone())
+        .with_features(features.clone());
+    if let Some(membership) = &membership {
+        server_context = server_context.with_membership(membership.clone());
+    }
+    if let Some(log) = &event_log {
+        server_context = server_context.with_event_log(log.clone());
+    }
+    let (server_context, services) = NodeServices::start(
+        server_context,
+        &db,
+        ServiceOptions {
+            node_id,
+            db_path: &db_path,
+            features: &features,
+            pbft: consensus_type == ConsensusType::PBFT,
+            finality,
+        },
+    )?;
+    let NodeServices {
+        accounts,
+        tenants,
+        mempool,
+        storage_guard,
+    } = services;
+    let forward_client = reqwest::Client::new();
+
+    // Consensus messages go through the outbox so a crash mid-broadcast is
+    // finished on restart, encoded in the version negotiated with each peer
+    let peer_versions = Arc::new(PeerVersions::default());
+    let server_context = server_context.with_peer_versions(peer_versions.clone());
+    let outbox = Arc::new(Outbox::from_env(db.clone()).with_peer_versions(peer_versions));
+
+    let committer = match GroupCommitConfig::from_env().map_err(ExitError::config)? {
+        Some(config) => {
+            info!(
+                max_delay_ms = config.max_delay_ms,
+                max_batch_size = config.max_batch_size,
+                "Load: Group commit enabled"
+            );
+            Some(GroupCommitter::spawn(db.clone(), config)?)
+        }
+        None => None,
+    };
+
+    let mut syncer = ChainSyncer::new(db.clone());
+    if let Ok(key) = env::var("SYNC_API_KEY") {
+        syncer = syncer.with_api_key(key);
+    }
+    if let Some(committer) = &committer {
+        // Synced pages are written in batches rather than a block at a time
+        syncer = syncer.with_committer(committer.clone());
+    }
+    let checkpoint = Checkpoint::from_env().map_err(ExitError::config)?;
 
     if consensus_type == ConsensusType::PBFT {
-        thread::spawn(move || {
-            actix_rt::System::new().block_on(async {
-                let _ = start_server(server_port, handler_for_server).await;
-            });
-        });
+        network::spawn_server(SocketAddr::new(bind_ip, port), server_context)
+            .await
+            .map_err(|e| {
+                ExitError::new(
+                    ExitCode::Unavailable,
+                    format!("cannot bind port {}: {}", port, e),
+                )
+            })?;
+        // Give peers launched alongside this node a moment to bind too
         tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // Peers started earlier will answer; later ones are reported unreachable
+        let clock_report = clock_monitor
+            .check_peers(&node_addresses, &local_addr)
+            .await;
+        if !clock_report.is_ok() {
+            for violation in &clock_report.violations {
+                error!(violation = %violation, "Clock: Skew exceeds MAX_CLOCK_SKEW_MS");
+            }
+            return Err(format!(
+                "Clock skew check failed: {}. Fix the system clock (e.g. enable NTP) \
+                 or raise MAX_CLOCK_SKEW_MS.",
+                clock_report.violations.join("; ")
+            )
+            .into());
+        }
+        info!(
+            max_abs_skew_ms = ?clock_report.max_abs_skew_ms(),
+            peers_measured = clock_report.samples.len(),
+            "Clock: Skew check passed"
+        );
+
+        // Finish broadcasts a crash interrupted
+        if let Err(e) = outbox.replay().await {
+            warn!(error = %e, "Outbox: Failed to replay pending messages");
+        }
+
+        // A new node starts from the trusted checkpoint rather than genesis
+        if let Some(checkpoint) = &checkpoint {
+            syncer
+                .bootstrap(checkpoint, &node_addresses, &local_addr)
+                .await
+                .map_err(|e| format!("Checkpoint bootstrap failed: {}", e))?;
+        }
+
+        // Catch up on blocks committed while this node was down
+        sync_with_peers(&syncer, &node_addresses, &local_addr).await;
     }
 
+    supervisor::notify_or_warn(&format!(
+        "READY=1\nSTATUS=Node {} running on port {}",
+        node_id, port
+    ));
+
     // Initialize ETL components
-    let extractor = Extractor::new()?;
-    let transformer = Transformer::new();
+    let validator = Validator::from_env().map_err(ExitError::config)?;
+    let symbols = validator.symbols().clone();
+    let extractor = setup::extractor_from_env(validator.clone(), extraction_tracker, use_offline)?;
+    let schedule =
+        ExtractionSchedule::from_env(extractor.source_name()).map_err(ExitError::config)?;
+    if !schedule.is_default() {
+        info!(schedule = %schedule, "Extract: Extraction schedule");
+    }
+    // Blocks are built from the latest streamed tick; polling remains the
+    // fallback once the stream ends
+    let mut price_stream = match extractor.stream_source_name() {
+        Some(name) if !use_offline => {
+            info!(source = name, "Extract: Streaming market data");
+            Some(extractor.stream()?)
+        }
+        _ => None,
+    };
+    let TransformSetup {
+        mut transformer,
+        conversion_rates,
+        indicators,
+        divergence,
+    } = setup::transformer_from_env(extractor.asset(), validator, transform_tracker, &db)?;
+    let mut pipeline = transformer.pipeline();
+    let mut annotations = etl::annotations_from_env().map_err(ExitError::config)?;
+    // Applied with the first block, or the first after an operator switch
+    let mut active_profile: Option<String> = None;
+    if !annotations.is_empty() {
+        info!(annotations = ?annotations, "Transform: Annotating proposed blocks");
+    }
+    let order_book = setup::order_book_from_env().map_err(ExitError::config)?;
 
     let mut last_hash = String::from("0000_genesis_hash");
     let mut last_index = 0u64;
-    let mut last_timestamp: Option<i64> = None;
+    let mut last_timestamps = AssetTimestamps::new();
+    // Committed blocks that would reorganize past MAX_REORG_DEPTH are not saved
+    let mut fork_tree = ForkTree::new();
+    if let Some(depth) = fork_choice::max_reorg_depth_from_env().map_err(ExitError::config)? {
+        info!(max_reorg_depth = depth, "Fork: Finality window enabled");
+        fork_tree = fork_tree.with_max_reorg_depth(depth);
+    }
 
     if let Ok(Some(latest_block)) = db.get_latest_block() {
+        let _ = fork_tree.insert(&latest_block, true);
         last_hash = latest_block.hash.clone();
         last_index = latest_block.index;
-        last_timestamp = Some(latest_block.timestamp);
+        last_timestamps = recent_asset_timestamps(
+            &db,
+            &latest_block,
+            transformer.longest_deduplication_window(),
+        );
+        // Blocks built after a restart must still order after the head
+        if let Some(hlc) = &latest_block.hlc {
+            if let Err(e) = clock.observe(hlc) {
+                warn!(error = %e, "ETL: Head block's HLC is ahead of the local clock");
+            }
+        }
         info!(
             block_index = last_index,
             hash_preview = &last_hash[0..8.min(last_hash.len())],
@@ -547,139 +1400,461 @@ async fn main() -> Result<(), Box<dyn Error>> {
         );
     }
 
+    // Counts at the end of the previous round, to log each round's share
+    let mut transform_stats = transformer.stats();
     for round in 0..3 {
+        if is_observer {
+            // Observers never propose; following the voters' ledger keeps
+            // reads current
+            info!(round = round + 1, "Observer: Syncing committed blocks");
+            sync_with_peers(&syncer, &node_addresses, &local_addr).await;
+            tokio::time::sleep(demo.block_interval(control.state().block_interval_ms)).await;
+            continue;
+        }
+
+        if control.state().paused {
+            info!(round = round + 1, "Admin: Block production paused, waiting");
+            control.wait_until_resumed().await;
+        }
+
+        let wanted_profile = control.state().validation_profile;
+        if wanted_profile != active_profile {
+            if let Some(profile) = wanted_profile.as_deref().and_then(ValidationProfile::named) {
+                info!(
+                    profile = profile.name,
+                    timestamp_drift_seconds = profile.timestamp_drift_seconds,
+                    dedup_window_seconds = profile.dedup_window_seconds,
+                    anomaly_threshold = profile.anomaly_threshold,
+                    "Transform: Validation profile active"
+                );
+                transformer = transformer.with_profile(&profile);
+                pipeline = transformer.pipeline();
+                annotations.insert(PROFILE_ANNOTATION.to_string(), profile.name.to_string());
+            }
+            active_profile = wanted_profile;
+        }
+
+        if let Some(guard) = &storage_guard {
+            if let Err(reason) = guard.check(&db) {
+                error!(
+                    round = round + 1,
+                    reason = %reason,
+                    "Storage: Refusing to produce a block, free disk space first"
+                );
+                wait_for_next_round(
+                    &schedule,
+                    demo.block_interval(control.state().block_interval_ms),
+                )
+                .await;
+                continue;
+            }
+        }
+
+        if consensus_type == ConsensusType::PBFT {
+            // Retry messages peers have not acknowledged yet
+            if let Err(e) = outbox.replay().await {
+                warn!(error = %e, "Outbox: Failed to replay pending messages");
+            }
+        }
+
+        // Correlates this round's logs here and, through its consensus
+        // messages, on every peer
+        let trace_id = logger::new_trace_id();
         info!("{}", "=".repeat(60));
         info!(
             round = round + 1,
             consensus = consensus_type.name(),
+            trace_id = %trace_id,
             "Starting ETL + Consensus"
         );
 
-        let extract_result = if use_offline {
-            extractor.extract_offline().await
-        } else {
-            extractor.extract_from_api().await
-        };
+        async {
+            demo.enter(DemoPhase::Extract, last_index + 1).await;
+            // A feed that has gone quiet falls back to polling for the round
+            let streamed = match price_stream.as_mut() {
+                Some(stream) => {
+                    match tokio::time::timeout(Duration::from_secs(10), stream.latest()).await {
+                        Ok(Some(tick)) => {
+                            if let Some(name) = extractor.stream_source_name() {
+                                extractor.tracker().record_success(name, None);
+                            }
+                            Some(tick)
+                        }
+                        Ok(None) => {
+                            warn!("Extract: Market data stream ended, polling instead");
+                            price_stream = None;
+                            None
+                        }
+                        Err(_) => {
+                            warn!("Extract: No streamed tick within 10s, polling this round");
+                            if let Some(name) = extractor.stream_source_name() {
+                                extractor.tracker().record_failure(
+                                    name,
+                                    Duration::from_secs(10),
+                                    "no streamed tick within 10s",
+                                );
+                            }
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
+            // Further assets are fetched alongside the main source
+            let (extract_result, asset_results) = tokio::join!(
+                async {
+                    match streamed {
+                        Some(tick) => Ok(tick),
+                        None if use_offline => extractor.extract_offline().await,
+                        None => extractor.extract().await,
+                    }
+                },
+                async {
+                    if use_offline {
+                        Vec::new()
+                    } else {
+                        extractor.extract_assets().await
+                    }
+                }
+            );
+
+            // FX pairs quoted this round convert this round's prices
+            if let Some(rates) = &conversion_rates {
+                for quote in asset_results.iter().flatten() {
+                    rates.observe(
+                        &symbols,
+                        &quote.asset,
+                        quote.price,
+                        &quote.source,
+                        quote.timestamp,
+                    );
+                }
+            }
 
-        match extract_result {
-            Ok(extract_data) => {
-                info!(
-                    price = extract_data.price,
-                    source = %extract_data.source,
-                    timestamp = extract_data.timestamp,
-                    "Extract: Market data retrieved"
-                );
+            match extract_result {
+                Ok(extract_data) => {
+                    info!(
+                        price = %extract_data.price,
+                        source = %extract_data.source,
+                        timestamp = extract_data.timestamp,
+                        cached = extract_data.cache_hit,
+                        "Extract: Market data retrieved"
+                    );
+                    for quote in &extract_data.quotes {
+                        debug!(
+                            source = %quote.source,
+                            price = %quote.price,
+                            "Extract: Source quote"
+                        );
+                    }
 
-                let transform_result = transformer.transform(
-                    extract_data.price,
-                    extract_data.timestamp,
-                    extract_data.source.clone(),
-                    last_timestamp,
-                );
+                    let transform_result = pipeline
+                        .run_extracted(&extract_data, last_timestamps.get(&extract_data.asset));
 
-                match transform_result {
-                    Ok(transformed_data) => {
-                        if transformed_data.is_deduplicated {
-                            warn!(
-                                window_seconds = transformer.deduplication_window_seconds(),
-                                "Transform: Data appears to be duplicate, skipping"
-                            );
-                            continue;
-                        }
+                    match transform_result {
+                        Ok(transformed_data) => {
+                            if transformed_data.is_deduplicated {
+                                warn!(
+                                    window_seconds = transformer
+                                        .deduplication_window_for(&transformed_data.asset),
+                                    "Transform: Data appears to be duplicate, skipping"
+                                );
+                                return;
+                            }
+                            // A quote held for its time bucket adds no entry
+                            // of its own; other assets' closed buckets and
+                            // tenant submissions still make a block
+                            let asset = transformed_data.asset.clone();
+                            let mut data = Vec::new();
+                            if transformed_data.is_buffered {
+                                debug!(asset = %asset, "Transform: Quote held for its time bucket");
+                            } else {
+                                for change in &transformed_data.sanitized.modifications {
+                                    debug!(
+                                        field = %change.field,
+                                        sanitizer = %change.sanitizer,
+                                        before = %change.before,
+                                        after = %change.after,
+                                        "Transform: Sanitized value"
+                                    );
+                                }
 
-                        let normalized_price = transformer.normalize_price(transformed_data.price);
+                                debug!(
+                                    asset = %transformed_data.asset,
+                                    raw_price = %transformed_data.provenance.raw_price,
+                                    price = %transformed_data.price,
+                                    "Transform: Data transformed and normalized"
+                                );
 
-                        debug!(
-                            asset = %transformed_data.asset,
-                            price = transformed_data.price,
-                            normalized_price = normalized_price,
-                            "Transform: Data transformed and normalized"
-                        );
+                                data.push(MarketData {
+                                    asset: transformed_data.asset,
+                                    price: transformed_data.price,
+                                    source: transformed_data.source,
+                                    timestamp: transformed_data.timestamp,
+                                    provenance: Some(
+                                        transformed_data.provenance.with_quotes(&extract_data.quotes),
+                                    ),
+                                    indicators: transformed_data.indicators,
+                                    conversion: transformed_data.conversion,
+                                    bucket: transformed_data.bucket,
+                                });
+                            }
+                            data.extend(asset_entries(&pipeline, asset_results, &last_timestamps));
+                            if mempool.is_some() && !data.is_empty() {
+                                // Only the primary's block is proposed; hand
+                                // this round's entries to it as well
+                                let primary = proposer
+                                    .primary_for(last_index + 1)
+                                    .filter(|primary| *primary != node_id)
+                                    .and_then(|primary| node_addresses.get(primary));
+                                if let Some(address) = primary.cloned() {
+                                    let client = forward_client.clone();
+                                    let forwarded = Forwarded {
+                                        node_id,
+                                        entries: data.clone(),
+                                    };
+                                    let trace_id = trace_id.clone();
+                                    tokio::spawn(
+                                        async move {
+                                            match forwarding::forward(
+                                                &client,
+                                                &address,
+                                                &forwarded,
+                                                Some(&trace_id),
+                                            )
+                                            .await
+                                            {
+                                                Ok(queued) => debug!(
+                                                    primary = %address,
+                                                    queued,
+                                                    "Forward: Entries sent to the primary"
+                                                ),
+                                                Err(e) => warn!(
+                                                    primary = %address,
+                                                    error = %e,
+                                                    "Forward: Failed to send entries to the primary"
+                                                ),
+                                            }
+                                        }
+                                        .in_current_span(),
+                                    );
+                                }
+                            }
+                            if let Some(registry) = &tenants {
+                                data.extend(registry.pending(tenancy::MAX_ENTRIES_PER_BLOCK));
+                            }
+                            if let Some(mempool) = &mempool {
+                                data.extend(mempool.pending(forwarding::MAX_FORWARDED_PER_BLOCK));
+                            }
+                            if data.is_empty() {
+                                info!("Transform: No time bucket closed this round, skipping");
+                                return;
+                            }
+                            let fees = loop {
+                                match accounts.as_ref().map(|book| book.quote(&data)) {
+                                    Some(Ok(fees)) => break fees,
+                                    Some(Err(e)) => {
+                                        // Queued entries would fail every
+                                        // round and hold up the rest
+                                        let submitter = e.submitter().to_string();
+                                        let queued = tenants
+                                            .as_ref()
+                                            .map_or(0, |registry| registry.drop_submitter(&submitter))
+                                            + mempool
+                                                .as_ref()
+                                                .map_or(0, |mempool| mempool.drop_source(&submitter));
+                                        warn!(
+                                            error = %e,
+                                            dropped_queued = queued,
+                                            "Accounting: Leaving out the entries of a submitter that cannot pay"
+                                        );
+                                        data.retain(|item| item.source != submitter);
+                                        if data.is_empty() {
+                                            return;
+                                        }
+                                    }
+                                    None => break Vec::new(),
+                                }
+                            };
+                            let mut divergences = divergence
+                                .map(|detector| detector.scan(&data))
+                                .unwrap_or_default();
+                            // Quotes behind an aggregated price are compared too
+                            if let Some(event) = divergence.and_then(|detector| {
+                                detector.check(&asset, &extract_data.quotes)
+                            }) {
+                                divergences.push(event);
+                            }
+                            for event in &divergences {
+                                warn!(
+                                    asset = %event.asset,
+                                    sources = event.quotes.len(),
+                                    spread_pct = event.spread_pct,
+                                    outlier = ?event.outlier().map(|q| &q.source),
+                                    "Transform: Sources disagree on price"
+                                );
+                            }
 
-                        let market_data = MarketData {
-                            asset: transformed_data.asset,
-                            price: normalized_price,
-                            source: transformed_data.source,
-                            timestamp: transformed_data.timestamp,
-                        };
-
-                        last_index += 1;
-                        let mut new_block = Block {
-                            index: last_index,
-                            timestamp: Utc::now().timestamp(),
-                            data: vec![market_data],
-                            previous_hash: last_hash.clone(),
-                            hash: String::new(),
-                            nonce: 0,
-                        };
-                        new_block.calculate_hash_with_nonce();
-
-                        info!(
-                            block_index = new_block.index,
-                            hash_preview = &new_block.hash[0..8.min(new_block.hash.len())],
-                            "Transform: Block created"
-                        );
+                            let mut order_books = Vec::new();
+                            if let Some((source, depth)) = &order_book {
+                                match source.fetch_depth(*depth).await {
+                                    Ok(book) => match book.validate() {
+                                        Ok(()) => order_books.push(book),
+                                        Err(e) => {
+                                            warn!(error = %e, "Extract: Order book rejected")
+                                        }
+                                    },
+                                    Err(e) => {
+                                        warn!(error = %e, "Extract: Order book snapshot failed")
+                                    }
+                                }
+                            }
 
-                        match run_consensus(
-                            consensus_type,
-                            new_block.clone(),
-                            node_id,
-                            total_nodes,
-                            &node_addresses,
-                            port,
-                            pbft.clone(),
-                        )
-                        .await
-                        {
-                            Ok(Some(committed_block)) => match db.save_block(&committed_block) {
-                                Ok(_) => {
-                                    last_hash = committed_block.hash.clone();
-                                    last_timestamp = Some(committed_block.timestamp);
-                                    info!(
-                                        block_index = committed_block.index,
+                            last_index += 1;
+                            let mut new_block = Block {
+                                index: last_index,
+                                timestamp: etl::now_millis(),
+                                data,
+                                previous_hash: last_hash.clone(),
+                                hash: String::new(),
+                                nonce: 0,
+                                format_version: BLOCK_FORMAT_VERSION,
+                                fees,
+                                divergences,
+                                hlc: Some(clock.now()),
+                                annotations: annotations.clone(),
+                                order_books,
+                            };
+                            new_block.calculate_hash_with_nonce();
+
+                            info!(
+                                block_index = new_block.index,
+                                hash_preview = &new_block.hash[0..8.min(new_block.hash.len())],
+                                "Transform: Block created"
+                            );
+
+                            let round = ConsensusRound {
+                                consensus_type,
+                                node_id,
+                                total_nodes,
+                                node_addresses: &node_addresses,
+                                local: &local_addr,
+                                coordinator: &coordinator,
+                                trace_id: &trace_id,
+                                demo: &demo,
+                                outbox: &outbox,
+                            };
+                            match run_consensus(&round, new_block.clone()).await
+                            {
+                                Ok(Some(committed_block)) => {
+                                    demo.enter(DemoPhase::Persist, committed_block.index).await;
+                                    if let Err(refused) = fork_tree.insert(&committed_block, true) {
+                                        error!(error = %refused, "Fork: Committed block rejected");
+                                        last_index -= 1;
+                                        return;
+                                    }
+                                    match persist_block(&db, committer.as_ref(), &committed_block).await
+                                    {
+                                        Ok(_) => {
+                                            if let Some(book) = &accounts {
+                                                if let Err(e) = book.settle(&committed_block.fees) {
+                                                    error!(error = %e, "Accounting: Failed to debit fees");
+                                                }
+                                            }
+                                            if let Some(registry) = &tenants {
+                                                registry.record_committed(&committed_block);
+                                            }
+                                            if let Some(mempool) = &mempool {
+                                                mempool.record_committed(&committed_block);
+                                            }
+                                            record_commit_latency(&db, &commit_sla, &committed_block);
+                                            last_hash = committed_block.hash.clone();
+                                            last_timestamps.record_block(&committed_block);
+                                            if let Some(stage) = &indicators {
+                                                stage.record_block(&committed_block);
+                                            }
+                                            info!(
+                                                block_index = committed_block.index,
+                                                consensus = consensus_type.name(),
+                                                "Load: Block committed and saved"
+                                            );
+                                        }
+                                        Err(e) => {
+                                            error!(error = %e, "Load: Database error");
+                                            last_index -= 1;
+                                        }
+                                    }
+                                }
+                                Ok(None) => {
+                                    warn!(
+                                        block_index = new_block.index,
                                         consensus = consensus_type.name(),
-                                        "Load: Block committed and saved"
+                                        "Consensus failed or pending"
                                     );
+                                    last_index -= 1;
                                 }
                                 Err(e) => {
-                                    error!(error = %e, "Load: Database error");
+                                    error!(
+                                        error = %e,
+                                        consensus = consensus_type.name(),
+                                        "Error during consensus"
+                                    );
                                     last_index -= 1;
                                 }
-                            },
-                            Ok(None) => {
-                                warn!(
-                                    block_index = new_block.index,
-                                    consensus = consensus_type.name(),
-                                    "Consensus failed or pending"
-                                );
-                                last_index -= 1;
-                            }
-                            Err(e) => {
-                                error!(
-                                    error = %e,
-                                    consensus = consensus_type.name(),
-                                    "Error during consensus"
-                                );
-                                last_index -= 1;
                             }
                         }
-                    }
-                    Err(e) => {
-                        error!(error = %e, "Transform: Validation/Transformation error");
+                        Err(e) => {
+                            error!(error = %e, "Transform: Validation/Transformation error");
+                        }
                     }
                 }
+                Err(e) => {
+                    error!(error = %e, "Extract: Fetch error");
+                }
             }
-            Err(e) => {
-                error!(error = %e, "Extract: Fetch error");
-            }
         }
+        .instrument(info_span!("round", trace_id = %trace_id))
+        .await;
+
+        let stats = transformer.stats();
+        let this_round = stats.since(&transform_stats);
+        if this_round.processed > 0 {
+            info!(
+                round = round + 1,
+                processed = this_round.processed,
+                validations_failed = this_round.validations_failed,
+                rejected = this_round.rejected,
+                duplicates_dropped = this_round.duplicates_dropped,
+                anomalies_flagged = this_round.anomalies_flagged,
+                buffered = this_round.buffered,
+                normalizations_applied = this_round.normalizations_applied,
+                "Transform: Round stats"
+            );
+        }
+        transform_stats = stats;
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        wait_for_next_round(
+            &schedule,
+            demo.block_interval(control.state().block_interval_ms),
+        )
+        .await;
     }
 
+    supervisor::notify_or_warn("STOPPING=1");
     info!("{}", "=".repeat(60));
     db.print_latest_blocks(5)?;
 
+    let cross_shard = coordinator.metrics();
+    if cross_shard.cross_shard_commits + cross_shard.cross_shard_aborts > 0 {
+        info!(
+            commits = cross_shard.cross_shard_commits,
+            aborts = cross_shard.cross_shard_aborts,
+            avg_latency_ms = ?cross_shard.avg_cross_shard_ms(),
+            overhead_ms = ?cross_shard.overhead_ms(),
+            "CrossShard: Two-phase commit summary"
+        );
+    }
+
     info!(node_id = node_id, "Node completed successfully");
 
     tokio::time::sleep(Duration::from_secs(5)).await;
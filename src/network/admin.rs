@@ -0,0 +1,356 @@
+//! Operator controls for a running node
+//!
+//! `NodeControl` is the pipeline governor: the ETL loop waits on it before
+//! producing each block, and the consensus message handler consults it before
+//! voting. The `/admin/*` routes change it at runtime so a node can be taken
+//! out of proposal duty for maintenance while `/health` and `/blocks` keep
+//! serving.
+//!
+//! Admin routes require `Authorization: Bearer <ADMIN_TOKEN>`, or a key with
+//! the admin role when access control is enabled (see `rbac`); when neither
+//! is configured they are disabled.
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use tracing::info;
+
+use super::rbac::{Principal, Role};
+use super::ServerContext;
+use crate::etl::profile::ValidationProfile;
+
+/// Delay between block production rounds unless reconfigured
+pub const DEFAULT_BLOCK_INTERVAL_MS: u64 = 3000;
+
+/// Shortest block interval `POST /admin/reconfigure` accepts; shorter
+/// intervals would spin the ETL loop without giving consensus time to finish
+pub const MIN_BLOCK_INTERVAL_MS: u64 = 100;
+
+/// Current operator settings
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlState {
+    /// Block production is suspended
+    pub paused: bool,
+    /// Whether this node votes on consensus messages from peers
+    pub consensus_participation: bool,
+    pub block_interval_ms: u64,
+    /// Name of the `ValidationProfile` applied to the next block, if any
+    pub validation_profile: Option<String>,
+}
+
+impl Default for ControlState {
+    fn default() -> Self {
+        ControlState {
+            paused: false,
+            consensus_participation: true,
+            block_interval_ms: DEFAULT_BLOCK_INTERVAL_MS,
+            validation_profile: None,
+        }
+    }
+}
+
+/// Body of `POST /admin/reconfigure`; omitted fields are left unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Reconfigure {
+    pub consensus_participation: Option<bool>,
+    pub block_interval_ms: Option<u64>,
+    /// `strict` or `lenient`
+    pub validation_profile: Option<String>,
+}
+
+/// Shared, watchable operator settings
+pub struct NodeControl {
+    state: watch::Sender<ControlState>,
+}
+
+impl Default for NodeControl {
+    fn default() -> Self {
+        NodeControl::new(ControlState::default())
+    }
+}
+
+impl NodeControl {
+    pub fn new(initial: ControlState) -> Self {
+        NodeControl {
+            state: watch::channel(initial).0,
+        }
+    }
+
+    pub fn state(&self) -> ControlState {
+        self.state.borrow().clone()
+    }
+
+    pub fn pause(&self) -> ControlState {
+        self.state.send_modify(|s| s.paused = true);
+        self.state()
+    }
+
+    pub fn resume(&self) -> ControlState {
+        self.state.send_modify(|s| s.paused = false);
+        self.state()
+    }
+
+    pub fn reconfigure(&self, change: &Reconfigure) -> ControlState {
+        self.state.send_modify(|s| {
+            if let Some(participation) = change.consensus_participation {
+                s.consensus_participation = participation;
+            }
+            if let Some(interval) = change.block_interval_ms {
+                s.block_interval_ms = interval;
+            }
+            if let Some(profile) = &change.validation_profile {
+                s.validation_profile = Some(profile.clone());
+            }
+        });
+        self.state()
+    }
+
+    pub fn participates_in_consensus(&self) -> bool {
+        self.state.borrow().consensus_participation
+    }
+
+    /// Return immediately when running, otherwise wait for `resume`
+    pub async fn wait_until_resumed(&self) {
+        let mut rx = self.state.subscribe();
+        // The sender lives in `self`, so the channel cannot close here
+        let _ = rx.wait_for(|s| !s.paused).await;
+    }
+}
+
+/// Check the bearer token against the configured admin token, or accept a
+/// caller the access policy has already authenticated as admin
+pub(super) fn authorize(req: &HttpRequest, context: &ServerContext) -> Result<(), HttpResponse> {
+    if req
+        .extensions()
+        .get::<Principal>()
+        .is_some_and(|p| p.role == Role::Admin)
+    {
+        return Ok(());
+    }
+    let Some(expected) = context.admin_token.as_deref() else {
+        return Err(HttpResponse::ServiceUnavailable()
+            .json(json!({ "error": "admin API disabled (ADMIN_TOKEN not set)" })));
+    };
+
+    let provided = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(HttpResponse::Unauthorized().json(json!({ "error": "invalid admin token" }))),
+    }
+}
+
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn control(context: &ServerContext) -> Result<&NodeControl, HttpResponse> {
+    context.control.as_deref().ok_or_else(|| {
+        HttpResponse::ServiceUnavailable().json(json!({ "error": "node control not available" }))
+    })
+}
+
+pub(super) async fn status(req: HttpRequest, context: web::Data<ServerContext>) -> impl Responder {
+    match authorize(&req, &context).and_then(|_| control(&context)) {
+        Ok(control) => HttpResponse::Ok().json(control.state()),
+        Err(response) => response,
+    }
+}
+
+pub(super) async fn pause(req: HttpRequest, context: web::Data<ServerContext>) -> impl Responder {
+    match authorize(&req, &context).and_then(|_| control(&context)) {
+        Ok(control) => {
+            info!("Admin: Block production paused");
+            HttpResponse::Ok().json(control.pause())
+        }
+        Err(response) => response,
+    }
+}
+
+pub(super) async fn resume(req: HttpRequest, context: web::Data<ServerContext>) -> impl Responder {
+    match authorize(&req, &context).and_then(|_| control(&context)) {
+        Ok(control) => {
+            info!("Admin: Block production resumed");
+            HttpResponse::Ok().json(control.resume())
+        }
+        Err(response) => response,
+    }
+}
+
+pub(super) async fn reconfigure(
+    req: HttpRequest,
+    change: web::Json<Reconfigure>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    match authorize(&req, &context).and_then(|_| control(&context)) {
+        Ok(control) => {
+            let mut change = change.into_inner();
+            if let Some(interval) = change.block_interval_ms {
+                if interval < MIN_BLOCK_INTERVAL_MS {
+                    return HttpResponse::BadRequest().json(json!({
+                        "error": format!(
+                            "block_interval_ms must be at least {}",
+                            MIN_BLOCK_INTERVAL_MS
+                        )
+                    }));
+                }
+            }
+            if let Some(name) = &change.validation_profile {
+                match ValidationProfile::parse(name) {
+                    Ok(profile) => change.validation_profile = Some(profile.name.to_string()),
+                    Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+                }
+            }
+            let state = control.reconfigure(&change);
+            info!(
+                consensus_participation = state.consensus_participation,
+                block_interval_ms = state.block_interval_ms,
+                validation_profile = ?state.validation_profile,
+                "Admin: Node reconfigured"
+            );
+            HttpResponse::Ok().json(state)
+        }
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetworkHandler;
+    use actix_web::{test, App};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn context(control: Arc<NodeControl>) -> ServerContext {
+        ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_admin(control, Some("secret".to_string()))
+    }
+
+    #[actix_web::test]
+    async fn test_admin_routes_require_token() {
+        let control = Arc::new(NodeControl::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(context(control.clone())))
+                .route("/admin/pause", web::post().to(pause)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/admin/pause").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::post()
+            .uri("/admin/pause")
+            .insert_header(("Authorization", "Bearer wrong"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        assert!(!control.state().paused);
+
+        let req = test::TestRequest::post()
+            .uri("/admin/pause")
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(control.state().paused);
+    }
+
+    #[actix_web::test]
+    async fn test_reconfigure_updates_only_given_fields() {
+        let control = Arc::new(NodeControl::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(context(control.clone())))
+                .route("/admin/reconfigure", web::post().to(reconfigure)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/admin/reconfigure")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "consensus_participation": false }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let state = control.state();
+        assert!(!state.consensus_participation);
+        assert_eq!(state.block_interval_ms, DEFAULT_BLOCK_INTERVAL_MS);
+        assert!(!control.participates_in_consensus());
+
+        let req = test::TestRequest::post()
+            .uri("/admin/reconfigure")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "validation_profile": "paranoid" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        let req = test::TestRequest::post()
+            .uri("/admin/reconfigure")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "validation_profile": "Lenient" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let state = control.state();
+        assert_eq!(state.validation_profile.as_deref(), Some("lenient"));
+        assert!(!state.consensus_participation);
+
+        for interval in [0, MIN_BLOCK_INTERVAL_MS - 1] {
+            let req = test::TestRequest::post()
+                .uri("/admin/reconfigure")
+                .insert_header(("Authorization", "Bearer secret"))
+                .set_json(json!({ "block_interval_ms": interval }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400);
+        }
+        assert_eq!(control.state().block_interval_ms, DEFAULT_BLOCK_INTERVAL_MS);
+        let req = test::TestRequest::post()
+            .uri("/admin/reconfigure")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "block_interval_ms": MIN_BLOCK_INTERVAL_MS }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert_eq!(control.state().block_interval_ms, MIN_BLOCK_INTERVAL_MS);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_disabled_without_token() {
+        let control = Arc::new(NodeControl::default());
+        let context =
+            ServerContext::new(Arc::new(NetworkHandler::new(|_| true))).with_admin(control, None);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/admin/status", web::get().to(status)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/admin/status")
+            .insert_header(("Authorization", "Bearer anything"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 503);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_resumed() {
+        let control = Arc::new(NodeControl::default());
+        control.pause();
+
+        let waiter = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait_until_resumed().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        control.resume();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
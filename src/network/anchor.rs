@@ -0,0 +1,620 @@
+//! Anchoring the chain head to Bitcoin through OpenTimestamps
+//!
+//! Attestations are only as trustworthy as the node key that signs them.
+//! Anchoring publishes the head hash outside the cluster: every
+//! `ANCHOR_INTERVAL_SECS` the node submits its head hash, if it changed, to
+//! each OpenTimestamps calendar in `ANCHOR_CALENDARS` and stores the proof
+//! each one returns in the `anchors` table. The calendars aggregate the
+//! hashes they receive into a Bitcoin transaction; on later ticks the node
+//! asks them for the completed proof and splices it in (`ots::Timestamp`).
+//!
+//! A confirmed proof shows the head hash existed before the Bitcoin block it
+//! commits to. `Anchorer::verify` checks that the ledger still has the
+//! anchored block and evaluates the proof; with `ANCHOR_BITCOIN_EXPLORER`
+//! set to an Esplora API (e.g. `https://blockstream.info/api`) it also
+//! checks the commitment against that block's merkle root.
+//!
+//! Served on `GET /anchors?limit=` and `GET /anchors/{id}/verify`.
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::attestation::check_head;
+use super::ots::{Attestation, Timestamp};
+use super::ServerContext;
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult, StoredAnchor};
+use crate::etl::now_millis;
+
+/// Public calendars run by the OpenTimestamps project
+///
+/// Proofs are only upgraded from configured calendars, so these are the
+/// calendars themselves rather than the pool aggregators, whose pending
+/// attestations name the calendars behind them.
+pub const DEFAULT_CALENDARS: &[&str] = &[
+    "https://alice.btc.calendar.opentimestamps.org",
+    "https://bob.btc.calendar.opentimestamps.org",
+];
+
+/// Anchors listed by `/anchors` unless `limit` is given
+pub const DEFAULT_LIST_LIMIT: u64 = 20;
+
+const OTS_CONTENT_TYPE: &str = "application/vnd.opentimestamps.v1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnchorConfig {
+    pub interval: Duration,
+    /// Calendar base URLs the head is submitted to
+    pub calendars: Vec<String>,
+    /// Esplora API used to check Bitcoin commitments; `None` skips the check
+    pub bitcoin_explorer: Option<String>,
+}
+
+impl AnchorConfig {
+    pub fn new(interval: Duration) -> Self {
+        AnchorConfig {
+            interval,
+            calendars: DEFAULT_CALENDARS.iter().map(|c| c.to_string()).collect(),
+            bitcoin_explorer: None,
+        }
+    }
+
+    pub fn with_calendars(mut self, calendars: Vec<String>) -> Self {
+        self.calendars = calendars;
+        self
+    }
+
+    pub fn with_bitcoin_explorer(mut self, url: impl Into<String>) -> Self {
+        self.bitcoin_explorer = Some(url.into());
+        self
+    }
+
+    /// `None` unless `ANCHOR_INTERVAL_SECS` is set to a positive number
+    pub fn from_env() -> Option<Self> {
+        let interval = std::env::var("ANCHOR_INTERVAL_SECS")
+            .ok()?
+            .parse::<u64>()
+            .ok()
+            .filter(|&secs| secs > 0)?;
+        let mut config = Self::new(Duration::from_secs(interval));
+        if let Ok(list) = std::env::var("ANCHOR_CALENDARS") {
+            let calendars: Vec<String> = list
+                .split(',')
+                .map(|c| c.trim().trim_end_matches('/').to_string())
+                .filter(|c| !c.is_empty())
+                .collect();
+            if !calendars.is_empty() {
+                config = config.with_calendars(calendars);
+            }
+        }
+        match std::env::var("ANCHOR_BITCOIN_EXPLORER") {
+            Ok(url) if !url.trim().is_empty() => {
+                Some(config.with_bitcoin_explorer(url.trim().trim_end_matches('/')))
+            }
+            _ => Some(config),
+        }
+    }
+}
+
+/// The message a head hash is timestamped as: the hash's 32 bytes
+pub fn head_digest(head_hash: &str) -> Result<Vec<u8>, String> {
+    match hex::decode(head_hash) {
+        Ok(bytes) if bytes.len() == 32 => Ok(bytes),
+        _ => Err(format!("head hash {} is not a SHA-256 digest", head_hash)),
+    }
+}
+
+/// Bitcoin block heights the proof commits to, lowest first
+fn bitcoin_heights(proof: &Timestamp, digest: &[u8]) -> Vec<u64> {
+    let mut heights: Vec<u64> = proof
+        .commitments(digest)
+        .into_iter()
+        .filter_map(|c| match c.attestation {
+            Attestation::Bitcoin { height } => Some(height),
+            _ => None,
+        })
+        .collect();
+    heights.sort_unstable();
+    heights.dedup();
+    heights
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorStatus {
+    /// The ledger no longer matches, or the proof is unusable
+    Failed,
+    /// Only calendar promises so far
+    Pending,
+    /// The proof reaches a Bitcoin block that was not checked
+    Committed,
+    /// The Bitcoin block's merkle root matches the proof
+    Confirmed,
+}
+
+/// A Bitcoin block the proof commits to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BitcoinCommitment {
+    pub height: u64,
+    /// Merkle root the block must have, in the byte order explorers display
+    pub merkle_root: String,
+    /// Whether the explorer agreed; `None` when it was not asked
+    pub confirmed: Option<bool>,
+}
+
+/// Result of `Anchorer::verify`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnchorVerification {
+    pub anchor_id: i64,
+    pub height: u64,
+    pub head_hash: String,
+    pub status: AnchorStatus,
+    pub pending_calendars: Vec<String>,
+    pub bitcoin: Vec<BitcoinCommitment>,
+    pub problem: Option<String>,
+}
+
+/// Submits the chain head to calendars and collects the completed proofs
+pub struct Anchorer {
+    db: Arc<DatabaseManager>,
+    config: AnchorConfig,
+    client: reqwest::Client,
+}
+
+impl Anchorer {
+    pub fn new(db: Arc<DatabaseManager>, config: AnchorConfig) -> Self {
+        Anchorer {
+            db,
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn fetch_proof(&self, request: reqwest::RequestBuilder) -> Result<Timestamp, String> {
+        let resp = request
+            .header(reqwest::header::ACCEPT, OTS_CONTENT_TYPE)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("status {}", resp.status()));
+        }
+        let body = resp.bytes().await.map_err(|e| e.to_string())?;
+        Timestamp::from_bytes(&body)
+    }
+
+    /// Submit the chain head to every calendar unless it was already
+    /// anchored; returns how many calendars accepted it
+    pub async fn submit(&self) -> DbResult<usize> {
+        let Some(head) = self.db.get_latest_block()? else {
+            return Ok(0);
+        };
+        if let Some(last) = self.db.get_anchors(1)?.first() {
+            if last.head_hash == head.hash {
+                return Ok(0);
+            }
+        }
+        let digest = head_digest(&head.hash).map_err(DatabaseError::InvalidData)?;
+        let mut accepted = 0;
+        for calendar in &self.config.calendars {
+            let request = self
+                .client
+                .post(format!("{}/digest", calendar))
+                .body(digest.clone());
+            match self.fetch_proof(request).await {
+                Ok(proof) => {
+                    self.db.save_anchor(&StoredAnchor {
+                        id: 0,
+                        height: head.index,
+                        head_hash: head.hash.clone(),
+                        calendar: calendar.clone(),
+                        proof: proof.to_bytes(),
+                        submitted_at: now_millis(),
+                        bitcoin_height: None,
+                        confirmed_at: None,
+                    })?;
+                    accepted += 1;
+                }
+                Err(e) => warn!(calendar = %calendar, error = %e, "Anchor: Submission failed"),
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Ask the calendars for the Bitcoin proofs of unconfirmed anchors;
+    /// returns how many anchors became confirmed
+    pub async fn upgrade(&self) -> DbResult<usize> {
+        let mut confirmed = 0;
+        for anchor in self.db.get_unconfirmed_anchors()? {
+            let (Ok(digest), Ok(mut proof)) = (
+                head_digest(&anchor.head_hash),
+                Timestamp::from_bytes(&anchor.proof),
+            ) else {
+                continue;
+            };
+            let mut changed = false;
+            for commitment in proof.commitments(&digest) {
+                let Attestation::Pending { uri } = &commitment.attestation else {
+                    continue;
+                };
+                // The URI comes from the calendar's answer; only the
+                // calendars this node was configured with are contacted
+                if !self.is_configured_calendar(uri) {
+                    debug!(uri = %uri, "Anchor: Skipping an unconfigured calendar");
+                    continue;
+                }
+                let url = format!(
+                    "{}/timestamp/{}",
+                    uri.trim_end_matches('/'),
+                    hex::encode(&commitment.message)
+                );
+                // Calendars answer 404 until the transaction is confirmed
+                match self.fetch_proof(self.client.get(&url)).await {
+                    Ok(upgrade) => {
+                        changed |= proof.splice(&digest, &commitment.message, &upgrade);
+                    }
+                    Err(e) => debug!(url = %url, error = %e, "Anchor: Not upgraded yet"),
+                }
+            }
+            if !changed {
+                continue;
+            }
+            let bitcoin_height = bitcoin_heights(&proof, &digest).first().copied();
+            self.db.update_anchor_proof(
+                anchor.id,
+                &proof.to_bytes(),
+                bitcoin_height,
+                bitcoin_height.map(|_| now_millis()),
+            )?;
+            if let Some(bitcoin_height) = bitcoin_height {
+                info!(
+                    height = anchor.height,
+                    bitcoin_height,
+                    calendar = %anchor.calendar,
+                    "Anchor: Chain head committed to Bitcoin"
+                );
+                confirmed += 1;
+            }
+        }
+        Ok(confirmed)
+    }
+
+    fn is_configured_calendar(&self, uri: &str) -> bool {
+        let uri = uri.trim_end_matches('/');
+        self.config
+            .calendars
+            .iter()
+            .any(|calendar| calendar.trim_end_matches('/') == uri)
+    }
+
+    pub async fn tick(&self) -> DbResult<()> {
+        self.submit().await?;
+        self.upgrade().await?;
+        Ok(())
+    }
+
+    /// Run forever on a background task
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        info!(
+            interval_secs = self.config.interval.as_secs(),
+            calendars = ?self.config.calendars,
+            "Anchor: Periodic anchoring enabled"
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.tick().await {
+                    warn!(error = %e, "Anchor: Failed to anchor");
+                }
+            }
+        })
+    }
+
+    /// Merkle root of the Bitcoin block at `height`, as displayed
+    async fn merkle_root(&self, explorer: &str, height: u64) -> Result<String, String> {
+        let get = |url: String| async move {
+            let resp = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if !resp.status().is_success() {
+                return Err(format!("explorer returned {}", resp.status()));
+            }
+            Ok::<_, String>(resp)
+        };
+        let hash = get(format!("{}/block-height/{}", explorer, height))
+            .await?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        let block: serde_json::Value = get(format!("{}/block/{}", explorer, hash.trim()))
+            .await?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        block["merkle_root"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "explorer returned no merkle root".to_string())
+    }
+
+    /// Check an anchor against the ledger and, if configured, Bitcoin
+    pub async fn verify(&self, anchor: &StoredAnchor) -> AnchorVerification {
+        let mut report = AnchorVerification {
+            anchor_id: anchor.id,
+            height: anchor.height,
+            head_hash: anchor.head_hash.clone(),
+            status: AnchorStatus::Failed,
+            pending_calendars: Vec::new(),
+            bitcoin: Vec::new(),
+            problem: None,
+        };
+        if let Err(problem) = check_head(&self.db, anchor.height, &anchor.head_hash) {
+            report.problem = Some(problem);
+            return report;
+        }
+        let (digest, proof) = match (
+            head_digest(&anchor.head_hash),
+            Timestamp::from_bytes(&anchor.proof),
+        ) {
+            (Ok(digest), Ok(proof)) => (digest, proof),
+            (Err(problem), _) | (_, Err(problem)) => {
+                report.problem = Some(problem);
+                return report;
+            }
+        };
+
+        for commitment in proof.commitments(&digest) {
+            match commitment.attestation {
+                Attestation::Pending { uri } => report.pending_calendars.push(uri),
+                Attestation::Bitcoin { height } => {
+                    let merkle_root = hex::encode(
+                        commitment
+                            .message
+                            .iter()
+                            .rev()
+                            .copied()
+                            .collect::<Vec<u8>>(),
+                    );
+                    let confirmed = match &self.config.bitcoin_explorer {
+                        Some(explorer) => match self.merkle_root(explorer, height).await {
+                            Ok(actual) => Some(actual == merkle_root),
+                            Err(e) => {
+                                warn!(error = %e, "Anchor: Bitcoin explorer unavailable");
+                                None
+                            }
+                        },
+                        None => None,
+                    };
+                    report.bitcoin.push(BitcoinCommitment {
+                        height,
+                        merkle_root,
+                        confirmed,
+                    });
+                }
+                Attestation::Unknown { .. } => {}
+            }
+        }
+
+        report.status = if report.bitcoin.iter().any(|b| b.confirmed == Some(true)) {
+            AnchorStatus::Confirmed
+        } else if report.bitcoin.iter().any(|b| b.confirmed == Some(false)) {
+            report.problem = Some("merkle root does not match the Bitcoin block".to_string());
+            AnchorStatus::Failed
+        } else if !report.bitcoin.is_empty() {
+            AnchorStatus::Committed
+        } else {
+            AnchorStatus::Pending
+        };
+        report
+    }
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "error": "anchoring is not enabled on this node" }))
+}
+
+#[derive(Deserialize)]
+pub struct AnchorsQuery {
+    limit: Option<u64>,
+}
+
+/// Most recent anchors, newest first
+pub async fn list(
+    query: web::Query<AnchorsQuery>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let Some(anchorer) = &context.anchors else {
+        return disabled();
+    };
+    match anchorer
+        .db
+        .get_anchors(query.limit.unwrap_or(DEFAULT_LIST_LIMIT))
+    {
+        Ok(anchors) => {
+            let anchors: Vec<_> = anchors
+                .into_iter()
+                .map(|anchor| {
+                    json!({
+                        "id": anchor.id,
+                        "height": anchor.height,
+                        "head_hash": anchor.head_hash,
+                        "calendar": anchor.calendar,
+                        "submitted_at": anchor.submitted_at,
+                        "bitcoin_height": anchor.bitcoin_height,
+                        "confirmed_at": anchor.confirmed_at,
+                        "proof": hex::encode(&anchor.proof),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({ "anchors": anchors }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// Verify one anchor; 409 when it fails
+pub async fn verify(path: web::Path<i64>, context: web::Data<ServerContext>) -> impl Responder {
+    let Some(anchorer) = &context.anchors else {
+        return disabled();
+    };
+    match anchorer.db.get_anchor(path.into_inner()) {
+        Ok(anchor) => {
+            let report = anchorer.verify(&anchor).await;
+            if report.status == AnchorStatus::Failed {
+                HttpResponse::Conflict().json(report)
+            } else {
+                HttpResponse::Ok().json(report)
+            }
+        }
+        Err(DatabaseError::NotFound(what)) => {
+            HttpResponse::NotFound().json(json!({ "error": format!("{} not found", what) }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::ots::Op;
+    use crate::testing;
+    use actix_web::{App, HttpServer};
+    use sha2::{Digest, Sha256};
+    use std::net::TcpListener;
+
+    fn pending(uri: &str) -> Timestamp {
+        Timestamp {
+            attestations: Vec::new(),
+            ops: vec![(
+                Op::Sha256,
+                Timestamp {
+                    attestations: vec![Attestation::Pending {
+                        uri: uri.to_string(),
+                    }],
+                    ops: Vec::new(),
+                },
+            )],
+        }
+    }
+
+    fn on_bitcoin(height: u64) -> Timestamp {
+        Timestamp {
+            attestations: Vec::new(),
+            ops: vec![(
+                Op::Append(b"txpath".to_vec()),
+                Timestamp {
+                    attestations: vec![Attestation::Bitcoin { height }],
+                    ops: Vec::new(),
+                },
+            )],
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_anchor_is_submitted_upgraded_and_verified() {
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+        let mut head = testing::block(1, testing::GENESIS_PARENT_HASH, Vec::new());
+        db.save_block(&head).unwrap();
+
+        // A calendar that has the transaction confirmed in block 800000,
+        // and an explorer serving that block
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let calendar_uri = base.clone();
+        let digest = head_digest(&head.hash).unwrap();
+        let commitment = Sha256::digest(&digest).to_vec();
+        let root: Vec<u8> = [commitment.as_slice(), b"txpath"].concat();
+        let displayed_root = hex::encode(root.iter().rev().copied().collect::<Vec<u8>>());
+        let server = HttpServer::new(move || {
+            let calendar_uri = calendar_uri.clone();
+            let displayed_root = displayed_root.clone();
+            App::new()
+                .route(
+                    "/digest",
+                    web::post().to(move |body: web::Bytes| {
+                        let proof = pending(&calendar_uri).to_bytes();
+                        async move {
+                            assert_eq!(body.len(), 32);
+                            HttpResponse::Ok().body(proof)
+                        }
+                    }),
+                )
+                .route(
+                    "/timestamp/{commitment}",
+                    web::get()
+                        .to(|| async { HttpResponse::Ok().body(on_bitcoin(800_000).to_bytes()) }),
+                )
+                .route(
+                    "/block-height/800000",
+                    web::get().to(|| async { HttpResponse::Ok().body("blockhash") }),
+                )
+                .route(
+                    "/block/blockhash",
+                    web::get().to(move || {
+                        let root = displayed_root.clone();
+                        async move { HttpResponse::Ok().json(json!({ "merkle_root": root })) }
+                    }),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let config = AnchorConfig::new(Duration::from_secs(60))
+            .with_calendars(vec![base.clone()])
+            .with_bitcoin_explorer(base.clone());
+        let anchorer = Anchorer::new(db.clone(), config);
+        assert_eq!(anchorer.submit().await.unwrap(), 1);
+        // The same head is not anchored twice
+        assert_eq!(anchorer.submit().await.unwrap(), 0);
+
+        let anchor = db.get_anchors(1).unwrap().remove(0);
+        let report = anchorer.verify(&anchor).await;
+        assert_eq!(report.status, AnchorStatus::Pending);
+        assert_eq!(report.pending_calendars, std::slice::from_ref(&base));
+
+        // A pending attestation naming a calendar that is not configured
+        // is never fetched
+        let elsewhere = Anchorer::new(
+            db.clone(),
+            AnchorConfig::new(Duration::from_secs(60))
+                .with_calendars(vec!["http://127.0.0.1:1".to_string()]),
+        );
+        assert_eq!(elsewhere.upgrade().await.unwrap(), 0);
+        assert_eq!(db.get_unconfirmed_anchors().unwrap().len(), 1);
+
+        assert_eq!(anchorer.upgrade().await.unwrap(), 1);
+        let anchor = db.get_anchor(anchor.id).unwrap();
+        assert_eq!(anchor.bitcoin_height, Some(800_000));
+        assert!(db.get_unconfirmed_anchors().unwrap().is_empty());
+        let report = anchorer.verify(&anchor).await;
+        assert_eq!(
+            report.status,
+            AnchorStatus::Confirmed,
+            "{:?}",
+            report.problem
+        );
+
+        // Rewriting the anchored block is detected
+        db.delete_block(1).unwrap();
+        head.nonce = 1;
+        head.calculate_hash_with_nonce();
+        db.save_block(&head).unwrap();
+        let report = anchorer.verify(&anchor).await;
+        assert_eq!(report.status, AnchorStatus::Failed);
+        assert!(report.problem.unwrap().contains("rewritten"));
+        handle.stop(true).await;
+    }
+}
@@ -0,0 +1,337 @@
+//! Signed, timestamped attestations of the ledger head
+//!
+//! Every `ATTESTATION_INTERVAL_SECS` the node signs a statement of its chain
+//! head (height, head hash, time, node id) with its `NODE_SIGNING_KEY`,
+//! stores it in the `attestations` table, and, if `ATTESTATION_NOTARY_URL`
+//! is set, POSTs it to that notarization endpoint. An auditor who kept an
+//! attestation can later check, with `verify` and `check_ledger`, that it
+//! was signed by the node and that the block at that height still has the
+//! attested hash, i.e. that the history up to there was not rewritten.
+//!
+//! The signature covers `Attestation::signing_input`, laid out like the
+//! oracle's quotes and tagged `rml-attestation-v1`. Stored attestations are
+//! served on `GET /attestations?limit=`.
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::oracle::{verify_signature, OracleSigner};
+use super::ServerContext;
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult, StoredAttestation};
+use crate::etl::now_millis;
+
+/// Attestations listed by `/attestations` unless `limit` is given
+pub const DEFAULT_LIST_LIMIT: u64 = 20;
+
+/// What the node attests to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub node_id: usize,
+    pub height: u64,
+    pub head_hash: String,
+    /// Milliseconds
+    pub attested_at: i64,
+}
+
+impl Attestation {
+    pub fn signing_input(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64 + self.head_hash.len());
+        buf.extend_from_slice(b"rml-attestation-v1");
+        buf.extend_from_slice(&(self.node_id as u64).to_be_bytes());
+        buf.extend_from_slice(&self.height.to_be_bytes());
+        buf.extend_from_slice(&(self.head_hash.len() as u64).to_be_bytes());
+        buf.extend_from_slice(self.head_hash.as_bytes());
+        buf.extend_from_slice(&self.attested_at.to_be_bytes());
+        buf
+    }
+}
+
+/// The document stored, served and sent to the notary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    #[serde(flatten)]
+    pub attestation: Attestation,
+    /// Hex-encoded Ed25519 public key of the node
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature over `attestation.signing_input()`
+    pub signature: String,
+}
+
+/// Check that `signed` was signed with the node key the auditor trusts
+pub fn verify(signed: &SignedAttestation, trusted_public_key: &str) -> Result<(), String> {
+    verify_signature(
+        trusted_public_key,
+        &signed.attestation.signing_input(),
+        &signed.signature,
+    )
+    .map_err(|_| "signature does not match the attestation".to_string())
+}
+
+/// Check that the ledger still has the attested block at the attested height
+pub fn check_ledger(signed: &SignedAttestation, db: &DatabaseManager) -> Result<(), String> {
+    check_head(db, signed.attestation.height, &signed.attestation.head_hash)
+}
+
+/// Check that the block at `height` still has hash `head_hash`
+pub fn check_head(db: &DatabaseManager, height: u64, head_hash: &str) -> Result<(), String> {
+    match db.get_block_by_index(height) {
+        Ok(block) if block.hash == head_hash => Ok(()),
+        Ok(block) => Err(format!(
+            "block {} was rewritten: attested {}, ledger has {}",
+            height, head_hash, block.hash
+        )),
+        Err(DatabaseError::NotFound(_)) => Err(format!("attested block {} is missing", height)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Attestation schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttestationConfig {
+    pub interval: Duration,
+    /// Receives a POST of every attestation
+    pub notary_url: Option<String>,
+}
+
+impl AttestationConfig {
+    pub fn new(interval: Duration) -> Self {
+        AttestationConfig {
+            interval,
+            notary_url: None,
+        }
+    }
+
+    pub fn with_notary_url(mut self, url: impl Into<String>) -> Self {
+        self.notary_url = Some(url.into());
+        self
+    }
+
+    /// `None` unless `ATTESTATION_INTERVAL_SECS` is set to a positive number
+    pub fn from_env() -> Option<Self> {
+        let interval = std::env::var("ATTESTATION_INTERVAL_SECS")
+            .ok()?
+            .parse::<u64>()
+            .ok()
+            .filter(|&secs| secs > 0)?;
+        let config = Self::new(Duration::from_secs(interval));
+        match std::env::var("ATTESTATION_NOTARY_URL") {
+            Ok(url) if !url.trim().is_empty() => Some(config.with_notary_url(url.trim())),
+            _ => Some(config),
+        }
+    }
+}
+
+/// Periodically attests to the chain head
+pub struct Attestor {
+    db: Arc<DatabaseManager>,
+    signer: Arc<OracleSigner>,
+    config: AttestationConfig,
+    client: reqwest::Client,
+}
+
+impl Attestor {
+    pub fn new(
+        db: Arc<DatabaseManager>,
+        signer: Arc<OracleSigner>,
+        config: AttestationConfig,
+    ) -> Self {
+        Attestor {
+            db,
+            signer,
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Sign and store an attestation of the current head; `None` for an
+    /// empty ledger
+    pub fn attest_once(&self) -> DbResult<Option<(i64, SignedAttestation)>> {
+        let Some(head) = self.db.get_latest_block()? else {
+            return Ok(None);
+        };
+        let attestation = Attestation {
+            node_id: self.signer.node_id(),
+            height: head.index,
+            head_hash: head.hash,
+            attested_at: now_millis(),
+        };
+        let signed = SignedAttestation {
+            signature: self.signer.sign_bytes(&attestation.signing_input()),
+            public_key: self.signer.public_key(),
+            attestation,
+        };
+        let document_json = serde_json::to_string(&signed)
+            .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        let id = self.db.save_attestation(&StoredAttestation {
+            id: 0,
+            height: signed.attestation.height,
+            head_hash: signed.attestation.head_hash.clone(),
+            attested_at: signed.attestation.attested_at,
+            document_json,
+            notarized_at: None,
+        })?;
+        Ok(Some((id, signed)))
+    }
+
+    /// Send an attestation to the notary; returns whether it was accepted
+    async fn notarize(&self, id: i64, signed: &SignedAttestation) -> bool {
+        let Some(url) = &self.config.notary_url else {
+            return false;
+        };
+        match self.client.post(url).json(signed).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                if let Err(e) = self.db.mark_attestation_notarized(id, now_millis()) {
+                    warn!(error = %e, "Attestation: Failed to record notarization");
+                }
+                debug!(notary = %url, id, "Attestation: Notarized");
+                true
+            }
+            Ok(resp) => {
+                warn!(notary = %url, status = %resp.status(), "Attestation: Notary rejected");
+                false
+            }
+            Err(e) => {
+                warn!(notary = %url, error = %e, "Attestation: Notary unreachable");
+                false
+            }
+        }
+    }
+
+    /// Attest once and notarize
+    pub async fn tick(&self) -> DbResult<Option<SignedAttestation>> {
+        let Some((id, signed)) = self.attest_once()? else {
+            return Ok(None);
+        };
+        info!(
+            height = signed.attestation.height,
+            head_hash = %signed.attestation.head_hash,
+            "Attestation: Signed chain head"
+        );
+        self.notarize(id, &signed).await;
+        Ok(Some(signed))
+    }
+
+    /// Run forever on a background task
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        info!(
+            interval_secs = self.config.interval.as_secs(),
+            notary = ?self.config.notary_url,
+            "Attestation: Periodic attestations enabled"
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.tick().await {
+                    warn!(error = %e, "Attestation: Failed to attest");
+                }
+            }
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AttestationsQuery {
+    limit: Option<u64>,
+}
+
+/// Most recent attestations, newest first, with when they were notarized
+pub async fn list(
+    query: web::Query<AttestationsQuery>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    match db.get_attestations(query.limit.unwrap_or(DEFAULT_LIST_LIMIT)) {
+        Ok(stored) => {
+            let attestations: Vec<_> = stored
+                .into_iter()
+                .map(|record| {
+                    json!({
+                        "id": record.id,
+                        "notarized_at": record.notarized_at,
+                        "attestation": serde_json::from_str::<serde_json::Value>(
+                            &record.document_json
+                        )
+                        .unwrap_or_default(),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({ "attestations": attestations }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::network::NetworkHandler;
+    use crate::testing::TestChainBuilder;
+    use actix_web::App;
+    use std::net::TcpListener;
+
+    #[actix_web::test]
+    async fn test_attestations_detect_rewritten_history() {
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+        let chain = TestChainBuilder::new().with_blocks(2).build();
+        db.save_blocks(&chain).unwrap();
+
+        // Nothing listens on the notary address
+        let notary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let notary_url = format!("http://{}/notarize", notary.local_addr().unwrap());
+        drop(notary);
+        let signer = Arc::new(OracleSigner::new(1, [9; 32]));
+        let attestor = Attestor::new(
+            db.clone(),
+            signer.clone(),
+            AttestationConfig::new(Duration::from_secs(60)).with_notary_url(notary_url),
+        );
+        let signed = attestor.tick().await.unwrap().unwrap();
+        assert_eq!(signed.attestation.height, 2);
+        assert!(verify(&signed, &signer.public_key()).is_ok());
+        assert!(check_ledger(&signed, &db).is_ok());
+
+        let mut forged = signed.clone();
+        forged.attestation.height = 1;
+        assert!(verify(&forged, &signer.public_key()).is_err());
+
+        // Rewriting block 2 is caught against the earlier attestation
+        db.delete_block(2).unwrap();
+        let mut rewritten = chain[1].clone();
+        rewritten.data[0].price = Decimal::ONE;
+        rewritten.calculate_hash_with_nonce();
+        db.save_block(&rewritten).unwrap();
+        assert!(check_ledger(&signed, &db)
+            .unwrap_err()
+            .contains("rewritten"));
+
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true))).with_database(db);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/attestations", web::get().to(list)),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/attestations")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        let listed = &body["attestations"][0];
+        // The notary was down
+        assert!(listed["notarized_at"].is_null());
+        let document: SignedAttestation =
+            serde_json::from_value(listed["attestation"].clone()).unwrap();
+        assert_eq!(document, signed);
+    }
+}
@@ -0,0 +1,311 @@
+//! Clock-skew measurement against peers and NTP
+//!
+//! Block and message timestamps come from each node's wall clock, so a node
+//! whose clock is far off will have its blocks rejected by timestamp
+//! validation on other nodes. At startup the node compares its clock with
+//! every reachable peer's `/health` time (and optionally an NTP server) and
+//! keeps the measured skew so it can be reported back through `/health`.
+
+use crate::etl::now_millis;
+use crate::network::peer_addr::{is_local, PeerAddr};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+/// Default tolerated difference between this node's clock and a peer's
+pub const DEFAULT_MAX_CLOCK_SKEW_MS: i64 = 1000;
+
+/// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// One skew measurement against a peer or time server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSample {
+    /// Remote clock minus local clock, corrected for half the round trip
+    pub skew_ms: i64,
+    pub round_trip_ms: i64,
+    /// Local time at which the sample was taken
+    pub measured_at: i64,
+}
+
+impl ClockSample {
+    /// Estimate skew from a remote time observed between `sent_at` and
+    /// `received_at` (all in unix milliseconds)
+    pub fn from_exchange(sent_at: i64, remote_time: i64, received_at: i64) -> Self {
+        let midpoint = sent_at + (received_at - sent_at) / 2;
+        ClockSample {
+            skew_ms: remote_time - midpoint,
+            round_trip_ms: received_at - sent_at,
+            measured_at: received_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HealthTime {
+    node_time_ms: i64,
+}
+
+/// Summary of a startup clock check
+#[derive(Debug, Clone, Default)]
+pub struct ClockCheckReport {
+    /// Samples keyed by peer address (or `ntp://server`)
+    pub samples: BTreeMap<String, ClockSample>,
+    /// Peers that could not be reached, with the error
+    pub unreachable: BTreeMap<String, String>,
+    /// Entries whose absolute skew exceeds the configured maximum
+    pub violations: Vec<String>,
+}
+
+impl ClockCheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Largest absolute skew observed, if any peer answered
+    pub fn max_abs_skew_ms(&self) -> Option<i64> {
+        self.samples.values().map(|s| s.skew_ms.abs()).max()
+    }
+}
+
+/// Keeps the latest skew sample per peer for health reporting
+pub struct ClockSkewMonitor {
+    max_skew_ms: i64,
+    ntp_server: Option<String>,
+    samples: RwLock<BTreeMap<String, ClockSample>>,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(max_skew_ms: i64) -> Self {
+        ClockSkewMonitor {
+            max_skew_ms,
+            ntp_server: None,
+            samples: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Also compare against an NTP server (`host:port`)
+    pub fn with_ntp_server(mut self, server: impl Into<String>) -> Self {
+        self.ntp_server = Some(server.into());
+        self
+    }
+
+    /// Build from `MAX_CLOCK_SKEW_MS` and `NTP_SERVER` environment variables
+    pub fn from_env() -> Self {
+        let max_skew_ms = std::env::var("MAX_CLOCK_SKEW_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CLOCK_SKEW_MS);
+        let monitor = ClockSkewMonitor::new(max_skew_ms);
+        match std::env::var("NTP_SERVER") {
+            Ok(server) if !server.is_empty() => monitor.with_ntp_server(server),
+            _ => monitor,
+        }
+    }
+
+    pub fn max_skew_ms(&self) -> i64 {
+        self.max_skew_ms
+    }
+
+    pub fn record(&self, source: &str, sample: ClockSample) {
+        self.samples.write().insert(source.to_string(), sample);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ClockSample> {
+        self.samples.read().clone()
+    }
+
+    /// Measure skew against every peer except ourselves (and the NTP server
+    /// when configured), record the samples and report violations
+    pub async fn check_peers(
+        &self,
+        peer_addresses: &[String],
+        local: &PeerAddr,
+    ) -> ClockCheckReport {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(2))
+            .build()
+            .unwrap_or_default();
+        let mut report = ClockCheckReport::default();
+
+        for addr in peer_addresses {
+            if is_local(addr, local) {
+                continue;
+            }
+            match measure_peer_skew(&client, addr).await {
+                Ok(sample) => {
+                    self.record(addr, sample.clone());
+                    report.samples.insert(addr.clone(), sample);
+                }
+                Err(e) => {
+                    report.unreachable.insert(addr.clone(), e.to_string());
+                }
+            }
+        }
+
+        if let Some(server) = &self.ntp_server {
+            let key = format!("ntp://{}", server);
+            match measure_ntp_skew(server).await {
+                Ok(sample) => {
+                    self.record(&key, sample.clone());
+                    report.samples.insert(key, sample);
+                }
+                Err(e) => {
+                    report.unreachable.insert(key, e.to_string());
+                }
+            }
+        }
+
+        for (source, sample) in &report.samples {
+            if sample.skew_ms.abs() > self.max_skew_ms {
+                report.violations.push(format!(
+                    "{} is {} ms {} (max {} ms)",
+                    source,
+                    sample.skew_ms.abs(),
+                    if sample.skew_ms > 0 {
+                        "ahead"
+                    } else {
+                        "behind"
+                    },
+                    self.max_skew_ms
+                ));
+            }
+        }
+
+        for (source, sample) in &report.samples {
+            info!(
+                source = %source,
+                skew_ms = sample.skew_ms,
+                round_trip_ms = sample.round_trip_ms,
+                "Clock: Measured skew"
+            );
+        }
+        for (source, error) in &report.unreachable {
+            warn!(source = %source, error = %error, "Clock: Could not measure skew");
+        }
+
+        report
+    }
+}
+
+/// Compare the local clock with the `node_time_ms` a peer reports on `/health`
+pub async fn measure_peer_skew(
+    client: &reqwest::Client,
+    addr: &str,
+) -> Result<ClockSample, Box<dyn Error>> {
+    let sent_at = now_millis();
+    let health: HealthTime = client
+        .get(format!("http://{}/health", addr))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let received_at = now_millis();
+
+    Ok(ClockSample::from_exchange(
+        sent_at,
+        health.node_time_ms,
+        received_at,
+    ))
+}
+
+/// Single SNTP (RFC 4330) query against `server` (`host:port`)
+pub async fn measure_ntp_skew(server: &str) -> Result<ClockSample, Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(server).await?;
+
+    // LI = 0, version = 4, mode = 3 (client)
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+
+    let sent_at = now_millis();
+    socket.send(&request).await?;
+
+    let mut response = [0u8; 48];
+    let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut response)).await??;
+    let received_at = now_millis();
+
+    if len < 48 {
+        return Err(format!("Short NTP response ({} bytes)", len).into());
+    }
+
+    Ok(ClockSample::from_exchange(
+        sent_at,
+        ntp_transmit_time_millis(&response)?,
+        received_at,
+    ))
+}
+
+/// Decode the transmit timestamp (bytes 40..48) of an NTP packet into unix ms
+fn ntp_transmit_time_millis(packet: &[u8; 48]) -> Result<i64, Box<dyn Error>> {
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+
+    if seconds < NTP_UNIX_OFFSET_SECS {
+        return Err("NTP server returned an unsynchronized timestamp".into());
+    }
+
+    let millis = (seconds - NTP_UNIX_OFFSET_SECS) * 1000 + ((fraction * 1000) >> 32);
+    Ok(millis as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_from_exchange() {
+        // Remote answered 600ms ahead of the midpoint of a 200ms round trip
+        let sample = ClockSample::from_exchange(10_000, 10_700, 10_200);
+        assert_eq!(sample.skew_ms, 600);
+        assert_eq!(sample.round_trip_ms, 200);
+        assert_eq!(sample.measured_at, 10_200);
+
+        let behind = ClockSample::from_exchange(10_000, 9_100, 10_000);
+        assert_eq!(behind.skew_ms, -900);
+    }
+
+    #[test]
+    fn test_ntp_transmit_time_decoding() {
+        let mut packet = [0u8; 48];
+        // 2024-01-01T00:00:00Z plus half a second
+        let seconds = (1_704_067_200u64 + NTP_UNIX_OFFSET_SECS) as u32;
+        packet[40..44].copy_from_slice(&seconds.to_be_bytes());
+        packet[44..48].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+
+        assert_eq!(
+            ntp_transmit_time_millis(&packet).unwrap(),
+            1_704_067_200_500
+        );
+
+        assert!(ntp_transmit_time_millis(&[0u8; 48]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_peers_skips_self_and_reports_unreachable() {
+        let monitor = ClockSkewMonitor::new(100);
+        let peers = vec!["127.0.0.1:8000".to_string(), "127.0.0.1:1".to_string()];
+
+        let report = monitor.check_peers(&peers, &PeerAddr::loopback(8000)).await;
+
+        assert!(report.samples.is_empty());
+        assert!(report.unreachable.contains_key("127.0.0.1:1"));
+        assert!(!report.unreachable.contains_key("127.0.0.1:8000"));
+        assert!(report.is_ok());
+        assert_eq!(report.max_abs_skew_ms(), None);
+    }
+
+    #[test]
+    fn test_monitor_records_samples() {
+        let monitor = ClockSkewMonitor::new(DEFAULT_MAX_CLOCK_SKEW_MS);
+        monitor.record("127.0.0.1:8001", ClockSample::from_exchange(0, 25, 10));
+
+        let snapshot = monitor.snapshot();
+        assert_eq!(snapshot["127.0.0.1:8001"].skew_ms, 20);
+        assert_eq!(monitor.max_skew_ms(), DEFAULT_MAX_CLOCK_SKEW_MS);
+    }
+}
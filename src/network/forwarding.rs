@@ -0,0 +1,352 @@
+//! Forwarding follower extractions to the primary
+//!
+//! Under PBFT only the primary of a sequence introduces its block, so the
+//! quotes a follower extracted and transformed that round never reach the
+//! proposal. With `FORWARD_TO_PRIMARY=true` on every node, a follower also
+//! POSTs the entries it built to `/forward` on the primary of the next
+//! sequence (of the unsharded instance). The receiving node checks each
+//! entry's provenance and price and queues it in its `Mempool`; the block
+//! loop adds queued entries to the next block it builds, after tenant
+//! entries, and drops them once a block holding them is committed.
+//!
+//! `/forward` is a peer route like `/message`: it needs no API key role, and
+//! with `PEER_ALLOWLIST` set it only accepts entries from listed members.
+//! A forward that fails is logged and not retried; the follower's entries
+//! still go into its own block.
+
+use crate::etl::validator::{Candidate, Validator};
+use crate::etl::{Block, MarketData};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::membership::{self, PeerRequest};
+use super::{ServerContext, TRACE_ID_HEADER};
+
+/// Forwarded entries a node holds before refusing more
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 1000;
+
+/// Forwarded entries included in a single block
+pub const MAX_FORWARDED_PER_BLOCK: usize = 500;
+
+/// Body of `POST /forward`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Forwarded {
+    /// Node that extracted the entries
+    pub node_id: usize,
+    pub entries: Vec<MarketData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardError {
+    Full { capacity: usize },
+    Invalid(String),
+}
+
+impl fmt::Display for ForwardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardError::Full { capacity } => {
+                write!(f, "mempool is full ({} entries)", capacity)
+            }
+            ForwardError::Invalid(reason) => write!(f, "invalid entry: {}", reason),
+        }
+    }
+}
+
+impl Error for ForwardError {}
+
+/// Entries forwarded by followers, awaiting a block
+pub struct Mempool {
+    capacity: usize,
+    validator: Validator,
+    entries: Mutex<VecDeque<MarketData>>,
+}
+
+/// What tells two forwarded entries apart
+fn entry_key(entry: &MarketData) -> (String, String, i64) {
+    (entry.asset.clone(), entry.source.clone(), entry.timestamp)
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool {
+            capacity: DEFAULT_MEMPOOL_CAPACITY,
+            validator: Validator::new(),
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// `None` unless `FORWARD_TO_PRIMARY` is on
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(value) = std::env::var("FORWARD_TO_PRIMARY") else {
+            return Ok(None);
+        };
+        match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => {}
+            "0" | "false" | "no" | "off" | "" => return Ok(None),
+            other => {
+                return Err(format!(
+                    "invalid FORWARD_TO_PRIMARY: '{}' (expected true or false)",
+                    other
+                ))
+            }
+        }
+        // Forwarded entries are held to the node's own price ranges
+        Ok(Some(Self::new().with_validator(Validator::from_env()?)))
+    }
+
+    /// Queue `entries` from a follower, skipping ones already queued;
+    /// returns how many were added
+    ///
+    /// Every entry must carry provenance that replays to its price and pass
+    /// this node's validation rules, or none is queued.
+    pub fn submit(&self, entries: Vec<MarketData>) -> Result<usize, ForwardError> {
+        for entry in &entries {
+            let provenance = entry.provenance.as_ref().ok_or_else(|| {
+                ForwardError::Invalid(format!("{} entry has no provenance", entry.asset))
+            })?;
+            provenance
+                .verify(entry)
+                .map_err(|e| ForwardError::Invalid(format!("{}: {}", entry.asset, e)))?;
+            self.validator
+                .validate(&Candidate {
+                    asset: &entry.asset,
+                    price: entry.price,
+                    timestamp: entry.timestamp,
+                    source: &entry.source,
+                })
+                .map_err(|e| ForwardError::Invalid(e.to_string()))?;
+        }
+
+        let mut queue = self.entries.lock();
+        let mut queued: HashSet<_> = queue.iter().map(entry_key).collect();
+        let fresh: Vec<MarketData> = entries
+            .into_iter()
+            .filter(|entry| queued.insert(entry_key(entry)))
+            .collect();
+        if queue.len() + fresh.len() > self.capacity {
+            return Err(ForwardError::Full {
+                capacity: self.capacity,
+            });
+        }
+        let added = fresh.len();
+        queue.extend(fresh);
+        Ok(added)
+    }
+
+    /// Up to `max` queued entries, oldest first, without removing them
+    pub fn pending(&self, max: usize) -> Vec<MarketData> {
+        self.entries.lock().iter().take(max).cloned().collect()
+    }
+
+    /// Drop the queued entries a committed block holds
+    pub fn record_committed(&self, block: &Block) {
+        let committed: HashSet<_> = block.data.iter().map(entry_key).collect();
+        self.entries
+            .lock()
+            .retain(|entry| !committed.contains(&entry_key(entry)));
+    }
+
+    /// Drop every queued entry from `source`, e.g. because it cannot cover
+    /// their fees; returns how many were dropped
+    pub fn drop_source(&self, source: &str) -> usize {
+        let mut queue = self.entries.lock();
+        let before = queue.len();
+        queue.retain(|entry| entry.source != source);
+        before - queue.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Send `forwarded` to the node at `address`; returns how many entries it
+/// queued
+pub async fn forward(
+    client: &reqwest::Client,
+    address: &str,
+    forwarded: &Forwarded,
+    trace_id: Option<&str>,
+) -> Result<usize, reqwest::Error> {
+    // Serialized here so the signature covers the exact body sent
+    let body = serde_json::to_vec(forwarded).unwrap_or_default();
+    let mut request = client
+        .post(format!("http://{}/forward", address))
+        .timeout(Duration::from_secs(5))
+        .header(CONTENT_TYPE, "application/json");
+    if let Some(trace_id) = trace_id {
+        request = request.header(TRACE_ID_HEADER, trace_id);
+    }
+    for (name, value) in membership::sign_request("/forward", &body) {
+        request = request.header(name, value);
+    }
+    let response = request.body(body).send().await?.error_for_status()?;
+    let body: serde_json::Value = response.json().await?;
+    Ok(body["queued"].as_u64().unwrap_or_default() as usize)
+}
+
+pub(super) async fn receive(
+    req: HttpRequest,
+    body: Bytes,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let Some(mempool) = context.mempool.as_deref() else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": "forwarding is not enabled on this node" }));
+    };
+    // Read from the raw body, which the sender's signature covers
+    let Forwarded { node_id, entries } = match serde_json::from_slice(&body) {
+        Ok(forwarded) => forwarded,
+        Err(e) => {
+            return HttpResponse::BadRequest()
+                .json(json!({ "error": format!("invalid forward: {}", e) }))
+        }
+    };
+    if let Some(membership) = &context.membership {
+        let remote_ip = req.peer_addr().map(|addr| addr.ip());
+        let request = PeerRequest::new(&req, &body);
+        if let Err(reason) = membership.authorize(node_id, remote_ip, &request) {
+            warn!(
+                node_id,
+                remote = ?remote_ip,
+                reason = %reason,
+                "Forward: Rejected entries from non-member"
+            );
+            return HttpResponse::Forbidden().json(json!({ "error": reason }));
+        }
+    }
+    match mempool.submit(entries) {
+        Ok(queued) => {
+            info!(
+                from_node = node_id,
+                entries = queued,
+                "Forward: Entries queued for the next block"
+            );
+            HttpResponse::Accepted().json(json!({ "queued": queued, "pending": mempool.len() }))
+        }
+        Err(e @ ForwardError::Full { .. }) => {
+            HttpResponse::ServiceUnavailable().json(json!({ "error": e.to_string() }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::now_millis;
+    use crate::etl::transform::Transformer;
+    use crate::network::NetworkHandler;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    fn entry(asset: &str, price: &str) -> MarketData {
+        let price = crate::etl::price::parse(price).unwrap();
+        let transformed = Transformer::new()
+            .pipeline()
+            .run(asset, price, now_millis(), "Kraken".to_string(), None)
+            .unwrap();
+        MarketData {
+            asset: transformed.asset,
+            price: transformed.price,
+            source: transformed.source,
+            timestamp: transformed.timestamp,
+            provenance: Some(transformed.provenance),
+            indicators: None,
+            conversion: None,
+            bucket: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_forwarded_entries_wait_for_a_block() {
+        let mempool = Arc::new(Mempool::new().with_capacity(3));
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_mempool(mempool.clone());
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/forward", web::post().to(receive)),
+        )
+        .await;
+        let post = |entries: Vec<MarketData>| {
+            actix_web::test::TestRequest::post()
+                .uri("/forward")
+                .set_json(Forwarded {
+                    node_id: 2,
+                    entries,
+                })
+                .to_request()
+        };
+
+        let (btc, eth) = (entry("BTC", "50000"), entry("ETH", "3000"));
+        let response = actix_web::test::call_service(&app, post(vec![btc.clone()])).await;
+        assert_eq!(response.status(), 202);
+        // Resending the same entry queues nothing new
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, post(vec![btc.clone(), eth.clone()]))
+                .await;
+        assert_eq!((body["queued"].as_u64(), mempool.len()), (Some(1), 2));
+
+        // A price that does not replay from its provenance is refused
+        let mut tampered = entry("SOL", "150");
+        tampered.price *= crate::etl::price::Decimal::from(2);
+        let response = actix_web::test::call_service(&app, post(vec![tampered])).await;
+        assert_eq!(response.status(), 400);
+        let response = actix_web::test::call_service(
+            &app,
+            post(vec![entry("SOL", "150"), entry("ADA", "0.5")]),
+        )
+        .await;
+        assert_eq!(response.status(), 503);
+
+        let block = Block {
+            index: 1,
+            timestamp: now_millis(),
+            data: vec![btc],
+            previous_hash: "0000_genesis".to_string(),
+            hash: String::new(),
+            nonce: 0,
+            format_version: crate::etl::BLOCK_FORMAT_VERSION,
+            fees: Vec::new(),
+            divergences: Vec::new(),
+            hlc: None,
+            annotations: Default::default(),
+            order_books: Vec::new(),
+        };
+        mempool.record_committed(&block);
+        let pending = mempool.pending(MAX_FORWARDED_PER_BLOCK);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].asset, eth.asset);
+    }
+}
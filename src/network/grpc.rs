@@ -0,0 +1,397 @@
+//! gRPC access to the ledger for downstream consumers
+//!
+//! `LedgerService` (see `proto/ledger.proto`) lets pipelines written in any
+//! language with a gRPC code generator read the chain: `GetHead` and
+//! `GetBlock` for point lookups and `StreamBlocks` to tail it. A stream first
+//! replays stored blocks from the requested height in index order, then polls
+//! the database for new ones, so a consumer that reconnects with the height
+//! after the last block it saw misses nothing. The service is read-only.
+//!
+//! Every block carries its finality as of when it was sent (see
+//! `consensus::finality`); a stream opened with `finalized_only` holds blocks
+//! back until they are final, for consumers that must never see a block
+//! reorganized away.
+//!
+//! Built with the `grpc` feature and served when `GRPC_PORT` is set.
+//! `GRPC_POLL_INTERVAL_MS` (default 500) sets how often open streams check
+//! for new blocks.
+
+use crate::consensus::finality::{Finality, FinalityRule, FinalityStatus};
+use crate::etl::load::{DatabaseError, DatabaseManager};
+use crate::etl::{price, Block};
+use crate::network::peer_addr::bind_ip_from_env;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::{debug, info};
+
+/// Types generated from `proto/ledger.proto`
+pub mod proto {
+    tonic::include_proto!("ledger.v1");
+}
+
+use proto::ledger_service_server::{LedgerService, LedgerServiceServer};
+
+/// Blocks read from the database per query while a stream catches up
+const PAGE_BLOCKS: u64 = 256;
+
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    /// `BIND_ADDRESS`, shared with the HTTP server
+    pub bind_ip: IpAddr,
+    pub port: u16,
+    pub poll_interval: Duration,
+}
+
+impl GrpcConfig {
+    pub fn new(port: u16) -> Self {
+        GrpcConfig {
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+
+    /// `None` unless `GRPC_PORT` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(port) = std::env::var("GRPC_PORT") else {
+            return Ok(None);
+        };
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid GRPC_PORT '{}': {}", port, e))?;
+        let mut config = Self::new(port);
+        config.bind_ip = bind_ip_from_env()?;
+        if let Ok(ms) = std::env::var("GRPC_POLL_INTERVAL_MS") {
+            let ms: u64 = ms
+                .parse()
+                .map_err(|e| format!("invalid GRPC_POLL_INTERVAL_MS: {}", e))?;
+            config.poll_interval = Duration::from_millis(ms.max(1));
+        }
+        Ok(Some(config))
+    }
+}
+
+impl From<&Block> for proto::Block {
+    fn from(block: &Block) -> Self {
+        proto::Block {
+            index: block.index,
+            timestamp: block.timestamp,
+            entries: block
+                .data
+                .iter()
+                .map(|entry| proto::Entry {
+                    asset: entry.asset.clone(),
+                    price: price::to_f32(entry.price),
+                    source: entry.source.clone(),
+                    timestamp: entry.timestamp,
+                    price_decimal: entry.price.to_string(),
+                })
+                .collect(),
+            previous_hash: block.previous_hash.clone(),
+            hash: block.hash.clone(),
+            nonce: block.nonce,
+            format_version: block.format_version,
+            block_json: serde_json::to_string(block).unwrap_or_default(),
+            finality: None,
+        }
+    }
+}
+
+impl From<Finality> for proto::Finality {
+    fn from(finality: Finality) -> Self {
+        proto::Finality {
+            finalized: finality.status == FinalityStatus::Finalized,
+            confirmations: finality.confirmations,
+            required_confirmations: finality.required_confirmations,
+        }
+    }
+}
+
+/// `block` as sent while the chain's head is at `head`
+fn block_message(block: &Block, finality: FinalityRule, head: u64) -> proto::Block {
+    proto::Block {
+        finality: Some(finality.of(block.index, head).into()),
+        ..block.into()
+    }
+}
+
+fn head_index(db: &DatabaseManager) -> Result<u64, DatabaseError> {
+    Ok(db.get_latest_block()?.map_or(0, |head| head.index))
+}
+
+fn to_status(err: DatabaseError) -> Status {
+    match err {
+        DatabaseError::NotFound(e) => Status::not_found(e),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+/// `LedgerService` over a node's database
+pub struct LedgerGrpc {
+    db: Arc<DatabaseManager>,
+    poll_interval: Duration,
+    finality: FinalityRule,
+}
+
+impl LedgerGrpc {
+    pub fn new(db: Arc<DatabaseManager>, poll_interval: Duration) -> Self {
+        LedgerGrpc {
+            db,
+            poll_interval,
+            finality: FinalityRule::default(),
+        }
+    }
+
+    pub fn with_finality(mut self, finality: FinalityRule) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    /// Serve on `listener` until the task is dropped
+    pub async fn serve_on(self, listener: TcpListener) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(LedgerServiceServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    /// Serve on `<config.bind_ip>:<config.port>`
+    pub async fn serve(
+        db: Arc<DatabaseManager>,
+        config: GrpcConfig,
+        finality: FinalityRule,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = TcpListener::bind((config.bind_ip, config.port)).await?;
+        info!(address = %listener.local_addr()?, "gRPC: Serving LedgerService");
+        LedgerGrpc::new(db, config.poll_interval)
+            .with_finality(finality)
+            .serve_on(listener)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Send blocks from `from_height` to `tx` until the receiver is dropped
+///
+/// Streams start at the lowest stored index at or above `from_height`, since
+/// a pruned ledger may not begin at 1. After that blocks are sent strictly in
+/// sequence: a gap (a block still being synced) is waited out rather than
+/// skipped. With `finalized_only` no block above the finalized height is
+/// sent.
+async fn feed(
+    db: Arc<DatabaseManager>,
+    poll_interval: Duration,
+    finality: FinalityRule,
+    request: proto::StreamBlocksRequest,
+    tx: mpsc::Sender<Result<proto::Block, Status>>,
+) {
+    let mut next: Option<u64> = None;
+    loop {
+        let start = next.unwrap_or(request.from_height);
+        let page = head_index(&db).and_then(|head| {
+            let mut end = start.saturating_add(PAGE_BLOCKS - 1);
+            if request.finalized_only {
+                end = end.min(finality.finalized_height(head));
+            }
+            let page = if start <= end {
+                db.get_blocks_range(start, end)?
+            } else {
+                Vec::new()
+            };
+            Ok((page, head))
+        });
+        let (page, head) = match page {
+            Ok(page) => page,
+            Err(e) => {
+                let _ = tx.send(Err(to_status(e))).await;
+                return;
+            }
+        };
+        let full_page = page.len() as u64 == PAGE_BLOCKS;
+        for block in &page {
+            let expected = *next.get_or_insert(block.index);
+            if block.index != expected {
+                break;
+            }
+            if tx
+                .send(Ok(block_message(block, finality, head)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            next = Some(expected + 1);
+        }
+        if !full_page {
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = tx.closed() => {
+                    debug!(next = ?next, "gRPC: Block stream closed by client");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+type BlockStream = Pin<Box<dyn Stream<Item = Result<proto::Block, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl LedgerService for LedgerGrpc {
+    type StreamBlocksStream = BlockStream;
+
+    async fn stream_blocks(
+        &self,
+        request: Request<proto::StreamBlocksRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        let (tx, rx) = mpsc::channel(PAGE_BLOCKS as usize);
+        tokio::spawn(feed(
+            self.db.clone(),
+            self.poll_interval,
+            self.finality,
+            request.into_inner(),
+            tx,
+        ));
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        use proto::get_block_request::Selector;
+        let block = match request.into_inner().selector {
+            Some(Selector::Index(index)) => self.db.get_block_by_index(index),
+            Some(Selector::Hash(hash)) => self.db.get_block_by_hash(&hash),
+            None => return Err(Status::invalid_argument("index or hash is required")),
+        }
+        .map_err(to_status)?;
+        let head = head_index(&self.db).map_err(to_status)?;
+        Ok(Response::new(block_message(&block, self.finality, head)))
+    }
+
+    async fn get_head(
+        &self,
+        _request: Request<proto::GetHeadRequest>,
+    ) -> Result<Response<proto::Head>, Status> {
+        let head = self
+            .db
+            .get_latest_block()
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found("ledger is empty"))?;
+        Ok(Response::new(proto::Head {
+            index: head.index,
+            hash: head.hash,
+            timestamp: head.timestamp,
+            block_count: self.db.get_block_count().map_err(to_status)?,
+            finalized_height: self.finality.finalized_height(head.index),
+            finality: self.finality.describe(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::ledger_service_client::LedgerServiceClient;
+    use super::*;
+    use crate::testing::TestChainBuilder;
+
+    #[tokio::test]
+    async fn test_ledger_service_serves_and_tails_chain() {
+        let chain = TestChainBuilder::new().with_blocks(3);
+        let db = chain.build_in_memory().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            LedgerGrpc::new(db.clone(), Duration::from_millis(10))
+                .with_finality(FinalityRule::Depth(1))
+                .serve_on(listener),
+        );
+
+        let mut client = LedgerServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        let head = client
+            .get_head(proto::GetHeadRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((head.index, head.block_count), (3, 3));
+        assert_eq!(
+            (head.finalized_height, head.finality.as_str()),
+            (2, "depth:1")
+        );
+
+        let block = client
+            .get_block(proto::GetBlockRequest {
+                selector: Some(proto::get_block_request::Selector::Hash(head.hash.clone())),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(block.index, 3);
+        assert!(!block.finality.unwrap().finalized);
+        let stored: Block = serde_json::from_str(&block.block_json).unwrap();
+        assert_eq!(stored.calculate_hash(), head.hash);
+
+        let missing = client
+            .get_block(proto::GetBlockRequest {
+                selector: Some(proto::get_block_request::Selector::Index(99)),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let mut stream = client
+            .stream_blocks(proto::StreamBlocksRequest {
+                from_height: 2,
+                finalized_only: false,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.message().await.unwrap().unwrap().index, 2);
+        assert_eq!(stream.message().await.unwrap().unwrap().index, 3);
+        let mut finalized = client
+            .stream_blocks(proto::StreamBlocksRequest {
+                from_height: 2,
+                finalized_only: true,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let first = finalized.message().await.unwrap().unwrap();
+        assert_eq!(first.index, 2);
+        assert!(first.finality.unwrap().finalized);
+
+        // Blocks committed after the stream opened are delivered too
+        let more = TestChainBuilder::new()
+            .with_blocks(1)
+            .starting_after(3, head.hash)
+            .build();
+        db.save_blocks(&more).unwrap();
+        let tailed = tokio::time::timeout(Duration::from_secs(5), stream.message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tailed.index, 4);
+        assert_eq!(tailed.previous_hash, block.hash);
+        // Block 3 is sent to the finalized stream once block 4 confirms it
+        let settled = tokio::time::timeout(Duration::from_secs(5), finalized.message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(settled.index, 3);
+        assert_eq!(settled.finality.unwrap().confirmations, 1);
+    }
+}
@@ -0,0 +1,453 @@
+//! Static cluster membership
+//!
+//! When `PEER_ALLOWLIST` is set, `/message` only accepts consensus messages
+//! whose `node_id` is listed and which arrive from that peer's configured IP.
+//! A peer entry may also pin the Ed25519 public key of the peer's
+//! `NODE_SIGNING_KEY`. Requests from that peer must then be signed with
+//! it: the sender signs the route, the time it sent the request and the
+//! body (`signing_input`), and puts the signature and time in the
+//! `X-Node-Signature` and `X-Node-Signed-At` headers. A request signed more
+//! than `MAX_SIGNATURE_SKEW_MS` away from the receiver's clock is refused,
+//! so a captured request can only be replayed briefly, and only from the
+//! peer's own address; consensus messages are idempotent, so a replay
+//! changes nothing.
+//!
+//! Format: comma-separated `node_id@host:port[/public_key]`, e.g.
+//! `0@127.0.0.1:8000,1@127.0.0.1:8001/8f3a...`
+
+use super::oracle::{self, OracleSigner};
+use super::peer_addr::PeerAddr;
+use crate::etl::now_millis;
+use actix_web::HttpRequest;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::OnceLock;
+
+/// Header carrying the sender's hex Ed25519 signature over the request
+pub const SIGNATURE_HEADER: &str = "X-Node-Signature";
+
+/// Header carrying when the sender signed the request, in Unix milliseconds
+pub const SIGNED_AT_HEADER: &str = "X-Node-Signed-At";
+
+/// How far a signed request's time may be from the receiver's clock
+pub const MAX_SIGNATURE_SKEW_MS: i64 = 30_000;
+
+/// Bytes a peer signs for a request to `route` with `body`
+pub fn signing_input(route: &str, signed_at: i64, body: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(32 + route.len() + body.len());
+    input.extend_from_slice(b"rml-peer-v1");
+    input.extend_from_slice(&(route.len() as u64).to_be_bytes());
+    input.extend_from_slice(route.as_bytes());
+    input.extend_from_slice(&signed_at.to_be_bytes());
+    input.extend_from_slice(body);
+    input
+}
+
+/// A request from a peer, as far as its signature is concerned
+#[derive(Debug, Clone, Copy)]
+pub struct PeerRequest<'a> {
+    pub route: &'a str,
+    pub body: &'a [u8],
+    pub signed_at: Option<&'a str>,
+    pub signature: Option<&'a str>,
+}
+
+impl<'a> PeerRequest<'a> {
+    pub fn new(req: &'a HttpRequest, body: &'a [u8]) -> Self {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        PeerRequest {
+            route: req.path(),
+            body,
+            signed_at: header(SIGNED_AT_HEADER),
+            signature: header(SIGNATURE_HEADER),
+        }
+    }
+
+    /// Check the request was signed with `public_key` (hex) within
+    /// `MAX_SIGNATURE_SKEW_MS` of `now_ms`
+    pub fn verify(&self, public_key: &str, now_ms: i64) -> Result<(), String> {
+        let signed_at: i64 = self
+            .signed_at
+            .and_then(|at| at.trim().parse().ok())
+            .ok_or_else(|| format!("missing or invalid {} header", SIGNED_AT_HEADER))?;
+        if now_ms.abs_diff(signed_at) > MAX_SIGNATURE_SKEW_MS as u64 {
+            return Err(format!(
+                "signed {}ms away from this node's clock",
+                now_ms - signed_at
+            ));
+        }
+        let signature = self
+            .signature
+            .ok_or_else(|| format!("missing {} header", SIGNATURE_HEADER))?;
+        oracle::verify_signature(
+            public_key,
+            &signing_input(self.route, signed_at, self.body),
+            signature,
+        )
+    }
+}
+
+/// Addresses of the local four-node cluster, indexed by node id
+pub fn default_node_addresses() -> Vec<String> {
+    (0..4)
+        .map(|id| format!("127.0.0.1:{}", 8000 + id))
+        .collect()
+}
+
+/// Port node `node_id` listens on unless told otherwise: the port of its
+/// entry in `node_addresses`, else 8000 + id; `None` when that is past the
+/// last port
+pub fn default_port(node_addresses: &[String], node_id: usize) -> Option<u16> {
+    match node_addresses.get(node_id) {
+        Some(address) => address.parse::<PeerAddr>().ok().map(|a| a.port()),
+        None => u16::try_from(node_id).ok()?.checked_add(8000),
+    }
+}
+
+/// Cluster addresses indexed by node id, from `NODE_ADDRESSES`
+/// (comma-separated `host:port`, IPv6 as `[addr]:port`); the local
+/// four-node cluster when unset
+pub fn node_addresses_from_env() -> Result<Vec<String>, String> {
+    match std::env::var("NODE_ADDRESSES") {
+        Ok(spec) => {
+            parse_node_addresses(&spec).map_err(|e| format!("invalid NODE_ADDRESSES: {}", e))
+        }
+        Err(_) => Ok(default_node_addresses()),
+    }
+}
+
+/// Parse comma-separated peer addresses into their canonical spelling
+pub fn parse_node_addresses(spec: &str) -> Result<Vec<String>, String> {
+    let addresses: Vec<String> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| a.parse::<PeerAddr>().map(|addr| addr.to_string()))
+        .collect::<Result<_, _>>()?;
+    if addresses.is_empty() {
+        return Err("no addresses".to_string());
+    }
+    Ok(addresses)
+}
+
+/// One allowed cluster member
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerIdentity {
+    pub node_id: usize,
+    /// `host:port` the peer serves on
+    pub address: String,
+    /// Hex-encoded Ed25519 key the peer must sign its requests with, if
+    /// pinned
+    pub public_key: Option<String>,
+    /// IPs `address` resolved to when the membership was loaded
+    ips: Vec<IpAddr>,
+}
+
+impl PeerIdentity {
+    pub fn new(
+        node_id: usize,
+        address: impl Into<String>,
+        public_key: Option<String>,
+    ) -> Result<Self, String> {
+        let address = address.into();
+        let ips = address
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve peer {} address {}: {}", node_id, address, e))?
+            .map(|sock| sock.ip())
+            .collect();
+        Ok(PeerIdentity {
+            node_id,
+            address,
+            public_key,
+            ips,
+        })
+    }
+}
+
+/// Whether two spellings name the same peer, e.g. `Node-1:8000` and
+/// `node-1:8000`
+fn same_address(a: &str, b: &str) -> bool {
+    match (a.parse::<PeerAddr>(), b.parse::<PeerAddr>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// The fixed set of nodes allowed to take part in consensus
+#[derive(Debug, Clone, Default)]
+pub struct ClusterMembership {
+    peers: BTreeMap<usize, PeerIdentity>,
+}
+
+impl ClusterMembership {
+    pub fn new(peers: Vec<PeerIdentity>) -> Self {
+        ClusterMembership {
+            peers: peers.into_iter().map(|p| (p.node_id, p)).collect(),
+        }
+    }
+
+    /// Parse the `PEER_ALLOWLIST` format described in the module docs
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut peers = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, rest) = entry.split_once('@').ok_or_else(|| {
+                format!("invalid allowlist entry '{}': expected id@host:port", entry)
+            })?;
+            let node_id = id
+                .parse()
+                .map_err(|_| format!("invalid node id '{}' in allowlist", id))?;
+            let (address, public_key) = match rest.split_once('/') {
+                Some((address, key)) => {
+                    if hex::decode(key).map_or(true, |bytes| bytes.len() != 32) {
+                        return Err(format!(
+                            "invalid public key for node {}: expected 64 hex characters",
+                            node_id
+                        ));
+                    }
+                    (address, Some(key.to_ascii_lowercase()))
+                }
+                None => (rest, None),
+            };
+            peers.push(PeerIdentity::new(node_id, address, public_key)?);
+        }
+        if peers.is_empty() {
+            return Err("PEER_ALLOWLIST is set but lists no peers".to_string());
+        }
+        Ok(ClusterMembership::new(peers))
+    }
+
+    /// Load from `PEER_ALLOWLIST`; `Ok(None)` when it is not set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("PEER_ALLOWLIST") {
+            Ok(spec) if !spec.trim().is_empty() => Self::parse(&spec).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get(&self, node_id: usize) -> Option<&PeerIdentity> {
+        self.peers.get(&node_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Check that the node's configured peer list is exactly this membership,
+    /// with `node_addresses[i]` belonging to node `i`
+    pub fn verify_cluster(&self, node_addresses: &[String]) -> Result<(), String> {
+        if node_addresses.len() != self.peers.len() {
+            return Err(format!(
+                "cluster has {} nodes but the allowlist has {}",
+                node_addresses.len(),
+                self.peers.len()
+            ));
+        }
+        for (node_id, address) in node_addresses.iter().enumerate() {
+            match self.peers.get(&node_id) {
+                Some(peer) if same_address(&peer.address, address) => {}
+                Some(peer) => {
+                    return Err(format!(
+                        "node {} is {} in the cluster but {} in the allowlist",
+                        node_id, address, peer.address
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "node {} ({}) is not in the allowlist",
+                        node_id, address
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decide whether `request`, claiming to come from `node_id`, is accepted
+    pub fn authorize(
+        &self,
+        node_id: usize,
+        remote_ip: Option<IpAddr>,
+        request: &PeerRequest<'_>,
+    ) -> Result<(), String> {
+        let peer = self
+            .peers
+            .get(&node_id)
+            .ok_or_else(|| format!("node {} is not a cluster member", node_id))?;
+
+        match remote_ip {
+            Some(ip) if peer.ips.contains(&ip) => {}
+            Some(ip) => {
+                return Err(format!(
+                    "node {} must connect from {}, not {}",
+                    node_id, peer.address, ip
+                ))
+            }
+            None => return Err("sender address unknown".to_string()),
+        }
+
+        if let Some(public_key) = &peer.public_key {
+            request
+                .verify(public_key, now_millis())
+                .map_err(|e| format!("node {} request is not properly signed: {}", node_id, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// This node's `NODE_SIGNING_KEY`, which signs its requests to peers;
+/// startup has already refused an invalid one
+fn local_signer() -> Option<&'static OracleSigner> {
+    static SIGNER: OnceLock<Option<OracleSigner>> = OnceLock::new();
+    SIGNER
+        .get_or_init(|| OracleSigner::from_env(0).ok().flatten())
+        .as_ref()
+}
+
+/// Signature headers for a request to `route` with `body`, signed now with
+/// `signer`
+pub fn signature_headers(
+    signer: &OracleSigner,
+    route: &str,
+    body: &[u8],
+) -> [(&'static str, String); 2] {
+    let signed_at = now_millis();
+    let signature = signer.sign_bytes(&signing_input(route, signed_at, body));
+    [
+        (SIGNED_AT_HEADER, signed_at.to_string()),
+        (SIGNATURE_HEADER, signature),
+    ]
+}
+
+/// Headers that sign a request to `route` with this node's key; none
+/// without a `NODE_SIGNING_KEY`
+pub fn sign_request(route: &str, body: &[u8]) -> Vec<(&'static str, String)> {
+    local_signer()
+        .map(|signer| signature_headers(signer, route, body).to_vec())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn localhost() -> Option<IpAddr> {
+        Some("127.0.0.1".parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_allowlist() {
+        let key = "AB".repeat(32);
+        let membership =
+            ClusterMembership::parse(&format!("0@127.0.0.1:8000, 1@127.0.0.1:8001/{}", key))
+                .unwrap();
+
+        assert_eq!(membership.len(), 2);
+        assert_eq!(membership.get(0).unwrap().public_key, None);
+        assert_eq!(membership.get(1).unwrap().public_key, Some("ab".repeat(32)));
+
+        assert!(ClusterMembership::parse("1@127.0.0.1:8001/abcd").is_err());
+        assert!(ClusterMembership::parse("127.0.0.1:8000").is_err());
+        assert!(ClusterMembership::parse("x@127.0.0.1:8000").is_err());
+        assert!(ClusterMembership::parse(" , ").is_err());
+    }
+
+    fn unsigned(body: &[u8]) -> PeerRequest<'_> {
+        PeerRequest {
+            route: "/message",
+            body,
+            signed_at: None,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_authorize_checks_id_address_and_signature() {
+        let signer = OracleSigner::new(1, [7; 32]);
+        let membership = ClusterMembership::parse(&format!(
+            "0@127.0.0.1:8000,1@127.0.0.1:8001/{}",
+            signer.public_key()
+        ))
+        .unwrap();
+        let body = br#"{"node_id":1}"#;
+
+        assert!(membership
+            .authorize(0, localhost(), &unsigned(body))
+            .is_ok());
+        assert!(membership
+            .authorize(7, localhost(), &unsigned(body))
+            .is_err());
+        assert!(membership
+            .authorize(0, Some("10.0.0.9".parse().unwrap()), &unsigned(body))
+            .is_err());
+        assert!(membership.authorize(0, None, &unsigned(body)).is_err());
+
+        // A pinned peer must sign with its key; presenting it is not enough
+        assert!(membership
+            .authorize(1, localhost(), &unsigned(body))
+            .is_err());
+        let [(_, signed_at), (_, signature)] = signature_headers(&signer, "/message", body);
+        let signed = PeerRequest {
+            signed_at: Some(&signed_at),
+            signature: Some(&signature),
+            ..unsigned(body)
+        };
+        assert!(membership.authorize(1, localhost(), &signed).is_ok());
+        let tampered = PeerRequest {
+            body: br#"{"node_id":2}"#,
+            ..signed
+        };
+        assert!(membership.authorize(1, localhost(), &tampered).is_err());
+        let other_route = PeerRequest {
+            route: "/forward",
+            ..signed
+        };
+        assert!(membership.authorize(1, localhost(), &other_route).is_err());
+        let stranger = OracleSigner::new(1, [8; 32]);
+        let [(_, _), (_, forged)] = signature_headers(&stranger, "/message", body);
+        let forged = PeerRequest {
+            signature: Some(&forged),
+            ..signed
+        };
+        assert!(membership.authorize(1, localhost(), &forged).is_err());
+
+        // A signature is only good close to when it was made
+        let now: i64 = signed_at.parse().unwrap();
+        assert!(signed.verify(&signer.public_key(), now + 1_000).is_ok());
+        assert!(signed
+            .verify(&signer.public_key(), now + MAX_SIGNATURE_SKEW_MS + 1)
+            .unwrap_err()
+            .contains("away from this node's clock"));
+    }
+
+    #[test]
+    fn test_default_port() {
+        let addresses = vec!["127.0.0.1:9000".to_string(), "[::1]:9001".to_string()];
+        assert_eq!(default_port(&addresses, 1), Some(9001));
+        assert_eq!(default_port(&addresses, 7), Some(8007));
+        assert_eq!(default_port(&[], 57_535), Some(65_535));
+        assert_eq!(default_port(&[], 57_536), None);
+        assert_eq!(default_port(&[], 1 << 20), None);
+    }
+
+    #[test]
+    fn test_verify_cluster() {
+        let membership = ClusterMembership::parse("0@127.0.0.1:8000,1@127.0.0.1:8001").unwrap();
+        let nodes = vec!["127.0.0.1:8000".to_string(), "127.0.0.1:8001".to_string()];
+        assert!(membership.verify_cluster(&nodes).is_ok());
+
+        let swapped = vec![nodes[1].clone(), nodes[0].clone()];
+        assert!(membership.verify_cluster(&swapped).is_err());
+        assert!(membership.verify_cluster(&nodes[..1]).is_err());
+
+        let membership = ClusterMembership::parse("0@[::1]:8000,1@LOCALHOST:8001").unwrap();
+        let nodes = parse_node_addresses("[::1]:8000, localhost:8001").unwrap();
+        assert!(membership.verify_cluster(&nodes).is_ok());
+        assert!(parse_node_addresses("::1:8000").is_err());
+        assert!(parse_node_addresses(" , ").is_err());
+    }
+}
@@ -1,89 +1,1501 @@
+pub mod admin;
+pub mod anchor;
+pub mod attestation;
+pub mod clock;
+pub mod forwarding;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod membership;
+pub mod oracle;
+pub mod ots;
+pub mod outbox;
+pub mod pagination;
+pub mod parallel_verify;
+pub mod peer_addr;
+pub mod protocol;
+pub mod rbac;
+pub mod redaction;
+pub mod services;
+pub mod sync;
+pub mod tenancy;
+pub mod topology;
+pub mod verification;
+
 use crate::consensus::algorithms::PBFTMessage;
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use crate::consensus::event_log::EventLog;
+use crate::consensus::finality::{Finality, FinalityRule};
+use crate::consensus::{
+    ConsensusAlgorithm, ConsensusError, ConsensusMessage, ConsensusResult, PendingDetails,
+};
+use crate::etl::accounting::AccountBook;
+use crate::etl::analytics::AnalyticsRange;
+use crate::etl::extract_status::ExtractionTracker;
+use crate::etl::guardrails::{StorageGuard, StorageState};
+use crate::etl::load::DatabaseManager;
+use crate::etl::sla::{self, CommitSla};
+use crate::etl::transform_stats::TransformTracker;
+use crate::etl::{now_millis, Block};
+use crate::features::FeatureFlags;
+use crate::retry::{classify_reqwest, RetryPolicy};
+use crate::system_metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Server, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use admin::NodeControl;
+use anchor::Anchorer;
+use bytes::Bytes;
+use clock::ClockSkewMonitor;
+use forwarding::Mempool;
+use futures_util::future::join_all;
+use membership::{ClusterMembership, PeerRequest};
+use oracle::OracleSigner;
+use pagination::Page;
+use peer_addr::PeerAddr;
+use protocol::{DecodeError, Decoded, PeerVersions, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER};
+use rbac::AccessPolicy;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::sync::Arc;
-use tracing::{info, warn};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tenancy::TenantRegistry;
+use topology::{ClusterGraph, MESSAGE_FLOW};
+use tracing::{info, info_span, warn, Instrument};
+use verification::RollingVerifier;
+
+/// Header carrying a request's correlation id; peers forward the trace id
+/// of a consensus message in it
+pub const TRACE_ID_HEADER: &str = "X-Trace-Id";
+
+/// What handling a consensus message did, reported to the sender
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandleOutcome {
+    /// The message completed a quorum
+    Accepted,
+    /// Recorded; the round has not reached a quorum yet
+    Pending(PendingDetails),
+    /// The message completed the round and the block committed
+    Committed { index: u64, hash: String },
+    /// The message was not processed
+    Rejected(String),
+}
+
+impl HandleOutcome {
+    pub fn quorum_reached(&self) -> bool {
+        matches!(
+            self,
+            HandleOutcome::Accepted | HandleOutcome::Committed { .. }
+        )
+    }
+
+    /// Response body for `/message`; `status` and `quorum_reached` keep the
+    /// shape older senders parse
+    pub fn to_json(&self, algorithm: &str) -> serde_json::Value {
+        let mut body = json!({
+            "algorithm": algorithm,
+            "quorum_reached": self.quorum_reached(),
+        });
+        match self {
+            HandleOutcome::Accepted => body["status"] = json!("accepted"),
+            HandleOutcome::Pending(progress) => {
+                body["status"] = json!("pending");
+                if !progress.phases.is_empty() {
+                    body["progress"] = json!(progress);
+                }
+            }
+            HandleOutcome::Committed { index, hash } => {
+                body["status"] = json!("committed");
+                body["block_index"] = json!(index);
+                body["block_hash"] = json!(hash);
+            }
+            HandleOutcome::Rejected(reason) => {
+                body["status"] = json!("rejected");
+                body["reason"] = json!(reason);
+            }
+        }
+        body
+    }
+}
+
+impl From<bool> for HandleOutcome {
+    /// `true` when the message completed a quorum
+    fn from(quorum_reached: bool) -> Self {
+        if quorum_reached {
+            HandleOutcome::Accepted
+        } else {
+            HandleOutcome::Pending(PendingDetails::default())
+        }
+    }
+}
 
+impl From<Result<ConsensusResult, ConsensusError>> for HandleOutcome {
+    fn from(result: Result<ConsensusResult, ConsensusError>) -> Self {
+        match result {
+            Ok(ConsensusResult::Committed(block)) => HandleOutcome::Committed {
+                index: block.index,
+                hash: block.hash,
+            },
+            Ok(ConsensusResult::Pending(progress)) => HandleOutcome::Pending(progress),
+            Ok(ConsensusResult::Rejected(reason)) => HandleOutcome::Rejected(reason),
+            Err(e) => HandleOutcome::Rejected(e.to_string()),
+        }
+    }
+}
+
+type HandleFuture = Pin<Box<dyn Future<Output = HandleOutcome> + Send>>;
+
+/// Processes the consensus messages the server receives on `/message`
 pub struct NetworkHandler {
-    pub on_message: Arc<dyn Fn(PBFTMessage) -> bool + Send + Sync>,
+    algorithm: String,
+    on_message: Arc<dyn Fn(PBFTMessage) -> HandleFuture + Send + Sync>,
 }
 
 impl NetworkHandler {
-    pub fn new<F>(handler: F) -> Self
+    /// Handle PBFT messages synchronously, e.g. with a closure returning
+    /// whether the message completed a quorum
+    pub fn new<F, O>(handler: F) -> Self
+    where
+        F: Fn(PBFTMessage) -> O + Send + Sync + 'static,
+        O: Into<HandleOutcome>,
+    {
+        Self::new_async(move |msg| std::future::ready(handler(msg).into()))
+    }
+
+    /// Handle messages with an async function
+    pub fn new_async<F, Fut>(handler: F) -> Self
     where
-        F: Fn(PBFTMessage) -> bool + Send + Sync + 'static,
+        F: Fn(PBFTMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandleOutcome> + Send + 'static,
     {
         NetworkHandler {
-            on_message: Arc::new(handler),
+            algorithm: "PBFT".to_string(),
+            on_message: Arc::new(move |msg| Box::pin(handler(msg))),
         }
     }
+
+    /// Hand each message to `algorithm`'s `handle_message`
+    pub fn for_algorithm(algorithm: Arc<dyn ConsensusAlgorithm>) -> Self {
+        let name = algorithm.name().to_string();
+        Self::new_async(move |msg| {
+            let algorithm = algorithm.clone();
+            async move {
+                let message = consensus_message(algorithm.name(), &msg);
+                algorithm.handle_message(message).await.into()
+            }
+        })
+        .with_algorithm(name)
+    }
+
+    /// Algorithm name reported in `/message` responses
+    pub fn with_algorithm(mut self, name: impl Into<String>) -> Self {
+        self.algorithm = name.into();
+        self
+    }
+
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    pub async fn handle(&self, msg: PBFTMessage) -> HandleOutcome {
+        (self.on_message)(msg).await
+    }
+}
+
+/// A `/message` envelope as the generic message `ConsensusAlgorithm`s take;
+/// the full PBFT message travels as JSON in `data`
+fn consensus_message(algorithm: &str, msg: &PBFTMessage) -> ConsensusMessage {
+    ConsensusMessage {
+        algorithm: algorithm.to_string(),
+        block_index: msg.sequence,
+        block_hash: msg.block_hash.clone(),
+        node_id: msg.node_id,
+        data: serde_json::to_vec(msg).unwrap_or_default(),
+    }
+}
+
+/// Shared node state made available to the HTTP server's routes
+#[derive(Clone)]
+pub struct ServerContext {
+    pub handler: Arc<NetworkHandler>,
+    pub clock: Option<Arc<ClockSkewMonitor>>,
+    /// Local ledger, served to peers catching up through `/blocks`
+    pub db: Option<Arc<DatabaseManager>>,
+    /// Operator controls exposed under `/admin`
+    pub control: Option<Arc<NodeControl>>,
+    /// Bearer token required by `/admin` routes; `None` disables them
+    pub admin_token: Option<String>,
+    /// When set, `/message` rejects senders outside the cluster
+    pub membership: Option<Arc<ClusterMembership>>,
+    /// Rolling ledger verifier whose status `/health` reports
+    pub verifier: Option<Arc<RollingVerifier>>,
+    /// Storage guardrails whose state `/health` reports
+    pub storage: Option<Arc<StorageGuard>>,
+    /// Fee accounting served under `/accounts`
+    pub accounts: Option<Arc<AccountBook>>,
+    /// API keys and namespaces served under `/tenant`
+    pub tenants: Option<Arc<TenantRegistry>>,
+    /// Per-route role checks; `None` leaves routes to their own checks
+    pub access: Option<Arc<AccessPolicy>>,
+    /// Commit latency target reported by `/stats`
+    pub commit_sla: CommitSla,
+    /// Node key signing `/oracle` responses; `None` disables them
+    pub oracle: Option<Arc<OracleSigner>>,
+    /// Chain anchoring served under `/anchors`; `None` disables it
+    pub anchors: Option<Arc<Anchorer>>,
+    /// Ingestion health served on `/extraction` and reported by `/health`
+    pub extraction: Option<Arc<ExtractionTracker>>,
+    /// Transform counters and recent rejections served on `/transform`
+    pub transform: Option<Arc<TransformTracker>>,
+    /// Entries followers forward on `/forward`; `None` disables it
+    pub mempool: Option<Arc<Mempool>>,
+    /// Feature flags `/health` reports
+    pub features: Option<FeatureFlags>,
+    /// When blocks served by `/blocks` and `/head` count as final
+    pub finality: FinalityRule,
+    /// Protocol versions negotiated with peers, reported by `/health`
+    pub peer_versions: Arc<PeerVersions>,
+    /// Consensus event log, scrubbed when a block is redacted
+    pub event_log: Option<Arc<EventLog>>,
+}
+
+impl ServerContext {
+    pub fn new(handler: Arc<NetworkHandler>) -> Self {
+        ServerContext {
+            handler,
+            clock: None,
+            db: None,
+            control: None,
+            admin_token: None,
+            membership: None,
+            verifier: None,
+            storage: None,
+            accounts: None,
+            tenants: None,
+            access: None,
+            commit_sla: CommitSla::default(),
+            oracle: None,
+            anchors: None,
+            extraction: None,
+            transform: None,
+            mempool: None,
+            features: None,
+            finality: FinalityRule::default(),
+            peer_versions: Arc::new(PeerVersions::default()),
+            event_log: None,
+        }
+    }
+
+    pub fn with_clock_monitor(mut self, clock: Arc<ClockSkewMonitor>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn with_database(mut self, db: Arc<DatabaseManager>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    pub fn with_admin(mut self, control: Arc<NodeControl>, token: Option<String>) -> Self {
+        self.control = Some(control);
+        self.admin_token = token.filter(|t| !t.is_empty());
+        self
+    }
+
+    pub fn with_membership(mut self, membership: Arc<ClusterMembership>) -> Self {
+        self.membership = Some(membership);
+        self
+    }
+
+    pub fn with_verifier(mut self, verifier: Arc<RollingVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    pub fn with_storage_guard(mut self, storage: Arc<StorageGuard>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn with_accounts(mut self, accounts: Arc<AccountBook>) -> Self {
+        self.accounts = Some(accounts);
+        self
+    }
+
+    pub fn with_tenants(mut self, tenants: Arc<TenantRegistry>) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    pub fn with_access_policy(mut self, access: Arc<AccessPolicy>) -> Self {
+        self.access = Some(access);
+        self
+    }
+
+    pub fn with_commit_sla(mut self, commit_sla: CommitSla) -> Self {
+        self.commit_sla = commit_sla;
+        self
+    }
+
+    pub fn with_event_log(mut self, event_log: Arc<EventLog>) -> Self {
+        self.event_log = Some(event_log);
+        self
+    }
+
+    pub fn with_oracle(mut self, oracle: Arc<OracleSigner>) -> Self {
+        self.oracle = Some(oracle);
+        self
+    }
+
+    pub fn with_anchorer(mut self, anchors: Arc<Anchorer>) -> Self {
+        self.anchors = Some(anchors);
+        self
+    }
+
+    pub fn with_extraction_tracker(mut self, tracker: Arc<ExtractionTracker>) -> Self {
+        self.extraction = Some(tracker);
+        self
+    }
+
+    pub fn with_transform_tracker(mut self, tracker: Arc<TransformTracker>) -> Self {
+        self.transform = Some(tracker);
+        self
+    }
+
+    pub fn with_mempool(mut self, mempool: Arc<Mempool>) -> Self {
+        self.mempool = Some(mempool);
+        self
+    }
+
+    pub fn with_features(mut self, features: FeatureFlags) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    pub fn with_finality(mut self, finality: FinalityRule) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    /// Report the versions the node's outgoing messages negotiated
+    pub fn with_peer_versions(mut self, peer_versions: Arc<PeerVersions>) -> Self {
+        self.peer_versions = peer_versions;
+        self
+    }
 }
 
 async fn receive_message(
-    msg: web::Json<PBFTMessage>,
-    handler: web::Data<Arc<NetworkHandler>>,
+    req: HttpRequest,
+    body: Bytes,
+    context: web::Data<ServerContext>,
 ) -> impl Responder {
-    let result = (handler.on_message)(msg.into_inner());
-    HttpResponse::Ok().json(json!({
-        "status": if result { "accepted" } else { "pending" },
-        "quorum_reached": result
-    }))
+    let mut response = match protocol::decode_message(&body) {
+        Ok(Decoded::Message(msg)) => {
+            let (sender, msg_type) = (msg.node_id, format!("{:?}", msg.msg_type));
+            let response = handle_message(&req, &body, msg, &context).await;
+            if response.status().is_success() {
+                if let Some(peer) = authenticated_peer(&req, sender, &context) {
+                    MESSAGE_FLOW.record_received(&peer, &msg_type, body.len());
+                }
+            }
+            response
+        }
+        Ok(Decoded::Skipped { version, reason }) => {
+            warn!(
+                protocol_version = version,
+                reason = %reason,
+                "Protocol: Skipped message from a newer protocol version"
+            );
+            HttpResponse::Accepted().json(json!({ "status": "skipped" }))
+        }
+        Err(e @ DecodeError::Invalid(_)) => {
+            HttpResponse::BadRequest().json(json!({ "error": e.to_string() }))
+        }
+        Err(e @ DecodeError::Unsupported { .. }) => {
+            HttpResponse::build(StatusCode::UPGRADE_REQUIRED).json(json!({
+                "error": e.to_string(),
+                "protocol_version": PROTOCOL_VERSION,
+                "min_protocol_version": protocol::MIN_PROTOCOL_VERSION,
+            }))
+        }
+    };
+    response.headers_mut().insert(
+        HeaderName::from_static("x-protocol-version"),
+        HeaderValue::from(PROTOCOL_VERSION),
+    );
+    response
+}
+
+/// Who sent an accepted message: the allowlisted address of `node_id`,
+/// which `handle_message` authorized, or without an allowlist the
+/// connection's IP, since the node id is only what the sender claims
+fn authenticated_peer(
+    req: &HttpRequest,
+    node_id: usize,
+    context: &ServerContext,
+) -> Option<String> {
+    match &context.membership {
+        Some(membership) => membership.get(node_id).map(|peer| peer.address.clone()),
+        None => req.peer_addr().map(|addr| addr.ip().to_string()),
+    }
+}
+
+async fn handle_message(
+    req: &HttpRequest,
+    body: &[u8],
+    msg: PBFTMessage,
+    context: &ServerContext,
+) -> HttpResponse {
+    if let Some(membership) = &context.membership {
+        let remote_ip = req.peer_addr().map(|addr| addr.ip());
+        let request = PeerRequest::new(req, body);
+        if let Err(reason) = membership.authorize(msg.node_id, remote_ip, &request) {
+            warn!(
+                node_id = msg.node_id,
+                remote = ?remote_ip,
+                reason = %reason,
+                "Network: Rejected message from non-member"
+            );
+            return HttpResponse::Forbidden().json(json!({ "error": reason }));
+        }
+    }
+
+    let outcome = context.handler.handle(msg).await;
+    HttpResponse::Ok().json(outcome.to_json(context.handler.algorithm()))
+}
+
+/// Liveness plus this node's clock, which peers use to measure skew, and
+/// the rolling verifier's `last_verified_at` and storage guardrail state when
+/// they run
+async fn health(context: web::Data<ServerContext>) -> impl Responder {
+    let mut body = json!({
+        "status": "healthy",
+        "node_time_ms": now_millis(),
+    });
+
+    if let Some(clock) = &context.clock {
+        body["clock"] = json!({
+            "max_skew_ms": clock.max_skew_ms(),
+            "peers": clock.snapshot(),
+        });
+    }
+
+    if let Some(verifier) = &context.verifier {
+        body["verification"] = json!(verifier.status());
+    }
+
+    if let Some(features) = &context.features {
+        body["features"] = json!(features.report());
+    }
+
+    body["protocol"] = json!({
+        "version": PROTOCOL_VERSION,
+        "min_supported": protocol::MIN_PROTOCOL_VERSION,
+        "peers": context.peer_versions.snapshot(),
+    });
+
+    if let Some(storage) = &context.storage {
+        let health = storage.health();
+        if health.state == StorageState::Critical {
+            body["status"] = json!("degraded");
+        }
+        body["storage"] = json!(health);
+    }
+
+    if let Some(tracker) = &context.extraction {
+        let status = tracker.status();
+        if !status.healthy {
+            body["status"] = json!("degraded");
+        }
+        body["extraction"] = json!({
+            "healthy": status.healthy,
+            "last_success_ms": status.last_success_ms,
+            "consecutive_failures": status.consecutive_failures,
+        });
+    }
+
+    HttpResponse::Ok().json(body)
+}
+
+/// Per-source ingestion health; see `etl::extract_status`
+async fn extraction(context: web::Data<ServerContext>) -> impl Responder {
+    match &context.extraction {
+        Some(tracker) => HttpResponse::Ok().json(tracker.status()),
+        None => HttpResponse::NotFound().json(json!({
+            "error": "this node does not extract market data"
+        })),
+    }
+}
+
+/// What the transform pipeline did with the quotes; see
+/// `etl::transform_stats`
+async fn transform(context: web::Data<ServerContext>) -> impl Responder {
+    match &context.transform {
+        Some(tracker) => HttpResponse::Ok().json(tracker.stats()),
+        None => HttpResponse::NotFound().json(json!({
+            "error": "this node does not transform market data"
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+struct BlocksQuery {
+    from: u64,
+    limit: Option<u64>,
+}
+
+/// Response header carrying the highest final index when a page was served
+pub const FINALIZED_HEIGHT_HEADER: &str = "X-Finalized-Height";
+
+/// A served block with its finality; peers syncing through `/blocks`
+/// ignore the extra field
+#[derive(Serialize)]
+struct BlockWithFinality<'a> {
+    #[serde(flatten)]
+    block: &'a Block,
+    finality: Finality,
+}
+
+/// Blocks starting at index `from`, in ascending order, for peers syncing
+/// their chain; paged as described in `pagination`, each with its finality
+async fn blocks(
+    req: HttpRequest,
+    query: web::Query<BlocksQuery>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    let page = match Page::parse(Some(query.from), query.limit) {
+        Ok(page) => page,
+        Err(e) => return pagination::bad_request(e),
+    };
+
+    match db
+        .get_stats()
+        .and_then(|stats| Ok((db.get_blocks_range(page.from, page.end())?, stats)))
+    {
+        Ok((blocks, stats)) => {
+            let head = stats.max_index.unwrap_or(0);
+            let mut response = HttpResponse::Ok();
+            page.headers(&mut response, req.path(), &stats);
+            response.insert_header((
+                FINALIZED_HEIGHT_HEADER,
+                context.finality.finalized_height(head),
+            ));
+            response.json(
+                blocks
+                    .iter()
+                    .map(|block| BlockWithFinality {
+                        block,
+                        finality: context.finality.of(block.index, head),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// The latest committed block and how far behind it blocks are final
+async fn head(context: web::Data<ServerContext>) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    match db
+        .get_latest_block()
+        .and_then(|head| Ok((head, db.get_block_count()?)))
+    {
+        Ok((Some(head), block_count)) => HttpResponse::Ok().json(json!({
+            "index": head.index,
+            "hash": head.hash,
+            "timestamp": head.timestamp,
+            "block_count": block_count,
+            "finality": context.finality.describe(),
+            "finalized_height": context.finality.finalized_height(head.index),
+        })),
+        Ok((None, _)) => HttpResponse::NotFound().json(json!({ "error": "ledger is empty" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    window: Option<u64>,
 }
 
-async fn health() -> impl Responder {
-    HttpResponse::Ok().json(json!({"status": "healthy"}))
+/// Ledger size plus commit latency percentiles over the last `window` blocks
+async fn stats(query: web::Query<StatsQuery>, context: web::Data<ServerContext>) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    let window = query.window.unwrap_or(sla::DEFAULT_REPORT_WINDOW).max(1);
+    let report = db
+        .get_stats()
+        .and_then(|blocks| Ok((blocks, context.commit_sla.report(db, window)?)));
+    match report {
+        Ok((blocks, latency)) => HttpResponse::Ok().json(json!({
+            "blocks": blocks,
+            "commit_latency": latency,
+            "block_cache": db.block_cache_stats(),
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+struct TopologyQuery {
+    format: Option<String>,
+}
+
+/// This node's peers and message-flow counters; see `topology`
+async fn topology(query: web::Query<TopologyQuery>) -> impl Responder {
+    let snapshot = MESSAGE_FLOW.snapshot();
+    match query.format.as_deref() {
+        None | Some("json") => HttpResponse::Ok().json(snapshot),
+        Some("dot") => {
+            let address = snapshot
+                .address
+                .clone()
+                .unwrap_or_else(|| "local".to_string());
+            HttpResponse::Ok()
+                .content_type("text/vnd.graphviz")
+                .body(ClusterGraph::build(&[(address, Ok(snapshot))]).to_dot())
+        }
+        Some(other) => HttpResponse::BadRequest().json(json!({
+            "error": format!("unknown format '{}' (expected json or dot)", other)
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+struct MetricsQuery {
+    format: Option<String>,
 }
 
-pub async fn start_server(port: u16, handler: Arc<NetworkHandler>) -> std::io::Result<()> {
-    let handler_data = web::Data::new(handler);
+/// This process's memory, CPU and file-descriptor usage; see
+/// `system_metrics`
+async fn metrics(query: web::Query<MetricsQuery>) -> impl Responder {
+    let sample = system_metrics::sample();
+    match query.format.as_deref() {
+        None | Some("json") => HttpResponse::Ok().json(json!({ "system": sample })),
+        Some("prometheus") => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(sample.to_prometheus()),
+        Some(other) => HttpResponse::BadRequest().json(json!({
+            "error": format!("unknown format '{}' (expected json or prometheus)", other)
+        })),
+    }
+}
 
-    info!(port = port, "Network: Starting HTTP server");
+#[derive(Deserialize)]
+struct AnalyticsQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+}
 
-    HttpServer::new(move || {
+/// Per-day block counts, source mix and daily prices for blocks between
+/// `from` and `to` (milliseconds, inclusive)
+async fn analytics(
+    query: web::Query<AnalyticsQuery>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    let range = AnalyticsRange {
+        from_timestamp: query.from,
+        to_timestamp: query.to,
+    };
+    match db.get_analytics(&range) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// Balance and usage of every submitter
+async fn accounts(context: web::Data<ServerContext>) -> impl Responder {
+    match &context.accounts {
+        Some(book) => HttpResponse::Ok().json(json!({
+            "fee_per_entry": book.schedule().fee_per_entry,
+            "accounts": book.accounts(),
+        })),
+        None => HttpResponse::NotFound().json(json!({
+            "error": "fee accounting is not enabled on this node"
+        })),
+    }
+}
+
+/// Balance and usage of one submitter
+async fn account(path: web::Path<String>, context: web::Data<ServerContext>) -> impl Responder {
+    let Some(book) = &context.accounts else {
+        return HttpResponse::NotFound().json(json!({
+            "error": "fee accounting is not enabled on this node"
+        }));
+    };
+    match book.account(&path) {
+        Some(account) => HttpResponse::Ok().json(account),
+        None => HttpResponse::NotFound().json(json!({
+            "error": format!("no account for submitter '{}'", path.as_str())
+        })),
+    }
+}
+
+/// Run each request in a span tagged with its `X-Trace-Id`, generating one
+/// when the caller sent none, and echo the id on the response
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let trace_id = req
+        .headers()
+        .get(TRACE_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 64)
+        .map(String::from)
+        .unwrap_or_else(crate::logger::new_trace_id);
+    let span = info_span!(
+        "request",
+        trace_id = %trace_id,
+        method = %req.method(),
+        path = %req.path()
+    );
+    let mut res = next.call(req).instrument(span).await?;
+    if let Ok(value) = HeaderValue::from_str(&trace_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static("x-trace-id"), value);
+    }
+    Ok(res)
+}
+
+/// Register every route a node serves; shared by `start_server` and
+/// in-process test apps
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/message", web::post().to(receive_message))
+        .route("/health", web::get().to(health))
+        .route("/healthz", web::get().to(health))
+        .route("/blocks", web::get().to(blocks))
+        .route("/head", web::get().to(head))
+        .route("/stats", web::get().to(stats))
+        .route("/analytics", web::get().to(analytics))
+        .route("/topology", web::get().to(topology))
+        .route("/metrics", web::get().to(metrics))
+        .route("/extraction", web::get().to(extraction))
+        .route("/transform", web::get().to(transform))
+        .route("/forward", web::post().to(forwarding::receive))
+        .route("/oracle/price/{asset}", web::get().to(oracle::price))
+        .route("/oracle/key", web::get().to(oracle::key))
+        .route("/attestations", web::get().to(attestation::list))
+        .route("/anchors", web::get().to(anchor::list))
+        .route("/anchors/{id}/verify", web::get().to(anchor::verify))
+        .route("/redactions", web::get().to(redaction::list))
+        .route("/accounts", web::get().to(accounts))
+        .route("/accounts/{submitter}", web::get().to(account))
+        .route("/tenant/submit", web::post().to(tenancy::submit))
+        .route("/tenant/blocks", web::get().to(tenancy::blocks))
+        .route("/tenant/stats", web::get().to(tenancy::stats))
+        .route("/admin/status", web::get().to(admin::status))
+        .route("/admin/pause", web::post().to(admin::pause))
+        .route("/admin/resume", web::post().to(admin::resume))
+        .route("/admin/reconfigure", web::post().to(admin::reconfigure))
+        .route("/admin/denials", web::get().to(rbac::denials))
+        .route(
+            "/admin/redact/{index}",
+            web::post().to(redaction::redact_block),
+        );
+}
+
+pub async fn start_server(addr: SocketAddr, context: ServerContext) -> std::io::Result<()> {
+    bind_server(addr, context)?.await
+}
+
+/// Bind the node's HTTP server without waiting for it to stop, so a caller
+/// learns the port is taken before reporting the node ready
+///
+/// `addr` may be IPv6, e.g. `[::]:8000`; see `peer_addr::bind_ip_from_env`.
+pub fn bind_server(addr: SocketAddr, context: ServerContext) -> std::io::Result<Server> {
+    let context_data = web::Data::new(context);
+
+    info!(address = %addr, "Network: Starting HTTP server");
+
+    Ok(HttpServer::new(move || {
         App::new()
-            .app_data(handler_data.clone())
-            .route("/message", web::post().to(receive_message))
-            .route("/health", web::get().to(health))
+            .app_data(context_data.clone())
+            .wrap(from_fn(rbac::enforce))
+            .wrap(from_fn(trace_requests))
+            .configure(configure_routes)
     })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await
+    .bind(addr)?
+    .run())
+}
+
+/// Run the HTTP server on its own thread and actix system, returning once
+/// it is bound or has failed to bind
+pub async fn spawn_server(addr: SocketAddr, context: ServerContext) -> std::io::Result<()> {
+    let (bound_tx, bound_rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        actix_rt::System::new().block_on(async {
+            match bind_server(addr, context) {
+                Ok(server) => {
+                    let _ = bound_tx.send(Ok(()));
+                    let _ = server.await;
+                }
+                Err(e) => {
+                    let _ = bound_tx.send(Err(e));
+                }
+            }
+        });
+    });
+    bound_rx.await.unwrap_or_else(|_| {
+        Err(std::io::Error::other(
+            "HTTP server thread exited before binding",
+        ))
+    })
+}
+
+/// Serialize a message once so the same buffer can be sent to every peer
+///
+/// Cloning the returned `Bytes` only bumps a reference count, so large
+/// pre-prepare payloads are neither re-serialized nor copied per peer.
+pub fn encode_message(message: &PBFTMessage) -> Result<Bytes, serde_json::Error> {
+    serde_json::to_vec(message).map(Bytes::from)
+}
+
+fn default_peer_retry_policy() -> RetryPolicy {
+    RetryPolicy::new(3, Duration::from_millis(100)).with_max_delay(Duration::from_secs(1))
+}
+
+/// Retry policy for requests to peers: consensus messages and chain sync
+pub fn peer_retry_policy() -> Result<RetryPolicy, String> {
+    default_peer_retry_policy().with_env_overrides("NETWORK")
+}
+
+/// `peer_retry_policy`, which the node checks at startup; the defaults if it
+/// does not parse
+static PEER_RETRY: LazyLock<RetryPolicy> =
+    LazyLock::new(|| peer_retry_policy().unwrap_or_else(|_| default_peer_retry_policy()));
+
+/// POST an already-encoded message to a peer's `/message` route, recording
+/// the version the peer answers with in `versions`
+pub async fn send_payload(
+    client: &reqwest::Client,
+    versions: &PeerVersions,
+    url: &str,
+    payload: Bytes,
+    trace_id: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let send = || {
+        let mut request = client
+            .post(format!("http://{}/message", url))
+            .header(CONTENT_TYPE, "application/json")
+            .header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION);
+        if let Some(trace_id) = trace_id {
+            request = request.header(TRACE_ID_HEADER, trace_id);
+        }
+        // Signed per attempt, so a retry is not refused as stale
+        for (name, value) in membership::sign_request("/message", &payload) {
+            request = request.header(name, value);
+        }
+        let request = request.body(payload.clone());
+        async move {
+            let response = request.send().await?;
+            // The first response settles the protocol version with this peer
+            versions.observe(
+                url,
+                protocol::header_version(
+                    response
+                        .headers()
+                        .get(PROTOCOL_VERSION_HEADER)
+                        .and_then(|v| v.to_str().ok()),
+                ),
+            );
+            response.error_for_status().map(|_| ())
+        }
+    };
+    // A peer that rejects us (e.g. 403 from membership checks) is not retried
+    let started = Instant::now();
+    let mut last_attempt = started;
+    let result = PEER_RETRY
+        .run(
+            |_| {
+                last_attempt = Instant::now();
+                send()
+            },
+            classify_reqwest,
+        )
+        .await;
+    match &result {
+        Ok(()) => MESSAGE_FLOW.record_sent(url, payload.len(), last_attempt.elapsed()),
+        Err(_) => MESSAGE_FLOW.record_failed(url),
+    }
+    result.map_err(Into::into)
 }
 
 pub async fn send_message(
+    versions: &PeerVersions,
     url: &str,
     message: &PBFTMessage,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post(&format!("http://{}/message", url))
-        .json(message)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(format!("HTTP error: {}", response.status()).into())
-    }
+    send_payload(
+        &reqwest::Client::new(),
+        versions,
+        url,
+        protocol::encode_for(message, versions.version_for(url))?,
+        message.trace_id.as_deref(),
+    )
+    .await
 }
 
+/// Send `message` to every node except ourselves, serializing it once per
+/// negotiated protocol version and sending to all peers at the same time
 pub async fn broadcast_message(
     message: &PBFTMessage,
     node_addresses: &[String],
-    current_node_port: u16,
+    local: &PeerAddr,
+    versions: &PeerVersions,
 ) {
-    for addr in node_addresses {
-        if let Some(port_str) = addr.split(':').last() {
-            if let Ok(port) = port_str.parse::<u16>() {
-                if port == current_node_port {
-                    continue;
-                }
+    let payloads = match versions.encode_for_peers(message, peers_excluding(node_addresses, local))
+    {
+        Ok(payloads) => payloads,
+        Err(e) => {
+            warn!(error = %e, "Network: Failed to encode message");
+            return;
+        }
+    };
+    let client = reqwest::Client::new();
+
+    // Concurrently, so a peer being retried does not hold up the others
+    join_all(payloads.into_iter().map(|(addr, payload)| {
+        let client = &client;
+        async move {
+            if let Err(e) =
+                send_payload(client, versions, addr, payload, message.trace_id.as_deref()).await
+            {
+                warn!(address = %addr, error = %e, "Network: Failed to send message");
             }
         }
+    }))
+    .await;
+}
+
+/// Every address in `node_addresses` except the local node's
+pub fn peers_excluding<'a>(
+    node_addresses: &'a [String],
+    local: &'a PeerAddr,
+) -> impl Iterator<Item = &'a String> {
+    node_addresses
+        .iter()
+        .filter(move |addr| !peer_addr::is_local(addr, local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::MessageType;
+    use crate::etl::price::Decimal;
+
+    fn test_message(node_id: usize) -> PBFTMessage {
+        PBFTMessage {
+            msg_type: MessageType::Prepare,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: None,
+            node_id,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_receive_message_rejects_non_members() {
+        let membership = ClusterMembership::parse("0@127.0.0.1:8000").unwrap();
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_membership(Arc::new(membership));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/message", web::post().to(receive_message)),
+        )
+        .await;
+
+        let send = |node_id: usize, from: &str| {
+            actix_web::test::TestRequest::post()
+                .uri("/message")
+                .peer_addr(from.parse().unwrap())
+                .set_json(test_message(node_id))
+                .to_request()
+        };
+
+        let member = actix_web::test::call_service(&app, send(0, "127.0.0.1:50000")).await;
+        assert!(member.status().is_success());
+
+        let unknown_id = actix_web::test::call_service(&app, send(5, "127.0.0.1:50000")).await;
+        assert_eq!(unknown_id.status(), 403);
+
+        let wrong_host = actix_web::test::call_service(&app, send(0, "10.1.2.3:50000")).await;
+        assert_eq!(wrong_host.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_receive_message_awaits_algorithm_handler() {
+        use crate::consensus::algorithms::gossip::GossipConsensus;
+
+        let gossip = Arc::new(GossipConsensus::new(0, 2, 2));
+        let handler = NetworkHandler::for_algorithm(gossip);
+        let context = ServerContext::new(Arc::new(handler));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/message", web::post().to(receive_message)),
+        )
+        .await;
+        let send = |node_id: usize| {
+            actix_web::test::TestRequest::post()
+                .uri("/message")
+                .set_json(test_message(node_id))
+                .to_request()
+        };
+
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, send(1)).await;
+        assert_eq!(body["algorithm"], "Gossip Protocol");
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["quorum_reached"], false);
+        assert_eq!(body["progress"]["phases"][0]["phase"], "gossip");
+        assert_eq!(body["progress"]["phases"][0]["votes"], 1.0);
+        assert_eq!(body["progress"]["phases"][0]["required"], 2.0);
+
+        let rejecting = NetworkHandler::new(|_| HandleOutcome::Rejected("paused".to_string()));
+        let outcome = rejecting.handle(test_message(1)).await;
+        assert_eq!(
+            outcome.to_json(rejecting.algorithm()),
+            json!({
+                "algorithm": "PBFT",
+                "status": "rejected",
+                "reason": "paused",
+                "quorum_reached": false,
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_receive_message_negotiates_protocol_version() {
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/message", web::post().to(receive_message)),
+        )
+        .await;
+        let send = |body: serde_json::Value| {
+            actix_web::test::TestRequest::post()
+                .uri("/message")
+                .set_json(body)
+                .to_request()
+        };
+
+        let current = actix_web::test::call_service(&app, send(json!(test_message(0)))).await;
+        assert!(current.status().is_success());
+        assert_eq!(
+            current.headers().get(PROTOCOL_VERSION_HEADER).unwrap(),
+            &PROTOCOL_VERSION.to_string()
+        );
+
+        let mut newer = json!(test_message(0));
+        newer["protocol_version"] = json!(PROTOCOL_VERSION + 1);
+        newer["msg_type"] = json!("ViewChange");
+        let accepted = actix_web::test::call_service(&app, send(newer.clone())).await;
+        assert!(accepted.status().is_success());
+        newer["msg_type"] = json!("Checkpoint");
+        let skipped = actix_web::test::call_service(&app, send(newer)).await;
+        assert_eq!(skipped.status(), 202);
+
+        let mut retired = json!(test_message(0));
+        retired["protocol_version"] = json!(0);
+        let refused = actix_web::test::call_service(&app, send(retired)).await;
+        assert_eq!(refused.status(), 426);
+
+        let garbage = actix_web::test::call_service(&app, send(json!({ "x": 1 }))).await;
+        assert_eq!(garbage.status(), 400);
+    }
 
-        if let Err(e) = send_message(addr, message).await {
-            warn!(address = %addr, error = %e, "Network: Failed to send message");
+    #[actix_web::test]
+    async fn test_accounts_report_usage() {
+        use crate::etl::accounting::{AccountBook, FeeRecord, FeeSchedule};
+
+        let path = "test_accounts_route.db";
+        std::fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+        let book = AccountBook::open(db, FeeSchedule::new(3)).unwrap();
+        book.credit("CoinGecko", 10).unwrap();
+        book.settle(&[FeeRecord {
+            submitter: "CoinGecko".to_string(),
+            entries: 2,
+            amount: 6,
+        }])
+        .unwrap();
+
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_accounts(Arc::new(book));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/accounts", web::get().to(accounts))
+                .route("/accounts/{submitter}", web::get().to(account)),
+        )
+        .await;
+
+        let get = |uri: &str| actix_web::test::TestRequest::get().uri(uri).to_request();
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/accounts")).await;
+        assert_eq!(body["fee_per_entry"], 3);
+        assert_eq!(body["accounts"][0]["submitter"], "CoinGecko");
+
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/accounts/CoinGecko")).await;
+        assert_eq!(body["balance"], 4);
+        assert_eq!(body["entries"], 2);
+        assert_eq!(body["fees_paid"], 6);
+
+        let missing = actix_web::test::call_service(&app, get("/accounts/Nobody")).await;
+        assert_eq!(missing.status(), 404);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_stats_reports_commit_latency() {
+        use crate::etl::load::CommitLatency;
+
+        let path = "test_stats_route.db";
+        std::fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+        for (index, latency_ms) in [(1, 200), (2, 400), (3, 3000)] {
+            db.save_commit_latency(&CommitLatency {
+                block_index: index,
+                observed_at: 1_000_000,
+                committed_at: 1_000_000 + latency_ms,
+            })
+            .unwrap();
         }
+
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_database(db)
+            .with_commit_sla(CommitSla::new(1000));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/stats", web::get().to(stats)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/stats")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        let latency = &body["commit_latency"];
+        assert_eq!(latency["blocks"], 3);
+        assert_eq!(latency["p50_ms"], 400);
+        assert_eq!(latency["max_ms"], 3000);
+        assert_eq!(latency["sla_ms"], 1000);
+
+        // Only the newest block is in a window of one
+        let req = actix_web::test::TestRequest::get()
+            .uri("/stats?window=1")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["commit_latency"]["p99_ms"], 3000);
+        assert_eq!(body["commit_latency"]["within_sla"], 0.0);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_route_formats() {
+        let app =
+            actix_web::test::init_service(App::new().route("/metrics", web::get().to(metrics)))
+                .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/metrics")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert!(body["system"]["rss_bytes"].as_u64().unwrap_or_default() > 0);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/metrics?format=prometheus")
+            .to_request();
+        let body = actix_web::test::call_and_read_body(&app, req).await;
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("process_resident_memory_bytes "), "{}", text);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/metrics?format=xml")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_analytics_route_filters_by_range() {
+        use crate::testing;
+
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+        for (index, timestamp) in [(1, 1_700_000_000_000), (2, 1_700_000_100_000)] {
+            let price = Decimal::from(100) * Decimal::from(index);
+            let entry = testing::entry("BTC", "Test", price, timestamp);
+            db.save_block(&testing::block(index, &index.to_string(), vec![entry]))
+                .unwrap();
+        }
+
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true))).with_database(db);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/analytics", web::get().to(analytics)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/analytics")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total_blocks"], 2);
+        assert_eq!(body["daily_prices"][0]["avg_price"], 150.0);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/analytics?from=1700000050000")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total_blocks"], 1);
+        assert_eq!(body["range"]["from_timestamp"], 1_700_000_050_000_i64);
+        assert_eq!(body["sources"][0]["share"], 1.0);
+    }
+
+    #[actix_web::test]
+    async fn test_blocks_route_pages_with_links() {
+        use crate::testing::{TestChainBuilder, TestNode};
+        use actix_web::test::TestRequest;
+
+        let node = TestNode::with_chain(&TestChainBuilder::new().with_blocks(25)).unwrap();
+        let res = node
+            .call(TestRequest::get().uri("/blocks?from=11&limit=10"))
+            .await;
+        assert!(res.status().is_success());
+        assert_eq!(
+            res.headers().get(pagination::TOTAL_COUNT_HEADER).unwrap(),
+            "25"
+        );
+        let links = res.headers().get("Link").unwrap().to_str().unwrap();
+        assert!(
+            links.contains("</blocks?from=21&limit=10>; rel=\"next\""),
+            "{}",
+            links
+        );
+        assert!(
+            links.contains("</blocks?from=16&limit=10>; rel=\"last\""),
+            "{}",
+            links
+        );
+        let page: Vec<crate::etl::Block> = actix_web::test::read_body_json(res).await;
+        assert_eq!(page.first().map(|b| b.index), Some(11));
+        assert_eq!(page.len(), 10);
+
+        // Oversized pages are refused instead of truncated
+        let res = node
+            .call(TestRequest::get().uri("/blocks?from=1&limit=1000"))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_blocks_and_head_report_finality() {
+        use crate::testing::{TestChainBuilder, TestNode};
+        use actix_web::test::TestRequest;
+
+        let node = TestNode::with_chain(&TestChainBuilder::new().with_blocks(10))
+            .unwrap()
+            .with_context(|context| context.with_finality(FinalityRule::Depth(6)));
+        let res = node
+            .call(TestRequest::get().uri("/blocks?from=3&limit=3"))
+            .await;
+        assert_eq!(res.headers().get(FINALIZED_HEIGHT_HEADER).unwrap(), "4");
+        let page: Vec<serde_json::Value> = actix_web::test::read_body_json(res).await;
+        let statuses: Vec<_> = page.iter().map(|b| &b["finality"]["status"]).collect();
+        assert_eq!(statuses, ["finalized", "finalized", "committed"]);
+        assert_eq!(page[2]["finality"]["confirmations"], 5);
+        // Peers syncing the page still read plain blocks
+        let block: Block = serde_json::from_value(page[0].clone()).unwrap();
+        assert_eq!(block.index, 3);
+
+        let head = node.get_json("/head").await;
+        assert_eq!(head["index"], 10);
+        assert_eq!(head["finalized_height"], 4);
+        assert_eq!(head["finality"], "depth:6");
+    }
+
+    #[actix_web::test]
+    async fn test_extraction_status_route() {
+        let tracker = Arc::new(ExtractionTracker::new().with_failure_threshold(1));
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_extraction_tracker(tracker.clone());
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/health", web::get().to(health))
+                .route("/extraction", web::get().to(extraction)),
+        )
+        .await;
+        let get = |uri: &str| actix_web::test::TestRequest::get().uri(uri).to_request();
+
+        tracker.record_success("CoinGecko", Some(Duration::from_millis(120)));
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/extraction")).await;
+        assert_eq!(body["healthy"], true);
+        assert_eq!(body["sources"][0]["source"], "CoinGecko");
+        assert_eq!(body["sources"][0]["last_latency_ms"], 120);
+
+        tracker.record_failure("CoinGecko", Duration::from_secs(2), "timeout");
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/health")).await;
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["extraction"]["consecutive_failures"], 1);
+    }
+
+    #[actix_web::test]
+    async fn test_transform_stats_route() {
+        use crate::etl::transform::Transformer;
+        let tracker = Arc::new(TransformTracker::new());
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_transform_tracker(tracker.clone());
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/transform", web::get().to(transform)),
+        )
+        .await;
+
+        let pipeline = Transformer::new().with_tracker(tracker).pipeline();
+        let now = crate::etl::now_millis();
+        assert!(pipeline
+            .run("BTC", Decimal::from(50_000), now, "CoinGecko".into(), None)
+            .is_ok());
+        assert!(pipeline
+            .run("BTC", Decimal::from(-1), now, "CoinGecko".into(), None)
+            .is_err());
+        let request = actix_web::test::TestRequest::get()
+            .uri("/transform")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["processed"], 2);
+        assert_eq!(body["validations_failed"], 1);
+        assert_eq!(body["normalizations_applied"], 1);
+        assert_eq!(body["recent_rejections"][0]["source"], "CoinGecko");
+    }
+
+    #[actix_web::test]
+    async fn test_health_reports_feature_flags() {
+        use crate::features::Feature;
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_features(FeatureFlags::all().without(Feature::Sharding));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .configure(configure_routes),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/healthz")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["features"]["sharding"], false);
+        assert_eq!(body["features"]["anchoring"], true);
+    }
+
+    #[actix_web::test]
+    async fn test_requests_carry_trace_ids() {
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .wrap(from_fn(trace_requests))
+                .route("/health", web::get().to(health)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/health")
+            .insert_header((TRACE_ID_HEADER, "round-42"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(TRACE_ID_HEADER).unwrap(), "round-42");
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/health")
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        let generated = res
+            .headers()
+            .get(TRACE_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(generated.len(), 16);
+
+        // The id travels inside consensus messages and is optional on the wire
+        let message = test_message(1).with_trace_id("round-42");
+        let encoded = encode_message(&message).unwrap();
+        let decoded: PBFTMessage = serde_json::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.trace_id.as_deref(), Some("round-42"));
+        let untraced = encode_message(&test_message(1)).unwrap();
+        assert!(!String::from_utf8_lossy(&untraced).contains("trace_id"));
+    }
+
+    #[test]
+    fn test_encoded_payload_is_shared_across_clones() {
+        let message = PBFTMessage {
+            msg_type: MessageType::PrePrepare,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: Some("{\"index\":1}".to_string()),
+            node_id: 0,
+            timestamp: 1_234_567_890_000,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+
+        let payload = encode_message(&message).unwrap();
+        let copy = payload.clone();
+        assert_eq!(payload.as_ptr(), copy.as_ptr());
+
+        let decoded: PBFTMessage = serde_json::from_slice(&copy).unwrap();
+        assert_eq!(decoded.sequence, 1);
+        assert_eq!(decoded.block_data_json.as_deref(), Some("{\"index\":1}"));
     }
 }
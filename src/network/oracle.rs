@@ -0,0 +1,274 @@
+//! Watch-only price oracle
+//!
+//! `GET /oracle/price/{asset}` returns the asset's latest committed price
+//! together with the block it was committed in, signed with the node's
+//! Ed25519 key. A consumer that trusts the node's public key (served on
+//! `GET /oracle/key`) can check the quote offline with `verify`, so the
+//! ledger can back contract-style integrations without trusting the
+//! transport.
+//!
+//! The signature covers `OracleQuote::signing_input`: a fixed binary layout
+//! (big-endian integers, length-prefixed UTF-8 strings, the price as
+//! `price::canonical_bytes`) tagged `rml-oracle-v2`. Version 1 signed the
+//! price's `f32` bits.
+//!
+//! Configured with `NODE_SIGNING_KEY`, the hex-encoded 32-byte Ed25519
+//! secret key; the routes are disabled without it.
+
+use actix_web::{web, HttpResponse, Responder};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::ServerContext;
+use crate::etl::price::{self, Decimal};
+
+/// The part of a quote covered by the signature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OracleQuote {
+    pub asset: String,
+    #[serde(with = "price::serde_number")]
+    pub price: Decimal,
+    /// When the price was observed (milliseconds)
+    pub timestamp: i64,
+    pub block_index: u64,
+    pub block_hash: String,
+    pub node_id: usize,
+}
+
+impl OracleQuote {
+    pub fn signing_input(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(96 + self.asset.len() + self.block_hash.len());
+        buf.extend_from_slice(b"rml-oracle-v2");
+        put_str(&mut buf, &self.asset);
+        buf.extend_from_slice(&price::canonical_bytes(self.price));
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.block_index.to_be_bytes());
+        put_str(&mut buf, &self.block_hash);
+        buf.extend_from_slice(&(self.node_id as u64).to_be_bytes());
+        buf
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u64).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Response of `/oracle/price/{asset}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedQuote {
+    #[serde(flatten)]
+    pub quote: OracleQuote,
+    /// Hex-encoded Ed25519 public key of the signing node
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature over `quote.signing_input()`
+    pub signature: String,
+}
+
+/// Check `signed` against the node key the consumer trusts (hex)
+pub fn verify(signed: &SignedQuote, trusted_public_key: &str) -> Result<(), String> {
+    verify_signature(
+        trusted_public_key,
+        &signed.quote.signing_input(),
+        &signed.signature,
+    )
+    .map_err(|_| "signature does not match the quote".to_string())
+}
+
+/// Check a hex Ed25519 `signature` over `message` against a hex public key
+pub fn verify_signature(public_key: &str, message: &[u8], signature: &str) -> Result<(), String> {
+    let key: [u8; 32] = decode_hex(public_key, "public key")?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|e| format!("invalid public key: {}", e))?;
+    let signature: [u8; 64] = decode_hex(signature, "signature")?;
+    key.verify(message, &Signature::from_bytes(&signature))
+        .map_err(|_| "signature does not match".to_string())
+}
+
+fn decode_hex<const N: usize>(value: &str, what: &str) -> Result<[u8; N], String> {
+    hex::decode(value.trim())
+        .map_err(|e| format!("invalid {} hex: {}", what, e))?
+        .try_into()
+        .map_err(|_| format!("{} must be {} bytes", what, N))
+}
+
+/// Signs quotes with the node's key
+pub struct OracleSigner {
+    node_id: usize,
+    key: SigningKey,
+}
+
+impl OracleSigner {
+    pub fn new(node_id: usize, secret_key: [u8; 32]) -> Self {
+        OracleSigner {
+            node_id,
+            key: SigningKey::from_bytes(&secret_key),
+        }
+    }
+
+    /// Read `NODE_SIGNING_KEY`; `Ok(None)` when it is not set
+    pub fn from_env(node_id: usize) -> Result<Option<Self>, String> {
+        match std::env::var("NODE_SIGNING_KEY") {
+            Ok(key) if !key.trim().is_empty() => {
+                let secret = decode_hex(&key, "NODE_SIGNING_KEY")?;
+                Ok(Some(OracleSigner::new(node_id, secret)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Hex-encoded public key consumers verify against
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().as_bytes())
+    }
+
+    pub fn node_id(&self) -> usize {
+        self.node_id
+    }
+
+    /// Hex-encoded signature over `message`
+    pub fn sign_bytes(&self, message: &[u8]) -> String {
+        hex::encode(self.key.sign(message).to_bytes())
+    }
+
+    pub fn sign(&self, quote: OracleQuote) -> SignedQuote {
+        let signature = self.sign_bytes(&quote.signing_input());
+        SignedQuote {
+            quote,
+            public_key: self.public_key(),
+            signature,
+        }
+    }
+
+    /// Latest committed price of `asset`, signed; `Ok(None)` when the
+    /// ledger has no entry for it
+    pub fn latest_quote(
+        &self,
+        db: &crate::etl::load::DatabaseManager,
+        asset: &str,
+    ) -> crate::etl::load::DbResult<Option<SignedQuote>> {
+        let Some(block) = db.get_latest_block_for_asset(asset)? else {
+            return Ok(None);
+        };
+        // A block may hold several entries for the asset; take the newest
+        let Some(entry) = block
+            .data
+            .iter()
+            .filter(|item| item.asset == asset)
+            .max_by_key(|item| crate::etl::timestamp_to_millis(item.timestamp))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(self.sign(OracleQuote {
+            asset: entry.asset.clone(),
+            price: entry.price,
+            timestamp: crate::etl::timestamp_to_millis(entry.timestamp),
+            block_index: block.index,
+            block_hash: block.hash.clone(),
+            node_id: self.node_id,
+        })))
+    }
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .json(json!({ "error": "oracle disabled (NODE_SIGNING_KEY not set)" }))
+}
+
+/// Latest committed price of an asset, signed by this node
+pub async fn price(path: web::Path<String>, context: web::Data<ServerContext>) -> impl Responder {
+    let Some(signer) = &context.oracle else {
+        return disabled();
+    };
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    let asset = path.into_inner();
+    match signer.latest_quote(db, &asset) {
+        Ok(Some(signed)) => HttpResponse::Ok().json(signed),
+        Ok(None) => HttpResponse::NotFound()
+            .json(json!({ "error": format!("no committed price for '{}'", asset) })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// The public key oracle responses are signed with
+pub async fn key(context: web::Data<ServerContext>) -> impl Responder {
+    match &context.oracle {
+        Some(signer) => HttpResponse::Ok().json(json!({
+            "algorithm": "ed25519",
+            "public_key": signer.public_key(),
+        })),
+        None => disabled(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::load::DatabaseManager;
+    use crate::etl::Block;
+    use crate::network::NetworkHandler;
+    use crate::testing;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    fn block(index: u64, price: Decimal) -> Block {
+        let timestamp = testing::BASE_TIMESTAMP_MS + index as i64;
+        let entry = testing::entry("BTC", "Test", price, timestamp);
+        testing::block(index, &index.to_string(), vec![entry])
+    }
+
+    #[actix_web::test]
+    async fn test_oracle_serves_verifiable_latest_price() {
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+        db.save_block(&block(1, Decimal::from(50_000))).unwrap();
+        let latest = block(2, price::parse("51000.25").unwrap());
+        db.save_block(&latest).unwrap();
+
+        let signer = Arc::new(OracleSigner::new(3, [7; 32]));
+        let public_key = signer.public_key();
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_database(db)
+            .with_oracle(signer);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/oracle/price/{asset}", web::get().to(price))
+                .route("/oracle/key", web::get().to(key)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/oracle/price/BTC")
+            .to_request();
+        let signed: SignedQuote = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(signed.quote.price, price::parse("51000.25").unwrap());
+        assert_eq!(signed.quote.block_index, 2);
+        assert_eq!(signed.quote.block_hash, latest.hash);
+        assert_eq!(signed.quote.node_id, 3);
+        assert!(verify(&signed, &public_key).is_ok());
+
+        // Any change to the quote breaks the signature
+        let mut forged = signed.clone();
+        forged.quote.price = Decimal::ONE;
+        assert!(verify(&forged, &public_key).is_err());
+        let other_key = OracleSigner::new(3, [8; 32]).public_key();
+        assert!(verify(&signed, &other_key).is_err());
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/oracle/key")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["public_key"], public_key);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/oracle/price/ETH")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+    }
+}
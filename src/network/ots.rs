@@ -0,0 +1,351 @@
+//! OpenTimestamps proof encoding
+//!
+//! A proof is a tree of operations applied to a starting message (here, a
+//! block hash). Each path ends in an attestation: a calendar's promise to
+//! include the result in Bitcoin (pending), or a Bitcoin block whose merkle
+//! root equals the result. This module reads and writes the binary timestamp
+//! format that calendars return from `POST /digest` and
+//! `GET /timestamp/{commitment}`, evaluates it from the starting message, and
+//! splices in the upgrade a calendar returns once the Bitcoin transaction is
+//! confirmed.
+//!
+//! Only the operations calendars emit are supported (sha256, append,
+//! prepend, reverse, hexlify); a proof using any other is rejected.
+
+use sha2::{Digest, Sha256};
+
+/// Nesting limit, far above what calendars produce
+const MAX_DEPTH: usize = 256;
+/// Longest argument or payload accepted
+const MAX_VARBYTES: usize = 8192;
+
+const TAG_ATTESTATION: u8 = 0x00;
+const TAG_FORK: u8 = 0xff;
+const TAG_SHA256: u8 = 0x08;
+const TAG_APPEND: u8 = 0xf0;
+const TAG_PREPEND: u8 = 0xf1;
+const TAG_REVERSE: u8 = 0xf2;
+const TAG_HEXLIFY: u8 = 0xf3;
+
+const PENDING_TAG: [u8; 8] = [0x83, 0xdf, 0xe3, 0x0d, 0x2e, 0xf9, 0x0c, 0x8e];
+const BITCOIN_TAG: [u8; 8] = [0x05, 0x88, 0x96, 0x0d, 0x73, 0xd7, 0x19, 0x01];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Sha256,
+    Append(Vec<u8>),
+    Prepend(Vec<u8>),
+    Reverse,
+    Hexlify,
+}
+
+impl Op {
+    pub fn apply(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            Op::Sha256 => Sha256::digest(msg).to_vec(),
+            Op::Append(arg) => [msg, arg].concat(),
+            Op::Prepend(arg) => [arg, msg].concat(),
+            Op::Reverse => msg.iter().rev().copied().collect(),
+            Op::Hexlify => hex::encode(msg).into_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attestation {
+    /// The calendar at `uri` will commit the message to Bitcoin
+    Pending { uri: String },
+    /// The message is the merkle root of this Bitcoin block
+    Bitcoin { height: u64 },
+    /// Kept verbatim so proofs round-trip
+    Unknown { tag: [u8; 8], payload: Vec<u8> },
+}
+
+/// An attestation with the message it commits to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commitment {
+    pub message: Vec<u8>,
+    pub attestation: Attestation,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timestamp {
+    pub attestations: Vec<Attestation>,
+    pub ops: Vec<(Op, Timestamp)>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("proof is truncated")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or("proof is truncated")?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn varuint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varuint is too long".to_string())
+    }
+
+    fn varbytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.varuint()? as usize;
+        if len > MAX_VARBYTES {
+            return Err(format!("field of {} bytes is too long", len));
+        }
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+fn put_varuint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn put_varbytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_varuint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+impl Attestation {
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let mut tag = [0u8; 8];
+        tag.copy_from_slice(reader.take(8)?);
+        let payload = reader.varbytes()?;
+        let mut inner = Reader {
+            bytes: &payload,
+            pos: 0,
+        };
+        Ok(match tag {
+            PENDING_TAG => Attestation::Pending {
+                uri: String::from_utf8(inner.varbytes()?)
+                    .map_err(|_| "calendar uri is not UTF-8")?,
+            },
+            BITCOIN_TAG => Attestation::Bitcoin {
+                height: inner.varuint()?,
+            },
+            _ => Attestation::Unknown { tag, payload },
+        })
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        let mut payload = Vec::new();
+        let tag = match self {
+            Attestation::Pending { uri } => {
+                put_varbytes(&mut payload, uri.as_bytes());
+                PENDING_TAG
+            }
+            Attestation::Bitcoin { height } => {
+                put_varuint(&mut payload, *height);
+                BITCOIN_TAG
+            }
+            Attestation::Unknown { tag, payload: raw } => {
+                payload.extend_from_slice(raw);
+                *tag
+            }
+        };
+        buf.push(TAG_ATTESTATION);
+        buf.extend_from_slice(&tag);
+        put_varbytes(buf, &payload);
+    }
+}
+
+impl Timestamp {
+    /// Decode a timestamp as served by a calendar
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let timestamp = Self::read(&mut reader, 0)?;
+        if reader.pos != bytes.len() {
+            return Err("trailing bytes after proof".to_string());
+        }
+        Ok(timestamp)
+    }
+
+    fn read(reader: &mut Reader, depth: usize) -> Result<Self, String> {
+        if depth > MAX_DEPTH {
+            return Err("proof is nested too deeply".to_string());
+        }
+        let mut timestamp = Timestamp::default();
+        loop {
+            let tag = reader.byte()?;
+            let (tag, last) = match tag {
+                TAG_FORK => (reader.byte()?, false),
+                tag => (tag, true),
+            };
+            if tag == TAG_ATTESTATION {
+                timestamp.attestations.push(Attestation::read(reader)?);
+            } else {
+                let op = match tag {
+                    TAG_SHA256 => Op::Sha256,
+                    TAG_APPEND => Op::Append(reader.varbytes()?),
+                    TAG_PREPEND => Op::Prepend(reader.varbytes()?),
+                    TAG_REVERSE => Op::Reverse,
+                    TAG_HEXLIFY => Op::Hexlify,
+                    other => return Err(format!("unsupported operation 0x{:02x}", other)),
+                };
+                timestamp.ops.push((op, Self::read(reader, depth + 1)?));
+            }
+            if last {
+                return Ok(timestamp);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write(&mut buf);
+        buf
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        let count = self.attestations.len() + self.ops.len();
+        let mut written = 0;
+        let mut fork = |buf: &mut Vec<u8>| {
+            written += 1;
+            if written < count {
+                buf.push(TAG_FORK);
+            }
+        };
+        for attestation in &self.attestations {
+            fork(buf);
+            attestation.write(buf);
+        }
+        for (op, child) in &self.ops {
+            fork(buf);
+            buf.push(match op {
+                Op::Sha256 => TAG_SHA256,
+                Op::Append(_) => TAG_APPEND,
+                Op::Prepend(_) => TAG_PREPEND,
+                Op::Reverse => TAG_REVERSE,
+                Op::Hexlify => TAG_HEXLIFY,
+            });
+            if let Op::Append(arg) | Op::Prepend(arg) = op {
+                put_varbytes(buf, arg);
+            }
+            child.write(buf);
+        }
+    }
+
+    /// Every attestation in the proof with the message it commits to, when
+    /// evaluated from `message`
+    pub fn commitments(&self, message: &[u8]) -> Vec<Commitment> {
+        let mut found = Vec::new();
+        self.collect(message, &mut found);
+        found
+    }
+
+    fn collect(&self, message: &[u8], found: &mut Vec<Commitment>) {
+        for attestation in &self.attestations {
+            found.push(Commitment {
+                message: message.to_vec(),
+                attestation: attestation.clone(),
+            });
+        }
+        for (op, child) in &self.ops {
+            child.collect(&op.apply(message), found);
+        }
+    }
+
+    /// Replace the pending attestations at `commitment` with `upgrade`,
+    /// which continues the proof from that commitment; false if the
+    /// commitment is not in the proof
+    pub fn splice(&mut self, message: &[u8], commitment: &[u8], upgrade: &Timestamp) -> bool {
+        if message == commitment {
+            self.attestations
+                .retain(|a| !matches!(a, Attestation::Pending { .. }));
+            self.attestations
+                .extend(upgrade.attestations.iter().cloned());
+            self.ops.extend(upgrade.ops.iter().cloned());
+            return true;
+        }
+        self.ops
+            .iter_mut()
+            .any(|(op, child)| child.splice(&op.apply(message), commitment, upgrade))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_round_trip_and_upgrade() {
+        let digest = Sha256::digest(b"head").to_vec();
+        let mut proof = Timestamp {
+            attestations: Vec::new(),
+            ops: vec![(
+                Op::Append(vec![1, 2, 3]),
+                Timestamp {
+                    attestations: Vec::new(),
+                    ops: vec![(
+                        Op::Sha256,
+                        Timestamp {
+                            attestations: vec![Attestation::Pending {
+                                uri: "https://calendar.example".to_string(),
+                            }],
+                            ops: Vec::new(),
+                        },
+                    )],
+                },
+            )],
+        };
+        let bytes = proof.to_bytes();
+        assert_eq!(Timestamp::from_bytes(&bytes).unwrap(), proof);
+        assert!(Timestamp::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Timestamp::from_bytes(&[0x67]).is_err());
+
+        let pending = proof.commitments(&digest);
+        let expected = Sha256::digest([digest.as_slice(), &[1, 2, 3]].concat()).to_vec();
+        assert_eq!(pending[0].message, expected);
+
+        let upgrade = Timestamp {
+            attestations: Vec::new(),
+            ops: vec![(
+                Op::Prepend(vec![9]),
+                Timestamp {
+                    attestations: vec![Attestation::Bitcoin { height: 800_000 }],
+                    ops: Vec::new(),
+                },
+            )],
+        };
+        assert!(proof.splice(&digest, &pending[0].message, &upgrade));
+        assert!(!proof.splice(&digest, b"elsewhere", &upgrade));
+
+        let upgraded = Timestamp::from_bytes(&proof.to_bytes()).unwrap();
+        let commitments = upgraded.commitments(&digest);
+        assert_eq!(commitments.len(), 1);
+        assert_eq!(
+            commitments[0].attestation,
+            Attestation::Bitcoin { height: 800_000 }
+        );
+        assert_eq!(commitments[0].message, [&[9], expected.as_slice()].concat());
+    }
+}
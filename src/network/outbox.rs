@@ -0,0 +1,346 @@
+//! Persistent outbox for consensus messages
+//!
+//! Every outbound PBFT message is written to the `outbox` table, one row per
+//! peer, before it is sent, and the row is marked delivered once the peer
+//! acknowledges it. A node that crashes halfway through a broadcast finds
+//! the rest of the broadcast still pending when it restarts and replays it,
+//! so peers do not end up with a partial set of its votes. Delivery is
+//! at-least-once: peers must (and do) tolerate duplicate votes.
+//!
+//! A message is given up after `OUTBOX_MAX_ATTEMPTS` sends (default 5), so
+//! a peer that is gone for good does not slow down every round. Between
+//! sends a message backs off exponentially, and a replay sends the messages
+//! that are due concurrently, `REPLAY_CONCURRENCY` at a time, so one
+//! unreachable peer does not hold up the rest.
+
+use bytes::Bytes;
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use super::peer_addr::PeerAddr;
+use super::protocol::PeerVersions;
+use super::{peers_excluding, send_payload};
+use crate::consensus::algorithms::PBFTMessage;
+use crate::etl::load::{DatabaseManager, DbResult, OutboxEntry};
+use crate::retry::RetryPolicy;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Messages a replay has in flight at once
+pub const REPLAY_CONCURRENCY: usize = 16;
+
+/// Wait after a message's first failed send; doubles per send up to a minute
+pub const DEFAULT_REPLAY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What a replay of pending messages did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub delivered: usize,
+    /// Still undelivered, to be retried on the next replay
+    pub pending: usize,
+    /// Removed after running out of attempts
+    pub dropped: usize,
+}
+
+pub struct Outbox {
+    db: Arc<DatabaseManager>,
+    client: reqwest::Client,
+    max_attempts: u32,
+    /// Versions negotiated with peers, which messages are encoded in
+    versions: Arc<PeerVersions>,
+    backoff: RetryPolicy,
+    /// Earliest next send of messages whose last send failed, by row id
+    not_before: Mutex<HashMap<i64, Instant>>,
+}
+
+impl Outbox {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Outbox {
+            db,
+            client: reqwest::Client::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            versions: Arc::new(PeerVersions::default()),
+            backoff: RetryPolicy::new(DEFAULT_MAX_ATTEMPTS, DEFAULT_REPLAY_BACKOFF)
+                .with_max_delay(Duration::from_secs(60)),
+            not_before: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read `OUTBOX_MAX_ATTEMPTS`
+    pub fn from_env(db: Arc<DatabaseManager>) -> Self {
+        let outbox = Outbox::new(db);
+        match std::env::var("OUTBOX_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            Some(max_attempts) => outbox.with_max_attempts(max_attempts),
+            None => outbox,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delays between a message's sends; only the backoff settings are used
+    pub fn with_backoff(mut self, backoff: RetryPolicy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Share the node's negotiated protocol versions
+    pub fn with_peer_versions(mut self, versions: Arc<PeerVersions>) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    /// Messages not yet acknowledged, oldest first
+    pub fn pending(&self) -> DbResult<Vec<OutboxEntry>> {
+        self.db.get_pending_outbox()
+    }
+
+    /// Persist `message` for every node except ourselves, then send it
+    pub async fn broadcast(
+        &self,
+        message: &PBFTMessage,
+        node_addresses: &[String],
+        local: &PeerAddr,
+    ) {
+        let payloads = match self
+            .versions
+            .encode_for_peers(message, peers_excluding(node_addresses, local))
+        {
+            Ok(payloads) => payloads,
+            Err(e) => {
+                warn!(error = %e, "Network: Failed to encode message");
+                return;
+            }
+        };
+        let trace_id = message.trace_id.as_deref();
+
+        // The payloads are JSON, so they are stored as text
+        let ids = match payloads
+            .iter()
+            .map(|(peer, payload)| Ok((peer.as_str(), std::str::from_utf8(payload)?)))
+            .collect::<Result<Vec<_>, std::str::Utf8Error>>()
+            .map_err(|e| e.to_string())
+            .and_then(|messages| {
+                self.db
+                    .enqueue_outbox(&messages, trace_id)
+                    .map_err(|e| e.to_string())
+            }) {
+            Ok(ids) => ids,
+            Err(e) => {
+                // Still send: losing durability beats stalling the round
+                warn!(error = %e, "Outbox: Failed to persist message, sending without it");
+                for (peer, payload) in payloads {
+                    if let Err(e) =
+                        send_payload(&self.client, &self.versions, peer, payload, trace_id).await
+                    {
+                        warn!(address = %peer, error = %e, "Network: Failed to send message");
+                    }
+                }
+                return;
+            }
+        };
+
+        join_all(
+            ids.into_iter()
+                .zip(payloads)
+                .map(|(id, (peer, payload))| self.deliver(id, 1, peer, payload, trace_id)),
+        )
+        .await;
+    }
+
+    /// Resend what is pending and due, e.g. after a restart; messages still
+    /// backing off are counted as pending
+    pub async fn replay(&self) -> DbResult<ReplayReport> {
+        let mut report = ReplayReport::default();
+        let now = Instant::now();
+        let mut due = Vec::new();
+        {
+            let not_before = self.not_before.lock();
+            for entry in self.db.get_pending_outbox()? {
+                if entry.attempts >= self.max_attempts {
+                    report.dropped += 1;
+                } else if not_before.get(&entry.id).is_some_and(|at| *at > now) {
+                    report.pending += 1;
+                } else {
+                    due.push(entry);
+                }
+            }
+        }
+
+        let outcomes: Vec<(u32, bool)> = stream::iter(due)
+            .map(|entry: OutboxEntry| async move {
+                let attempt = entry.attempts + 1;
+                let payload = Bytes::from(entry.payload.into_bytes());
+                let delivered = self
+                    .deliver(
+                        entry.id,
+                        attempt,
+                        &entry.peer,
+                        payload,
+                        entry.trace_id.as_deref(),
+                    )
+                    .await;
+                (attempt, delivered)
+            })
+            .buffer_unordered(REPLAY_CONCURRENCY)
+            .collect()
+            .await;
+        for (attempt, delivered) in outcomes {
+            if delivered {
+                report.delivered += 1;
+            } else if attempt >= self.max_attempts {
+                report.dropped += 1;
+            } else {
+                report.pending += 1;
+            }
+        }
+        self.db.prune_outbox(self.max_attempts)?;
+        if report != ReplayReport::default() {
+            info!(
+                delivered = report.delivered,
+                pending = report.pending,
+                dropped = report.dropped,
+                "Outbox: Replayed pending messages"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Make send number `attempt` of one queued message and record the
+    /// outcome, backing the message off when it failed
+    async fn deliver(
+        &self,
+        id: i64,
+        attempt: u32,
+        peer: &str,
+        payload: Bytes,
+        trace_id: Option<&str>,
+    ) -> bool {
+        let delivered =
+            match send_payload(&self.client, &self.versions, peer, payload, trace_id).await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(address = %peer, error = %e, "Network: Failed to send message");
+                    false
+                }
+            };
+        if let Err(e) = self.db.record_outbox_attempt(id, delivered) {
+            warn!(error = %e, "Outbox: Failed to record delivery");
+        }
+        let mut not_before = self.not_before.lock();
+        if delivered || attempt >= self.max_attempts {
+            not_before.remove(&id);
+        } else {
+            not_before.insert(id, Instant::now() + self.backoff.delay(attempt));
+        }
+        debug!(id, address = %peer, delivered, "Outbox: Send attempt");
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::MessageType;
+    use crate::network::protocol::PROTOCOL_VERSION;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[actix_web::test]
+    async fn test_undelivered_messages_are_replayed() {
+        let db = Arc::new(DatabaseManager::in_memory().unwrap());
+        db.init().unwrap();
+
+        // Reserve a port, then leave it closed so the first send fails
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let message = PBFTMessage {
+            msg_type: MessageType::Commit,
+            view: 0,
+            sequence: 1,
+            block_hash: "h".to_string(),
+            block_data_json: None,
+            node_id: 0,
+            timestamp: 1,
+            shard: None,
+            trace_id: Some("trace".to_string()),
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+        let outbox = Outbox::new(db.clone()).with_max_attempts(3);
+        outbox
+            .broadcast(
+                &message,
+                &[peer.clone(), "127.0.0.1:9000".to_string()],
+                &PeerAddr::loopback(9000),
+            )
+            .await;
+        let pending = outbox.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].peer, peer);
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].trace_id.as_deref(), Some("trace"));
+
+        // The peer comes up; a restarted node replays what it still owes
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().route(
+                "/message",
+                web::post().to(move |body: web::Json<PBFTMessage>| {
+                    let counter = counter.clone();
+                    async move {
+                        assert_eq!(body.sequence, 1);
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        HttpResponse::Ok().finish()
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(&peer)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        // Still backing off, so replaying right away sends nothing
+        assert_eq!(
+            outbox.replay().await.unwrap(),
+            ReplayReport {
+                pending: 1,
+                ..ReplayReport::default()
+            }
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+
+        let restarted = Outbox::new(db.clone()).with_max_attempts(3);
+        let report = restarted.replay().await.unwrap();
+        assert_eq!(report.delivered, 1);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        assert!(restarted.pending().unwrap().is_empty());
+        // Delivered rows are pruned, so a second replay sends nothing
+        assert_eq!(restarted.replay().await.unwrap(), ReplayReport::default());
+        handle.stop(true).await;
+    }
+}
@@ -0,0 +1,164 @@
+//! Paging through block listings
+//!
+//! `GET /blocks` and `GET /tenant/blocks` return one page of blocks as a JSON
+//! array, as peers syncing their chain expect, and describe the rest of the
+//! ledger in headers:
+//!
+//! ```text
+//! X-Total-Count: 1342
+//! Link: </blocks?from=1&limit=100>; rel="first", </blocks?from=101&limit=100>; rel="next", ...
+//! ```
+//!
+//! `Link` follows RFC 5988 with `first`, `prev`, `next` and `last` relations
+//! computed from the lowest and highest stored index, so a crawler starts
+//! correctly on a pruned ledger and stops when `next` is absent. Pages hold
+//! at most `MAX_PAGE_SIZE` blocks; a larger `limit`, a `limit` of 0 or a
+//! `from` of 0 is rejected rather than silently adjusted.
+
+use crate::etl::load::DatabaseStats;
+use actix_web::http::header::LINK;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use serde_json::json;
+
+/// Largest page a listing serves
+pub const MAX_PAGE_SIZE: u64 = super::sync::MAX_BLOCKS_PER_REQUEST;
+
+/// Total number of stored blocks
+pub const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
+
+/// A requested range of block indexes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub from: u64,
+    pub limit: u64,
+}
+
+impl Page {
+    /// Validate `from` (default 1) and `limit` (default `MAX_PAGE_SIZE`)
+    pub fn parse(from: Option<u64>, limit: Option<u64>) -> Result<Self, String> {
+        let from = from.unwrap_or(1);
+        let limit = limit.unwrap_or(MAX_PAGE_SIZE);
+        if from == 0 {
+            return Err("from must be at least 1".to_string());
+        }
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+        }
+        Ok(Page { from, limit })
+    }
+
+    /// Last index the page covers
+    pub fn end(&self) -> u64 {
+        self.from.saturating_add(self.limit - 1)
+    }
+
+    /// `Link` header value for this page of `path`, or `None` on an empty
+    /// ledger
+    pub fn links(&self, path: &str, stats: &DatabaseStats) -> Option<String> {
+        let (first, last) = (stats.min_index?, stats.max_index?);
+        let link = |from: u64, rel: &str| {
+            format!(
+                "<{}?from={}&limit={}>; rel=\"{}\"",
+                path, from, self.limit, rel
+            )
+        };
+        let last_page = last.saturating_sub(self.limit - 1).max(first);
+        let mut links = vec![link(first, "first")];
+        if self.from > first {
+            let prev = self.from.saturating_sub(self.limit).max(first);
+            links.push(link(prev.min(last_page), "prev"));
+        }
+        if self.end() < last {
+            links.push(link(self.end().max(first - 1) + 1, "next"));
+        }
+        links.push(link(last_page, "last"));
+        Some(links.join(", "))
+    }
+
+    /// Attach `X-Total-Count` and `Link` to a response builder
+    pub fn headers(&self, response: &mut HttpResponseBuilder, path: &str, stats: &DatabaseStats) {
+        response.insert_header((TOTAL_COUNT_HEADER, stats.total_blocks));
+        if let Some(links) = self.links(path, stats) {
+            response.insert_header((LINK, links));
+        }
+    }
+}
+
+/// 400 response for a rejected page request
+pub fn bad_request(error: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": error,
+        "max_page_size": MAX_PAGE_SIZE,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(min: u64, max: u64) -> DatabaseStats {
+        DatabaseStats {
+            total_blocks: max - min + 1,
+            min_index: Some(min),
+            max_index: Some(max),
+            min_timestamp: None,
+            max_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_page_links() {
+        assert_eq!(
+            Page::parse(None, None),
+            Ok(Page {
+                from: 1,
+                limit: 100
+            })
+        );
+        assert!(Page::parse(Some(0), None).is_err());
+        assert!(Page::parse(None, Some(0)).is_err());
+        assert!(Page::parse(None, Some(MAX_PAGE_SIZE + 1)).is_err());
+
+        let page = Page::parse(Some(11), Some(10)).unwrap();
+        assert_eq!(
+            page.links("/blocks", &stats(1, 35)).unwrap(),
+            "</blocks?from=1&limit=10>; rel=\"first\", \
+             </blocks?from=1&limit=10>; rel=\"prev\", \
+             </blocks?from=21&limit=10>; rel=\"next\", \
+             </blocks?from=26&limit=10>; rel=\"last\""
+        );
+
+        // Pruned ledger: a request below the first stored block pages from it
+        let links = Page::parse(Some(1), Some(10))
+            .unwrap()
+            .links("/blocks", &stats(50, 80))
+            .unwrap();
+        assert!(
+            links.contains("from=50&limit=10>; rel=\"next\""),
+            "{}",
+            links
+        );
+        assert!(!links.contains("prev"));
+
+        // Past the tip: no next page, prev leads back into the ledger
+        let links = Page::parse(Some(200), Some(10))
+            .unwrap()
+            .links("/blocks", &stats(1, 35))
+            .unwrap();
+        assert!(!links.contains("next"));
+        assert!(
+            links.contains("from=26&limit=10>; rel=\"prev\""),
+            "{}",
+            links
+        );
+
+        let empty = DatabaseStats {
+            total_blocks: 0,
+            min_index: None,
+            max_index: None,
+            min_timestamp: None,
+            max_timestamp: None,
+        };
+        assert_eq!(page.links("/blocks", &empty), None);
+    }
+}
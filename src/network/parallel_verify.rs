@@ -0,0 +1,233 @@
+//! Parallel full-chain verification
+//!
+//! Re-hashing a multi-million-block chain on one thread is slow, and every
+//! block can be checked independently except for its link to the previous
+//! one. `verify_chain_parallel` splits the index range into segments of
+//! `segment_blocks` blocks. It checks each segment on a rayon pool of
+//! `jobs` threads, recomputing every hash and checking the links inside the
+//! segment. It then checks that each segment's first block links to the
+//! previous segment's last block.
+//!
+//! The checks are the ones the rolling verifier and chain sync use
+//! (`StoredHashVerifier`, `LinkVerifier`, `HlcVerifier`), so redacted blocks
+//! pass, and the reported failure is the one with the lowest block index, as
+//! a sequential pass would find it.
+
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult};
+use crate::etl::Block;
+use crate::network::sync::{BlockVerifier, HlcVerifier, LinkVerifier, StoredHashVerifier};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Blocks per segment unless configured otherwise
+pub const DEFAULT_SEGMENT_BLOCKS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelVerifyConfig {
+    /// Worker threads
+    pub jobs: usize,
+    pub segment_blocks: u64,
+}
+
+impl ParallelVerifyConfig {
+    pub fn new(jobs: usize) -> Self {
+        ParallelVerifyConfig {
+            jobs: jobs.max(1),
+            segment_blocks: DEFAULT_SEGMENT_BLOCKS,
+        }
+    }
+
+    pub fn with_segment_blocks(mut self, segment_blocks: u64) -> Self {
+        self.segment_blocks = segment_blocks.max(1);
+        self
+    }
+}
+
+impl Default for ParallelVerifyConfig {
+    /// One job per available CPU
+    fn default() -> Self {
+        ParallelVerifyConfig::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+/// Result of `verify_chain_parallel`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChainReport {
+    /// Blocks whose hash was recomputed
+    pub checked: u64,
+    pub first_index: Option<u64>,
+    pub last_index: Option<u64>,
+    pub segments: usize,
+    pub jobs: usize,
+    pub elapsed_ms: u64,
+    /// First problem in index order, if any
+    pub failure: Option<String>,
+}
+
+impl ChainReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// What one worker found in its segment
+#[derive(Default)]
+struct SegmentCheck {
+    first: Option<Block>,
+    last: Option<Block>,
+    checked: u64,
+    failure: Option<(u64, String)>,
+}
+
+fn check_block(
+    hashes: &StoredHashVerifier,
+    block: &Block,
+    parent: Option<&Block>,
+) -> Result<(), String> {
+    for verifier in [&LinkVerifier as &dyn BlockVerifier, &HlcVerifier, hashes] {
+        verifier
+            .verify(block, parent)
+            .map_err(|reason| format!("block {}: {}: {}", block.index, verifier.name(), reason))?;
+    }
+    Ok(())
+}
+
+/// Check `start..=end`, giving up past an index where another segment
+/// already failed
+fn check_segment(
+    db: &DatabaseManager,
+    hashes: &StoredHashVerifier,
+    start: u64,
+    end: u64,
+    earliest_failure: &AtomicU64,
+) -> DbResult<SegmentCheck> {
+    let mut segment = SegmentCheck::default();
+    for block in db.iter_blocks(start..=end) {
+        let block = block?;
+        if block.index > earliest_failure.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Err(reason) = check_block(hashes, &block, segment.last.as_ref()) {
+            earliest_failure.fetch_min(block.index, Ordering::Relaxed);
+            segment.failure = Some((block.index, reason));
+            break;
+        }
+        segment.checked += 1;
+        if segment.first.is_none() {
+            segment.first = Some(block.clone());
+        }
+        segment.last = Some(block);
+    }
+    Ok(segment)
+}
+
+/// Verify every block's hash and link, `config.jobs` segments at a time
+pub fn verify_chain_parallel(
+    db: &DatabaseManager,
+    config: ParallelVerifyConfig,
+) -> DbResult<ChainReport> {
+    let started = Instant::now();
+    let stats = db.get_stats()?;
+    let mut report = ChainReport {
+        first_index: stats.min_index,
+        last_index: stats.max_index,
+        jobs: config.jobs,
+        ..Default::default()
+    };
+    let (Some(min), Some(max)) = (stats.min_index, stats.max_index) else {
+        return Ok(report);
+    };
+
+    let mut ranges = Vec::new();
+    let mut start = min;
+    loop {
+        let end = start.saturating_add(config.segment_blocks - 1).min(max);
+        ranges.push((start, end));
+        if end == max {
+            break;
+        }
+        start = end + 1;
+    }
+    report.segments = ranges.len();
+    let hashes = StoredHashVerifier::new(db)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.jobs)
+        .build()
+        .map_err(|e| DatabaseError::InvalidData(format!("cannot start verify workers: {}", e)))?;
+    let earliest_failure = AtomicU64::new(u64::MAX);
+    let segments: Vec<DbResult<SegmentCheck>> = pool.install(|| {
+        ranges
+            .par_iter()
+            .map(|&(start, end)| check_segment(db, &hashes, start, end, &earliest_failure))
+            .collect()
+    });
+
+    // Stitch the segments together in index order
+    let mut previous: Option<Block> = None;
+    for segment in segments {
+        let segment = segment?;
+        report.checked += segment.checked;
+        if let Some(first) = &segment.first {
+            if let Err(reason) = check_block(&hashes, first, previous.as_ref()) {
+                report.failure = Some(reason);
+                break;
+            }
+        }
+        if let Some((_, reason)) = segment.failure {
+            report.failure = Some(reason);
+            break;
+        }
+        // Empty segments (pruned or missing ranges) leave the link to the
+        // next block to check
+        if segment.last.is_some() {
+            previous = segment.last;
+        }
+    }
+    report.elapsed_ms = started.elapsed().as_millis() as u64;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing::TestChainBuilder;
+
+    fn save_chain(db: &DatabaseManager, len: u64) -> Vec<Block> {
+        let blocks = TestChainBuilder::new().with_blocks(len).build();
+        db.save_blocks(&blocks).unwrap();
+        blocks
+    }
+
+    #[test]
+    fn test_parallel_verification_matches_sequential() {
+        let db = DatabaseManager::in_memory().unwrap();
+        db.init().unwrap();
+        let blocks = save_chain(&db, 95);
+
+        let config = ParallelVerifyConfig::new(4).with_segment_blocks(10);
+        let report = verify_chain_parallel(&db, config).unwrap();
+        assert!(report.is_ok(), "{:?}", report.failure);
+        assert_eq!((report.checked, report.segments), (95, 10));
+
+        // A block whose link is broken right at a segment boundary
+        let mut forged = blocks[40].clone();
+        forged.previous_hash = "forged".to_string();
+        forged.calculate_hash_with_nonce();
+        db.delete_block(41).unwrap();
+        db.save_block(&forged).unwrap();
+        // And a later block whose contents no longer match its hash
+        let mut tampered = blocks[70].clone();
+        tampered.data[0].price = Decimal::ONE;
+        db.delete_block(71).unwrap();
+        db.save_block(&tampered).unwrap();
+
+        let report = verify_chain_parallel(&db, config).unwrap();
+        assert!(!db.verify_chain().unwrap());
+        let failure = report.failure.unwrap();
+        assert!(failure.starts_with("block 41: link"), "{}", failure);
+    }
+}
@@ -0,0 +1,192 @@
+//! Typed peer addresses
+//!
+//! A peer is addressed by an IP socket address (`127.0.0.1:8000`,
+//! `[::1]:8000`) or by a DNS name and port (`node-1.ledger.svc:8000`).
+//! IPv6 addresses need brackets so the port can be told apart. Hostnames
+//! are kept unresolved and lower-cased; reqwest resolves them per request.
+//!
+//! `PeerAddr::same_node` decides whether an address from the peer list is
+//! the local node, so a node skips itself when broadcasting, syncing or
+//! checking clocks. Comparing ports alone would treat every peer in a
+//! cluster whose nodes all serve on the same port as ourselves.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Where a peer serves its HTTP routes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddr {
+    Socket(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl PeerAddr {
+    /// `127.0.0.1:<port>`
+    pub fn loopback(port: u16) -> Self {
+        PeerAddr::Socket(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            PeerAddr::Socket(addr) => addr.port(),
+            PeerAddr::Host { port, .. } => *port,
+        }
+    }
+
+    /// Loopback IP or `localhost`
+    pub fn is_loopback(&self) -> bool {
+        match self {
+            PeerAddr::Socket(addr) => addr.ip().to_canonical().is_loopback(),
+            PeerAddr::Host { host, .. } => host == "localhost",
+        }
+    }
+
+    /// Whether both addresses reach the same node: same port, and the same
+    /// host or both on this machine's loopback interface
+    pub fn same_node(&self, other: &PeerAddr) -> bool {
+        if self.port() != other.port() {
+            return false;
+        }
+        match (self, other) {
+            (PeerAddr::Socket(a), PeerAddr::Socket(b))
+                if a.ip().to_canonical() == b.ip().to_canonical() =>
+            {
+                true
+            }
+            (PeerAddr::Host { host: a, .. }, PeerAddr::Host { host: b, .. }) if a == b => true,
+            _ => self.is_loopback() && other.is_loopback(),
+        }
+    }
+
+    /// `http://<addr><path>`
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self, path)
+    }
+}
+
+/// The local node's address: its entry in `node_addresses`, or
+/// `127.0.0.1:<port>` when that entry is missing or names another port
+pub fn local_address(node_addresses: &[String], node_id: usize, port: u16) -> PeerAddr {
+    node_addresses
+        .get(node_id)
+        .and_then(|address| address.parse::<PeerAddr>().ok())
+        .filter(|address| address.port() == port)
+        .unwrap_or_else(|| PeerAddr::loopback(port))
+}
+
+/// Whether `address` from the peer list is `local`; an address that does
+/// not parse is never the local node
+pub fn is_local(address: &str, local: &PeerAddr) -> bool {
+    address
+        .parse::<PeerAddr>()
+        .is_ok_and(|address| address.same_node(local))
+}
+
+/// IP the node's HTTP and gRPC servers listen on, from `BIND_ADDRESS`
+/// (default `127.0.0.1`); `::` listens on every IPv6 and, where the OS
+/// allows dual-stack sockets, IPv4 interface
+pub fn bind_ip_from_env() -> Result<IpAddr, String> {
+    match std::env::var("BIND_ADDRESS") {
+        Ok(value) => value
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|e| format!("invalid BIND_ADDRESS '{}': {}", value, e)),
+        Err(_) => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+    }
+}
+
+impl FromStr for PeerAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(PeerAddr::Socket(addr));
+        }
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("peer address '{}' has no port", s))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| format!("peer address '{}' has an invalid port", s))?;
+        if host.contains(':') {
+            return Err(format!(
+                "peer address '{}' looks like IPv6; write it as [{}]:{}",
+                s, host, port
+            ));
+        }
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if host.len() > 253 || !host.trim_end_matches('.').split('.').all(valid_label) {
+            return Err(format!("peer address '{}' has an invalid host", s));
+        }
+        Ok(PeerAddr::Host {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Socket(addr) => write!(f, "{}", addr),
+            PeerAddr::Host { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> PeerAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_compare_peer_addresses() {
+        assert_eq!(addr("[::1]:8001").port(), 8001);
+        assert_eq!(addr("[::1]:8001").to_string(), "[::1]:8001");
+        assert_eq!(
+            addr("Node-1.Ledger.svc:8000").to_string(),
+            "node-1.ledger.svc:8000"
+        );
+        assert_eq!(
+            addr("[2001:db8::7]:8000").url("/message"),
+            "http://[2001:db8::7]:8000/message"
+        );
+        assert!("::1:8000".parse::<PeerAddr>().is_err());
+        assert!("node-1".parse::<PeerAddr>().is_err());
+        assert!("node_1..svc:8000".parse::<PeerAddr>().is_err());
+        assert!("node-1:http".parse::<PeerAddr>().is_err());
+
+        // Nodes on different hosts may share a port
+        let local = addr("node-0.ledger.svc:8000");
+        assert!(!addr("node-1.ledger.svc:8000").same_node(&local));
+        assert!(addr("NODE-0.ledger.svc:8000").same_node(&local));
+        assert!(!addr("node-0.ledger.svc:8001").same_node(&local));
+
+        // Loopback spellings of the same port are one node
+        assert!(addr("localhost:8000").same_node(&addr("127.0.0.1:8000")));
+        assert!(addr("[::1]:8000").same_node(&addr("127.0.0.1:8000")));
+        assert!(addr("[::ffff:10.0.0.2]:8000").same_node(&addr("10.0.0.2:8000")));
+        assert!(!addr("10.0.0.3:8000").same_node(&addr("10.0.0.2:8000")));
+
+        let peers = vec!["[::1]:8000".to_string(), "[::1]:8001".to_string()];
+        assert_eq!(local_address(&peers, 1, 8001), addr("[::1]:8001"));
+        assert_eq!(local_address(&peers, 1, 9000), PeerAddr::loopback(9000));
+        assert!(is_local("[::1]:8001", &local_address(&peers, 1, 8001)));
+        assert!(!is_local("not an address", &PeerAddr::loopback(8000)));
+    }
+}
@@ -0,0 +1,394 @@
+//! Wire protocol versioning for consensus messages
+//!
+//! Every `PBFTMessage` carries the `protocol_version` it was encoded with,
+//! and `/message` requests and responses carry the sender's version in
+//! `X-Protocol-Version`. Messages without the field come from nodes that
+//! predate versioning and are read as `LEGACY_PROTOCOL_VERSION`.
+//!
+//! During a rolling upgrade old and new nodes share a cluster, so:
+//! - a node accepts every version from `MIN_PROTOCOL_VERSION` up, including
+//!   newer ones; unknown optional fields are ignored when decoding
+//! - a message from a newer version that cannot be decoded at all (e.g. a
+//!   message type this node does not know) is acknowledged and skipped
+//!   rather than rejected, so the sender does not retry or fail the round
+//! - versions below `MIN_PROTOCOL_VERSION` are refused with
+//!   `426 Upgrade Required`
+//!
+//! The first response from each peer settles the version used with it
+//! (the lower of both sides): later messages to that peer are stamped with
+//! the negotiated version, and legacy peers get them without the field.
+//! Each node keeps its own `PeerVersions`, which `/health` reports.
+//!
+//! Message types newer than a peer's version are not sent to it: a
+//! `ViewChange` only goes to peers that negotiated version 3, and one
+//! stamped with an older version is refused as invalid. A version 3 peer
+//! reads a `ViewChange` without its prepared certificate, and is not sent
+//! the `NewView` that follows.
+//!
+//! History:
+//! - 1: unversioned messages
+//! - 2: `protocol_version` field and `X-Protocol-Version` header
+//! - 3: `ViewChange` messages
+//! - 4: prepared certificates in `ViewChange` and `NewView` messages
+
+use crate::consensus::algorithms::{MessageType, PBFTMessage};
+use bytes::Bytes;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use tracing::{debug, info, warn};
+
+/// Version this node speaks
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Oldest version this node still accepts
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Version of messages and peers that send no version
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Header carrying the sender's protocol version
+pub const PROTOCOL_VERSION_HEADER: &str = "X-Protocol-Version";
+
+/// First version whose nodes understand `msg_type`
+pub fn introduced_in(msg_type: &MessageType) -> u32 {
+    match msg_type {
+        MessageType::PrePrepare | MessageType::Prepare | MessageType::Commit => {
+            LEGACY_PROTOCOL_VERSION
+        }
+        MessageType::ViewChange => 3,
+        MessageType::NewView => 4,
+    }
+}
+
+/// Serde default for messages encoded before versioning
+pub fn legacy_protocol_version() -> u32 {
+    LEGACY_PROTOCOL_VERSION
+}
+
+/// Version to use with a peer that speaks `peer_version`
+pub fn negotiate(peer_version: u32) -> Result<u32, String> {
+    if peer_version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "peer speaks protocol {}, this node needs at least {}",
+            peer_version, MIN_PROTOCOL_VERSION
+        ));
+    }
+    Ok(peer_version.min(PROTOCOL_VERSION))
+}
+
+/// Read `X-Protocol-Version`; `None` when missing or malformed
+pub fn header_version(value: Option<&str>) -> Option<u32> {
+    value.and_then(|v| v.trim().parse().ok())
+}
+
+/// Why a `/message` body was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Not a message of any version this node can tell
+    Invalid(String),
+    /// A message of a version older than `MIN_PROTOCOL_VERSION`
+    Unsupported { version: u32 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Invalid(reason) => write!(f, "invalid message: {}", reason),
+            DecodeError::Unsupported { version } => write!(
+                f,
+                "protocol {} is no longer supported (minimum {})",
+                version, MIN_PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Outcome of decoding a `/message` body
+#[derive(Debug)]
+pub enum Decoded {
+    Message(PBFTMessage),
+    /// A newer peer sent something this version cannot read
+    Skipped {
+        version: u32,
+        reason: String,
+    },
+}
+
+/// Decode a message, tolerating what newer versions may add
+pub fn decode_message(body: &[u8]) -> Result<Decoded, DecodeError> {
+    decode_message_at(body, PROTOCOL_VERSION)
+}
+
+/// Decode a message as a node speaking `local_version` would: message types
+/// introduced after it are unknown
+pub fn decode_message_at(body: &[u8], local_version: u32) -> Result<Decoded, DecodeError> {
+    let error = match serde_json::from_slice::<PBFTMessage>(body) {
+        Ok(message) => {
+            if message.protocol_version < MIN_PROTOCOL_VERSION {
+                return Err(DecodeError::Unsupported {
+                    version: message.protocol_version,
+                });
+            }
+            let introduced = introduced_in(&message.msg_type);
+            if introduced > local_version || introduced > message.protocol_version {
+                format!(
+                    "{:?} messages need protocol {}",
+                    message.msg_type, introduced
+                )
+            } else {
+                return Ok(Decoded::Message(message));
+            }
+        }
+        Err(e) => e.to_string(),
+    };
+
+    let version = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| value.get("protocol_version")?.as_u64())
+        .map(|v| v as u32);
+    match version {
+        Some(version) if version > local_version => Ok(Decoded::Skipped {
+            version,
+            reason: error,
+        }),
+        _ => Err(DecodeError::Invalid(error)),
+    }
+}
+
+/// Encode `message` for a peer that negotiated `version`
+///
+/// The message is stamped with that version; legacy peers get it without
+/// the `protocol_version` field, as they sent it themselves.
+pub fn encode_for(message: &PBFTMessage, version: u32) -> Result<Bytes, serde_json::Error> {
+    let mut value = serde_json::to_value(message)?;
+    if let Some(fields) = value.as_object_mut() {
+        if version <= LEGACY_PROTOCOL_VERSION {
+            fields.remove("protocol_version");
+        } else {
+            fields.insert("protocol_version".to_string(), version.into());
+        }
+    }
+    serde_json::to_vec(&value).map(Bytes::from)
+}
+
+/// Protocol version negotiated with a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerProtocol {
+    /// What the peer announced (`LEGACY_PROTOCOL_VERSION` without a header)
+    pub peer_version: u32,
+    /// The lower of both sides' versions
+    pub negotiated: u32,
+}
+
+/// Versions a node learned from its peers' responses, by address
+#[derive(Default)]
+pub struct PeerVersions {
+    peers: RwLock<BTreeMap<String, PeerProtocol>>,
+}
+
+impl PeerVersions {
+    /// Record the version `peer` answered with, logging when it changes
+    pub fn observe(&self, peer: &str, peer_version: Option<u32>) {
+        let peer_version = peer_version.unwrap_or(LEGACY_PROTOCOL_VERSION);
+        let negotiated = match negotiate(peer_version) {
+            Ok(version) => version,
+            Err(reason) => {
+                warn!(peer = %peer, reason = %reason, "Protocol: Peer is incompatible");
+                return;
+            }
+        };
+        let entry = PeerProtocol {
+            peer_version,
+            negotiated,
+        };
+        let previous = self.peers.write().insert(peer.to_string(), entry.clone());
+        if previous.as_ref() != Some(&entry) {
+            info!(
+                peer = %peer,
+                peer_version,
+                negotiated,
+                "Protocol: Negotiated protocol version"
+            );
+        }
+    }
+
+    pub fn get(&self, peer: &str) -> Option<PeerProtocol> {
+        self.peers.read().get(peer).cloned()
+    }
+
+    /// Version to send `peer` messages in; this node's own until the peer
+    /// has answered
+    pub fn version_for(&self, peer: &str) -> u32 {
+        self.get(peer)
+            .map_or(PROTOCOL_VERSION, |protocol| protocol.negotiated)
+    }
+
+    /// `message` encoded for each of `peers`, once per distinct version;
+    /// peers whose version predates the message type are left out
+    pub fn encode_for_peers<'a>(
+        &self,
+        message: &PBFTMessage,
+        peers: impl IntoIterator<Item = &'a String>,
+    ) -> Result<Vec<(&'a String, Bytes)>, serde_json::Error> {
+        let mut encoded: BTreeMap<u32, Bytes> = BTreeMap::new();
+        let introduced = introduced_in(&message.msg_type);
+        peers
+            .into_iter()
+            .filter(|peer| {
+                let readable = self.version_for(peer) >= introduced;
+                if !readable {
+                    debug!(
+                        peer = %peer,
+                        msg_type = ?message.msg_type,
+                        "Protocol: Peer predates the message type, not sending it"
+                    );
+                }
+                readable
+            })
+            .map(|peer| {
+                let version = self.version_for(peer);
+                let payload = match encoded.get(&version) {
+                    Some(payload) => payload.clone(),
+                    None => {
+                        let payload = encode_for(message, version)?;
+                        encoded.insert(version, payload.clone());
+                        payload
+                    }
+                };
+                Ok((peer, payload))
+            })
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, PeerProtocol> {
+        self.peers.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoding_tolerates_newer_versions() {
+        // Unversioned messages from nodes that predate versioning
+        let legacy = r#"{"msg_type":"Commit","view":0,"sequence":1,"block_hash":"h",
+            "block_data_json":null,"node_id":2,"timestamp":1}"#;
+        match decode_message(legacy.as_bytes()).unwrap() {
+            Decoded::Message(msg) => assert_eq!(msg.protocol_version, LEGACY_PROTOCOL_VERSION),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Unknown optional fields from a newer node are ignored
+        let newer = r#"{"msg_type":"Commit","view":0,"sequence":1,"block_hash":"h",
+            "block_data_json":null,"node_id":2,"timestamp":1,"protocol_version":3,
+            "signature":"abc"}"#;
+        assert!(matches!(
+            decode_message(newer.as_bytes()),
+            Ok(Decoded::Message(_))
+        ));
+
+        // A message type this version does not know is skipped, not refused
+        let unknown = r#"{"msg_type":"ViewChange","view":1,"sequence":1,"block_hash":"h",
+            "block_data_json":null,"node_id":2,"timestamp":1,"protocol_version":3}"#;
+        assert!(matches!(
+            decode_message_at(unknown.as_bytes(), 2),
+            Ok(Decoded::Skipped { version: 3, .. })
+        ));
+        let broken = unknown.replace("\"protocol_version\":3", "\"protocol_version\":2");
+        assert!(matches!(
+            decode_message(broken.as_bytes()),
+            Err(DecodeError::Invalid(_))
+        ));
+        // Version 3 nodes know view changes
+        assert!(matches!(
+            decode_message(unknown.as_bytes()),
+            Ok(Decoded::Message(_))
+        ));
+        let new_view = unknown
+            .replace("ViewChange", "NewView")
+            .replace("\"protocol_version\":3", "\"protocol_version\":4");
+        assert!(matches!(
+            decode_message_at(new_view.as_bytes(), 3),
+            Ok(Decoded::Skipped { version: 4, .. })
+        ));
+        assert!(matches!(
+            decode_message(new_view.as_bytes()),
+            Ok(Decoded::Message(_))
+        ));
+        let checkpoint = unknown.replace("ViewChange", "Checkpoint");
+        let checkpoint = checkpoint.replace("\"protocol_version\":3", "\"protocol_version\":5");
+        assert!(matches!(
+            decode_message(checkpoint.as_bytes()),
+            Ok(Decoded::Skipped { version: 5, .. })
+        ));
+
+        let too_old = newer.replace("\"protocol_version\":3", "\"protocol_version\":0");
+        assert_eq!(
+            decode_message(too_old.as_bytes()).unwrap_err(),
+            DecodeError::Unsupported { version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_negotiation_picks_the_lower_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1), Ok(PROTOCOL_VERSION));
+        assert_eq!(negotiate(1), Ok(1));
+        assert!(negotiate(0).is_err());
+
+        let peers = PeerVersions::default();
+        peers.observe("10.0.0.1:8000", None);
+        peers.observe("10.0.0.2:8000", header_version(Some("7")));
+        assert_eq!(peers.get("10.0.0.1:8000").unwrap().negotiated, 1);
+        assert_eq!(
+            peers.get("10.0.0.2:8000").unwrap(),
+            PeerProtocol {
+                peer_version: 7,
+                negotiated: PROTOCOL_VERSION
+            }
+        );
+
+        // Messages go out in the version negotiated with each peer
+        let message = PBFTMessage {
+            msg_type: MessageType::Commit,
+            view: 0,
+            sequence: 1,
+            block_hash: "h".to_string(),
+            block_data_json: None,
+            node_id: 2,
+            timestamp: 1,
+            shard: None,
+            trace_id: None,
+            protocol_version: PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+        let addresses = ["10.0.0.1:8000", "10.0.0.2:8000", "10.0.0.3:8000"].map(String::from);
+        let encoded = peers.encode_for_peers(&message, &addresses).unwrap();
+        let version = |payload: &Bytes| {
+            serde_json::from_slice::<serde_json::Value>(payload).unwrap()["protocol_version"]
+                .as_u64()
+        };
+        assert_eq!(version(&encoded[0].1), None);
+        assert_eq!(version(&encoded[1].1), Some(PROTOCOL_VERSION as u64));
+        assert_eq!(version(&encoded[2].1), Some(PROTOCOL_VERSION as u64));
+        match decode_message(&encoded[0].1).unwrap() {
+            Decoded::Message(msg) => assert_eq!(msg.protocol_version, LEGACY_PROTOCOL_VERSION),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // A view change only goes to peers that can read it
+        let view_change = PBFTMessage {
+            msg_type: MessageType::ViewChange,
+            ..message
+        };
+        let encoded = peers.encode_for_peers(&view_change, &addresses).unwrap();
+        let recipients: Vec<&String> = encoded.iter().map(|(peer, _)| *peer).collect();
+        assert_eq!(recipients, [&addresses[1], &addresses[2]]);
+    }
+}
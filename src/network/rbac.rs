@@ -0,0 +1,615 @@
+//! Role-based access control for the HTTP API
+//!
+//! Every API key or client certificate maps to a principal with one role.
+//! Roles are ordered, so each one includes the rights of the roles below it:
+//! `reader` may query the ledger, `writer` may also submit data, and `admin`
+//! may also use the `/admin` routes. `required_role` lists what each route
+//! needs. The `enforce` middleware checks it before any handler runs.
+//!
+//! Callers authenticate with `Authorization: Bearer <key>`. Behind a proxy
+//! that terminates TLS, a client certificate can be used instead: the proxy
+//! forwards the certificate's SHA-256 fingerprint in `API_CERT_HEADER`. That
+//! header is trusted only when fingerprints are configured. A tenant's
+//! `X-API-Key` also grants `writer` on the `/tenant` routes.
+//!
+//! Denied requests are logged and kept for `GET /admin/denials`. They are
+//! also appended as JSON lines to `RBAC_AUDIT_LOG` when that is set. Lines
+//! are buffered and flushed at most once a second, so a flood of refused
+//! requests costs no write per request. Once the file would grow past
+//! `RBAC_AUDIT_LOG_MAX_MB` it is rotated to `<path>.1`, which bounds the
+//! audit log to twice that size on disk.
+//!
+//! Configured with `API_KEYS` (`NAME=ROLE:KEY,...`, enables RBAC),
+//! `API_CERT_FINGERPRINTS` (`NAME=ROLE:FINGERPRINT,...`), `API_CERT_HEADER`,
+//! `RBAC_AUDIT_LOG` and `RBAC_AUDIT_LOG_MAX_MB`.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::admin::constant_time_eq;
+use super::tenancy::API_KEY_HEADER;
+use super::ServerContext;
+use crate::etl::now_millis;
+
+/// Header carrying a client certificate fingerprint unless `API_CERT_HEADER`
+/// is set
+pub const DEFAULT_CERT_HEADER: &str = "X-Client-Cert-Fingerprint";
+
+/// Denials kept in memory for `/admin/denials`
+pub const MAX_DENIALS: usize = 1000;
+
+/// Size the audit log may reach before rotating unless
+/// `RBAC_AUDIT_LOG_MAX_MB` is set
+pub const DEFAULT_AUDIT_LOG_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Longest a denial waits in the buffer before reaching the audit log
+const AUDIT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Writer,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Reader => write!(f, "reader"),
+            Role::Writer => write!(f, "writer"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("unknown role '{}'", other)),
+        }
+    }
+}
+
+/// An authenticated caller, attached to the request for handlers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Role a route requires; `None` for routes that are open or authenticate
+/// by other means (peer messages and forwarded entries are checked against
+/// cluster membership)
+pub fn required_role(method: &Method, path: &str) -> Option<Role> {
+    match path {
+        "/health" | "/healthz" | "/message" | "/forward" => None,
+        _ if path.starts_with("/admin/") => Some(Role::Admin),
+        _ if method == Method::GET || method == Method::HEAD => Some(Role::Reader),
+        _ => Some(Role::Writer),
+    }
+}
+
+/// A refused request
+#[derive(Debug, Clone, Serialize)]
+pub struct Denial {
+    pub at_ms: i64,
+    pub method: String,
+    pub path: String,
+    pub remote: Option<String>,
+    /// Authenticated caller, if the credentials were valid
+    pub principal: Option<String>,
+    pub required: Role,
+    pub reason: String,
+}
+
+/// Credentials, their roles, and the denial log
+pub struct AccessPolicy {
+    keys: Vec<(String, Principal)>,
+    fingerprints: Vec<(String, Principal)>,
+    cert_header: String,
+    audit_log: Option<Mutex<AuditLog>>,
+    denials: Mutex<VecDeque<Denial>>,
+}
+
+/// Buffered, size-capped JSON lines file of denials
+struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    /// Opened on the first denial
+    writer: Option<BufWriter<File>>,
+    /// Bytes in the current file, buffered ones included
+    size: u64,
+    last_flush: Instant,
+}
+
+impl AuditLog {
+    fn new(path: PathBuf) -> Self {
+        AuditLog {
+            path,
+            max_bytes: DEFAULT_AUDIT_LOG_MAX_BYTES,
+            writer: None,
+            size: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    fn append(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.writer.is_some() && self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.size = file.metadata()?.len();
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        writeln!(writer, "{}", line)?;
+        self.size += len;
+        if self.last_flush.elapsed() >= AUDIT_FLUSH_INTERVAL {
+            writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Move the full file to `<path>.1`, replacing the previous one
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, rotated)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.last_flush = Instant::now();
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessPolicy {
+    pub fn new() -> Self {
+        AccessPolicy {
+            keys: Vec::new(),
+            fingerprints: Vec::new(),
+            cert_header: DEFAULT_CERT_HEADER.to_string(),
+            audit_log: None,
+            denials: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn with_key(mut self, name: impl Into<String>, role: Role, key: impl Into<String>) -> Self {
+        let principal = Principal {
+            name: name.into(),
+            role,
+        };
+        self.keys.push((key.into(), principal));
+        self
+    }
+
+    pub fn with_cert(
+        mut self,
+        name: impl Into<String>,
+        role: Role,
+        fingerprint: impl Into<String>,
+    ) -> Self {
+        let principal = Principal {
+            name: name.into(),
+            role,
+        };
+        self.fingerprints
+            .push((normalize_fingerprint(&fingerprint.into()), principal));
+        self
+    }
+
+    pub fn with_cert_header(mut self, header: impl Into<String>) -> Self {
+        self.cert_header = header.into();
+        self
+    }
+
+    pub fn with_audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(Mutex::new(AuditLog::new(path.into())));
+        self
+    }
+
+    /// Rotate the audit log once it would grow past `max_bytes`
+    pub fn with_audit_log_limit(self, max_bytes: u64) -> Self {
+        if let Some(log) = &self.audit_log {
+            log.lock().max_bytes = max_bytes.max(1);
+        }
+        self
+    }
+
+    /// `None` unless `API_KEYS` or `API_CERT_FINGERPRINTS` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let keys = std::env::var("API_KEYS").ok();
+        let certs = std::env::var("API_CERT_FINGERPRINTS").ok();
+        if keys.is_none() && certs.is_none() {
+            return Ok(None);
+        }
+
+        let mut policy = Self::new();
+        for (name, role, key) in parse_credentials("API_KEYS", keys.as_deref())? {
+            policy = policy.with_key(name, role, key);
+        }
+        for (name, role, fingerprint) in
+            parse_credentials("API_CERT_FINGERPRINTS", certs.as_deref())?
+        {
+            policy = policy.with_cert(name, role, fingerprint);
+        }
+        if let Ok(header) = std::env::var("API_CERT_HEADER") {
+            policy = policy.with_cert_header(header);
+        }
+        if let Ok(path) = std::env::var("RBAC_AUDIT_LOG") {
+            policy = policy.with_audit_log(path);
+        }
+        if let Ok(value) = std::env::var("RBAC_AUDIT_LOG_MAX_MB") {
+            let mb: u64 = value
+                .parse()
+                .ok()
+                .filter(|mb| *mb > 0)
+                .ok_or_else(|| format!("invalid RBAC_AUDIT_LOG_MAX_MB: '{}'", value))?;
+            policy = policy.with_audit_log_limit(mb * 1024 * 1024);
+        }
+        Ok(Some(policy))
+    }
+
+    /// Principal for the request's credentials
+    ///
+    /// `Ok(None)` when none were presented, `Err` when they are not valid.
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<Option<Principal>, String> {
+        if let Some(header) = req.headers().get("Authorization") {
+            let key = header
+                .to_str()
+                .ok()
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .ok_or("malformed Authorization header")?;
+            return self
+                .keys
+                .iter()
+                .find(|(k, _)| constant_time_eq(k.as_bytes(), key.as_bytes()))
+                .map(|(_, principal)| Some(principal.clone()))
+                .ok_or_else(|| "unknown API key".to_string());
+        }
+        if self.fingerprints.is_empty() {
+            return Ok(None);
+        }
+        match req.headers().get(self.cert_header.as_str()) {
+            Some(header) => {
+                let fingerprint = normalize_fingerprint(header.to_str().unwrap_or_default());
+                self.fingerprints
+                    .iter()
+                    .find(|(f, _)| *f == fingerprint)
+                    .map(|(_, principal)| Some(principal.clone()))
+                    .ok_or_else(|| "unknown client certificate".to_string())
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn deny(&self, denial: Denial) {
+        warn!(
+            method = %denial.method,
+            path = %denial.path,
+            remote = ?denial.remote,
+            principal = ?denial.principal,
+            required = %denial.required,
+            reason = %denial.reason,
+            "Access: Request denied"
+        );
+        if let Some(log) = &self.audit_log {
+            let line = serde_json::to_string(&denial).unwrap_or_default();
+            let mut log = log.lock();
+            if let Err(e) = log.append(&line) {
+                warn!(error = %e, path = %log.path.display(), "Access: Failed to write audit log");
+            }
+        }
+        let mut denials = self.denials.lock();
+        if denials.len() == MAX_DENIALS {
+            denials.pop_front();
+        }
+        denials.push_back(denial);
+    }
+
+    /// Recorded denials, oldest first
+    pub fn denials(&self) -> Vec<Denial> {
+        self.denials.lock().iter().cloned().collect()
+    }
+
+    /// Flush the audit log every `AUDIT_FLUSH_INTERVAL`, so a denial
+    /// reaches the file even when no other follows it
+    pub fn spawn_audit_flusher(self: Arc<Self>) -> Option<JoinHandle<()>> {
+        self.audit_log.as_ref()?;
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(AUDIT_FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                self.flush_audit_log();
+            }
+        }))
+    }
+
+    /// Write out buffered audit log lines
+    pub fn flush_audit_log(&self) {
+        if let Some(log) = &self.audit_log {
+            let mut log = log.lock();
+            if let Err(e) = log.flush() {
+                warn!(error = %e, path = %log.path.display(), "Access: Failed to write audit log");
+            }
+        }
+    }
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn parse_credentials(
+    var: &str,
+    value: Option<&str>,
+) -> Result<Vec<(String, Role, String)>, String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .filter(|e| !e.trim().is_empty())
+        .map(|entry| {
+            let invalid = || format!("invalid {} entry '{}', expected NAME=ROLE:KEY", var, entry);
+            let (name, rest) = entry.split_once('=').ok_or_else(invalid)?;
+            let (role, secret) = rest.split_once(':').ok_or_else(invalid)?;
+            if name.trim().is_empty() || secret.trim().is_empty() {
+                return Err(invalid());
+            }
+            Ok((
+                name.trim().to_string(),
+                role.parse()?,
+                secret.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Check the caller's role against the route before running its handler
+pub async fn enforce<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let Some(context) = req.app_data::<web::Data<ServerContext>>().cloned() else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+    let (Some(policy), Some(required)) = (
+        context.access.as_deref(),
+        required_role(req.method(), req.path()),
+    ) else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+
+    let mut principal = policy.authenticate(req.request());
+    // A tenant's own key lets it write within its namespace
+    if let (Ok(None), Some(tenants)) = (&principal, context.tenants.as_deref()) {
+        if req.path().starts_with("/tenant/") {
+            let tenant = req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|key| tenants.authenticate(key));
+            if let Some(tenant) = tenant {
+                principal = Ok(Some(Principal {
+                    name: format!("tenant:{}", tenant),
+                    role: Role::Writer,
+                }));
+            }
+        }
+    }
+
+    let (status, principal, reason) = match principal {
+        Ok(Some(principal)) if principal.role >= required => {
+            req.extensions_mut().insert(principal);
+            return next.call(req).await.map(|res| res.map_into_left_body());
+        }
+        Ok(Some(principal)) => (
+            StatusCode::FORBIDDEN,
+            Some(principal.name),
+            format!("role {} required", required),
+        ),
+        Ok(None) => (
+            StatusCode::UNAUTHORIZED,
+            None,
+            "credentials required".to_string(),
+        ),
+        Err(reason) => (StatusCode::UNAUTHORIZED, None, reason),
+    };
+    policy.deny(Denial {
+        at_ms: now_millis(),
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        remote: req.peer_addr().map(|addr| addr.ip().to_string()),
+        principal,
+        required,
+        reason: reason.clone(),
+    });
+    let response = HttpResponse::build(status).json(json!({ "error": reason }));
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// Recent denials; the route requires the admin role
+pub(super) async fn denials(context: web::Data<ServerContext>) -> impl Responder {
+    match context.access.as_deref() {
+        Some(policy) => HttpResponse::Ok().json(policy.denials()),
+        None => HttpResponse::NotFound().json(json!({
+            "error": "access control is not enabled on this node"
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::admin::{self, NodeControl};
+    use crate::network::NetworkHandler;
+    use actix_web::middleware::from_fn;
+    use actix_web::App;
+
+    #[test]
+    fn test_required_role_by_route() {
+        assert_eq!(required_role(&Method::GET, "/health"), None);
+        assert_eq!(required_role(&Method::POST, "/message"), None);
+        assert_eq!(required_role(&Method::POST, "/forward"), None);
+        assert_eq!(required_role(&Method::GET, "/blocks"), Some(Role::Reader));
+        assert_eq!(
+            required_role(&Method::POST, "/tenant/submit"),
+            Some(Role::Writer)
+        );
+        assert_eq!(
+            required_role(&Method::GET, "/admin/status"),
+            Some(Role::Admin)
+        );
+        assert!(Role::Admin > Role::Writer && Role::Writer > Role::Reader);
+        assert!(parse_credentials("API_KEYS", Some("ops=root:k")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_routes_enforce_roles_and_log_denials() {
+        let control = Arc::new(NodeControl::default());
+        let policy = Arc::new(
+            AccessPolicy::new()
+                .with_key("dashboard", Role::Reader, "read-key")
+                .with_key("ops", Role::Admin, "admin-key")
+                .with_cert("ingest", Role::Writer, "AB:CD:EF"),
+        );
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_admin(control.clone(), None)
+            .with_access_policy(policy.clone());
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .wrap(from_fn(enforce))
+                .route("/health", web::get().to(super::super::health))
+                .route("/accounts", web::get().to(super::super::accounts))
+                .route("/admin/pause", web::post().to(admin::pause)),
+        )
+        .await;
+
+        let call = |method: Method, uri: &str, header: Option<(&str, &str)>| {
+            let mut req = actix_web::test::TestRequest::default()
+                .method(method)
+                .uri(uri);
+            if let Some((name, value)) = header {
+                req = req.insert_header((name.to_string(), value.to_string()));
+            }
+            req.to_request()
+        };
+        let status = |res: ServiceResponse<_>| res.status().as_u16();
+
+        let res = actix_web::test::call_service(&app, call(Method::GET, "/health", None)).await;
+        assert_eq!(status(res), 200);
+        let res = actix_web::test::call_service(&app, call(Method::GET, "/accounts", None)).await;
+        assert_eq!(status(res), 401);
+        // Reader passes the role check; the route itself reports accounting is off
+        let reader = Some(("Authorization", "Bearer read-key"));
+        let res = actix_web::test::call_service(&app, call(Method::GET, "/accounts", reader)).await;
+        assert_eq!(status(res), 404);
+        let res =
+            actix_web::test::call_service(&app, call(Method::POST, "/admin/pause", reader)).await;
+        assert_eq!(status(res), 403);
+        let cert = Some((DEFAULT_CERT_HEADER, "abcdef"));
+        let res =
+            actix_web::test::call_service(&app, call(Method::POST, "/admin/pause", cert)).await;
+        assert_eq!(status(res), 403);
+        assert!(!control.state().paused);
+
+        // An admin key works without ADMIN_TOKEN being configured
+        let admin = Some(("Authorization", "Bearer admin-key"));
+        let res =
+            actix_web::test::call_service(&app, call(Method::POST, "/admin/pause", admin)).await;
+        assert_eq!(status(res), 200);
+        assert!(control.state().paused);
+
+        let denials = policy.denials();
+        assert_eq!(denials.len(), 3);
+        assert_eq!(denials[1].principal.as_deref(), Some("dashboard"));
+        assert_eq!(denials[1].required, Role::Admin);
+        assert_eq!(denials[2].principal.as_deref(), Some("ingest"));
+    }
+
+    #[test]
+    fn test_audit_log_is_buffered_and_rotated() {
+        let path = std::env::temp_dir().join(format!(
+            "rbac_audit_{}.jsonl",
+            crate::logger::new_trace_id()
+        ));
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        let policy = AccessPolicy::new()
+            .with_audit_log(&path)
+            .with_audit_log_limit(1024);
+        let denial = || Denial {
+            at_ms: 0,
+            method: "GET".to_string(),
+            path: "/blocks".to_string(),
+            remote: None,
+            principal: None,
+            required: Role::Reader,
+            reason: "credentials required".to_string(),
+        };
+
+        policy.deny(denial());
+        policy.flush_audit_log();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        for _ in 0..50 {
+            policy.deny(denial());
+        }
+        policy.flush_audit_log();
+        let size = |p: &std::path::Path| std::fs::metadata(p).unwrap().len();
+        assert!(size(&path) <= 1024);
+        assert!(size(std::path::Path::new(&rotated)) <= 1024);
+        assert_eq!(policy.denials().len(), 51);
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&rotated);
+    }
+}
@@ -0,0 +1,313 @@
+//! Redaction of block payloads
+//!
+//! Personal or licensed data that must be erased cannot simply be deleted
+//! from a hash chain. Instead, `redact` tombstones a block: its entries,
+//! fees and divergence events are removed from the store, while the block
+//! keeps its hash and links. The node signs a redaction record with its
+//! `NODE_SIGNING_KEY` and stores it next to the chain. Local verification
+//! (`verify_chain`, the rolling verifier and `verify --jobs`) accepts a
+//! redacted block only when its payload is empty, its stored hash is the
+//! one the record covers, and the record is signed with a trusted key: the
+//! node's own `NODE_SIGNING_KEY` or one listed in `REDACTION_TRUSTED_KEYS`.
+//!
+//! The payload is also removed from the other copies the node keeps:
+//! queued outbox messages are deleted, the consensus event log drops the
+//! block data of the messages that carried it, and archived exports in
+//! `ARCHIVE_DIR` have the block's line replaced by the tombstone.
+//!
+//! Redaction is per node. A peer that syncs a tombstoned block cannot
+//! re-hash it and will quarantine it, so the same redaction must be applied
+//! on every node that holds the block.
+//!
+//! Operators redact with `POST /admin/redact/{index}` and a body of
+//! `{"reason": "..."}`. Records are served on `GET /redactions`.
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use super::admin::authorize;
+use super::oracle::{verify_signature, OracleSigner};
+use super::ServerContext;
+use crate::etl::guardrails::redact_archived;
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult, StoredRedaction};
+use crate::etl::now_millis;
+
+/// What the node signs when it redacts a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redaction {
+    pub block_index: u64,
+    pub block_hash: String,
+    pub reason: String,
+    pub redacted_by: usize,
+    /// Milliseconds
+    pub redacted_at: i64,
+}
+
+impl Redaction {
+    pub fn signing_input(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64 + self.block_hash.len() + self.reason.len());
+        buf.extend_from_slice(b"rml-redaction-v1");
+        buf.extend_from_slice(&self.block_index.to_be_bytes());
+        buf.extend_from_slice(&(self.block_hash.len() as u64).to_be_bytes());
+        buf.extend_from_slice(self.block_hash.as_bytes());
+        buf.extend_from_slice(&(self.reason.len() as u64).to_be_bytes());
+        buf.extend_from_slice(self.reason.as_bytes());
+        buf.extend_from_slice(&(self.redacted_by as u64).to_be_bytes());
+        buf.extend_from_slice(&self.redacted_at.to_be_bytes());
+        buf
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRedaction {
+    #[serde(flatten)]
+    pub redaction: Redaction,
+    /// Hex-encoded Ed25519 public key of the node
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature over `redaction.signing_input()`
+    pub signature: String,
+}
+
+impl From<StoredRedaction> for SignedRedaction {
+    fn from(stored: StoredRedaction) -> Self {
+        SignedRedaction {
+            redaction: Redaction {
+                block_index: stored.block_index,
+                block_hash: stored.block_hash,
+                reason: stored.reason,
+                redacted_by: stored.redacted_by,
+                redacted_at: stored.redacted_at,
+            },
+            public_key: stored.public_key,
+            signature: stored.signature,
+        }
+    }
+}
+
+/// Public keys whose redaction records verification accepts: the node's
+/// own `NODE_SIGNING_KEY` and the comma-separated hex keys in
+/// `REDACTION_TRUSTED_KEYS`
+pub fn trusted_keys_from_env() -> Result<Vec<String>, String> {
+    let mut keys = Vec::new();
+    if let Some(signer) = OracleSigner::from_env(0)? {
+        keys.push(signer.public_key());
+    }
+    if let Ok(list) = std::env::var("REDACTION_TRUSTED_KEYS") {
+        for key in list.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            match hex::decode(key) {
+                Ok(bytes) if bytes.len() == 32 => keys.push(key.to_ascii_lowercase()),
+                _ => {
+                    return Err(format!(
+                        "invalid REDACTION_TRUSTED_KEYS: '{}' is not a 32-byte hex public key",
+                        key
+                    ))
+                }
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Check that `signed` was signed with a node key the auditor trusts
+pub fn verify(signed: &SignedRedaction, trusted_public_key: &str) -> Result<(), String> {
+    verify_signature(
+        trusted_public_key,
+        &signed.redaction.signing_input(),
+        &signed.signature,
+    )
+    .map_err(|_| "signature does not match the redaction".to_string())
+}
+
+/// Tombstone the payload of block `index` and store the signed record
+pub fn redact(
+    db: &DatabaseManager,
+    signer: &OracleSigner,
+    index: u64,
+    reason: &str,
+) -> DbResult<SignedRedaction> {
+    let block = db.get_block_by_index(index)?;
+    let redaction = Redaction {
+        block_index: block.index,
+        block_hash: block.hash,
+        reason: reason.to_string(),
+        redacted_by: signer.node_id(),
+        redacted_at: now_millis(),
+    };
+    let signed = SignedRedaction {
+        signature: signer.sign_bytes(&redaction.signing_input()),
+        public_key: signer.public_key(),
+        redaction,
+    };
+    db.redact_block(&StoredRedaction {
+        block_index: signed.redaction.block_index,
+        block_hash: signed.redaction.block_hash.clone(),
+        reason: signed.redaction.reason.clone(),
+        redacted_by: signed.redaction.redacted_by,
+        redacted_at: signed.redaction.redacted_at,
+        public_key: signed.public_key.clone(),
+        signature: signed.signature.clone(),
+    })?;
+    Ok(signed)
+}
+
+/// Remove a redacted block's payload from the event log and the archives
+fn purge_copies(
+    context: &ServerContext,
+    db: &DatabaseManager,
+    signed: &SignedRedaction,
+) -> Result<(), String> {
+    let hash = &signed.redaction.block_hash;
+    if let Some(log) = &context.event_log {
+        let messages = log
+            .redact_block(hash)
+            .map_err(|e| format!("event log: {}", e))?;
+        info!(messages, "Redaction: Removed block data from the event log");
+    }
+    let archive_dir = context
+        .storage
+        .as_ref()
+        .and_then(|storage| storage.limits().archive_dir.as_ref());
+    if let Some(dir) = archive_dir.filter(|dir| dir.exists()) {
+        let tombstone = db
+            .get_block_by_index(signed.redaction.block_index)
+            .map_err(|e| e.to_string())?;
+        let copies = redact_archived(dir, &tombstone)
+            .map_err(|e| format!("archive {}: {}", dir.display(), e))?;
+        info!(copies, "Redaction: Replaced archived copies");
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct RedactRequest {
+    reason: String,
+}
+
+pub async fn redact_block(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    body: web::Json<RedactRequest>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    if let Err(resp) = authorize(&req, &context) {
+        return resp;
+    }
+    let (Some(db), Some(signer)) = (&context.db, &context.oracle) else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "redaction needs the ledger and a node signing key (NODE_SIGNING_KEY)"
+        }));
+    };
+    if body.reason.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "reason is required" }));
+    }
+    let index = path.into_inner();
+    match redact(db, signer, index, body.reason.trim()) {
+        Ok(signed) => match purge_copies(&context, db, &signed) {
+            Ok(()) => HttpResponse::Ok().json(signed),
+            Err(e) => HttpResponse::InternalServerError().json(json!({
+                "error": format!("block {} redacted, but its copies were not: {}", index, e)
+            })),
+        },
+        Err(DatabaseError::NotFound(_)) => {
+            HttpResponse::NotFound().json(json!({ "error": format!("block {} not found", index) }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// Every redaction record, by block index
+pub async fn list(context: web::Data<ServerContext>) -> impl Responder {
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+    match db.get_redactions() {
+        Ok(stored) => {
+            let redactions: Vec<SignedRedaction> =
+                stored.into_iter().map(SignedRedaction::from).collect();
+            HttpResponse::Ok().json(json!({ "redactions": redactions }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::admin::NodeControl;
+    use crate::network::NetworkHandler;
+    use crate::testing::TestChainBuilder;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn test_redacted_block_keeps_chain_verifiable() {
+        let signer = Arc::new(OracleSigner::new(1, [7; 32]));
+        let db = Arc::new(
+            DatabaseManager::in_memory()
+                .unwrap()
+                .with_redaction_keys(vec![signer.public_key()]),
+        );
+        db.init().unwrap();
+        let chain = TestChainBuilder::new().with_blocks(3).build();
+        db.save_blocks(&chain).unwrap();
+        let second = &chain[1];
+
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_database(db.clone())
+            .with_oracle(signer.clone())
+            .with_admin(Arc::new(NodeControl::default()), Some("secret".to_string()));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/admin/redact/{index}", web::post().to(redact_block))
+                .route("/redactions", web::get().to(list)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/admin/redact/2")
+            .set_json(json!({ "reason": "erasure request" }))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/admin/redact/2")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "reason": "erasure request" }))
+            .to_request();
+        let signed: SignedRedaction = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(signed.redaction.block_hash, second.hash);
+        assert!(verify(&signed, &signer.public_key()).is_ok());
+
+        let tombstoned = db.get_block_by_index(2).unwrap();
+        assert!(tombstoned.data.is_empty());
+        assert_eq!(tombstoned.hash, second.hash);
+        assert_ne!(tombstoned.calculate_hash(), tombstoned.hash);
+        assert!(db.verify_chain().unwrap());
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/redactions")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        let listed: SignedRedaction =
+            serde_json::from_value(body["redactions"][0].clone()).unwrap();
+        assert_eq!(listed, signed);
+
+        let mut forged = signed.clone();
+        forged.redaction.reason = "something else".to_string();
+        assert!(verify(&forged, &signer.public_key()).is_err());
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/admin/redact/9")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(json!({ "reason": "erasure request" }))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+    }
+}
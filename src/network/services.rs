@@ -0,0 +1,164 @@
+//! Optional services of a running node
+//!
+//! `NodeServices::start` reads the settings of the services a node runs
+//! beside its block loop: signed oracle prices, attestations, anchoring, fee
+//! accounting, tenants, follower forwarding, access control, storage
+//! guardrails, rolling verification, gRPC and commit notifications. It
+//! starts their background tasks and registers them on the `ServerContext`;
+//! the block loop keeps the handles it uses while building blocks. Settings
+//! that do not parse are configuration errors (`ExitError::config`).
+
+use super::anchor::{AnchorConfig, Anchorer};
+use super::attestation::{AttestationConfig, Attestor};
+use super::forwarding::Mempool;
+use super::oracle::OracleSigner;
+use super::rbac::{AccessPolicy, Role};
+use super::tenancy::TenantRegistry;
+use super::verification::{RollingVerifier, VerificationConfig};
+use super::ServerContext;
+use crate::consensus::finality::FinalityRule;
+use crate::etl::accounting::AccountBook;
+use crate::etl::guardrails::{StorageGuard, StorageLimits};
+use crate::etl::load::DatabaseManager;
+use crate::features::{Feature, FeatureFlags};
+use crate::supervisor::ExitError;
+use std::error::Error;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// What the services depend on besides the environment
+pub struct ServiceOptions<'a> {
+    pub node_id: usize,
+    /// Ledger file, whose disk the storage guard watches
+    pub db_path: &'a str,
+    pub features: &'a FeatureFlags,
+    /// Follower forwarding only applies to PBFT
+    pub pbft: bool,
+    /// Reported by the block routes and the gRPC service
+    pub finality: FinalityRule,
+}
+
+/// Services the block loop reads or updates each round
+#[derive(Default)]
+pub struct NodeServices {
+    pub accounts: Option<Arc<AccountBook>>,
+    pub tenants: Option<Arc<TenantRegistry>>,
+    pub mempool: Option<Arc<Mempool>>,
+    pub storage_guard: Option<Arc<StorageGuard>>,
+}
+
+impl NodeServices {
+    /// Start the services the environment enables and add them to `context`
+    pub fn start(
+        mut context: ServerContext,
+        db: &Arc<DatabaseManager>,
+        options: ServiceOptions<'_>,
+    ) -> Result<(ServerContext, Self), Box<dyn Error>> {
+        let mut services = NodeServices::default();
+        context = context.with_finality(options.finality);
+        let signer = OracleSigner::from_env(options.node_id)
+            .map_err(ExitError::config)?
+            .map(Arc::new);
+        if let Some(signer) = &signer {
+            info!(
+                public_key = %signer.public_key(),
+                "Oracle: Serving signed prices on /oracle/price"
+            );
+            context = context.with_oracle(signer.clone());
+        }
+        if let Some(config) = AttestationConfig::from_env() {
+            let Some(signer) = &signer else {
+                return Err(ExitError::config(
+                    "ATTESTATION_INTERVAL_SECS needs NODE_SIGNING_KEY to sign with",
+                )
+                .into());
+            };
+            Arc::new(Attestor::new(db.clone(), signer.clone(), config)).spawn();
+        }
+        let anchoring = options.features.is_enabled(Feature::Anchoring);
+        let anchor_config = AnchorConfig::from_env();
+        if anchor_config.is_some() && !anchoring {
+            warn!("Anchor: Anchoring feature disabled, ignoring ANCHOR_INTERVAL_SECS");
+        }
+        if let Some(config) = anchor_config.filter(|_| anchoring) {
+            let anchorer = Arc::new(Anchorer::new(db.clone(), config));
+            anchorer.clone().spawn();
+            context = context.with_anchorer(anchorer);
+        }
+        services.accounts = AccountBook::from_env(db.clone())?.map(Arc::new);
+        if let Some(book) = &services.accounts {
+            info!(
+                fee_per_entry = book.schedule().fee_per_entry,
+                submitters = book.accounts().len(),
+                "Accounting: Fee accounting enabled"
+            );
+            context = context.with_accounts(book.clone());
+        }
+        services.tenants = TenantRegistry::from_env()
+            .map_err(ExitError::config)?
+            .map(Arc::new);
+        if let Some(registry) = &services.tenants {
+            info!(tenants = ?registry.tenant_ids(), "Tenant: Multi-tenancy enabled");
+            context = context.with_tenants(registry.clone());
+        }
+        services.mempool = match Mempool::from_env().map_err(ExitError::config)? {
+            Some(_) if !options.pbft => {
+                warn!("Forward: FORWARD_TO_PRIMARY only applies to PBFT, ignoring it");
+                None
+            }
+            Some(mempool) => {
+                info!("Forward: Followers forward their entries to the primary");
+                let mempool = Arc::new(mempool);
+                context = context.with_mempool(mempool.clone());
+                Some(mempool)
+            }
+            None => None,
+        };
+        if let Some(mut policy) = AccessPolicy::from_env().map_err(ExitError::config)? {
+            // The admin token stays valid as an admin key once roles are enforced
+            if let Some(token) = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) {
+                policy = policy.with_key("admin", Role::Admin, token);
+            }
+            info!("Access: Role-based access control enabled");
+            let policy = Arc::new(policy);
+            policy.clone().spawn_audit_flusher();
+            context = context.with_access_policy(policy);
+        }
+        let storage_limits = StorageLimits::from_env().map_err(ExitError::config)?;
+        if storage_limits.is_enabled() {
+            let guard = Arc::new(StorageGuard::new(options.db_path, storage_limits));
+            context = context.with_storage_guard(guard.clone());
+            services.storage_guard = Some(guard);
+        }
+        if let Some(config) = VerificationConfig::from_env() {
+            let verifier = Arc::new(RollingVerifier::new(db.clone(), config));
+            context = context.with_verifier(verifier.clone());
+            verifier.spawn();
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(config) = super::grpc::GrpcConfig::from_env().map_err(ExitError::config)? {
+            let db = db.clone();
+            let finality = options.finality;
+            tokio::spawn(async move {
+                if let Err(e) = super::grpc::LedgerGrpc::serve(db, config, finality).await {
+                    warn!(error = %e, "gRPC: LedgerService stopped");
+                }
+            });
+        }
+        #[cfg(not(feature = "grpc"))]
+        if std::env::var("GRPC_PORT").is_ok() {
+            warn!("gRPC: GRPC_PORT is set but this build lacks the grpc feature");
+        }
+        if let Some(config) =
+            crate::etl::pg_notify::NotifyConfig::from_env().map_err(ExitError::config)?
+        {
+            let commits = db.subscribe_commits();
+            tokio::spawn(async move {
+                if let Err(e) = crate::etl::pg_notify::publish(commits, config).await {
+                    warn!(error = %e, "Notify: Commit notifications stopped");
+                }
+            });
+        }
+        Ok((context, services))
+    }
+}
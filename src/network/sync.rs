@@ -0,0 +1,812 @@
+//! Catching up on blocks from peers
+//!
+//! A node that starts behind its peers pulls the missing blocks from their
+//! `/blocks` route. Peers are not trusted blindly: every block runs through
+//! the syncer's `BlockVerifier`s before it is appended, and a block that
+//! fails is written to the quarantine table instead of the chain. Syncing
+//! from that peer stops there, since later blocks cannot link to it.
+//!
+//! Hash recomputation, chain linkage and HLC ordering are always checked.
+//! Signature or commit-certificate checks plug in through
+//! `ChainSyncer::with_verifier`.
+//!
+//! A new node does not have to sync from genesis. Given a trusted
+//! `Checkpoint` (a height and block hash published by the cluster's
+//! operators), `ChainSyncer::bootstrap` starts an empty ledger at that
+//! block: either from a block export, whose history must run through the
+//! same verifiers and end at the checkpoint hash, or from the checkpoint
+//! block alone, fetched from a peer. Forward sync then continues from the
+//! checkpoint's successor.
+
+use crate::etl::group_commit::GroupCommitter;
+use crate::etl::load::{DatabaseManager, DbResult};
+use crate::etl::Block;
+use crate::network::peer_addr::{is_local, PeerAddr};
+use crate::retry::{classify_reqwest, RetryPolicy};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Upper bound on the number of blocks served per `/blocks` request
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+/// Time allowed for downloading a checkpoint snapshot
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A block the operator trusts to start a new node from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: String,
+    /// Block export (JSON lines or a JSON array) holding the history up to
+    /// the checkpoint: an http(s) URL or a local path
+    pub snapshot_url: Option<String>,
+}
+
+impl Checkpoint {
+    pub fn new(height: u64, hash: impl Into<String>) -> Self {
+        Checkpoint {
+            height,
+            hash: hash.into(),
+            snapshot_url: None,
+        }
+    }
+
+    pub fn with_snapshot_url(mut self, url: impl Into<String>) -> Self {
+        self.snapshot_url = Some(url.into());
+        self
+    }
+
+    /// Parse `<height>:<hash>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (height, hash) = value
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("expected <height>:<hash>, got '{}'", value))?;
+        let height: u64 = height
+            .parse()
+            .map_err(|e| format!("invalid checkpoint height '{}': {}", height, e))?;
+        if height == 0 {
+            return Err("checkpoint height starts at 1".to_string());
+        }
+        if hash.is_empty() {
+            return Err("checkpoint hash is empty".to_string());
+        }
+        Ok(Self::new(height, hash))
+    }
+
+    /// `None` unless `CHECKPOINT` is set; `CHECKPOINT_SNAPSHOT_URL` adds the
+    /// snapshot
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(value) = std::env::var("CHECKPOINT") else {
+            return Ok(None);
+        };
+        let mut checkpoint =
+            Self::parse(&value).map_err(|e| format!("invalid CHECKPOINT: {}", e))?;
+        if let Ok(url) = std::env::var("CHECKPOINT_SNAPSHOT_URL") {
+            checkpoint = checkpoint.with_snapshot_url(url);
+        }
+        Ok(Some(checkpoint))
+    }
+}
+
+/// Blocks from an export: a JSON array or one block per line
+fn parse_snapshot(body: &str) -> Result<Vec<Block>, String> {
+    if body.trim_start().starts_with('[') {
+        return serde_json::from_str(body).map_err(|e| e.to_string());
+    }
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line).map_err(|e| format!("line {}: {}", number + 1, e))
+        })
+        .collect()
+}
+
+/// Check applied to every block pulled from a peer before it is appended
+pub trait BlockVerifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Verify `block` as the successor of `parent`, the current local head
+    /// (`None` when the local chain is empty)
+    fn verify(&self, block: &Block, parent: Option<&Block>) -> Result<(), String>;
+}
+
+/// Recomputes the block hash and compares it with the one the peer sent
+pub struct HashVerifier;
+
+impl BlockVerifier for HashVerifier {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn verify(&self, block: &Block, _parent: Option<&Block>) -> Result<(), String> {
+        let calculated = block.calculate_hash();
+        if calculated != block.hash {
+            return Err(format!(
+                "hash mismatch: block claims {}, contents hash to {}",
+                block.hash, calculated
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `HashVerifier` for blocks already in the local store: a block whose
+/// payload was redacted passes if its payload is empty and it kept the hash
+/// a trusted redaction record covers. Peers cannot use this; a redacted
+/// block never syncs.
+pub struct StoredHashVerifier {
+    redacted: HashMap<u64, String>,
+}
+
+impl StoredHashVerifier {
+    pub fn new(db: &DatabaseManager) -> DbResult<Self> {
+        Ok(StoredHashVerifier {
+            redacted: db.get_redacted_hashes()?,
+        })
+    }
+}
+
+impl BlockVerifier for StoredHashVerifier {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn verify(&self, block: &Block, parent: Option<&Block>) -> Result<(), String> {
+        if self.redacted.get(&block.index) == Some(&block.hash) && block.has_empty_payload() {
+            return Ok(());
+        }
+        HashVerifier.verify(block, parent)
+    }
+}
+
+/// Checks that the block directly extends the local head
+pub struct LinkVerifier;
+
+impl BlockVerifier for LinkVerifier {
+    fn name(&self) -> &str {
+        "link"
+    }
+
+    fn verify(&self, block: &Block, parent: Option<&Block>) -> Result<(), String> {
+        let Some(parent) = parent else {
+            return Ok(());
+        };
+        if block.index != parent.index + 1 {
+            return Err(format!(
+                "expected index {}, got {}",
+                parent.index + 1,
+                block.index
+            ));
+        }
+        if block.previous_hash != parent.hash {
+            return Err(format!(
+                "previous hash {} does not match local head {}",
+                block.previous_hash, parent.hash
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the block orders after its parent in hybrid logical time
+///
+/// Wall-clock timestamps are not compared: skew between proposers can make
+/// them run backwards. Blocks without an HLC (built before nodes kept one)
+/// are not checked.
+pub struct HlcVerifier;
+
+impl BlockVerifier for HlcVerifier {
+    fn name(&self) -> &str {
+        "hlc"
+    }
+
+    fn verify(&self, block: &Block, parent: Option<&Block>) -> Result<(), String> {
+        let (Some(hlc), Some(parent_hlc)) = (block.hlc, parent.and_then(|p| p.hlc)) else {
+            return Ok(());
+        };
+        if hlc <= parent_hlc {
+            return Err(format!(
+                "HLC {} does not order after parent's {}",
+                hlc, parent_hlc
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of syncing from one peer
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Number of blocks verified and appended
+    pub appended: usize,
+    /// Index and reason of the block that failed verification, if any
+    pub quarantined: Option<(u64, String)>,
+}
+
+/// The blocks of one page that verified, and the block quarantined after
+/// them, if any
+struct VerifiedPage {
+    blocks: Vec<Block>,
+    quarantined: Option<(u64, String)>,
+}
+
+/// Pulls missing blocks from peers and appends the ones that verify
+pub struct ChainSyncer {
+    db: Arc<DatabaseManager>,
+    client: reqwest::Client,
+    verifiers: Vec<Box<dyn BlockVerifier>>,
+    /// Bearer key sent to peers that enforce access control
+    api_key: Option<String>,
+    retry: RetryPolicy,
+    /// Writes verified pages alongside the node's own commits
+    committer: Option<GroupCommitter>,
+}
+
+impl ChainSyncer {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        ChainSyncer {
+            db,
+            client,
+            verifiers: vec![
+                Box::new(LinkVerifier),
+                Box::new(HlcVerifier),
+                Box::new(HashVerifier),
+            ],
+            api_key: None,
+            retry: super::PEER_RETRY.clone(),
+            committer: None,
+        }
+    }
+
+    /// Authenticate to peers with a reader key (see `rbac`)
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Write synced blocks through the node's group committer
+    pub fn with_committer(mut self, committer: GroupCommitter) -> Self {
+        self.committer = Some(committer);
+        self
+    }
+
+    /// Add a verifier that runs after the built-in hash and link checks
+    pub fn with_verifier(mut self, verifier: impl BlockVerifier + 'static) -> Self {
+        self.verifiers.push(Box::new(verifier));
+        self
+    }
+
+    fn verify(&self, block: &Block, parent: Option<&Block>) -> Result<(), String> {
+        for verifier in &self.verifiers {
+            verifier
+                .verify(block, parent)
+                .map_err(|reason| format!("{}: {}", verifier.name(), reason))?;
+        }
+        Ok(())
+    }
+
+    /// Verify and append `blocks` (ascending by index) received from `peer`
+    ///
+    /// Blocks at or below the local head are skipped. The first block that
+    /// fails verification is quarantined and nothing after it is applied.
+    /// The blocks that verified are written in one transaction.
+    pub fn apply_blocks(&self, peer: &str, blocks: &[Block]) -> DbResult<SyncReport> {
+        let page = self.verify_page(peer, blocks)?;
+        if !page.blocks.is_empty() {
+            self.db.save_blocks(&page.blocks)?;
+        }
+        Ok(SyncReport {
+            appended: page.blocks.len(),
+            quarantined: page.quarantined,
+        })
+    }
+
+    /// `apply_blocks`, writing through the group committer when there is one
+    async fn apply_page(&self, peer: &str, blocks: &[Block]) -> DbResult<SyncReport> {
+        let Some(committer) = &self.committer else {
+            return self.apply_blocks(peer, blocks);
+        };
+        let page = self.verify_page(peer, blocks)?;
+        let mut report = SyncReport {
+            appended: 0,
+            quarantined: page.quarantined,
+        };
+        for result in committer.commit_all(page.blocks).await {
+            result?;
+            report.appended += 1;
+        }
+        Ok(report)
+    }
+
+    /// The blocks of `blocks` past the local head that verify, up to the
+    /// first that does not, which is quarantined
+    fn verify_page(&self, peer: &str, blocks: &[Block]) -> DbResult<VerifiedPage> {
+        let mut verified: Vec<Block> = Vec::new();
+        let mut quarantined = None;
+        let local_head = self.db.get_latest_block()?;
+
+        for block in blocks {
+            let head = verified.last().or(local_head.as_ref());
+            if let Some(head) = head {
+                if block.index <= head.index {
+                    debug!(block_index = block.index, "Sync: Skipping known block");
+                    continue;
+                }
+            }
+
+            if let Err(reason) = self.verify(block, head) {
+                warn!(
+                    block_index = block.index,
+                    peer = %peer,
+                    reason = %reason,
+                    "Sync: Block failed verification"
+                );
+                self.db.quarantine_block(block, peer, &reason)?;
+                quarantined = Some((block.index, reason));
+                break;
+            }
+
+            verified.push(block.clone());
+        }
+
+        Ok(VerifiedPage {
+            blocks: verified,
+            quarantined,
+        })
+    }
+
+    async fn fetch_blocks(
+        &self,
+        peer: &str,
+        from: u64,
+        limit: u64,
+    ) -> Result<Vec<Block>, reqwest::Error> {
+        let mut request = self.client.get(format!(
+            "http://{}/blocks?from={}&limit={}",
+            peer, from, limit
+        ));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        request.send().await?.error_for_status()?.json().await
+    }
+
+    /// Pull blocks past the local head from `peer` until it has no more or
+    /// a block fails verification
+    pub async fn sync_from(&self, peer: &str) -> Result<SyncReport, Box<dyn Error>> {
+        let mut report = SyncReport::default();
+
+        loop {
+            let from = self.db.get_latest_block()?.map_or(1, |b| b.index + 1);
+            let blocks = self
+                .retry
+                .run(
+                    |_| self.fetch_blocks(peer, from, MAX_BLOCKS_PER_REQUEST),
+                    classify_reqwest,
+                )
+                .await?;
+
+            let batch = self.apply_page(peer, &blocks).await?;
+            report.appended += batch.appended;
+
+            if batch.quarantined.is_some() {
+                report.quarantined = batch.quarantined;
+                break;
+            }
+            if (blocks.len() as u64) < MAX_BLOCKS_PER_REQUEST || batch.appended == 0 {
+                break;
+            }
+        }
+
+        info!(
+            peer = %peer,
+            appended = report.appended,
+            quarantined = report.quarantined.is_some(),
+            "Sync: Finished syncing from peer"
+        );
+        Ok(report)
+    }
+
+    /// Sync from every peer except ourselves, in order
+    pub async fn sync_from_peers(
+        &self,
+        peer_addresses: &[String],
+        local: &PeerAddr,
+    ) -> Vec<(String, Result<SyncReport, String>)> {
+        let mut results = Vec::new();
+        for addr in peer_addresses {
+            if is_local(addr, local) {
+                continue;
+            }
+            let result = self.sync_from(addr).await.map_err(|e| e.to_string());
+            results.push((addr.clone(), result));
+        }
+        results
+    }
+
+    /// Verify `blocks` (ascending by index) as the history ending at
+    /// `checkpoint` and store them in the empty ledger
+    ///
+    /// Blocks past the checkpoint are ignored. Every block runs through the
+    /// syncer's verifiers, starting without a parent, and the last one must
+    /// be the checkpoint block; otherwise nothing is stored.
+    pub fn apply_checkpoint(
+        &self,
+        checkpoint: &Checkpoint,
+        blocks: &[Block],
+    ) -> Result<usize, Box<dyn Error>> {
+        if self.db.get_latest_block()?.is_some() {
+            return Err("the ledger is not empty".into());
+        }
+        let history: Vec<&Block> = blocks
+            .iter()
+            .take_while(|b| b.index <= checkpoint.height)
+            .collect();
+        let Some(tip) = history.last() else {
+            return Err(format!("no blocks up to height {}", checkpoint.height).into());
+        };
+        if tip.index != checkpoint.height || tip.hash != checkpoint.hash {
+            return Err(format!(
+                "history ends at block {} ({}), expected checkpoint {} ({})",
+                tip.index, tip.hash, checkpoint.height, checkpoint.hash
+            )
+            .into());
+        }
+        let mut parent = None;
+        for block in &history {
+            self.verify(block, parent)
+                .map_err(|reason| format!("block {}: {}", block.index, reason))?;
+            parent = Some(*block);
+        }
+        let history: Vec<Block> = history.into_iter().cloned().collect();
+        Ok(self.db.save_blocks(&history)?)
+    }
+
+    async fn fetch_snapshot(&self, url: &str) -> Result<Vec<Block>, Box<dyn Error>> {
+        let body = if url.starts_with("http://") || url.starts_with("https://") {
+            self.client
+                .get(url)
+                .timeout(SNAPSHOT_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?
+        } else {
+            std::fs::read_to_string(Path::new(url))?
+        };
+        Ok(parse_snapshot(&body).map_err(|e| format!("snapshot {}: {}", url, e))?)
+    }
+
+    /// The checkpoint block from the first peer to serve it
+    async fn fetch_checkpoint_block(
+        &self,
+        checkpoint: &Checkpoint,
+        peer_addresses: &[String],
+        local: &PeerAddr,
+    ) -> Result<Block, Box<dyn Error>> {
+        let mut failures = Vec::new();
+        // Asked concurrently, so a peer being retried does not hold up the rest
+        let mut fetches: FuturesUnordered<_> = peer_addresses
+            .iter()
+            .filter(|a| !is_local(a, local))
+            .map(|peer| async move {
+                let fetched = self
+                    .retry
+                    .run(
+                        |_| self.fetch_blocks(peer, checkpoint.height, 1),
+                        classify_reqwest,
+                    )
+                    .await;
+                (peer, fetched)
+            })
+            .collect();
+        while let Some((peer, fetched)) = fetches.next().await {
+            match fetched.map(|blocks| blocks.into_iter().next()) {
+                Ok(Some(block))
+                    if block.index == checkpoint.height && block.hash == checkpoint.hash =>
+                {
+                    return Ok(block);
+                }
+                Ok(Some(block)) => failures.push(format!(
+                    "{} served block {} ({})",
+                    peer, block.index, block.hash
+                )),
+                Ok(None) => failures.push(format!("{} has no block {}", peer, checkpoint.height)),
+                Err(e) => failures.push(format!("{}: {}", peer, e)),
+            }
+        }
+        Err(format!(
+            "no peer served checkpoint block {}: {}",
+            checkpoint.height,
+            failures.join("; ")
+        )
+        .into())
+    }
+
+    /// Start an empty ledger at `checkpoint` instead of syncing from genesis
+    ///
+    /// Returns the number of blocks stored. A ledger that already holds
+    /// blocks is left alone, but fails if its block at the checkpoint height
+    /// has a different hash.
+    pub async fn bootstrap(
+        &self,
+        checkpoint: &Checkpoint,
+        peer_addresses: &[String],
+        local: &PeerAddr,
+    ) -> Result<usize, Box<dyn Error>> {
+        if let Some(head) = self.db.get_latest_block()? {
+            if head.index >= checkpoint.height {
+                if let Ok(block) = self.db.get_block_by_index(checkpoint.height) {
+                    if block.hash != checkpoint.hash {
+                        return Err(format!(
+                            "local block {} has hash {}, checkpoint expects {}",
+                            checkpoint.height, block.hash, checkpoint.hash
+                        )
+                        .into());
+                    }
+                }
+            }
+            debug!(
+                head = head.index,
+                "Sync: Ledger not empty, skipping checkpoint"
+            );
+            return Ok(0);
+        }
+
+        let blocks = match &checkpoint.snapshot_url {
+            Some(url) => self.fetch_snapshot(url).await?,
+            None => vec![
+                self.fetch_checkpoint_block(checkpoint, peer_addresses, local)
+                    .await?,
+            ],
+        };
+        let stored = self.apply_checkpoint(checkpoint, &blocks)?;
+        info!(
+            height = checkpoint.height,
+            stored,
+            from_snapshot = checkpoint.snapshot_url.is_some(),
+            "Sync: Bootstrapped ledger from checkpoint"
+        );
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::testing::TestChainBuilder;
+    use std::fs;
+
+    fn make_chain(len: u64) -> Vec<Block> {
+        TestChainBuilder::new().with_blocks(len).build()
+    }
+
+    fn open_db(path: &str) -> Arc<DatabaseManager> {
+        fs::remove_file(path).ok();
+        let db = DatabaseManager::new(path).unwrap();
+        db.init().unwrap();
+        Arc::new(db)
+    }
+
+    #[test]
+    fn test_apply_blocks_appends_verified_chain() {
+        let test_db = "test_sync_apply.db";
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        let chain = make_chain(3);
+
+        db.save_block(&chain[0]).unwrap();
+        let report = syncer.apply_blocks("peer", &chain).unwrap();
+
+        assert_eq!(report.appended, 2);
+        assert!(report.quarantined.is_none());
+        assert_eq!(db.get_block_count().unwrap(), 3);
+        assert!(db.verify_chain().unwrap());
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_apply_blocks_quarantines_tampered_block() {
+        let test_db = "test_sync_tampered.db";
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        let mut chain = make_chain(3);
+
+        // Peer rewrote a price but kept the original hash
+        chain[1].data[0].price = Decimal::ONE;
+
+        let report = syncer.apply_blocks("127.0.0.1:8001", &chain).unwrap();
+
+        assert_eq!(report.appended, 1);
+        let (index, reason) = report.quarantined.unwrap();
+        assert_eq!(index, 2);
+        assert!(reason.starts_with("hash:"));
+
+        assert_eq!(db.get_block_count().unwrap(), 1);
+        let quarantined = db.get_quarantined_blocks(10).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].block.index, 2);
+        assert_eq!(quarantined[0].peer, "127.0.0.1:8001");
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_apply_blocks_rejects_unlinked_block() {
+        let test_db = "test_sync_unlinked.db";
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        let chain = make_chain(3);
+
+        db.save_block(&chain[0]).unwrap();
+        // Block 3 arrives without block 2
+        let report = syncer.apply_blocks("peer", &chain[2..]).unwrap();
+
+        assert_eq!(report.appended, 0);
+        assert!(report.quarantined.unwrap().1.starts_with("link:"));
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_apply_blocks_orders_by_hlc_not_wall_clock() {
+        let test_db = "test_sync_hlc.db";
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        let mut chain = make_chain(3);
+        let stamp = |wall_ms: i64, node_id: usize| {
+            Some(crate::etl::hlc::HlcTimestamp {
+                wall_ms,
+                logical: 0,
+                node_id,
+            })
+        };
+        // Block 2's proposer runs a minute behind, but its HLC adopted the
+        // time it had seen from block 1's proposer; block 3's HLC went back
+        chain[0].hlc = stamp(1_234_567_891_000, 0);
+        chain[1].timestamp -= 60_000;
+        chain[1].hlc = Some(crate::etl::hlc::HlcTimestamp {
+            logical: 1,
+            ..stamp(1_234_567_891_000, 1).unwrap()
+        });
+        chain[2].hlc = stamp(1_234_567_890_000, 2);
+        let mut previous_hash = "0000_genesis_hash".to_string();
+        for block in chain.iter_mut() {
+            block.previous_hash = previous_hash;
+            block.calculate_hash_with_nonce();
+            previous_hash = block.hash.clone();
+        }
+
+        let report = syncer.apply_blocks("peer", &chain).unwrap();
+
+        assert_eq!(report.appended, 2);
+        let (index, reason) = report.quarantined.unwrap();
+        assert_eq!(index, 3);
+        assert!(reason.starts_with("hlc:"));
+
+        fs::remove_file(test_db).ok();
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let checkpoint = Checkpoint::parse("1200:abc123").unwrap();
+        assert_eq!(checkpoint, Checkpoint::new(1200, "abc123"));
+        assert!(Checkpoint::parse("abc123").is_err());
+        assert!(Checkpoint::parse("0:abc123").is_err());
+        assert!(Checkpoint::parse("12:").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_verifies_snapshot_against_checkpoint() {
+        let test_db = "test_sync_checkpoint.db";
+        let snapshot = "test_sync_checkpoint.jsonl";
+        let chain = make_chain(5);
+        let lines: Vec<String> = chain
+            .iter()
+            .map(|b| serde_json::to_string(b).unwrap())
+            .collect();
+        fs::write(snapshot, lines.join("\n")).unwrap();
+
+        // A checkpoint the history does not lead to stores nothing
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        let forged = Checkpoint::new(4, "feedface").with_snapshot_url(snapshot);
+        let err = syncer
+            .bootstrap(&forged, &[], &PeerAddr::loopback(8000))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("expected checkpoint 4"), "{}", err);
+        assert_eq!(db.get_block_count().unwrap(), 0);
+
+        // Blocks past the checkpoint are left to forward sync
+        let checkpoint = Checkpoint::new(4, chain[3].hash.clone()).with_snapshot_url(snapshot);
+        assert_eq!(
+            syncer
+                .bootstrap(&checkpoint, &[], &PeerAddr::loopback(8000))
+                .await
+                .unwrap(),
+            4
+        );
+        assert_eq!(db.get_latest_block().unwrap().unwrap().hash, chain[3].hash);
+        assert_eq!(syncer.apply_blocks("peer", &chain).unwrap().appended, 1);
+
+        // Restarting with the same checkpoint is a no-op; a conflicting one fails
+        assert_eq!(
+            syncer
+                .bootstrap(&checkpoint, &[], &PeerAddr::loopback(8000))
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(syncer
+            .bootstrap(&forged, &[], &PeerAddr::loopback(8000))
+            .await
+            .is_err());
+
+        // Without a snapshot the node starts at the checkpoint block alone
+        fs::remove_file(test_db).ok();
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone());
+        assert_eq!(
+            syncer
+                .apply_checkpoint(&Checkpoint::new(4, chain[3].hash.clone()), &chain[3..4])
+                .unwrap(),
+            1
+        );
+        let mut tampered = chain[4].clone();
+        tampered.data[0].price = Decimal::ONE;
+        let report = syncer.apply_blocks("peer", &[tampered]).unwrap();
+        assert!(report.quarantined.unwrap().1.starts_with("hash:"));
+
+        fs::remove_file(test_db).ok();
+        fs::remove_file(snapshot).ok();
+    }
+
+    struct RejectAll;
+
+    impl BlockVerifier for RejectAll {
+        fn name(&self) -> &str {
+            "signature"
+        }
+
+        fn verify(&self, _block: &Block, _parent: Option<&Block>) -> Result<(), String> {
+            Err("no valid commit certificate".to_string())
+        }
+    }
+
+    #[test]
+    fn test_custom_verifier_runs_after_builtin_checks() {
+        let test_db = "test_sync_custom.db";
+        let db = open_db(test_db);
+        let syncer = ChainSyncer::new(db.clone()).with_verifier(RejectAll);
+
+        let report = syncer.apply_blocks("peer", &make_chain(1)).unwrap();
+
+        assert_eq!(report.appended, 0);
+        assert_eq!(
+            report.quarantined.unwrap().1,
+            "signature: no valid commit certificate"
+        );
+
+        fs::remove_file(test_db).ok();
+    }
+}
@@ -0,0 +1,688 @@
+//! Tenant isolation for a shared node
+//!
+//! Each API key maps to one tenant. Tenants submit entries with
+//! `POST /tenant/submit`; their assets are stored on the shared chain under
+//! the tenant's namespace (`<tenant>/<asset>`), so entries from different
+//! tenants never collide. `GET /tenant/blocks` returns the chain as the
+//! tenant sees it: only its own entries, with the namespace stripped, and
+//! `GET /tenant/stats` reports its usage against the quota.
+//!
+//! Submitted entries wait in a queue until the block loop includes them in
+//! the next block. Their `source` is always the tenant, so fee accounting
+//! bills the tenant that submitted them; entries whose tenant cannot pay are
+//! dropped from the queue rather than holding up the entries behind them.
+//! Requests authenticate with `X-API-Key`.
+//!
+//! Configured with `TENANT_API_KEYS` (`TENANT=KEY,...`, enables tenancy),
+//! `TENANT_QUOTA_ENTRIES` and `TENANT_QUOTA_WINDOW_SECS`.
+
+use crate::etl::accounting::FeeRecord;
+use crate::etl::price::Decimal;
+use crate::etl::validator::{Candidate, Validator};
+use crate::etl::{now_millis, Block, MarketData};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use tracing::info;
+
+use super::admin::constant_time_eq;
+use super::pagination::{self, Page};
+use super::ServerContext;
+
+/// Header carrying a tenant's API key
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Separator between a tenant id and its asset symbols
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// Tenant entries included in a single block
+pub const MAX_ENTRIES_PER_BLOCK: usize = 500;
+
+/// Quota window unless `TENANT_QUOTA_WINDOW_SECS` is set
+pub const DEFAULT_QUOTA_WINDOW_SECS: u64 = 3600;
+
+/// `TENANT_QUOTA_ENTRIES` and `TENANT_QUOTA_WINDOW_SECS` (default
+/// `DEFAULT_QUOTA_WINDOW_SECS`) read through `lookup`; `None` without a quota
+fn quota_from(lookup: impl Fn(&str) -> Option<String>) -> Result<Option<(u64, u64)>, String> {
+    let Some(entries) = lookup("TENANT_QUOTA_ENTRIES") else {
+        return Ok(None);
+    };
+    let entries = entries.trim().parse().map_err(|_| {
+        format!(
+            "invalid TENANT_QUOTA_ENTRIES '{}' (expected a number of entries)",
+            entries
+        )
+    })?;
+    let window = match lookup("TENANT_QUOTA_WINDOW_SECS") {
+        Some(window) => window
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&secs: &u64| secs > 0)
+            .ok_or_else(|| {
+                format!(
+                    "invalid TENANT_QUOTA_WINDOW_SECS '{}' (expected seconds, at least 1)",
+                    window
+                )
+            })?,
+        None => DEFAULT_QUOTA_WINDOW_SECS,
+    };
+    Ok(Some((entries, window)))
+}
+
+/// Usage of one tenant
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TenantStats {
+    /// Entries accepted for inclusion
+    pub submitted: u64,
+    /// Entries included in committed blocks
+    pub committed: u64,
+    /// Entries waiting for the next block
+    pub pending: u64,
+    /// Entries refused for exceeding the quota
+    pub rejected: u64,
+    /// Queued entries dropped because the tenant could not pay their fees
+    pub dropped: u64,
+    /// Entries accepted in the current quota window
+    pub window_used: u64,
+    pub quota: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TenantError {
+    UnknownTenant(String),
+    QuotaExceeded { limit: u64, window_secs: u64 },
+    Invalid(String),
+}
+
+impl fmt::Display for TenantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TenantError::UnknownTenant(id) => write!(f, "unknown tenant '{}'", id),
+            TenantError::QuotaExceeded { limit, window_secs } => write!(
+                f,
+                "quota of {} entries per {}s exceeded",
+                limit, window_secs
+            ),
+            TenantError::Invalid(reason) => write!(f, "invalid submission: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TenantError {}
+
+/// One entry in a `POST /tenant/submit` body
+#[derive(Debug, Clone, Deserialize)]
+pub struct Submission {
+    pub asset: String,
+    /// A JSON number or decimal string
+    #[serde(with = "crate::etl::price::serde_number")]
+    pub price: Decimal,
+    /// The tenant id; any other source is refused
+    pub source: Option<String>,
+    /// Milliseconds; defaults to the time of submission
+    pub timestamp: Option<i64>,
+}
+
+/// A block restricted to one tenant's entries
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantBlock {
+    pub index: u64,
+    pub timestamp: i64,
+    pub hash: String,
+    pub data: Vec<MarketData>,
+    /// What the tenant paid for its entries, when accounting is enabled
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fees: Vec<FeeRecord>,
+}
+
+#[derive(Debug, Default)]
+struct TenantState {
+    stats: TenantStats,
+    window_started_at: i64,
+}
+
+/// Tenants, their API keys and quotas, and entries awaiting a block
+pub struct TenantRegistry {
+    /// API key and tenant id pairs
+    keys: Vec<(String, String)>,
+    quota: Option<u64>,
+    quota_window_secs: u64,
+    validator: Validator,
+    tenants: Mutex<BTreeMap<String, TenantState>>,
+    pending: Mutex<VecDeque<MarketData>>,
+}
+
+impl Default for TenantRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TenantRegistry {
+    pub fn new() -> Self {
+        TenantRegistry {
+            keys: Vec::new(),
+            quota: None,
+            quota_window_secs: DEFAULT_QUOTA_WINDOW_SECS,
+            validator: Validator::new(),
+            tenants: Mutex::new(BTreeMap::new()),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn with_tenant(mut self, id: impl Into<String>, api_key: impl Into<String>) -> Self {
+        let id = id.into();
+        self.tenants.get_mut().entry(id.clone()).or_default();
+        self.keys.push((api_key.into(), id));
+        self
+    }
+
+    /// Limit every tenant to `entries` per `window_secs`
+    pub fn with_quota(mut self, entries: u64, window_secs: u64) -> Self {
+        self.quota = Some(entries);
+        self.quota_window_secs = window_secs.max(1);
+        self
+    }
+
+    /// `None` unless `TENANT_API_KEYS` is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(keys) = std::env::var("TENANT_API_KEYS") else {
+            return Ok(None);
+        };
+        let mut registry = Self::new();
+        // Tenants' entries are held to the node's per-class price ranges
+        registry.validator = Validator::from_env()?;
+        for entry in keys.split(',').filter(|e| !e.trim().is_empty()) {
+            let (id, key) = entry
+                .split_once('=')
+                .map(|(id, key)| (id.trim(), key.trim()))
+                .filter(|(id, key)| !id.is_empty() && !key.is_empty())
+                .ok_or_else(|| format!("invalid TENANT_API_KEYS entry '{}'", entry))?;
+            if id.contains(NAMESPACE_SEPARATOR) {
+                return Err(format!(
+                    "tenant id '{}' must not contain '{}'",
+                    id, NAMESPACE_SEPARATOR
+                ));
+            }
+            registry = registry.with_tenant(id, key);
+        }
+        if let Some((quota, window)) = quota_from(|name| std::env::var(name).ok())? {
+            registry = registry.with_quota(quota, window);
+        }
+        Ok(Some(registry))
+    }
+
+    /// Tenant owning `api_key`
+    pub fn authenticate(&self, api_key: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(key, _)| constant_time_eq(key.as_bytes(), api_key.as_bytes()))
+            .map(|(_, id)| id.as_str())
+    }
+
+    pub fn tenant_ids(&self) -> Vec<String> {
+        self.tenants.lock().keys().cloned().collect()
+    }
+
+    /// Asset symbol as stored on the chain
+    pub fn namespaced(tenant: &str, asset: &str) -> String {
+        format!("{}{}{}", tenant, NAMESPACE_SEPARATOR, asset)
+    }
+
+    /// Asset symbol as the tenant knows it, if `asset` is in its namespace
+    pub fn strip_namespace<'a>(tenant: &str, asset: &'a str) -> Option<&'a str> {
+        asset
+            .strip_prefix(tenant)
+            .and_then(|rest| rest.strip_prefix(NAMESPACE_SEPARATOR))
+    }
+
+    /// Validate and queue a tenant's entries; returns how many were queued
+    ///
+    /// The submission is accepted or refused as a whole.
+    pub fn submit(&self, tenant: &str, entries: Vec<Submission>) -> Result<usize, TenantError> {
+        let now = now_millis();
+        let mut data = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if entry.asset.contains(NAMESPACE_SEPARATOR) {
+                return Err(TenantError::Invalid(format!(
+                    "asset '{}' must not contain '{}'",
+                    entry.asset, NAMESPACE_SEPARATOR
+                )));
+            }
+            if let Some(source) = entry.source.as_deref().filter(|s| *s != tenant) {
+                return Err(TenantError::Invalid(format!(
+                    "source '{}' is not the tenant '{}'",
+                    source, tenant
+                )));
+            }
+            let source = tenant.to_string();
+            let timestamp = entry.timestamp.unwrap_or(now);
+            self.validator
+                .validate(&Candidate {
+                    asset: &entry.asset,
+                    price: entry.price,
+                    timestamp,
+                    source: &source,
+                })
+                .map_err(|e| TenantError::Invalid(e.to_string()))?;
+            data.push(MarketData {
+                asset: Self::namespaced(tenant, &entry.asset),
+                price: entry.price,
+                source,
+                timestamp,
+                provenance: None,
+                indicators: None,
+                conversion: None,
+                bucket: None,
+            });
+        }
+
+        let mut tenants = self.tenants.lock();
+        let state = tenants
+            .get_mut(tenant)
+            .ok_or_else(|| TenantError::UnknownTenant(tenant.to_string()))?;
+        let count = data.len() as u64;
+        if let Some(limit) = self.quota {
+            let window_ms = self.quota_window_secs as i64 * 1000;
+            if now - state.window_started_at >= window_ms {
+                state.window_started_at = now;
+                state.stats.window_used = 0;
+            }
+            if state.stats.window_used + count > limit {
+                state.stats.rejected += count;
+                return Err(TenantError::QuotaExceeded {
+                    limit,
+                    window_secs: self.quota_window_secs,
+                });
+            }
+        }
+        state.stats.window_used += count;
+        state.stats.submitted += count;
+        state.stats.pending += count;
+        self.pending.lock().extend(data);
+        Ok(count as usize)
+    }
+
+    /// Up to `max` queued entries, oldest first, without removing them
+    pub fn pending(&self, max: usize) -> Vec<MarketData> {
+        self.pending.lock().iter().take(max).cloned().collect()
+    }
+
+    /// Remove a committed block's tenant entries from the queue and count
+    /// them as committed
+    ///
+    /// Entries are only ever taken from the front of the queue, so the
+    /// block's tenant entries are the queue's first ones.
+    pub fn record_committed(&self, block: &Block) {
+        let mut tenants = self.tenants.lock();
+        let mut pending = self.pending.lock();
+        for item in &block.data {
+            let Some((tenant, _)) = item.asset.split_once(NAMESPACE_SEPARATOR) else {
+                continue;
+            };
+            let Some(state) = tenants.get_mut(tenant) else {
+                continue;
+            };
+            state.stats.committed += 1;
+            if pending
+                .front()
+                .is_some_and(|p| p.asset == item.asset && p.timestamp == item.timestamp)
+            {
+                pending.pop_front();
+                state.stats.pending = state.stats.pending.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Drop every queued entry `submitter` made, e.g. because it cannot
+    /// cover their fees; returns how many were dropped
+    pub fn drop_submitter(&self, submitter: &str) -> usize {
+        let mut tenants = self.tenants.lock();
+        let Some(state) = tenants.get_mut(submitter) else {
+            return 0;
+        };
+        let mut pending = self.pending.lock();
+        let before = pending.len();
+        pending.retain(|item| item.source != submitter);
+        let dropped = (before - pending.len()) as u64;
+        state.stats.pending = state.stats.pending.saturating_sub(dropped);
+        state.stats.dropped += dropped;
+        dropped as usize
+    }
+
+    pub fn stats(&self, tenant: &str) -> Option<TenantStats> {
+        self.tenants.lock().get(tenant).map(|state| TenantStats {
+            quota: self.quota,
+            ..state.stats.clone()
+        })
+    }
+
+    /// `block` as `tenant` sees it; `None` if it holds none of its entries
+    pub fn scope_block(tenant: &str, block: &Block) -> Option<TenantBlock> {
+        let data: Vec<MarketData> = block
+            .data
+            .iter()
+            .filter_map(|item| {
+                Self::strip_namespace(tenant, &item.asset).map(|asset| MarketData {
+                    asset: asset.to_string(),
+                    ..item.clone()
+                })
+            })
+            .collect();
+        (!data.is_empty()).then(|| TenantBlock {
+            index: block.index,
+            timestamp: block.timestamp,
+            hash: block.hash.clone(),
+            data,
+            fees: block
+                .fees
+                .iter()
+                .filter(|fee| fee.submitter == tenant)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Resolve the calling tenant from its API key
+fn authorize<'a>(
+    req: &HttpRequest,
+    context: &'a ServerContext,
+) -> Result<(&'a TenantRegistry, String), HttpResponse> {
+    let Some(registry) = context.tenants.as_deref() else {
+        return Err(HttpResponse::NotFound()
+            .json(json!({ "error": "tenancy is not enabled on this node" })));
+    };
+    req.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|key| registry.authenticate(key))
+        .map(|tenant| (registry, tenant.to_string()))
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({ "error": "invalid API key" })))
+}
+
+pub(super) async fn submit(
+    req: HttpRequest,
+    entries: web::Json<Vec<Submission>>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let (registry, tenant) = match authorize(&req, &context) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    match registry.submit(&tenant, entries.into_inner()) {
+        Ok(queued) => {
+            info!(tenant = %tenant, entries = queued, "Tenant: Entries queued");
+            HttpResponse::Accepted().json(json!({ "tenant": tenant, "queued": queued }))
+        }
+        Err(e @ TenantError::QuotaExceeded { .. }) => {
+            HttpResponse::TooManyRequests().json(json!({ "error": e.to_string() }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct TenantBlocksQuery {
+    from: Option<u64>,
+    limit: Option<u64>,
+}
+
+/// The caller's entries from blocks starting at `from`; blocks without any
+/// are omitted, so a page may hold fewer blocks than `limit` while the
+/// `pagination` headers still describe the whole ledger
+pub(super) async fn blocks(
+    req: HttpRequest,
+    query: web::Query<TenantBlocksQuery>,
+    context: web::Data<ServerContext>,
+) -> impl Responder {
+    let (_, tenant) = match authorize(&req, &context) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let Some(db) = &context.db else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "ledger not available on this node"
+        }));
+    };
+
+    let page = match Page::parse(query.from, query.limit) {
+        Ok(page) => page,
+        Err(e) => return pagination::bad_request(e),
+    };
+    match db
+        .get_stats()
+        .and_then(|stats| Ok((db.get_blocks_range(page.from, page.end())?, stats)))
+    {
+        Ok((blocks, stats)) => {
+            let mut response = HttpResponse::Ok();
+            page.headers(&mut response, req.path(), &stats);
+            response.json(
+                blocks
+                    .iter()
+                    .filter_map(|block| TenantRegistry::scope_block(&tenant, block))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub(super) async fn stats(req: HttpRequest, context: web::Data<ServerContext>) -> impl Responder {
+    match authorize(&req, &context) {
+        Ok((registry, tenant)) => HttpResponse::Ok().json(json!({
+            "tenant": tenant,
+            "stats": registry.stats(&tenant),
+        })),
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::load::DatabaseManager;
+    use crate::network::NetworkHandler;
+    use crate::testing;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    fn submission(asset: &str, price: f32) -> Submission {
+        Submission {
+            asset: asset.to_string(),
+            price: crate::etl::price::from_f32(price).unwrap(),
+            source: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_submissions_are_namespaced_and_quota_limited() {
+        let registry = TenantRegistry::new()
+            .with_tenant("alpha", "key-a")
+            .with_tenant("beta", "key-b")
+            .with_quota(2, 60);
+        assert_eq!(registry.authenticate("key-b"), Some("beta"));
+        assert_eq!(registry.authenticate("key-c"), None);
+
+        registry
+            .submit("alpha", vec![submission("BTC", 50000.0)])
+            .unwrap();
+        registry
+            .submit("beta", vec![submission("BTC", 51000.0)])
+            .unwrap();
+        let err = registry
+            .submit(
+                "alpha",
+                vec![submission("ETH", 3000.0), submission("SOL", 1.0)],
+            )
+            .unwrap_err();
+        assert!(matches!(err, TenantError::QuotaExceeded { limit: 2, .. }));
+        assert!(matches!(
+            registry.submit("alpha", vec![submission("beta/BTC", 1.0)]),
+            Err(TenantError::Invalid(_))
+        ));
+        // Entries are billed to the tenant, which cannot name another source
+        let mut spoofed = submission("BTC", 1.0);
+        spoofed.source = Some("beta".to_string());
+        assert!(matches!(
+            registry.submit("alpha", vec![spoofed]),
+            Err(TenantError::Invalid(_))
+        ));
+
+        let pending = registry.pending(10);
+        let assets: Vec<&str> = pending.iter().map(|d| d.asset.as_str()).collect();
+        assert_eq!(assets, ["alpha/BTC", "beta/BTC"]);
+        assert_eq!(pending[0].source, "alpha");
+
+        // Each tenant only sees its own entries, without the namespace
+        let mut block = testing::block(1, testing::GENESIS_PARENT_HASH, pending);
+        block.fees = ["alpha", "beta"]
+            .map(|submitter| FeeRecord {
+                submitter: submitter.to_string(),
+                entries: 1,
+                amount: 2,
+            })
+            .to_vec();
+        let scoped = TenantRegistry::scope_block("alpha", &block).unwrap();
+        assert_eq!(scoped.data.len(), 1);
+        assert_eq!(scoped.data[0].asset, "BTC");
+        assert_eq!(scoped.data[0].price, Decimal::from(50000));
+        assert_eq!(scoped.fees, block.fees[..1]);
+
+        registry.record_committed(&block);
+        assert!(registry.pending(10).is_empty());
+        let stats = registry.stats("alpha").unwrap();
+        assert_eq!(stats.submitted, 1);
+        assert_eq!(stats.committed, 1);
+        assert_eq!(stats.pending, 0);
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.quota, Some(2));
+    }
+
+    #[test]
+    fn test_quota_settings_are_validated() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(quota_from(vars(&[])), Ok(None));
+        assert_eq!(
+            quota_from(vars(&[("TENANT_QUOTA_ENTRIES", "50")])),
+            Ok(Some((50, DEFAULT_QUOTA_WINDOW_SECS)))
+        );
+        assert_eq!(
+            quota_from(vars(&[
+                ("TENANT_QUOTA_ENTRIES", "50"),
+                ("TENANT_QUOTA_WINDOW_SECS", "60")
+            ])),
+            Ok(Some((50, 60)))
+        );
+
+        let err = quota_from(vars(&[("TENANT_QUOTA_ENTRIES", "lots")])).unwrap_err();
+        assert!(err.contains("TENANT_QUOTA_ENTRIES"), "{}", err);
+        for window in [
+            &[
+                ("TENANT_QUOTA_ENTRIES", "50"),
+                ("TENANT_QUOTA_WINDOW_SECS", "0"),
+            ],
+            &[
+                ("TENANT_QUOTA_ENTRIES", "50"),
+                ("TENANT_QUOTA_WINDOW_SECS", "hourly"),
+            ],
+        ] {
+            let err = quota_from(vars(window)).unwrap_err();
+            assert!(err.contains("TENANT_QUOTA_WINDOW_SECS"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_drop_submitter_unblocks_the_queue() {
+        let registry = TenantRegistry::new()
+            .with_tenant("alpha", "key-a")
+            .with_tenant("beta", "key-b");
+        registry
+            .submit("alpha", vec![submission("BTC", 50000.0)])
+            .unwrap();
+        registry
+            .submit("beta", vec![submission("ETH", 3000.0)])
+            .unwrap();
+
+        assert_eq!(registry.drop_submitter("alpha"), 1);
+        let pending = registry.pending(10);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].asset, "beta/ETH");
+        let stats = registry.stats("alpha").unwrap();
+        assert_eq!((stats.pending, stats.dropped), (0, 1));
+        assert_eq!(registry.drop_submitter("gamma"), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_tenant_routes_are_scoped_by_api_key() {
+        let path = "test_tenant_routes.db";
+        std::fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+
+        let registry = Arc::new(
+            TenantRegistry::new()
+                .with_tenant("alpha", "key-a")
+                .with_tenant("beta", "key-b"),
+        );
+        let context = ServerContext::new(Arc::new(NetworkHandler::new(|_| true)))
+            .with_database(db.clone())
+            .with_tenants(registry.clone());
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(context))
+                .route("/tenant/submit", web::post().to(submit))
+                .route("/tenant/blocks", web::get().to(blocks))
+                .route("/tenant/stats", web::get().to(stats)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/tenant/submit")
+            .set_json(json!([{ "asset": "BTC", "price": 50000.0 }]))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), 401);
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/tenant/submit")
+            .insert_header((API_KEY_HEADER, "key-a"))
+            .set_json(json!([{ "asset": "BTC", "price": 50000.0 }]))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), 202);
+
+        let block = testing::block(1, testing::GENESIS_PARENT_HASH, registry.pending(10));
+        db.save_block(&block).unwrap();
+        registry.record_committed(&block);
+
+        let get = |uri: &str, key: &str| {
+            actix_web::test::TestRequest::get()
+                .uri(uri)
+                .insert_header((API_KEY_HEADER, key.to_string()))
+                .to_request()
+        };
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/tenant/blocks", "key-a")).await;
+        assert_eq!(body[0]["data"][0]["asset"], "BTC");
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/tenant/blocks", "key-b")).await;
+        assert_eq!(body, json!([]));
+
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, get("/tenant/stats", "key-a")).await;
+        assert_eq!(body["stats"]["committed"], 1);
+
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,466 @@
+//! Peer topology and message-flow statistics
+//!
+//! Every consensus message this process sends through `send_payload` and
+//! every one `/message` accepts is counted in `MESSAGE_FLOW`, per peer:
+//! messages, bytes, failed sends, and round-trip latency over the last
+//! `LATENCY_SAMPLES` sends. `GET /topology` serves the node's view as JSON
+//! (`TopologySnapshot`) or, with `?format=dot`, as a Graphviz graph.
+//!
+//! Received messages are counted under the sender's allowlisted address
+//! when `PEER_ALLOWLIST` authenticated it, and under the connection's IP
+//! otherwise, never under the node id a message claims. At most
+//! `MAX_TRACKED_PEERS` peers are kept in each direction; the one heard from
+//! or sent to least recently makes room for a new one.
+//!
+//! `ClusterGraph` merges the views of several nodes into one directed graph
+//! (the `topology` command fetches them), so operators can see who talks to
+//! whom and spot asymmetric connectivity: a link that carries messages one
+//! way while the reverse direction only fails or is silent.
+
+use crate::etl::now_millis;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Latency samples kept per peer
+pub const LATENCY_SAMPLES: usize = 128;
+
+/// Peers tracked per direction
+pub const MAX_TRACKED_PEERS: usize = 256;
+
+/// Round-trip latency of recent sends, in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn from_samples(samples: &VecDeque<f64>) -> Self {
+        if samples.is_empty() {
+            return LatencySummary::default();
+        }
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let at = |q: f64| sorted[((sorted.len() - 1) as f64 * q).round() as usize];
+        LatencySummary {
+            samples: sorted.len(),
+            mean_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50_ms: at(0.5),
+            p95_ms: at(0.95),
+            max_ms: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// Messages sent to one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutboundStats {
+    /// Delivered messages
+    pub messages: u64,
+    pub bytes: u64,
+    /// Sends that failed after retries
+    pub failures: u64,
+    pub latency: LatencySummary,
+    /// Unix milliseconds of the last delivered message
+    pub last_sent_at: Option<i64>,
+}
+
+/// Messages received from one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InboundStats {
+    pub messages: u64,
+    pub bytes: u64,
+    /// Message count by type, e.g. `Prepare`
+    pub by_type: BTreeMap<String, u64>,
+    pub last_received_at: Option<i64>,
+}
+
+/// One node's view of its peers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopologySnapshot {
+    pub node_id: Option<usize>,
+    pub address: Option<String>,
+    /// Configured cluster addresses, indexed by node id
+    pub peers: Vec<String>,
+    pub generated_at: i64,
+    /// By peer address
+    pub outbound: BTreeMap<String, OutboundStats>,
+    /// By authenticated peer address, or the sender's IP when the node has
+    /// no `PEER_ALLOWLIST`
+    pub inbound: BTreeMap<String, InboundStats>,
+}
+
+#[derive(Default)]
+struct Outbound {
+    messages: u64,
+    bytes: u64,
+    failures: u64,
+    latencies: VecDeque<f64>,
+    last_sent_at: Option<i64>,
+}
+
+#[derive(Default)]
+struct FlowState {
+    node_id: Option<usize>,
+    address: Option<String>,
+    peers: Vec<String>,
+    outbound: BTreeMap<String, Outbound>,
+    inbound: BTreeMap<String, InboundStats>,
+}
+
+/// Make room in `links` for `peer` by dropping the least recently active
+/// link once `MAX_TRACKED_PEERS` are tracked
+fn evict_for<T>(
+    links: &mut BTreeMap<String, T>,
+    peer: &str,
+    last_active: impl Fn(&T) -> Option<i64>,
+) {
+    if links.len() < MAX_TRACKED_PEERS || links.contains_key(peer) {
+        return;
+    }
+    let stalest = links
+        .iter()
+        .min_by_key(|(_, link)| last_active(link))
+        .map(|(peer, _)| peer.clone());
+    if let Some(stalest) = stalest {
+        links.remove(&stalest);
+    }
+}
+
+/// Message counters by peer; see the module docs
+#[derive(Default)]
+pub struct MessageFlow {
+    state: RwLock<FlowState>,
+}
+
+impl MessageFlow {
+    /// Name this node and its cluster, so inbound senders can be shown by
+    /// address
+    pub fn set_local(&self, node_id: usize, peers: &[String]) {
+        let mut state = self.state.write();
+        state.node_id = Some(node_id);
+        state.address = peers.get(node_id).cloned();
+        state.peers = peers.to_vec();
+    }
+
+    pub fn record_sent(&self, peer: &str, bytes: usize, latency: Duration) {
+        let mut state = self.state.write();
+        evict_for(&mut state.outbound, peer, |link| link.last_sent_at);
+        let link = state.outbound.entry(peer.to_string()).or_default();
+        link.messages += 1;
+        link.bytes += bytes as u64;
+        link.last_sent_at = Some(now_millis());
+        if link.latencies.len() == LATENCY_SAMPLES {
+            link.latencies.pop_front();
+        }
+        link.latencies.push_back(latency.as_secs_f64() * 1000.0);
+    }
+
+    pub fn record_failed(&self, peer: &str) {
+        let mut state = self.state.write();
+        evict_for(&mut state.outbound, peer, |link| link.last_sent_at);
+        state.outbound.entry(peer.to_string()).or_default().failures += 1;
+    }
+
+    /// Count a message from `peer`, an authenticated address or IP
+    pub fn record_received(&self, peer: &str, message_type: &str, bytes: usize) {
+        let mut state = self.state.write();
+        evict_for(&mut state.inbound, peer, |link| link.last_received_at);
+        let link = state.inbound.entry(peer.to_string()).or_default();
+        link.messages += 1;
+        link.bytes += bytes as u64;
+        *link.by_type.entry(message_type.to_string()).or_default() += 1;
+        link.last_received_at = Some(now_millis());
+    }
+
+    pub fn snapshot(&self) -> TopologySnapshot {
+        let state = self.state.read();
+        TopologySnapshot {
+            node_id: state.node_id,
+            address: state.address.clone(),
+            peers: state.peers.clone(),
+            generated_at: now_millis(),
+            outbound: state
+                .outbound
+                .iter()
+                .map(|(peer, link)| {
+                    let stats = OutboundStats {
+                        messages: link.messages,
+                        bytes: link.bytes,
+                        failures: link.failures,
+                        latency: LatencySummary::from_samples(&link.latencies),
+                        last_sent_at: link.last_sent_at,
+                    };
+                    (peer.clone(), stats)
+                })
+                .collect(),
+            inbound: state.inbound.clone(),
+        }
+    }
+}
+
+/// Message flow of this process
+pub static MESSAGE_FLOW: LazyLock<MessageFlow> = LazyLock::new(MessageFlow::default);
+
+/// A node in the cluster graph
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    pub address: String,
+    pub node_id: Option<usize>,
+    /// Whether its topology could be fetched; `None` for nodes only seen in
+    /// other nodes' views
+    pub reachable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Traffic from one node to another, as both ends saw it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    /// The sender's counters, when its view is available
+    pub sent: Option<OutboundStats>,
+    /// The receiver's counters, when its view is available
+    pub received: Option<InboundStats>,
+    /// Traffic flows this way but not back; see `ClusterGraph::build`
+    pub asymmetric: bool,
+}
+
+impl GraphEdge {
+    fn delivered(&self) -> u64 {
+        let sent = self.sent.as_ref().map_or(0, |s| s.messages);
+        let received = self.received.as_ref().map_or(0, |r| r.messages);
+        sent.max(received)
+    }
+}
+
+fn graph_node<'a>(nodes: &'a mut BTreeMap<String, GraphNode>, address: &str) -> &'a mut GraphNode {
+    nodes
+        .entry(address.to_string())
+        .or_insert_with(|| GraphNode {
+            address: address.to_string(),
+            node_id: None,
+            reachable: None,
+            error: None,
+        })
+}
+
+fn graph_edge<'a>(
+    edges: &'a mut BTreeMap<(String, String), GraphEdge>,
+    from: &str,
+    to: &str,
+) -> &'a mut GraphEdge {
+    edges
+        .entry((from.to_string(), to.to_string()))
+        .or_insert_with(|| GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            sent: None,
+            received: None,
+            asymmetric: false,
+        })
+}
+
+/// Directed graph merged from several nodes' `TopologySnapshot`s
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClusterGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl ClusterGraph {
+    /// Merge the snapshots fetched from each address
+    ///
+    /// An edge is asymmetric when messages were delivered along it while
+    /// nothing was delivered in the opposite direction even though that
+    /// direction was tried (it has failed sends) or the reverse sender's
+    /// view is available and shows no traffic to it.
+    pub fn build(views: &[(String, Result<TopologySnapshot, String>)]) -> Self {
+        let mut nodes: BTreeMap<String, GraphNode> = BTreeMap::new();
+        let mut edges: BTreeMap<(String, String), GraphEdge> = BTreeMap::new();
+        let mut viewed = BTreeSet::new();
+        for (address, view) in views {
+            let node = graph_node(&mut nodes, address);
+            match view {
+                Ok(snapshot) => {
+                    node.reachable = Some(true);
+                    node.node_id = snapshot.node_id;
+                    viewed.insert(address.clone());
+                    for (peer, stats) in &snapshot.outbound {
+                        graph_edge(&mut edges, address, peer).sent = Some(stats.clone());
+                    }
+                    for (peer, stats) in &snapshot.inbound {
+                        graph_edge(&mut edges, peer, address).received = Some(stats.clone());
+                    }
+                }
+                Err(e) => {
+                    node.reachable = Some(false);
+                    node.error = Some(e.clone());
+                }
+            }
+        }
+        for (from, to) in edges.keys() {
+            graph_node(&mut nodes, from);
+            graph_node(&mut nodes, to);
+        }
+
+        let keys: Vec<(String, String)> = edges.keys().cloned().collect();
+        for (from, to) in keys {
+            let forward = edges[&(from.clone(), to.clone())].delivered();
+            let reverse = edges.get(&(to.clone(), from.clone()));
+            let reverse_delivered = reverse.map_or(0, GraphEdge::delivered);
+            let reverse_tried = reverse
+                .and_then(|e| e.sent.as_ref())
+                .is_some_and(|s| s.failures > 0);
+            let asymmetric =
+                forward > 0 && reverse_delivered == 0 && (reverse_tried || viewed.contains(&to));
+            edges
+                .get_mut(&(from.clone(), to.clone()))
+                .expect("edge exists")
+                .asymmetric = asymmetric;
+        }
+
+        ClusterGraph {
+            nodes: nodes.into_values().collect(),
+            edges: edges.into_values().collect(),
+        }
+    }
+
+    /// Graphviz rendering: edges are labelled with delivered messages and
+    /// p50 latency; failing links are red, asymmetric ones bold and
+    /// unreachable nodes dashed
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph cluster {\n  rankdir=LR;\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let label = match node.node_id {
+                Some(id) => format!("node {}\\n{}", id, node.address),
+                None => node.address.clone(),
+            };
+            let style = match node.reachable {
+                Some(false) => ", style=dashed, color=red",
+                None => ", style=dotted",
+                Some(true) => "",
+            };
+            out.push_str(&format!(
+                "  \"{}\" [label=\"{}\"{}];\n",
+                node.address, label, style
+            ));
+        }
+        for edge in &self.edges {
+            let mut label = format!("{} msgs", edge.delivered());
+            let mut attrs = Vec::new();
+            if let Some(sent) = &edge.sent {
+                if sent.latency.samples > 0 {
+                    label.push_str(&format!("\\np50 {:.1} ms", sent.latency.p50_ms));
+                }
+                if sent.failures > 0 {
+                    label.push_str(&format!("\\n{} failed", sent.failures));
+                    attrs.push("color=red");
+                }
+            }
+            if edge.asymmetric {
+                attrs.push("style=bold");
+            }
+            let attrs = if attrs.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", attrs.join(", "))
+            };
+            out.push_str(&format!(
+                "  \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+                edge.from, edge.to, label, attrs
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers() -> Vec<String> {
+        (0..3)
+            .map(|id| format!("127.0.0.1:{}", 8000 + id))
+            .collect()
+    }
+
+    #[test]
+    fn test_cluster_graph_flags_one_way_links() {
+        // Node 0 reaches node 1, but node 1's sends to node 0 all fail
+        let node0 = MessageFlow::default();
+        node0.set_local(0, &peers());
+        for ms in [4, 6, 50] {
+            node0.record_sent("127.0.0.1:8001", 100, Duration::from_millis(ms));
+        }
+        node0.record_received("127.0.0.1:8002", "Prepare", 80);
+        let node1 = MessageFlow::default();
+        node1.set_local(1, &peers());
+        node1.record_received("127.0.0.1:8000", "PrePrepare", 100);
+        node1.record_failed("127.0.0.1:8000");
+
+        let snapshot = node0.snapshot();
+        assert_eq!(snapshot.address.as_deref(), Some("127.0.0.1:8000"));
+        let link = &snapshot.outbound["127.0.0.1:8001"];
+        assert_eq!((link.messages, link.bytes), (3, 300));
+        assert_eq!((link.latency.p50_ms, link.latency.max_ms), (6.0, 50.0));
+        assert_eq!(snapshot.inbound["127.0.0.1:8002"].by_type["Prepare"], 1);
+
+        let graph = ClusterGraph::build(&[
+            ("127.0.0.1:8000".to_string(), Ok(snapshot)),
+            ("127.0.0.1:8001".to_string(), Ok(node1.snapshot())),
+            (
+                "127.0.0.1:8002".to_string(),
+                Err("connection refused".into()),
+            ),
+        ]);
+        let edge = |from: &str, to: &str| {
+            graph
+                .edges
+                .iter()
+                .find(|e| e.from.ends_with(from) && e.to.ends_with(to))
+                .unwrap()
+        };
+        assert!(edge("8000", "8001").asymmetric);
+        assert_eq!(edge("8000", "8001").received.as_ref().unwrap().messages, 1);
+        assert!(!edge("8001", "8000").asymmetric);
+        // Node 0 hears from node 2 but never sends to it
+        assert!(edge("8002", "8000").asymmetric);
+        assert!(edge("8002", "8000").sent.is_none());
+        assert_eq!(graph.nodes[2].reachable, Some(false));
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph cluster {"));
+        assert!(dot.contains(
+            "\"127.0.0.1:8000\" -> \"127.0.0.1:8001\" [label=\"3 msgs\\np50 6.0 ms\", style=bold];"
+        ));
+        assert!(dot.contains(
+            "\"127.0.0.1:8001\" -> \"127.0.0.1:8000\" [label=\"0 msgs\\n1 failed\", color=red];"
+        ));
+        assert!(dot.contains("style=dashed, color=red"));
+    }
+
+    #[test]
+    fn test_tracked_peers_are_bounded() {
+        let flow = MessageFlow::default();
+        flow.record_received("10.0.0.1", "Prepare", 10);
+        std::thread::sleep(Duration::from_millis(2));
+        for i in 2..=MAX_TRACKED_PEERS + 10 {
+            flow.record_received(&format!("10.0.{}.{}", i / 256, i % 256), "Prepare", 10);
+            flow.record_failed(&format!("10.1.{}.{}:8000", i / 256, i % 256));
+        }
+        let snapshot = flow.snapshot();
+        assert_eq!(snapshot.inbound.len(), MAX_TRACKED_PEERS);
+        assert_eq!(snapshot.outbound.len(), MAX_TRACKED_PEERS);
+        // The sender heard from first made room
+        assert!(!snapshot.inbound.contains_key("10.0.0.1"));
+    }
+}
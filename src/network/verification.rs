@@ -0,0 +1,364 @@
+//! Rolling verification of the local ledger
+//!
+//! `RollingVerifier` runs in the background on a live node and re-checks the
+//! most recent blocks every interval: each hash is recomputed, each block
+//! must link to the one before it and order after it in hybrid logical time
+//! (`etl::hlc`), and the tip confirmed by the previous pass
+//! (checkpointed in the database) must still be there unchanged. Corruption
+//! is therefore noticed within one interval rather than whenever someone
+//! runs a full verify.
+//!
+//! The last successful pass is published as `last_verified_at` on `/health`.
+//! When a pass fails after a healthy one, every configured webhook receives
+//! a JSON alert.
+//!
+//! Configured with `VERIFY_INTERVAL_SECS` (enables the task),
+//! `VERIFY_WINDOW_BLOCKS` and `VERIFY_ALERT_WEBHOOKS`.
+
+use crate::etl::load::{DatabaseError, DatabaseManager, DbResult, VerificationCheckpoint};
+use crate::etl::now_millis;
+use crate::network::sync::{BlockVerifier, HlcVerifier, LinkVerifier, StoredHashVerifier};
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Blocks re-checked per pass unless `VERIFY_WINDOW_BLOCKS` is set
+pub const DEFAULT_WINDOW_BLOCKS: u64 = 100;
+
+/// Rolling verifier settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationConfig {
+    pub interval: Duration,
+    /// Number of most recent blocks checked each pass
+    pub window: u64,
+    /// URLs that receive a POST when verification fails
+    pub webhooks: Vec<String>,
+}
+
+impl VerificationConfig {
+    pub fn new(interval: Duration) -> Self {
+        VerificationConfig {
+            interval,
+            window: DEFAULT_WINDOW_BLOCKS,
+            webhooks: Vec::new(),
+        }
+    }
+
+    /// `None` unless `VERIFY_INTERVAL_SECS` is set to a positive number
+    pub fn from_env() -> Option<Self> {
+        let interval = std::env::var("VERIFY_INTERVAL_SECS")
+            .ok()?
+            .parse::<u64>()
+            .ok()
+            .filter(|&secs| secs > 0)?;
+        let mut config = Self::new(Duration::from_secs(interval));
+        if let Some(window) = std::env::var("VERIFY_WINDOW_BLOCKS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.window = window;
+        }
+        if let Ok(hooks) = std::env::var("VERIFY_ALERT_WEBHOOKS") {
+            config.webhooks = hooks
+                .split(',')
+                .map(str::trim)
+                .filter(|h| !h.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        Some(config)
+    }
+}
+
+/// Result of one verification pass
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassReport {
+    /// Blocks whose hash and link were checked
+    pub checked: usize,
+    /// Tip the pass verified up to; `None` for an empty ledger
+    pub tip: Option<u64>,
+    /// First problem found, if any
+    pub failure: Option<String>,
+}
+
+impl PassReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Published state of the verifier
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerificationStatus {
+    /// When the last successful pass finished (milliseconds)
+    pub last_verified_at: Option<i64>,
+    pub last_verified_index: Option<u64>,
+    pub passes: u64,
+    pub failures: u64,
+    pub last_failure: Option<String>,
+    /// Whether the most recent pass succeeded
+    pub healthy: bool,
+}
+
+/// Periodically re-verifies the most recent blocks
+pub struct RollingVerifier {
+    db: Arc<DatabaseManager>,
+    config: VerificationConfig,
+    client: reqwest::Client,
+    status: RwLock<VerificationStatus>,
+}
+
+impl RollingVerifier {
+    pub fn new(db: Arc<DatabaseManager>, config: VerificationConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        RollingVerifier {
+            db,
+            config,
+            client,
+            status: RwLock::new(VerificationStatus {
+                healthy: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn status(&self) -> VerificationStatus {
+        self.status.read().clone()
+    }
+
+    /// Check the last `window` blocks and the previous checkpoint, then move
+    /// the checkpoint to the current tip if everything verifies
+    pub fn verify_once(&self) -> DbResult<PassReport> {
+        let Some(tip) = self.db.get_latest_block()? else {
+            return Ok(PassReport::default());
+        };
+        let mut report = PassReport {
+            tip: Some(tip.index),
+            ..Default::default()
+        };
+
+        if let Some(checkpoint) = self.db.get_verification_checkpoint()? {
+            if let Err(reason) = self.check_checkpoint(&checkpoint, tip.index) {
+                report.failure = Some(reason);
+                return Ok(report);
+            }
+        }
+
+        let start = tip
+            .index
+            .saturating_sub(self.config.window.saturating_sub(1));
+        let blocks = self.db.get_blocks_range(start, tip.index)?;
+        let hashes = StoredHashVerifier::new(&self.db)?;
+        let mut parent = None;
+        for block in &blocks {
+            for verifier in [&LinkVerifier as &dyn BlockVerifier, &HlcVerifier, &hashes] {
+                if let Err(reason) = verifier.verify(block, parent) {
+                    report.failure = Some(format!(
+                        "block {}: {}: {}",
+                        block.index,
+                        verifier.name(),
+                        reason
+                    ));
+                    return Ok(report);
+                }
+            }
+            parent = Some(block);
+            report.checked += 1;
+        }
+
+        self.db
+            .save_verification_checkpoint(&VerificationCheckpoint {
+                block_index: tip.index,
+                hash: tip.hash.clone(),
+                verified_at: now_millis(),
+            })?;
+        Ok(report)
+    }
+
+    fn check_checkpoint(
+        &self,
+        checkpoint: &VerificationCheckpoint,
+        tip: u64,
+    ) -> Result<(), String> {
+        if checkpoint.block_index > tip {
+            return Err(format!(
+                "chain tip {} is below verified checkpoint {}",
+                tip, checkpoint.block_index
+            ));
+        }
+        match self.db.get_block_by_index(checkpoint.block_index) {
+            Ok(block) if block.hash == checkpoint.hash => Ok(()),
+            Ok(block) => Err(format!(
+                "checkpoint block {} changed: was {}, now {}",
+                checkpoint.block_index, checkpoint.hash, block.hash
+            )),
+            Err(DatabaseError::NotFound(_)) => Err(format!(
+                "checkpoint block {} is missing",
+                checkpoint.block_index
+            )),
+            Err(e) => Err(format!("cannot read checkpoint block: {}", e)),
+        }
+    }
+
+    /// Publish a pass's outcome; returns the failure to alert on, if any
+    fn record_pass(&self, report: &DbResult<PassReport>) -> Option<String> {
+        let mut status = self.status.write();
+        status.passes += 1;
+        let failure = match report {
+            Ok(report) if report.is_ok() => {
+                status.last_verified_at = Some(now_millis());
+                if report.tip.is_some() {
+                    status.last_verified_index = report.tip;
+                }
+                status.healthy = true;
+                return None;
+            }
+            Ok(report) => report.failure.clone().unwrap_or_default(),
+            Err(e) => format!("database error: {}", e),
+        };
+        status.failures += 1;
+        status.last_failure = Some(failure.clone());
+        // Alert on the transition to failing, not on every failed pass
+        let was_healthy = std::mem::replace(&mut status.healthy, false);
+        was_healthy.then_some(failure)
+    }
+
+    async fn alert(&self, failure: &str) {
+        let body = json!({
+            "event": "verification_failed",
+            "failure": failure,
+            "last_verified_index": self.status.read().last_verified_index,
+            "at_ms": now_millis(),
+        });
+        for hook in &self.config.webhooks {
+            match self.client.post(hook).json(&body).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    debug!(webhook = %hook, "Verify: Alert delivered")
+                }
+                Ok(resp) => {
+                    warn!(webhook = %hook, status = %resp.status(), "Verify: Alert rejected")
+                }
+                Err(e) => warn!(webhook = %hook, error = %e, "Verify: Alert failed"),
+            }
+        }
+    }
+
+    /// One pass plus status update and alerting
+    pub async fn tick(&self) -> DbResult<PassReport> {
+        let report = self.verify_once();
+        if let Some(failure) = self.record_pass(&report) {
+            error!(failure = %failure, "Verify: Ledger verification failed");
+            self.alert(&failure).await;
+        } else if let Ok(report) = &report {
+            debug!(
+                checked = report.checked,
+                tip = ?report.tip,
+                "Verify: Recent blocks verified"
+            );
+        }
+        report
+    }
+
+    /// Run forever on a background task
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        info!(
+            interval_secs = self.config.interval.as_secs(),
+            window = self.config.window,
+            webhooks = self.config.webhooks.len(),
+            "Verify: Rolling verification enabled"
+        );
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
+            loop {
+                ticker.tick().await;
+                let _ = self.tick().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etl::price::Decimal;
+    use crate::etl::Block;
+    use crate::testing::TestChainBuilder;
+    use std::fs;
+
+    fn chain(len: u64) -> Vec<Block> {
+        TestChainBuilder::new().with_blocks(len).build()
+    }
+
+    #[tokio::test]
+    async fn test_rolling_verifier_checkpoints_and_detects_corruption() {
+        let path = "test_rolling_verifier.db";
+        fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+        let blocks = chain(5);
+        db.save_blocks(&blocks).unwrap();
+
+        let mut config = VerificationConfig::new(Duration::from_secs(60));
+        config.window = 3;
+        let verifier = RollingVerifier::new(db.clone(), config);
+
+        let report = verifier.tick().await.unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked, 3);
+        assert_eq!(
+            db.get_verification_checkpoint()
+                .unwrap()
+                .unwrap()
+                .block_index,
+            5
+        );
+        assert!(verifier.status().last_verified_at.is_some());
+
+        // Rewriting the checkpointed tip is caught on the next pass
+        let mut tampered = blocks[4].clone();
+        tampered.data[0].price = Decimal::ONE;
+        tampered.calculate_hash_with_nonce();
+        db.delete_block(5).unwrap();
+        db.save_block(&tampered).unwrap();
+
+        let report = verifier.tick().await.unwrap();
+        assert!(report
+            .failure
+            .unwrap()
+            .contains("checkpoint block 5 changed"));
+        let status = verifier.status();
+        assert!(!status.healthy);
+        assert_eq!(status.failures, 1);
+        assert_eq!(status.last_verified_index, Some(5));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_rolling_verifier_detects_broken_link() {
+        let path = "test_rolling_verifier_link.db";
+        fs::remove_file(path).ok();
+        let db = Arc::new(DatabaseManager::new(path).unwrap());
+        db.init().unwrap();
+        let mut blocks = chain(3);
+        blocks[2].previous_hash = "forged".to_string();
+        blocks[2].calculate_hash_with_nonce();
+        db.save_blocks(&blocks).unwrap();
+
+        let verifier =
+            RollingVerifier::new(db.clone(), VerificationConfig::new(Duration::from_secs(60)));
+        let report = verifier.verify_once().unwrap();
+        assert_eq!(report.checked, 2);
+        assert!(report.failure.unwrap().starts_with("block 3: link"));
+        assert_eq!(db.get_verification_checkpoint().unwrap(), None);
+
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,480 @@
+//! Retry with jittered backoff
+//!
+//! `RetryPolicy` drives every retry loop in the node: market data extraction,
+//! consensus messages to peers, and chain sync. The caller classifies each
+//! failure as retryable, throttled (retry, but wait at least the given
+//! delay) or fatal. Delays grow exponentially by default; fixed and linear
+//! backoff are available too.
+//!
+//! Each component starts from its own defaults, overridden by the shared
+//! `RETRY_MAX_ATTEMPTS`, `RETRY_BASE_DELAY_MS`, `RETRY_MAX_DELAY_MS`,
+//! `RETRY_JITTER`, `RETRY_STRATEGY` (`exponential`, `linear` or `fixed`) and
+//! `RETRY_STATUSES` (HTTP statuses to retry, e.g. `429,502-504`; by default
+//! those `classify_status` treats as transient). Those are in turn
+//! overridden per component by the same names with a prefix, e.g.
+//! `EXTRACT_RETRY_MAX_ATTEMPTS` or `NETWORK_RETRY_BASE_DELAY_MS`.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+/// How a failed attempt should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    /// Transient; retry after the backoff delay
+    Retry,
+    /// The remote asked us to slow down; retry after at least this long
+    Throttled(Duration),
+    /// Retrying cannot help
+    Fatal,
+}
+
+/// Classify an HTTP status: timeouts and server errors are transient, 429
+/// is throttling, and other client errors are fatal
+pub fn classify_status(status: u16) -> RetryClass {
+    match status {
+        408 | 500..=599 => RetryClass::Retry,
+        429 => RetryClass::Throttled(Duration::from_secs(1)),
+        _ => RetryClass::Fatal,
+    }
+}
+
+/// Classify a `reqwest` error: connection problems and timeouts are
+/// transient, HTTP errors are classified by status
+pub fn classify_reqwest(err: &reqwest::Error) -> RetryClass {
+    match err.status() {
+        Some(status) => classify_status(status.as_u16()),
+        None if err.is_builder() => RetryClass::Fatal,
+        None => RetryClass::Retry,
+    }
+}
+
+/// How the delay grows from one retry to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backoff {
+    /// `base_delay` before every retry
+    Fixed,
+    /// `base_delay` times the retry number
+    Linear,
+    /// `base_delay`, doubling on each further retry
+    #[default]
+    Exponential,
+}
+
+impl std::str::FromStr for Backoff {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Backoff::Fixed),
+            "linear" => Ok(Backoff::Linear),
+            "exponential" => Ok(Backoff::Exponential),
+            other => Err(format!("unknown backoff strategy '{}'", other)),
+        }
+    }
+}
+
+/// Parse a list of HTTP statuses and ranges, e.g. `429,500-599`
+pub fn parse_statuses(value: &str) -> Result<Vec<u16>, String> {
+    let mut statuses = Vec::new();
+    for part in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let parse = |code: &str| {
+            code.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|c| (100..=599).contains(c))
+                .ok_or_else(|| format!("invalid HTTP status '{}'", code))
+        };
+        match part.split_once('-') {
+            Some((low, high)) => statuses.extend(parse(low)?..=parse(high)?),
+            None => statuses.push(parse(part)?),
+        }
+    }
+    Ok(statuses)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts including the first; at least 1
+    pub max_attempts: u32,
+    /// Delay before the first retry; grows per `strategy` after that
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction of each delay that is randomized, from 0.0 (fixed delays) to
+    /// 1.0 (anywhere between zero and the full delay)
+    pub jitter: f64,
+    pub strategy: Backoff,
+    /// HTTP statuses worth retrying; `None` uses `classify_status`
+    pub retryable_statuses: Option<Vec<u16>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(500))
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+            strategy: Backoff::Exponential,
+            retryable_statuses: None,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set the jitter fraction, clamped to 0.0..=1.0; NaN means no jitter
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_nan() {
+            0.0
+        } else {
+            jitter.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: Backoff) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Retry exactly these HTTP statuses; every other status is fatal
+    pub fn with_retryable_statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.retryable_statuses = Some(statuses.into_iter().collect());
+        self
+    }
+
+    /// Apply the shared `RETRY_*` settings, then `<component>_RETRY_*`,
+    /// naming the variable that does not parse
+    pub fn with_env_overrides(self, component: &str) -> Result<Self, String> {
+        self.with_overrides(component, |name| std::env::var(name).ok())
+    }
+
+    fn with_overrides(
+        self,
+        component: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, String> {
+        let prefix = format!("{}_RETRY_", component.to_uppercase());
+        let policy = self
+            .apply_overrides("RETRY_", &lookup)?
+            .apply_overrides(&prefix, &lookup)?;
+        if policy.max_delay < policy.base_delay {
+            return Err(format!(
+                "invalid {}MAX_DELAY_MS: {} ms is below the base delay of {} ms \
+                 (RETRY_BASE_DELAY_MS / {}BASE_DELAY_MS)",
+                prefix,
+                policy.max_delay.as_millis(),
+                policy.base_delay.as_millis(),
+                prefix
+            ));
+        }
+        Ok(policy)
+    }
+
+    fn apply_overrides(
+        mut self,
+        prefix: &str,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, String> {
+        let var = |name: &str| {
+            let name = format!("{}{}", prefix, name);
+            lookup(&name).map(|value| (name, value))
+        };
+        let invalid = |name: &str, value: &str, expected: &str| {
+            format!("invalid {} '{}' ({})", name, value, expected)
+        };
+        if let Some((name, value)) = var("MAX_ATTEMPTS") {
+            let attempts = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid(&name, &value, "expected a number of attempts"))?;
+            self = self.with_max_attempts(attempts);
+        }
+        if let Some((name, value)) = var("BASE_DELAY_MS") {
+            let ms = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid(&name, &value, "expected milliseconds"))?;
+            self.base_delay = Duration::from_millis(ms);
+        }
+        if let Some((name, value)) = var("MAX_DELAY_MS") {
+            let ms = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid(&name, &value, "expected milliseconds"))?;
+            self.max_delay = Duration::from_millis(ms);
+        }
+        if let Some((name, value)) = var("JITTER") {
+            let jitter = value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|j| (0.0..=1.0).contains(j))
+                .ok_or_else(|| invalid(&name, &value, "expected a fraction from 0 to 1"))?;
+            self = self.with_jitter(jitter);
+        }
+        if let Some((name, value)) = var("STRATEGY") {
+            self.strategy = value
+                .parse()
+                .map_err(|_| invalid(&name, &value, "expected exponential, linear or fixed"))?;
+        }
+        if let Some((name, value)) = var("STATUSES") {
+            let statuses =
+                parse_statuses(&value).map_err(|e| format!("invalid {}: {}", name, e))?;
+            self.retryable_statuses = Some(statuses);
+        }
+        Ok(self)
+    }
+
+    /// Backoff before retry number `retry` (1 for the first retry), without
+    /// jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = match self.strategy {
+            Backoff::Fixed => 1,
+            Backoff::Linear => retry.max(1),
+            Backoff::Exponential => 2u32.saturating_pow(retry.saturating_sub(1)),
+        };
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// `classify_status`, restricted to `retryable_statuses` when set; a
+    /// listed 429 is still treated as throttling
+    pub fn classify_status(&self, status: u16) -> RetryClass {
+        match &self.retryable_statuses {
+            None => classify_status(status),
+            Some(statuses) if statuses.contains(&status) => match classify_status(status) {
+                RetryClass::Fatal => RetryClass::Retry,
+                class => class,
+            },
+            Some(_) => RetryClass::Fatal,
+        }
+    }
+
+    /// Backoff with jitter applied
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        backoff.mul_f64(1.0 - self.jitter * unit_random())
+    }
+
+    /// Run `op` until it succeeds, fails fatally, or attempts run out,
+    /// returning the last error. `op` receives the attempt number, from 1.
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        mut op: F,
+        classify: impl Fn(&E) -> RetryClass,
+    ) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let err = match op(attempt).await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let wait = match classify(&err) {
+                _ if attempt >= self.max_attempts => return Err(err),
+                RetryClass::Fatal => return Err(err),
+                RetryClass::Retry => self.delay(attempt),
+                RetryClass::Throttled(min) => self.delay(attempt).max(min),
+            };
+            debug!(
+                attempt = attempt,
+                max_attempts = self.max_attempts,
+                delay_ms = wait.as_millis() as u64,
+                "Retry: Attempt failed, backing off"
+            );
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Uniform value in [0, 1) from a splitmix64 sequence seeded by the clock;
+/// good enough to spread out retries, not for anything security-related
+fn unit_random() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let seed = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    let mut z = STATE
+        .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+        .wrapping_add(seed);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap_and_jitter_stays_below() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(350));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay <= Duration::from_millis(200));
+            assert!(delay >= Duration::from_millis(100));
+        }
+        assert_eq!(
+            policy.clone().with_jitter(0.0).delay(2),
+            Duration::from_millis(200)
+        );
+        let linear = policy.clone().with_strategy(Backoff::Linear);
+        assert_eq!(linear.backoff(3), Duration::from_millis(300));
+        assert_eq!(linear.backoff(4), Duration::from_millis(350));
+        let fixed = policy.clone().with_strategy("fixed".parse().unwrap());
+        assert_eq!(fixed.backoff(4), Duration::from_millis(100));
+
+        assert_eq!(classify_status(503), RetryClass::Retry);
+        assert!(matches!(classify_status(429), RetryClass::Throttled(_)));
+        assert_eq!(classify_status(403), RetryClass::Fatal);
+    }
+
+    #[test]
+    fn test_retryable_statuses_override_classification() {
+        assert_eq!(
+            parse_statuses("429, 502-504").unwrap(),
+            vec![429, 502, 503, 504]
+        );
+        assert!(parse_statuses("5xx").is_err());
+        assert!(parse_statuses("700").is_err());
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.classify_status(500), RetryClass::Retry);
+        assert_eq!(policy.classify_status(409), RetryClass::Fatal);
+
+        let policy = policy.with_retryable_statuses([409, 429, 503]);
+        assert_eq!(policy.classify_status(409), RetryClass::Retry);
+        assert!(matches!(
+            policy.classify_status(429),
+            RetryClass::Throttled(_)
+        ));
+        assert_eq!(policy.classify_status(503), RetryClass::Retry);
+        assert_eq!(policy.classify_status(500), RetryClass::Fatal);
+    }
+
+    #[test]
+    fn test_env_overrides_name_the_bad_variable() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let base = RetryPolicy::new(3, Duration::from_millis(100));
+
+        let policy = base
+            .clone()
+            .with_overrides(
+                "extract",
+                vars(&[
+                    ("RETRY_MAX_ATTEMPTS", "5"),
+                    ("EXTRACT_RETRY_JITTER", "0.25"),
+                ]),
+            )
+            .unwrap();
+        assert_eq!(policy.max_attempts, 5);
+        assert_eq!(policy.jitter, 0.25);
+
+        for (pairs, name) in [
+            (&[("RETRY_JITTER", "NaN")][..], "RETRY_JITTER"),
+            (
+                &[("EXTRACT_RETRY_JITTER", "inf")][..],
+                "EXTRACT_RETRY_JITTER",
+            ),
+            (
+                &[("EXTRACT_RETRY_MAX_ATTEMPTS", "many")][..],
+                "EXTRACT_RETRY_MAX_ATTEMPTS",
+            ),
+            (&[("RETRY_STRATEGY", "random")][..], "RETRY_STRATEGY"),
+            (&[("RETRY_STATUSES", "5xx")][..], "RETRY_STATUSES"),
+            (
+                &[
+                    ("RETRY_BASE_DELAY_MS", "2000"),
+                    ("EXTRACT_RETRY_MAX_DELAY_MS", "500"),
+                ][..],
+                "EXTRACT_RETRY_MAX_DELAY_MS",
+            ),
+        ] {
+            let err = base
+                .clone()
+                .with_overrides("extract", vars(pairs))
+                .unwrap_err();
+            assert!(err.contains(name), "{}", err);
+        }
+        assert_eq!(RetryPolicy::default().with_jitter(f64::NAN).jitter, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_transient_errors_only() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(1));
+        let calls = AtomicU32::new(0);
+        let result: Result<u32, &str> = policy
+            .run(
+                |attempt| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if attempt < 3 {
+                            Err("transient")
+                        } else {
+                            Ok(attempt)
+                        }
+                    }
+                },
+                |_| RetryClass::Retry,
+            )
+            .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), &str> = policy
+            .run(
+                |_| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async { Err("denied") }
+                },
+                |_| RetryClass::Fatal,
+            )
+            .await;
+        assert_eq!(result, Err("denied"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), &str> = policy
+            .run(
+                |_| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async { Err("down") }
+                },
+                |_| RetryClass::Retry,
+            )
+            .await;
+        assert_eq!(result, Err("down"));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
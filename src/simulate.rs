@@ -0,0 +1,312 @@
+//! In-process cluster simulation
+//!
+//! `--simulate-cluster N` runs N nodes inside one process instead of one
+//! node per terminal. Each is a full `run_node` with its own ledger under
+//! `simulated_cluster/` and its own HTTP server on port 8000 + id, so PBFT
+//! messages travel over localhost exactly as between separate processes.
+//! `NODE_ADDRESSES` is set to the simulated nodes; every other flag
+//! (`--offline`, `--consensus`, `--demo`) applies to all of them. Logs carry
+//! a `node` span with the id of the node that wrote them.
+//!
+//! Once every node has finished its rounds, the ledgers are read back and
+//! compared. The cluster has converged when every node holds an intact chain
+//! of the same block indices. Each node commits the block it built itself,
+//! so block contents agree only where the nodes extracted the same data; the
+//! report counts the indices whose content ids match on every node.
+
+use crate::cli;
+use crate::supervisor::{ExitCode, ExitError};
+use futures_util::future::join_all;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+use tracing::{error, info, info_span, Instrument};
+
+/// Where the simulated nodes keep their ledgers; cleared on every run
+pub const DATA_DIR: &str = "simulated_cluster";
+
+/// Port of node 0; node `i` listens on `BASE_PORT + i`
+pub const BASE_PORT: u16 = 8000;
+
+/// Largest cluster one process simulates
+pub const MAX_NODES: usize = 16;
+
+const FLAG: &str = "--simulate-cluster";
+
+/// Nodes to simulate: `--simulate-cluster N` or `--simulate-cluster=N`
+pub fn cluster_size(args: &[String]) -> Result<Option<usize>, String> {
+    let value = match args.iter().position(|arg| arg == FLAG) {
+        Some(i) => args.get(i + 1).map(String::as_str).unwrap_or_default(),
+        None => match args
+            .iter()
+            .find_map(|arg| arg.strip_prefix(FLAG)?.strip_prefix('='))
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        },
+    };
+    match value.parse::<usize>() {
+        Ok(size) if (1..=MAX_NODES).contains(&size) => Ok(Some(size)),
+        _ => Err(format!(
+            "invalid {}: '{}' (expected 1 to {} nodes)",
+            FLAG, value, MAX_NODES
+        )),
+    }
+}
+
+/// Arguments `run_node` gets for simulated node `node_id`
+///
+/// `consensus` goes ahead of the caller's flags so every node runs the
+/// algorithm chosen once for the cluster.
+pub fn node_args(args: &[String], node_id: usize, consensus: &str) -> Vec<String> {
+    let program = args.first().cloned().unwrap_or_default();
+    let mut node_args = vec![
+        program,
+        node_id.to_string(),
+        (BASE_PORT + node_id as u16).to_string(),
+        format!("--consensus={}", consensus),
+        "--data-dir".to_string(),
+        DATA_DIR.to_string(),
+    ];
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if arg == FLAG {
+            rest.next();
+        } else if !arg.starts_with(FLAG) {
+            node_args.push(arg.clone());
+        }
+    }
+    node_args
+}
+
+/// `NODE_ADDRESSES` for a cluster of `size` nodes
+pub fn node_addresses(size: usize) -> String {
+    (0..size)
+        .map(|id| format!("127.0.0.1:{}", BASE_PORT + id as u16))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A simulated node's ledger once it stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeLedger {
+    pub node_id: usize,
+    /// `(index, content id)` of every block, genesis first
+    pub blocks: Vec<(u64, String)>,
+    pub head_hash: String,
+    /// The hash chain verified
+    pub intact: bool,
+}
+
+impl NodeLedger {
+    pub fn read(node_id: usize, db_path: &str) -> Result<Self, Box<dyn Error>> {
+        let db = cli::open_ledger(db_path)?;
+        let head = db
+            .get_latest_block()?
+            .ok_or_else(|| format!("{} holds no blocks", db_path))?;
+        let blocks = db
+            .get_blocks_range(0, head.index)?
+            .iter()
+            .map(|block| (block.index, block.content_id()))
+            .collect();
+        Ok(NodeLedger {
+            node_id,
+            blocks,
+            head_hash: head.hash,
+            intact: db.verify_chain()?,
+        })
+    }
+
+    pub fn height(&self) -> u64 {
+        self.blocks.last().map_or(0, |(index, _)| *index)
+    }
+}
+
+/// How far the simulated nodes agree
+#[derive(Debug, Clone)]
+pub struct ClusterReport {
+    pub nodes: Vec<NodeLedger>,
+    /// Nodes that stopped with an error or whose ledger could not be read
+    pub failures: Vec<(usize, String)>,
+}
+
+impl ClusterReport {
+    pub fn converged(&self) -> bool {
+        let Some(first) = self.nodes.first() else {
+            return false;
+        };
+        self.failures.is_empty()
+            && self.nodes.iter().all(|node| {
+                node.intact
+                    && node.blocks.len() == first.blocks.len()
+                    && node
+                        .blocks
+                        .iter()
+                        .zip(&first.blocks)
+                        .all(|((a, _), (b, _))| a == b)
+            })
+    }
+
+    /// Indices past genesis whose content id is the same on every node
+    pub fn matching_contents(&self) -> usize {
+        let Some(first) = self.nodes.first() else {
+            return 0;
+        };
+        first
+            .blocks
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(position, block)| {
+                self.nodes
+                    .iter()
+                    .all(|node| node.blocks.get(*position) == Some(block))
+            })
+            .count()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Simulated cluster");
+        let _ = writeln!(out, "  node    height  head       chain");
+        for node in &self.nodes {
+            let _ = writeln!(
+                out,
+                "  {:<6} {:>7}  {:<10} {}",
+                node.node_id,
+                node.height(),
+                &node.head_hash[..8.min(node.head_hash.len())],
+                if node.intact { "ok" } else { "BROKEN" }
+            );
+        }
+        for (node_id, reason) in &self.failures {
+            let _ = writeln!(out, "  {:<6} failed: {}", node_id, reason);
+        }
+        let height = self.nodes.iter().map(NodeLedger::height).max().unwrap_or(0);
+        if self.converged() {
+            let _ = writeln!(
+                out,
+                "Converged: every node committed blocks 1 to {}",
+                height
+            );
+        } else {
+            let _ = writeln!(out, "Diverged: nodes disagree on the committed blocks");
+        }
+        let _ = writeln!(
+            out,
+            "Identical contents at {} of {} blocks",
+            self.matching_contents(),
+            height
+        );
+        out
+    }
+}
+
+/// Run a cluster of `size` nodes to completion and report on their ledgers;
+/// fails unless they converged
+pub async fn run(args: &[String], size: usize) -> Result<(), Box<dyn Error>> {
+    // Asked once, rather than by every node
+    let consensus = crate::get_consensus_selection(args);
+    if Path::new(DATA_DIR).exists() {
+        std::fs::remove_dir_all(DATA_DIR)?;
+    }
+    std::fs::create_dir_all(DATA_DIR)?;
+    std::env::set_var("NODE_ADDRESSES", node_addresses(size));
+    info!(
+        nodes = size,
+        consensus = consensus.name(),
+        data_dir = DATA_DIR,
+        "Simulation: Starting an in-process cluster"
+    );
+
+    let outcomes = join_all((0..size).map(|node_id| {
+        let node_args = node_args(args, node_id, consensus.key());
+        async move {
+            let outcome = crate::run_node(&node_args).await.map_err(|e| e.to_string());
+            if let Err(e) = &outcome {
+                error!(error = %e, "Node stopped");
+            }
+            (node_id, outcome)
+        }
+        .instrument(info_span!("node", id = node_id))
+    }))
+    .await;
+
+    let mut report = ClusterReport {
+        nodes: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (node_id, outcome) in outcomes {
+        let db_path = crate::ledger_path(Some(DATA_DIR), node_id);
+        match outcome
+            .map_err(Into::into)
+            .and_then(|_| NodeLedger::read(node_id, &db_path))
+        {
+            Ok(ledger) => report.nodes.push(ledger),
+            Err(e) => report.failures.push((node_id, e.to_string())),
+        }
+    }
+    println!("{}", report.render());
+    if report.converged() {
+        Ok(())
+    } else {
+        Err(ExitError::new(ExitCode::Failure, "the simulated nodes did not converge").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_report() {
+        let args: Vec<String> = ["node", "--simulate-cluster", "4", "--offline"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(cluster_size(&args), Ok(Some(4)));
+        assert!(cluster_size(&["node".into(), "--simulate-cluster=0".into()]).is_err());
+        assert_eq!(
+            node_args(&args, 2, "pbft"),
+            [
+                "node",
+                "2",
+                "8002",
+                "--consensus=pbft",
+                "--data-dir",
+                DATA_DIR,
+                "--offline"
+            ]
+        );
+
+        let ledger = |node_id: usize, contents: &[&str]| NodeLedger {
+            node_id,
+            blocks: contents
+                .iter()
+                .enumerate()
+                .map(|(index, id)| (index as u64, id.to_string()))
+                .collect(),
+            head_hash: "abcdef0123".to_string(),
+            intact: true,
+        };
+        let mut report = ClusterReport {
+            nodes: vec![
+                ledger(0, &["g", "a", "b"]),
+                ledger(1, &["g", "a", "c"]),
+                ledger(2, &["g", "a", "b"]),
+            ],
+            failures: Vec::new(),
+        };
+        // Same indices everywhere, even where the contents differ
+        assert!(report.converged());
+        assert_eq!(report.matching_contents(), 1);
+
+        report.nodes[2] = ledger(2, &["g", "a"]);
+        assert!(!report.converged());
+        report.nodes[2] = ledger(2, &["g", "a", "b"]);
+        report
+            .failures
+            .push((3, "cannot bind port 8003".to_string()));
+        assert!(!report.converged());
+        assert!(report.render().contains("Diverged"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Running under a service manager
+//!
+//! Three conventions let systemd (or any supervisor that speaks them) manage
+//! a node:
+//!
+//! - **Readiness.** With `Type=notify`, the node sends `READY=1` to
+//!   `$NOTIFY_SOCKET` once its ledger has verified and its HTTP server is
+//!   bound, and `STOPPING=1` when it shuts down. `STATUS=` lines describe
+//!   what it is doing meanwhile. Without `NOTIFY_SOCKET` nothing is sent.
+//! - **PID file.** `PID_FILE` (or `--pid-file PATH`) names a file the node
+//!   writes its PID to after taking the ledger lock and removes on exit.
+//! - **Exit codes.** Failures exit with a `sysexits.h` code saying whether a
+//!   restart can help; see `ExitCode`.
+//!
+//! ```text
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/local/bin/rust-market-ledger 0 8000
+//! PIDFile=/run/market-ledger/node0.pid
+//! Environment=PID_FILE=/run/market-ledger/node0.pid
+//! RestartPreventExitStatus=65 78
+//! Restart=on-failure
+//! ```
+
+use crate::etl::lock::LockError;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Process exit status for a failure, following `sysexits.h`; a node that
+/// completes its rounds exits 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Any failure not classified below
+    Failure = 1,
+    /// The ledger failed verification; restarting will not fix it
+    DataErr = 65,
+    /// A resource the node needs, such as its port, is unavailable
+    Unavailable = 69,
+    /// Another process holds the ledger; a later restart may succeed
+    TempFail = 75,
+    /// A setting is missing or does not parse; restarting will not fix it
+    Config = 78,
+}
+
+impl ExitCode {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Exit code for an error that ended the node
+    pub fn for_error(err: &(dyn Error + 'static)) -> Self {
+        if let Some(exit) = err.downcast_ref::<ExitError>() {
+            return exit.code;
+        }
+        match err.downcast_ref::<LockError>() {
+            Some(LockError::InUse { .. } | LockError::Unreadable { .. }) => ExitCode::TempFail,
+            _ => ExitCode::Failure,
+        }
+    }
+}
+
+/// An error that carries the exit code the node should end with
+#[derive(Debug)]
+pub struct ExitError {
+    pub code: ExitCode,
+    pub message: String,
+}
+
+impl ExitError {
+    pub fn new(code: ExitCode, message: impl fmt::Display) -> Self {
+        ExitError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// A setting that is missing or does not parse
+    pub fn config(message: impl fmt::Display) -> Self {
+        Self::new(ExitCode::Config, message)
+    }
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ExitError {}
+
+/// Send `state` (e.g. `READY=1`) to the service manager. Returns whether a
+/// notification socket was configured.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) if !socket.is_empty() => notify_to(Path::new(&socket), state).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+/// Like `notify`, but failures are logged rather than returned; a node that
+/// cannot reach its supervisor keeps running
+pub fn notify_or_warn(state: &str) {
+    match notify(state) {
+        Ok(true) => debug!(state = state, "Supervisor: Notified service manager"),
+        Ok(false) => {}
+        Err(e) => warn!(error = %e, state = state, "Supervisor: Notification failed"),
+    }
+}
+
+/// Send `state` to the datagram socket at `socket`; a path starting with
+/// `@` names a Linux abstract socket
+#[cfg(unix)]
+pub fn notify_to(socket: &Path, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    let path = socket.to_string_lossy();
+    let sent = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+            sender.send_to_addr(state.as_bytes(), &addr)?
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract notification sockets need Linux",
+            ))
+        }
+        None => sender.send_to(state.as_bytes(), socket)?,
+    };
+    if sent != state.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "notification was truncated",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn notify_to(_socket: &Path, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "service manager notification needs a Unix platform",
+    ))
+}
+
+/// The node's PID written to a file, removed on drop
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current PID to `path`, replacing a file left by an earlier
+    /// run. The write goes through a temporary file so a supervisor never
+    /// reads a partial PID.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, format!("{}\n", std::process::id()))?;
+        fs::rename(&temp, &path)?;
+        Ok(PidFile { path })
+    }
+
+    /// `--pid-file PATH`, else `PID_FILE`
+    pub fn path_from_args(args: &[String]) -> Option<PathBuf> {
+        args.iter()
+            .position(|arg| arg == "--pid-file")
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+            .or_else(|| std::env::var("PID_FILE").ok())
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // A newer instance may have replaced the file already
+        let ours = fs::read_to_string(&self.path)
+            .map(|pid| pid.trim() == std::process::id().to_string())
+            .unwrap_or(false);
+        if ours {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!(error = %e, path = %self.path.display(), "Supervisor: Failed to remove PID file");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_and_exit_codes() {
+        let path = PathBuf::from("test_supervisor.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+
+        let args: Vec<String> = ["node", "0", "--pid-file", "/run/node.pid"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            PidFile::path_from_args(&args),
+            Some(PathBuf::from("/run/node.pid"))
+        );
+
+        let config: Box<dyn Error> = ExitError::config("bad PBFT_QUORUM_POLICY").into();
+        assert_eq!(ExitCode::for_error(config.as_ref()), ExitCode::Config);
+        assert_eq!(ExitCode::Config.code(), 78);
+        let other: Box<dyn Error> = "boom".into();
+        assert_eq!(ExitCode::for_error(other.as_ref()), ExitCode::Failure);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_sends_state_to_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let socket =
+            std::env::temp_dir().join(format!("ledger-notify-{}.sock", std::process::id()));
+        fs::remove_file(&socket).ok();
+        let receiver = UnixDatagram::bind(&socket).unwrap();
+
+        notify_to(&socket, "READY=1\nSTATUS=Serving").unwrap();
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=Serving");
+
+        fs::remove_file(&socket).ok();
+        assert!(notify_to(&socket, "READY=1").is_err());
+    }
+}
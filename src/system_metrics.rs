@@ -0,0 +1,197 @@
+//! Process resource usage: memory, CPU and open file descriptors
+//!
+//! Every figure comes from `sysinfo`, which reads `/proc` on Linux and the
+//! native APIs on macOS and Windows, so the logger, `GET /metrics` and the
+//! benchmarks report the same numbers on every platform. Inside a container
+//! the memory limit is the cgroup's (`memory.max`, or `memory.limit_in_bytes`
+//! under cgroup v1) rather than the host's, and the CPU count honours the
+//! cgroup's CPU quota.
+//!
+//! CPU usage is measured between two samples of one `SystemMonitor`, so the
+//! first sample has none. `sample()` uses a process-wide monitor; a
+//! benchmark creates its own to measure just its run.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::LazyLock;
+use sysinfo::{MemoryRefreshKind, Pid, ProcessRefreshKind, System};
+
+/// One reading of this process's resource usage
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SystemMetrics {
+    /// Resident set size (bytes)
+    pub rss_bytes: Option<u64>,
+    pub virtual_memory_bytes: Option<u64>,
+    /// Memory available to the process: the cgroup limit in a container,
+    /// else the host's physical memory (bytes)
+    pub memory_limit_bytes: Option<u64>,
+    /// Whether `memory_limit_bytes` is a cgroup limit
+    pub cgroup_limited: bool,
+    /// Percent of one core used since the monitor's previous sample
+    pub cpu_percent: Option<f32>,
+    /// Cores the process may run on
+    pub available_cpus: usize,
+    /// `None` where the platform offers no count (Windows)
+    pub open_fds: Option<u64>,
+}
+
+impl SystemMetrics {
+    /// Resident memory as the logger prints it, e.g. `42.7M`
+    pub fn memory_display(&self) -> String {
+        match self.rss_bytes {
+            Some(bytes) => format!("{:.1}M", bytes as f64 / (1024.0 * 1024.0)),
+            None => "N/A".to_string(),
+        }
+    }
+
+    /// Prometheus text exposition; readings the platform lacks are left out
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: Option<f64>| {
+            if let Some(value) = value {
+                out.push_str(&format!(
+                    "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+                ));
+            }
+        };
+        gauge(
+            "process_resident_memory_bytes",
+            "Resident memory size in bytes.",
+            self.rss_bytes.map(|b| b as f64),
+        );
+        gauge(
+            "process_virtual_memory_bytes",
+            "Virtual memory size in bytes.",
+            self.virtual_memory_bytes.map(|b| b as f64),
+        );
+        gauge(
+            "process_memory_limit_bytes",
+            "Memory available to the process (cgroup limit in a container).",
+            self.memory_limit_bytes.map(|b| b as f64),
+        );
+        gauge(
+            "process_cpu_percent",
+            "CPU usage since the previous sample, percent of one core.",
+            self.cpu_percent.map(f64::from),
+        );
+        gauge(
+            "process_available_cpus",
+            "Cores the process may run on.",
+            Some(self.available_cpus as f64),
+        );
+        gauge(
+            "process_open_fds",
+            "Number of open file descriptors.",
+            self.open_fds.map(|n| n as f64),
+        );
+        out
+    }
+}
+
+/// Takes successive samples of this process, measuring CPU usage between
+/// them
+pub struct SystemMonitor {
+    pid: Pid,
+    state: Mutex<MonitorState>,
+}
+
+struct MonitorState {
+    system: System,
+    primed: bool,
+}
+
+impl SystemMonitor {
+    pub fn new() -> Self {
+        let monitor = SystemMonitor {
+            pid: Pid::from_u32(std::process::id()),
+            state: Mutex::new(MonitorState {
+                system: System::new(),
+                primed: false,
+            }),
+        };
+        // Start the CPU measurement window now
+        monitor.sample();
+        monitor
+    }
+
+    pub fn sample(&self) -> SystemMetrics {
+        let mut state = self.state.lock();
+        let primed = state.primed;
+        let system = &mut state.system;
+        system.refresh_memory_specifics(MemoryRefreshKind::new().with_ram());
+        let found = system.refresh_process_specifics(
+            self.pid,
+            ProcessRefreshKind::new().with_memory().with_cpu(),
+        );
+        let process = system.process(self.pid).filter(|_| found);
+        let cgroup = system.cgroup_limits();
+        let host_memory = Some(system.total_memory()).filter(|&total| total > 0);
+
+        let metrics = SystemMetrics {
+            rss_bytes: process.map(|p| p.memory()),
+            virtual_memory_bytes: process.map(|p| p.virtual_memory()),
+            memory_limit_bytes: cgroup.as_ref().map(|c| c.total_memory).or(host_memory),
+            cgroup_limited: cgroup.is_some(),
+            cpu_percent: process.filter(|_| primed).map(|p| p.cpu_usage()),
+            available_cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            open_fds: open_fd_count(),
+        };
+        state.primed = metrics.rss_bytes.is_some();
+        metrics
+    }
+}
+
+impl Default for SystemMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static MONITOR: LazyLock<SystemMonitor> = LazyLock::new(SystemMonitor::new);
+
+/// Sample this process with the process-wide monitor
+pub fn sample() -> SystemMetrics {
+    MONITOR.sample()
+}
+
+/// Open file descriptors of this process
+pub fn open_fd_count() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    let dir = "/proc/self/fd";
+    #[cfg(all(unix, not(target_os = "linux")))]
+    let dir = "/dev/fd";
+    #[cfg(unix)]
+    {
+        // Reading the directory opens one descriptor, which is listed too
+        std::fs::read_dir(dir)
+            .ok()
+            .map(|entries| (entries.count() as u64).saturating_sub(1))
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_reports_this_process() {
+        let monitor = SystemMonitor::new();
+        let metrics = monitor.sample();
+        assert!(metrics.rss_bytes.unwrap_or_default() > 0);
+        assert!(metrics.memory_limit_bytes.unwrap_or_default() > 0);
+        assert!(metrics.available_cpus >= 1);
+        // The second sample measures CPU since the first
+        assert!(metrics.cpu_percent.is_some());
+        assert!(metrics.memory_display().ends_with('M'));
+        #[cfg(unix)]
+        assert!(metrics.open_fds.unwrap_or_default() > 0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE process_resident_memory_bytes gauge"));
+        assert!(text.contains("process_available_cpus "));
+    }
+}
@@ -0,0 +1,397 @@
+//! Fixtures for tests of applications embedding this crate
+//!
+//! Enabled with the `testing` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! rust-market-ledger = { version = "0.1", features = ["testing"] }
+//! ```
+//!
+//! `MarketDataGenerator` yields deterministic entries from a seed,
+//! `TestChainBuilder` links them into a valid hashed chain, and `TestNode`
+//! serves that chain from an in-memory database through the same HTTP routes
+//! a running node exposes, without binding a port. `entry` and `block` build
+//! single entries and blocks with chosen contents.
+
+use crate::consensus::algorithms::PBFTMessage;
+use crate::etl::load::{DatabaseManager, DbResult};
+use crate::etl::price::{self, Decimal};
+use crate::etl::{Block, MarketData, BLOCK_FORMAT_VERSION};
+use crate::network::{self, NetworkHandler, ServerContext};
+use actix_web::dev::ServiceResponse;
+use actix_web::middleware::from_fn;
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Parent hash of a chain's first block, as used by a fresh node
+pub const GENESIS_PARENT_HASH: &str = "0000_genesis_hash";
+
+/// Timestamp of the first generated entry: 2023-11-14 22:13:20 UTC
+pub const BASE_TIMESTAMP_MS: i64 = 1_700_000_000_000;
+
+/// An entry of `asset` from `source` at `price`, stamped `timestamp`
+pub fn entry(asset: &str, source: &str, price: Decimal, timestamp: i64) -> MarketData {
+    MarketData {
+        asset: asset.to_string(),
+        price,
+        source: source.to_string(),
+        timestamp,
+        provenance: None,
+        indicators: None,
+        conversion: None,
+        bucket: None,
+    }
+}
+
+/// A hashed block of `data` linked to `previous_hash`, stamped with its last
+/// entry's timestamp
+pub fn block(index: u64, previous_hash: &str, data: Vec<MarketData>) -> Block {
+    let mut block = Block {
+        index,
+        timestamp: data.last().map_or(BASE_TIMESTAMP_MS, |d| d.timestamp),
+        data,
+        previous_hash: previous_hash.to_string(),
+        hash: String::new(),
+        nonce: 0,
+        format_version: BLOCK_FORMAT_VERSION,
+        fees: Vec::new(),
+        divergences: Vec::new(),
+        hlc: None,
+        annotations: Default::default(),
+        order_books: Vec::new(),
+    };
+    block.calculate_hash_with_nonce();
+    block
+}
+
+/// Deterministic stream of `MarketData`
+///
+/// Entries cycle through the configured assets and sources, one
+/// `interval_ms` apart. Prices follow a seeded random walk of at most 1%
+/// per step around each asset's base price, so the same seed always yields
+/// the same entries.
+#[derive(Debug, Clone)]
+pub struct MarketDataGenerator {
+    state: u64,
+    assets: Vec<(String, Decimal)>,
+    sources: Vec<String>,
+    timestamp: i64,
+    interval_ms: i64,
+    step: usize,
+}
+
+impl MarketDataGenerator {
+    pub fn new(seed: u64) -> Self {
+        MarketDataGenerator {
+            state: seed,
+            assets: vec![("BTC".to_string(), Decimal::from(50_000))],
+            sources: vec!["CoinGecko".to_string()],
+            timestamp: BASE_TIMESTAMP_MS,
+            interval_ms: 1000,
+            step: 0,
+        }
+    }
+
+    /// Assets and their starting prices
+    pub fn with_assets<I, S>(mut self, assets: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Decimal)>,
+        S: Into<String>,
+    {
+        self.assets = assets.into_iter().map(|(a, p)| (a.into(), p)).collect();
+        self
+    }
+
+    pub fn with_sources<I, S>(mut self, sources: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.sources = sources.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_start_timestamp(mut self, timestamp_ms: i64) -> Self {
+        self.timestamp = timestamp_ms;
+        self
+    }
+
+    pub fn with_interval_ms(mut self, interval_ms: i64) -> Self {
+        self.interval_ms = interval_ms;
+        self
+    }
+
+    /// splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl Iterator for MarketDataGenerator {
+    type Item = MarketData;
+
+    fn next(&mut self) -> Option<MarketData> {
+        if self.assets.is_empty() || self.sources.is_empty() {
+            return None;
+        }
+        let asset_index = self.step % self.assets.len();
+        let source = self.sources[self.step % self.sources.len()].clone();
+        // Uniform in [-1%, 1%], rounded to cents like the pipeline does
+        let change = Decimal::new((self.next_u64() % 2001) as i64 - 1000, 5);
+        let (asset, price) = &mut self.assets[asset_index];
+        *price = price::round(*price * (Decimal::ONE + change), 2);
+
+        let entry = entry(asset, &source, *price, self.timestamp);
+        self.timestamp += self.interval_ms;
+        self.step += 1;
+        Some(entry)
+    }
+}
+
+/// Builds a valid chain of hashed, linked blocks
+#[derive(Debug, Clone)]
+pub struct TestChainBuilder {
+    blocks: u64,
+    entries_per_block: usize,
+    start_index: u64,
+    parent_hash: String,
+    generator: MarketDataGenerator,
+}
+
+impl Default for TestChainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestChainBuilder {
+    /// An empty chain; entries come from `MarketDataGenerator::new(0)`
+    pub fn new() -> Self {
+        TestChainBuilder {
+            blocks: 0,
+            entries_per_block: 1,
+            start_index: 1,
+            parent_hash: GENESIS_PARENT_HASH.to_string(),
+            generator: MarketDataGenerator::new(0),
+        }
+    }
+
+    pub fn with_blocks(mut self, blocks: u64) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    pub fn with_entries_per_block(mut self, entries: usize) -> Self {
+        self.entries_per_block = entries;
+        self
+    }
+
+    pub fn with_generator(mut self, generator: MarketDataGenerator) -> Self {
+        self.generator = generator;
+        self
+    }
+
+    /// Continue an existing chain: the first block gets `index` and links to
+    /// `parent_hash`
+    pub fn starting_after(mut self, index: u64, parent_hash: impl Into<String>) -> Self {
+        self.start_index = index + 1;
+        self.parent_hash = parent_hash.into();
+        self
+    }
+
+    /// Blocks in ascending index order; each block is stamped with its
+    /// last entry's timestamp
+    pub fn build(&self) -> Vec<Block> {
+        let mut generator = self.generator.clone();
+        let mut parent_hash = self.parent_hash.clone();
+        let mut blocks = Vec::with_capacity(self.blocks as usize);
+        for index in self.start_index..self.start_index + self.blocks {
+            let data = generator.by_ref().take(self.entries_per_block).collect();
+            let block = block(index, &parent_hash, data);
+            parent_hash = block.hash.clone();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    /// The chain saved to a fresh in-memory database
+    pub fn build_in_memory(&self) -> DbResult<Arc<DatabaseManager>> {
+        let db = DatabaseManager::in_memory()?;
+        db.init()?;
+        db.save_blocks(&self.build())?;
+        Ok(Arc::new(db))
+    }
+}
+
+/// A node's HTTP surface backed by an in-memory ledger
+///
+/// Requests are dispatched in-process through the routes and middleware of
+/// `network::start_server`. Consensus messages POSTed to `/message` are
+/// accepted and recorded rather than processed.
+pub struct TestNode {
+    db: Arc<DatabaseManager>,
+    context: ServerContext,
+    received: Arc<Mutex<Vec<PBFTMessage>>>,
+}
+
+impl TestNode {
+    /// A node with an empty ledger
+    pub fn new() -> DbResult<Self> {
+        Self::with_chain(&TestChainBuilder::new())
+    }
+
+    /// A node whose ledger holds the chain `builder` produces
+    pub fn with_chain(builder: &TestChainBuilder) -> DbResult<Self> {
+        let db = builder.build_in_memory()?;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let handler = NetworkHandler::new(move |msg| {
+            sink.lock().push(msg);
+            true
+        });
+        let context = ServerContext::new(Arc::new(handler)).with_database(db.clone());
+        Ok(TestNode {
+            db,
+            context,
+            received,
+        })
+    }
+
+    /// Adjust the server context, e.g. to enable accounting or tenants
+    pub fn with_context(mut self, f: impl FnOnce(ServerContext) -> ServerContext) -> Self {
+        self.context = f(self.context);
+        self
+    }
+
+    pub fn db(&self) -> &Arc<DatabaseManager> {
+        &self.db
+    }
+
+    pub fn context(&self) -> &ServerContext {
+        &self.context
+    }
+
+    /// Consensus messages delivered to `/message` so far
+    pub fn received_messages(&self) -> Vec<PBFTMessage> {
+        self.received.lock().clone()
+    }
+
+    /// Dispatch a request to the node
+    pub async fn call(&self, request: TestRequest) -> ServiceResponse {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(self.context.clone()))
+                .wrap(from_fn(network::rbac::enforce))
+                .wrap(from_fn(network::trace_requests))
+                .configure(network::configure_routes),
+        )
+        .await;
+        test::call_service(&app, request.to_request())
+            .await
+            .map_into_boxed_body()
+    }
+
+    /// GET `uri` and decode the JSON response body
+    pub async fn get_json(&self, uri: &str) -> serde_json::Value {
+        let response = self.call(TestRequest::get().uri(uri)).await;
+        test::read_body_json(response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::algorithms::MessageType;
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let generator = MarketDataGenerator::new(7)
+            .with_assets([
+                ("BTC", Decimal::from(50_000)),
+                ("ETH", Decimal::from(3_000)),
+            ])
+            .with_sources(["Kraken", "Coinbase", "CoinGecko"]);
+        let a: Vec<MarketData> = generator.clone().take(6).collect();
+        let b: Vec<MarketData> = generator.take(6).collect();
+
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            serde_json::to_string(&b).unwrap()
+        );
+        assert_eq!(a[1].asset, "ETH");
+        assert_eq!(a[4].source, "Coinbase");
+        assert_eq!(a[5].timestamp, BASE_TIMESTAMP_MS + 5000);
+        assert!(a
+            .iter()
+            .all(|d| d.price.is_sign_positive() && !d.price.is_zero()));
+        assert!((a[2].price / Decimal::from(50_000) - Decimal::ONE).abs() < Decimal::new(3, 2));
+        assert!(a.iter().all(|d| d.price.scale() <= 2));
+
+        let other: Vec<MarketData> = MarketDataGenerator::new(8).take(6).collect();
+        assert_ne!(
+            serde_json::to_string(&other).unwrap(),
+            serde_json::to_string(&MarketDataGenerator::new(7).take(6).collect::<Vec<_>>())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_chain_builder_links_blocks() {
+        let chain = TestChainBuilder::new()
+            .with_blocks(5)
+            .with_entries_per_block(3)
+            .build();
+
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain[0].previous_hash, GENESIS_PARENT_HASH);
+        for pair in chain.windows(2) {
+            assert_eq!(pair[1].previous_hash, pair[0].hash);
+            assert_eq!(pair[1].index, pair[0].index + 1);
+        }
+        assert!(chain.iter().all(|b| b.hash == b.calculate_hash()));
+        assert_eq!(chain[4].data.len(), 3);
+
+        let more = TestChainBuilder::new()
+            .with_blocks(2)
+            .starting_after(5, chain[4].hash.clone())
+            .build();
+        assert_eq!(more[0].index, 6);
+        assert_eq!(more[0].previous_hash, chain[4].hash);
+    }
+
+    #[actix_web::test]
+    async fn test_node_serves_chain() {
+        let node = TestNode::with_chain(&TestChainBuilder::new().with_blocks(4)).unwrap();
+        assert_eq!(node.db().get_block_count().unwrap(), 4);
+
+        let analytics = node.get_json("/analytics").await;
+        assert_eq!(analytics["total_blocks"], 4);
+
+        let message = PBFTMessage {
+            msg_type: MessageType::Prepare,
+            view: 0,
+            sequence: 1,
+            block_hash: "abc".to_string(),
+            block_data_json: None,
+            node_id: 2,
+            timestamp: BASE_TIMESTAMP_MS,
+            shard: None,
+            trace_id: None,
+            protocol_version: network::protocol::PROTOCOL_VERSION,
+            hlc: None,
+            prepared: None,
+            view_changes: Vec::new(),
+        };
+        let response = node
+            .call(TestRequest::post().uri("/message").set_json(&message))
+            .await;
+        assert!(response.status().is_success());
+        assert_eq!(node.received_messages()[0].node_id, 2);
+    }
+}